@@ -0,0 +1,124 @@
+//! End-to-end broadcast latency in a local cluster.
+//!
+//! The nodes of the cluster are registered in a single service, so the latency includes
+//! the RPC round trips over the loopback interface but not the network delays.
+use criterion::{criterion_group, criterion_main, Criterion};
+use fibers::sync::mpsc as fibers_mpsc;
+use futures::{Async, Future, Poll, Stream};
+use plumcast::message::MessageId;
+use plumcast::node::{Node, SerialLocalNodeIdGenerator};
+use plumcast::service::Service;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+const NODES: usize = 20;
+
+struct Member {
+    node: Node<Vec<u8>>,
+    broadcast_rx: Option<(fibers_mpsc::Receiver<Vec<u8>>, mpsc::Sender<MessageId>)>,
+    delivered_tx: mpsc::Sender<MessageId>,
+}
+impl Future for Member {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some((ref mut rx, ref id_tx)) = self.broadcast_rx {
+            while let Async::Ready(Some(payload)) = rx.poll().expect("Never fails") {
+                let _ = id_tx.send(self.node.broadcast(payload));
+            }
+        }
+        while let Async::Ready(m) = self.node.poll().map_err(|e| panic!("{}", e))? {
+            let m = m.expect("The node has stopped");
+            self.node.forget_message(m.id());
+            let _ = self.delivered_tx.send(*m.id());
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+struct Cluster {
+    broadcast_tx: fibers_mpsc::Sender<Vec<u8>>,
+    broadcasted_rx: mpsc::Receiver<MessageId>,
+    delivered_rx: mpsc::Receiver<MessageId>,
+}
+impl Cluster {
+    fn start(port: u16) -> Self {
+        let server_addr = ([127, 0, 0, 1], port).into();
+        let service = Service::<Vec<u8>>::new(
+            server_addr,
+            fibers_global::handle(),
+            SerialLocalNodeIdGenerator::new(),
+        );
+        let handle = service.handle();
+        fibers_global::spawn(service.map_err(|e| panic!("{}", e)));
+
+        let (broadcast_tx, broadcast_rx) = fibers_mpsc::channel();
+        let (broadcasted_tx, broadcasted_rx) = mpsc::channel();
+        let (delivered_tx, delivered_rx) = mpsc::channel();
+        let mut broadcast_rx = Some((broadcast_rx, broadcasted_tx));
+        let mut contact = None;
+        for _ in 0..NODES {
+            let mut node = Node::new(handle.clone());
+            if let Some(id) = contact {
+                node.join(id);
+            } else {
+                contact = Some(node.id());
+            }
+            fibers_global::spawn(Member {
+                node,
+                broadcast_rx: broadcast_rx.take(),
+                delivered_tx: delivered_tx.clone(),
+            });
+        }
+        Cluster {
+            broadcast_tx,
+            broadcasted_rx,
+            delivered_rx,
+        }
+    }
+
+    /// Broadcasts a message from the first node, and waits until all the nodes deliver it.
+    ///
+    /// Returns `false` if some nodes have not delivered the message within `timeout`.
+    fn broadcast(&self, payload: Vec<u8>, timeout: Duration) -> bool {
+        self.broadcast_tx.send(payload).expect("Never fails");
+        let deadline = Instant::now() + timeout;
+        let id = match self.broadcasted_rx.recv_timeout(timeout) {
+            Err(_) => return false,
+            Ok(id) => id,
+        };
+        let mut delivered = 0;
+        while delivered < NODES {
+            let now = Instant::now();
+            if deadline <= now {
+                return false;
+            }
+            match self.delivered_rx.recv_timeout(deadline - now) {
+                Err(_) => return false,
+                Ok(m) if m == id => delivered += 1,
+                Ok(_) => {
+                    // NOTE: A delivery of a message broadcasted by a timed out call.
+                }
+            }
+        }
+        true
+    }
+}
+
+fn broadcast_latency(c: &mut Criterion) {
+    let cluster = Cluster::start(14_200);
+
+    // Waits until the membership of the cluster converges.
+    while !cluster.broadcast(vec![0], Duration::from_secs(1)) {}
+
+    let mut group = c.benchmark_group("cluster");
+    group.sample_size(20);
+    group.bench_function(format!("broadcast_latency/{}_nodes", NODES), |b| {
+        b.iter(|| assert!(cluster.broadcast(vec![1; 64], Duration::from_secs(10))))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, broadcast_latency);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+//! Throughput of the codecs of RPC frames.
+//!
+//! Run with `cargo bench --features bench --bench codec`.
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use plumcast::bench::{
+    decode_gossip_frame, decode_ihave_frame, encode_gossip_frame, encode_ihave_frame,
+};
+use plumcast::service::WireCodec;
+
+const PAYLOAD_SIZES: &[usize] = &[16, 1024, 64 * 1024];
+
+fn gossip(c: &mut Criterion) {
+    let mut group = c.benchmark_group("gossip");
+    for &size in PAYLOAD_SIZES {
+        let frame = encode_gossip_frame(1, vec![0; size]).unwrap();
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        group.bench_function(format!("encode/{}", size), |b| {
+            b.iter_batched(
+                || vec![0; size],
+                |payload| encode_gossip_frame(1, payload).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_function(format!("decode/{}", size), |b| {
+            b.iter(|| decode_gossip_frame(&frame).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn ihave(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ihave");
+    for &codec in &[WireCodec::Fixed, WireCodec::Varint] {
+        let frame = encode_ihave_frame(codec, 100).unwrap();
+        group.throughput(Throughput::Elements(1));
+        group.bench_function(format!("encode/{:?}", codec), |b| {
+            b.iter(|| encode_ihave_frame(codec, 100).unwrap())
+        });
+        group.bench_function(format!("decode/{:?}", codec), |b| {
+            b.iter(|| decode_ihave_frame(codec, &frame).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, gossip, ihave);
+criterion_main!(benches);
@@ -0,0 +1,175 @@
+//! Throughput of the action loop of `Node::poll`.
+//!
+//! Each iteration feeds a synthetic stream of messages to a standalone node
+//! and polls the node until all of them are delivered.
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use fibers::{Executor, ThreadPoolExecutor};
+use futures::{Async, Future, Poll, Stream};
+use plumcast::dedicated::DedicatedNode;
+use plumcast::node::{Node, SerialLocalNodeIdGenerator};
+use plumcast::service::{Service, ServiceHandle};
+
+const MESSAGES: usize = 1000;
+
+struct Drain {
+    node: Option<Node<Vec<u8>>>,
+    remaining: usize,
+}
+impl Future for Drain {
+    type Item = Node<Vec<u8>>;
+    type Error = plumcast::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let node = self.node.as_mut().expect("Cannot poll Drain twice");
+            while self.remaining > 0 {
+                match node.poll()? {
+                    Async::NotReady => return Ok(Async::NotReady),
+                    Async::Ready(None) => panic!("The node has stopped"),
+                    Async::Ready(Some(m)) => {
+                        if !m.is_local() {
+                            node.forget_message(m.id());
+                        }
+                        self.remaining -= 1;
+                    }
+                }
+            }
+        }
+        Ok(Async::Ready(self.node.take().expect("Never fails")))
+    }
+}
+
+fn drain(node: Node<Vec<u8>>, remaining: usize) -> Node<Vec<u8>> {
+    fibers_global::execute(Drain {
+        node: Some(node),
+        remaining,
+    })
+    .unwrap()
+}
+
+struct DrainDedicated {
+    node: Option<DedicatedNode<Vec<u8>>>,
+    remaining: usize,
+}
+impl Future for DrainDedicated {
+    type Item = DedicatedNode<Vec<u8>>;
+    type Error = plumcast::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let node = self
+                .node
+                .as_mut()
+                .expect("Cannot poll DrainDedicated twice");
+            while self.remaining > 0 {
+                match node.poll()? {
+                    Async::NotReady => return Ok(Async::NotReady),
+                    Async::Ready(None) => panic!("The node has stopped"),
+                    Async::Ready(Some(m)) => {
+                        node.forget_message(m.id());
+                        self.remaining -= 1;
+                    }
+                }
+            }
+        }
+        Ok(Async::Ready(self.node.take().expect("Never fails")))
+    }
+}
+
+fn drain_dedicated(node: DedicatedNode<Vec<u8>>, remaining: usize) -> DedicatedNode<Vec<u8>> {
+    fibers_global::execute(DrainDedicated {
+        node: Some(node),
+        remaining,
+    })
+    .unwrap()
+}
+
+fn start_service(port: u16) -> ServiceHandle<Vec<u8>> {
+    let server_addr = ([127, 0, 0, 1], port).into();
+    let service = Service::<Vec<u8>>::new(
+        server_addr,
+        fibers_global::handle(),
+        SerialLocalNodeIdGenerator::new(),
+    );
+    let handle = service.handle();
+    fibers_global::spawn(service.map_err(|e| panic!("{}", e)));
+    handle
+}
+
+fn poll_loop(c: &mut Criterion) {
+    let service = start_service(14_100);
+    let payloads = (0..MESSAGES)
+        .map(|i| (i as u32).to_be_bytes().to_vec())
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("node_poll");
+    group.throughput(Throughput::Elements(MESSAGES as u64));
+
+    let mut node = Some(Node::new(service.clone()));
+    group.bench_function("broadcast_batch", |b| {
+        b.iter(|| {
+            let mut n = node.take().expect("Never fails");
+            n.broadcast_batch(payloads.clone());
+            node = Some(drain(n, MESSAGES));
+        })
+    });
+
+    node = Some(Node::new(service.clone()));
+    group.bench_function("broadcast_local", |b| {
+        b.iter(|| {
+            for payload in &payloads {
+                service.broadcast_local(payload.clone());
+            }
+            node = Some(drain(node.take().expect("Never fails"), MESSAGES));
+        })
+    });
+    group.finish();
+}
+
+/// Compares a busy node polled by the shared pool (together with many idle nodes)
+/// with the same node spawned onto a dedicated executor by `Node::spawn_on`.
+fn dedicated(c: &mut Criterion) {
+    const IDLE_NODES: usize = 200;
+
+    let service = start_service(14_101);
+    for _ in 0..IDLE_NODES {
+        let idle = Node::<Vec<u8>>::new(service.clone());
+        fibers_global::spawn(idle.for_each(|_| Ok(())).map_err(|e| panic!("{}", e)));
+    }
+
+    let mut group = c.benchmark_group("dedicated");
+    group.throughput(Throughput::Elements(MESSAGES as u64));
+
+    let mut node = Some(Node::new(service.clone()));
+    group.bench_function("shared_pool", |b| {
+        b.iter(|| {
+            let mut n = node.take().expect("Never fails");
+            for i in 0..MESSAGES {
+                n.broadcast((i as u32).to_be_bytes().to_vec());
+            }
+            node = Some(drain(n, MESSAGES));
+        })
+    });
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let executor = ThreadPoolExecutor::with_thread_count(1).unwrap();
+        tx.send(executor.handle()).unwrap();
+        executor.run()
+    });
+    let executor = rx.recv().unwrap();
+    let mut busy = Some(Node::new(service.clone()).spawn_on(&executor, MESSAGES));
+    group.bench_function("dedicated_executor", |b| {
+        b.iter(|| {
+            let n = busy.take().expect("Never fails");
+            for i in 0..MESSAGES {
+                n.broadcast((i as u32).to_be_bytes().to_vec());
+            }
+            busy = Some(drain_dedicated(n, MESSAGES));
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, poll_loop, dedicated);
+criterion_main!(benches);
@@ -39,11 +39,13 @@ fn main() -> Result<(), MainError> {
     let addr: SocketAddr = track_any_err!(format!("127.0.0.1:{}", port).parse())?;
 
     let executor = track_any_err!(ThreadPoolExecutor::new())?;
-    let service = ServiceBuilder::new(addr)
+    let service = track!(ServiceBuilder::new(addr)
         .logger(logger.clone())
-        .finish(executor.handle(), SerialLocalNodeIdGenerator::new());
+        .try_finish(executor.handle(), SerialLocalNodeIdGenerator::new()))?;
 
-    let mut node = NodeBuilder::new().logger(logger).finish(service.handle());
+    let mut node = track!(NodeBuilder::new()
+        .logger(logger)
+        .try_finish(service.handle()))?;
     if let Some(contact) = matches.value_of("CONTACT_SERVER") {
         let contact: SocketAddr = track_any_err!(contact.parse())?;
         node.join(NodeId::new(contact, LocalNodeId::new(0)));
@@ -0,0 +1,129 @@
+use crate::node::NodeId;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::sync::Arc;
+
+/// This trait allows for normalizing the socket addresses contained in [`NodeId`]s.
+///
+/// Node identifiers received from remote nodes and the identifiers of local nodes are
+/// normalized by a [`Service`] before being handled by the HyParView/Plumtree nodes.
+/// As a result, different representations of the same peer (e.g., `::ffff:127.0.0.1:3000`
+/// and `127.0.0.1:3000` on a dual-stack host) are treated as the same node.
+///
+/// [`NodeId`]: ../node/struct.NodeId.html
+/// [`Service`]: ./struct.Service.html
+pub trait NormalizeAddr: Send + Sync + 'static {
+    /// Normalizes the given socket address.
+    fn normalize_addr(&self, addr: SocketAddr) -> SocketAddr;
+}
+
+#[derive(Clone)]
+pub(crate) struct ArcAddrNormalizer(Arc<dyn NormalizeAddr>);
+impl ArcAddrNormalizer {
+    pub(crate) fn new<T: NormalizeAddr>(inner: T) -> Self {
+        ArcAddrNormalizer(Arc::new(inner))
+    }
+
+    pub(crate) fn normalize_node_id(&self, id: NodeId) -> NodeId {
+        NodeId::new(self.normalize_addr(id.address()), id.local_id())
+    }
+}
+impl fmt::Debug for ArcAddrNormalizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ArcAddrNormalizer(_)")
+    }
+}
+impl NormalizeAddr for ArcAddrNormalizer {
+    fn normalize_addr(&self, addr: SocketAddr) -> SocketAddr {
+        self.0.normalize_addr(addr)
+    }
+}
+
+/// The default implementation of [`NormalizeAddr`].
+///
+/// This converts addresses as follows:
+/// - IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) are converted to the IPv4 addresses (`a.b.c.d`)
+/// - The flow information of IPv6 addresses is cleared (the scope identifier is kept as is)
+///
+/// [`NormalizeAddr`]: ./trait.NormalizeAddr.html
+#[derive(Debug, Default, Clone)]
+pub struct CanonicalAddrNormalizer;
+impl CanonicalAddrNormalizer {
+    /// Makes a new `CanonicalAddrNormalizer` instance.
+    pub fn new() -> Self {
+        CanonicalAddrNormalizer
+    }
+}
+impl NormalizeAddr for CanonicalAddrNormalizer {
+    fn normalize_addr(&self, addr: SocketAddr) -> SocketAddr {
+        match addr {
+            SocketAddr::V4(_) => addr,
+            SocketAddr::V6(a) => {
+                if let Some(ip) = ipv4_mapped(a.ip()) {
+                    SocketAddr::V4(SocketAddrV4::new(ip, a.port()))
+                } else {
+                    SocketAddr::V6(SocketAddrV6::new(*a.ip(), a.port(), 0, a.scope_id()))
+                }
+            }
+        }
+    }
+}
+
+/// An implementation of [`NormalizeAddr`] that returns the given addresses without any modification.
+///
+/// [`NormalizeAddr`]: ./trait.NormalizeAddr.html
+#[derive(Debug, Default, Clone)]
+pub struct IdentityAddrNormalizer;
+impl IdentityAddrNormalizer {
+    /// Makes a new `IdentityAddrNormalizer` instance.
+    pub fn new() -> Self {
+        IdentityAddrNormalizer
+    }
+}
+impl NormalizeAddr for IdentityAddrNormalizer {
+    fn normalize_addr(&self, addr: SocketAddr) -> SocketAddr {
+        addr
+    }
+}
+
+fn ipv4_mapped(ip: &Ipv6Addr) -> Option<Ipv4Addr> {
+    match ip.segments() {
+        [0, 0, 0, 0, 0, 0xffff, ..] => ip.to_ipv4(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::LocalNodeId;
+
+    #[test]
+    fn canonical_normalizer_works() {
+        let n = CanonicalAddrNormalizer::new();
+
+        let v4: SocketAddr = "127.0.0.1:3000".parse().unwrap();
+        let mapped: SocketAddr = "[::ffff:127.0.0.1]:3000".parse().unwrap();
+        assert_eq!(n.normalize_addr(v4), v4);
+        assert_eq!(n.normalize_addr(mapped), v4);
+
+        let loopback: SocketAddr = "[::1]:3000".parse().unwrap();
+        assert_eq!(n.normalize_addr(loopback), loopback);
+
+        let with_flowinfo = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 3000, 10, 2));
+        let without_flowinfo = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 3000, 0, 2));
+        assert_eq!(n.normalize_addr(with_flowinfo), without_flowinfo);
+    }
+
+    #[test]
+    fn normalized_node_ids_are_equal() {
+        let n = ArcAddrNormalizer::new(CanonicalAddrNormalizer::new());
+        let a = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(1));
+        let b = NodeId::new(
+            "[::ffff:127.0.0.1]:3000".parse().unwrap(),
+            LocalNodeId::new(1),
+        );
+        assert_ne!(a, b);
+        assert_eq!(n.normalize_node_id(a), n.normalize_node_id(b));
+    }
+}
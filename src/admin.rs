@@ -0,0 +1,380 @@
+//! Administrative operations on running nodes.
+//!
+//! The parameters of a running [`Node`] can be adjusted either locally by [`Node::update_parameter`]
+//! or remotely by [`ServiceHandle::send_parameter_update`] (via the admin RPC).
+//! This enables controlled tuning experiments in production clusters without restarting nodes.
+//! Remote updates are rejected unless the destination service accepts them from the caller
+//! (see [`ServiceBuilder::parameter_update_policy`]).
+//! Note that only the parameters listed in [`ParameterUpdate`] can be adjusted
+//! (e.g., there is no compression threshold, because this crate does not compress frames).
+//!
+//! The health of a service can be checked by [`ServiceHandle::health`]
+//! (e.g., for implementing readiness probes of orchestrators).
+//!
+//! The [`NodeStatus`] of a node can be taken locally by [`Node::status`]
+//! or remotely by [`ServiceHandle::node_statuses`] (via the admin RPC).
+//!
+//! The recent protocol events of a node (see [`NodeBuilder::event_log_capacity`]) can be taken
+//! locally by [`Node::debug_dump`] or remotely by [`ServiceHandle::debug_dump`] (via the admin RPC).
+//! This helps to investigate the problems that are hard to reproduce.
+//!
+//! [`Node`]: ../node/struct.Node.html
+//! [`ParameterUpdate`]: ./enum.ParameterUpdate.html
+//! [`ServiceBuilder::parameter_update_policy`]: ../service/struct.ServiceBuilder.html#method.parameter_update_policy
+//! [`NodeStatus`]: ./struct.NodeStatus.html
+//! [`Node::status`]: ../node/struct.Node.html#method.status
+//! [`Node::debug_dump`]: ../node/struct.Node.html#method.debug_dump
+//! [`NodeBuilder::event_log_capacity`]: ../node/struct.NodeBuilder.html#method.event_log_capacity
+//! [`ServiceHandle::debug_dump`]: ../service/struct.ServiceHandle.html#method.debug_dump
+//! [`ServiceHandle::health`]: ../service/struct.ServiceHandle.html#method.health
+//! [`ServiceHandle::node_statuses`]: ../service/struct.ServiceHandle.html#method.node_statuses
+//! [`Node::update_parameter`]: ../node/struct.Node.html#method.update_parameter
+//! [`ServiceHandle::send_parameter_update`]: ../service/struct.ServiceHandle.html#method.send_parameter_update
+use crate::node::NodeId;
+use crate::{Error, ErrorKind, Result};
+use fibers_rpc::client::Response;
+use futures::{Future, Poll};
+#[cfg(feature = "serialize")]
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// The minimum value of `ParameterUpdate::TickIntervalMultiplier`.
+pub const MIN_TICK_INTERVAL_MULTIPLIER: f64 = 0.1;
+
+/// The maximum value of `ParameterUpdate::TickIntervalMultiplier`.
+pub const MAX_TICK_INTERVAL_MULTIPLIER: f64 = 10.0;
+
+/// The minimum value of `ParameterUpdate::HyparviewShuffleInterval`.
+pub const MIN_HYPARVIEW_SHUFFLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The maximum value of `ParameterUpdate::HyparviewShuffleInterval`.
+pub const MAX_HYPARVIEW_SHUFFLE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// An update of a runtime parameter of a node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParameterUpdate {
+    /// Sets the multiplier applied to the tick interval specified by `NodeBuilder::tick_interval()`.
+    ///
+    /// The value must be between `MIN_TICK_INTERVAL_MULTIPLIER` and `MAX_TICK_INTERVAL_MULTIPLIER`.
+    TickIntervalMultiplier(f64),
+
+    /// Sets the execution interval of `HyparviewNode::shuffle_passive_view()` method.
+    ///
+    /// The value must be between `MIN_HYPARVIEW_SHUFFLE_INTERVAL` and
+    /// `MAX_HYPARVIEW_SHUFFLE_INTERVAL`, and it is transmitted in milliseconds precision.
+    HyparviewShuffleInterval(Duration),
+}
+impl ParameterUpdate {
+    /// Checks whether the value of the update is within the bounds.
+    ///
+    /// If it is out of the bounds, an `ErrorKind::InvalidInput` error is returned.
+    pub fn validate(&self) -> Result<()> {
+        match *self {
+            ParameterUpdate::TickIntervalMultiplier(m) => {
+                track_assert!(
+                    (MIN_TICK_INTERVAL_MULTIPLIER..=MAX_TICK_INTERVAL_MULTIPLIER).contains(&m),
+                    ErrorKind::InvalidInput,
+                    "Tick interval multiplier out of bounds: {}",
+                    m
+                );
+            }
+            ParameterUpdate::HyparviewShuffleInterval(d) => {
+                track_assert!(
+                    (MIN_HYPARVIEW_SHUFFLE_INTERVAL..=MAX_HYPARVIEW_SHUFFLE_INTERVAL).contains(&d),
+                    ErrorKind::InvalidInput,
+                    "HyParView shuffle interval out of bounds: {:?}",
+                    d
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// This trait allows for restricting the callers that can update the parameters of local nodes
+/// via the admin RPC.
+///
+/// A policy is consulted by a [`Service`] for each incoming parameter update
+/// before the update is delivered to the destination node.
+/// The rejected updates are logged and dropped.
+///
+/// Note that the checked address is the one advertised by the caller
+/// (i.e., the address of the RPC server of the calling service contained in the message),
+/// not the source address of the connection.
+/// So a policy is a guard against misdirected updates rather than an authentication mechanism.
+///
+/// [`Service`]: ../service/struct.Service.html
+pub trait ParameterUpdatePolicy: Send + Sync + 'static {
+    /// Returns `true` if the service at `caller` is allowed to apply `update` to a local node.
+    fn is_allowed(&self, caller: SocketAddr, update: &ParameterUpdate) -> bool;
+}
+impl<F> ParameterUpdatePolicy for F
+where
+    F: Fn(SocketAddr, &ParameterUpdate) -> bool + Send + Sync + 'static,
+{
+    fn is_allowed(&self, caller: SocketAddr, update: &ParameterUpdate) -> bool {
+        self(caller, update)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ArcParameterUpdatePolicy(Arc<dyn ParameterUpdatePolicy>);
+impl ArcParameterUpdatePolicy {
+    pub(crate) fn new<T: ParameterUpdatePolicy>(inner: T) -> Self {
+        ArcParameterUpdatePolicy(Arc::new(inner))
+    }
+}
+impl fmt::Debug for ArcParameterUpdatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ArcParameterUpdatePolicy(_)")
+    }
+}
+impl ParameterUpdatePolicy for ArcParameterUpdatePolicy {
+    fn is_allowed(&self, caller: SocketAddr, update: &ParameterUpdate) -> bool {
+        self.0.is_allowed(caller, update)
+    }
+}
+
+/// The health of a service reported in response to a ping.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+    pub(crate) local_nodes: u64,
+}
+impl Health {
+    /// Returns the number of the nodes registered in the service.
+    ///
+    /// A service having no nodes can not be joined by other nodes.
+    pub fn local_nodes(&self) -> u64 {
+        self.local_nodes
+    }
+}
+
+/// A [`Future`] that sends a ping to a service and waits for its health.
+///
+/// This is created by calling [`ServiceHandle::health`] or [`ServiceHandle::ping`] methods.
+/// The future fails if the service does not respond within a few seconds.
+///
+/// [`Future`]: https://docs.rs/futures/0.1/futures/future/trait.Future.html
+/// [`ServiceHandle::health`]: ../service/struct.ServiceHandle.html#method.health
+/// [`ServiceHandle::ping`]: ../service/struct.ServiceHandle.html#method.ping
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct HealthCheck(pub(crate) Response<Health>);
+impl Future for HealthCheck {
+    type Item = Health;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        track!(self.0.poll().map_err(Error::from))
+    }
+}
+
+/// A snapshot of the status of a node.
+///
+/// This is intended to be used for monitoring and debugging.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct NodeStatus {
+    pub(crate) id: NodeId,
+    pub(crate) active_view: Vec<NodeId>,
+    pub(crate) passive_view: Vec<NodeId>,
+    pub(crate) eager_push_peers: Vec<NodeId>,
+    pub(crate) lazy_push_peers: Vec<NodeId>,
+    pub(crate) ticks: u64,
+    pub(crate) cached_messages: u64,
+    pub(crate) known_messages: u64,
+    pub(crate) pending_deliveries: u64,
+    pub(crate) broadcasted_messages: u64,
+    pub(crate) delivered_messages: u64,
+    pub(crate) forgot_messages: u64,
+    pub(crate) connected_neighbors: u64,
+    pub(crate) disconnected_neighbors: u64,
+}
+impl NodeStatus {
+    /// Returns the identifier of the node.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Returns the HyParView active view (i.e., the neighbors) of the node.
+    pub fn active_view(&self) -> &[NodeId] {
+        &self.active_view
+    }
+
+    /// Returns the HyParView passive view of the node.
+    pub fn passive_view(&self) -> &[NodeId] {
+        &self.passive_view
+    }
+
+    /// Returns the Plumtree eager push peers of the node (sorted in ascending order).
+    pub fn eager_push_peers(&self) -> &[NodeId] {
+        &self.eager_push_peers
+    }
+
+    /// Returns the Plumtree lazy push peers of the node (sorted in ascending order).
+    pub fn lazy_push_peers(&self) -> &[NodeId] {
+        &self.lazy_push_peers
+    }
+
+    /// Returns the number of the ticks executed by the node.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Returns the number of the messages cached by the node.
+    pub fn cached_messages(&self) -> u64 {
+        self.cached_messages
+    }
+
+    /// Returns the number of the messages that the node keeps track of (e.g., for counting duplicates).
+    pub fn known_messages(&self) -> u64 {
+        self.known_messages
+    }
+
+    /// Returns the number of the inbound messages waiting to be handled by the node.
+    pub fn pending_deliveries(&self) -> u64 {
+        self.pending_deliveries
+    }
+
+    /// Returns the value of the `plumcast_node_broadcasted_messages_total` metric.
+    pub fn broadcasted_messages(&self) -> u64 {
+        self.broadcasted_messages
+    }
+
+    /// Returns the value of the `plumcast_node_delivered_messages_total` metric.
+    pub fn delivered_messages(&self) -> u64 {
+        self.delivered_messages
+    }
+
+    /// Returns the value of the `plumcast_node_forgot_messages_total` metric.
+    pub fn forgot_messages(&self) -> u64 {
+        self.forgot_messages
+    }
+
+    /// Returns the value of the `plumcast_node_connected_neighbors_total` metric.
+    pub fn connected_neighbors(&self) -> u64 {
+        self.connected_neighbors
+    }
+
+    /// Returns the value of the `plumcast_node_disconnected_neighbors_total` metric.
+    pub fn disconnected_neighbors(&self) -> u64 {
+        self.disconnected_neighbors
+    }
+}
+
+/// A [`Future`] that fetches the statuses of the nodes registered in a service.
+///
+/// This is created by calling [`ServiceHandle::node_statuses`] method.
+/// The future fails if the service does not respond within a few seconds.
+///
+/// Note that each status is a snapshot taken at the last tick of the node.
+///
+/// [`Future`]: https://docs.rs/futures/0.1/futures/future/trait.Future.html
+/// [`ServiceHandle::node_statuses`]: ../service/struct.ServiceHandle.html#method.node_statuses
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct NodeStatusQuery(pub(crate) Response<Vec<NodeStatus>>);
+impl Future for NodeStatusQuery {
+    type Item = Vec<NodeStatus>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        track!(self.0.poll().map_err(Error::from))
+    }
+}
+
+/// A protocol event recorded by a node.
+///
+/// This is intended to be used for debugging.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct DebugEvent {
+    pub(crate) node: NodeId,
+    pub(crate) seqno: u64,
+    pub(crate) time: SystemTime,
+    pub(crate) kind: String,
+    pub(crate) peer: Option<NodeId>,
+    pub(crate) detail: String,
+}
+impl DebugEvent {
+    /// Returns the identifier of the node that recorded the event.
+    pub fn node(&self) -> NodeId {
+        self.node
+    }
+
+    /// Returns the sequence number of the event.
+    ///
+    /// Sequence numbers are assigned by each node in ascending order.
+    pub fn seqno(&self) -> u64 {
+        self.seqno
+    }
+
+    /// Returns the time when the event was recorded.
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+
+    /// Returns the kind of the event (e.g., `"send_plumtree"` or `"deliver"`).
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /// Returns the peer involved in the event, if any.
+    pub fn peer(&self) -> Option<NodeId> {
+        self.peer
+    }
+
+    /// Returns the human readable detail of the event (e.g., the identifier of a message).
+    pub fn detail(&self) -> &str {
+        &self.detail
+    }
+}
+
+/// A [`Future`] that fetches the recent protocol events kept by the nodes registered in a service.
+///
+/// This is created by calling [`ServiceHandle::debug_dump`] method.
+/// The future fails if the service does not respond within a few seconds.
+///
+/// [`Future`]: https://docs.rs/futures/0.1/futures/future/trait.Future.html
+/// [`ServiceHandle::debug_dump`]: ../service/struct.ServiceHandle.html#method.debug_dump
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct DebugDumpQuery(pub(crate) Response<Vec<DebugEvent>>);
+impl Future for DebugDumpQuery {
+    type Item = Vec<DebugEvent>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        track!(self.0.poll().map_err(Error::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameter_update_validation_works() {
+        assert!(ParameterUpdate::TickIntervalMultiplier(1.0)
+            .validate()
+            .is_ok());
+        assert!(ParameterUpdate::TickIntervalMultiplier(0.0)
+            .validate()
+            .is_err());
+        assert!(ParameterUpdate::TickIntervalMultiplier(std::f64::NAN)
+            .validate()
+            .is_err());
+
+        let d = Duration::from_secs(60);
+        assert!(ParameterUpdate::HyparviewShuffleInterval(d)
+            .validate()
+            .is_ok());
+        let d = Duration::from_millis(10);
+        assert!(ParameterUpdate::HyparviewShuffleInterval(d)
+            .validate()
+            .is_err());
+    }
+}
@@ -0,0 +1,462 @@
+//! [`Bridge`] and related components.
+//!
+//! [`Bridge`]: ./struct.Bridge.html
+use crate::message::{Message, MessageId, MessagePayload};
+use crate::node::Node;
+use crate::Error;
+use futures::{Async, Future, Poll, Stream};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// A [`Future`] that relays broadcasts between two clusters.
+///
+/// A bridge drives two nodes, each of which belongs to a different cluster
+/// (typically, the nodes are created from different [`Service`]s, and
+/// the payload types of the clusters may differ).
+/// The messages delivered to one node are converted by the given mapping function and
+/// rebroadcasted from the other node (see [`Node::rebroadcast`]).
+/// If the mapping function returns `None`, the message is not relayed.
+///
+/// Because rebroadcasting preserves the identifiers of messages,
+/// the provenance of each message is tracked by its [`MessageId`]:
+/// a message that has been relayed from one cluster is never relayed back to it,
+/// even if there are other bridges between the clusters.
+///
+/// The bridge is the application of both nodes,
+/// so relayed messages are forgot after the retention period has passed
+/// (see [`Bridge::retention`]).
+///
+/// The future completes when either of the nodes stops.
+///
+/// # Examples
+///
+/// ```no_run
+/// use futures::Future;
+/// use plumcast::bridge::Bridge;
+/// use plumcast::node::{Node, SerialLocalNodeIdGenerator};
+/// use plumcast::service::Service;
+///
+/// let left = Service::<String>::new(
+///     "127.0.0.1:4000".parse().unwrap(),
+///     fibers_global::handle(),
+///     SerialLocalNodeIdGenerator::new(),
+/// );
+/// let right = Service::<Vec<u8>>::new(
+///     "127.0.0.1:5000".parse().unwrap(),
+///     fibers_global::handle(),
+///     SerialLocalNodeIdGenerator::new(),
+/// );
+/// let bridge = Bridge::new(
+///     Node::new(left.handle()),
+///     Node::new(right.handle()),
+///     |s: String| Some(s.into_bytes()),
+///     |b: Vec<u8>| String::from_utf8(b).ok(),
+/// );
+/// fibers_global::spawn(left.map_err(|e| panic!("{}", e)));
+/// fibers_global::spawn(right.map_err(|e| panic!("{}", e)));
+/// fibers_global::spawn(bridge.map_err(|e| panic!("{}", e)));
+/// ```
+///
+/// [`Future`]: https://docs.rs/futures/0.1/futures/future/trait.Future.html
+/// [`Service`]: ../service/struct.Service.html
+/// [`Node::rebroadcast`]: ../node/struct.Node.html#method.rebroadcast
+/// [`MessageId`]: ../message/struct.MessageId.html
+/// [`Bridge::retention`]: ./struct.Bridge.html#method.retention
+#[must_use = "futures do nothing unless polled"]
+pub struct Bridge<A: MessagePayload, B: MessagePayload> {
+    left: Node<A>,
+    right: Node<B>,
+    left_to_right: Box<dyn FnMut(A) -> Option<B> + Send>,
+    right_to_left: Box<dyn FnMut(B) -> Option<A> + Send>,
+    retention: Duration,
+    origins: HashSet<MessageId>,
+    history: VecDeque<Relayed>,
+}
+impl<A: MessagePayload, B: MessagePayload> Bridge<A, B> {
+    /// Makes a new `Bridge` instance.
+    ///
+    /// `left_to_right` converts the payloads of the messages delivered to `left`
+    /// into the ones rebroadcasted from `right`, and `right_to_left` does the reverse.
+    pub fn new<F, G>(left: Node<A>, right: Node<B>, left_to_right: F, right_to_left: G) -> Self
+    where
+        F: FnMut(A) -> Option<B> + Send + 'static,
+        G: FnMut(B) -> Option<A> + Send + 'static,
+    {
+        Bridge {
+            left,
+            right,
+            left_to_right: Box::new(left_to_right),
+            right_to_left: Box::new(right_to_left),
+            retention: Duration::from_secs(60),
+            origins: HashSet::new(),
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Sets the period during which the nodes keep the relayed messages.
+    ///
+    /// After the period has passed, the messages are forgot by both nodes
+    /// (i.e., `Node::forget_message()` is called).
+    /// The period should be longer than the time taken to broadcast a message to
+    /// the whole clusters, otherwise the messages may be relayed more than once.
+    ///
+    /// The default value is `Duration::from_secs(60)`.
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Returns a reference to the left node.
+    pub fn left(&self) -> &Node<A> {
+        &self.left
+    }
+
+    /// Returns a mutable reference to the left node.
+    pub fn left_mut(&mut self) -> &mut Node<A> {
+        &mut self.left
+    }
+
+    /// Returns a reference to the right node.
+    pub fn right(&self) -> &Node<B> {
+        &self.right
+    }
+
+    /// Returns a mutable reference to the right node.
+    pub fn right_mut(&mut self) -> &mut Node<B> {
+        &mut self.right
+    }
+
+    /// Takes ownership of the bridge, and returns the left and right nodes.
+    pub fn into_nodes(self) -> (Node<A>, Node<B>) {
+        (self.left, self.right)
+    }
+
+    fn handle_left_message(&mut self, message: Message<A>) {
+        let id = *message.id();
+        if !self.start_relay(id, Side::Left) {
+            return;
+        }
+        let (f, right) = (&mut self.left_to_right, &mut self.right);
+        let rebroadcasted = message
+            .filter_map_payload(|p| f(p))
+            .map_or(false, |m| right.rebroadcast(m));
+        self.finish_relay(id, Side::Left, rebroadcasted);
+    }
+
+    fn handle_right_message(&mut self, message: Message<B>) {
+        let id = *message.id();
+        if !self.start_relay(id, Side::Right) {
+            return;
+        }
+        let (f, left) = (&mut self.right_to_left, &mut self.left);
+        let rebroadcasted = message
+            .filter_map_payload(|p| f(p))
+            .map_or(false, |m| left.rebroadcast(m));
+        self.finish_relay(id, Side::Right, rebroadcasted);
+    }
+
+    fn start_relay(&mut self, id: MessageId, from: Side) -> bool {
+        if self.origins.contains(&id) {
+            // The message has come back from the other cluster.
+            self.history.push_back(Relayed {
+                time: Instant::now(),
+                id,
+                from,
+                rebroadcasted: false,
+                origin: false,
+            });
+            return false;
+        }
+        self.origins.insert(id);
+        true
+    }
+
+    fn finish_relay(&mut self, id: MessageId, from: Side, rebroadcasted: bool) {
+        self.history.push_back(Relayed {
+            time: Instant::now(),
+            id,
+            from,
+            rebroadcasted,
+            origin: true,
+        });
+    }
+
+    fn forget_expired_messages(&mut self) {
+        let now = Instant::now();
+        while let Some(relayed) = self.history.front().cloned() {
+            if now.duration_since(relayed.time) < self.retention {
+                break;
+            }
+            self.history.pop_front();
+
+            let (left, right) = match relayed.from {
+                Side::Left => (true, relayed.rebroadcasted),
+                Side::Right => (relayed.rebroadcasted, true),
+            };
+            if left {
+                self.left.forget_message(&relayed.id);
+            }
+            if right {
+                self.right.forget_message(&relayed.id);
+            }
+            if relayed.origin {
+                self.origins.remove(&relayed.id);
+            }
+        }
+    }
+}
+impl<A: MessagePayload, B: MessagePayload> Future for Bridge<A, B> {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let mut did_something = false;
+            match track!(self.left.poll())? {
+                Async::NotReady => {}
+                Async::Ready(None) => return Ok(Async::Ready(())),
+                Async::Ready(Some(message)) => {
+                    self.handle_left_message(message);
+                    did_something = true;
+                }
+            }
+            match track!(self.right.poll())? {
+                Async::NotReady => {}
+                Async::Ready(None) => return Ok(Async::Ready(())),
+                Async::Ready(Some(message)) => {
+                    self.handle_right_message(message);
+                    did_something = true;
+                }
+            }
+            if !did_something {
+                break;
+            }
+        }
+
+        // NOTE: The nodes wake up this task at least every tick.
+        self.forget_expired_messages();
+        Ok(Async::NotReady)
+    }
+}
+impl<A: MessagePayload, B: MessagePayload> fmt::Debug for Bridge<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Bridge {{ left: {:?}, right: {:?}, retention: {:?}, .. }}",
+            self.left.id(),
+            self.right.id(),
+            self.retention
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone)]
+struct Relayed {
+    time: Instant,
+    id: MessageId,
+    from: Side,
+
+    // Whether the message has been rebroadcasted to the other side.
+    rebroadcasted: bool,
+
+    // Whether this is the first delivery of the message (i.e., the provenance of it).
+    origin: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+    use crate::node::{NodeBuilder, NodeId};
+    use crate::service::Service;
+    use crate::testing::{in_memory_service, poll_with, InMemoryOutbox, Wakeup};
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    // Two clusters connected by bridges, which messages are transmitted in memory.
+    struct Network {
+        clock: Clock,
+        wakeup: Arc<Wakeup>,
+        services: Vec<(Service<String>, InMemoryOutbox<String>)>,
+        members: Vec<Node<String>>,
+        bridges: Vec<Bridge<String, String>>,
+        deliveries: HashMap<NodeId, Vec<Message<String>>>,
+    }
+    impl Network {
+        fn new(members_per_cluster: usize, bridges: usize, retention: Duration) -> Self {
+            let clock = Clock::new();
+            let services = vec![
+                in_memory_service(SocketAddr::from(([127, 0, 0, 1], 1))),
+                in_memory_service(SocketAddr::from(([127, 0, 0, 1], 2))),
+            ];
+            let mut builder = NodeBuilder::new();
+            builder.clock(clock.clone());
+
+            let mut members = Vec::new();
+            for (service, _) in &services {
+                let contact = builder.finish(service.handle());
+                let contact_id = contact.id();
+                members.push(contact);
+                for _ in 1..members_per_cluster {
+                    let mut node = builder.finish(service.handle());
+                    node.join(contact_id);
+                    members.push(node);
+                }
+            }
+            let bridges = (0..bridges)
+                .map(|_| {
+                    let mut left = builder.finish(services[0].0.handle());
+                    let mut right = builder.finish(services[1].0.handle());
+                    left.join(members[0].id());
+                    right.join(members[members_per_cluster].id());
+                    Bridge::new(left, right, Some, Some).retention(retention)
+                })
+                .collect();
+
+            let mut network = Network {
+                clock,
+                wakeup: Arc::new(Wakeup::default()),
+                services,
+                members,
+                bridges,
+                deliveries: HashMap::new(),
+            };
+            for _ in 0..10 {
+                network.tick();
+            }
+            network
+        }
+
+        fn tick(&mut self) {
+            self.clock.tick(Duration::from_millis(200));
+            self.flush();
+        }
+
+        fn flush(&mut self) {
+            loop {
+                self.wakeup.reset();
+                let mut did_something = false;
+                for (service, _) in &mut self.services {
+                    poll_with(&self.wakeup, || service.poll()).unwrap();
+                }
+                for node in &mut self.members {
+                    while let Async::Ready(Some(m)) =
+                        poll_with(&self.wakeup, || node.poll()).unwrap()
+                    {
+                        self.deliveries
+                            .entry(node.id())
+                            .or_insert_with(Vec::new)
+                            .push(m);
+                        did_something = true;
+                    }
+                }
+                for bridge in &mut self.bridges {
+                    poll_with(&self.wakeup, || bridge.poll()).unwrap();
+                }
+                for i in 0..self.services.len() {
+                    for (destination, message) in self.services[i].1.take() {
+                        let handle = self
+                            .services
+                            .iter()
+                            .map(|s| s.0.handle())
+                            .find(|h| h.rpc_server_addr() == destination.address());
+                        if let Some(node) =
+                            handle.and_then(|h| h.get_local_node(destination.local_id()))
+                        {
+                            node.send_rpc_message(message);
+                        }
+                        did_something = true;
+                    }
+                }
+                if !did_something && !self.wakeup.is_notified() {
+                    break;
+                }
+            }
+        }
+
+        fn delivery_count(&self, node: &Node<String>, id: &MessageId) -> usize {
+            self.deliveries
+                .get(&node.id())
+                .map_or(0, |ms| ms.iter().filter(|m| m.id() == id).count())
+        }
+
+        fn relays(&self, id: &MessageId, from: Side) -> usize {
+            self.bridges
+                .iter()
+                .flat_map(|b| b.history.iter())
+                .filter(|r| r.id == *id && r.from == from && r.rebroadcasted)
+                .count()
+        }
+    }
+
+    #[test]
+    fn relayed_messages_are_not_relayed_back() {
+        let mut network = Network::new(3, 1, Duration::from_secs(60));
+        let id = network.members[1].broadcast("foo".to_owned());
+        network.flush();
+
+        for node in &network.members {
+            assert_eq!(network.delivery_count(node, &id), 1);
+        }
+        assert_eq!(network.relays(&id, Side::Left), 1);
+        assert_eq!(network.relays(&id, Side::Right), 0);
+
+        for _ in 0..10 {
+            network.tick();
+        }
+        for node in &network.members {
+            assert_eq!(network.delivery_count(node, &id), 1);
+        }
+    }
+
+    #[test]
+    fn messages_are_relayed_once_by_multiple_bridges() {
+        let mut network = Network::new(3, 3, Duration::from_secs(60));
+        let id = network.members[4].broadcast("foo".to_owned());
+        network.flush();
+        for _ in 0..10 {
+            network.tick();
+        }
+
+        for node in &network.members {
+            assert_eq!(network.delivery_count(node, &id), 1);
+        }
+
+        // Each bridge relays the message at most once, whichever node delivers it first.
+        assert!(network.relays(&id, Side::Right) >= 1);
+        for bridge in &network.bridges {
+            assert!(bridge.origins.contains(&id));
+            let relayed = bridge.history.iter().filter(|r| r.id == id && r.origin);
+            assert_eq!(relayed.count(), 1);
+        }
+    }
+
+    #[test]
+    fn relayed_messages_are_forgot_after_retention() {
+        let mut network = Network::new(2, 1, Duration::from_secs(60));
+        let id = network.members[0].broadcast("foo".to_owned());
+        network.flush();
+        assert!(network.bridges[0].origins.contains(&id));
+        assert!(!network.bridges[0].history.is_empty());
+
+        let mut network = Network::new(2, 1, Duration::from_secs(0));
+        let id = network.members[0].broadcast("foo".to_owned());
+        network.flush();
+        assert_eq!(network.delivery_count(&network.members[3], &id), 1);
+        assert!(network.bridges[0].origins.is_empty());
+        assert!(network.bridges[0].history.is_empty());
+
+        // Both nodes of the bridge have forgot the message.
+        let message = network.deliveries[&network.members[3].id()][0].clone();
+        assert!(network.bridges[0].left_mut().rebroadcast(message.clone()));
+        assert!(network.bridges[0].right_mut().rebroadcast(message));
+    }
+}
@@ -0,0 +1,134 @@
+//! Clocks driving the periodic operations of nodes.
+//!
+//! By default, each node has its own timer that fires at every tick interval
+//! (see `NodeBuilder::tick_interval()`).
+//! If a [`Clock`] is given by [`NodeBuilder::clock`], the node is driven by the ticks of the clock
+//! instead, so multiple nodes in a process can share a single timer, and
+//! simulations can drive nodes manually (by calling [`Clock::tick`]) without relying on real time.
+//!
+//! [`Clock`]: ./struct.Clock.html
+//! [`Clock::tick`]: ./struct.Clock.html#method.tick
+//! [`NodeBuilder::clock`]: ../node/struct.NodeBuilder.html#method.clock
+use crate::Error;
+use fibers::sync::mpsc;
+use fibers::time::timer::{self, Timeout};
+use futures::{Async, Future, Poll};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A source of ticks shared by nodes.
+///
+/// A clock is a handle: the cloned instances share the same subscribers.
+#[derive(Clone, Default)]
+pub struct Clock {
+    subscribers: Arc<Mutex<Vec<mpsc::Sender<Duration>>>>,
+}
+impl Clock {
+    /// Makes a new `Clock` instance.
+    ///
+    /// The clock does not tick until [`tick`] is called or it is driven by [`drive`].
+    ///
+    /// [`tick`]: #method.tick
+    /// [`drive`]: #method.drive
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advances the clocks of all the nodes subscribing to this clock by `elapsed`.
+    ///
+    /// Each node executes a tick (e.g., retransmitting `GRAFT` messages and
+    /// the periodic HyParView operations) the next time it is polled.
+    pub fn tick(&self, elapsed: Duration) {
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        subscribers.retain(|tx| tx.send(elapsed).is_ok());
+    }
+
+    /// Returns a future that ticks the clock every `interval`.
+    ///
+    /// The future never completes, so it should be spawned (or polled) along with the nodes.
+    pub fn drive(&self, interval: Duration) -> ClockDriver {
+        ClockDriver {
+            clock: self.clone(),
+            interval,
+            timeout: timer::timeout(interval),
+        }
+    }
+
+    /// Returns the number of the nodes subscribing to this clock.
+    pub fn subscribers(&self) -> usize {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .len()
+    }
+
+    pub(crate) fn subscribe(&self) -> mpsc::Receiver<Duration> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(tx);
+        rx
+    }
+}
+impl fmt::Debug for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Clock {{ subscribers: {} }}", self.subscribers())
+    }
+}
+
+/// A [`Future`] that ticks a [`Clock`] periodically.
+///
+/// This is created by [`Clock::drive`].
+///
+/// [`Future`]: https://docs.rs/futures/0.1/futures/future/trait.Future.html
+/// [`Clock`]: ./struct.Clock.html
+/// [`Clock::drive`]: ./struct.Clock.html#method.drive
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct ClockDriver {
+    clock: Clock,
+    interval: Duration,
+    timeout: Timeout,
+}
+impl ClockDriver {
+    /// Returns a reference to the clock driven by this future.
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+}
+impl Future for ClockDriver {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        while track!(self.timeout.poll().map_err(Error::from))?.is_ready() {
+            self.timeout = timer::timeout(self.interval);
+            self.clock.tick(self.interval);
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Stream;
+
+    #[test]
+    fn ticks_are_fanned_out_to_subscribers() {
+        let clock = Clock::new();
+        let mut rx0 = clock.subscribe();
+        let rx1 = clock.subscribe();
+        assert_eq!(clock.subscribers(), 2);
+
+        std::mem::drop(rx1);
+        clock.tick(Duration::from_millis(100));
+        assert_eq!(clock.subscribers(), 1);
+        assert_eq!(
+            rx0.poll().unwrap(),
+            Async::Ready(Some(Duration::from_millis(100)))
+        );
+    }
+}
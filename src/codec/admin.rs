@@ -0,0 +1,681 @@
+use super::net::{SocketAddrDecoder, SocketAddrEncoder};
+use super::node::{LocalNodeIdDecoder, LocalNodeIdEncoder, NodeIdDecoder, NodeIdEncoder};
+use crate::admin::{DebugEvent, Health, NodeStatus, ParameterUpdate};
+use crate::node::{LocalNodeId, NodeId};
+use bytecodec::fixnum::{
+    U32beDecoder, U32beEncoder, U64beDecoder, U64beEncoder, U8Decoder, U8Encoder,
+};
+use bytecodec::{ByteCount, Decode, Encode, Eos, ErrorKind, Result, SizedEncode};
+use std::fmt;
+use std::mem;
+use std::net::SocketAddr;
+use std::time::{Duration, UNIX_EPOCH};
+use std::vec;
+
+const TAG_TICK_INTERVAL_MULTIPLIER: u8 = 0;
+const TAG_HYPARVIEW_SHUFFLE_INTERVAL: u8 = 1;
+
+#[derive(Debug, Default)]
+pub struct ParameterUpdateMessageDecoder {
+    destination: LocalNodeIdDecoder,
+    caller: SocketAddrDecoder,
+    tag: U8Decoder,
+    value: U64beDecoder,
+}
+impl Decode for ParameterUpdateMessageDecoder {
+    type Item = (LocalNodeId, SocketAddr, ParameterUpdate);
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_decode!(self.destination, offset, buf, eos);
+        bytecodec_try_decode!(self.caller, offset, buf, eos);
+        bytecodec_try_decode!(self.tag, offset, buf, eos);
+        bytecodec_try_decode!(self.value, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let destination = track!(self.destination.finish_decoding())?;
+        let caller = track!(self.caller.finish_decoding())?;
+        let tag = track!(self.tag.finish_decoding())?;
+        let value = track!(self.value.finish_decoding())?;
+        let update = match tag {
+            TAG_TICK_INTERVAL_MULTIPLIER => {
+                ParameterUpdate::TickIntervalMultiplier(f64::from_bits(value))
+            }
+            TAG_HYPARVIEW_SHUFFLE_INTERVAL => {
+                ParameterUpdate::HyparviewShuffleInterval(Duration::from_millis(value))
+            }
+            _ => track_panic!(ErrorKind::InvalidInput, "Unknown parameter: {}", tag),
+        };
+        Ok((destination, caller, update))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.destination
+            .requiring_bytes()
+            .add_for_decoding(self.caller.requiring_bytes())
+            .add_for_decoding(self.tag.requiring_bytes())
+            .add_for_decoding(self.value.requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.value.is_idle()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ParameterUpdateMessageEncoder {
+    destination: LocalNodeIdEncoder,
+    caller: SocketAddrEncoder,
+    tag: U8Encoder,
+    value: U64beEncoder,
+}
+impl Encode for ParameterUpdateMessageEncoder {
+    type Item = (LocalNodeId, SocketAddr, ParameterUpdate);
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_encode!(self.destination, offset, buf, eos);
+        bytecodec_try_encode!(self.caller, offset, buf, eos);
+        bytecodec_try_encode!(self.tag, offset, buf, eos);
+        bytecodec_try_encode!(self.value, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        let (tag, value) = match item.2 {
+            ParameterUpdate::TickIntervalMultiplier(m) => {
+                (TAG_TICK_INTERVAL_MULTIPLIER, m.to_bits())
+            }
+            ParameterUpdate::HyparviewShuffleInterval(d) => (
+                TAG_HYPARVIEW_SHUFFLE_INTERVAL,
+                d.as_secs() * 1000 + u64::from(d.subsec_millis()),
+            ),
+        };
+        track!(self.destination.start_encoding(item.0))?;
+        track!(self.caller.start_encoding(item.1))?;
+        track!(self.tag.start_encoding(tag))?;
+        track!(self.value.start_encoding(value))?;
+        Ok(())
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Finite(self.exact_requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.value.is_idle()
+    }
+}
+impl SizedEncode for ParameterUpdateMessageEncoder {
+    fn exact_requiring_bytes(&self) -> u64 {
+        self.destination.exact_requiring_bytes()
+            + self.caller.exact_requiring_bytes()
+            + self.tag.exact_requiring_bytes()
+            + self.value.exact_requiring_bytes()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct HealthDecoder {
+    local_nodes: U64beDecoder,
+}
+impl Decode for HealthDecoder {
+    type Item = Health;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        track!(self.local_nodes.decode(buf, eos))
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let local_nodes = track!(self.local_nodes.finish_decoding())?;
+        Ok(Health { local_nodes })
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.local_nodes.requiring_bytes()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.local_nodes.is_idle()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct HealthEncoder {
+    local_nodes: U64beEncoder,
+}
+impl Encode for HealthEncoder {
+    type Item = Health;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        track!(self.local_nodes.encode(buf, eos))
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        track!(self.local_nodes.start_encoding(item.local_nodes))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Finite(self.exact_requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.local_nodes.is_idle()
+    }
+}
+impl SizedEncode for HealthEncoder {
+    fn exact_requiring_bytes(&self) -> u64 {
+        self.local_nodes.exact_requiring_bytes()
+    }
+}
+
+/// Length-prefixed list decoder.
+pub struct ListDecoder<D: Decode> {
+    len: U32beDecoder,
+    remaining: Option<u32>,
+    item: D,
+    items: Vec<D::Item>,
+}
+impl<D: Decode + Default> Default for ListDecoder<D> {
+    fn default() -> Self {
+        ListDecoder {
+            len: U32beDecoder::default(),
+            remaining: None,
+            item: D::default(),
+            items: Vec::new(),
+        }
+    }
+}
+impl<D: Decode> fmt::Debug for ListDecoder<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ListDecoder {{ remaining: {:?}, .. }}", self.remaining)
+    }
+}
+impl<D: Decode> Decode for ListDecoder<D> {
+    type Item = Vec<D::Item>;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        if self.remaining.is_none() {
+            bytecodec_try_decode!(self.len, offset, buf, eos);
+            self.remaining = Some(track!(self.len.finish_decoding())?);
+        }
+        while let Some(remaining) = self.remaining.filter(|&n| n > 0) {
+            bytecodec_try_decode!(self.item, offset, buf, eos);
+            self.items.push(track!(self.item.finish_decoding())?);
+            self.remaining = Some(remaining - 1);
+        }
+        Ok(offset)
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        track_assert_eq!(self.remaining, Some(0), ErrorKind::IncompleteDecoding);
+        self.remaining = None;
+        Ok(mem::replace(&mut self.items, Vec::new()))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        match self.remaining {
+            None => self.len.requiring_bytes(),
+            Some(0) => ByteCount::Finite(0),
+            Some(_) => ByteCount::Unknown,
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.remaining == Some(0)
+    }
+}
+
+/// Length-prefixed list encoder.
+pub struct ListEncoder<E: Encode> {
+    len: U32beEncoder,
+    item: E,
+    items: vec::IntoIter<E::Item>,
+}
+impl<E: Encode + Default> Default for ListEncoder<E> {
+    fn default() -> Self {
+        ListEncoder {
+            len: U32beEncoder::default(),
+            item: E::default(),
+            items: Vec::new().into_iter(),
+        }
+    }
+}
+impl<E: Encode> fmt::Debug for ListEncoder<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ListEncoder {{ remaining: {}, .. }}", self.items.len())
+    }
+}
+impl<E: Encode> Encode for ListEncoder<E> {
+    type Item = Vec<E::Item>;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_encode!(self.len, offset, buf, eos);
+        loop {
+            bytecodec_try_encode!(self.item, offset, buf, eos);
+            if let Some(item) = self.items.next() {
+                track!(self.item.start_encoding(item))?;
+            } else {
+                break;
+            }
+        }
+        Ok(offset)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        track_assert!(
+            item.len() <= std::u32::MAX as usize,
+            ErrorKind::InvalidInput,
+            "Too many items: {}",
+            item.len()
+        );
+        track!(self.len.start_encoding(item.len() as u32))?;
+        self.items = item.into_iter();
+        Ok(())
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        if self.is_idle() {
+            ByteCount::Finite(0)
+        } else {
+            ByteCount::Unknown
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.len.is_idle() && self.item.is_idle() && self.items.len() == 0
+    }
+}
+
+// NOTE: The counters of a status are encoded as a list in this order,
+// so that new ones can be appended without breaking compatibility.
+const STATUS_COUNTERS: usize = 9;
+
+#[derive(Debug, Default)]
+pub struct NodeStatusDecoder {
+    id: NodeIdDecoder,
+    active_view: ListDecoder<NodeIdDecoder>,
+    passive_view: ListDecoder<NodeIdDecoder>,
+    eager_push_peers: ListDecoder<NodeIdDecoder>,
+    lazy_push_peers: ListDecoder<NodeIdDecoder>,
+    counters: ListDecoder<U64beDecoder>,
+}
+impl Decode for NodeStatusDecoder {
+    type Item = NodeStatus;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_decode!(self.id, offset, buf, eos);
+        bytecodec_try_decode!(self.active_view, offset, buf, eos);
+        bytecodec_try_decode!(self.passive_view, offset, buf, eos);
+        bytecodec_try_decode!(self.eager_push_peers, offset, buf, eos);
+        bytecodec_try_decode!(self.lazy_push_peers, offset, buf, eos);
+        bytecodec_try_decode!(self.counters, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let id = track!(self.id.finish_decoding())?;
+        let active_view = track!(self.active_view.finish_decoding())?;
+        let passive_view = track!(self.passive_view.finish_decoding())?;
+        let eager_push_peers = track!(self.eager_push_peers.finish_decoding())?;
+        let lazy_push_peers = track!(self.lazy_push_peers.finish_decoding())?;
+        let mut counters = track!(self.counters.finish_decoding())?;
+        counters.resize(counters.len().max(STATUS_COUNTERS), 0);
+        Ok(NodeStatus {
+            id,
+            active_view,
+            passive_view,
+            eager_push_peers,
+            lazy_push_peers,
+            ticks: counters[0],
+            cached_messages: counters[1],
+            known_messages: counters[2],
+            pending_deliveries: counters[3],
+            broadcasted_messages: counters[4],
+            delivered_messages: counters[5],
+            forgot_messages: counters[6],
+            connected_neighbors: counters[7],
+            disconnected_neighbors: counters[8],
+        })
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Unknown
+    }
+
+    fn is_idle(&self) -> bool {
+        self.counters.is_idle()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct NodeStatusEncoder {
+    id: NodeIdEncoder,
+    active_view: ListEncoder<NodeIdEncoder>,
+    passive_view: ListEncoder<NodeIdEncoder>,
+    eager_push_peers: ListEncoder<NodeIdEncoder>,
+    lazy_push_peers: ListEncoder<NodeIdEncoder>,
+    counters: ListEncoder<U64beEncoder>,
+}
+impl Encode for NodeStatusEncoder {
+    type Item = NodeStatus;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_encode!(self.id, offset, buf, eos);
+        bytecodec_try_encode!(self.active_view, offset, buf, eos);
+        bytecodec_try_encode!(self.passive_view, offset, buf, eos);
+        bytecodec_try_encode!(self.eager_push_peers, offset, buf, eos);
+        bytecodec_try_encode!(self.lazy_push_peers, offset, buf, eos);
+        bytecodec_try_encode!(self.counters, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        let counters = vec![
+            item.ticks,
+            item.cached_messages,
+            item.known_messages,
+            item.pending_deliveries,
+            item.broadcasted_messages,
+            item.delivered_messages,
+            item.forgot_messages,
+            item.connected_neighbors,
+            item.disconnected_neighbors,
+        ];
+        debug_assert_eq!(counters.len(), STATUS_COUNTERS);
+        track!(self.id.start_encoding(item.id))?;
+        track!(self.active_view.start_encoding(item.active_view))?;
+        track!(self.passive_view.start_encoding(item.passive_view))?;
+        track!(self.eager_push_peers.start_encoding(item.eager_push_peers))?;
+        track!(self.lazy_push_peers.start_encoding(item.lazy_push_peers))?;
+        track!(self.counters.start_encoding(counters))?;
+        Ok(())
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        if self.is_idle() {
+            ByteCount::Finite(0)
+        } else {
+            ByteCount::Unknown
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.id.is_idle()
+            && self.active_view.is_idle()
+            && self.passive_view.is_idle()
+            && self.eager_push_peers.is_idle()
+            && self.lazy_push_peers.is_idle()
+            && self.counters.is_idle()
+    }
+}
+
+/// Length-prefixed UTF-8 string decoder.
+#[derive(Debug, Default)]
+pub struct StringDecoder(ListDecoder<U8Decoder>);
+impl Decode for StringDecoder {
+    type Item = String;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        track!(self.0.decode(buf, eos))
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let bytes = track!(self.0.finish_decoding())?;
+        let s = track_assert_some!(String::from_utf8(bytes).ok(), ErrorKind::InvalidInput);
+        Ok(s)
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.0.requiring_bytes()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.0.is_idle()
+    }
+}
+
+/// Length-prefixed UTF-8 string encoder.
+#[derive(Debug, Default)]
+pub struct StringEncoder(ListEncoder<U8Encoder>);
+impl Encode for StringEncoder {
+    type Item = String;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        track!(self.0.encode(buf, eos))
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        track!(self.0.start_encoding(item.into_bytes()))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.0.requiring_bytes()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.0.is_idle()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DebugEventDecoder {
+    node: NodeIdDecoder,
+    seqno: U64beDecoder,
+    unixtime_millis: U64beDecoder,
+    kind: StringDecoder,
+    peer: ListDecoder<NodeIdDecoder>,
+    detail: StringDecoder,
+}
+impl Decode for DebugEventDecoder {
+    type Item = DebugEvent;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_decode!(self.node, offset, buf, eos);
+        bytecodec_try_decode!(self.seqno, offset, buf, eos);
+        bytecodec_try_decode!(self.unixtime_millis, offset, buf, eos);
+        bytecodec_try_decode!(self.kind, offset, buf, eos);
+        bytecodec_try_decode!(self.peer, offset, buf, eos);
+        bytecodec_try_decode!(self.detail, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let node = track!(self.node.finish_decoding())?;
+        let seqno = track!(self.seqno.finish_decoding())?;
+        let unixtime_millis = track!(self.unixtime_millis.finish_decoding())?;
+        let kind = track!(self.kind.finish_decoding())?;
+        let peer = track!(self.peer.finish_decoding())?;
+        let detail = track!(self.detail.finish_decoding())?;
+        track_assert!(
+            peer.len() <= 1,
+            ErrorKind::InvalidInput,
+            "Too many peers: {}",
+            peer.len()
+        );
+        Ok(DebugEvent {
+            node,
+            seqno,
+            time: UNIX_EPOCH + Duration::from_millis(unixtime_millis),
+            kind,
+            peer: peer.into_iter().next(),
+            detail,
+        })
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Unknown
+    }
+
+    fn is_idle(&self) -> bool {
+        self.detail.is_idle()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DebugEventEncoder {
+    node: NodeIdEncoder,
+    seqno: U64beEncoder,
+    unixtime_millis: U64beEncoder,
+    kind: StringEncoder,
+    peer: ListEncoder<NodeIdEncoder>,
+    detail: StringEncoder,
+}
+impl Encode for DebugEventEncoder {
+    type Item = DebugEvent;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_encode!(self.node, offset, buf, eos);
+        bytecodec_try_encode!(self.seqno, offset, buf, eos);
+        bytecodec_try_encode!(self.unixtime_millis, offset, buf, eos);
+        bytecodec_try_encode!(self.kind, offset, buf, eos);
+        bytecodec_try_encode!(self.peer, offset, buf, eos);
+        bytecodec_try_encode!(self.detail, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        let elapsed = item
+            .time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        let unixtime_millis = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+        track!(self.node.start_encoding(item.node))?;
+        track!(self.seqno.start_encoding(item.seqno))?;
+        track!(self.unixtime_millis.start_encoding(unixtime_millis))?;
+        track!(self.kind.start_encoding(item.kind))?;
+        track!(self.peer.start_encoding(item.peer.into_iter().collect()))?;
+        track!(self.detail.start_encoding(item.detail))?;
+        Ok(())
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        if self.is_idle() {
+            ByteCount::Finite(0)
+        } else {
+            ByteCount::Unknown
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.node.is_idle()
+            && self.seqno.is_idle()
+            && self.unixtime_millis.is_idle()
+            && self.kind.is_idle()
+            && self.peer.is_idle()
+            && self.detail.is_idle()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytecodec::{DecodeExt, EncodeExt};
+
+    #[test]
+    fn parameter_update_message_codec_works() {
+        let updates = [
+            ParameterUpdate::TickIntervalMultiplier(1.5),
+            ParameterUpdate::HyparviewShuffleInterval(Duration::from_millis(1500)),
+        ];
+        for &update in &updates {
+            let item = (
+                LocalNodeId::new(3),
+                "127.0.0.1:3000".parse().unwrap(),
+                update,
+            );
+            let bytes = ParameterUpdateMessageEncoder::default()
+                .encode_into_bytes(item)
+                .unwrap();
+            let decoded = ParameterUpdateMessageDecoder::default()
+                .decode_from_bytes(&bytes)
+                .unwrap();
+            assert_eq!(decoded, item);
+        }
+    }
+
+    #[test]
+    fn health_codec_works() {
+        let health = Health { local_nodes: 3 };
+        let bytes = HealthEncoder::default()
+            .encode_into_bytes(health.clone())
+            .unwrap();
+        let decoded = HealthDecoder::default().decode_from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, health);
+    }
+
+    #[test]
+    fn node_statuses_codec_works() {
+        let node = |i| NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(i));
+        let status = NodeStatus {
+            id: node(0),
+            active_view: vec![node(1), node(2)],
+            passive_view: vec![node(3)],
+            eager_push_peers: vec![node(1)],
+            lazy_push_peers: vec![node(2)],
+            ticks: 10,
+            cached_messages: 3,
+            known_messages: 4,
+            pending_deliveries: 0,
+            broadcasted_messages: 1,
+            delivered_messages: 2,
+            forgot_messages: 0,
+            connected_neighbors: 2,
+            disconnected_neighbors: 0,
+        };
+        let empty = NodeStatus {
+            id: node(4),
+            active_view: Vec::new(),
+            passive_view: Vec::new(),
+            eager_push_peers: Vec::new(),
+            lazy_push_peers: Vec::new(),
+            ..status.clone()
+        };
+        let statuses = vec![status, empty];
+
+        let bytes = ListEncoder::<NodeStatusEncoder>::default()
+            .encode_into_bytes(statuses.clone())
+            .unwrap();
+        let decoded = ListDecoder::<NodeStatusDecoder>::default()
+            .decode_from_bytes(&bytes)
+            .unwrap();
+        assert_eq!(decoded, statuses);
+    }
+
+    #[test]
+    fn debug_events_codec_works() {
+        let node = |i| NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(i));
+        let event = DebugEvent {
+            node: node(0),
+            seqno: 3,
+            time: UNIX_EPOCH + Duration::from_millis(1_500_000_000_123),
+            kind: "send_plumtree".to_owned(),
+            peer: Some(node(1)),
+            detail: "gossip 127.0.0.1:3000:0#7".to_owned(),
+        };
+        let empty = DebugEvent {
+            seqno: 4,
+            kind: "tick".to_owned(),
+            peer: None,
+            detail: String::new(),
+            ..event.clone()
+        };
+        let events = vec![event, empty];
+
+        let bytes = ListEncoder::<DebugEventEncoder>::default()
+            .encode_into_bytes(events.clone())
+            .unwrap();
+        let decoded = ListDecoder::<DebugEventDecoder>::default()
+            .decode_from_bytes(&bytes)
+            .unwrap();
+        assert_eq!(decoded, events);
+    }
+}
@@ -0,0 +1,74 @@
+//! Helpers for benchmarking the codecs of RPC messages (used by `benches/codec.rs`).
+use super::plumtree::{
+    GossipExtensionFields, GossipMessageDecoder, GossipMessageEncoder, IhaveMessageDecoder,
+    IhaveMessageEncoder, VarintIhaveMessageDecoder, VarintIhaveMessageEncoder,
+};
+use super::version::{VersionedDecoder, VersionedEncoder};
+use crate::message::{Envelope, MessageId};
+use crate::misc::{GossipMessage, IhaveMessage, PlumtreeAppMessage};
+use crate::node::{LocalNodeId, NodeId};
+use crate::protocol::WireCodec;
+use bytecodec::{DecodeExt, EncodeExt, Result};
+
+fn sender() -> NodeId {
+    NodeId::new(([127, 0, 0, 1], 3000).into(), LocalNodeId::new(1))
+}
+
+/// Encodes a gossip frame carrying `payload`.
+pub fn encode_gossip_frame(seqno: u64, payload: Vec<u8>) -> Result<Vec<u8>> {
+    let m = GossipMessage {
+        sender: sender(),
+        round: 1,
+        message: PlumtreeAppMessage {
+            id: MessageId::new(sender(), seqno),
+            payload: Envelope::new(payload),
+        },
+    };
+    track!(
+        VersionedEncoder::<GossipMessageEncoder<Vec<u8>>, GossipExtensionFields>::default()
+            .encode_into_bytes((LocalNodeId::new(2), m))
+    )
+}
+
+/// Decodes a gossip frame, and returns the size of the payload.
+pub fn decode_gossip_frame(frame: &[u8]) -> Result<usize> {
+    let (_, m) = track!(
+        VersionedDecoder::<GossipMessageDecoder<Vec<u8>>, GossipExtensionFields>::default()
+            .decode_from_bytes(frame)
+    )?;
+    Ok(m.message.payload.payload.len())
+}
+
+/// Encodes an `IHAVE` frame by using the given codec.
+pub fn encode_ihave_frame(codec: WireCodec, seqno: u64) -> Result<Vec<u8>> {
+    let m = IhaveMessage::<Vec<u8>> {
+        sender: sender(),
+        round: 1,
+        message_id: MessageId::new(sender(), seqno),
+        realtime: true,
+    };
+    let item = (LocalNodeId::new(2), m);
+    match codec {
+        WireCodec::Fixed => {
+            track!(VersionedEncoder::<IhaveMessageEncoder<_>>::default().encode_into_bytes(item))
+        }
+        WireCodec::Varint => track!(
+            VersionedEncoder::<VarintIhaveMessageEncoder<_>>::default().encode_into_bytes(item)
+        ),
+    }
+}
+
+/// Decodes an `IHAVE` frame encoded by the given codec, and returns the message identifier.
+pub fn decode_ihave_frame(codec: WireCodec, frame: &[u8]) -> Result<MessageId> {
+    let (_, m) = match codec {
+        WireCodec::Fixed => {
+            track!(VersionedDecoder::<IhaveMessageDecoder<Vec<u8>>>::default()
+                .decode_from_bytes(frame))?
+        }
+        WireCodec::Varint => track!(
+            VersionedDecoder::<VarintIhaveMessageDecoder<Vec<u8>>>::default()
+                .decode_from_bytes(frame)
+        )?,
+    };
+    Ok(m.message_id)
+}
@@ -0,0 +1,295 @@
+//! Extension section of RPC frames.
+//!
+//! The section consists of the number of entries (`u16`) followed by TLV encoded entries
+//! (`tag: u16`, `length: u16` and `value: [u8; length]`).
+//! Decoders skip the entries having unknown tags, so new optional fields
+//! (e.g., timestamps, trace identifiers and authentication tags) can be added
+//! without breaking the peers that do not know them.
+use bytecodec::bytes::{BytesEncoder, RemainingBytesDecoder};
+use bytecodec::combinator::{Length, Peekable};
+use bytecodec::fixnum::{U16beDecoder, U16beEncoder};
+use bytecodec::{ByteCount, Decode, Encode, Eos, ErrorKind, Result, SizedEncode};
+use std::collections::VecDeque;
+
+/// The maximum length of the value of an extension.
+pub const MAX_EXTENSION_VALUE_LEN: usize = 0xFFFF;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    pub tag: u16,
+    pub value: Vec<u8>,
+}
+
+/// This trait allows for carrying some fields of frame bodies (which type is `T`)
+/// in the extension section.
+pub trait ExtensionFields<T> {
+    /// Returns the extensions representing the fields of the given item.
+    ///
+    /// `version` is the protocol version of the frame being encoded,
+    /// and the extensions introduced by later versions must be omitted.
+    fn to_extensions(item: &T, version: u8) -> Vec<Extension>;
+
+    /// Sets the fields of the given item from the decoded extensions.
+    ///
+    /// Unknown extensions must be ignored.
+    fn apply_extensions(item: &mut T, extensions: &[Extension]);
+}
+
+/// An `ExtensionFields` implementation for frames that have no extension fields.
+#[derive(Debug, Default)]
+pub struct NoExtensionFields;
+impl<T> ExtensionFields<T> for NoExtensionFields {
+    fn to_extensions(_item: &T, _version: u8) -> Vec<Extension> {
+        Vec::new()
+    }
+
+    fn apply_extensions(_item: &mut T, _extensions: &[Extension]) {}
+}
+
+#[derive(Debug, Default)]
+pub struct ExtensionsDecoder {
+    count: Peekable<U16beDecoder>,
+    remaining: u16,
+    entry: ExtensionDecoder,
+    extensions: Vec<Extension>,
+}
+impl Decode for ExtensionsDecoder {
+    type Item = Vec<Extension>;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        if !self.count.is_idle() {
+            bytecodec_try_decode!(self.count, offset, buf, eos);
+            self.remaining = self.count.peek().cloned().expect("Never fails");
+        }
+        while self.remaining > 0 {
+            bytecodec_try_decode!(self.entry, offset, buf, eos);
+            let extension = track!(self.entry.finish_decoding())?;
+            self.extensions.push(extension);
+            self.remaining -= 1;
+        }
+        Ok(offset)
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        track_assert_eq!(self.remaining, 0, ErrorKind::IncompleteDecoding);
+        let _ = track!(self.count.finish_decoding())?;
+        Ok(std::mem::replace(&mut self.extensions, Vec::new()))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        if !self.count.is_idle() {
+            self.count.requiring_bytes()
+        } else if self.remaining == 0 {
+            ByteCount::Finite(0)
+        } else {
+            ByteCount::Unknown
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.count.is_idle() && self.remaining == 0
+    }
+}
+
+#[derive(Debug, Default)]
+struct ExtensionDecoder {
+    tag: U16beDecoder,
+    len: Peekable<U16beDecoder>,
+    value: Length<RemainingBytesDecoder>,
+}
+impl Decode for ExtensionDecoder {
+    type Item = Extension;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        if !self.len.is_idle() {
+            bytecodec_try_decode!(self.tag, offset, buf, eos);
+            bytecodec_try_decode!(self.len, offset, buf, eos);
+
+            let len = self.len.peek().cloned().expect("Never fails");
+            track!(self.value.set_expected_bytes(u64::from(len)))?;
+        }
+        bytecodec_try_decode!(self.value, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let tag = track!(self.tag.finish_decoding())?;
+        let _ = track!(self.len.finish_decoding())?;
+        let value = track!(self.value.finish_decoding())?;
+        Ok(Extension { tag, value })
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.tag
+            .requiring_bytes()
+            .add_for_decoding(self.len.requiring_bytes())
+            .add_for_decoding(self.value.requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.len.is_idle() && self.value.is_idle()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ExtensionsEncoder {
+    count: U16beEncoder,
+    entry: ExtensionEncoder,
+    pending: VecDeque<Extension>,
+}
+impl Encode for ExtensionsEncoder {
+    type Item = Vec<Extension>;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_encode!(self.count, offset, buf, eos);
+        loop {
+            bytecodec_try_encode!(self.entry, offset, buf, eos);
+            if let Some(extension) = self.pending.pop_front() {
+                track!(self.entry.start_encoding(extension))?;
+            } else {
+                break;
+            }
+        }
+        Ok(offset)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        track_assert!(
+            item.len() <= usize::from(u16::max_value()),
+            ErrorKind::InvalidInput,
+            "Too many extensions: {}",
+            item.len()
+        );
+        for extension in &item {
+            track_assert!(
+                extension.value.len() <= MAX_EXTENSION_VALUE_LEN,
+                ErrorKind::InvalidInput,
+                "Too large extension: tag={}, len={}",
+                extension.tag,
+                extension.value.len()
+            );
+        }
+        track!(self.count.start_encoding(item.len() as u16))?;
+        self.pending.extend(item);
+        Ok(())
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Finite(self.exact_requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.count.is_idle() && self.entry.is_idle() && self.pending.is_empty()
+    }
+}
+impl SizedEncode for ExtensionsEncoder {
+    fn exact_requiring_bytes(&self) -> u64 {
+        self.count.exact_requiring_bytes()
+            + self.entry.exact_requiring_bytes()
+            + self
+                .pending
+                .iter()
+                .map(|e| 4 + e.value.len() as u64)
+                .sum::<u64>()
+    }
+}
+
+#[derive(Debug, Default)]
+struct ExtensionEncoder {
+    tag: U16beEncoder,
+    len: U16beEncoder,
+    value: BytesEncoder<Vec<u8>>,
+}
+impl Encode for ExtensionEncoder {
+    type Item = Extension;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_encode!(self.tag, offset, buf, eos);
+        bytecodec_try_encode!(self.len, offset, buf, eos);
+        bytecodec_try_encode!(self.value, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        track!(self.tag.start_encoding(item.tag))?;
+        track!(self.len.start_encoding(item.value.len() as u16))?;
+        track!(self.value.start_encoding(item.value))?;
+        Ok(())
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Finite(self.exact_requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.tag.is_idle() && self.len.is_idle() && self.value.is_idle()
+    }
+}
+impl SizedEncode for ExtensionEncoder {
+    fn exact_requiring_bytes(&self) -> u64 {
+        self.tag.exact_requiring_bytes()
+            + self.len.exact_requiring_bytes()
+            + self.value.exact_requiring_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytecodec::{DecodeExt, EncodeExt};
+
+    #[test]
+    fn extensions_codec_works() {
+        let extensions = vec![
+            Extension {
+                tag: 1,
+                value: vec![1, 2, 3],
+            },
+            Extension {
+                tag: 0xFFFF,
+                value: Vec::new(),
+            },
+        ];
+        let bytes = ExtensionsEncoder::default()
+            .encode_into_bytes(extensions.clone())
+            .unwrap();
+        assert_eq!(bytes.len(), 2 + (4 + 3) + 4);
+
+        let decoded = ExtensionsDecoder::default()
+            .decode_from_bytes(&bytes)
+            .unwrap();
+        assert_eq!(decoded, extensions);
+
+        let bytes = ExtensionsEncoder::default()
+            .encode_into_bytes(Vec::new())
+            .unwrap();
+        assert_eq!(bytes, [0, 0]);
+        assert!(ExtensionsDecoder::default()
+            .decode_from_bytes(&bytes)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn truncated_extensions_are_rejected() {
+        let bytes = [0, 2, 0, 1, 0, 0];
+        assert!(ExtensionsDecoder::default()
+            .decode_from_bytes(&bytes)
+            .is_err());
+    }
+
+    #[test]
+    fn too_large_extensions_are_rejected() {
+        let extension = Extension {
+            tag: 0,
+            value: vec![0; MAX_EXTENSION_VALUE_LEN + 1],
+        };
+        assert!(ExtensionsEncoder::default()
+            .encode_into_bytes(vec![extension])
+            .is_err());
+    }
+}
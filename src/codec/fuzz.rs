@@ -0,0 +1,298 @@
+//! Helpers for checking the properties of the codecs (used by tests and fuzz targets).
+use bytecodec::{Decode, DecodeExt, Encode, EncodeExt, Result};
+
+/// Encodes `item` by using a newly created encoder.
+pub fn encode_to_vec<E>(item: E::Item) -> Result<Vec<u8>>
+where
+    E: Encode + Default,
+{
+    track!(E::default().encode_into_bytes(item))
+}
+
+/// Decodes an item from `buf` by using a newly created decoder.
+///
+/// `buf` must contain exactly one complete frame; trailing bytes are rejected as errors.
+///
+/// Truncated frames are also rejected, except for the frames whose last field is
+/// read until the end of the frame (e.g., the payloads of gossip messages).
+pub fn decode_from_slice<D>(buf: &[u8]) -> Result<D::Item>
+where
+    D: Decode + Default,
+{
+    track!(D::default().decode_from_bytes(buf))
+}
+
+/// Feeds the given bytes to one of the RPC message decoders.
+///
+/// The decoder is selected by the first byte of `data`, and the remaining bytes are
+/// decoded as a frame. Malformed frames must be rejected as errors; if the frame is decoded
+/// successfully, re-encoding the decoded message must reproduce a frame that decodes to the same bytes.
+///
+/// This is intended to be used as a fuzzing entry point, so it panics only when
+/// the codecs violate the above properties.
+pub fn decode_frames(data: &[u8]) {
+    use super::admin::*;
+    use super::hyparview::*;
+    use super::plumtree::*;
+    use super::version::*;
+
+    let (selector, frame) = match data.split_first() {
+        None => return,
+        Some(x) => x,
+    };
+    match selector % 14 {
+        0 => check_frame::<JoinMessageEncoder, JoinMessageDecoder>(frame),
+        1 => check_frame::<ForwardJoinMessageEncoder, ForwardJoinMessageDecoder>(frame),
+        2 => check_frame::<NeighborMessageEncoder, NeighborMessageDecoder>(frame),
+        3 => check_frame::<ShuffleMessageEncoder, ShuffleMessageDecoder>(frame),
+        4 => check_frame::<ShuffleReplyMessageEncoder, ShuffleReplyMessageDecoder>(frame),
+        5 => check_frame::<DisconnectMessageEncoder, DisconnectMessageDecoder>(frame),
+        6 => check_frame::<GossipMessageEncoder<Vec<u8>>, GossipMessageDecoder<Vec<u8>>>(frame),
+        7 => check_frame::<IhaveMessageEncoder<Vec<u8>>, IhaveMessageDecoder<Vec<u8>>>(frame),
+        8 => check_frame::<GraftMessageEncoder<Vec<u8>>, GraftMessageDecoder<Vec<u8>>>(frame),
+        9 => check_frame::<
+            GraftOptimizeMessageEncoder<Vec<u8>>,
+            GraftOptimizeMessageDecoder<Vec<u8>>,
+        >(frame),
+        10 => check_frame::<PruneMessageEncoder<Vec<u8>>, PruneMessageDecoder<Vec<u8>>>(frame),
+        11 => check_frame::<ParameterUpdateMessageEncoder, ParameterUpdateMessageDecoder>(frame),
+        12 => {
+            check_frame::<VarintIhaveMessageEncoder<Vec<u8>>, VarintIhaveMessageDecoder<Vec<u8>>>(
+                frame,
+            )
+        }
+        _ => check_frame::<HandshakeEncoder, HandshakeDecoder>(frame),
+    }
+}
+
+fn check_frame<E, D>(frame: &[u8])
+where
+    E: Encode + Default,
+    D: Decode<Item = E::Item> + Default,
+{
+    if let Ok(item) = decode_from_slice::<D>(frame) {
+        let bytes = encode_to_vec::<E>(item).expect("Cannot re-encode a decoded message");
+        let item = decode_from_slice::<D>(&bytes).expect("Cannot decode a re-encoded message");
+        let again = encode_to_vec::<E>(item).expect("Cannot re-encode a decoded message");
+        assert_eq!(bytes, again);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::ParameterUpdate;
+    use crate::message::{Envelope, MessageId};
+    use crate::misc::{
+        DisconnectMessage, ForwardJoinMessage, GossipMessage, GraftMessage, IhaveMessage,
+        JoinMessage, NeighborMessage, PlumtreeAppMessage, PruneMessage, ShuffleMessage,
+        ShuffleReplyMessage,
+    };
+    use crate::node::{LocalNodeId, NodeId};
+    use ::hyparview::TimeToLive;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::net::{SocketAddr, SocketAddrV6};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const ITERATIONS: usize = 200;
+
+    // Checks that `encode(decode(encode(item))) == encode(item)`.
+    fn assert_round_trip<E, D>(item: E::Item)
+    where
+        E: Encode + Default,
+        D: Decode<Item = E::Item> + Default,
+    {
+        let bytes = encode_to_vec::<E>(item).unwrap();
+        let decoded = decode_from_slice::<D>(&bytes).unwrap();
+        assert_eq!(encode_to_vec::<E>(decoded).unwrap(), bytes);
+    }
+
+    // Checks `assert_round_trip` and that truncated frames are rejected.
+    //
+    // NOTE: Gossip frames cannot be checked by this,
+    // because their payloads are read until the end of the frame.
+    fn assert_framed_round_trip<E, D>(item: E::Item)
+    where
+        E: Encode + Default,
+        D: Decode<Item = E::Item> + Default,
+    {
+        let bytes = encode_to_vec::<E>(item).unwrap();
+        assert_round_trip::<E, D>(decode_from_slice::<D>(&bytes).unwrap());
+        if !bytes.is_empty() {
+            assert!(decode_from_slice::<D>(&bytes[..bytes.len() - 1]).is_err());
+        }
+    }
+
+    fn gen_node_id<R: Rng>(rng: &mut R) -> NodeId {
+        let addr = if rng.gen() {
+            SocketAddr::from((rng.gen::<[u8; 4]>(), rng.gen()))
+        } else {
+            let ip = rng.gen::<[u16; 8]>().into();
+            SocketAddr::V6(SocketAddrV6::new(ip, rng.gen(), rng.gen(), rng.gen()))
+        };
+        NodeId::new(addr, LocalNodeId::new(rng.gen()))
+    }
+
+    fn gen_node_ids<R: Rng>(rng: &mut R) -> Vec<NodeId> {
+        let n = rng.gen_range(0, 8);
+        (0..n).map(|_| gen_node_id(rng)).collect()
+    }
+
+    fn gen_message_id<R: Rng>(rng: &mut R) -> MessageId {
+        MessageId::new(gen_node_id(rng), rng.gen())
+    }
+
+    fn gen_app_message<R: Rng>(rng: &mut R) -> PlumtreeAppMessage<Vec<u8>> {
+        let size = rng.gen_range(0, 256);
+        let mut payload = Envelope::new((0..size).map(|_| rng.gen()).collect::<Vec<u8>>());
+        if rng.gen() {
+            payload.deadline = Some(UNIX_EPOCH + Duration::from_millis(rng.gen_range(1, 1 << 50)));
+        }
+        payload.origin_time = UNIX_EPOCH + Duration::from_micros(rng.gen_range(0, 1 << 60));
+        payload.trace.trace_id = rng.gen();
+        payload.trace.span_id = rng.gen();
+        PlumtreeAppMessage {
+            id: gen_message_id(rng),
+            payload,
+        }
+    }
+
+    #[test]
+    fn hyparview_codecs_round_trip() {
+        use crate::codec::hyparview::*;
+
+        let mut rng = StdRng::from_seed([3; 32]);
+        for _ in 0..ITERATIONS {
+            let dst = LocalNodeId::new(rng.gen());
+            let m = JoinMessage {
+                sender: gen_node_id(&mut rng),
+            };
+            assert_framed_round_trip::<JoinMessageEncoder, JoinMessageDecoder>((dst, m));
+
+            let m = ForwardJoinMessage {
+                sender: gen_node_id(&mut rng),
+                new_node: gen_node_id(&mut rng),
+                ttl: TimeToLive::new(rng.gen()),
+            };
+            assert_framed_round_trip::<ForwardJoinMessageEncoder, ForwardJoinMessageDecoder>((
+                dst, m,
+            ));
+
+            let m = NeighborMessage {
+                sender: gen_node_id(&mut rng),
+                high_priority: rng.gen(),
+            };
+            assert_framed_round_trip::<NeighborMessageEncoder, NeighborMessageDecoder>((dst, m));
+
+            let m = ShuffleMessage {
+                sender: gen_node_id(&mut rng),
+                origin: gen_node_id(&mut rng),
+                nodes: gen_node_ids(&mut rng),
+                ttl: TimeToLive::new(rng.gen()),
+            };
+            assert_framed_round_trip::<ShuffleMessageEncoder, ShuffleMessageDecoder>((dst, m));
+
+            let m = ShuffleReplyMessage {
+                sender: gen_node_id(&mut rng),
+                nodes: gen_node_ids(&mut rng),
+            };
+            assert_framed_round_trip::<ShuffleReplyMessageEncoder, ShuffleReplyMessageDecoder>((
+                dst, m,
+            ));
+
+            let m = DisconnectMessage {
+                sender: gen_node_id(&mut rng),
+                alive: rng.gen(),
+            };
+            assert_framed_round_trip::<DisconnectMessageEncoder, DisconnectMessageDecoder>((
+                dst, m,
+            ));
+        }
+    }
+
+    #[test]
+    fn plumtree_codecs_round_trip() {
+        use crate::codec::plumtree::*;
+
+        let mut rng = StdRng::from_seed([4; 32]);
+        for _ in 0..ITERATIONS {
+            let dst = LocalNodeId::new(rng.gen());
+            let m = GossipMessage {
+                sender: gen_node_id(&mut rng),
+                round: rng.gen(),
+                message: gen_app_message(&mut rng),
+            };
+            assert_round_trip::<GossipMessageEncoder<_>, GossipMessageDecoder<_>>((dst, m));
+
+            let m = IhaveMessage::<Vec<u8>> {
+                sender: gen_node_id(&mut rng),
+                round: rng.gen(),
+                message_id: gen_message_id(&mut rng),
+                realtime: rng.gen(),
+            };
+            assert_framed_round_trip::<IhaveMessageEncoder<_>, IhaveMessageDecoder<_>>((dst, m));
+
+            let m = IhaveMessage::<Vec<u8>> {
+                sender: gen_node_id(&mut rng),
+                round: rng.gen(),
+                message_id: gen_message_id(&mut rng),
+                realtime: rng.gen(),
+            };
+            assert_framed_round_trip::<VarintIhaveMessageEncoder<_>, VarintIhaveMessageDecoder<_>>(
+                (dst, m),
+            );
+
+            let m = GraftMessage::<Vec<u8>> {
+                sender: gen_node_id(&mut rng),
+                round: rng.gen(),
+                message_id: Some(gen_message_id(&mut rng)),
+            };
+            assert_framed_round_trip::<GraftMessageEncoder<_>, GraftMessageDecoder<_>>((dst, m));
+
+            let m = GraftMessage::<Vec<u8>> {
+                sender: gen_node_id(&mut rng),
+                round: rng.gen(),
+                message_id: None,
+            };
+            assert_framed_round_trip::<
+                GraftOptimizeMessageEncoder<_>,
+                GraftOptimizeMessageDecoder<_>,
+            >((dst, m));
+
+            let m = PruneMessage::<Vec<u8>> {
+                sender: gen_node_id(&mut rng),
+            };
+            assert_framed_round_trip::<PruneMessageEncoder<_>, PruneMessageDecoder<_>>((dst, m));
+        }
+    }
+
+    #[test]
+    fn admin_codecs_round_trip() {
+        use crate::codec::admin::*;
+
+        let mut rng = StdRng::from_seed([5; 32]);
+        for _ in 0..ITERATIONS {
+            let dst = LocalNodeId::new(rng.gen());
+            let update = if rng.gen() {
+                ParameterUpdate::TickIntervalMultiplier(rng.gen())
+            } else {
+                ParameterUpdate::HyparviewShuffleInterval(Duration::from_millis(rng.gen()))
+            };
+            let caller = gen_node_id(&mut rng).address();
+            assert_framed_round_trip::<ParameterUpdateMessageEncoder, ParameterUpdateMessageDecoder>(
+                (dst, caller, update),
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_frames_are_rejected_gracefully() {
+        let mut rng = StdRng::from_seed([6; 32]);
+        for _ in 0..ITERATIONS * 10 {
+            let size = rng.gen_range(0, 128);
+            let frame = (0..size).map(|_| rng.gen()).collect::<Vec<u8>>();
+            decode_frames(&frame);
+        }
+    }
+}
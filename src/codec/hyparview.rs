@@ -1,3 +1,4 @@
+use super::extension::{Extension, ExtensionFields, MAX_EXTENSION_VALUE_LEN};
 use super::node::{LocalNodeIdDecoder, LocalNodeIdEncoder, NodeIdDecoder, NodeIdEncoder};
 use crate::misc::{
     DisconnectMessage, ForwardJoinMessage, JoinMessage, NeighborMessage, ShuffleMessage,
@@ -10,6 +11,160 @@ use bytecodec::{ByteCount, Decode, Encode, Eos, Result, SizedEncode};
 use hyparview::TimeToLive;
 use std;
 
+const EXTENSION_OBSERVER: u16 = 0;
+const EXTENSION_ZONES: u16 = 1;
+
+/// Attributes of the nodes contained in a HyParView frame.
+///
+/// These are carried in the extension section, so the peers that do not know them
+/// simply ignore them.
+///
+/// The subject nodes of each frame are as follows:
+/// - `JOIN` and `NEIGHBOR`: the sender
+/// - `FORWARD_JOIN`: the new node
+/// - `SHUFFLE`: the origin followed by the shuffled nodes
+/// - `SHUFFLE_REPLY`: the sender followed by the shuffled nodes
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NodeAttributes {
+    /// Whether the first subject node is an observer.
+    pub observer: bool,
+
+    /// The zones of the subject nodes (an empty string means "unknown").
+    ///
+    /// This is empty if no zones are known.
+    pub zones: Vec<String>,
+}
+
+/// An `ExtensionFields` implementation that carries `NodeAttributes` in HyParView frames.
+#[derive(Debug, Default)]
+pub struct NodeAttributesExtensionFields;
+impl<T> ExtensionFields<(LocalNodeId, T, NodeAttributes)> for NodeAttributesExtensionFields {
+    fn to_extensions(item: &(LocalNodeId, T, NodeAttributes), _version: u8) -> Vec<Extension> {
+        let mut extensions = Vec::new();
+        if item.2.observer {
+            extensions.push(Extension {
+                tag: EXTENSION_OBSERVER,
+                value: Vec::new(),
+            });
+        }
+        if item.2.zones.iter().any(|z| !z.is_empty()) {
+            extensions.push(Extension {
+                tag: EXTENSION_ZONES,
+                value: encode_zones(&item.2.zones),
+            });
+        }
+        extensions
+    }
+
+    fn apply_extensions(item: &mut (LocalNodeId, T, NodeAttributes), extensions: &[Extension]) {
+        for extension in extensions {
+            match extension.tag {
+                EXTENSION_OBSERVER => {
+                    item.2.observer = true;
+                }
+                EXTENSION_ZONES => {
+                    if let Some(zones) = decode_zones(&extension.value) {
+                        item.2.zones = zones;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// Each zone is encoded as a length-prefixed (`u8`) UTF-8 string.
+// Zones longer than 255 bytes (or making the value too large) are encoded as unknown ones.
+fn encode_zones(zones: &[String]) -> Vec<u8> {
+    let mut value = Vec::new();
+    for zone in zones {
+        let fits = value.len() + 1 + zone.len() <= MAX_EXTENSION_VALUE_LEN;
+        if zone.len() <= 0xFF && fits {
+            value.push(zone.len() as u8);
+            value.extend_from_slice(zone.as_bytes());
+        } else if value.len() < MAX_EXTENSION_VALUE_LEN {
+            value.push(0);
+        }
+    }
+    value
+}
+
+fn decode_zones(mut value: &[u8]) -> Option<Vec<String>> {
+    let mut zones = Vec::new();
+    while let Some((&len, rest)) = value.split_first() {
+        let len = len as usize;
+        if rest.len() < len {
+            return None;
+        }
+        zones.push(String::from_utf8(rest[..len].to_vec()).ok()?);
+        value = &rest[len..];
+    }
+    Some(zones)
+}
+
+/// A decoder that attaches `NodeAttributes` (empty until the extensions are applied)
+/// to the items decoded by `D`.
+#[derive(Debug, Default)]
+pub struct WithAttributesDecoder<D>(D);
+impl<D, T> Decode for WithAttributesDecoder<D>
+where
+    D: Decode<Item = (LocalNodeId, T)>,
+{
+    type Item = (LocalNodeId, T, NodeAttributes);
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        track!(self.0.decode(buf, eos))
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let (destination, message) = track!(self.0.finish_decoding())?;
+        Ok((destination, message, NodeAttributes::default()))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.0.requiring_bytes()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.0.is_idle()
+    }
+}
+
+/// An encoder that drops `NodeAttributes` (carried by `NodeAttributesExtensionFields`)
+/// before encoding items by `E`.
+#[derive(Debug, Default)]
+pub struct WithAttributesEncoder<E>(E);
+impl<E, T> Encode for WithAttributesEncoder<E>
+where
+    E: Encode<Item = (LocalNodeId, T)>,
+{
+    type Item = (LocalNodeId, T, NodeAttributes);
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        track!(self.0.encode(buf, eos))
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        track!(self.0.start_encoding((item.0, item.1)))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.0.requiring_bytes()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.0.is_idle()
+    }
+}
+impl<E, T> SizedEncode for WithAttributesEncoder<E>
+where
+    E: SizedEncode<Item = (LocalNodeId, T)>,
+{
+    fn exact_requiring_bytes(&self) -> u64 {
+        self.0.exact_requiring_bytes()
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct JoinMessageDecoder {
     destination: LocalNodeIdDecoder,
@@ -492,3 +647,60 @@ impl SizedEncode for DisconnectMessageEncoder {
             + self.alive.exact_requiring_bytes()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::version::{VersionedDecoder, VersionedEncoder};
+    use bytecodec::{DecodeExt, EncodeExt};
+
+    #[test]
+    fn node_attributes_are_carried_in_extension_section() {
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(1));
+        let attrs = vec![
+            NodeAttributes::default(),
+            NodeAttributes {
+                observer: true,
+                zones: Vec::new(),
+            },
+            NodeAttributes {
+                observer: false,
+                zones: vec!["ap-northeast-1a".to_owned()],
+            },
+        ];
+        for attrs in attrs {
+            let m = NeighborMessage {
+                sender: node,
+                high_priority: true,
+            };
+            let bytes = VersionedEncoder::<
+                WithAttributesEncoder<NeighborMessageEncoder>,
+                NodeAttributesExtensionFields,
+            >::default()
+            .encode_into_bytes((LocalNodeId::new(2), m, attrs.clone()))
+            .unwrap();
+            let (destination, m, decoded) = VersionedDecoder::<
+                WithAttributesDecoder<NeighborMessageDecoder>,
+                NodeAttributesExtensionFields,
+            >::default()
+            .decode_from_bytes(&bytes)
+            .unwrap();
+            assert_eq!(destination, LocalNodeId::new(2));
+            assert_eq!(m.sender, node);
+            assert_eq!(decoded, attrs);
+        }
+    }
+
+    #[test]
+    fn zones_codec_works() {
+        let zones = vec![
+            "a".to_owned(),
+            String::new(),
+            "x".repeat(256),
+            "b".to_owned(),
+        ];
+        let decoded = decode_zones(&encode_zones(&zones)).unwrap();
+        assert_eq!(decoded, vec!["a", "", "", "b"]);
+        assert_eq!(decode_zones(&[3, b'a']), None);
+    }
+}
@@ -0,0 +1,164 @@
+//! Codecs counting the bytes (and the codec errors) of RPC frames.
+use crate::metrics::Counter;
+use bytecodec::{ByteCount, Decode, Encode, Eos, Result, SizedEncode};
+
+/// An encoder that adds the size of each encoded frame to a counter.
+///
+/// The size is taken from the inner encoder when an item is started encoding
+/// (i.e., `requiring_bytes()`, which is the same as `exact_requiring_bytes()` for sized encoders).
+/// If the size is unknown in advance, the encoded bytes are counted instead.
+///
+/// Failed encodings are counted by `errors`.
+#[derive(Debug, Default)]
+pub struct MeteredEncoder<E> {
+    inner: E,
+    counter: Option<Counter>,
+    errors: Option<Counter>,
+    counting: bool,
+}
+impl<E> MeteredEncoder<E> {
+    pub fn new(inner: E, counter: Counter, errors: Counter) -> Self {
+        MeteredEncoder {
+            inner,
+            counter: Some(counter),
+            errors: Some(errors),
+            counting: false,
+        }
+    }
+}
+impl<E: Encode> Encode for MeteredEncoder<E> {
+    type Item = E::Item;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        let size = track!(count_error(self.inner.encode(buf, eos), &self.errors))?;
+        if self.counting {
+            if let Some(ref counter) = self.counter {
+                counter.add_u64(size as u64);
+            }
+            self.counting = !self.inner.is_idle();
+        }
+        Ok(size)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        track!(count_error(self.inner.start_encoding(item), &self.errors))?;
+        if let Some(ref counter) = self.counter {
+            match self.inner.requiring_bytes() {
+                ByteCount::Finite(n) => counter.add_u64(n),
+                _ => self.counting = true,
+            }
+        }
+        Ok(())
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.inner.requiring_bytes()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.inner.is_idle()
+    }
+}
+impl<E: SizedEncode> SizedEncode for MeteredEncoder<E> {
+    fn exact_requiring_bytes(&self) -> u64 {
+        self.inner.exact_requiring_bytes()
+    }
+}
+
+/// A decoder that adds the size of each decoded frame to a counter.
+///
+/// Failed decodings are counted by `errors`.
+#[derive(Debug, Default)]
+pub struct MeteredDecoder<D> {
+    inner: D,
+    counter: Option<Counter>,
+    errors: Option<Counter>,
+}
+impl<D> MeteredDecoder<D> {
+    pub fn new(inner: D, counter: Option<Counter>, errors: Option<Counter>) -> Self {
+        MeteredDecoder {
+            inner,
+            counter,
+            errors,
+        }
+    }
+}
+impl<D: Decode> Decode for MeteredDecoder<D> {
+    type Item = D::Item;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        let size = track!(count_error(self.inner.decode(buf, eos), &self.errors))?;
+        if let Some(ref counter) = self.counter {
+            counter.add_u64(size as u64);
+        }
+        Ok(size)
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        track!(count_error(self.inner.finish_decoding(), &self.errors))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.inner.requiring_bytes()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.inner.is_idle()
+    }
+}
+
+fn count_error<T>(result: Result<T>, errors: &Option<Counter>) -> Result<T> {
+    if result.is_err() {
+        if let Some(ref errors) = *errors {
+            errors.increment();
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::net::{SocketAddrDecoder, SocketAddrEncoder};
+    use crate::metrics::{MetricsFactory, ServiceMetrics};
+    use bytecodec::{DecodeExt, EncodeExt};
+    use prometrics::metrics::MetricBuilder;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn frame_bytes_are_counted() {
+        let factory = MetricsFactory::new(MetricBuilder::new(), None, Vec::new());
+        let metrics = ServiceMetrics::new(factory);
+        let addr: SocketAddr = "127.0.0.1:3000".parse().unwrap();
+        let size = SocketAddrEncoder::default()
+            .encode_into_bytes(addr)
+            .unwrap()
+            .len() as u64;
+
+        let mut encoder = MeteredEncoder::new(
+            SocketAddrEncoder::default(),
+            metrics.sent_gossip_bytes.clone(),
+            metrics.codec_errors.encode("plumtree.gossip"),
+        );
+        let mut bytes = encoder.encode_into_bytes(addr).unwrap();
+        bytes.extend(encoder.encode_into_bytes(addr).unwrap());
+
+        let mut decoder = MeteredDecoder::new(
+            SocketAddrDecoder::default(),
+            Some(metrics.received_ihave_bytes.clone()),
+            Some(metrics.codec_errors.decode("plumtree.ihave")),
+        );
+        assert_eq!(
+            decoder.decode_from_bytes(&bytes[..size as usize]).unwrap(),
+            addr
+        );
+
+        let enabled = if cfg!(feature = "metrics") { 1 } else { 0 };
+        assert_eq!(metrics.sent_gossip_bytes(), enabled * size * 2);
+        assert_eq!(metrics.received_ihave_bytes(), enabled * size);
+        assert_eq!(metrics.received_gossip_bytes(), 0);
+        assert_eq!(metrics.sent_ihave_bytes(), 0);
+        assert_eq!(metrics.encode_errors("plumtree.gossip"), 0);
+        assert_eq!(metrics.decode_errors("plumtree.ihave"), 0);
+    }
+}
@@ -1,4 +1,14 @@
+pub mod admin;
+pub mod extension;
 pub mod hyparview;
+pub mod metered;
 pub mod net;
 pub mod node;
 pub mod plumtree;
+pub mod varint;
+pub mod version;
+
+#[cfg(feature = "bench")]
+pub mod bench;
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
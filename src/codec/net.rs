@@ -253,3 +253,49 @@ impl SizedEncode for SocketAddrV6Encoder {
             + self.scope_id.exact_requiring_bytes()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytecodec::{DecodeExt, EncodeExt};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn round_trip(addr: SocketAddr) -> SocketAddr {
+        let bytes = SocketAddrEncoder::default()
+            .encode_into_bytes(addr)
+            .unwrap();
+        SocketAddrDecoder::default()
+            .decode_from_bytes(&bytes)
+            .unwrap()
+    }
+
+    #[test]
+    fn socket_addr_codec_works() {
+        let v4 = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 3000));
+        assert_eq!(round_trip(v4), v4);
+
+        let v6 = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 3000, 0, 0));
+        assert_eq!(round_trip(v6), v6);
+
+        let mapped = SocketAddr::V6(SocketAddrV6::new(
+            Ipv4Addr::new(127, 0, 0, 1).to_ipv6_mapped(),
+            3000,
+            0,
+            0,
+        ));
+        assert_eq!(round_trip(mapped), mapped);
+    }
+
+    #[test]
+    fn socket_addr_codec_keeps_flowinfo_and_scope_id() {
+        let ip = "fe80::1".parse().unwrap();
+        let v6 = SocketAddrV6::new(ip, 3000, 0x12345, 7);
+        match round_trip(SocketAddr::V6(v6)) {
+            SocketAddr::V6(a) => {
+                assert_eq!(a.flowinfo(), 0x12345);
+                assert_eq!(a.scope_id(), 7);
+            }
+            a => panic!("{:?}", a),
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use super::net::{SocketAddrDecoder, SocketAddrEncoder};
+use super::varint::{VarU64Decoder, VarU64Encoder};
 use crate::node::{LocalNodeId, NodeId};
 use bytecodec::fixnum::{U64beDecoder, U64beEncoder};
 use bytecodec::{ByteCount, Decode, Encode, Eos, Result, SizedEncode};
@@ -118,3 +119,72 @@ impl SizedEncode for NodeIdEncoder {
         self.addr.exact_requiring_bytes() + self.local_id.exact_requiring_bytes()
     }
 }
+
+/// A decoder of `NodeId` used by `WireCodec::Varint` (the local identifier part is a varint).
+#[derive(Debug, Default)]
+pub struct VarintNodeIdDecoder {
+    addr: SocketAddrDecoder,
+    local_id: VarU64Decoder,
+}
+impl Decode for VarintNodeIdDecoder {
+    type Item = NodeId;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_decode!(self.addr, offset, buf, eos);
+        bytecodec_try_decode!(self.local_id, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let addr = track!(self.addr.finish_decoding())?;
+        let local_id = track!(self.local_id.finish_decoding())?;
+        Ok(NodeId::new(addr, LocalNodeId::new(local_id)))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.addr
+            .requiring_bytes()
+            .add_for_decoding(self.local_id.requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.addr.is_idle() && self.local_id.is_idle()
+    }
+}
+
+/// An encoder of `NodeId` used by `WireCodec::Varint` (see `VarintNodeIdDecoder`).
+#[derive(Debug, Default)]
+pub struct VarintNodeIdEncoder {
+    addr: SocketAddrEncoder,
+    local_id: VarU64Encoder,
+}
+impl Encode for VarintNodeIdEncoder {
+    type Item = NodeId;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_encode!(self.addr, offset, buf, eos);
+        bytecodec_try_encode!(self.local_id, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        track!(self.addr.start_encoding(item.address()))?;
+        track!(self.local_id.start_encoding(item.local_id().value()))?;
+        Ok(())
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Finite(self.exact_requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.addr.is_idle() && self.local_id.is_idle()
+    }
+}
+impl SizedEncode for VarintNodeIdEncoder {
+    fn exact_requiring_bytes(&self) -> u64 {
+        self.addr.exact_requiring_bytes() + self.local_id.exact_requiring_bytes()
+    }
+}
@@ -1,16 +1,259 @@
-use super::node::{LocalNodeIdDecoder, LocalNodeIdEncoder, NodeIdDecoder, NodeIdEncoder};
-use crate::message::{MessageId, MessagePayload};
-use crate::misc::{GossipMessage, GraftMessage, IhaveMessage, PlumtreeAppMessage, PruneMessage};
+use super::extension::{Extension, ExtensionFields};
+use super::node::{
+    LocalNodeIdDecoder, LocalNodeIdEncoder, NodeIdDecoder, NodeIdEncoder, VarintNodeIdDecoder,
+    VarintNodeIdEncoder,
+};
+use super::varint::{VarU16Decoder, VarU16Encoder, VarU64Decoder, VarU64Encoder};
+#[cfg(feature = "encryption")]
+use crate::encryption::PayloadCipher;
+use crate::message::{Envelope, MessageId, MessagePayload};
+use crate::metrics::{Counter, Gauge};
+use crate::misc::{
+    GossipMessage, GraftMessage, IhaveMessage, PlumtreeAppMessage, PruneMessage, RetractMessage,
+};
 use crate::node::LocalNodeId;
-use bytecodec::bytes::{BytesDecoder, BytesEncoder};
-use bytecodec::combinator::Peekable;
+use crate::trace::TraceContext;
+use bytecodec::bytes::BytesEncoder;
+#[cfg(feature = "encryption")]
+use bytecodec::bytes::RemainingBytesDecoder;
 use bytecodec::fixnum::{
-    U16beDecoder, U16beEncoder, U32beDecoder, U32beEncoder, U64beDecoder, U64beEncoder, U8Decoder,
-    U8Encoder,
+    U16beDecoder, U16beEncoder, U64beDecoder, U64beEncoder, U8Decoder, U8Encoder,
 };
-use bytecodec::{ByteCount, Decode, Encode, Eos, ErrorKind, Result, SizedEncode};
+use bytecodec::{
+    ByteCount, Decode, DecodeExt, Encode, EncodeExt, Eos, ErrorKind, Result, SizedEncode,
+};
+use futures::task::{self, Task};
 use std::fmt;
 use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const EXTENSION_HIGH_PRIORITY: u16 = 0;
+const EXTENSION_HOP_LIMIT: u16 = 1;
+const EXTENSION_TOMBSTONE_OF: u16 = 2;
+const EXTENSION_CONTENT_HASH: u16 = 3;
+const EXTENSION_DEADLINE: u16 = 4;
+const EXTENSION_ORIGIN_TIME: u16 = 5;
+const EXTENSION_TRACE_CONTEXT: u16 = 6;
+
+/// The protocol version that introduced `EXTENSION_ORIGIN_TIME`.
+const ORIGIN_TIME_VERSION: u8 = 2;
+
+/// The fields of gossip messages carried in the extension section.
+#[derive(Debug, Default)]
+pub struct GossipExtensionFields;
+impl<M: MessagePayload> ExtensionFields<(LocalNodeId, GossipMessage<M>)> for GossipExtensionFields {
+    fn to_extensions(item: &(LocalNodeId, GossipMessage<M>), version: u8) -> Vec<Extension> {
+        let mut extensions = Vec::new();
+        if item.1.message.payload.high_priority {
+            extensions.push(Extension {
+                tag: EXTENSION_HIGH_PRIORITY,
+                value: Vec::new(),
+            });
+        }
+        if let Some(hop_limit) = item.1.message.payload.hop_limit {
+            extensions.push(Extension {
+                tag: EXTENSION_HOP_LIMIT,
+                value: hop_limit.to_be_bytes().to_vec(),
+            });
+        }
+        if let Some(id) = item.1.message.payload.tombstone_of {
+            if let Ok(value) = MessageIdEncoder::default().encode_into_bytes(id) {
+                extensions.push(Extension {
+                    tag: EXTENSION_TOMBSTONE_OF,
+                    value,
+                });
+            }
+        }
+        if let Some(hash) = item.1.message.payload.content_hash {
+            extensions.push(content_hash_extension(hash));
+        }
+        if let Some(deadline) = item.1.message.payload.deadline {
+            extensions.push(Extension {
+                tag: EXTENSION_DEADLINE,
+                value: deadline_to_unixtime_millis(deadline).to_be_bytes().to_vec(),
+            });
+        }
+        if version >= ORIGIN_TIME_VERSION {
+            extensions.push(Extension {
+                tag: EXTENSION_ORIGIN_TIME,
+                value: unixtime_micros(item.1.message.payload.origin_time)
+                    .to_be_bytes()
+                    .to_vec(),
+            });
+        }
+        let trace = item.1.message.payload.trace;
+        if trace.is_traced() {
+            let mut value = trace.trace_id.to_be_bytes().to_vec();
+            value.extend_from_slice(&trace.span_id.to_be_bytes());
+            extensions.push(Extension {
+                tag: EXTENSION_TRACE_CONTEXT,
+                value,
+            });
+        }
+        extensions
+    }
+
+    fn apply_extensions(item: &mut (LocalNodeId, GossipMessage<M>), extensions: &[Extension]) {
+        for extension in extensions {
+            match extension.tag {
+                EXTENSION_HIGH_PRIORITY => {
+                    item.1.message.payload.high_priority = true;
+                }
+                EXTENSION_HOP_LIMIT if extension.value.len() == 2 => {
+                    let hop_limit = u16::from_be_bytes([extension.value[0], extension.value[1]]);
+                    item.1.message.payload.hop_limit = Some(hop_limit);
+                }
+                EXTENSION_TOMBSTONE_OF => {
+                    let id = MessageIdDecoder::default().decode_from_bytes(&extension.value);
+                    item.1.message.payload.tombstone_of = id.ok();
+                }
+                EXTENSION_CONTENT_HASH => {
+                    item.1.message.payload.content_hash = decode_content_hash(&extension.value);
+                }
+                EXTENSION_DEADLINE => {
+                    if let Some(millis) = decode_u64(&extension.value) {
+                        item.1.message.payload.deadline =
+                            Some(UNIX_EPOCH + Duration::from_millis(millis));
+                    }
+                }
+                EXTENSION_ORIGIN_TIME => {
+                    if let Some(micros) = decode_u64(&extension.value) {
+                        item.1.message.payload.origin_time =
+                            UNIX_EPOCH + Duration::from_micros(micros);
+                    }
+                }
+                EXTENSION_TRACE_CONTEXT if extension.value.len() == 16 => {
+                    let trace_id = decode_u64(&extension.value[..8]).expect("Never fails");
+                    let span_id = decode_u64(&extension.value[8..]).expect("Never fails");
+                    item.1.message.payload.trace = TraceContext { trace_id, span_id };
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The content hash of `IHAVE` messages carried in the extension section.
+#[derive(Debug, Default)]
+pub struct IhaveExtensionFields;
+impl<M: MessagePayload> ExtensionFields<(LocalNodeId, IhaveMessage<M>, Option<u64>)>
+    for IhaveExtensionFields
+{
+    fn to_extensions(
+        item: &(LocalNodeId, IhaveMessage<M>, Option<u64>),
+        _version: u8,
+    ) -> Vec<Extension> {
+        item.2.map(content_hash_extension).into_iter().collect()
+    }
+
+    fn apply_extensions(
+        item: &mut (LocalNodeId, IhaveMessage<M>, Option<u64>),
+        extensions: &[Extension],
+    ) {
+        for extension in extensions {
+            if extension.tag == EXTENSION_CONTENT_HASH {
+                item.2 = decode_content_hash(&extension.value);
+            }
+        }
+    }
+}
+
+fn content_hash_extension(hash: u64) -> Extension {
+    Extension {
+        tag: EXTENSION_CONTENT_HASH,
+        value: hash.to_be_bytes().to_vec(),
+    }
+}
+
+fn decode_content_hash(value: &[u8]) -> Option<u64> {
+    decode_u64(value)
+}
+
+fn decode_u64(value: &[u8]) -> Option<u64> {
+    if value.len() != 8 {
+        return None;
+    }
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(value);
+    Some(u64::from_be_bytes(bytes))
+}
+
+fn deadline_to_unixtime_millis(deadline: SystemTime) -> u64 {
+    match deadline.duration_since(UNIX_EPOCH) {
+        Err(_) => 0, // NOTE: The deadline has already passed
+        Ok(d) => d.as_secs() * 1000 + u64::from(d.subsec_millis()),
+    }
+}
+
+fn unixtime_micros(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| {
+        d.as_secs() * 1_000_000 + u64::from(d.subsec_micros())
+    })
+}
+
+/// A decoder that attaches the content hash (`None` until the extensions are applied)
+/// to the `IHAVE` messages decoded by `D`.
+#[derive(Debug, Default)]
+pub struct WithContentHashDecoder<D>(D);
+impl<D, T> Decode for WithContentHashDecoder<D>
+where
+    D: Decode<Item = (LocalNodeId, T)>,
+{
+    type Item = (LocalNodeId, T, Option<u64>);
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        track!(self.0.decode(buf, eos))
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let (destination, message) = track!(self.0.finish_decoding())?;
+        Ok((destination, message, None))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.0.requiring_bytes()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.0.is_idle()
+    }
+}
+
+/// An encoder that drops the content hash (carried by `IhaveExtensionFields`)
+/// before encoding `IHAVE` messages by `E`.
+#[derive(Debug, Default)]
+pub struct WithContentHashEncoder<E>(E);
+impl<E, T> Encode for WithContentHashEncoder<E>
+where
+    E: Encode<Item = (LocalNodeId, T)>,
+{
+    type Item = (LocalNodeId, T, Option<u64>);
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        track!(self.0.encode(buf, eos))
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        track!(self.0.start_encoding((item.0, item.1)))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.0.requiring_bytes()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.0.is_idle()
+    }
+}
+impl<E, T> SizedEncode for WithContentHashEncoder<E>
+where
+    E: SizedEncode<Item = (LocalNodeId, T)>,
+{
+    fn exact_requiring_bytes(&self) -> u64 {
+        self.0.exact_requiring_bytes()
+    }
+}
 
 pub struct GossipMessageDecoder<M: MessagePayload> {
     destination: LocalNodeIdDecoder,
@@ -28,6 +271,144 @@ impl<M: MessagePayload> Default for GossipMessageDecoder<M> {
         }
     }
 }
+impl<M: MessagePayload> GossipMessageDecoder<M> {
+    pub fn with_payload_decoder(payload: M::Decoder) -> Self {
+        GossipMessageDecoder {
+            destination: Default::default(),
+            sender: Default::default(),
+            round: Default::default(),
+            message: MessageDecoder::with_payload_decoder(payload),
+        }
+    }
+
+    pub fn set_payload_size_limit(&mut self, limit: PayloadSizeLimit) {
+        self.message.limit = limit;
+    }
+
+    pub fn set_payload_decode_budget(&mut self, budget: PayloadDecodeBudget) {
+        self.message.budget = budget;
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn set_payload_cipher(&mut self, cipher: PayloadCipher) {
+        self.message.cipher = Some(cipher);
+    }
+}
+
+/// The maximum size of the payloads accepted by decoders.
+#[derive(Debug, Default, Clone)]
+pub struct PayloadSizeLimit {
+    max: Option<u64>,
+    rejected_frames: Option<Counter>,
+}
+impl PayloadSizeLimit {
+    pub fn new(max: Option<u64>, rejected_frames: Counter) -> Self {
+        PayloadSizeLimit {
+            max,
+            rejected_frames: Some(rejected_frames),
+        }
+    }
+
+    fn remaining(&self, size: u64) -> Option<u64> {
+        self.max.map(|max| max.saturating_sub(size))
+    }
+
+    fn check(&self, size: u64) -> Result<()> {
+        if let Some(max) = self.max {
+            if size > max {
+                if let Some(ref c) = self.rejected_frames {
+                    c.increment();
+                }
+                track_panic!(
+                    ErrorKind::InvalidInput,
+                    "Too large payload: size={}, max={}",
+                    size,
+                    max
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The budget of the payload bytes being decoded concurrently (shared by all connections).
+///
+/// While the bytes held by in-progress payload decodes reach the maximum,
+/// new payload decodes are not started (i.e., the decoders consume no more bytes)
+/// and the tasks of the decoders are notified when enough bytes are released.
+/// Payload decodes that have already started are never throttled,
+/// so a stall caused by partially decoded payloads waiting for each other does not occur.
+#[derive(Debug, Default, Clone)]
+pub struct PayloadDecodeBudget {
+    inner: Option<Arc<PayloadDecodeBudgetInner>>,
+}
+impl PayloadDecodeBudget {
+    pub fn new(max: Option<u64>, decoding_bytes: Gauge, throttled_decodes: Counter) -> Self {
+        let inner = max.map(|max| {
+            Arc::new(PayloadDecodeBudgetInner {
+                max,
+                state: Mutex::new(BudgetState::default()),
+                decoding_bytes,
+                throttled_decodes,
+            })
+        });
+        PayloadDecodeBudget { inner }
+    }
+
+    /// Returns `true` if a new payload decode can be started.
+    ///
+    /// If `false` is returned, the current task will be notified when the budget becomes available.
+    fn try_start(&self) -> bool {
+        let inner = match self.inner {
+            None => return true,
+            Some(ref inner) => inner,
+        };
+        let mut state = inner.state.lock().expect("Never fails");
+        if state.decoding_bytes < inner.max {
+            true
+        } else {
+            inner.throttled_decodes.increment();
+            state.waiters.push(task::current());
+            false
+        }
+    }
+
+    fn acquire(&self, bytes: u64) {
+        if let Some(ref inner) = self.inner {
+            let mut state = inner.state.lock().expect("Never fails");
+            state.decoding_bytes += bytes;
+            inner.decoding_bytes.set(state.decoding_bytes as f64);
+        }
+    }
+
+    fn release(&self, bytes: u64) {
+        if let Some(ref inner) = self.inner {
+            let mut state = inner.state.lock().expect("Never fails");
+            state.decoding_bytes = state.decoding_bytes.saturating_sub(bytes);
+            inner.decoding_bytes.set(state.decoding_bytes as f64);
+            if state.decoding_bytes < inner.max {
+                for waiter in state.waiters.drain(..) {
+                    waiter.notify();
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PayloadDecodeBudgetInner {
+    max: u64,
+    state: Mutex<BudgetState>,
+    decoding_bytes: Gauge,
+    throttled_decodes: Counter,
+}
+
+#[derive(Debug, Default)]
+struct BudgetState {
+    decoding_bytes: u64,
+    waiters: Vec<Task>,
+}
+
 impl<M: MessagePayload> fmt::Debug for GossipMessageDecoder<M>
 where
     M::Decoder: fmt::Debug,
@@ -82,14 +463,97 @@ impl<M: MessagePayload> Decode for GossipMessageDecoder<M> {
 struct MessageDecoder<M: MessagePayload> {
     id: MessageIdDecoder,
     payload: M::Decoder,
+    payload_size: u64,
+    limit: PayloadSizeLimit,
+    budget: PayloadDecodeBudget,
+    budget_started: bool,
+
+    // NOTE: If a cipher is set, the whole sealed payload is buffered before decoding it.
+    #[cfg(feature = "encryption")]
+    cipher: Option<PayloadCipher>,
+    #[cfg(feature = "encryption")]
+    sealed_payload: RemainingBytesDecoder,
 }
-impl<M: MessagePayload> Default for MessageDecoder<M> {
-    fn default() -> Self {
+impl<M: MessagePayload> MessageDecoder<M> {
+    fn with_payload_decoder(payload: M::Decoder) -> Self {
         MessageDecoder {
             id: Default::default(),
-            payload: Default::default(),
+            payload,
+            payload_size: 0,
+            limit: Default::default(),
+            budget: Default::default(),
+            budget_started: false,
+            #[cfg(feature = "encryption")]
+            cipher: None,
+            #[cfg(feature = "encryption")]
+            sealed_payload: Default::default(),
         }
     }
+
+    fn decode_payload(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        #[cfg(feature = "encryption")]
+        {
+            if self.cipher.is_some() {
+                return track!(self.sealed_payload.decode(buf, eos));
+            }
+        }
+        track!(self.payload.decode(buf, eos))
+    }
+
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    fn finish_decoding_payload(&mut self, id: &MessageId) -> Result<M> {
+        #[cfg(feature = "encryption")]
+        {
+            if let Some(ref cipher) = self.cipher {
+                let sealed = track!(self.sealed_payload.finish_decoding())?;
+                let payload = track_assert_some!(
+                    cipher.open(id, &sealed),
+                    ErrorKind::InvalidInput,
+                    "Cannot decrypt the payload of {:?}",
+                    id
+                );
+                return track!(self.payload.decode_from_bytes(&payload));
+            }
+        }
+        track!(self.payload.finish_decoding())
+    }
+
+    fn payload_requiring_bytes(&self) -> ByteCount {
+        #[cfg(feature = "encryption")]
+        {
+            if self.cipher.is_some() {
+                return self.sealed_payload.requiring_bytes();
+            }
+        }
+        self.payload.requiring_bytes()
+    }
+
+    fn is_payload_idle(&self) -> bool {
+        #[cfg(feature = "encryption")]
+        {
+            if self.cipher.is_some() {
+                return self.sealed_payload.is_idle();
+            }
+        }
+        self.payload.is_idle()
+    }
+
+    fn release_budget(&mut self) {
+        if self.budget_started {
+            self.budget.release(self.payload_size);
+            self.budget_started = false;
+        }
+    }
+}
+impl<M: MessagePayload> Drop for MessageDecoder<M> {
+    fn drop(&mut self) {
+        self.release_budget();
+    }
+}
+impl<M: MessagePayload> Default for MessageDecoder<M> {
+    fn default() -> Self {
+        Self::with_payload_decoder(Default::default())
+    }
 }
 impl<M: MessagePayload> fmt::Debug for MessageDecoder<M>
 where
@@ -98,8 +562,8 @@ where
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "MessageDecoder {{ id: {:?}, payload: {:?} }}",
-            self.id, self.payload
+            "MessageDecoder {{ id: {:?}, payload: {:?}, payload_size: {:?} }}",
+            self.id, self.payload, self.payload_size
         )
     }
 }
@@ -109,24 +573,66 @@ impl<M: MessagePayload> Decode for MessageDecoder<M> {
     fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
         let mut offset = 0;
         bytecodec_try_decode!(self.id, offset, buf, eos);
-        bytecodec_try_decode!(self.payload, offset, buf, eos);
+
+        if !self.is_payload_idle() {
+            if !self.budget_started {
+                if !self.budget.try_start() {
+                    return Ok(offset);
+                }
+                self.budget_started = true;
+            }
+
+            // NOTE: At most one byte beyond the limit is passed to the payload decoder,
+            // so oversized payloads are rejected before they are buffered.
+            let (buf, eos) = match self.limit.remaining(self.payload_size) {
+                Some(remaining) if (buf.len() - offset) as u64 > remaining => {
+                    (&buf[..offset + remaining as usize + 1], Eos::new(false))
+                }
+                _ => (buf, eos),
+            };
+            let size = track!(self.decode_payload(&buf[offset..], eos))?;
+            offset += size;
+            self.payload_size += size as u64;
+            self.budget.acquire(size as u64);
+            track!(self.limit.check(self.payload_size))?;
+        }
         Ok(offset)
     }
 
     fn finish_decoding(&mut self) -> Result<Self::Item> {
         let id = track!(self.id.finish_decoding())?;
-        let payload = track!(self.payload.finish_decoding())?;
+        self.release_budget();
+        let payload = track!(self.finish_decoding_payload(&id))?;
+        let payload_size = Some(self.payload_size);
+        self.payload_size = 0;
+        let payload = Envelope {
+            payload,
+            deadline: None,
+
+            // NOTE: Overwritten by the extension (peers that do not send it get the receipt time).
+            origin_time: SystemTime::now(),
+            trace: TraceContext::default(),
+            high_priority: false,
+            hop_limit: None,
+            tombstone_of: None,
+            payload_size,
+            received_from: None,
+            duplicates: 0,
+            local: false,
+            repair: false,
+            encoded_payload: None,
+        };
         Ok(PlumtreeAppMessage { id, payload })
     }
 
     fn requiring_bytes(&self) -> ByteCount {
         self.id
             .requiring_bytes()
-            .add_for_decoding(self.payload.requiring_bytes())
+            .add_for_decoding(self.payload_requiring_bytes())
     }
 
     fn is_idle(&self) -> bool {
-        self.id.is_idle() && self.payload.is_idle()
+        self.id.is_idle() && self.is_payload_idle()
     }
 }
 
@@ -162,44 +668,6 @@ impl Decode for MessageIdDecoder {
     }
 }
 
-#[derive(Debug, Default)]
-struct MessagePayloadDecoder {
-    size: Peekable<U32beDecoder>,
-    data: BytesDecoder<Vec<u8>>,
-}
-impl Decode for MessagePayloadDecoder {
-    type Item = Vec<u8>;
-
-    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
-        let mut offset = 0;
-        if !self.size.is_idle() {
-            bytecodec_try_decode!(self.size, offset, buf, eos);
-
-            let size = self.size.peek().cloned().expect("Never fails");
-            let buf = vec![0; size as usize];
-            self.data.set_bytes(buf);
-        }
-        bytecodec_try_decode!(self.data, offset, buf, eos);
-        Ok(offset)
-    }
-
-    fn finish_decoding(&mut self) -> Result<Self::Item> {
-        let _ = track!(self.size.finish_decoding())?;
-        let data = track!(self.data.finish_decoding())?;
-        Ok(data)
-    }
-
-    fn requiring_bytes(&self) -> ByteCount {
-        self.size
-            .requiring_bytes()
-            .add_for_decoding(self.data.requiring_bytes())
-    }
-
-    fn is_idle(&self) -> bool {
-        self.size.is_idle() && self.data.is_idle()
-    }
-}
-
 pub struct GossipMessageEncoder<M: MessagePayload> {
     destination: LocalNodeIdEncoder,
     sender: NodeIdEncoder,
@@ -273,15 +741,26 @@ where
     }
 }
 
+/// A payload encoded in advance (and shared by the gossip messages sent to multiple peers).
+#[derive(Debug, Default)]
+struct EncodedPayload(Arc<Vec<u8>>);
+impl AsRef<[u8]> for EncodedPayload {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 struct MessageEncoder<M: MessagePayload> {
     id: MessageIdEncoder,
     payload: M::Encoder,
+    encoded_payload: BytesEncoder<EncodedPayload>,
 }
 impl<M: MessagePayload> Default for MessageEncoder<M> {
     fn default() -> Self {
         MessageEncoder {
             id: Default::default(),
             payload: Default::default(),
+            encoded_payload: Default::default(),
         }
     }
 }
@@ -304,12 +783,18 @@ impl<M: MessagePayload> Encode for MessageEncoder<M> {
         let mut offset = 0;
         bytecodec_try_encode!(self.id, offset, buf, eos);
         bytecodec_try_encode!(self.payload, offset, buf, eos);
+        bytecodec_try_encode!(self.encoded_payload, offset, buf, eos);
         Ok(offset)
     }
 
     fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
         track!(self.id.start_encoding(item.id))?;
-        track!(self.payload.start_encoding(item.payload))?;
+        if let Some(bytes) = item.payload.encoded_payload {
+            // NOTE: The bytes are the same as those produced by `M::Encoder`.
+            track!(self.encoded_payload.start_encoding(EncodedPayload(bytes)))?;
+        } else {
+            track!(self.payload.start_encoding(item.payload.payload))?;
+        }
         Ok(())
     }
 
@@ -317,10 +802,11 @@ impl<M: MessagePayload> Encode for MessageEncoder<M> {
         self.id
             .requiring_bytes()
             .add_for_encoding(self.payload.requiring_bytes())
+            .add_for_encoding(self.encoded_payload.requiring_bytes())
     }
 
     fn is_idle(&self) -> bool {
-        self.id.is_idle() && self.payload.is_idle()
+        self.id.is_idle() && self.payload.is_idle() && self.encoded_payload.is_idle()
     }
 }
 impl<M: MessagePayload> SizedEncode for MessageEncoder<M>
@@ -328,7 +814,9 @@ where
     M::Encoder: SizedEncode,
 {
     fn exact_requiring_bytes(&self) -> u64 {
-        self.id.exact_requiring_bytes() + self.payload.exact_requiring_bytes()
+        self.id.exact_requiring_bytes()
+            + self.payload.exact_requiring_bytes()
+            + self.encoded_payload.exact_requiring_bytes()
     }
 }
 
@@ -369,55 +857,147 @@ impl SizedEncode for MessageIdEncoder {
     }
 }
 
-#[derive(Debug, Default)]
-struct MessagePayloadEncoder {
-    size: U32beEncoder,
-    data: BytesEncoder<Vec<u8>>,
+#[derive(Debug)]
+pub struct IhaveMessageDecoder<M> {
+    destination: LocalNodeIdDecoder,
+    sender: NodeIdDecoder,
+    round: U16beDecoder,
+    message_id: MessageIdDecoder,
+    realtime: U8Decoder,
+    _phantom: PhantomData<M>,
+}
+impl<M> Default for IhaveMessageDecoder<M> {
+    fn default() -> Self {
+        IhaveMessageDecoder {
+            destination: Default::default(),
+            sender: Default::default(),
+            round: Default::default(),
+            message_id: Default::default(),
+            realtime: Default::default(),
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<M: MessagePayload> Decode for IhaveMessageDecoder<M> {
+    type Item = (LocalNodeId, IhaveMessage<M>);
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_decode!(self.destination, offset, buf, eos);
+        bytecodec_try_decode!(self.sender, offset, buf, eos);
+        bytecodec_try_decode!(self.round, offset, buf, eos);
+        bytecodec_try_decode!(self.message_id, offset, buf, eos);
+        bytecodec_try_decode!(self.realtime, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let destination = track!(self.destination.finish_decoding())?;
+        let sender = track!(self.sender.finish_decoding())?;
+        let round = track!(self.round.finish_decoding())?;
+        let message_id = track!(self.message_id.finish_decoding())?;
+        let realtime = track!(self.realtime.finish_decoding())?;
+
+        let message = IhaveMessage {
+            sender,
+            round,
+            message_id,
+            realtime: realtime != 0,
+        };
+        Ok((destination, message))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.destination
+            .requiring_bytes()
+            .add_for_decoding(self.sender.requiring_bytes())
+            .add_for_decoding(self.round.requiring_bytes())
+            .add_for_decoding(self.message_id.requiring_bytes())
+            .add_for_decoding(self.realtime.requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.realtime.is_idle()
+    }
 }
-impl Encode for MessagePayloadEncoder {
-    type Item = Vec<u8>;
+
+#[derive(Debug)]
+pub struct IhaveMessageEncoder<M> {
+    destination: LocalNodeIdEncoder,
+    sender: NodeIdEncoder,
+    round: U16beEncoder,
+    message_id: MessageIdEncoder,
+    realtime: U8Encoder,
+    _phantom: PhantomData<M>,
+}
+impl<M> Default for IhaveMessageEncoder<M> {
+    fn default() -> Self {
+        IhaveMessageEncoder {
+            destination: Default::default(),
+            sender: Default::default(),
+            round: Default::default(),
+            message_id: Default::default(),
+            realtime: Default::default(),
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<M: MessagePayload> Encode for IhaveMessageEncoder<M> {
+    type Item = (LocalNodeId, IhaveMessage<M>);
 
     fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
         let mut offset = 0;
-        bytecodec_try_encode!(self.size, offset, buf, eos);
-        bytecodec_try_encode!(self.data, offset, buf, eos);
+        bytecodec_try_encode!(self.destination, offset, buf, eos);
+        bytecodec_try_encode!(self.sender, offset, buf, eos);
+        bytecodec_try_encode!(self.round, offset, buf, eos);
+        bytecodec_try_encode!(self.message_id, offset, buf, eos);
+        bytecodec_try_encode!(self.realtime, offset, buf, eos);
         Ok(offset)
     }
 
     fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
-        track!(self.size.start_encoding(item.len() as u32))?;
-        track!(self.data.start_encoding(item))?;
+        track!(self.destination.start_encoding(item.0))?;
+        track!(self.sender.start_encoding(item.1.sender))?;
+        track!(self.round.start_encoding(item.1.round))?;
+        track!(self.message_id.start_encoding(item.1.message_id))?;
+        track!(self.realtime.start_encoding(item.1.realtime as u8))?;
         Ok(())
     }
 
     fn requiring_bytes(&self) -> ByteCount {
-        self.size
-            .requiring_bytes()
-            .add_for_encoding(self.data.requiring_bytes())
+        ByteCount::Finite(self.exact_requiring_bytes())
     }
 
     fn is_idle(&self) -> bool {
-        self.size.is_idle() && self.data.is_idle()
+        self.realtime.is_idle()
     }
 }
-impl SizedEncode for MessagePayloadEncoder {
+impl<M: MessagePayload> SizedEncode for IhaveMessageEncoder<M> {
     fn exact_requiring_bytes(&self) -> u64 {
-        self.size.exact_requiring_bytes() + self.data.exact_requiring_bytes()
+        self.destination.exact_requiring_bytes()
+            + self.sender.exact_requiring_bytes()
+            + self.round.exact_requiring_bytes()
+            + self.message_id.exact_requiring_bytes()
+            + self.realtime.exact_requiring_bytes()
     }
 }
 
+/// A decoder of `IHAVE` messages used by `WireCodec::Varint`.
+///
+/// The layout is the same as `IhaveMessageDecoder`, except that the destination,
+/// the local identifiers of the nodes, the round and the sequence number are varints.
 #[derive(Debug)]
-pub struct IhaveMessageDecoder<M> {
-    destination: LocalNodeIdDecoder,
-    sender: NodeIdDecoder,
-    round: U16beDecoder,
-    message_id: MessageIdDecoder,
+pub struct VarintIhaveMessageDecoder<M> {
+    destination: VarU64Decoder,
+    sender: VarintNodeIdDecoder,
+    round: VarU16Decoder,
+    message_id: VarintMessageIdDecoder,
     realtime: U8Decoder,
     _phantom: PhantomData<M>,
 }
-impl<M> Default for IhaveMessageDecoder<M> {
+impl<M> Default for VarintIhaveMessageDecoder<M> {
     fn default() -> Self {
-        IhaveMessageDecoder {
+        VarintIhaveMessageDecoder {
             destination: Default::default(),
             sender: Default::default(),
             round: Default::default(),
@@ -427,7 +1007,7 @@ impl<M> Default for IhaveMessageDecoder<M> {
         }
     }
 }
-impl<M: MessagePayload> Decode for IhaveMessageDecoder<M> {
+impl<M: MessagePayload> Decode for VarintIhaveMessageDecoder<M> {
     type Item = (LocalNodeId, IhaveMessage<M>);
 
     fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
@@ -453,7 +1033,7 @@ impl<M: MessagePayload> Decode for IhaveMessageDecoder<M> {
             message_id,
             realtime: realtime != 0,
         };
-        Ok((destination, message))
+        Ok((LocalNodeId::new(destination), message))
     }
 
     fn requiring_bytes(&self) -> ByteCount {
@@ -470,18 +1050,19 @@ impl<M: MessagePayload> Decode for IhaveMessageDecoder<M> {
     }
 }
 
+/// An encoder of `IHAVE` messages used by `WireCodec::Varint` (see `VarintIhaveMessageDecoder`).
 #[derive(Debug)]
-pub struct IhaveMessageEncoder<M> {
-    destination: LocalNodeIdEncoder,
-    sender: NodeIdEncoder,
-    round: U16beEncoder,
-    message_id: MessageIdEncoder,
+pub struct VarintIhaveMessageEncoder<M> {
+    destination: VarU64Encoder,
+    sender: VarintNodeIdEncoder,
+    round: VarU16Encoder,
+    message_id: VarintMessageIdEncoder,
     realtime: U8Encoder,
     _phantom: PhantomData<M>,
 }
-impl<M> Default for IhaveMessageEncoder<M> {
+impl<M> Default for VarintIhaveMessageEncoder<M> {
     fn default() -> Self {
-        IhaveMessageEncoder {
+        VarintIhaveMessageEncoder {
             destination: Default::default(),
             sender: Default::default(),
             round: Default::default(),
@@ -491,7 +1072,7 @@ impl<M> Default for IhaveMessageEncoder<M> {
         }
     }
 }
-impl<M: MessagePayload> Encode for IhaveMessageEncoder<M> {
+impl<M: MessagePayload> Encode for VarintIhaveMessageEncoder<M> {
     type Item = (LocalNodeId, IhaveMessage<M>);
 
     fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
@@ -505,7 +1086,7 @@ impl<M: MessagePayload> Encode for IhaveMessageEncoder<M> {
     }
 
     fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
-        track!(self.destination.start_encoding(item.0))?;
+        track!(self.destination.start_encoding(item.0.value()))?;
         track!(self.sender.start_encoding(item.1.sender))?;
         track!(self.round.start_encoding(item.1.round))?;
         track!(self.message_id.start_encoding(item.1.message_id))?;
@@ -521,7 +1102,7 @@ impl<M: MessagePayload> Encode for IhaveMessageEncoder<M> {
         self.realtime.is_idle()
     }
 }
-impl<M: MessagePayload> SizedEncode for IhaveMessageEncoder<M> {
+impl<M: MessagePayload> SizedEncode for VarintIhaveMessageEncoder<M> {
     fn exact_requiring_bytes(&self) -> u64 {
         self.destination.exact_requiring_bytes()
             + self.sender.exact_requiring_bytes()
@@ -531,6 +1112,73 @@ impl<M: MessagePayload> SizedEncode for IhaveMessageEncoder<M> {
     }
 }
 
+#[derive(Debug, Default)]
+struct VarintMessageIdDecoder {
+    node: VarintNodeIdDecoder,
+    seqno: VarU64Decoder,
+}
+impl Decode for VarintMessageIdDecoder {
+    type Item = MessageId;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_decode!(self.node, offset, buf, eos);
+        bytecodec_try_decode!(self.seqno, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let node = track!(self.node.finish_decoding())?;
+        let seqno = track!(self.seqno.finish_decoding())?;
+        Ok(MessageId::new(node, seqno))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.node
+            .requiring_bytes()
+            .add_for_decoding(self.seqno.requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.seqno.is_idle()
+    }
+}
+
+#[derive(Debug, Default)]
+struct VarintMessageIdEncoder {
+    node: VarintNodeIdEncoder,
+    seqno: VarU64Encoder,
+}
+impl Encode for VarintMessageIdEncoder {
+    type Item = MessageId;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_encode!(self.node, offset, buf, eos);
+        bytecodec_try_encode!(self.seqno, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        track!(self.node.start_encoding(item.node()))?;
+        track!(self.seqno.start_encoding(item.seqno()))?;
+        Ok(())
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Finite(self.exact_requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.seqno.is_idle()
+    }
+}
+impl SizedEncode for VarintMessageIdEncoder {
+    fn exact_requiring_bytes(&self) -> u64 {
+        self.node.exact_requiring_bytes() + self.seqno.exact_requiring_bytes()
+    }
+}
+
 #[derive(Debug)]
 pub struct GraftMessageDecoder<M> {
     destination: LocalNodeIdDecoder,
@@ -840,3 +1488,479 @@ impl<M: MessagePayload> SizedEncode for PruneMessageEncoder<M> {
         self.destination.exact_requiring_bytes() + self.sender.exact_requiring_bytes()
     }
 }
+
+#[derive(Debug, Default)]
+pub struct RetractMessageDecoder {
+    destination: LocalNodeIdDecoder,
+    sender: NodeIdDecoder,
+    message_id: MessageIdDecoder,
+}
+impl Decode for RetractMessageDecoder {
+    type Item = (LocalNodeId, RetractMessage);
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_decode!(self.destination, offset, buf, eos);
+        bytecodec_try_decode!(self.sender, offset, buf, eos);
+        bytecodec_try_decode!(self.message_id, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let destination = track!(self.destination.finish_decoding())?;
+        let sender = track!(self.sender.finish_decoding())?;
+        let message_id = track!(self.message_id.finish_decoding())?;
+
+        let message = RetractMessage { sender, message_id };
+        Ok((destination, message))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.destination
+            .requiring_bytes()
+            .add_for_decoding(self.sender.requiring_bytes())
+            .add_for_decoding(self.message_id.requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.message_id.is_idle()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RetractMessageEncoder {
+    destination: LocalNodeIdEncoder,
+    sender: NodeIdEncoder,
+    message_id: MessageIdEncoder,
+}
+impl Encode for RetractMessageEncoder {
+    type Item = (LocalNodeId, RetractMessage);
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_encode!(self.destination, offset, buf, eos);
+        bytecodec_try_encode!(self.sender, offset, buf, eos);
+        bytecodec_try_encode!(self.message_id, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        track!(self.destination.start_encoding(item.0))?;
+        track!(self.sender.start_encoding(item.1.sender))?;
+        track!(self.message_id.start_encoding(item.1.message_id))?;
+        Ok(())
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Finite(self.exact_requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.message_id.is_idle()
+    }
+}
+impl SizedEncode for RetractMessageEncoder {
+    fn exact_requiring_bytes(&self) -> u64 {
+        self.destination.exact_requiring_bytes()
+            + self.sender.exact_requiring_bytes()
+            + self.message_id.exact_requiring_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::NodeId;
+    use bytecodec::{DecodeExt, EncodeExt};
+
+    #[test]
+    fn oversized_payloads_are_rejected() {
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(1));
+        let gossip = GossipMessage {
+            sender: node,
+            round: 0,
+            message: PlumtreeAppMessage {
+                id: MessageId::new(node, 0),
+                payload: Envelope::new(vec![0; 100]),
+            },
+        };
+        let bytes = GossipMessageEncoder::default()
+            .encode_into_bytes((LocalNodeId::new(2), gossip))
+            .unwrap();
+
+        let limit = |max| PayloadSizeLimit {
+            max: Some(max),
+            rejected_frames: None,
+        };
+        let mut decoder = GossipMessageDecoder::<Vec<u8>>::default();
+        decoder.set_payload_size_limit(limit(100));
+        assert!(decoder.decode_from_bytes(&bytes).is_ok());
+
+        let mut decoder = GossipMessageDecoder::<Vec<u8>>::default();
+        decoder.set_payload_size_limit(limit(99));
+        let e = decoder.decode_from_bytes(&bytes).err().unwrap();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn oversized_payloads_are_rejected_without_being_buffered() {
+        use crate::metrics::{MetricsFactory, ServiceMetrics};
+        use prometrics::metrics::MetricBuilder;
+
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(1));
+        let message = PlumtreeAppMessage {
+            id: MessageId::new(node, 0),
+            payload: Envelope::new(vec![0; 1000]),
+        };
+        let bytes = MessageEncoder::default()
+            .encode_into_bytes(message)
+            .unwrap();
+
+        let factory = MetricsFactory::new(MetricBuilder::new(), None, Vec::new());
+        let rejected = ServiceMetrics::new(factory).oversized_payload_frames;
+        let mut decoder = MessageDecoder::<Vec<u8>>::default();
+        decoder.limit = PayloadSizeLimit::new(Some(10), rejected.clone());
+
+        // Chunks within the limit are accepted.
+        let header = bytes.len() - 1000;
+        let size = decoder
+            .decode(&bytes[..header + 5], Eos::new(false))
+            .unwrap();
+        assert_eq!(size, header + 5);
+        assert_eq!(decoder.payload_size, 5);
+
+        // No more than one byte beyond the limit is consumed.
+        let e = decoder
+            .decode(&bytes[size..], Eos::new(true))
+            .err()
+            .unwrap();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        assert_eq!(decoder.payload_size, 11);
+        let expected = if cfg!(feature = "metrics") { 1 } else { 0 };
+        assert_eq!(rejected.value() as u64, expected);
+    }
+
+    #[test]
+    fn payload_decodes_are_postponed_while_budget_is_exhausted() {
+        use crate::metrics::{MetricsFactory, ServiceMetrics};
+        use futures::future::{self, Future};
+
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(1));
+        let gossip = GossipMessage {
+            sender: node,
+            round: 0,
+            message: PlumtreeAppMessage {
+                id: MessageId::new(node, 0),
+                payload: Envelope::new(vec![0; 100]),
+            },
+        };
+        let bytes = GossipMessageEncoder::default()
+            .encode_into_bytes((LocalNodeId::new(2), gossip))
+            .unwrap();
+
+        let metrics = ServiceMetrics::new(MetricsFactory::disabled());
+        let budget = PayloadDecodeBudget::new(
+            Some(10),
+            metrics.decoding_payload_bytes.clone(),
+            metrics.throttled_payload_decodes.clone(),
+        );
+        future::lazy(move || -> std::result::Result<(), ()> {
+            let mut d0 = GossipMessageDecoder::<Vec<u8>>::default();
+            let mut d1 = GossipMessageDecoder::<Vec<u8>>::default();
+            d0.set_payload_decode_budget(budget.clone());
+            d1.set_payload_decode_budget(budget.clone());
+
+            // `d0` starts decoding the payload and exhausts the budget.
+            let half = bytes.len() - 50;
+            assert_eq!(d0.decode(&bytes[..half], Eos::new(false)).unwrap(), half);
+
+            // `d1` can not start decoding the payload.
+            let size = d1.decode(&bytes, Eos::new(false)).unwrap();
+            assert!(size < half);
+            assert_eq!(d1.decode(&bytes[size..], Eos::new(false)).unwrap(), 0);
+
+            // `d0` is never throttled.
+            let rest = &bytes[half..];
+            assert_eq!(d0.decode(rest, Eos::new(true)).unwrap(), rest.len());
+            assert!(d0.finish_decoding().is_ok());
+
+            // The budget has been released.
+            let rest = &bytes[size..];
+            assert_eq!(d1.decode(rest, Eos::new(true)).unwrap(), rest.len());
+            assert!(d1.finish_decoding().is_ok());
+            Ok(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn priority_is_carried_in_extension_section() {
+        use crate::codec::version::{VersionedDecoder, VersionedEncoder};
+
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(1));
+        for &high_priority in &[false, true] {
+            let mut payload = Envelope::new(vec![1, 2, 3]);
+            payload.high_priority = high_priority;
+            let gossip = GossipMessage {
+                sender: node,
+                round: 0,
+                message: PlumtreeAppMessage {
+                    id: MessageId::new(node, 0),
+                    payload,
+                },
+            };
+            let bytes =
+                VersionedEncoder::<GossipMessageEncoder<_>, GossipExtensionFields>::default()
+                    .encode_into_bytes((LocalNodeId::new(2), gossip))
+                    .unwrap();
+            let (_, gossip) =
+                VersionedDecoder::<GossipMessageDecoder<Vec<u8>>, GossipExtensionFields>::default()
+                    .decode_from_bytes(&bytes)
+                    .unwrap();
+            assert_eq!(gossip.message.payload.high_priority, high_priority);
+        }
+    }
+
+    #[test]
+    fn hop_limit_is_carried_in_extension_section() {
+        use crate::codec::version::{VersionedDecoder, VersionedEncoder};
+
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(1));
+        for &hop_limit in &[None, Some(0), Some(3), Some(0xFFFF)] {
+            let mut payload = Envelope::new(vec![1, 2, 3]);
+            payload.hop_limit = hop_limit;
+            let gossip = GossipMessage {
+                sender: node,
+                round: 2,
+                message: PlumtreeAppMessage {
+                    id: MessageId::new(node, 0),
+                    payload,
+                },
+            };
+            let bytes =
+                VersionedEncoder::<GossipMessageEncoder<_>, GossipExtensionFields>::default()
+                    .encode_into_bytes((LocalNodeId::new(2), gossip))
+                    .unwrap();
+            let (_, gossip) =
+                VersionedDecoder::<GossipMessageDecoder<Vec<u8>>, GossipExtensionFields>::default()
+                    .decode_from_bytes(&bytes)
+                    .unwrap();
+            assert_eq!(gossip.round, 2);
+            assert_eq!(gossip.message.payload.hop_limit, hop_limit);
+        }
+    }
+
+    #[test]
+    fn tombstone_is_carried_in_extension_section() {
+        use crate::codec::version::{VersionedDecoder, VersionedEncoder};
+
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(1));
+        for &tombstone_of in &[None, Some(MessageId::new(node, 7))] {
+            let mut payload = Envelope::new(vec![1, 2, 3]);
+            payload.tombstone_of = tombstone_of;
+            let gossip = GossipMessage {
+                sender: node,
+                round: 2,
+                message: PlumtreeAppMessage {
+                    id: MessageId::new(node, 8),
+                    payload,
+                },
+            };
+            let bytes =
+                VersionedEncoder::<GossipMessageEncoder<_>, GossipExtensionFields>::default()
+                    .encode_into_bytes((LocalNodeId::new(2), gossip))
+                    .unwrap();
+            let (_, gossip) =
+                VersionedDecoder::<GossipMessageDecoder<Vec<u8>>, GossipExtensionFields>::default()
+                    .decode_from_bytes(&bytes)
+                    .unwrap();
+            assert_eq!(gossip.message.payload.payload, vec![1, 2, 3]);
+            assert_eq!(gossip.message.payload.tombstone_of, tombstone_of);
+        }
+    }
+
+    #[test]
+    fn deadline_is_carried_in_extension_section() {
+        use crate::codec::version::{VersionedDecoder, VersionedEncoder};
+
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(1));
+        let deadline = UNIX_EPOCH + Duration::from_millis(1_500_000_000_123);
+        let mut sizes = Vec::new();
+        for &deadline in &[None, Some(deadline)] {
+            let mut payload = Envelope::new(vec![1, 2, 3]);
+            payload.deadline = deadline;
+            let gossip = GossipMessage {
+                sender: node,
+                round: 2,
+                message: PlumtreeAppMessage {
+                    id: MessageId::new(node, 8),
+                    payload,
+                },
+            };
+            let bytes =
+                VersionedEncoder::<GossipMessageEncoder<_>, GossipExtensionFields>::default()
+                    .encode_into_bytes((LocalNodeId::new(2), gossip))
+                    .unwrap();
+            sizes.push(bytes.len());
+            let (_, gossip) =
+                VersionedDecoder::<GossipMessageDecoder<Vec<u8>>, GossipExtensionFields>::default()
+                    .decode_from_bytes(&bytes)
+                    .unwrap();
+            assert_eq!(gossip.message.payload.payload, vec![1, 2, 3]);
+            assert_eq!(gossip.message.payload.deadline, deadline);
+        }
+
+        // Messages without deadlines do not pay for the field.
+        assert_eq!(sizes[1] - sizes[0], 4 + 8);
+    }
+
+    #[test]
+    fn origin_time_is_carried_in_extension_section() {
+        use crate::codec::extension::NoExtensionFields;
+        use crate::codec::version::{VersionedDecoder, VersionedEncoder};
+
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(1));
+        let origin_time = UNIX_EPOCH + Duration::from_micros(1_500_000_000_123_456);
+        let item = || {
+            let mut payload = Envelope::new(vec![1, 2, 3]);
+            payload.origin_time = origin_time;
+            let gossip = GossipMessage {
+                sender: node,
+                round: 2,
+                message: PlumtreeAppMessage {
+                    id: MessageId::new(node, 8),
+                    payload,
+                },
+            };
+            (LocalNodeId::new(2), gossip)
+        };
+
+        let bytes = VersionedEncoder::<GossipMessageEncoder<_>, GossipExtensionFields>::default()
+            .encode_into_bytes(item())
+            .unwrap();
+        let (_, gossip) =
+            VersionedDecoder::<GossipMessageDecoder<Vec<u8>>, GossipExtensionFields>::default()
+                .decode_from_bytes(&bytes)
+                .unwrap();
+        assert_eq!(gossip.message.payload.origin_time, origin_time);
+
+        // If the sender does not attach the time, the receipt time is used instead.
+        let before = SystemTime::now();
+        let bytes = VersionedEncoder::<GossipMessageEncoder<_>, NoExtensionFields>::default()
+            .encode_into_bytes(item())
+            .unwrap();
+        let (_, gossip) =
+            VersionedDecoder::<GossipMessageDecoder<Vec<u8>>, GossipExtensionFields>::default()
+                .decode_from_bytes(&bytes)
+                .unwrap();
+        assert_eq!(gossip.message.payload.payload, vec![1, 2, 3]);
+        assert!(gossip.message.payload.origin_time >= before);
+
+        // The time is not sent to the peers that negotiated an older version.
+        let before = SystemTime::now();
+        let bytes =
+            VersionedEncoder::<GossipMessageEncoder<_>, GossipExtensionFields>::with_version(
+                GossipMessageEncoder::default(),
+                ORIGIN_TIME_VERSION - 1,
+            )
+            .encode_into_bytes(item())
+            .unwrap();
+        assert_eq!(bytes[0], ORIGIN_TIME_VERSION - 1);
+        assert_eq!(bytes[1..3], [0, 0]);
+        let (_, gossip) =
+            VersionedDecoder::<GossipMessageDecoder<Vec<u8>>, GossipExtensionFields>::default()
+                .decode_from_bytes(&bytes)
+                .unwrap();
+        assert!(gossip.message.payload.origin_time >= before);
+    }
+
+    #[test]
+    fn trace_context_is_carried_only_if_traced() {
+        use crate::codec::version::{VersionedDecoder, VersionedEncoder};
+
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(1));
+        let traced = TraceContext::hop(123, node);
+        let mut sizes = Vec::new();
+        for &trace in &[TraceContext::default(), traced] {
+            let mut payload = Envelope::new(vec![1, 2, 3]);
+            payload.trace = trace;
+            let gossip = GossipMessage {
+                sender: node,
+                round: 2,
+                message: PlumtreeAppMessage {
+                    id: MessageId::new(node, 8),
+                    payload,
+                },
+            };
+            let bytes =
+                VersionedEncoder::<GossipMessageEncoder<_>, GossipExtensionFields>::default()
+                    .encode_into_bytes((LocalNodeId::new(2), gossip))
+                    .unwrap();
+            sizes.push(bytes.len());
+            let (_, gossip) =
+                VersionedDecoder::<GossipMessageDecoder<Vec<u8>>, GossipExtensionFields>::default()
+                    .decode_from_bytes(&bytes)
+                    .unwrap();
+            assert_eq!(gossip.message.payload.payload, vec![1, 2, 3]);
+            assert_eq!(gossip.message.payload.trace, trace);
+        }
+
+        // Untraced messages do not pay for the context.
+        assert_eq!(sizes[1] - sizes[0], 4 + 16);
+    }
+
+    #[test]
+    fn varint_ihave_frames_are_smaller() {
+        use crate::codec::version::VersionedEncoder;
+
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(1));
+        let item = || {
+            (
+                LocalNodeId::new(2),
+                IhaveMessage::<Vec<u8>> {
+                    sender: node,
+                    round: 3,
+                    message_id: MessageId::new(node, 100),
+                    realtime: true,
+                },
+            )
+        };
+        let fixed = VersionedEncoder::<IhaveMessageEncoder<_>>::default()
+            .encode_into_bytes(item())
+            .unwrap();
+        let varint = VersionedEncoder::<VarintIhaveMessageEncoder<_>>::default()
+            .encode_into_bytes(item())
+            .unwrap();
+        assert_eq!(fixed.len(), 52);
+        assert_eq!(varint.len(), 23);
+
+        let (destination, m) = VarintIhaveMessageDecoder::<Vec<u8>>::default()
+            .decode_from_bytes(&varint[3..])
+            .unwrap();
+        assert_eq!(destination, LocalNodeId::new(2));
+        assert_eq!(m.sender, node);
+        assert_eq!(m.round, 3);
+        assert_eq!(m.message_id, MessageId::new(node, 100));
+        assert!(m.realtime);
+    }
+
+    #[test]
+    fn retract_message_codec_works() {
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(1));
+        let item = (
+            LocalNodeId::new(2),
+            RetractMessage {
+                sender: node,
+                message_id: MessageId::new(node, 10),
+            },
+        );
+        let bytes = RetractMessageEncoder::default()
+            .encode_into_bytes(item)
+            .unwrap();
+        let decoded = RetractMessageDecoder::default()
+            .decode_from_bytes(&bytes)
+            .unwrap();
+        assert_eq!(decoded, item);
+    }
+}
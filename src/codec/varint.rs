@@ -0,0 +1,203 @@
+//! Variable length integers used by `WireCodec::Varint`.
+//!
+//! Integers are encoded in LEB128 (i.e., seven bits per byte, least significant group first,
+//! and the most significant bit of each byte indicates whether more bytes follow).
+use bytecodec::{ByteCount, Decode, Encode, Eos, ErrorKind, Result, SizedEncode};
+use std::cmp;
+
+/// The maximum number of bytes of an encoded `u64`.
+const MAX_VARINT_LEN: usize = 10;
+
+#[derive(Debug, Default)]
+pub struct VarU64Decoder {
+    value: u64,
+    shift: u32,
+    done: bool,
+}
+impl Decode for VarU64Decoder {
+    type Item = u64;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+        for (i, &b) in buf.iter().enumerate() {
+            let bits = u64::from(b & 0x7F);
+            track_assert!(
+                self.shift < 63 || (self.shift == 63 && bits <= 1),
+                ErrorKind::InvalidInput,
+                "Too large varint"
+            );
+            self.value |= bits << self.shift;
+            self.shift += 7;
+            if b & 0x80 == 0 {
+                self.done = true;
+                return Ok(i + 1);
+            }
+        }
+        track_assert!(!eos.is_reached(), ErrorKind::UnexpectedEos);
+        Ok(buf.len())
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        track_assert!(self.done, ErrorKind::IncompleteDecoding);
+        let value = self.value;
+        *self = Self::default();
+        Ok(value)
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        if self.done {
+            ByteCount::Finite(0)
+        } else {
+            ByteCount::Unknown
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.done
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct VarU64Encoder {
+    bytes: [u8; MAX_VARINT_LEN],
+    len: usize,
+    offset: usize,
+}
+impl Encode for VarU64Encoder {
+    type Item = u64;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        let size = cmp::min(buf.len(), self.len - self.offset);
+        buf[..size].copy_from_slice(&self.bytes[self.offset..][..size]);
+        self.offset += size;
+        if self.offset < self.len {
+            track_assert!(!eos.is_reached(), ErrorKind::UnexpectedEos);
+        }
+        Ok(size)
+    }
+
+    fn start_encoding(&mut self, mut item: Self::Item) -> Result<()> {
+        track_assert!(self.is_idle(), ErrorKind::EncoderFull);
+        self.len = 0;
+        self.offset = 0;
+        loop {
+            let b = (item & 0x7F) as u8;
+            item >>= 7;
+            if item == 0 {
+                self.bytes[self.len] = b;
+                self.len += 1;
+                break;
+            }
+            self.bytes[self.len] = b | 0x80;
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Finite(self.exact_requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.offset == self.len
+    }
+}
+impl SizedEncode for VarU64Encoder {
+    fn exact_requiring_bytes(&self) -> u64 {
+        (self.len - self.offset) as u64
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct VarU16Decoder(VarU64Decoder);
+impl Decode for VarU16Decoder {
+    type Item = u16;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        track!(self.0.decode(buf, eos))
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let n = track!(self.0.finish_decoding())?;
+        track_assert!(
+            n <= u64::from(u16::max_value()),
+            ErrorKind::InvalidInput,
+            "Too large value: {}",
+            n
+        );
+        Ok(n as u16)
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.0.requiring_bytes()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.0.is_idle()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct VarU16Encoder(VarU64Encoder);
+impl Encode for VarU16Encoder {
+    type Item = u16;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        track!(self.0.encode(buf, eos))
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        track!(self.0.start_encoding(u64::from(item)))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.0.requiring_bytes()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.0.is_idle()
+    }
+}
+impl SizedEncode for VarU16Encoder {
+    fn exact_requiring_bytes(&self) -> u64 {
+        self.0.exact_requiring_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytecodec::{DecodeExt, EncodeExt};
+
+    #[test]
+    fn varint_codec_works() {
+        for &(n, len) in &[
+            (0, 1),
+            (0x7F, 1),
+            (0x80, 2),
+            (0x3FFF, 2),
+            (0x4000, 3),
+            (u64::max_value(), MAX_VARINT_LEN),
+        ] {
+            let bytes = VarU64Encoder::default().encode_into_bytes(n).unwrap();
+            assert_eq!(bytes.len(), len);
+            assert_eq!(
+                VarU64Decoder::default().decode_from_bytes(&bytes).unwrap(),
+                n
+            );
+        }
+
+        // Truncated.
+        assert!(VarU64Decoder::default().decode_from_bytes(&[0x80]).is_err());
+
+        // Overflowed.
+        let mut bytes = vec![0xFF; MAX_VARINT_LEN - 1];
+        bytes.push(0x02);
+        assert!(VarU64Decoder::default().decode_from_bytes(&bytes).is_err());
+
+        let bytes = VarU64Encoder::default().encode_into_bytes(0x10000).unwrap();
+        assert!(VarU16Decoder::default().decode_from_bytes(&bytes).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+use super::extension::{ExtensionFields, ExtensionsDecoder, ExtensionsEncoder, NoExtensionFields};
+use super::net::{SocketAddrDecoder, SocketAddrEncoder};
+use crate::protocol::{self, Handshake, WireCodec, PROTOCOL_VERSION};
+use bytecodec::bytes::{BytesEncoder, RemainingBytesDecoder};
+use bytecodec::combinator::Peekable;
+use bytecodec::fixnum::{U8Decoder, U8Encoder};
+use bytecodec::{ByteCount, Decode, Encode, Eos, ErrorKind, Result, SizedEncode};
+use std::marker::PhantomData;
+
+/// A decoder for RPC frames.
+///
+/// A frame consists of the protocol version byte, the extension section and the body decoded by `D`.
+/// The extensions are applied to the decoded body by `X`.
+///
+/// Note that the extension section precedes the body because some bodies
+/// (e.g., `GOSSIP` ones whose payloads are not length-prefixed) extend to the end of the frame.
+#[derive(Debug, Default)]
+pub struct VersionedDecoder<D, X = NoExtensionFields> {
+    version: Peekable<U8Decoder>,
+    inner: D,
+    extensions: ExtensionsDecoder,
+    _fields: PhantomData<X>,
+}
+impl<D, X> VersionedDecoder<D, X> {
+    pub fn new(inner: D) -> Self {
+        VersionedDecoder {
+            version: Default::default(),
+            inner,
+            extensions: Default::default(),
+            _fields: PhantomData,
+        }
+    }
+}
+impl<D: Decode, X: ExtensionFields<D::Item>> Decode for VersionedDecoder<D, X> {
+    type Item = D::Item;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_decode!(self.version, offset, buf, eos);
+
+        let version = self.version.peek().cloned().expect("Never fails");
+        track_assert!(
+            protocol::is_supported_version(version),
+            ErrorKind::InvalidInput,
+            "Unsupported protocol version: {}",
+            version
+        );
+        bytecodec_try_decode!(self.extensions, offset, buf, eos);
+        bytecodec_try_decode!(self.inner, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let _ = track!(self.version.finish_decoding())?;
+        let extensions = track!(self.extensions.finish_decoding())?;
+        let mut item = track!(self.inner.finish_decoding())?;
+        X::apply_extensions(&mut item, &extensions);
+        Ok(item)
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.version
+            .requiring_bytes()
+            .add_for_decoding(self.extensions.requiring_bytes())
+            .add_for_decoding(self.inner.requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.version.is_idle() && self.inner.is_idle() && self.extensions.is_idle()
+    }
+}
+
+/// An encoder for RPC frames (see `VersionedDecoder` for the layout).
+///
+/// Frames are encoded in `PROTOCOL_VERSION` unless another version is specified by `with_version()`.
+#[derive(Debug)]
+pub struct VersionedEncoder<E, X = NoExtensionFields> {
+    version: u8,
+    version_encoder: U8Encoder,
+    inner: E,
+    extensions: ExtensionsEncoder,
+    _fields: PhantomData<X>,
+}
+impl<E, X> VersionedEncoder<E, X> {
+    /// Makes a new encoder which encodes frames in the given (negotiated) version.
+    pub fn with_version(inner: E, version: u8) -> Self {
+        VersionedEncoder {
+            version,
+            version_encoder: Default::default(),
+            inner,
+            extensions: Default::default(),
+            _fields: PhantomData,
+        }
+    }
+}
+impl<E: Default, X> Default for VersionedEncoder<E, X> {
+    fn default() -> Self {
+        Self::with_version(E::default(), PROTOCOL_VERSION)
+    }
+}
+impl<E: Encode, X: ExtensionFields<E::Item>> Encode for VersionedEncoder<E, X> {
+    type Item = E::Item;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_encode!(self.version_encoder, offset, buf, eos);
+        bytecodec_try_encode!(self.extensions, offset, buf, eos);
+        bytecodec_try_encode!(self.inner, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        let extensions = X::to_extensions(&item, self.version);
+        track!(self.version_encoder.start_encoding(self.version))?;
+        track!(self.extensions.start_encoding(extensions))?;
+        track!(self.inner.start_encoding(item))?;
+        Ok(())
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.version_encoder
+            .requiring_bytes()
+            .add_for_encoding(self.extensions.requiring_bytes())
+            .add_for_encoding(self.inner.requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.version_encoder.is_idle() && self.inner.is_idle() && self.extensions.is_idle()
+    }
+}
+impl<E: SizedEncode, X: ExtensionFields<E::Item>> SizedEncode for VersionedEncoder<E, X> {
+    fn exact_requiring_bytes(&self) -> u64 {
+        self.version_encoder.exact_requiring_bytes()
+            + self.extensions.exact_requiring_bytes()
+            + self.inner.exact_requiring_bytes()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct HandshakeDecoder {
+    server_addr: SocketAddrDecoder,
+    min_version: U8Decoder,
+    max_version: U8Decoder,
+    codecs: RemainingBytesDecoder,
+}
+impl Decode for HandshakeDecoder {
+    type Item = Handshake;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_decode!(self.server_addr, offset, buf, eos);
+        bytecodec_try_decode!(self.min_version, offset, buf, eos);
+        bytecodec_try_decode!(self.max_version, offset, buf, eos);
+        bytecodec_try_decode!(self.codecs, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let server_addr = track!(self.server_addr.finish_decoding())?;
+        let min_version = track!(self.min_version.finish_decoding())?;
+        let max_version = track!(self.max_version.finish_decoding())?;
+
+        // NOTE: Unknown codecs are ignored.
+        let codecs = track!(self.codecs.finish_decoding())?
+            .into_iter()
+            .filter_map(WireCodec::from_id)
+            .collect();
+        Ok(Handshake {
+            server_addr,
+            min_version,
+            max_version,
+            codecs,
+        })
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.server_addr
+            .requiring_bytes()
+            .add_for_decoding(self.min_version.requiring_bytes())
+            .add_for_decoding(self.max_version.requiring_bytes())
+            .add_for_decoding(self.codecs.requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.codecs.is_idle()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct HandshakeEncoder {
+    server_addr: SocketAddrEncoder,
+    min_version: U8Encoder,
+    max_version: U8Encoder,
+    codecs: BytesEncoder<Vec<u8>>,
+}
+impl Encode for HandshakeEncoder {
+    type Item = Handshake;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_encode!(self.server_addr, offset, buf, eos);
+        bytecodec_try_encode!(self.min_version, offset, buf, eos);
+        bytecodec_try_encode!(self.max_version, offset, buf, eos);
+        bytecodec_try_encode!(self.codecs, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        track!(self.server_addr.start_encoding(item.server_addr))?;
+        track!(self.min_version.start_encoding(item.min_version))?;
+        track!(self.max_version.start_encoding(item.max_version))?;
+        let codecs = item.codecs.iter().map(|c| c.id()).collect();
+        track!(self.codecs.start_encoding(codecs))?;
+        Ok(())
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Finite(self.exact_requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.codecs.is_idle()
+    }
+}
+impl SizedEncode for HandshakeEncoder {
+    fn exact_requiring_bytes(&self) -> u64 {
+        self.server_addr.exact_requiring_bytes()
+            + self.min_version.exact_requiring_bytes()
+            + self.max_version.exact_requiring_bytes()
+            + self.codecs.exact_requiring_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::extension::Extension;
+    use crate::codec::hyparview::{DisconnectMessageDecoder, DisconnectMessageEncoder};
+    use crate::misc::DisconnectMessage;
+    use crate::node::{LocalNodeId, NodeId};
+    use bytecodec::{DecodeExt, EncodeExt};
+
+    #[test]
+    fn versioned_codec_works() {
+        let sender = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(1));
+        let item = (
+            LocalNodeId::new(2),
+            DisconnectMessage {
+                sender,
+                alive: true,
+            },
+        );
+        let mut bytes = VersionedEncoder::<DisconnectMessageEncoder>::default()
+            .encode_into_bytes(item)
+            .unwrap();
+        assert_eq!(bytes[0], PROTOCOL_VERSION);
+
+        let (destination, m) = VersionedDecoder::<DisconnectMessageDecoder>::default()
+            .decode_from_bytes(&bytes)
+            .unwrap();
+        assert_eq!(destination, LocalNodeId::new(2));
+        assert_eq!(m.sender, sender);
+        assert!(m.alive);
+
+        // Unknown extensions are skipped.
+        let extension = Extension {
+            tag: 100,
+            value: vec![1, 2, 3],
+        };
+        assert_eq!(bytes[1..3], [0, 0]);
+        let mut extended = vec![PROTOCOL_VERSION, 0, 1, 0, 100, 0, 3, 1, 2, 3];
+        extended.extend_from_slice(&bytes[3..]);
+        assert_eq!(
+            extended[1..10],
+            ExtensionsEncoder::default()
+                .encode_into_bytes(vec![extension])
+                .unwrap()[..]
+        );
+        let (destination, _) = VersionedDecoder::<DisconnectMessageDecoder>::default()
+            .decode_from_bytes(&extended)
+            .unwrap();
+        assert_eq!(destination, LocalNodeId::new(2));
+
+        bytes[0] = 0;
+        let e = VersionedDecoder::<DisconnectMessageDecoder>::default()
+            .decode_from_bytes(&bytes)
+            .err()
+            .unwrap();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn negotiated_version_is_encoded() {
+        let sender = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(1));
+        let item = (
+            LocalNodeId::new(2),
+            DisconnectMessage {
+                sender,
+                alive: false,
+            },
+        );
+        let mut encoder = VersionedEncoder::<DisconnectMessageEncoder>::with_version(
+            DisconnectMessageEncoder::default(),
+            protocol::MIN_PROTOCOL_VERSION,
+        );
+        let bytes = encoder.encode_into_bytes(item).unwrap();
+        assert_eq!(bytes[0], protocol::MIN_PROTOCOL_VERSION);
+
+        let (destination, _) = VersionedDecoder::<DisconnectMessageDecoder>::default()
+            .decode_from_bytes(&bytes)
+            .unwrap();
+        assert_eq!(destination, LocalNodeId::new(2));
+    }
+
+    #[test]
+    fn handshake_codec_works() {
+        let handshake = Handshake::local("[::1]:3000".parse().unwrap(), &[]);
+        let bytes = HandshakeEncoder::default()
+            .encode_into_bytes(handshake.clone())
+            .unwrap();
+        let decoded = HandshakeDecoder::default()
+            .decode_from_bytes(&bytes)
+            .unwrap();
+        assert_eq!(decoded, handshake);
+
+        // Codec identifiers follow the fixed part, and unknown ones are ignored.
+        let mut extended = bytes.clone();
+        extended.extend_from_slice(&[WireCodec::Varint.id(), 255]);
+        let decoded = HandshakeDecoder::default()
+            .decode_from_bytes(&extended)
+            .unwrap();
+        assert_eq!(decoded.codecs, vec![WireCodec::Varint]);
+    }
+}
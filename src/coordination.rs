@@ -0,0 +1,291 @@
+//! Leader hints disseminated over plumcast broadcasts.
+//!
+//! Many applications elect a leader by other means (e.g., Raft) and use plumcast only for
+//! telling the other nodes who the current leader is.
+//! This module provides a standard format for such announcements ([`LeaderAnnouncement`]) and
+//! a tracker of the received announcements ([`LeaderHint`]).
+//!
+//! The protocol is simple:
+//! a node that believes it is the leader periodically broadcasts an announcement
+//! (e.g., by [`Node::schedule_periodic_broadcast`]), and
+//! each node considers the announcements broadcasted within the last TTL as fresh.
+//! If there are multiple fresh announcements (e.g., during a leadership transfer),
+//! the one that has the highest term wins, and ties are broken by the highest `NodeId`.
+//! So all the nodes that have received the same announcements agree on the same leader.
+//!
+//! Note that this is just a hint: the freshness of an announcement is computed from
+//! its origin timestamp (see [`Message::origin_time`]), so the clock skew between the nodes
+//! should be much smaller than the TTL.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use plumcast::coordination::{LeaderAnnouncement, LeaderHint};
+//! use plumcast::node::{Node, SerialLocalNodeIdGenerator};
+//! use plumcast::service::Service;
+//! use std::time::Duration;
+//!
+//! let service = Service::<LeaderAnnouncement>::new(
+//!     "127.0.0.1:4000".parse().unwrap(),
+//!     fibers_global::handle(),
+//!     SerialLocalNodeIdGenerator::new(),
+//! );
+//! let mut node = Node::new(service.handle());
+//!
+//! // Announces the local node as the leader of the term `3` (elected elsewhere).
+//! let id = node.id();
+//! node.schedule_periodic_broadcast(Duration::from_secs(1), move || {
+//!     LeaderAnnouncement::new(id, 3)
+//! });
+//!
+//! // Tracks the announcements delivered to the node.
+//! let mut hint = LeaderHint::new(Duration::from_secs(5));
+//! # let message: plumcast::message::Message<LeaderAnnouncement> = unimplemented!();
+//! hint.handle_message(&message);
+//! println!("Leader: {:?}", hint.leader());
+//! ```
+//!
+//! [`LeaderAnnouncement`]: ./struct.LeaderAnnouncement.html
+//! [`LeaderHint`]: ./struct.LeaderHint.html
+//! [`Node::schedule_periodic_broadcast`]: ../node/struct.Node.html#method.schedule_periodic_broadcast
+//! [`Message::origin_time`]: ../message/struct.Message.html#method.origin_time
+use crate::codec::node::{NodeIdDecoder, NodeIdEncoder};
+use crate::message::{Message, MessagePayload};
+use crate::node::NodeId;
+use bytecodec::fixnum::{U64beDecoder, U64beEncoder};
+use bytecodec::{ByteCount, Decode, Encode, Eos, Result, SizedEncode};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// An announcement claiming that a node is the leader.
+///
+/// This can be used as the payload of broadcasting messages as is,
+/// or embedded in application payloads by using [`LeaderAnnouncementEncoder`] and
+/// [`LeaderAnnouncementDecoder`].
+///
+/// [`LeaderAnnouncementEncoder`]: ./struct.LeaderAnnouncementEncoder.html
+/// [`LeaderAnnouncementDecoder`]: ./struct.LeaderAnnouncementDecoder.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LeaderAnnouncement {
+    /// The node claiming to be the leader.
+    pub leader: NodeId,
+
+    /// The term (or epoch) in which the leader was elected.
+    ///
+    /// If the election mechanism has no notion of terms, `0` can be used.
+    pub term: u64,
+}
+impl LeaderAnnouncement {
+    /// Makes a new `LeaderAnnouncement` instance.
+    pub fn new(leader: NodeId, term: u64) -> Self {
+        LeaderAnnouncement { leader, term }
+    }
+}
+impl MessagePayload for LeaderAnnouncement {
+    type Encoder = LeaderAnnouncementEncoder;
+    type Decoder = LeaderAnnouncementDecoder;
+}
+
+/// Tracker of the leader announcements received by a node.
+///
+/// See the [module documentation] for the rule of choosing the leader.
+///
+/// [module documentation]: ./index.html
+#[derive(Debug, Clone)]
+pub struct LeaderHint {
+    ttl: Duration,
+    claims: HashMap<NodeId, Claim>,
+}
+impl LeaderHint {
+    /// Makes a new `LeaderHint` instance.
+    ///
+    /// Announcements are considered fresh until `ttl` has passed since they were broadcasted,
+    /// so `ttl` should be several times longer than the announcement interval.
+    pub fn new(ttl: Duration) -> Self {
+        LeaderHint {
+            ttl,
+            claims: HashMap::new(),
+        }
+    }
+
+    /// Returns the TTL of announcements.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Handles the announcement delivered by the given message.
+    pub fn handle_message(&mut self, message: &Message<LeaderAnnouncement>) {
+        self.handle_announcement(*message.payload(), message.origin_time());
+    }
+
+    /// Handles the given announcement broadcasted at `origin_time`.
+    ///
+    /// This is useful if announcements are embedded in application payloads.
+    pub fn handle_announcement(
+        &mut self,
+        announcement: LeaderAnnouncement,
+        origin_time: SystemTime,
+    ) {
+        let now = SystemTime::now();
+        self.expire(now);
+
+        let claim = Claim {
+            term: announcement.term,
+            expiry_time: origin_time + self.ttl,
+        };
+        if claim.expiry_time <= now {
+            return;
+        }
+        let current = self.claims.entry(announcement.leader).or_insert(claim);
+        if (claim.term, claim.expiry_time) > (current.term, current.expiry_time) {
+            *current = claim;
+        }
+    }
+
+    /// Returns the current leader hint.
+    ///
+    /// `None` is returned if there are no fresh announcements.
+    pub fn leader(&self) -> Option<NodeId> {
+        self.leader_at(SystemTime::now())
+    }
+
+    /// Forgets all the announcements.
+    pub fn clear(&mut self) {
+        self.claims.clear();
+    }
+
+    fn leader_at(&self, now: SystemTime) -> Option<NodeId> {
+        self.claims
+            .iter()
+            .filter(|(_, c)| now < c.expiry_time)
+            .max_by_key(|&(&leader, c)| (c.term, leader))
+            .map(|(&leader, _)| leader)
+    }
+
+    fn expire(&mut self, now: SystemTime) {
+        self.claims.retain(|_, c| now < c.expiry_time);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Claim {
+    term: u64,
+    expiry_time: SystemTime,
+}
+
+/// Decoder of [`LeaderAnnouncement`].
+///
+/// [`LeaderAnnouncement`]: ./struct.LeaderAnnouncement.html
+#[derive(Debug, Default)]
+pub struct LeaderAnnouncementDecoder {
+    leader: NodeIdDecoder,
+    term: U64beDecoder,
+}
+impl Decode for LeaderAnnouncementDecoder {
+    type Item = LeaderAnnouncement;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_decode!(self.leader, offset, buf, eos);
+        bytecodec_try_decode!(self.term, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let leader = track!(self.leader.finish_decoding())?;
+        let term = track!(self.term.finish_decoding())?;
+        Ok(LeaderAnnouncement { leader, term })
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.leader
+            .requiring_bytes()
+            .add_for_decoding(self.term.requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.leader.is_idle() && self.term.is_idle()
+    }
+}
+
+/// Encoder of [`LeaderAnnouncement`].
+///
+/// [`LeaderAnnouncement`]: ./struct.LeaderAnnouncement.html
+#[derive(Debug, Default)]
+pub struct LeaderAnnouncementEncoder {
+    leader: NodeIdEncoder,
+    term: U64beEncoder,
+}
+impl Encode for LeaderAnnouncementEncoder {
+    type Item = LeaderAnnouncement;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        let mut offset = 0;
+        bytecodec_try_encode!(self.leader, offset, buf, eos);
+        bytecodec_try_encode!(self.term, offset, buf, eos);
+        Ok(offset)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        track!(self.leader.start_encoding(item.leader))?;
+        track!(self.term.start_encoding(item.term))?;
+        Ok(())
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Finite(self.exact_requiring_bytes())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.leader.is_idle() && self.term.is_idle()
+    }
+}
+impl SizedEncode for LeaderAnnouncementEncoder {
+    fn exact_requiring_bytes(&self) -> u64 {
+        self.leader.exact_requiring_bytes() + self.term.exact_requiring_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::LocalNodeId;
+    use bytecodec::{DecodeExt, EncodeExt};
+
+    fn node(id: u64) -> NodeId {
+        NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(id))
+    }
+
+    #[test]
+    fn leader_hint_works() {
+        let mut hint = LeaderHint::new(Duration::from_secs(10));
+        let now = SystemTime::now();
+        assert_eq!(hint.leader(), None);
+
+        hint.handle_announcement(LeaderAnnouncement::new(node(2), 1), now);
+        hint.handle_announcement(LeaderAnnouncement::new(node(1), 1), now);
+        assert_eq!(hint.leader(), Some(node(2)));
+
+        // Higher terms win.
+        hint.handle_announcement(LeaderAnnouncement::new(node(1), 2), now);
+        assert_eq!(hint.leader(), Some(node(1)));
+
+        // Stale announcements are ignored.
+        let stale = now - Duration::from_secs(20);
+        hint.handle_announcement(LeaderAnnouncement::new(node(3), 5), stale);
+        assert_eq!(hint.leader(), Some(node(1)));
+        assert_eq!(hint.leader_at(now + Duration::from_secs(11)), None);
+    }
+
+    #[test]
+    fn leader_announcement_codec_works() {
+        let announcement = LeaderAnnouncement::new(node(7), 42);
+        let bytes = LeaderAnnouncementEncoder::default()
+            .encode_into_bytes(announcement)
+            .unwrap();
+        let decoded = LeaderAnnouncementDecoder::default()
+            .decode_from_bytes(&bytes)
+            .unwrap();
+        assert_eq!(decoded, announcement);
+    }
+}
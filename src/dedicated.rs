@@ -0,0 +1,167 @@
+//! [`DedicatedNode`] and related components.
+//!
+//! [`DedicatedNode`]: ./struct.DedicatedNode.html
+use crate::message::{Message, MessageId, MessagePayload};
+use crate::node::{Node, NodeId};
+use crate::sink::{Forward, SinkAdapter};
+use crate::Error;
+use fibers::sync::{mpsc as fibers_mpsc, oneshot};
+use fibers::Spawn;
+use futures::sync::mpsc;
+use futures::{Async, Future, Poll, Stream};
+use std::fmt;
+
+/// A handle of a [`Node`] that is driven by a fiber of another executor.
+///
+/// This is created by calling [`Node::spawn_on`] method.
+///
+/// The messages delivered to the node are yielded by this stream.
+/// They are passed through a channel with a fixed capacity,
+/// and the delivery is paused while the channel is full (see [`Forward`]).
+///
+/// If the node fails, the error is yielded after the messages delivered before the failure.
+/// The node is stopped (and leaves the cluster) when the handle is dropped.
+///
+/// [`Node`]: ../node/struct.Node.html
+/// [`Node::spawn_on`]: ../node/struct.Node.html#method.spawn_on
+/// [`Forward`]: ../sink/struct.Forward.html
+#[must_use = "streams do nothing unless polled"]
+pub struct DedicatedNode<M: MessagePayload> {
+    id: NodeId,
+    command_tx: fibers_mpsc::Sender<Command<M>>,
+    message_rx: mpsc::Receiver<Message<M>>,
+    error_rx: oneshot::Receiver<Error>,
+}
+impl<M: MessagePayload> DedicatedNode<M> {
+    pub(crate) fn spawn<S: Spawn>(node: Node<M>, spawner: &S, message_buffer: usize) -> Self {
+        let id = node.id();
+        let (command_tx, command_rx) = fibers_mpsc::channel();
+        let (message_tx, message_rx) = mpsc::channel(message_buffer);
+        let (error_tx, error_rx) = oneshot::channel();
+        spawner.spawn(DedicatedNodeTask {
+            forward: node.forward_to(SinkAdapter::new(message_tx)),
+            command_rx,
+            error_tx: Some(error_tx),
+        });
+        DedicatedNode {
+            id,
+            command_tx,
+            message_rx,
+            error_rx,
+        }
+    }
+
+    /// Returns the identifier of the node.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Broadcasts a message from the node.
+    ///
+    /// See [`Node::broadcast`] for more details.
+    ///
+    /// [`Node::broadcast`]: ../node/struct.Node.html#method.broadcast
+    pub fn broadcast(&self, message_payload: M) {
+        let _ = self.command_tx.send(Command::Broadcast(message_payload));
+    }
+
+    /// Forgets the specified message.
+    ///
+    /// See [`Node::forget_message`] for more details.
+    ///
+    /// [`Node::forget_message`]: ../node/struct.Node.html#method.forget_message
+    pub fn forget_message(&self, message_id: &MessageId) {
+        let _ = self.command_tx.send(Command::Forget(*message_id));
+    }
+}
+impl<M: MessagePayload> Stream for DedicatedNode<M> {
+    type Item = Message<M>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.message_rx.poll().expect("Never fails") {
+            Async::Ready(None) => {}
+            other => return Ok(other),
+        }
+        match self.error_rx.poll() {
+            Err(_) => Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(e)) => Err(track!(e)),
+        }
+    }
+}
+impl<M: MessagePayload> fmt::Debug for DedicatedNode<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DedicatedNode {{ id: {:?}, .. }}", self.id)
+    }
+}
+
+enum Command<M> {
+    Broadcast(M),
+    Forget(MessageId),
+}
+
+struct DedicatedNodeTask<M: MessagePayload> {
+    forward: Forward<M, SinkAdapter<M, mpsc::Sender<Message<M>>>>,
+    command_rx: fibers_mpsc::Receiver<Command<M>>,
+    error_tx: Option<oneshot::Sender<Error>>,
+}
+impl<M: MessagePayload> Future for DedicatedNodeTask<M> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.command_rx.poll().expect("Never fails") {
+                Async::NotReady => break,
+                Async::Ready(None) => {
+                    // NOTE: The handle has been dropped.
+                    return Ok(Async::Ready(()));
+                }
+                Async::Ready(Some(Command::Broadcast(payload))) => {
+                    self.forward.node_mut().broadcast(payload);
+                }
+                Async::Ready(Some(Command::Forget(id))) => {
+                    self.forward.node_mut().forget_message(&id);
+                }
+            }
+        }
+        match self.forward.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(())) => Ok(Async::Ready(())),
+            Err(e) => {
+                if let Some(tx) = self.error_tx.take() {
+                    let _ = tx.send(e);
+                }
+                Ok(Async::Ready(()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::SerialLocalNodeIdGenerator;
+    use crate::service::ServiceBuilder;
+
+    #[test]
+    fn dedicated_node_delivers_broadcasted_messages() {
+        let service = ServiceBuilder::new("127.0.0.1:0".parse().unwrap())
+            .enable_metrics(false)
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        let node = Node::<String>::new(service.handle());
+        let id = node.id();
+        let dedicated = node.spawn_on(&fibers_global::handle(), 1);
+        assert_eq!(dedicated.id(), id);
+
+        dedicated.broadcast("foo".to_owned());
+        dedicated.broadcast("bar".to_owned());
+        let messages = fibers_global::execute(dedicated.take(2).collect()).unwrap();
+        let payloads = messages
+            .iter()
+            .map(|m| m.payload().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(payloads, ["foo", "bar"]);
+    }
+}
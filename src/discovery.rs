@@ -0,0 +1,240 @@
+//! Local node discovery via UDP multicast (LAN mode).
+//!
+//! If [`NodeBuilder::lan_discovery`] is specified, a node periodically announces
+//! its identifier to a multicast group, and joins the cluster via a discovered node
+//! when the node has no neighbors.
+//! This enables zero-config local clusters (e.g., IoT devices or desktop applications)
+//! that form without a contact node known in advance.
+//!
+//! Note that the nodes announce the address of their RPC servers, so the servers should be bound
+//! to addresses reachable from the other nodes (not `0.0.0.0` or loopback addresses).
+//!
+//! [`NodeBuilder::lan_discovery`]: ../node/struct.NodeBuilder.html#method.lan_discovery
+use crate::codec::node::{NodeIdDecoder, NodeIdEncoder};
+use crate::node::NodeId;
+use crate::{Error, ErrorKind, Result};
+use bytecodec::{DecodeExt, EncodeExt};
+use fibers::net::futures::{RecvFrom, SendTo, UdpSocketBind};
+use fibers::net::UdpSocket;
+use fibers::time::timer::{self, Timeout};
+use futures::{Async, Future, Poll, Stream};
+use slog::Logger;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use trackable::error::ErrorKindExt;
+
+const MAGIC: &[u8; 8] = b"PLUMCAST";
+const MAX_ANNOUNCEMENT_SIZE: usize = 1024;
+
+/// Options for LAN discovery.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanDiscoveryOptions {
+    /// The multicast group (and the port) to which announcements are sent.
+    ///
+    /// The default value is `239.255.80.67:4646`.
+    pub group: SocketAddrV4,
+
+    /// The interval between announcements.
+    ///
+    /// The default value is `Duration::from_secs(5)`.
+    pub announce_interval: Duration,
+
+    /// The name of the cluster.
+    ///
+    /// Nodes only join the nodes announcing the same name,
+    /// so multiple clusters can share a multicast group.
+    /// The name must be at most 255 bytes.
+    ///
+    /// The default value is `"plumcast"`.
+    pub cluster_name: String,
+}
+impl Default for LanDiscoveryOptions {
+    fn default() -> Self {
+        LanDiscoveryOptions {
+            group: SocketAddrV4::new(Ipv4Addr::new(239, 255, 80, 67), 4646),
+            announce_interval: Duration::from_secs(5),
+            cluster_name: "plumcast".to_owned(),
+        }
+    }
+}
+
+/// A stream of the nodes discovered on the LAN.
+///
+/// This also announces the local node while being polled.
+#[derive(Debug)]
+pub(crate) struct LanDiscovery {
+    logger: Logger,
+    options: LanDiscoveryOptions,
+    node: NodeId,
+    announcement: Vec<u8>,
+    bind: Option<UdpSocketBind>,
+    socket: Option<UdpSocket>,
+    send: Option<SendTo<Vec<u8>>>,
+    recv: Option<RecvFrom<Vec<u8>>>,
+    announce_timeout: Timeout,
+}
+impl LanDiscovery {
+    pub(crate) fn new(logger: Logger, options: LanDiscoveryOptions, node: NodeId) -> Result<Self> {
+        let announcement = track!(encode_announcement(&options.cluster_name, node))?;
+        let bind_addr = SocketAddr::from(SocketAddrV4::new(
+            Ipv4Addr::UNSPECIFIED,
+            options.group.port(),
+        ));
+        Ok(LanDiscovery {
+            logger,
+            options,
+            node,
+            announcement,
+            bind: Some(UdpSocket::bind(bind_addr)),
+            socket: None,
+            send: None,
+            recv: None,
+            announce_timeout: timer::timeout(Duration::from_secs(0)),
+        })
+    }
+
+    fn poll_bind(&mut self) -> Poll<(), Error> {
+        if let Some(mut bind) = self.bind.take() {
+            let socket = match track!(bind.poll().map_err(io_error))? {
+                Async::NotReady => {
+                    self.bind = Some(bind);
+                    return Ok(Async::NotReady);
+                }
+                Async::Ready(socket) => socket,
+            };
+            let group = *self.options.group.ip();
+            track!(socket
+                .with_inner(|s| s.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED))
+                .map_err(io_error))?;
+            track!(socket
+                .with_inner(|s| s.set_multicast_loop_v4(true))
+                .map_err(io_error))?;
+            info!(
+                self.logger,
+                "Joined the LAN discovery group: {}", self.options.group
+            );
+            self.recv = Some(socket.clone().recv_from(vec![0; MAX_ANNOUNCEMENT_SIZE]));
+            self.socket = Some(socket);
+        }
+        Ok(Async::Ready(()))
+    }
+
+    fn poll_announce(&mut self) -> Result<()> {
+        while track!(self.announce_timeout.poll().map_err(Error::from))?.is_ready() {
+            self.announce_timeout = timer::timeout(self.options.announce_interval);
+            if self.send.is_none() {
+                if let Some(ref socket) = self.socket {
+                    let target = SocketAddr::from(self.options.group);
+                    let send = socket.clone().send_to(self.announcement.clone(), target);
+                    self.send = Some(send);
+                }
+            }
+        }
+        if let Some(mut send) = self.send.take() {
+            match send.poll() {
+                Err((_, _, e)) => warn!(self.logger, "Cannot send a LAN announcement: {}", e),
+                Ok(Async::NotReady) => self.send = Some(send),
+                Ok(Async::Ready(_)) => {}
+            }
+        }
+        Ok(())
+    }
+}
+impl Stream for LanDiscovery {
+    type Item = NodeId;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if track!(self.poll_bind())?.is_not_ready() {
+            return Ok(Async::NotReady);
+        }
+        track!(self.poll_announce())?;
+
+        while let Some(mut recv) = self.recv.take() {
+            let (socket, buf, size, from) = match recv.poll() {
+                Err((socket, buf, e)) => {
+                    warn!(self.logger, "Cannot receive a LAN announcement: {}", e);
+                    self.recv = Some(socket.recv_from(buf));
+                    continue;
+                }
+                Ok(Async::NotReady) => {
+                    self.recv = Some(recv);
+                    return Ok(Async::NotReady);
+                }
+                Ok(Async::Ready(x)) => x,
+            };
+            let discovered = decode_announcement(&self.options.cluster_name, &buf[..size]);
+            self.recv = Some(socket.recv_from(buf));
+            match discovered {
+                Err(e) => debug!(
+                    self.logger,
+                    "Ignored a malformed LAN announcement from {}: {}", from, e
+                ),
+                Ok(None) => {}
+                Ok(Some(node)) if node == self.node => {}
+                Ok(Some(node)) => return Ok(Async::Ready(Some(node))),
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+fn encode_announcement(cluster_name: &str, node: NodeId) -> Result<Vec<u8>> {
+    track_assert!(
+        cluster_name.len() <= 0xFF,
+        ErrorKind::InvalidInput,
+        "Too long cluster name: {:?}",
+        cluster_name
+    );
+    let node = track!(NodeIdEncoder::default()
+        .encode_into_bytes(node)
+        .map_err(|e| ErrorKind::InvalidInput.takes_over(e)))?;
+
+    let mut buf = Vec::with_capacity(MAGIC.len() + 1 + cluster_name.len() + node.len());
+    buf.extend_from_slice(MAGIC);
+    buf.push(cluster_name.len() as u8);
+    buf.extend_from_slice(cluster_name.as_bytes());
+    buf.extend_from_slice(&node);
+    Ok(buf)
+}
+
+/// Decodes an announcement.
+///
+/// Returns `Ok(None)` if the announcement belongs to another cluster.
+fn decode_announcement(cluster_name: &str, buf: &[u8]) -> Result<Option<NodeId>> {
+    track_assert!(
+        buf.len() > MAGIC.len() && buf.starts_with(MAGIC),
+        ErrorKind::InvalidInput
+    );
+    let buf = &buf[MAGIC.len()..];
+    let name_len = buf[0] as usize;
+    track_assert!(buf.len() > name_len, ErrorKind::InvalidInput);
+    if &buf[1..=name_len] != cluster_name.as_bytes() {
+        return Ok(None);
+    }
+    let node = track!(NodeIdDecoder::default()
+        .decode_from_bytes(&buf[1 + name_len..])
+        .map_err(|e| ErrorKind::InvalidInput.takes_over(e)))?;
+    Ok(Some(node))
+}
+
+fn io_error(e: std::io::Error) -> Error {
+    ErrorKind::Other.cause(e).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::LocalNodeId;
+
+    #[test]
+    fn announcement_codec_works() {
+        let node = NodeId::new("192.168.0.10:3000".parse().unwrap(), LocalNodeId::new(7));
+        let buf = encode_announcement("foo", node).unwrap();
+        assert_eq!(decode_announcement("foo", &buf).unwrap(), Some(node));
+        assert_eq!(decode_announcement("bar", &buf).unwrap(), None);
+        assert!(decode_announcement("foo", &buf[..buf.len() - 1]).is_err());
+        assert!(decode_announcement("foo", b"PLUMCAS").is_err());
+        assert!(encode_announcement(&"x".repeat(256), node).is_err());
+    }
+}
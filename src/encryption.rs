@@ -0,0 +1,201 @@
+//! Encryption of the application payloads of gossip messages.
+//!
+//! A sealed payload consists of the 32-bit identifier of the key (big endian),
+//! a random 24-byte nonce and the XChaCha20-Poly1305 ciphertext (including the authentication tag)
+//! of the encoded payload.
+//! The identifier of the message is used as the associated data,
+//! so a sealed payload cannot be replayed as the payload of another message.
+//!
+//! Payloads are always sealed with the primary key, but can be opened with any registered key,
+//! so keys can be rotated without a coordinated restart of the cluster.
+use crate::message::MessageId;
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::Rng;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+const KEY_ID_SIZE: usize = 4;
+const NONCE_SIZE: usize = 24;
+
+/// A cipher sealing and opening payloads with per-cluster symmetric keys.
+///
+/// The clones of a cipher share the same keys.
+#[derive(Clone)]
+pub(crate) struct PayloadCipher(Arc<RwLock<Keyring>>);
+impl PayloadCipher {
+    pub(crate) fn new(key_id: u32, key: [u8; 32]) -> Self {
+        let mut keys = HashMap::new();
+        keys.insert(key_id, new_aead(&key));
+        PayloadCipher(Arc::new(RwLock::new(Keyring {
+            primary: key_id,
+            keys,
+        })))
+    }
+
+    /// Registers a key that can be used for opening payloads.
+    ///
+    /// If a key having the same identifier exists, it is replaced.
+    pub(crate) fn add_key(&self, key_id: u32, key: [u8; 32]) {
+        let mut keyring = self.0.write().expect("Never fails");
+        keyring.keys.insert(key_id, new_aead(&key));
+    }
+
+    /// Removes the given key.
+    ///
+    /// Returns `false` if the key is missing or the primary key.
+    pub(crate) fn remove_key(&self, key_id: u32) -> bool {
+        let mut keyring = self.0.write().expect("Never fails");
+        keyring.primary != key_id && keyring.keys.remove(&key_id).is_some()
+    }
+
+    /// Makes the given key the one used for sealing payloads.
+    ///
+    /// Returns `false` if the key is missing.
+    pub(crate) fn set_primary_key(&self, key_id: u32) -> bool {
+        let mut keyring = self.0.write().expect("Never fails");
+        if keyring.keys.contains_key(&key_id) {
+            keyring.primary = key_id;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the identifier of the primary key.
+    pub(crate) fn primary_key_id(&self) -> u32 {
+        self.0.read().expect("Never fails").primary
+    }
+
+    /// Returns the identifiers of the registered keys in ascending order.
+    pub(crate) fn key_ids(&self) -> Vec<u32> {
+        let keyring = self.0.read().expect("Never fails");
+        let mut ids = keyring.keys.keys().cloned().collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    /// Encrypts the encoded payload of the message identified by `id` with the primary key.
+    pub(crate) fn seal(&self, id: &MessageId, payload: &[u8]) -> Option<Vec<u8>> {
+        let keyring = self.0.read().expect("Never fails");
+        let key_id = keyring.primary;
+        let aead = &keyring.keys[&key_id];
+
+        let mut nonce = [0; NONCE_SIZE];
+        rand::thread_rng().fill(&mut nonce[..]);
+        let aad = associated_data(id);
+        let ciphertext = aead
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: payload,
+                    aad: &aad,
+                },
+            )
+            .ok()?;
+
+        let mut sealed = Vec::with_capacity(KEY_ID_SIZE + NONCE_SIZE + ciphertext.len());
+        sealed.extend_from_slice(&key_id.to_be_bytes());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Some(sealed)
+    }
+
+    /// Decrypts the sealed payload of the message identified by `id`.
+    ///
+    /// `None` is returned if the payload has been tampered or sealed with an unknown key.
+    pub(crate) fn open(&self, id: &MessageId, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < KEY_ID_SIZE + NONCE_SIZE {
+            return None;
+        }
+        let (key_id, rest) = sealed.split_at(KEY_ID_SIZE);
+        let (nonce, ciphertext) = rest.split_at(NONCE_SIZE);
+        let key_id = u32::from_be_bytes([key_id[0], key_id[1], key_id[2], key_id[3]]);
+
+        let keyring = self.0.read().expect("Never fails");
+        let aead = keyring.keys.get(&key_id)?;
+        let aad = associated_data(id);
+        aead.decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: &aad,
+            },
+        )
+        .ok()
+    }
+}
+impl fmt::Debug for PayloadCipher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PayloadCipher {{ primary_key_id: {}, key_ids: {:?} }}",
+            self.primary_key_id(),
+            self.key_ids()
+        )
+    }
+}
+
+struct Keyring {
+    primary: u32,
+    keys: HashMap<u32, XChaCha20Poly1305>,
+}
+
+fn new_aead(key: &[u8; 32]) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new(Key::from_slice(key))
+}
+
+fn associated_data(id: &MessageId) -> Vec<u8> {
+    let mut aad = id.node().to_string().into_bytes();
+    aad.extend_from_slice(&id.seqno().to_be_bytes());
+    aad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::{LocalNodeId, NodeId};
+
+    fn message_id(seqno: u64) -> MessageId {
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(0));
+        MessageId::new(node, seqno)
+    }
+
+    #[test]
+    fn sealed_payloads_can_be_opened() {
+        let id = message_id(1);
+        let cipher = PayloadCipher::new(0, [7; 32]);
+
+        let sealed = cipher.seal(&id, b"foo").unwrap();
+        assert_eq!(cipher.open(&id, &sealed), Some(b"foo".to_vec()));
+
+        // Other messages or keys.
+        assert_eq!(cipher.open(&message_id(2), &sealed), None);
+        assert_eq!(PayloadCipher::new(0, [8; 32]).open(&id, &sealed), None);
+        assert_eq!(cipher.open(&id, &sealed[..10]), None);
+    }
+
+    #[test]
+    fn keys_can_be_rotated() {
+        let id = message_id(1);
+        let old = PayloadCipher::new(1, [1; 32]);
+        let new = PayloadCipher::new(1, [1; 32]);
+
+        new.add_key(2, [2; 32]);
+        assert!(new.set_primary_key(2));
+        assert!(!new.remove_key(2));
+        let sealed = new.seal(&id, b"foo").unwrap();
+        assert_eq!(new.open(&id, &sealed), Some(b"foo".to_vec()));
+        assert_eq!(old.open(&id, &sealed), None);
+
+        // Payloads sealed with the old key can still be opened.
+        let sealed = old.seal(&id, b"bar").unwrap();
+        assert_eq!(new.open(&id, &sealed), Some(b"bar".to_vec()));
+
+        assert!(new.remove_key(1));
+        assert_eq!(new.open(&id, &sealed), None);
+        assert_eq!(new.key_ids(), vec![2]);
+        assert!(!new.set_primary_key(1));
+    }
+}
@@ -1,8 +1,19 @@
+use crate::node::NodeId;
 use trackable::error::{ErrorKind as TrackableErrorKind, ErrorKindExt, TrackableError};
 
 /// This crate specific `Error` type.
 #[derive(Debug, Clone, TrackableError)]
 pub struct Error(TrackableError<ErrorKind>);
+impl Error {
+    /// Returns `true` if the error is transient (i.e., retrying the operation may succeed).
+    ///
+    /// See [`ErrorKind::is_transient`] for more details.
+    ///
+    /// [`ErrorKind::is_transient`]: ./enum.ErrorKind.html#method.is_transient
+    pub fn is_transient(&self) -> bool {
+        self.kind().is_transient()
+    }
+}
 impl From<std::sync::mpsc::RecvError> for Error {
     fn from(f: std::sync::mpsc::RecvError) -> Self {
         ErrorKind::Other.cause(f).into()
@@ -12,14 +23,20 @@ impl From<fibers_rpc::Error> for Error {
     fn from(f: fibers_rpc::Error) -> Self {
         let kind = match f.kind() {
             fibers_rpc::ErrorKind::InvalidInput => ErrorKind::InvalidInput,
-            fibers_rpc::ErrorKind::Timeout
-            | fibers_rpc::ErrorKind::Unavailable
-            | fibers_rpc::ErrorKind::Other => ErrorKind::Other,
+            fibers_rpc::ErrorKind::Timeout => ErrorKind::Timeout,
+            fibers_rpc::ErrorKind::Unavailable => ErrorKind::QueueFull,
+            fibers_rpc::ErrorKind::Other => ErrorKind::Other,
         };
         let rpc_error_kind = *f.kind();
         track!(kind.takes_over(f); rpc_error_kind).into()
     }
 }
+#[cfg(feature = "exporter")]
+impl From<fibers_http_server::Error> for Error {
+    fn from(f: fibers_http_server::Error) -> Self {
+        ErrorKind::Other.takes_over(f).into()
+    }
+}
 
 /// Possible error kinds.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -32,7 +49,66 @@ pub enum ErrorKind {
     /// There are probably bugs in the program.
     InconsistentState,
 
+    /// The peer could not be reached.
+    ///
+    /// This is returned when sending a message to the node fails for reasons other than
+    /// the ones represented by the other kinds (e.g., the peer speaks an incompatible protocol).
+    PeerUnreachable(NodeId),
+
+    /// The message could not be sent because the send queue to the peer is full.
+    ///
+    /// This is also returned by `NodeBuilder::finish()` if the queue of the pending
+    /// registrations of the service is full.
+    QueueFull,
+
+    /// The operation did not complete in time.
+    Timeout,
+
     /// Other errors.
     Other,
 }
+impl ErrorKind {
+    /// Returns `true` if the kind represents a transient error.
+    ///
+    /// `PeerUnreachable`, `QueueFull` and `Timeout` are transient,
+    /// so the operations failed with them may succeed by retrying later.
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            ErrorKind::PeerUnreachable(_) | ErrorKind::QueueFull | ErrorKind::Timeout => true,
+            ErrorKind::InvalidInput | ErrorKind::InconsistentState | ErrorKind::Other => false,
+        }
+    }
+}
 impl TrackableErrorKind for ErrorKind {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::LocalNodeId;
+
+    #[test]
+    fn rpc_errors_are_mapped_to_transient_kinds() {
+        let kind_of = |kind: fibers_rpc::ErrorKind| {
+            *Error::from(fibers_rpc::Error::from(kind.error())).kind()
+        };
+        assert_eq!(kind_of(fibers_rpc::ErrorKind::Timeout), ErrorKind::Timeout);
+        assert_eq!(
+            kind_of(fibers_rpc::ErrorKind::Unavailable),
+            ErrorKind::QueueFull
+        );
+        assert_eq!(
+            kind_of(fibers_rpc::ErrorKind::InvalidInput),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(kind_of(fibers_rpc::ErrorKind::Other), ErrorKind::Other);
+
+        let peer = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(0));
+        assert!(Error::from(ErrorKind::PeerUnreachable(peer).error()).is_transient());
+        assert!(Error::from(fibers_rpc::Error::from(
+            fibers_rpc::ErrorKind::Timeout.error()
+        ))
+        .is_transient());
+        assert!(!Error::from(ErrorKind::InvalidInput.error()).is_transient());
+        assert!(!Error::from(ErrorKind::Other.error()).is_transient());
+    }
+}
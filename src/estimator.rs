@@ -0,0 +1,130 @@
+use crate::node::NodeId;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// The cluster size for which the default Plumtree options are tuned.
+const REFERENCE_CLUSTER_SIZE: f64 = 10.0;
+
+/// An estimator of the number of the nodes in a cluster.
+///
+/// The estimation is based on the capture-recapture method (i.e., Lincoln-Petersen estimator).
+/// The nodes contained in the HyParView `SHUFFLE` and `SHUFFLE_REPLY` messages
+/// are regarded as random samples of the cluster.
+/// The samples are collected per shuffle round, and
+/// the size is estimated from the overlap of the samples of the last two rounds.
+#[derive(Debug, Clone)]
+pub(crate) struct ClusterSizeEstimator {
+    local_node: NodeId,
+    previous: HashSet<NodeId>,
+    current: HashSet<NodeId>,
+    estimate: usize,
+}
+impl ClusterSizeEstimator {
+    pub(crate) fn new(local_node: NodeId) -> Self {
+        ClusterSizeEstimator {
+            local_node,
+            previous: HashSet::new(),
+            current: HashSet::new(),
+            estimate: 1,
+        }
+    }
+
+    /// Returns the latest estimate.
+    ///
+    /// The value is never less than `known_nodes` (i.e., the number of the nodes
+    /// in the HyParView views) plus one (i.e., the local node).
+    pub(crate) fn estimate(&self, known_nodes: usize) -> usize {
+        self.estimate.max(known_nodes + 1)
+    }
+
+    pub(crate) fn observe<'a, I>(&mut self, nodes: I)
+    where
+        I: IntoIterator<Item = &'a NodeId>,
+    {
+        let local_node = self.local_node;
+        self.current
+            .extend(nodes.into_iter().filter(|&&n| n != local_node));
+    }
+
+    /// Finishes the current sampling round and updates the estimate.
+    pub(crate) fn rotate(&mut self) {
+        if !self.current.is_empty() {
+            let recaptured = self.previous.intersection(&self.current).count();
+            self.estimate = if recaptured == 0 {
+                // Too few samples to estimate; the distinct nodes are used as a lower bound.
+                self.previous.union(&self.current).count() + 1
+            } else {
+                self.previous.len() * self.current.len() / recaptured + 1
+            };
+        }
+        self.previous = std::mem::replace(&mut self.current, HashSet::new());
+    }
+}
+
+/// Returns the number of the eager push peers suitable for the given cluster size.
+///
+/// Gossip with a fanout of `ln(N) + 1` delivers messages to all the `N` nodes with high probability.
+pub(crate) fn adaptive_eager_push_degree(cluster_size: usize) -> usize {
+    (cluster_size.max(1) as f64).ln().ceil() as usize + 1
+}
+
+/// Scales the given `IHAVE` timeout by the expected depth of the broadcast tree,
+/// which is proportional to the logarithm of the cluster size.
+///
+/// The timeout is never shortened below `base`.
+pub(crate) fn adaptive_ihave_timeout(base: Duration, cluster_size: usize) -> Duration {
+    let factor = (cluster_size as f64).log10() / REFERENCE_CLUSTER_SIZE.log10();
+    if factor <= 1.0 {
+        return base;
+    }
+    let millis = base.as_secs() as f64 * 1000.0 + f64::from(base.subsec_millis());
+    Duration::from_millis((millis * factor) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::LocalNodeId;
+
+    fn node(n: u64) -> NodeId {
+        NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(n))
+    }
+
+    #[test]
+    fn cluster_size_is_estimated_from_recaptured_samples() {
+        let mut e = ClusterSizeEstimator::new(node(0));
+        assert_eq!(e.estimate(0), 1);
+        assert_eq!(e.estimate(3), 4);
+
+        let first = (1..=10).map(node).collect::<Vec<_>>();
+        e.observe(&first);
+        e.observe(&[node(0)]);
+        e.rotate();
+        assert_eq!(e.estimate(0), 11);
+
+        // 10 * 10 / 5 + 1 (the local node)
+        let second = (6..=15).map(node).collect::<Vec<_>>();
+        e.observe(&second);
+        e.rotate();
+        assert_eq!(e.estimate(0), 21);
+
+        // Empty rounds do not reset the estimate.
+        e.rotate();
+        assert_eq!(e.estimate(0), 21);
+    }
+
+    #[test]
+    fn adaptive_parameters_grow_with_cluster_size() {
+        assert_eq!(adaptive_eager_push_degree(0), 1);
+        assert_eq!(adaptive_eager_push_degree(10), 4);
+        assert_eq!(adaptive_eager_push_degree(10_000), 11);
+
+        let base = Duration::from_millis(500);
+        assert_eq!(adaptive_ihave_timeout(base, 1), base);
+        assert_eq!(adaptive_ihave_timeout(base, 10), base);
+        assert_eq!(
+            adaptive_ihave_timeout(base, 10_000),
+            Duration::from_millis(2000)
+        );
+    }
+}
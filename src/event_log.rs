@@ -0,0 +1,146 @@
+use crate::admin::DebugEvent;
+use crate::node::NodeId;
+use crate::{Error, ErrorKind};
+use slog::Logger;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// A ring buffer that keeps the most recent protocol events.
+///
+/// The events are dumped to the logger when an `ErrorKind::InconsistentState` error is detected,
+/// so that the history leading up to the violation can be examined afterwards.
+/// They can also be taken on demand as [`DebugEvent`]s (e.g., via the admin RPC).
+///
+/// The clones of a log share the same buffer.
+///
+/// [`DebugEvent`]: ../admin/struct.DebugEvent.html
+#[derive(Debug, Clone)]
+pub(crate) struct EventLog {
+    capacity: usize,
+    inner: Arc<Mutex<Inner>>,
+}
+impl EventLog {
+    pub(crate) fn new(capacity: usize) -> Self {
+        EventLog {
+            capacity,
+            inner: Arc::new(Mutex::new(Inner {
+                next_seqno: 0,
+                events: VecDeque::with_capacity(capacity),
+            })),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub(crate) fn record<F>(&mut self, kind: &'static str, peer: Option<NodeId>, detail: F)
+    where
+        F: FnOnce() -> String,
+    {
+        if !self.is_enabled() {
+            return;
+        }
+        let detail = detail();
+        let mut inner = self.inner.lock().expect("Never fails");
+        if inner.events.len() == self.capacity {
+            inner.events.pop_front();
+        }
+        let seqno = inner.next_seqno;
+        inner.events.push_back(Event {
+            seqno,
+            time: SystemTime::now(),
+            kind,
+            peer,
+            detail,
+        });
+        inner.next_seqno += 1;
+    }
+
+    /// Returns the kept events as the ones recorded by `node`.
+    pub(crate) fn debug_events(&self, node: NodeId) -> Vec<DebugEvent> {
+        let inner = self.inner.lock().expect("Never fails");
+        inner
+            .events
+            .iter()
+            .map(|e| DebugEvent {
+                node,
+                seqno: e.seqno,
+                time: e.time,
+                kind: e.kind.to_owned(),
+                peer: e.peer,
+                detail: e.detail.clone(),
+            })
+            .collect()
+    }
+
+    pub(crate) fn dump(&self, logger: &Logger) {
+        if !self.is_enabled() {
+            return;
+        }
+        let inner = self.inner.lock().expect("Never fails");
+        error!(
+            logger,
+            "Dumps the last {} protocol events",
+            inner.events.len()
+        );
+        for e in &inner.events {
+            error!(logger, "Protocol event";
+                   "seqno" => e.seqno,
+                   "kind" => e.kind,
+                   "peer" => e.peer.map(|p| p.to_string()),
+                   "detail" => &e.detail);
+        }
+    }
+
+    pub(crate) fn dump_if_inconsistent(&self, logger: &Logger, error: &Error) {
+        if *error.kind() == ErrorKind::InconsistentState {
+            self.dump(logger);
+        }
+    }
+
+    #[cfg(test)]
+    fn events(&self) -> VecDeque<Event> {
+        self.inner.lock().expect("Never fails").events.clone()
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    next_seqno: u64,
+    events: VecDeque<Event>,
+}
+
+#[derive(Debug, Clone)]
+struct Event {
+    seqno: u64,
+    time: SystemTime,
+    kind: &'static str,
+    peer: Option<NodeId>,
+    detail: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_log_keeps_last_events() {
+        let mut log = EventLog::new(2);
+        log.record("a", None, || "0".to_owned());
+        log.record("b", None, || "1".to_owned());
+        log.record("c", None, || "2".to_owned());
+
+        let kinds = log.events().iter().map(|e| e.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, ["b", "c"]);
+        assert_eq!(log.events()[0].seqno, 1);
+    }
+
+    #[test]
+    fn disabled_event_log_records_nothing() {
+        let mut log = EventLog::new(0);
+        log.record("a", None, || panic!());
+        assert!(log.events().is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+//! Built-in [Prometheus][prometheus] HTTP exporter.
+//!
+//! [`MetricsServer`] serves the metrics registered to the prometrics default gatherer
+//! (including the plumcast metrics defined in the [`metrics`] module) at `GET /metrics`.
+//!
+//! This module is available only if the `exporter` feature is enabled.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use fibers::{Executor, Spawn, ThreadPoolExecutor};
+//! use futures::Future;
+//! use plumcast::exporter::MetricsServerBuilder;
+//! use plumcast::node::UnixtimeLocalNodeIdGenerator;
+//! use plumcast::service::ServiceBuilder;
+//!
+//! let mut executor = ThreadPoolExecutor::new().unwrap();
+//! let service = ServiceBuilder::new("127.0.0.1:3000".parse().unwrap())
+//!     .finish::<_, Vec<u8>, _>(executor.handle(), UnixtimeLocalNodeIdGenerator::new());
+//! let metrics_server = MetricsServerBuilder::new("127.0.0.1:9100".parse().unwrap())
+//!     .finish(executor.handle())
+//!     .unwrap();
+//!
+//! executor.spawn(service.map_err(|e| panic!("{}", e)));
+//! executor.spawn(metrics_server.map_err(|e| panic!("{}", e)));
+//! executor.run().unwrap();
+//! ```
+//!
+//! [prometheus]: https://prometheus.io/
+//! [`MetricsServer`]: ./struct.MetricsServer.html
+//! [`metrics`]: ../metrics/index.html
+use crate::{Error, Result};
+use bytecodec::bytes::Utf8Encoder;
+use bytecodec::null::NullDecoder;
+use fibers::Spawn;
+use fibers_http_server::{
+    HandleRequest, Reply, Req, Res, Server as HttpServer, ServerBuilder as HttpServerBuilder,
+    Status,
+};
+use futures::{Future, Poll};
+use httpcodec::{BodyDecoder, BodyEncoder};
+use slog::{Discard, Logger};
+use std::fmt;
+use std::net::SocketAddr;
+
+/// The builder of [`MetricsServer`].
+///
+/// [`MetricsServer`]: ./struct.MetricsServer.html
+#[derive(Debug)]
+pub struct MetricsServerBuilder {
+    logger: Logger,
+    bind_addr: SocketAddr,
+}
+impl MetricsServerBuilder {
+    /// Makes a new `MetricsServerBuilder` instance with the default settings.
+    pub fn new(bind_addr: SocketAddr) -> Self {
+        MetricsServerBuilder {
+            logger: Logger::root(Discard, o!()),
+            bind_addr,
+        }
+    }
+
+    /// Sets the logger used by the server.
+    ///
+    /// The default value is `Logger::root(Discard, o!())`.
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    /// Builds a [`MetricsServer`] with the given settings.
+    ///
+    /// [`MetricsServer`]: ./struct.MetricsServer.html
+    pub fn finish<S>(self, spawner: S) -> Result<MetricsServer>
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let mut builder = HttpServerBuilder::new(self.bind_addr);
+        builder.logger(self.logger);
+        track!(builder.add_handler(GetMetrics))?;
+        Ok(MetricsServer(builder.finish(spawner)))
+    }
+}
+
+/// A [`Future`] that serves the metrics registered to the prometrics default gatherer over HTTP.
+///
+/// Usually this is spawned alongside [`Service`].
+///
+/// [`Future`]: https://docs.rs/futures/0.1/futures/future/trait.Future.html
+/// [`Service`]: ../service/struct.Service.html
+pub struct MetricsServer(HttpServer);
+impl fmt::Debug for MetricsServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MetricsServer {{ .. }}")
+    }
+}
+impl Future for MetricsServer {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        track!(self.0.poll().map_err(Error::from))
+    }
+}
+
+struct GetMetrics;
+impl HandleRequest for GetMetrics {
+    const METHOD: &'static str = "GET";
+    const PATH: &'static str = "/metrics";
+
+    type ReqBody = ();
+    type ResBody = String;
+    type Decoder = BodyDecoder<NullDecoder>;
+    type Encoder = BodyEncoder<Utf8Encoder>;
+    type Reply = Reply<Self::ResBody>;
+
+    fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+        let res = match prometrics::default_gatherer().lock() {
+            Ok(mut gatherer) => Res::new(Status::Ok, gatherer.gather().to_text()),
+            Err(e) => Res::new(Status::InternalServerError, e.to_string()),
+        };
+        Box::new(futures::future::ok(res))
+    }
+}
@@ -0,0 +1,152 @@
+use crate::node::NodeId;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// The kinds of HyParView messages checked by [`JoinPolicy`].
+///
+/// [`JoinPolicy`]: ./trait.JoinPolicy.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JoinKind {
+    /// A `JOIN` message sent by a node joining the cluster via a local node.
+    Join,
+
+    /// A `FORWARD_JOIN` message relayed on behalf of a node joining the cluster.
+    ForwardJoin,
+
+    /// A `NEIGHBOR` message sent by a node requesting to be added to the active view.
+    Neighbor,
+}
+
+/// This trait allows for restricting the nodes that can enter the overlay via local nodes.
+///
+/// A policy is consulted by a [`Service`] for each incoming `JOIN`, `FORWARD_JOIN` and
+/// `NEIGHBOR` message before the message is delivered to the destination node.
+/// The rejected `JOIN` and `NEIGHBOR` messages are answered with `DISCONNECT` messages,
+/// and the rejected `FORWARD_JOIN` messages are dropped
+/// (i.e., the joining node is not added to the views of the local node).
+///
+/// Note that the checked addresses are the ones advertised by the peers
+/// (i.e., the addresses of the RPC servers contained in the messages), not
+/// the source addresses of the connections.
+///
+/// [`Service`]: ./struct.Service.html
+pub trait JoinPolicy: Send + Sync + 'static {
+    /// Returns `true` if `peer` is allowed to enter the overlay by the message of `kind`.
+    fn is_allowed(&self, peer: &NodeId, kind: JoinKind) -> bool;
+}
+impl<F> JoinPolicy for F
+where
+    F: Fn(&NodeId, JoinKind) -> bool + Send + Sync + 'static,
+{
+    fn is_allowed(&self, peer: &NodeId, kind: JoinKind) -> bool {
+        self(peer, kind)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ArcJoinPolicy(Arc<dyn JoinPolicy>);
+impl ArcJoinPolicy {
+    pub(crate) fn new<T: JoinPolicy>(inner: T) -> Self {
+        ArcJoinPolicy(Arc::new(inner))
+    }
+}
+impl fmt::Debug for ArcJoinPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ArcJoinPolicy(_)")
+    }
+}
+impl JoinPolicy for ArcJoinPolicy {
+    fn is_allowed(&self, peer: &NodeId, kind: JoinKind) -> bool {
+        self.0.is_allowed(peer, kind)
+    }
+}
+
+/// An implementation of [`JoinPolicy`] that allows only the nodes in the given address ranges.
+///
+/// # Examples
+///
+/// ```
+/// use plumcast::service::AddrRangeJoinPolicy;
+///
+/// let policy = AddrRangeJoinPolicy::new()
+///     .allow("10.0.0.0".parse().unwrap(), 8)
+///     .allow("192.168.1.0".parse().unwrap(), 24);
+/// # let _ = policy;
+/// ```
+///
+/// [`JoinPolicy`]: ./trait.JoinPolicy.html
+#[derive(Debug, Default, Clone)]
+pub struct AddrRangeJoinPolicy {
+    ranges: Vec<(IpAddr, u8)>,
+}
+impl AddrRangeJoinPolicy {
+    /// Makes a new `AddrRangeJoinPolicy` instance that rejects all the nodes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows the nodes which addresses are in the range `addr/prefix_len` (CIDR notation).
+    ///
+    /// `prefix_len` is truncated to the bit length of `addr`.
+    pub fn allow(mut self, addr: IpAddr, prefix_len: u8) -> Self {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        self.ranges.push((addr, prefix_len.min(max)));
+        self
+    }
+}
+impl JoinPolicy for AddrRangeJoinPolicy {
+    fn is_allowed(&self, peer: &NodeId, _kind: JoinKind) -> bool {
+        let ip = peer.address().ip();
+        self.ranges
+            .iter()
+            .any(|&(addr, prefix_len)| in_range(ip, addr, prefix_len))
+    }
+}
+
+fn in_range(ip: IpAddr, addr: IpAddr, prefix_len: u8) -> bool {
+    match (ip, addr) {
+        (IpAddr::V4(ip), IpAddr::V4(addr)) => {
+            let mask = u32::max_value()
+                .checked_shl(32 - u32::from(prefix_len))
+                .unwrap_or(0);
+            u32::from(ip) & mask == u32::from(addr) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(addr)) => {
+            let mask = u128::max_value()
+                .checked_shl(128 - u32::from(prefix_len))
+                .unwrap_or(0);
+            u128::from(ip) & mask == u128::from(addr) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::LocalNodeId;
+
+    fn node(addr: &str) -> NodeId {
+        NodeId::new(addr.parse().unwrap(), LocalNodeId::new(0))
+    }
+
+    #[test]
+    fn addr_range_join_policy_works() {
+        let policy = AddrRangeJoinPolicy::new()
+            .allow("10.1.0.0".parse().unwrap(), 16)
+            .allow("fd00::".parse().unwrap(), 8)
+            .allow("192.168.0.1".parse().unwrap(), 32);
+
+        assert!(policy.is_allowed(&node("10.1.2.3:3000"), JoinKind::Join));
+        assert!(!policy.is_allowed(&node("10.2.0.1:3000"), JoinKind::Join));
+        assert!(policy.is_allowed(&node("[fd12::1]:3000"), JoinKind::Neighbor));
+        assert!(!policy.is_allowed(&node("[fe80::1]:3000"), JoinKind::Neighbor));
+        assert!(policy.is_allowed(&node("192.168.0.1:3000"), JoinKind::ForwardJoin));
+        assert!(!policy.is_allowed(&node("192.168.0.2:3000"), JoinKind::ForwardJoin));
+
+        let any = AddrRangeJoinPolicy::new().allow("0.0.0.0".parse().unwrap(), 0);
+        assert!(any.is_allowed(&node("127.0.0.1:3000"), JoinKind::Join));
+        assert!(!any.is_allowed(&node("[::1]:3000"), JoinKind::Join));
+    }
+}
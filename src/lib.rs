@@ -23,11 +23,32 @@
 //! If some of the above guarantees are mandatory for your application,
 //! it is need to be provided by upper layers.
 //!
+//! # Features
+//!
+//! The following features are enabled by default, and can be disabled to slim
+//! the instrumentation compiled into the crate:
+//!
+//! - `metrics`: registers the metrics of services and nodes to [prometrics]
+//!   (without it, all the metrics remain zero)
+//! - `logging`: emits log records to the `slog` loggers given to the builders
+//!   (without it, the log records are compiled out)
+//! - `thread-rng`: seeds the random number generators by `rand::thread_rng()`
+//!   (without it, the seeds are derived from the randomly keyed hashers of `std`;
+//!   the `encryption` feature always enables this)
+//!
+//! Note that `prometrics`, `slog` and `rand` remain dependencies of the crate even if these are
+//! disabled, because `fibers_rpc` and `hyparview` depend on them.
+//!
+//! The `testing` feature enables the [`testing`] module, which provides a simulator
+//! for evaluating protocol parameters without deploying real clusters.
+//!
 //! # References
 //!
 //! - [HyParView: a membership protocol for reliable gossip-based broadcast][HyParView]
 //! - [Plumtree: Epidemic Broadcast Trees][Plumtree]
 //!
+//! [prometrics]: https://crates.io/crates/prometrics
+//! [`testing`]: ./testing/index.html
 //! [HyParView]: http://asc.di.fct.unl.pt/~jleitao/pdf/dsn07-leitao.pdf
 //! [Plumtree]: http://www.gsd.inesc-id.pt/~ler/reports/srds07.pdf
 #![warn(missing_docs)]
@@ -40,17 +61,71 @@ extern crate trackable;
 
 pub use error::{Error, ErrorKind};
 
+#[macro_use]
+mod macros;
+
+mod addr_normalizer;
 mod codec;
+#[cfg(feature = "encryption")]
+mod encryption;
 mod error;
+mod estimator;
+mod event_log;
+mod join_policy;
 mod node_id;
 mod node_id_generator;
+mod protocol;
+mod quarantine;
+mod random;
 mod rpc;
+mod trace;
 
+pub mod admin;
+pub mod bridge;
+pub mod clock;
+pub mod coordination;
+pub mod dedicated;
+pub mod discovery;
+#[cfg(feature = "exporter")]
+pub mod exporter;
+pub mod membership;
 pub mod message;
 pub mod metrics;
 pub mod misc;
 pub mod node;
+pub mod pool;
+#[cfg(feature = "registry")]
+pub mod registry;
 pub mod service;
+pub mod shuffle;
+pub mod sink;
+pub mod state;
+pub mod subscription;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod topology;
+
+/// Entry points for fuzzing the codecs of RPC messages.
+#[cfg(feature = "fuzz")]
+#[doc(hidden)]
+pub mod fuzz {
+    pub use crate::codec::fuzz::{decode_frames, decode_from_slice, encode_to_vec};
+}
+
+/// Helpers for benchmarking the codecs of RPC messages.
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench {
+    pub use crate::codec::bench::{
+        decode_gossip_frame, decode_ihave_frame, encode_gossip_frame, encode_ihave_frame,
+    };
+}
+
+#[doc(hidden)]
+pub mod macro_support {
+    pub use bytecodec;
+    pub use trackable;
+}
 
 /// This crate specific `Result` type.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,257 @@
+// NOTE: If the `logging` feature is disabled, the following macros shadow the ones of `slog`,
+// so that the log records are compiled out (their arguments are still type-checked).
+#[cfg(not(feature = "logging"))]
+macro_rules! noop_log {
+    ($logger:expr, $format:expr $(, $arg:expr)* $(,)*) => {{
+        let _ = &$logger;
+        if false {
+            let _ = format_args!($format $(, $arg)*);
+        }
+    }};
+    ($logger:expr, $($rest:tt)*) => {{
+        let _ = &$logger;
+    }};
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! debug {
+    ($($t:tt)*) => { noop_log!($($t)*) };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! info {
+    ($($t:tt)*) => { noop_log!($($t)*) };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! warn {
+    ($($t:tt)*) => { noop_log!($($t)*) };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! error {
+    ($($t:tt)*) => { noop_log!($($t)*) };
+}
+
+/// Defines an enum that can be used as the payload of broadcasting messages.
+///
+/// Each variant of the enum has a single field which type implements [`MessagePayload`] and
+/// a distinct tag byte.
+/// This macro defines the enum itself and the encoder/decoder types
+/// (with the given names) that prepend the tag byte to the encoded field.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use]
+/// extern crate plumcast;
+///
+/// plumcast_payload_enum! {
+///     /// Application payload.
+///     #[derive(Debug, Clone, PartialEq)]
+///     pub enum Payload: PayloadEncoder, PayloadDecoder {
+///         Text(String) = 0,
+///         Binary(Vec<u8>) = 1,
+///     }
+/// }
+///
+/// # fn main() {
+/// use plumcast::message::MessagePayload;
+///
+/// fn assert_payload<T: MessagePayload>() {}
+/// assert_payload::<Payload>();
+/// # }
+/// ```
+///
+/// [`MessagePayload`]: ./message/trait.MessagePayload.html
+#[macro_export]
+macro_rules! plumcast_payload_enum {
+    (
+        $(#[$attr:meta])*
+        $vis:vis enum $name:ident: $encoder:ident, $decoder:ident {
+            $($variant:ident($ty:ty) = $tag:expr),* $(,)*
+        }
+    ) => {
+        $(#[$attr])*
+        $vis enum $name {
+            $(
+                #[allow(missing_docs)]
+                $variant($ty)
+            ),*
+        }
+        impl $crate::message::MessagePayload for $name {
+            type Encoder = $encoder;
+            type Decoder = $decoder;
+        }
+
+        #[doc = "Encoder generated by `plumcast_payload_enum!`."]
+        #[allow(non_snake_case)]
+        #[derive(Default)]
+        $vis struct $encoder {
+            tag: $crate::macro_support::bytecodec::fixnum::U8Encoder,
+            $($variant: <$ty as $crate::message::MessagePayload>::Encoder),*
+        }
+        impl $crate::macro_support::bytecodec::Encode for $encoder {
+            type Item = $name;
+
+            fn encode(
+                &mut self,
+                buf: &mut [u8],
+                eos: $crate::macro_support::bytecodec::Eos,
+            ) -> $crate::macro_support::bytecodec::Result<usize> {
+                use $crate::macro_support::bytecodec::Encode;
+
+                let mut offset = self.tag.encode(buf, eos)?;
+                if !self.tag.is_idle() {
+                    return Ok(offset);
+                }
+                $(offset += self.$variant.encode(&mut buf[offset..], eos)?;)*
+                Ok(offset)
+            }
+
+            fn start_encoding(
+                &mut self,
+                item: Self::Item,
+            ) -> $crate::macro_support::bytecodec::Result<()> {
+                use $crate::macro_support::bytecodec::Encode;
+
+                match item {
+                    $($name::$variant(x) => {
+                        self.tag.start_encoding($tag)?;
+                        self.$variant.start_encoding(x)?;
+                    })*
+                }
+                Ok(())
+            }
+
+            fn requiring_bytes(&self) -> $crate::macro_support::bytecodec::ByteCount {
+                use $crate::macro_support::bytecodec::Encode;
+
+                self.tag.requiring_bytes()
+                    $(.add_for_encoding(self.$variant.requiring_bytes()))*
+            }
+
+            fn is_idle(&self) -> bool {
+                use $crate::macro_support::bytecodec::Encode;
+
+                self.tag.is_idle() $(&& self.$variant.is_idle())*
+            }
+        }
+
+        #[doc = "Decoder generated by `plumcast_payload_enum!`."]
+        #[allow(non_snake_case)]
+        #[derive(Default)]
+        $vis struct $decoder {
+            tag: $crate::macro_support::bytecodec::combinator::Peekable<
+                $crate::macro_support::bytecodec::fixnum::U8Decoder,
+            >,
+            $($variant: <$ty as $crate::message::MessagePayload>::Decoder),*
+        }
+        impl $crate::macro_support::bytecodec::Decode for $decoder {
+            type Item = $name;
+
+            fn decode(
+                &mut self,
+                buf: &[u8],
+                eos: $crate::macro_support::bytecodec::Eos,
+            ) -> $crate::macro_support::bytecodec::Result<usize> {
+                use $crate::macro_support::bytecodec::{Decode, ErrorKind};
+                use $crate::macro_support::trackable::error::ErrorKindExt;
+
+                let mut offset = 0;
+                if !self.tag.is_idle() {
+                    offset += self.tag.decode(buf, eos)?;
+                    if !self.tag.is_idle() {
+                        return Ok(offset);
+                    }
+                }
+                let tag = *self.tag.peek().expect("Never fails");
+                $(if tag == $tag {
+                    offset += self.$variant.decode(&buf[offset..], eos)?;
+                    return Ok(offset);
+                })*
+                Err(ErrorKind::InvalidInput
+                    .cause(format!("Unknown tag of {}: {}", stringify!($name), tag))
+                    .into())
+            }
+
+            fn finish_decoding(&mut self) -> $crate::macro_support::bytecodec::Result<Self::Item> {
+                use $crate::macro_support::bytecodec::{Decode, ErrorKind};
+                use $crate::macro_support::trackable::error::ErrorKindExt;
+
+                let tag = self.tag.finish_decoding()?;
+                $(if tag == $tag {
+                    let x = self.$variant.finish_decoding()?;
+                    return Ok($name::$variant(x));
+                })*
+                Err(ErrorKind::InvalidInput
+                    .cause(format!("Unknown tag of {}: {}", stringify!($name), tag))
+                    .into())
+            }
+
+            fn requiring_bytes(&self) -> $crate::macro_support::bytecodec::ByteCount {
+                use $crate::macro_support::bytecodec::Decode;
+
+                if let Some(&tag) = self.tag.peek() {
+                    $(if tag == $tag {
+                        return self.$variant.requiring_bytes();
+                    })*
+                }
+                self.tag.requiring_bytes()
+            }
+
+            fn is_idle(&self) -> bool {
+                use $crate::macro_support::bytecodec::Decode;
+
+                if let Some(&tag) = self.tag.peek() {
+                    $(if tag == $tag {
+                        return self.$variant.is_idle();
+                    })*
+                }
+                false
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use bytecodec::{DecodeExt, EncodeExt};
+
+    plumcast_payload_enum! {
+        #[derive(Debug, Clone, PartialEq)]
+        enum Payload: PayloadEncoder, PayloadDecoder {
+            Text(String) = 0,
+            Binary(Vec<u8>) = 1,
+        }
+    }
+
+    fn round_trip(payload: Payload) -> Payload {
+        let bytes = PayloadEncoder::default()
+            .encode_into_bytes(payload)
+            .unwrap();
+        PayloadDecoder::default().decode_from_bytes(&bytes).unwrap()
+    }
+
+    #[test]
+    fn log_macros_accept_slog_syntax() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let count = 1;
+        debug!(logger, "Debug: {}", count);
+        info!(logger, "Info");
+        warn!(logger, "Warn: {:?}", count,);
+        error!(logger, "Error"; "count" => count);
+    }
+
+    #[test]
+    fn payload_enum_works() {
+        let text = Payload::Text("foo".to_owned());
+        let binary = Payload::Binary(vec![1, 2, 3]);
+        assert_eq!(round_trip(text.clone()), text);
+        assert_eq!(round_trip(binary.clone()), binary);
+
+        assert!(PayloadDecoder::default()
+            .decode_from_bytes(&[9, 1])
+            .is_err());
+    }
+}
@@ -0,0 +1,246 @@
+//! Export of the cluster membership known by nodes.
+//!
+//! The membership of a node (i.e., the HyParView active and passive views) can be
+//! periodically exported by registering an [`ExportMembership`] implementation
+//! via [`NodeBuilder::membership_exporter`].
+//! This allows other infrastructure (e.g., Prometheus) to discover the members of a cluster.
+//!
+//! [`ExportMembership`]: ./trait.ExportMembership.html
+//! [`NodeBuilder::membership_exporter`]: ../node/struct.NodeBuilder.html#method.membership_exporter
+use crate::codec::node::{NodeIdDecoder, NodeIdEncoder};
+use crate::node::NodeId;
+use crate::{Error, ErrorKind, Result};
+use bytecodec::combinator::{Collect, Repeat};
+use bytecodec::{DecodeExt, EncodeExt};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use trackable::error::ErrorKindExt;
+
+/// The format version of view snapshots.
+const VIEW_SNAPSHOT_VERSION: u8 = 0;
+
+/// The cluster membership known by a node.
+#[derive(Debug, Clone)]
+pub struct Membership {
+    pub(crate) local_node: NodeId,
+    pub(crate) active_view: Vec<NodeId>,
+    pub(crate) passive_view: Vec<NodeId>,
+}
+impl Membership {
+    /// Returns the identifier of the node that exports the membership.
+    pub fn local_node(&self) -> NodeId {
+        self.local_node
+    }
+
+    /// Returns the HyParView active view (i.e., the neighbors) of the node.
+    pub fn active_view(&self) -> &[NodeId] {
+        &self.active_view
+    }
+
+    /// Returns the HyParView passive view of the node.
+    pub fn passive_view(&self) -> &[NodeId] {
+        &self.passive_view
+    }
+
+    /// Returns the deduplicated RPC server addresses of the known members (including the local node).
+    ///
+    /// The addresses are sorted in ascending order.
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        let mut addrs = Some(&self.local_node)
+            .into_iter()
+            .chain(self.active_view.iter())
+            .chain(self.passive_view.iter())
+            .map(|n| n.address())
+            .collect::<Vec<_>>();
+        addrs.sort();
+        addrs.dedup();
+        addrs
+    }
+}
+
+/// This trait allows the implementations to export the cluster membership known by nodes.
+///
+/// `export` is called by the node every export interval.
+/// If it returns an error, the error is logged and the next export is tried at the next interval.
+pub trait ExportMembership: Send + Sync + 'static {
+    /// Exports the given membership.
+    fn export(&self, membership: &Membership) -> Result<()>;
+}
+impl<F> ExportMembership for F
+where
+    F: Fn(&Membership) -> Result<()> + Send + Sync + 'static,
+{
+    fn export(&self, membership: &Membership) -> Result<()> {
+        self(membership)
+    }
+}
+
+/// An [`ExportMembership`] implementation that writes the membership to a file in
+/// the [Prometheus `file_sd_config`] JSON format.
+///
+/// The file contains one target group which targets are the RPC server addresses of the members
+/// (see [`Membership::addrs`]).
+/// The file is replaced atomically (i.e., a temporary file is written and then renamed).
+///
+/// [`ExportMembership`]: ./trait.ExportMembership.html
+/// [Prometheus `file_sd_config`]: https://prometheus.io/docs/prometheus/latest/configuration/configuration/#file_sd_config
+/// [`Membership::addrs`]: ./struct.Membership.html#method.addrs
+#[derive(Debug, Clone)]
+pub struct FileSdExporter {
+    path: PathBuf,
+    labels: Vec<(String, String)>,
+}
+impl FileSdExporter {
+    /// Makes a new `FileSdExporter` instance that writes to the given path.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        FileSdExporter {
+            path: path.into(),
+            labels: Vec::new(),
+        }
+    }
+
+    /// Adds a label attached to the target group.
+    pub fn label(mut self, name: &str, value: &str) -> Self {
+        self.labels.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Returns the content of the file for the given membership.
+    pub fn to_json(&self, membership: &Membership) -> String {
+        let targets = membership
+            .addrs()
+            .iter()
+            .map(|addr| json_string(&addr.to_string()))
+            .collect::<Vec<_>>();
+        let labels = self
+            .labels
+            .iter()
+            .map(|(k, v)| format!("{}:{}", json_string(k), json_string(v)))
+            .collect::<Vec<_>>();
+        format!(
+            "[{{\"targets\":[{}],\"labels\":{{{}}}}}]\n",
+            targets.join(","),
+            labels.join(",")
+        )
+    }
+}
+impl ExportMembership for FileSdExporter {
+    fn export(&self, membership: &Membership) -> Result<()> {
+        let json = self.to_json(membership);
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        {
+            let mut file = track!(fs::File::create(&tmp).map_err(io_error))?;
+            track!(file.write_all(json.as_bytes()).map_err(io_error))?;
+        }
+        track!(fs::rename(&tmp, &self.path).map_err(io_error))?;
+        Ok(())
+    }
+}
+
+/// Encodes the given nodes into a view snapshot.
+///
+/// A snapshot consists of a version byte followed by the encoded node identifiers.
+pub(crate) fn encode_view_snapshot(nodes: Vec<NodeId>) -> Vec<u8> {
+    let mut bytes = vec![VIEW_SNAPSHOT_VERSION];
+    let encoded = Repeat::<NodeIdEncoder, _>::default()
+        .encode_into_bytes(nodes.into_iter())
+        .expect("Never fails");
+    bytes.extend_from_slice(&encoded);
+    bytes
+}
+
+/// Decodes a view snapshot made by `encode_view_snapshot()`.
+pub(crate) fn decode_view_snapshot(bytes: &[u8]) -> Result<Vec<NodeId>> {
+    track_assert!(!bytes.is_empty(), ErrorKind::InvalidInput, "Empty snapshot");
+    track_assert_eq!(bytes[0], VIEW_SNAPSHOT_VERSION, ErrorKind::InvalidInput);
+    let nodes = track!(Collect::<NodeIdDecoder, Vec<_>>::default()
+        .decode_from_bytes(&bytes[1..])
+        .map_err(|e| Error::from(ErrorKind::InvalidInput.takes_over(e))))?;
+    Ok(nodes)
+}
+
+#[derive(Clone)]
+pub(crate) struct ArcMembershipExporter(Arc<dyn ExportMembership>);
+impl ArcMembershipExporter {
+    pub(crate) fn new<E: ExportMembership>(inner: E) -> Self {
+        ArcMembershipExporter(Arc::new(inner))
+    }
+
+    pub(crate) fn export(&self, membership: &Membership) -> Result<()> {
+        self.0.export(membership)
+    }
+}
+impl fmt::Debug for ArcMembershipExporter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ArcMembershipExporter(_)")
+    }
+}
+
+fn io_error(e: std::io::Error) -> Error {
+    ErrorKind::Other.cause(e).into()
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::LocalNodeId;
+
+    fn node(port: u16, local_id: u64) -> NodeId {
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        NodeId::new(addr, LocalNodeId::new(local_id))
+    }
+
+    #[test]
+    fn file_sd_json_works() {
+        let membership = Membership {
+            local_node: node(3000, 0),
+            active_view: vec![node(3001, 0), node(3000, 1)],
+            passive_view: vec![node(3002, 0)],
+        };
+        let exporter = FileSdExporter::new("/dev/null").label("cluster", "a\"b");
+        assert_eq!(
+            exporter.to_json(&membership),
+            "[{\"targets\":[\"127.0.0.1:3000\",\"127.0.0.1:3001\",\"127.0.0.1:3002\"],\
+             \"labels\":{\"cluster\":\"a\\\"b\"}}]\n"
+        );
+    }
+
+    #[test]
+    fn view_snapshot_codec_works() {
+        let nodes = vec![node(3001, 0), node(3000, 1)];
+        let bytes = encode_view_snapshot(nodes.clone());
+        assert_eq!(decode_view_snapshot(&bytes).unwrap(), nodes);
+        assert_eq!(
+            decode_view_snapshot(&encode_view_snapshot(Vec::new())).unwrap(),
+            Vec::new()
+        );
+
+        assert!(decode_view_snapshot(&[]).is_err());
+        assert!(decode_view_snapshot(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut unknown_version = bytes.clone();
+        unknown_version[0] = VIEW_SNAPSHOT_VERSION + 1;
+        assert!(decode_view_snapshot(&unknown_version).is_err());
+    }
+}
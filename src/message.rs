@@ -3,8 +3,12 @@
 //! [`Message`]: ./struct.Message.html
 use crate::misc::PlumtreeAppMessage;
 use crate::node::NodeId;
+use crate::trace::TraceContext;
 use bytecodec::bytes::{BytesEncoder, RemainingBytesDecoder, Utf8Decoder, Utf8Encoder};
-use bytecodec::{Decode, Encode};
+use bytecodec::{ByteCount, Decode, Encode, EncodeExt};
+use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
 
 /// Broadcasted application message.
 #[derive(Debug, Clone)]
@@ -17,22 +21,226 @@ impl<T: MessagePayload> Message<T> {
 
     /// Returns a reference to the payload of the message.
     pub fn payload(&self) -> &T {
-        &self.0.payload
+        &self.0.payload.payload
     }
 
     /// Returns a mutable reference to the payload of the message.
     pub fn payload_mut(&mut self) -> &mut T {
-        &mut self.0.payload
+        &mut self.0.payload.payload
     }
 
     /// Takes the ownership of the message, and returns its payload.
     pub fn into_payload(self) -> T {
-        self.0.payload
+        self.0.payload.payload
+    }
+
+    /// Returns the deadline of the message if it was broadcasted with one.
+    ///
+    /// See [`Node::broadcast_with_deadline`] for more details.
+    ///
+    /// [`Node::broadcast_with_deadline`]: ../node/struct.Node.html#method.broadcast_with_deadline
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.0.payload.deadline
+    }
+
+    /// Returns the wall-clock time at which the message was broadcasted by its origin node.
+    ///
+    /// This is carried in gossip frames, so receivers can compute the end-to-end latency
+    /// of the message or discard stale data without embedding timestamps in their payloads.
+    /// Note that the time is taken from the clock of the origin node,
+    /// so the clock skew between the nodes should be taken into account.
+    ///
+    /// The peers that speak older protocol versions (see `ServiceBuilder::protocol_negotiation()`)
+    /// do not forward the time. If a message is received from such a peer,
+    /// the time at which the message was received by the local node is returned instead.
+    pub fn origin_time(&self) -> SystemTime {
+        self.0.payload.origin_time
+    }
+
+    /// Returns `true` if the message was broadcasted with high priority.
+    ///
+    /// See [`Node::broadcast_with_high_priority`] for more details.
+    ///
+    /// [`Node::broadcast_with_high_priority`]: ../node/struct.Node.html#method.broadcast_with_high_priority
+    pub fn is_high_priority(&self) -> bool {
+        self.0.payload.high_priority
+    }
+
+    /// Returns the hop limit of the message if it was broadcasted with one.
+    ///
+    /// See [`Node::broadcast_with_ttl`] for more details.
+    ///
+    /// [`Node::broadcast_with_ttl`]: ../node/struct.Node.html#method.broadcast_with_ttl
+    pub fn ttl(&self) -> Option<u16> {
+        self.0.payload.hop_limit
+    }
+
+    /// Returns the neighbor from which the message was received.
+    ///
+    /// This is the sender of the first gossip message carrying the message.
+    /// `None` is returned if the message was broadcasted by the local node.
+    pub fn received_from(&self) -> Option<NodeId> {
+        self.0.payload.received_from.map(|(sender, _)| sender)
+    }
+
+    /// Returns the Plumtree round (i.e., the number of hops from the origin minus one)
+    /// at which the message was received.
+    ///
+    /// `None` is returned if the message was broadcasted by the local node.
+    pub fn round(&self) -> Option<u16> {
+        self.0.payload.received_from.map(|(_, round)| round)
+    }
+
+    /// Returns the number of the duplicate gossip and `IHAVE` messages
+    /// the node had received for the message until it was delivered.
+    ///
+    /// Duplicates received after the delivery can be retrieved by [`Node::duplicates`].
+    ///
+    /// [`Node::duplicates`]: ../node/struct.Node.html#method.duplicates
+    pub fn duplicates(&self) -> usize {
+        self.0.payload.duplicates
+    }
+
+    /// Returns the identifier of the message redacted by this message if it is a tombstone.
+    ///
+    /// See [`Node::broadcast_tombstone`] for more details.
+    ///
+    /// [`Node::broadcast_tombstone`]: ../node/struct.Node.html#method.broadcast_tombstone
+    pub fn tombstone_of(&self) -> Option<&MessageId> {
+        self.0.payload.tombstone_of.as_ref()
+    }
+
+    /// Returns `true` if the message was broadcasted by [`ServiceHandle::broadcast_local`].
+    ///
+    /// [`ServiceHandle::broadcast_local`]: ../service/struct.ServiceHandle.html#method.broadcast_local
+    pub fn is_local(&self) -> bool {
+        self.0.payload.local
     }
 
     pub(crate) fn new(message: PlumtreeAppMessage<T>) -> Self {
         Message(message)
     }
+
+    pub(crate) fn into_inner(self) -> PlumtreeAppMessage<T> {
+        self.0
+    }
+
+    pub(crate) fn filter_map_payload<U, F>(self, f: F) -> Option<Message<U>>
+    where
+        U: MessagePayload,
+        F: FnOnce(T) -> Option<U>,
+    {
+        let id = self.0.id;
+        self.0
+            .payload
+            .filter_map(f)
+            .map(|payload| Message(PlumtreeAppMessage { id, payload }))
+    }
+}
+
+/// Application payload together with the metadata attached to it by plumcast.
+///
+/// This is used as the payload type of the underlying Plumtree messages.
+#[derive(Debug, Clone)]
+pub struct Envelope<T> {
+    pub(crate) payload: T,
+    pub(crate) deadline: Option<SystemTime>,
+    pub(crate) origin_time: SystemTime,
+    pub(crate) trace: TraceContext,
+
+    // The priority is carried in the extension section of gossip frames
+    // (and inherited by the repair gossips sent in response to Grafts).
+    pub(crate) high_priority: bool,
+
+    // The maximum number of hops (i.e., the Plumtree gossip round) the message propagates.
+    // This is also carried in the extension section of gossip frames.
+    pub(crate) hop_limit: Option<u16>,
+
+    // The identifier of the message redacted by this (tombstone) message.
+    // This is also carried in the extension section of gossip frames.
+    pub(crate) tombstone_of: Option<MessageId>,
+
+    // The hash of the encoded payload (only set for the messages broadcasted by
+    // `Node::broadcast_dedup()`). This is carried in the extension section of gossip and
+    // `IHAVE` frames.
+    pub(crate) content_hash: Option<u64>,
+
+    // The encoded size of `payload` (only available for messages received from remote nodes).
+    pub(crate) payload_size: Option<u64>,
+
+    // The sender and round of the gossip message from which this was received,
+    // and the number of the duplicates seen until the delivery (these are not transmitted).
+    pub(crate) received_from: Option<(NodeId, u16)>,
+    pub(crate) duplicates: usize,
+
+    // Whether this was broadcasted by `ServiceHandle::broadcast_local()` (this is not transmitted).
+    pub(crate) local: bool,
+
+    // Whether this is sent in response to a `GRAFT` message, and the payload encoded in advance
+    // for serving multiple requesters of the message (these are not transmitted).
+    pub(crate) repair: bool,
+    pub(crate) encoded_payload: Option<Arc<Vec<u8>>>,
+}
+impl<T> Envelope<T> {
+    /// Returns a reference to the application payload.
+    pub fn payload(&self) -> &T {
+        &self.payload
+    }
+
+    /// Returns the deadline of the message.
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.deadline
+    }
+
+    pub(crate) fn new(payload: T) -> Self {
+        Envelope {
+            payload,
+            deadline: None,
+            origin_time: SystemTime::now(),
+            trace: TraceContext::default(),
+            high_priority: false,
+            hop_limit: None,
+            tombstone_of: None,
+            content_hash: None,
+            payload_size: None,
+            received_from: None,
+            duplicates: 0,
+            local: false,
+            repair: false,
+            encoded_payload: None,
+        }
+    }
+
+    pub(crate) fn filter_map<U, F>(self, f: F) -> Option<Envelope<U>>
+    where
+        F: FnOnce(T) -> Option<U>,
+    {
+        let payload = f(self.payload)?;
+        Some(Envelope {
+            payload,
+            deadline: self.deadline,
+            origin_time: self.origin_time,
+            trace: self.trace,
+            high_priority: self.high_priority,
+            hop_limit: self.hop_limit,
+            tombstone_of: self.tombstone_of,
+            content_hash: self.content_hash,
+            payload_size: None,
+            received_from: self.received_from,
+            duplicates: self.duplicates,
+            local: self.local,
+            repair: self.repair,
+            encoded_payload: None,
+        })
+    }
+
+    pub(crate) fn is_expired(&self, now: SystemTime) -> bool {
+        self.deadline.map_or(false, |deadline| deadline <= now)
+    }
+
+    pub(crate) fn is_hop_limit_exceeded(&self, round: u16) -> bool {
+        self.hop_limit.map_or(false, |limit| round >= limit)
+    }
 }
 
 /// Message identifier.
@@ -41,7 +249,8 @@ impl<T: MessagePayload> Message<T> {
 /// The node identifier part which type is [`NodeId`] indicates the sender (origin) of the message.
 /// The sequence number part indicates the number of messages broadcasted by the sender so far.
 ///
-/// Identifiers are assigned automatically when broadcasting messages.
+/// Identifiers are assigned automatically when broadcasting messages
+/// (the assignment can be customized by [`MessageIdPolicy`]).
 ///
 /// It is guaranteed that the identifiers are unique in a cluster
 /// unless the OS processes executing plumcast nodes are restarted.
@@ -49,6 +258,7 @@ impl<T: MessagePayload> Message<T> {
 /// even if OS processes are frequently restarted.
 ///
 /// [`NodeId`]: ../node/struct.NodeId.html
+/// [`MessageIdPolicy`]: ./trait.MessageIdPolicy.html
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct MessageId {
     node: NodeId,
@@ -65,11 +275,144 @@ impl MessageId {
         self.seqno
     }
 
-    pub(crate) fn new(node: NodeId, seqno: u64) -> Self {
+    /// Makes a new `MessageId` instance.
+    ///
+    /// This is intended to be used by the implementations of [`MessageIdPolicy`].
+    ///
+    /// [`MessageIdPolicy`]: ./trait.MessageIdPolicy.html
+    pub fn new(node: NodeId, seqno: u64) -> Self {
         MessageId { node, seqno }
     }
 }
 
+/// This trait allows for customizing the identifiers of the messages broadcasted by a node.
+///
+/// For example, deriving the sequence number parts from the hashes of the payloads
+/// makes rebroadcasting the same content idempotent,
+/// because nodes regard a message having a known identifier as a duplicate.
+///
+/// Implementations must keep the uniqueness of identifiers:
+/// the node identifier part must be the identifier of the broadcasting node
+/// (this is checked in debug builds), and
+/// messages having different contents must not be assigned the same identifier.
+pub trait MessageIdPolicy<M: MessagePayload>: Send + 'static {
+    /// Returns the identifier of the message having `payload` that is broadcasted by `node`.
+    ///
+    /// `seqno` is the number of the messages broadcasted by the node so far
+    /// (i.e., the sequence number part used by default).
+    fn message_id(&mut self, node: NodeId, seqno: u64, payload: &M) -> MessageId;
+}
+impl<M, F> MessageIdPolicy<M> for F
+where
+    M: MessagePayload,
+    F: FnMut(NodeId, u64, &M) -> MessageId + Send + 'static,
+{
+    fn message_id(&mut self, node: NodeId, seqno: u64, payload: &M) -> MessageId {
+        self(node, seqno, payload)
+    }
+}
+
+pub(crate) struct BoxMessageIdPolicy<M: MessagePayload>(Box<dyn MessageIdPolicy<M>>);
+impl<M: MessagePayload> BoxMessageIdPolicy<M> {
+    pub(crate) fn new<P: MessageIdPolicy<M>>(inner: P) -> Self {
+        BoxMessageIdPolicy(Box::new(inner))
+    }
+
+    pub(crate) fn message_id(&mut self, node: NodeId, seqno: u64, payload: &M) -> MessageId {
+        self.0.message_id(node, seqno, payload)
+    }
+}
+impl<M: MessagePayload> fmt::Debug for BoxMessageIdPolicy<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BoxMessageIdPolicy(_)")
+    }
+}
+
+/// This trait allows for validating the payloads of the messages received by a node
+/// before relaying them.
+///
+/// A policy is invoked when a gossip message carrying a message unknown to the node is received.
+/// If the policy returns `false`, the message is neither relayed to the other nodes
+/// nor delivered to the application, and the subsequent gossip and `IHAVE` messages for it
+/// are ignored for a while.
+/// This prevents malformed or abusive content from propagating through the honest nodes.
+///
+/// Note that the policy is not applied to the messages broadcasted by the node itself.
+pub trait RelayPolicy<M: MessagePayload>: Send + 'static {
+    /// Returns `true` if the message having `payload` received from `sender` may be relayed.
+    fn allow_relay(&mut self, sender: NodeId, message_id: &MessageId, payload: &M) -> bool;
+}
+impl<M, F> RelayPolicy<M> for F
+where
+    M: MessagePayload,
+    F: FnMut(NodeId, &MessageId, &M) -> bool + Send + 'static,
+{
+    fn allow_relay(&mut self, sender: NodeId, message_id: &MessageId, payload: &M) -> bool {
+        self(sender, message_id, payload)
+    }
+}
+
+pub(crate) struct BoxRelayPolicy<M: MessagePayload>(Box<dyn RelayPolicy<M>>);
+impl<M: MessagePayload> BoxRelayPolicy<M> {
+    pub(crate) fn new<P: RelayPolicy<M>>(inner: P) -> Self {
+        BoxRelayPolicy(Box::new(inner))
+    }
+
+    pub(crate) fn allow_relay(
+        &mut self,
+        sender: NodeId,
+        message_id: &MessageId,
+        payload: &M,
+    ) -> bool {
+        self.0.allow_relay(sender, message_id, payload)
+    }
+}
+impl<M: MessagePayload> fmt::Debug for BoxRelayPolicy<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BoxRelayPolicy(_)")
+    }
+}
+
+/// This trait allows for filtering the messages delivered to a node.
+///
+/// A filter is invoked for each message right before it is yielded by the node
+/// (i.e., after the deadline and the other delivery conditions are checked).
+/// If the filter returns `false`, the message is not yielded to the application.
+/// This is useful for discarding uninteresting messages cheaply (e.g., by payload prefix)
+/// without handing them over to the application code.
+///
+/// Note that rejected messages are still relayed to the other nodes, and kept by the node
+/// like delivered ones, so they need to be forgot by calling `Node::forget_message()`.
+pub trait DeliveryFilter<M: MessagePayload>: Send + 'static {
+    /// Returns `true` if `message` should be delivered to the application.
+    fn filter(&mut self, message: &Message<M>) -> bool;
+}
+impl<M, F> DeliveryFilter<M> for F
+where
+    M: MessagePayload,
+    F: FnMut(&Message<M>) -> bool + Send + 'static,
+{
+    fn filter(&mut self, message: &Message<M>) -> bool {
+        self(message)
+    }
+}
+
+pub(crate) struct BoxDeliveryFilter<M: MessagePayload>(Box<dyn DeliveryFilter<M>>);
+impl<M: MessagePayload> BoxDeliveryFilter<M> {
+    pub(crate) fn new<F: DeliveryFilter<M>>(inner: F) -> Self {
+        BoxDeliveryFilter(Box::new(inner))
+    }
+
+    pub(crate) fn filter(&mut self, message: &Message<M>) -> bool {
+        self.0.filter(message)
+    }
+}
+impl<M: MessagePayload> fmt::Debug for BoxDeliveryFilter<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BoxDeliveryFilter(_)")
+    }
+}
+
 /// This trait allows the implementations to be used as the payload of broadcasting messages.
 pub trait MessagePayload: Sized + Clone + Send + 'static {
     /// Payload encoder.
@@ -82,6 +425,61 @@ pub trait MessagePayload: Sized + Clone + Send + 'static {
     /// This is used to deserialize payload from octets received from remote nodes.
     type Decoder: Decode<Item = Self> + Default + Send + 'static;
 }
+
+/// This trait allows payload decoders to decode payloads into caller-provided buffers or arenas.
+///
+/// By default, the payload decoders of gossip messages are created by `Default::default()`,
+/// so each decoder typically allocates fresh memory (e.g., a `Vec<u8>`) for every message.
+/// If `MessagePayload::Decoder` implements this trait,
+/// [`ServiceBuilder::finish_with_allocator`] can be used instead of `ServiceBuilder::finish`
+/// to make the decoders by using the given allocator
+/// (e.g., a handle of a buffer pool or an arena shared by the application).
+///
+/// [`ServiceBuilder::finish_with_allocator`]: ../service/struct.ServiceBuilder.html#method.finish_with_allocator
+pub trait DecoderWithAllocator: Decode {
+    /// Allocator type.
+    ///
+    /// An allocator is cloned each time a payload decoder is made.
+    type Allocator: Clone + Send + Sync + 'static;
+
+    /// Makes a new decoder that allocates memory for decoded payloads by using `allocator`.
+    fn with_allocator(allocator: Self::Allocator) -> Self;
+}
+
+/// Encodes the given payload by `M::Encoder`.
+pub(crate) fn encode_payload<M: MessagePayload>(payload: &M) -> Option<Vec<u8>> {
+    M::Encoder::default()
+        .encode_into_bytes(payload.clone())
+        .ok()
+}
+
+/// Returns the FNV-1a hash of the given payload encoded by `M::Encoder`.
+///
+/// The hash is exchanged between nodes, so it must not depend on the process (unlike `RandomState`).
+pub(crate) fn content_hash<M: MessagePayload>(payload: &M) -> Option<u64> {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let bytes = encode_payload(payload)?;
+    Some(bytes.iter().fold(OFFSET_BASIS, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(PRIME)
+    }))
+}
+
+/// Returns the size of the given payload encoded by `M::Encoder`.
+///
+/// If the encoder cannot tell the size in advance, the payload is actually encoded.
+pub(crate) fn encoded_payload_size<M: MessagePayload>(payload: &M) -> u64 {
+    let mut encoder = M::Encoder::default();
+    if encoder.start_encoding(payload.clone()).is_err() {
+        return 0;
+    }
+    if let ByteCount::Finite(n) = encoder.requiring_bytes() {
+        return n;
+    }
+    encode_payload(payload).map_or(0, |bytes| bytes.len() as u64)
+}
+
 impl MessagePayload for Vec<u8> {
     type Encoder = BytesEncoder<Vec<u8>>;
     type Decoder = RemainingBytesDecoder;
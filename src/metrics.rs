@@ -1,19 +1,63 @@
 //! [Prometheus][prometheus] metrics.
 //!
+//! The metrics are registered to [prometrics]. In addition, all the updates of them can be
+//! forwarded to other backends (e.g., statsd or OpenTelemetry) by using [`MetricsSink`].
+//!
+//! If the `metrics` feature (enabled by default) is disabled, no metrics are registered and
+//! all the updates of them are skipped, as if `ServiceBuilder::enable_metrics(false)` were called.
+//!
 //! Note that you can also use [fibers_rpc's metrics] in addition to the metrics defined in this module.
 //!
 //! [prometheus]: https://prometheus.io/
+//! [prometrics]: https://crates.io/crates/prometrics
+//! [`MetricsSink`]: ./trait.MetricsSink.html
 //! [fibers_rpc's metrics]: https://docs.rs/fibers_rpc/0.2/fibers_rpc/metrics/index.html
-use prometrics::metrics::{Counter, MetricBuilder};
+use prometrics::metrics::{self as prom, MetricBuilder};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
 
 /// Metrics of a [`Service`].
 ///
+/// The bytes of RPC frames are accounted per message class
+/// (`gossip`, `ihave`, `graft` (including `GRAFT_OPTIMIZE`), `prune`, `retract` and `hyparview`),
+/// so the bandwidth used by the protocol overhead can be compared with the payloads.
+///
 /// [`Service`]: ../service/struct.Service.html
 #[derive(Debug, Clone)]
 pub struct ServiceMetrics {
     pub(crate) registered_nodes: Counter,
     pub(crate) deregistered_nodes: Counter,
     pub(crate) destination_unknown_messages: Counter,
+    pub(crate) tombstoned_destination_messages: Counter,
+    pub(crate) redirected_messages: Counter,
+    pub(crate) oversized_payload_frames: Counter,
+    pub(crate) expired_node_leases: Counter,
+    pub(crate) command_queue_len: Gauge,
+    pub(crate) decoding_payload_bytes: Gauge,
+    pub(crate) throttled_payload_decodes: Counter,
+    pub(crate) incompatible_peers: Counter,
+    pub(crate) opened_circuits: Counter,
+    pub(crate) circuit_rejected_messages: Counter,
+    pub(crate) payload_key_changes: Counter,
+    pub(crate) rejected_joins: Counter,
+    pub(crate) rejected_forward_joins: Counter,
+    pub(crate) rejected_neighbors: Counter,
+    pub(crate) rejected_parameter_updates: Counter,
+    pub(crate) sent_gossip_bytes: Counter,
+    pub(crate) received_gossip_bytes: Counter,
+    pub(crate) sent_ihave_bytes: Counter,
+    pub(crate) received_ihave_bytes: Counter,
+    pub(crate) sent_graft_bytes: Counter,
+    pub(crate) received_graft_bytes: Counter,
+    pub(crate) sent_prune_bytes: Counter,
+    pub(crate) received_prune_bytes: Counter,
+    pub(crate) sent_retract_bytes: Counter,
+    pub(crate) received_retract_bytes: Counter,
+    pub(crate) sent_hyparview_bytes: Counter,
+    pub(crate) received_hyparview_bytes: Counter,
+    pub(crate) received_payload_bytes: Counter,
+    pub(crate) codec_errors: CodecErrors,
 }
 impl ServiceMetrics {
     /// Metric: `plumcast_service_registered_nodes_total <COUNTER>`
@@ -31,24 +75,303 @@ impl ServiceMetrics {
         self.destination_unknown_messages.value() as u64
     }
 
-    pub(crate) fn new(mut builder: MetricBuilder) -> Self {
-        builder.namespace("plumcast").subsystem("service");
+    /// Metric: `plumcast_service_tombstoned_destination_messages_total <COUNTER>`
+    pub fn tombstoned_destination_messages(&self) -> u64 {
+        self.tombstoned_destination_messages.value() as u64
+    }
+
+    /// Metric: `plumcast_service_redirected_messages_total <COUNTER>`
+    pub fn redirected_messages(&self) -> u64 {
+        self.redirected_messages.value() as u64
+    }
+
+    /// Metric: `plumcast_service_oversized_payload_frames_total <COUNTER>`
+    pub fn oversized_payload_frames(&self) -> u64 {
+        self.oversized_payload_frames.value() as u64
+    }
+
+    /// Metric: `plumcast_service_expired_node_leases_total <COUNTER>`
+    pub fn expired_node_leases(&self) -> u64 {
+        self.expired_node_leases.value() as u64
+    }
+
+    /// Metric: `plumcast_service_command_queue_len <GAUGE>`
+    pub fn command_queue_len(&self) -> u64 {
+        self.command_queue_len.value() as u64
+    }
+
+    /// Metric: `plumcast_service_decoding_payload_bytes <GAUGE>`
+    pub fn decoding_payload_bytes(&self) -> u64 {
+        self.decoding_payload_bytes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_throttled_payload_decodes_total <COUNTER>`
+    pub fn throttled_payload_decodes(&self) -> u64 {
+        self.throttled_payload_decodes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_incompatible_peers_total <COUNTER>`
+    pub fn incompatible_peers(&self) -> u64 {
+        self.incompatible_peers.value() as u64
+    }
+
+    /// Metric: `plumcast_service_opened_circuits_total <COUNTER>`
+    pub fn opened_circuits(&self) -> u64 {
+        self.opened_circuits.value() as u64
+    }
+
+    /// Metric: `plumcast_service_circuit_rejected_messages_total <COUNTER>`
+    pub fn circuit_rejected_messages(&self) -> u64 {
+        self.circuit_rejected_messages.value() as u64
+    }
+
+    /// Metric: `plumcast_service_payload_key_changes_total <COUNTER>`
+    pub fn payload_key_changes(&self) -> u64 {
+        self.payload_key_changes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_rejected_joins_total { message="join" } <COUNTER>`
+    pub fn rejected_joins(&self) -> u64 {
+        self.rejected_joins.value() as u64
+    }
+
+    /// Metric: `plumcast_service_rejected_joins_total { message="forward_join" } <COUNTER>`
+    pub fn rejected_forward_joins(&self) -> u64 {
+        self.rejected_forward_joins.value() as u64
+    }
+
+    /// Metric: `plumcast_service_rejected_joins_total { message="neighbor" } <COUNTER>`
+    pub fn rejected_neighbors(&self) -> u64 {
+        self.rejected_neighbors.value() as u64
+    }
+
+    /// Metric: `plumcast_service_rejected_parameter_updates_total <COUNTER>`
+    pub fn rejected_parameter_updates(&self) -> u64 {
+        self.rejected_parameter_updates.value() as u64
+    }
+
+    /// Metric: `plumcast_service_sent_bytes_total { class="gossip" } <COUNTER>`
+    pub fn sent_gossip_bytes(&self) -> u64 {
+        self.sent_gossip_bytes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_received_bytes_total { class="gossip" } <COUNTER>`
+    pub fn received_gossip_bytes(&self) -> u64 {
+        self.received_gossip_bytes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_sent_bytes_total { class="ihave" } <COUNTER>`
+    pub fn sent_ihave_bytes(&self) -> u64 {
+        self.sent_ihave_bytes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_received_bytes_total { class="ihave" } <COUNTER>`
+    pub fn received_ihave_bytes(&self) -> u64 {
+        self.received_ihave_bytes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_sent_bytes_total { class="graft" } <COUNTER>`
+    pub fn sent_graft_bytes(&self) -> u64 {
+        self.sent_graft_bytes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_received_bytes_total { class="graft" } <COUNTER>`
+    pub fn received_graft_bytes(&self) -> u64 {
+        self.received_graft_bytes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_sent_bytes_total { class="prune" } <COUNTER>`
+    pub fn sent_prune_bytes(&self) -> u64 {
+        self.sent_prune_bytes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_received_bytes_total { class="prune" } <COUNTER>`
+    pub fn received_prune_bytes(&self) -> u64 {
+        self.received_prune_bytes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_sent_bytes_total { class="retract" } <COUNTER>`
+    pub fn sent_retract_bytes(&self) -> u64 {
+        self.sent_retract_bytes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_received_bytes_total { class="retract" } <COUNTER>`
+    pub fn received_retract_bytes(&self) -> u64 {
+        self.received_retract_bytes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_sent_bytes_total { class="hyparview" } <COUNTER>`
+    pub fn sent_hyparview_bytes(&self) -> u64 {
+        self.sent_hyparview_bytes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_received_bytes_total { class="hyparview" } <COUNTER>`
+    pub fn received_hyparview_bytes(&self) -> u64 {
+        self.received_hyparview_bytes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_received_payload_bytes_total <COUNTER>`
+    pub fn received_payload_bytes(&self) -> u64 {
+        self.received_payload_bytes.value() as u64
+    }
+
+    /// Metric: `plumcast_service_decode_errors_total { procedure="..." } <COUNTER>`
+    ///
+    /// `procedure` is the name of an RPC procedure (e.g., `"plumtree.gossip"`).
+    pub fn decode_errors(&self, procedure: &str) -> u64 {
+        self.codec_errors.decode_errors(procedure)
+    }
+
+    /// Metric: `plumcast_service_encode_errors_total { procedure="..." } <COUNTER>`
+    ///
+    /// `procedure` is the name of an RPC procedure (e.g., `"plumtree.gossip"`).
+    pub fn encode_errors(&self, procedure: &str) -> u64 {
+        self.codec_errors.encode_errors(procedure)
+    }
+
+    pub(crate) fn new(mut factory: MetricsFactory) -> Self {
+        factory.subsystem("service");
         ServiceMetrics {
-            registered_nodes: builder
-                .counter("registered_nodes_total")
-                .help("Number of nodes registered so far")
-                .finish()
-                .expect("Never fails"),
-            deregistered_nodes: builder
-                .counter("deregistered_nodes_total")
-                .help("Number of nodes deregistered so far")
-                .finish()
-                .expect("Never fails"),
-            destination_unknown_messages: builder
-                .counter("destination_unknown_messages_total")
-                .help("Number of RPC messages received but the destination node is missing")
-                .finish()
-                .expect("Never fails"),
+            registered_nodes: factory.counter(
+                "registered_nodes_total",
+                "Number of nodes registered so far",
+            ),
+            deregistered_nodes: factory.counter(
+                "deregistered_nodes_total",
+                "Number of nodes deregistered so far",
+            ),
+            destination_unknown_messages: factory.counter(
+                "destination_unknown_messages_total",
+                "Number of RPC messages received but the destination node is missing",
+            ),
+            tombstoned_destination_messages: factory.counter(
+                "tombstoned_destination_messages_total",
+                "Number of RPC messages dropped because the destination node was recently deregistered",
+            ),
+            redirected_messages: factory.counter(
+                "redirected_messages_total",
+                "Number of RPC messages redirected to the default local node because the destination node is missing",
+            ),
+            oversized_payload_frames: factory.counter(
+                "oversized_payload_frames_total",
+                "Number of RPC frames rejected because the payload exceeds the maximum size",
+            ),
+            expired_node_leases: factory.counter(
+                "expired_node_leases_total",
+                "Number of nodes deregistered because their liveness leases have expired",
+            ),
+            command_queue_len: factory.gauge(
+                "command_queue_len",
+                "Number of node registrations and deregistrations waiting to be handled by the service",
+            ),
+            decoding_payload_bytes: factory.gauge(
+                "decoding_payload_bytes",
+                "Number of payload bytes held by in-progress decodes of received messages",
+            ),
+            throttled_payload_decodes: factory.counter(
+                "throttled_payload_decodes_total",
+                "Number of times a payload decode was postponed because the decoding budget was exhausted",
+            ),
+            incompatible_peers: factory.counter(
+                "incompatible_peers_total",
+                "Number of peers refused because they speak incompatible protocol versions",
+            ),
+            opened_circuits: factory.counter(
+                "opened_circuits_total",
+                "Number of times the circuit to a peer was opened due to consecutive send failures",
+            ),
+            circuit_rejected_messages: factory.counter(
+                "circuit_rejected_messages_total",
+                "Number of RPC messages rejected because the circuit to the destination peer is open",
+            ),
+            payload_key_changes: factory.counter(
+                "payload_key_changes_total",
+                "Number of times the payload encryption keys were added, removed or switched",
+            ),
+            rejected_joins: factory.counter_with_label(
+                "rejected_joins_total",
+                "Number of HyParView messages rejected by the join policy",
+                ("message", "join"),
+            ),
+            rejected_forward_joins: factory.counter_with_label(
+                "rejected_joins_total",
+                "Number of HyParView messages rejected by the join policy",
+                ("message", "forward_join"),
+            ),
+            rejected_neighbors: factory.counter_with_label(
+                "rejected_joins_total",
+                "Number of HyParView messages rejected by the join policy",
+                ("message", "neighbor"),
+            ),
+            rejected_parameter_updates: factory.counter(
+                "rejected_parameter_updates_total",
+                "Number of parameter updates rejected by the parameter update policy",
+            ),
+            sent_gossip_bytes: factory.counter_with_label(
+                "sent_bytes_total",
+                "Number of bytes of the RPC frames sent so far",
+                ("class", "gossip"),
+            ),
+            received_gossip_bytes: factory.counter_with_label(
+                "received_bytes_total",
+                "Number of bytes of the RPC frames received so far",
+                ("class", "gossip"),
+            ),
+            sent_ihave_bytes: factory.counter_with_label(
+                "sent_bytes_total",
+                "Number of bytes of the RPC frames sent so far",
+                ("class", "ihave"),
+            ),
+            received_ihave_bytes: factory.counter_with_label(
+                "received_bytes_total",
+                "Number of bytes of the RPC frames received so far",
+                ("class", "ihave"),
+            ),
+            sent_graft_bytes: factory.counter_with_label(
+                "sent_bytes_total",
+                "Number of bytes of the RPC frames sent so far",
+                ("class", "graft"),
+            ),
+            received_graft_bytes: factory.counter_with_label(
+                "received_bytes_total",
+                "Number of bytes of the RPC frames received so far",
+                ("class", "graft"),
+            ),
+            sent_prune_bytes: factory.counter_with_label(
+                "sent_bytes_total",
+                "Number of bytes of the RPC frames sent so far",
+                ("class", "prune"),
+            ),
+            received_prune_bytes: factory.counter_with_label(
+                "received_bytes_total",
+                "Number of bytes of the RPC frames received so far",
+                ("class", "prune"),
+            ),
+            sent_retract_bytes: factory.counter_with_label(
+                "sent_bytes_total",
+                "Number of bytes of the RPC frames sent so far",
+                ("class", "retract"),
+            ),
+            received_retract_bytes: factory.counter_with_label(
+                "received_bytes_total",
+                "Number of bytes of the RPC frames received so far",
+                ("class", "retract"),
+            ),
+            sent_hyparview_bytes: factory.counter_with_label(
+                "sent_bytes_total",
+                "Number of bytes of the RPC frames sent so far",
+                ("class", "hyparview"),
+            ),
+            received_hyparview_bytes: factory.counter_with_label(
+                "received_bytes_total",
+                "Number of bytes of the RPC frames received so far",
+                ("class", "hyparview"),
+            ),
+            received_payload_bytes: factory.counter(
+                "received_payload_bytes_total",
+                "Number of bytes of the application payloads contained in the received gossip frames",
+            ),
+            codec_errors: CodecErrors::new(factory.clone()),
         }
     }
 }
@@ -60,15 +383,51 @@ impl ServiceMetrics {
 pub struct NodeMetrics {
     pub(crate) broadcasted_messages: Counter,
     pub(crate) forgot_messages: Counter,
+    pub(crate) cache_evicted_bytes: Counter,
     pub(crate) delivered_messages: Counter,
+    pub(crate) expired_messages: Counter,
+    pub(crate) filtered_messages: Counter,
+    pub(crate) rejected_messages: Counter,
+    pub(crate) retracted_messages: Counter,
+    pub(crate) coalesced_grafts: Counter,
+    pub(crate) expired_gossips: Counter,
+    pub(crate) hop_limited_gossips: Counter,
     pub(crate) connected_neighbors: Counter,
     pub(crate) disconnected_neighbors: Counter,
+    pub(crate) left_neighbors: Counter,
+    pub(crate) failed_neighbors: Counter,
     pub(crate) isolated_times: Counter,
     pub(crate) deisolated_times: Counter,
+    pub(crate) falling_behind_times: Counter,
+    pub(crate) overloaded_times: Counter,
+    pub(crate) shed_gossips: Counter,
+    pub(crate) deduplicated_broadcasts: Counter,
+    pub(crate) deduplicated_ihaves: Counter,
+    pub(crate) quarantined_nodes: Counter,
+    pub(crate) stale_identities: Counter,
+    pub(crate) suppressed_neighbor_requests: Counter,
+    pub(crate) rejected_joins: Counter,
+    pub(crate) exhausted_poll_budgets: Counter,
+    pub(crate) throttled_gossips: Counter,
+    pub(crate) service_downs: Counter,
+    pub(crate) deferred_forward_joins: Counter,
+    pub(crate) dropped_forward_joins: Counter,
+    pub(crate) join_retries: Counter,
     pub(crate) forget_unknown_message_errors: Counter,
     pub(crate) cannot_send_hyparview_message_errors: Counter,
     pub(crate) cannot_send_plumtree_message_errors: Counter,
     pub(crate) unknown_plumtree_node_errors: Counter,
+    pub(crate) inbound_queue_overflow_errors: Counter,
+    pub(crate) active_view_size: Gauge,
+    pub(crate) passive_view_size: Gauge,
+    pub(crate) eager_push_peers: Gauge,
+    pub(crate) lazy_push_peers: Gauge,
+    pub(crate) cached_messages: Gauge,
+    pub(crate) inbound_queue_len: Gauge,
+    pub(crate) estimated_cluster_size: Gauge,
+    pub(crate) delivery_latency: Histogram,
+    pub(crate) payload_size: Histogram,
+    pub(crate) gossip_round: Histogram,
 }
 impl NodeMetrics {
     /// Metric: `plumcast_node_broadcasted_messages_total <COUNTER>`
@@ -81,11 +440,51 @@ impl NodeMetrics {
         self.forgot_messages.value() as u64
     }
 
+    /// Metric: `plumcast_node_cache_evicted_bytes_total <COUNTER>`
+    pub fn cache_evicted_bytes(&self) -> u64 {
+        self.cache_evicted_bytes.value() as u64
+    }
+
     /// Metric: `plumcast_node_delivered_messages_total <COUNTER>`
     pub fn delivered_messages(&self) -> u64 {
         self.delivered_messages.value() as u64
     }
 
+    /// Metric: `plumcast_node_expired_messages_total <COUNTER>`
+    pub fn expired_messages(&self) -> u64 {
+        self.expired_messages.value() as u64
+    }
+
+    /// Metric: `plumcast_node_filtered_messages_total <COUNTER>`
+    pub fn filtered_messages(&self) -> u64 {
+        self.filtered_messages.value() as u64
+    }
+
+    /// Metric: `plumcast_node_rejected_messages_total <COUNTER>`
+    pub fn rejected_messages(&self) -> u64 {
+        self.rejected_messages.value() as u64
+    }
+
+    /// Metric: `plumcast_node_retracted_messages_total <COUNTER>`
+    pub fn retracted_messages(&self) -> u64 {
+        self.retracted_messages.value() as u64
+    }
+
+    /// Metric: `plumcast_node_coalesced_grafts_total <COUNTER>`
+    pub fn coalesced_grafts(&self) -> u64 {
+        self.coalesced_grafts.value() as u64
+    }
+
+    /// Metric: `plumcast_node_expired_gossips_total <COUNTER>`
+    pub fn expired_gossips(&self) -> u64 {
+        self.expired_gossips.value() as u64
+    }
+
+    /// Metric: `plumcast_node_hop_limited_gossips_total <COUNTER>`
+    pub fn hop_limited_gossips(&self) -> u64 {
+        self.hop_limited_gossips.value() as u64
+    }
+
     /// Metric: `plumcast_node_connected_neighbors_total <COUNTER>`
     pub fn connected_neighbors(&self) -> u64 {
         self.connected_neighbors.value() as u64
@@ -96,6 +495,16 @@ impl NodeMetrics {
         self.disconnected_neighbors.value() as u64
     }
 
+    /// Metric: `plumcast_node_left_neighbors_total <COUNTER>`
+    pub fn left_neighbors(&self) -> u64 {
+        self.left_neighbors.value() as u64
+    }
+
+    /// Metric: `plumcast_node_failed_neighbors_total <COUNTER>`
+    pub fn failed_neighbors(&self) -> u64 {
+        self.failed_neighbors.value() as u64
+    }
+
     /// Metric: `plumcast_node_isolated_times_total <COUNTER>`
     pub fn isolated_times(&self) -> u64 {
         self.isolated_times.value() as u64
@@ -106,6 +515,81 @@ impl NodeMetrics {
         self.deisolated_times.value() as u64
     }
 
+    /// Metric: `plumcast_node_falling_behind_times_total <COUNTER>`
+    pub fn falling_behind_times(&self) -> u64 {
+        self.falling_behind_times.value() as u64
+    }
+
+    /// Metric: `plumcast_node_overloaded_times_total <COUNTER>`
+    pub fn overloaded_times(&self) -> u64 {
+        self.overloaded_times.value() as u64
+    }
+
+    /// Metric: `plumcast_node_shed_gossips_total <COUNTER>`
+    pub fn shed_gossips(&self) -> u64 {
+        self.shed_gossips.value() as u64
+    }
+
+    /// Metric: `plumcast_node_deduplicated_broadcasts_total <COUNTER>`
+    pub fn deduplicated_broadcasts(&self) -> u64 {
+        self.deduplicated_broadcasts.value() as u64
+    }
+
+    /// Metric: `plumcast_node_deduplicated_ihaves_total <COUNTER>`
+    pub fn deduplicated_ihaves(&self) -> u64 {
+        self.deduplicated_ihaves.value() as u64
+    }
+
+    /// Metric: `plumcast_node_quarantined_nodes_total <COUNTER>`
+    pub fn quarantined_nodes(&self) -> u64 {
+        self.quarantined_nodes.value() as u64
+    }
+
+    /// Metric: `plumcast_node_stale_identities_total <COUNTER>`
+    pub fn stale_identities(&self) -> u64 {
+        self.stale_identities.value() as u64
+    }
+
+    /// Metric: `plumcast_node_rejected_joins_total <COUNTER>`
+    pub fn rejected_joins(&self) -> u64 {
+        self.rejected_joins.value() as u64
+    }
+
+    /// Metric: `plumcast_node_exhausted_poll_budgets_total <COUNTER>`
+    pub fn exhausted_poll_budgets(&self) -> u64 {
+        self.exhausted_poll_budgets.value() as u64
+    }
+
+    /// Metric: `plumcast_node_throttled_gossips_total <COUNTER>`
+    pub fn throttled_gossips(&self) -> u64 {
+        self.throttled_gossips.value() as u64
+    }
+
+    /// Metric: `plumcast_node_service_downs_total <COUNTER>`
+    pub fn service_downs(&self) -> u64 {
+        self.service_downs.value() as u64
+    }
+
+    /// Metric: `plumcast_node_deferred_forward_joins_total <COUNTER>`
+    pub fn deferred_forward_joins(&self) -> u64 {
+        self.deferred_forward_joins.value() as u64
+    }
+
+    /// Metric: `plumcast_node_dropped_forward_joins_total <COUNTER>`
+    pub fn dropped_forward_joins(&self) -> u64 {
+        self.dropped_forward_joins.value() as u64
+    }
+
+    /// Metric: `plumcast_node_join_retries_total <COUNTER>`
+    pub fn join_retries(&self) -> u64 {
+        self.join_retries.value() as u64
+    }
+
+    /// Metric: `plumcast_node_suppressed_neighbor_requests_total <COUNTER>`
+    pub fn suppressed_neighbor_requests(&self) -> u64 {
+        self.suppressed_neighbor_requests.value() as u64
+    }
+
     /// Metric: `plumcast_node_errors_total { kind="forget_unknown_message" } <COUNTER>`
     pub fn forget_unknown_message_errors(&self) -> u64 {
         self.forget_unknown_message_errors.value() as u64
@@ -126,89 +610,748 @@ impl NodeMetrics {
         self.unknown_plumtree_node_errors.value() as u64
     }
 
-    pub(crate) fn new(mut builder: MetricBuilder) -> Self {
-        builder.namespace("plumcast").subsystem("node");
+    /// Metric: `plumcast_node_errors_total { kind="inbound_queue_overflow" } <COUNTER>`
+    pub fn inbound_queue_overflow_errors(&self) -> u64 {
+        self.inbound_queue_overflow_errors.value() as u64
+    }
+
+    /// Metric: `plumcast_node_active_view_size <GAUGE>`
+    pub fn active_view_size(&self) -> u64 {
+        self.active_view_size.value() as u64
+    }
+
+    /// Metric: `plumcast_node_passive_view_size <GAUGE>`
+    pub fn passive_view_size(&self) -> u64 {
+        self.passive_view_size.value() as u64
+    }
+
+    /// Metric: `plumcast_node_eager_push_peers <GAUGE>`
+    pub fn eager_push_peers(&self) -> u64 {
+        self.eager_push_peers.value() as u64
+    }
+
+    /// Metric: `plumcast_node_lazy_push_peers <GAUGE>`
+    pub fn lazy_push_peers(&self) -> u64 {
+        self.lazy_push_peers.value() as u64
+    }
+
+    /// Metric: `plumcast_node_cached_messages <GAUGE>`
+    pub fn cached_messages(&self) -> u64 {
+        self.cached_messages.value() as u64
+    }
+
+    /// Metric: `plumcast_node_inbound_queue_len <GAUGE>`
+    pub fn inbound_queue_len(&self) -> u64 {
+        self.inbound_queue_len.value() as u64
+    }
+
+    /// Metric: `plumcast_node_estimated_cluster_size <GAUGE>`
+    pub fn estimated_cluster_size(&self) -> u64 {
+        self.estimated_cluster_size.value() as u64
+    }
+
+    pub(crate) fn new(mut factory: MetricsFactory, buckets: &NodeHistogramBuckets) -> Self {
+        factory.subsystem("node");
         NodeMetrics {
-            broadcasted_messages: builder
-                .counter("broadcasted_messages_total")
-                .help("Number of messages broadcasted so far")
-                .finish()
-                .expect("Never fails"),
-            forgot_messages: builder
-                .counter("forgot_messages_total")
-                .help("Number of messages forgot so far")
-                .finish()
-                .expect("Never fails"),
-            delivered_messages: builder
-                .counter("delivered_messages_total")
-                .help("Number of messages delivered so far")
-                .finish()
-                .expect("Never fails"),
-            connected_neighbors: builder
-                .counter("connected_neighbors_total")
-                .help("Number of neighbors connected so far")
-                .finish()
-                .expect("Never fails"),
-            disconnected_neighbors: builder
-                .counter("disconnected_neighbors_total")
-                .help("Number of neighbors disconnected so far")
-                .finish()
-                .expect("Never fails"),
-            isolated_times: builder
-                .counter("isolated_times_total")
-                .help("Number of times the node was isolated so far")
-                .finish()
-                .expect("Never fails"),
-            deisolated_times: builder
-                .counter("deisolated_times_total")
-                .help("Number of times the node was de-isolated so far")
-                .finish()
-                .expect("Never fails"),
-            forget_unknown_message_errors: builder
-                .counter("errors_total")
-                .help("Number of errors happened so far")
-                .label("kind", "forget_unknown_message")
-                .finish()
-                .expect("Never fails"),
-            cannot_send_hyparview_message_errors: builder
-                .counter("errors_total")
-                .help("Number of errors happened so far")
-                .label("kind", "cannot_send_hyparview_message")
-                .finish()
-                .expect("Never fails"),
-            cannot_send_plumtree_message_errors: builder
-                .counter("errors_total")
-                .help("Number of errors happened so far")
-                .label("kind", "cannot_send_plumtree_message")
-                .finish()
-                .expect("Never fails"),
-            unknown_plumtree_node_errors: builder
-                .counter("errors_total")
-                .help("Number of errors happened so far")
-                .label("kind", "unknown_plumtree_node")
-                .finish()
-                .expect("Never fails"),
+            broadcasted_messages: factory.counter(
+                "broadcasted_messages_total",
+                "Number of messages broadcasted so far",
+            ),
+            forgot_messages: factory
+                .counter("forgot_messages_total", "Number of messages forgot so far"),
+            cache_evicted_bytes: factory.counter(
+                "cache_evicted_bytes_total",
+                "Number of payload bytes evicted from the message cache so far",
+            ),
+            delivered_messages: factory.counter(
+                "delivered_messages_total",
+                "Number of messages delivered so far",
+            ),
+            expired_messages: factory.counter(
+                "expired_messages_total",
+                "Number of messages not delivered because their deadlines had passed",
+            ),
+            filtered_messages: factory.counter(
+                "filtered_messages_total",
+                "Number of messages not delivered because they were rejected by the delivery filter",
+            ),
+            rejected_messages: factory.counter(
+                "rejected_messages_total",
+                "Number of received messages not relayed because they were rejected by the relay policy",
+            ),
+            retracted_messages: factory.counter(
+                "retracted_messages_total",
+                "Number of messages retracted by their origin nodes or redacted by tombstones",
+            ),
+            coalesced_grafts: factory.counter(
+                "coalesced_grafts_total",
+                "Number of duplicate GRAFT messages coalesced with preceding ones",
+            ),
+            expired_gossips: factory.counter(
+                "expired_gossips_total",
+                "Number of gossip and IHAVE messages not sent because their deadlines had passed",
+            ),
+            hop_limited_gossips: factory.counter(
+                "hop_limited_gossips_total",
+                "Number of gossip and IHAVE messages not sent because their hop limits were reached",
+            ),
+            connected_neighbors: factory.counter(
+                "connected_neighbors_total",
+                "Number of neighbors connected so far",
+            ),
+            disconnected_neighbors: factory.counter(
+                "disconnected_neighbors_total",
+                "Number of neighbors disconnected so far",
+            ),
+            left_neighbors: factory.counter(
+                "left_neighbors_total",
+                "Number of neighbors disconnected because they left the cluster (i.e., sent DISCONNECT messages with alive=false)",
+            ),
+            failed_neighbors: factory.counter(
+                "failed_neighbors_total",
+                "Number of neighbors disconnected because they were detected to be failed",
+            ),
+            isolated_times: factory.counter(
+                "isolated_times_total",
+                "Number of times the node was isolated so far",
+            ),
+            deisolated_times: factory.counter(
+                "deisolated_times_total",
+                "Number of times the node was de-isolated so far",
+            ),
+            falling_behind_times: factory.counter(
+                "falling_behind_times_total",
+                "Number of times the pending deliveries of the node reached the high watermark",
+            ),
+            overloaded_times: factory.counter(
+                "overloaded_times_total",
+                "Number of times the node became overloaded",
+            ),
+            shed_gossips: factory.counter(
+                "shed_gossips_total",
+                "Number of gossips replaced with IHAVE messages while the node was overloaded",
+            ),
+            deduplicated_broadcasts: factory.counter(
+                "deduplicated_broadcasts_total",
+                "Number of broadcasts suppressed because the same payloads had been seen recently",
+            ),
+            deduplicated_ihaves: factory.counter(
+                "deduplicated_ihaves_total",
+                "Number of IHAVE messages ignored because the same payloads had been seen recently",
+            ),
+            quarantined_nodes: factory.counter(
+                "quarantined_nodes_total",
+                "Number of times nodes were quarantined so far",
+            ),
+            stale_identities: factory.counter(
+                "stale_identities_total",
+                "Number of neighbors disconnected because they were superseded by new identities at the same addresses",
+            ),
+            suppressed_neighbor_requests: factory.counter(
+                "suppressed_neighbor_requests_total",
+                "Number of NEIGHBOR requests to quarantined nodes suppressed so far",
+            ),
+            exhausted_poll_budgets: factory.counter(
+                "exhausted_poll_budgets_total",
+                "Number of times a poll yielded because the maximum messages per poll were handled",
+            ),
+            throttled_gossips: factory.counter(
+                "throttled_gossips_total",
+                "Number of GOSSIP messages dropped because their origins exceeded the broadcast quota",
+            ),
+            service_downs: factory.counter(
+                "service_downs_total",
+                "Number of times the node detected that its service had stopped",
+            ),
+            rejected_joins: factory.counter(
+                "rejected_joins_total",
+                "Number of JOIN messages dropped because the join rate limit was exceeded",
+            ),
+            deferred_forward_joins: factory.counter(
+                "deferred_forward_joins_total",
+                "Number of FORWARD_JOIN messages deferred to later ticks",
+            ),
+            dropped_forward_joins: factory.counter(
+                "dropped_forward_joins_total",
+                "Number of FORWARD_JOIN messages dropped because too many messages were deferred",
+            ),
+            join_retries: factory.counter(
+                "join_retries_total",
+                "Number of JOIN messages resent because the node was still isolated",
+            ),
+            forget_unknown_message_errors: factory.counter_with_label(
+                "errors_total",
+                "Number of errors happened so far",
+                ("kind", "forget_unknown_message"),
+            ),
+            cannot_send_hyparview_message_errors: factory.counter_with_label(
+                "errors_total",
+                "Number of errors happened so far",
+                ("kind", "cannot_send_hyparview_message"),
+            ),
+            cannot_send_plumtree_message_errors: factory.counter_with_label(
+                "errors_total",
+                "Number of errors happened so far",
+                ("kind", "cannot_send_plumtree_message"),
+            ),
+            unknown_plumtree_node_errors: factory.counter_with_label(
+                "errors_total",
+                "Number of errors happened so far",
+                ("kind", "unknown_plumtree_node"),
+            ),
+            inbound_queue_overflow_errors: factory.counter_with_label(
+                "errors_total",
+                "Number of errors happened so far",
+                ("kind", "inbound_queue_overflow"),
+            ),
+            active_view_size: factory.gauge(
+                "active_view_size",
+                "Number of nodes in the HyParView active view",
+            ),
+            passive_view_size: factory.gauge(
+                "passive_view_size",
+                "Number of nodes in the HyParView passive view",
+            ),
+            eager_push_peers: factory
+                .gauge("eager_push_peers", "Number of Plumtree eager push peers"),
+            lazy_push_peers: factory.gauge("lazy_push_peers", "Number of Plumtree lazy push peers"),
+            cached_messages: factory.gauge(
+                "cached_messages",
+                "Number of delivered messages that have not been forgot yet",
+            ),
+            inbound_queue_len: factory.gauge(
+                "inbound_queue_len",
+                "Number of inbound RPC messages waiting to be handled by the node",
+            ),
+            estimated_cluster_size: factory.gauge(
+                "estimated_cluster_size",
+                "Estimated number of nodes in the cluster",
+            ),
+            delivery_latency: factory.histogram(
+                "delivery_latency_seconds",
+                "Elapsed time from broadcasting messages to delivering them",
+                &buckets.delivery_latency_seconds,
+            ),
+            payload_size: factory.histogram(
+                "payload_size_bytes",
+                "Encoded size of the payloads of received gossip messages",
+                &buckets.payload_size_bytes,
+            ),
+            gossip_round: factory.histogram(
+                "gossip_rounds",
+                "Plumtree rounds of received gossip messages",
+                &buckets.gossip_rounds,
+            ),
         }
     }
 
+    /// Adds the counter values of `other` to `self`.
+    ///
+    /// The values are not forwarded to the `MetricsSink` again (see `Counter::carry_over()`).
+    /// Note that gauges are not aggregated because they represent the current state of a node.
+    /// Histograms are not aggregated either.
     pub(crate) fn add(&self, other: &Self) {
         self.broadcasted_messages
-            .add_u64(other.broadcasted_messages());
-        self.forgot_messages.add_u64(other.forgot_messages());
-        self.delivered_messages.add_u64(other.delivered_messages());
+            .carry_over(other.broadcasted_messages());
+        self.forgot_messages.carry_over(other.forgot_messages());
+        self.cache_evicted_bytes
+            .carry_over(other.cache_evicted_bytes());
+        self.delivered_messages
+            .carry_over(other.delivered_messages());
+        self.expired_messages.carry_over(other.expired_messages());
+        self.filtered_messages.carry_over(other.filtered_messages());
+        self.rejected_messages.carry_over(other.rejected_messages());
+        self.retracted_messages
+            .carry_over(other.retracted_messages());
+        self.coalesced_grafts.carry_over(other.coalesced_grafts());
+        self.expired_gossips.carry_over(other.expired_gossips());
+        self.hop_limited_gossips
+            .carry_over(other.hop_limited_gossips());
         self.connected_neighbors
-            .add_u64(other.connected_neighbors());
+            .carry_over(other.connected_neighbors());
         self.disconnected_neighbors
-            .add_u64(other.disconnected_neighbors());
-        self.isolated_times.add_u64(other.isolated_times());
-        self.deisolated_times.add_u64(other.deisolated_times());
+            .carry_over(other.disconnected_neighbors());
+        self.left_neighbors.carry_over(other.left_neighbors());
+        self.failed_neighbors.carry_over(other.failed_neighbors());
+        self.isolated_times.carry_over(other.isolated_times());
+        self.deisolated_times.carry_over(other.deisolated_times());
+        self.falling_behind_times
+            .carry_over(other.falling_behind_times());
+        self.overloaded_times.carry_over(other.overloaded_times());
+        self.shed_gossips.carry_over(other.shed_gossips());
+        self.deduplicated_broadcasts
+            .carry_over(other.deduplicated_broadcasts());
+        self.deduplicated_ihaves
+            .carry_over(other.deduplicated_ihaves());
+        self.quarantined_nodes.carry_over(other.quarantined_nodes());
+        self.stale_identities.carry_over(other.stale_identities());
+        self.rejected_joins.carry_over(other.rejected_joins());
+        self.exhausted_poll_budgets
+            .carry_over(other.exhausted_poll_budgets());
+        self.throttled_gossips.carry_over(other.throttled_gossips());
+        self.service_downs.carry_over(other.service_downs());
+        self.deferred_forward_joins
+            .carry_over(other.deferred_forward_joins());
+        self.dropped_forward_joins
+            .carry_over(other.dropped_forward_joins());
+        self.join_retries.carry_over(other.join_retries());
+        self.suppressed_neighbor_requests
+            .carry_over(other.suppressed_neighbor_requests());
         self.forget_unknown_message_errors
-            .add_u64(other.forget_unknown_message_errors());
+            .carry_over(other.forget_unknown_message_errors());
         self.cannot_send_hyparview_message_errors
-            .add_u64(other.cannot_send_hyparview_message_errors());
+            .carry_over(other.cannot_send_hyparview_message_errors());
         self.cannot_send_plumtree_message_errors
-            .add_u64(other.cannot_send_plumtree_message_errors());
+            .carry_over(other.cannot_send_plumtree_message_errors());
         self.unknown_plumtree_node_errors
-            .add_u64(other.unknown_plumtree_node_errors());
+            .carry_over(other.unknown_plumtree_node_errors());
+        self.inbound_queue_overflow_errors
+            .carry_over(other.inbound_queue_overflow_errors());
+    }
+}
+
+/// This trait allows for receiving the updates of the metrics defined in this module.
+///
+/// A sink is set by `ServiceBuilder::metrics_sink()` and shared by the service and all its nodes.
+///
+/// `name` is the fully qualified metric name (e.g., `plumcast_node_delivered_messages_total`) and
+/// `labels` are the labels attached to the metric (e.g., the ones set by `NodeBuilder::metric_label()`).
+pub trait MetricsSink: Send + Sync + 'static {
+    /// Adds `value` to the counter identified by `name` and `labels`.
+    fn add_counter(&self, name: &str, labels: &[(String, String)], value: u64);
+
+    /// Sets the gauge identified by `name` and `labels` to `value`.
+    fn set_gauge(&self, name: &str, labels: &[(String, String)], value: f64);
+
+    /// Records `value` to the histogram identified by `name` and `labels`.
+    ///
+    /// The default implementation does nothing.
+    fn observe_histogram(&self, name: &str, labels: &[(String, String)], value: f64) {
+        let _ = (name, labels, value);
+    }
+}
+
+/// Bucket settings of the histogram metrics of a [`Node`].
+///
+/// [`Node`]: ../node/struct.Node.html
+#[derive(Debug, Clone)]
+pub struct NodeHistogramBuckets {
+    /// Upper bounds of the buckets of `plumcast_node_delivery_latency_seconds <HISTOGRAM>`.
+    ///
+    /// This metric is the elapsed time from the broadcast of a message at the origin node
+    /// to its delivery at the local node.
+    /// Note that the accuracy depends on the clock synchronization between nodes.
+    pub delivery_latency_seconds: Vec<f64>,
+
+    /// Upper bounds of the buckets of `plumcast_node_payload_size_bytes <HISTOGRAM>`.
+    ///
+    /// This metric is the encoded size of the payloads of gossip messages received from remote nodes.
+    pub payload_size_bytes: Vec<f64>,
+
+    /// Upper bounds of the buckets of `plumcast_node_gossip_rounds <HISTOGRAM>`.
+    ///
+    /// This metric is the Plumtree round (i.e., the number of hops from the origin node)
+    /// of gossip messages received from remote nodes.
+    pub gossip_rounds: Vec<f64>,
+}
+impl Default for NodeHistogramBuckets {
+    fn default() -> Self {
+        NodeHistogramBuckets {
+            delivery_latency_seconds: vec![
+                0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+            ],
+            payload_size_bytes: vec![
+                64.0,
+                256.0,
+                1024.0,
+                4096.0,
+                16_384.0,
+                65_536.0,
+                262_144.0,
+                1_048_576.0,
+            ],
+            gossip_rounds: vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0, 10.0, 15.0, 20.0],
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ArcMetricsSink(Arc<dyn MetricsSink>);
+impl ArcMetricsSink {
+    pub(crate) fn new<S: MetricsSink>(inner: S) -> Self {
+        ArcMetricsSink(Arc::new(inner))
+    }
+}
+impl fmt::Debug for ArcMetricsSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ArcMetricsSink(_)")
+    }
+}
+
+#[derive(Debug)]
+struct MetricKey {
+    name: String,
+    labels: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+struct SinkHandle {
+    sink: ArcMetricsSink,
+    key: Arc<MetricKey>,
+}
+
+/// A counter registered to prometrics (unless metrics are disabled) and
+/// (optionally) forwarded to a `MetricsSink`.
+#[derive(Debug, Clone)]
+pub(crate) struct Counter {
+    inner: Option<prom::Counter>,
+    sink: Option<SinkHandle>,
+}
+impl Counter {
+    pub(crate) fn value(&self) -> f64 {
+        self.inner.as_ref().map_or(0.0, |c| c.value())
+    }
+
+    pub(crate) fn increment(&self) {
+        self.add_u64(1);
+    }
+
+    pub(crate) fn add_u64(&self, value: u64) {
+        if let Some(ref c) = self.inner {
+            c.add_u64(value);
+        }
+        if let Some(ref s) = self.sink {
+            s.sink.0.add_counter(&s.key.name, &s.key.labels, value);
+        }
+    }
+
+    /// Adds the value of another counter (e.g., the one of a deregistered node) to this counter.
+    ///
+    /// Unlike `add_u64()`, the value is not forwarded to the sink,
+    /// because the increments of the other counter have already been forwarded.
+    pub(crate) fn carry_over(&self, value: u64) {
+        if let Some(ref c) = self.inner {
+            c.add_u64(value);
+        }
+    }
+}
+
+/// A gauge registered to prometrics (unless metrics are disabled) and
+/// (optionally) forwarded to a `MetricsSink`.
+#[derive(Debug, Clone)]
+pub(crate) struct Gauge {
+    inner: Option<prom::Gauge>,
+    sink: Option<SinkHandle>,
+}
+impl Gauge {
+    pub(crate) fn value(&self) -> f64 {
+        self.inner.as_ref().map_or(0.0, |g| g.value())
+    }
+
+    pub(crate) fn set(&self, value: f64) {
+        if let Some(ref g) = self.inner {
+            g.set(value);
+        }
+        if let Some(ref s) = self.sink {
+            s.sink.0.set_gauge(&s.key.name, &s.key.labels, value);
+        }
+    }
+}
+
+/// A histogram registered to prometrics (unless metrics are disabled) and
+/// (optionally) forwarded to a `MetricsSink`.
+#[derive(Debug, Clone)]
+pub(crate) struct Histogram {
+    inner: Option<prom::Histogram>,
+    sink: Option<SinkHandle>,
+}
+impl Histogram {
+    pub(crate) fn observe(&self, value: f64) {
+        if let Some(ref h) = self.inner {
+            h.observe(value);
+        }
+        if let Some(ref s) = self.sink {
+            s.sink
+                .0
+                .observe_histogram(&s.key.name, &s.key.labels, value);
+        }
+    }
+}
+
+/// The counters of the RPC frames that failed to be encoded or decoded.
+///
+/// Since the counters are labeled by the names of RPC procedures,
+/// they are registered when they are first requested.
+#[derive(Debug, Clone)]
+pub(crate) struct CodecErrors(Arc<Mutex<CodecErrorsInner>>);
+impl CodecErrors {
+    fn new(factory: MetricsFactory) -> Self {
+        CodecErrors(Arc::new(Mutex::new(CodecErrorsInner {
+            factory,
+            decode: HashMap::new(),
+            encode: HashMap::new(),
+        })))
+    }
+
+    pub(crate) fn decode(&self, procedure: &'static str) -> Counter {
+        let mut inner = self.0.lock().expect("Never fails");
+        let CodecErrorsInner {
+            ref mut factory,
+            ref mut decode,
+            ..
+        } = *inner;
+        decode
+            .entry(procedure)
+            .or_insert_with(|| {
+                factory.counter_with_label(
+                    "decode_errors_total",
+                    "Number of received RPC frames that failed to be decoded",
+                    ("procedure", procedure),
+                )
+            })
+            .clone()
+    }
+
+    pub(crate) fn encode(&self, procedure: &'static str) -> Counter {
+        let mut inner = self.0.lock().expect("Never fails");
+        let CodecErrorsInner {
+            ref mut factory,
+            ref mut encode,
+            ..
+        } = *inner;
+        encode
+            .entry(procedure)
+            .or_insert_with(|| {
+                factory.counter_with_label(
+                    "encode_errors_total",
+                    "Number of RPC frames that failed to be encoded",
+                    ("procedure", procedure),
+                )
+            })
+            .clone()
+    }
+
+    fn decode_errors(&self, procedure: &str) -> u64 {
+        let inner = self.0.lock().expect("Never fails");
+        inner.decode.get(procedure).map_or(0, |c| c.value() as u64)
+    }
+
+    fn encode_errors(&self, procedure: &str) -> u64 {
+        let inner = self.0.lock().expect("Never fails");
+        inner.encode.get(procedure).map_or(0, |c| c.value() as u64)
+    }
+}
+
+#[derive(Debug)]
+struct CodecErrorsInner {
+    factory: MetricsFactory,
+    decode: HashMap<&'static str, Counter>,
+    encode: HashMap<&'static str, Counter>,
+}
+
+/// A factory of metrics.
+///
+/// If `builder` is `None`, the metrics made by this factory are disabled
+/// (i.e., they are never registered nor updated).
+#[derive(Debug, Clone)]
+pub(crate) struct MetricsFactory {
+    builder: Option<MetricBuilder>,
+    sink: Option<ArcMetricsSink>,
+    labels: Vec<(String, String)>,
+    subsystem: &'static str,
+}
+impl MetricsFactory {
+    pub(crate) fn new(
+        mut builder: MetricBuilder,
+        sink: Option<ArcMetricsSink>,
+        labels: Vec<(String, String)>,
+    ) -> Self {
+        if cfg!(not(feature = "metrics")) {
+            return Self::disabled();
+        }
+        for (name, value) in &labels {
+            builder.label(name, value);
+        }
+        MetricsFactory {
+            builder: Some(builder),
+            sink,
+            labels,
+            subsystem: "",
+        }
+    }
+
+    pub(crate) fn disabled() -> Self {
+        MetricsFactory {
+            builder: None,
+            sink: None,
+            labels: Vec::new(),
+            subsystem: "",
+        }
+    }
+
+    fn subsystem(&mut self, subsystem: &'static str) {
+        if let Some(ref mut builder) = self.builder {
+            builder.namespace("plumcast").subsystem(subsystem);
+        }
+        self.subsystem = subsystem;
+    }
+
+    fn counter(&mut self, name: &str, help: &str) -> Counter {
+        let inner = self.builder.as_ref().map(|builder| {
+            builder
+                .counter(name)
+                .help(help)
+                .finish()
+                .expect("Never fails")
+        });
+        let sink = self.sink_handle(name, None);
+        Counter { inner, sink }
+    }
+
+    fn counter_with_label(&mut self, name: &str, help: &str, label: (&str, &str)) -> Counter {
+        let inner = self.builder.as_ref().map(|builder| {
+            builder
+                .counter(name)
+                .help(help)
+                .label(label.0, label.1)
+                .finish()
+                .expect("Never fails")
+        });
+        let sink = self.sink_handle(name, Some(label));
+        Counter { inner, sink }
+    }
+
+    fn gauge(&mut self, name: &str, help: &str) -> Gauge {
+        let inner = self.builder.as_ref().map(|builder| {
+            builder
+                .gauge(name)
+                .help(help)
+                .finish()
+                .expect("Never fails")
+        });
+        let sink = self.sink_handle(name, None);
+        Gauge { inner, sink }
+    }
+
+    fn histogram(&mut self, name: &str, help: &str, buckets: &[f64]) -> Histogram {
+        let inner = self.builder.as_ref().map(|builder| {
+            let mut builder = builder.histogram(name);
+            builder.help(help);
+            for &b in buckets {
+                builder.bucket(b);
+            }
+            builder.finish().expect("Never fails")
+        });
+        let sink = self.sink_handle(name, None);
+        Histogram { inner, sink }
+    }
+
+    fn sink_handle(&self, name: &str, label: Option<(&str, &str)>) -> Option<SinkHandle> {
+        self.sink.clone().map(|sink| {
+            let mut labels = self.labels.clone();
+            labels.extend(label.map(|(k, v)| (k.to_owned(), v.to_owned())));
+            let name = format!("plumcast_{}_{}", self.subsystem, name);
+            let key = Arc::new(MetricKey { name, labels });
+            SinkHandle { sink, key }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingSink(Mutex<Vec<(String, Vec<(String, String)>, u64)>>);
+    impl MetricsSink for Arc<RecordingSink> {
+        fn add_counter(&self, name: &str, labels: &[(String, String)], value: u64) {
+            let entry = (name.to_owned(), labels.to_owned(), value);
+            self.0.lock().unwrap().push(entry);
+        }
+
+        fn set_gauge(&self, _name: &str, _labels: &[(String, String)], _value: f64) {}
+    }
+
+    #[test]
+    fn metrics_are_skipped_without_feature() {
+        let factory = MetricsFactory::new(MetricBuilder::new(), None, Vec::new());
+        let metrics = NodeMetrics::new(factory, &NodeHistogramBuckets::default());
+        metrics.delivered_messages.increment();
+        let expected = if cfg!(feature = "metrics") { 1 } else { 0 };
+        assert_eq!(metrics.delivered_messages(), expected);
+    }
+
+    #[test]
+    fn metrics_sink_receives_counter_updates() {
+        let sink = Arc::new(RecordingSink::default());
+        let labels = vec![("topic".to_owned(), "foo".to_owned())];
+        let factory = MetricsFactory::new(
+            MetricBuilder::new(),
+            Some(ArcMetricsSink::new(sink.clone())),
+            labels.clone(),
+        );
+        let metrics = NodeMetrics::new(factory, &NodeHistogramBuckets::default());
+        metrics.delivered_messages.increment();
+        metrics.unknown_plumtree_node_errors.add_u64(2);
+        assert_eq!(metrics.delivered_messages(), 1);
+
+        let mut error_labels = labels.clone();
+        error_labels.push(("kind".to_owned(), "unknown_plumtree_node".to_owned()));
+        assert_eq!(
+            *sink.0.lock().unwrap(),
+            [
+                (
+                    "plumcast_node_delivered_messages_total".to_owned(),
+                    labels,
+                    1
+                ),
+                ("plumcast_node_errors_total".to_owned(), error_labels, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn carried_over_counters_are_not_forwarded_again() {
+        let sink = Arc::new(RecordingSink::default());
+        let factory = || {
+            MetricsFactory::new(
+                MetricBuilder::new(),
+                Some(ArcMetricsSink::new(sink.clone())),
+                Vec::new(),
+            )
+        };
+        let removed = NodeMetrics::new(factory(), &NodeHistogramBuckets::default());
+        let node = NodeMetrics::new(factory(), &NodeHistogramBuckets::default());
+        node.delivered_messages.add_u64(3);
+        assert_eq!(sink.0.lock().unwrap().len(), 1);
+
+        removed.add(&node);
+        assert_eq!(removed.delivered_messages(), 3);
+        assert_eq!(sink.0.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn codec_errors_are_counted_per_procedure() {
+        let factory = MetricsFactory::new(MetricBuilder::new(), None, Vec::new());
+        let metrics = ServiceMetrics::new(factory);
+        metrics.codec_errors.decode("plumtree.gossip").increment();
+        metrics.codec_errors.decode("plumtree.gossip").increment();
+        metrics.codec_errors.encode("hyparview.join").increment();
+
+        assert_eq!(metrics.decode_errors("plumtree.gossip"), 2);
+        assert_eq!(metrics.encode_errors("plumtree.gossip"), 0);
+        assert_eq!(metrics.encode_errors("hyparview.join"), 1);
+        assert_eq!(metrics.decode_errors("unknown"), 0);
+    }
+
+    #[test]
+    fn disabled_metrics_are_never_updated() {
+        let metrics = NodeMetrics::new(MetricsFactory::disabled(), &Default::default());
+        metrics.delivered_messages.increment();
+        metrics.active_view_size.set(3.0);
+        metrics.delivery_latency.observe(0.1);
+        assert_eq!(metrics.delivered_messages(), 0);
+        assert_eq!(metrics.active_view_size(), 0);
     }
 }
@@ -1,5 +1,5 @@
 //! Miscellaneous components.
-use crate::message::{MessageId, MessagePayload};
+use crate::message::{Envelope, MessageId, MessagePayload};
 use crate::node::NodeId;
 use fibers::Spawn;
 use futures::Future;
@@ -66,6 +66,13 @@ pub(crate) type GraftMessage<M> = plumtree::message::GraftMessage<PlumtreeSystem
 pub(crate) type IhaveMessage<M> = plumtree::message::IhaveMessage<PlumtreeSystem<M>>;
 pub(crate) type PruneMessage<M> = plumtree::message::PruneMessage<PlumtreeSystem<M>>;
 
+/// A control message that retracts a broadcasted message (see `Node::cancel_broadcast()`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RetractMessage {
+    pub sender: NodeId,
+    pub message_id: MessageId,
+}
+
 /// An implementation of [`plumtree::System`] trait specialised to this crate.
 ///
 /// [`plumtree::System`]: https://docs.rs/plumtree/0.1/plumtree/trait.System.html
@@ -74,5 +81,5 @@ pub struct PlumtreeSystem<M>(PhantomData<M>);
 impl<M: MessagePayload> plumtree::System for PlumtreeSystem<M> {
     type NodeId = NodeId;
     type MessageId = MessageId;
-    type MessagePayload = M;
+    type MessagePayload = Envelope<M>;
 }
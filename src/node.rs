@@ -1,29 +1,61 @@
 //! [`Node`] and related components.
 //!
 //! [`Node`]: ./node/struct.Node.html
-use crate::message::{Message, MessageId, MessagePayload};
-use crate::metrics::NodeMetrics;
+use crate::admin::{DebugEvent, NodeStatus, ParameterUpdate};
+use crate::clock;
+use crate::dedicated::DedicatedNode;
+use crate::discovery::{LanDiscovery, LanDiscoveryOptions};
+use crate::estimator::{self, ClusterSizeEstimator};
+use crate::event_log::EventLog;
+use crate::membership::{self, ArcMembershipExporter, ExportMembership, Membership};
+use crate::message::{
+    content_hash, encode_payload, encoded_payload_size, BoxDeliveryFilter, BoxMessageIdPolicy,
+    BoxRelayPolicy, DeliveryFilter, Envelope, Message, MessageId, MessageIdPolicy, MessagePayload,
+    RelayPolicy,
+};
+use crate::metrics::{NodeHistogramBuckets, NodeMetrics};
 use crate::misc::{
-    HyparviewAction, HyparviewNode, HyparviewNodeOptions, PlumtreeAction, PlumtreeNode,
-    PlumtreeNodeOptions,
+    GossipMessage, GraftMessage, HyparviewAction, HyparviewMessage, HyparviewNode,
+    HyparviewNodeOptions, IhaveMessage, PlumtreeAction, PlumtreeMessage, PlumtreeNode,
+    PlumtreeNodeOptions, PruneMessage, RetractMessage, ShuffleReplyMessage,
 };
+use crate::node_id;
+use crate::quarantine::Quarantine;
+use crate::random;
 use crate::rpc::RpcMessage;
 use crate::service::ServiceHandle;
-use crate::{Error, ErrorKind};
-use fibers::sync::mpsc;
+use crate::shuffle::{BoxShufflePolicy, ShuffleContext, ShufflePolicy};
+use crate::sink::{ExternalSink, Forward};
+use crate::subscription::{Subscribers, Subscription};
+use crate::topology::{RttTable, TopologyAwareness};
+use crate::trace::{self, TraceContext};
+use crate::{Error, ErrorKind, Result};
+use atomic_immut::AtomicImmut;
+use fibers::sync::{mpsc, oneshot};
 use fibers::time::timer::{self, Timeout};
-use futures::{Async, Future, Poll, Stream};
+use fibers::Spawn;
+use futures::{task, Async, Future, Poll, Stream};
 use plumtree::message::Message as PlumtreeAppMessage;
 use plumtree::time::{Clock, NodeTime};
+use prometrics::metrics::MetricBuilder;
 use rand::rngs::StdRng;
-use rand::{self, Rng, SeedableRng};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 use slog::{Discard, Logger};
+use std::cmp;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use trackable::error::ErrorKindExt;
 
 pub use crate::node_id::{LocalNodeId, NodeId};
 pub use crate::node_id_generator::{
-    GenerateLocalNodeId, SerialLocalNodeIdGenerator, UnixtimeLocalNodeIdGenerator,
+    GenerateLocalNodeId, RandomLocalNodeIdGenerator, SerialLocalNodeIdGenerator,
+    UnixtimeLocalNodeIdGenerator,
 };
 
 /// The builder of [`Node`].
@@ -34,22 +66,86 @@ pub struct NodeBuilder {
     logger: Logger,
     hyparview_options: HyparviewNodeOptions,
     plumtree_options: PlumtreeNodeOptions,
+    ihave_timeouts: Option<(Duration, Duration)>,
     params: Parameters,
+    event_log_capacity: usize,
+    max_inbound_queue_len: Option<usize>,
+    delivery_watermarks: Option<(usize, usize)>,
+    overload_policy: Option<OverloadPolicy>,
+    message_cache_budget: Option<u64>,
+    clock: Option<clock::Clock>,
+    identity_file: Option<PathBuf>,
+    disconnect_stale_identities: bool,
+    quarantine_failure_threshold: usize,
+    quarantine_duration: Duration,
+    max_joins_per_tick: Option<usize>,
+    max_forward_joins_per_tick: Option<usize>,
+    max_deferred_forward_joins: usize,
+    join_retry_backoff: Option<(Duration, Duration)>,
+    max_messages_per_poll: Option<usize>,
+    dedup_window: Duration,
+    max_broadcasts_per_origin: Option<u64>,
+    metrics: Option<MetricBuilder>,
+    metric_labels: Vec<(String, String)>,
+    histogram_buckets: NodeHistogramBuckets,
+    rng_seed: Option<[u8; 32]>,
+    membership_exporter: Option<(ArcMembershipExporter, Duration)>,
+    adaptive: bool,
+    lan_discovery: Option<LanDiscoveryOptions>,
+    static_members: Option<Vec<NodeId>>,
+    initial_passive_view: Option<Vec<u8>>,
+    observer: bool,
+    topology_awareness: Option<TopologyAwareness>,
+    zone: Option<String>,
+    min_cross_zone_links: usize,
 }
 impl NodeBuilder {
     /// Makes a new `NodeBuilder` instance with the default settings.
     pub fn new() -> Self {
         let params = Parameters {
             tick_interval: Duration::from_millis(200),
+            tick_interval_multiplier: 1.0,
             hyparview_shuffle_interval: Duration::from_secs(300),
             hyparview_sync_active_view_interval: Duration::from_secs(60),
             hyparview_fill_active_view_interval: Duration::from_secs(30),
+            jitter: JitterPolicy::default(),
         };
         NodeBuilder {
             logger: Logger::root(Discard, o!()),
             hyparview_options: HyparviewNodeOptions::default(),
             plumtree_options: PlumtreeNodeOptions::default(),
+            ihave_timeouts: None,
             params,
+            event_log_capacity: 0,
+            max_inbound_queue_len: None,
+            delivery_watermarks: None,
+            overload_policy: None,
+            message_cache_budget: None,
+            clock: None,
+            identity_file: None,
+            disconnect_stale_identities: false,
+            quarantine_failure_threshold: 3,
+            quarantine_duration: Duration::from_secs(0),
+            max_joins_per_tick: None,
+            max_forward_joins_per_tick: None,
+            max_deferred_forward_joins: 1024,
+            join_retry_backoff: None,
+            max_messages_per_poll: None,
+            dedup_window: Duration::from_secs(60),
+            max_broadcasts_per_origin: None,
+            metrics: None,
+            metric_labels: Vec::new(),
+            histogram_buckets: NodeHistogramBuckets::default(),
+            rng_seed: None,
+            membership_exporter: None,
+            adaptive: false,
+            lan_discovery: None,
+            static_members: None,
+            initial_passive_view: None,
+            observer: false,
+            topology_awareness: None,
+            zone: None,
+            min_cross_zone_links: 1,
         }
     }
 
@@ -61,6 +157,37 @@ impl NodeBuilder {
         self
     }
 
+    /// Sets the metrics settings of the node.
+    ///
+    /// By default, the settings specified by `ServiceBuilder::metrics()` are used.
+    pub fn metrics(&mut self, metrics: MetricBuilder) -> &mut Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Adds a label that will be attached to all the metrics of the node.
+    ///
+    /// This is useful for distinguishing the metrics of the nodes that belong to the same process
+    /// (e.g., `builder.metric_label("topic", "foo")`).
+    ///
+    /// Note that `name` must be a valid Prometheus label name that differs from
+    /// the other labels and the ones used by plumcast itself (`kind` and `le`),
+    /// otherwise [`try_finish`] returns an `ErrorKind::InvalidInput` error.
+    ///
+    /// [`try_finish`]: #method.try_finish
+    pub fn metric_label(&mut self, name: &str, value: &str) -> &mut Self {
+        self.metric_labels.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Sets the bucket settings of the histogram metrics of the node.
+    ///
+    /// The default value is `NodeHistogramBuckets::default()`.
+    pub fn histogram_buckets(&mut self, buckets: NodeHistogramBuckets) -> &mut Self {
+        self.histogram_buckets = buckets;
+        self
+    }
+
     /// Sets the unit of the node local [`Clock`].
     ///
     /// The default value is `Duration::from_millis(200)`.
@@ -69,6 +196,22 @@ impl NodeBuilder {
         self
     }
 
+    /// Makes the node driven by the ticks of the given clock instead of its own timer.
+    ///
+    /// In this case, the tick interval (and its multiplier) of the node is ignored,
+    /// and the clock of the node advances by the elapsed time given to each tick.
+    /// See the [`clock`] module for more details.
+    ///
+    /// By default, the node is driven by the shared clock of the service if any
+    /// (see [`ServiceBuilder::shared_tick_interval`]), otherwise by its own timer.
+    ///
+    /// [`clock`]: ../clock/index.html
+    /// [`ServiceBuilder::shared_tick_interval`]: ../service/struct.ServiceBuilder.html#method.shared_tick_interval
+    pub fn clock(&mut self, clock: clock::Clock) -> &mut Self {
+        self.clock = Some(clock);
+        self
+    }
+
     /// Sets the execution interval of `HyparviewNode::shuffle_passive_view()` method.
     ///
     /// The default value is `Duration::from_secs(300)`.
@@ -93,6 +236,14 @@ impl NodeBuilder {
         self
     }
 
+    /// Sets the jitter policy applied to the execution intervals of the periodic HyParView operations.
+    ///
+    /// The default value is `JitterPolicy::PositivePercent(10)`.
+    pub fn jitter_policy(&mut self, policy: JitterPolicy) -> &mut Self {
+        self.params.jitter = policy;
+        self
+    }
+
     /// Sets the options for the underlying HyParView node.
     ///
     /// The default value is `HyparviewNodeOptions::default()`.
@@ -109,42 +260,772 @@ impl NodeBuilder {
         self
     }
 
+    /// Sets the `IHAVE` timeouts for realtime and non-realtime `IHAVE` messages respectively.
+    ///
+    /// When a node receives an `IHAVE` message for an unknown message,
+    /// it waits for the timeout before requesting the message by sending a `GRAFT` message.
+    /// Each `IHAVE` message carries the `realtime` flag set by the Plumtree layer of the sender.
+    /// A shorter `realtime` timeout makes the messages announced in realtime repaired faster,
+    /// while a longer `non_realtime` timeout avoids needless `GRAFT` messages for the others.
+    ///
+    /// `non_realtime` overrides the `ihave_timeout` of the options given by [`plumtree_options`].
+    /// If [`adaptive`] is enabled, both timeouts are scaled by the estimated cluster size.
+    ///
+    /// By default, both timeouts are the `ihave_timeout` of the Plumtree options.
+    ///
+    /// [`plumtree_options`]: #method.plumtree_options
+    /// [`adaptive`]: #method.adaptive
+    pub fn ihave_timeouts(&mut self, realtime: Duration, non_realtime: Duration) -> &mut Self {
+        self.ihave_timeouts = Some((realtime, non_realtime));
+        self
+    }
+
+    /// Sets the number of recent protocol events kept by the node for debugging.
+    ///
+    /// If an `ErrorKind::InconsistentState` error occurs in the node,
+    /// the kept events (sent/received messages, actions and view changes) are dumped to the logger.
+    /// They can also be taken at any time by [`Node::debug_dump`],
+    /// or remotely by [`ServiceHandle::debug_dump`] (via the admin RPC).
+    ///
+    /// The default value is `0` (i.e., no events are kept).
+    ///
+    /// [`Node::debug_dump`]: ./struct.Node.html#method.debug_dump
+    /// [`ServiceHandle::debug_dump`]: ../service/struct.ServiceHandle.html#method.debug_dump
+    pub fn event_log_capacity(&mut self, capacity: usize) -> &mut Self {
+        self.event_log_capacity = capacity;
+        self
+    }
+
+    /// Sets the maximum number of inbound Plumtree messages that can be queued for the node.
+    ///
+    /// If the queue is full, newly arrived Plumtree messages are discarded
+    /// (and counted by the `plumcast_node_errors_total { kind="inbound_queue_overflow" }` metric).
+    /// HyParView messages are always queued regardless of this limit.
+    ///
+    /// This is useful for nodes that may receive bursts of messages faster than they are polled.
+    /// The queue length is bounded to the given value instead of growing indefinitely.
+    ///
+    /// By default, the queue length is unlimited.
+    pub fn max_inbound_queue_len(&mut self, max: usize) -> &mut Self {
+        self.max_inbound_queue_len = Some(max);
+        self
+    }
+
+    /// Sets the high and low watermarks of the pending deliveries of the node.
+    ///
+    /// When the number of the pending deliveries (see [`Node::pending_deliveries`])
+    /// reaches `high`, the node is regarded as falling behind and
+    /// [`Node::poll_watermark`] yields `DeliveryWatermark::High`.
+    /// After that, once the number drops to `low` or below, it yields `DeliveryWatermark::Low`.
+    /// Applications can use these signals to shed load or pause broadcasting.
+    ///
+    /// `low` is clamped to `high` if it is greater than `high`.
+    ///
+    /// By default, no watermarks are set.
+    ///
+    /// [`Node::pending_deliveries`]: ./struct.Node.html#method.pending_deliveries
+    /// [`Node::poll_watermark`]: ./struct.Node.html#method.poll_watermark
+    pub fn delivery_watermarks(&mut self, high: usize, low: usize) -> &mut Self {
+        self.delivery_watermarks = Some((high, low.min(high)));
+        self
+    }
+
+    /// Sets the policy of the load shedding of the node under overload.
+    ///
+    /// When the number of the pending deliveries or the cached messages reaches
+    /// the threshold of the policy, the node is regarded as overloaded, and until the numbers drop
+    /// below the recovery level, the node sheds load as follows:
+    ///
+    /// - The gossips relayed to the eager push peers are replaced with `IHAVE` messages
+    ///   (i.e., the node behaves as if all the peers were lazy push peers).
+    ///   The gossips of high priority messages, the ones originated by the node and
+    ///   the ones sent in response to `GRAFT` messages are not affected.
+    /// - The HyParView shuffles are delayed.
+    ///
+    /// The transitions are reported by [`Node::poll_overload`].
+    /// Without this, overload usually manifests as flapping of the neighbors.
+    ///
+    /// By default, no load shedding is performed.
+    ///
+    /// [`Node::poll_overload`]: ./struct.Node.html#method.poll_overload
+    pub fn overload_policy(&mut self, policy: OverloadPolicy) -> &mut Self {
+        self.overload_policy = Some(policy);
+        self
+    }
+
+    /// Sets the memory budget of the message cache of the node in bytes.
+    ///
+    /// The size of a cached message is the encoded size of its payload.
+    /// If the total size of the cached messages exceeds the budget,
+    /// the node forgets the messages in the order they were cached
+    /// (and counts them by the `plumcast_node_cache_evicted_bytes_total` metric)
+    /// until the total size fits in the budget.
+    /// The most recent message is never evicted, even if it alone exceeds the budget.
+    ///
+    /// Note that evicted messages are no longer known to the node,
+    /// so calling `Node::forget_message` for them is counted as
+    /// a `forget_unknown_message` error.
+    ///
+    /// By default, the message cache is not bounded by bytes.
+    pub fn message_cache_budget(&mut self, bytes: u64) -> &mut Self {
+        self.message_cache_budget = Some(bytes);
+        self
+    }
+
+    /// Sets the number of consecutive failed HyParView `NEIGHBOR` requests
+    /// after which the destination node is quarantined.
+    ///
+    /// A request is regarded as failed if sending it fails or if the destination does not become
+    /// a neighbor until the next execution of `HyparviewNode::fill_active_view()` method.
+    ///
+    /// The default value is `3`.
+    pub fn quarantine_failure_threshold(&mut self, threshold: usize) -> &mut Self {
+        self.quarantine_failure_threshold = threshold;
+        self
+    }
+
+    /// Sets the period during which quarantined nodes are not promoted to the active view.
+    ///
+    /// While a node is quarantined, `NEIGHBOR` requests to it are suppressed
+    /// (and counted by the `plumcast_node_suppressed_neighbor_requests_total` metric).
+    /// This prevents clusters with frequent restarts from wasting ticks retrying long-dead nodes
+    /// remaining in the passive view.
+    /// Quarantined nodes are also evicted from the passive view (see [`Node::passive_view`]).
+    ///
+    /// The default value is `Duration::from_secs(0)` (i.e., no nodes are quarantined).
+    ///
+    /// [`Node::passive_view`]: ./struct.Node.html#method.passive_view
+    pub fn quarantine_duration(&mut self, duration: Duration) -> &mut Self {
+        self.quarantine_duration = duration;
+        self
+    }
+
+    /// Sets the maximum number of HyParView `JOIN` messages handled by the node per tick.
+    ///
+    /// The excess messages are dropped
+    /// (and counted by the `plumcast_node_rejected_joins_total` metric).
+    /// This protects a well-known contact node from being overwhelmed when a large number of
+    /// nodes restart at once, on the assumption that the joiners retry later
+    /// (see [`join_retry_backoff`]).
+    ///
+    /// By default, the number is unlimited.
+    ///
+    /// [`join_retry_backoff`]: #method.join_retry_backoff
+    pub fn max_joins_per_tick(&mut self, n: usize) -> &mut Self {
+        self.max_joins_per_tick = Some(n);
+        self
+    }
+
+    /// Sets the maximum number of HyParView `FORWARD_JOIN` messages sent by the node per tick.
+    ///
+    /// When a `JOIN` message arrives, the node sends `FORWARD_JOIN` messages to all its neighbors.
+    /// If this is specified, the excess messages are deferred to the subsequent ticks
+    /// (and counted by the `plumcast_node_deferred_forward_joins_total` metric),
+    /// which spreads the bursts caused by mass joins over time.
+    ///
+    /// By default, the number is unlimited.
+    ///
+    /// See also [`max_deferred_forward_joins`].
+    ///
+    /// [`max_deferred_forward_joins`]: #method.max_deferred_forward_joins
+    pub fn max_forward_joins_per_tick(&mut self, n: usize) -> &mut Self {
+        self.max_forward_joins_per_tick = Some(n);
+        self
+    }
+
+    /// Sets the maximum number of HyParView `FORWARD_JOIN` messages deferred by the node.
+    ///
+    /// If the deferred messages (see [`max_forward_joins_per_tick`]) reach this number,
+    /// the excess messages are dropped
+    /// (and counted by the `plumcast_node_dropped_forward_joins_total` metric).
+    ///
+    /// The default value is `1024`.
+    ///
+    /// [`max_forward_joins_per_tick`]: #method.max_forward_joins_per_tick
+    pub fn max_deferred_forward_joins(&mut self, n: usize) -> &mut Self {
+        self.max_deferred_forward_joins = n;
+        self
+    }
+
+    /// Makes the node resend HyParView `JOIN` messages while it is isolated after [`Node::join`].
+    ///
+    /// The first retry happens `min` after joining, and the delay is doubled
+    /// (up to `max`) at every retry. A random jitter of up to 50% is applied to each delay,
+    /// so that the nodes restarted at once do not retry in lockstep.
+    /// The retries stop once the node gets a neighbor (they are counted by
+    /// the `plumcast_node_join_retries_total` metric).
+    ///
+    /// By default, `JOIN` messages are not resent.
+    ///
+    /// [`Node::join`]: ./struct.Node.html#method.join
+    pub fn join_retry_backoff(&mut self, min: Duration, max: Duration) -> &mut Self {
+        self.join_retry_backoff = Some((min, cmp::max(min, max)));
+        self
+    }
+
+    /// Sets the maximum number of inbound RPC messages handled by a single call of `Node::poll()`.
+    ///
+    /// Under sustained inbound traffic, a poll of the node may keep handling messages
+    /// for a long time, which starves the other fibers sharing the same thread
+    /// (and delays the ticks of the node itself).
+    /// If the number of handled messages reaches this limit, the node yields
+    /// (i.e., notifies the current task and returns `Async::NotReady`),
+    /// and the remaining messages are handled by the next poll.
+    /// Such yields are counted by the `plumcast_node_exhausted_poll_budgets_total` metric.
+    ///
+    /// By default, the number is unlimited.
+    pub fn max_messages_per_poll(&mut self, n: usize) -> &mut Self {
+        self.max_messages_per_poll = Some(n);
+        self
+    }
+
+    /// Sets the window during which [`Node::broadcast_dedup`] suppresses identical payloads.
+    ///
+    /// The default value is `Duration::from_secs(60)`.
+    ///
+    /// [`Node::broadcast_dedup`]: ./struct.Node.html#method.broadcast_dedup
+    pub fn dedup_window(&mut self, window: Duration) -> &mut Self {
+        self.dedup_window = window;
+        self
+    }
+
+    /// Sets the maximum number of new messages per second that the node accepts from each origin.
+    ///
+    /// The gossips of the messages originated by a node that exceeds the limit are dropped
+    /// (i.e., they are neither delivered nor relayed) and counted by
+    /// the `plumcast_node_throttled_gossips_total` metric.
+    /// Since the node does not acknowledge the dropped messages, they may be received again
+    /// via the Plumtree lazy push (i.e., `IHAVE` and `GRAFT`) after the rate has gone down.
+    /// The origins exceeding the limit are reported by [`Node::poll_quota_exceeded`].
+    ///
+    /// This protects the cluster from a runaway or malicious publisher.
+    /// The messages broadcasted by the node itself are not limited.
+    ///
+    /// By default, the number is unlimited.
+    ///
+    /// [`Node::poll_quota_exceeded`]: ./struct.Node.html#method.poll_quota_exceeded
+    pub fn max_broadcasts_per_origin(&mut self, per_second: u64) -> &mut Self {
+        self.max_broadcasts_per_origin = Some(per_second);
+        self
+    }
+
+    /// Sets the seed of the random number generator used by the node.
+    ///
+    /// The generator is used to make the random decisions of HyParView (e.g., shuffling and
+    /// promoting nodes) and to apply the jitter to the execution intervals of the periodic operations.
+    /// Fixing the seed makes these decisions reproducible, which is useful for
+    /// test harnesses and simulators.
+    ///
+    /// By default, the seed is generated randomly
+    /// (by `rand::thread_rng()` if the `thread-rng` feature is enabled).
+    pub fn rng_seed(&mut self, seed: [u8; 32]) -> &mut Self {
+        self.rng_seed = Some(seed);
+        self
+    }
+
+    /// Sets the exporter of the cluster membership known by the node.
+    ///
+    /// The membership (i.e., the HyParView active and passive views) is exported every `interval`.
+    /// See the [`membership`] module for more details.
+    ///
+    /// By default, the membership is not exported.
+    ///
+    /// [`membership`]: ../membership/index.html
+    pub fn membership_exporter<E: ExportMembership>(
+        &mut self,
+        exporter: E,
+        interval: Duration,
+    ) -> &mut Self {
+        self.membership_exporter = Some((ArcMembershipExporter::new(exporter), interval));
+        self
+    }
+
+    /// Sets whether the node adapts its Plumtree settings to the estimated cluster size.
+    ///
+    /// If `true`, every time the estimate is updated (see [`Node::estimated_cluster_size`]),
+    /// the number of the eager push peers is adjusted to `ln(N) + 1` and
+    /// the `IHAVE` timeout given by [`plumtree_options`] is scaled by the expected depth
+    /// of the broadcast tree (`N` is the estimated cluster size).
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`Node::estimated_cluster_size`]: ./struct.Node.html#method.estimated_cluster_size
+    /// [`plumtree_options`]: #method.plumtree_options
+    pub fn adaptive(&mut self, adaptive: bool) -> &mut Self {
+        self.adaptive = adaptive;
+        self
+    }
+
+    /// Enables LAN discovery with the given options.
+    ///
+    /// The node announces itself via UDP multicast, and joins the cluster via
+    /// a discovered node whenever it has no neighbors.
+    /// See the [`discovery`] module for more details.
+    ///
+    /// By default, LAN discovery is disabled.
+    ///
+    /// [`discovery`]: ../discovery/index.html
+    pub fn lan_discovery(&mut self, options: LanDiscoveryOptions) -> &mut Self {
+        self.lan_discovery = Some(options);
+        self
+    }
+
+    /// Makes the node a member of a small fixed cluster consisting of the given nodes.
+    ///
+    /// In the static cluster mode, the HyParView passive view is seeded from `members`
+    /// (the node itself may be included) instead of joining a cluster,
+    /// and the periodic shuffles are disabled.
+    /// The active view is filled when the node starts and whenever a neighbor goes down
+    /// (the periodic filling only happens while the node is isolated),
+    /// which reduces the chatter among the nodes.
+    ///
+    /// By default, the node is not in the static cluster mode.
+    pub fn static_members(&mut self, members: Vec<NodeId>) -> &mut Self {
+        self.static_members = Some(members);
+        self
+    }
+
+    /// Seeds the HyParView passive view of the node with the given snapshot
+    /// taken by [`Node::passive_view_snapshot`] (e.g., before the process restarted).
+    ///
+    /// The node tries to fill its active view from the seeded nodes as soon as it is built,
+    /// so it can rejoin the cluster without calling [`Node::join`].
+    /// This avoids a crowd of restarted nodes flooding the contact node with `JOIN` messages.
+    /// It is still recommended to call `Node::join` if the node remains isolated
+    /// (e.g., all the nodes in the snapshot have gone).
+    ///
+    /// If the snapshot is invalid, the error is logged and the passive view is not seeded.
+    /// This is ignored in the static cluster mode (see [`static_members`]).
+    ///
+    /// By default, the passive view is initially empty.
+    ///
+    /// [`Node::passive_view_snapshot`]: ./struct.Node.html#method.passive_view_snapshot
+    /// [`Node::join`]: ./struct.Node.html#method.join
+    /// [`static_members`]: #method.static_members
+    pub fn initial_passive_view(&mut self, snapshot: &[u8]) -> &mut Self {
+        self.initial_passive_view = Some(snapshot.to_owned());
+        self
+    }
+
+    /// Makes the node an observer if `observer` is `true`.
+    ///
+    /// An observer receives broadcasted messages as a leaf of the broadcast trees,
+    /// but never relays them (nor broadcasts its own messages).
+    /// The role is advertised to the other nodes via HyParView `JOIN` and `NEIGHBOR` messages,
+    /// and they never keep an observer in their eager push peers
+    /// (i.e., an observer receives messages by sending `GRAFT` messages in response to `IHAVE`s).
+    /// This is suitable for monitoring dashboards and the like.
+    ///
+    /// The default value is `false`.
+    pub fn observer(&mut self, observer: bool) -> &mut Self {
+        self.observer = observer;
+        self
+    }
+
+    /// Enables topology awareness with the given options.
+    ///
+    /// The node measures the RTTs to its neighbors, and prefers low-latency neighbors
+    /// as its Plumtree eager push peers.
+    /// See the [`topology`] module for more details.
+    ///
+    /// By default, topology awareness is disabled.
+    ///
+    /// [`topology`]: ../topology/index.html
+    pub fn topology_awareness(&mut self, options: TopologyAwareness) -> &mut Self {
+        self.topology_awareness = Some(options);
+        self
+    }
+
+    /// Sets the locality label (e.g., an availability zone or a region) of the node.
+    ///
+    /// Zones are advertised to the other nodes via HyParView messages.
+    /// A node having a zone prefers the neighbors in the same zone:
+    /// surplus cross-zone neighbors are periodically replaced with other nodes
+    /// if there are known in-zone nodes, and the cross-zone neighbors become lazy push peers
+    /// except for the number specified by [`min_cross_zone_links`].
+    /// This reduces the traffic across zones while keeping the zones connected.
+    ///
+    /// Note that the zone must be at most 255 bytes, otherwise it is not advertised.
+    ///
+    /// By default, the node has no zone.
+    ///
+    /// [`min_cross_zone_links`]: #method.min_cross_zone_links
+    pub fn zone(&mut self, zone: &str) -> &mut Self {
+        self.zone = Some(zone.to_owned());
+        self
+    }
+
+    /// Sets the minimum number of the cross-zone neighbors kept by the node.
+    ///
+    /// This is only meaningful if [`zone`] is specified.
+    ///
+    /// The default value is `1`.
+    ///
+    /// [`zone`]: #method.zone
+    pub fn min_cross_zone_links(&mut self, n: usize) -> &mut Self {
+        self.min_cross_zone_links = n;
+        self
+    }
+
+    /// Sets the file in which the identity of the node is persisted.
+    ///
+    /// If the file has the identifier of a node having the same address as the given service
+    /// (i.e., the node was built with the file before restarting the process),
+    /// the new node reclaims the identifier instead of generating a new one.
+    /// Otherwise, a new identifier is generated and saved to the file.
+    /// This keeps the passive views of the other nodes valid across restarts,
+    /// instead of the cluster accumulating the identifiers of dead nodes.
+    ///
+    /// Note that a file must not be shared by the nodes running at the same time,
+    /// and the generator of the service should not generate the reclaimed identifiers for other nodes
+    /// (e.g., `RandomLocalNodeIdGenerator` is suitable but `SerialLocalNodeIdGenerator` is not).
+    /// If the file cannot be read or written, the error is logged and
+    /// the node is built as if no file was specified.
+    ///
+    /// By default, the identity is not persisted.
+    pub fn identity_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.identity_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets whether the node disconnects the stale identities of restarted nodes.
+    ///
+    /// If `true`, when a HyParView `JOIN` message arrives from a node,
+    /// the neighbors having the same address but different local identifiers as the node
+    /// are regarded as the previous incarnations of it, and disconnected immediately
+    /// (and counted by the `plumcast_node_stale_identities_total` metric).
+    /// This speeds up the recovery after process restarts
+    /// (e.g., with `UnixtimeLocalNodeIdGenerator`), instead of waiting for
+    /// sending messages to the stale identities to fail.
+    ///
+    /// Note that this must be enabled only if every service in the cluster has a single node,
+    /// because the nodes sharing a service also share the same address.
+    ///
+    /// The default value is `false`.
+    pub fn disconnect_stale_identities(&mut self, enabled: bool) -> &mut Self {
+        self.disconnect_stale_identities = enabled;
+        self
+    }
+
     /// Builds a [`Node`] instance with the specified settings.
     ///
+    /// # Panics
+    ///
+    /// This panics if the settings are inconsistent (see [`try_finish`]).
+    ///
     /// [`Node`]: ./struct.Node.html
+    /// [`try_finish`]: #method.try_finish
     pub fn finish<M: MessagePayload>(&self, service: ServiceHandle<M>) -> Node<M> {
-        let id = service.generate_node_id();
+        self.try_finish(service)
+            .unwrap_or_else(|e| panic!("Invalid node settings: {}", e))
+    }
+
+    /// Builds a [`Node`] instance with the specified settings.
+    ///
+    /// If the settings are inconsistent (e.g., the tick interval is zero or longer than
+    /// the HyParView intervals, or the maximum active view size is zero),
+    /// an `ErrorKind::InvalidInput` error describing the problem is returned.
+    ///
+    /// [`Node`]: ./struct.Node.html
+    pub fn try_finish<M: MessagePayload>(&self, service: ServiceHandle<M>) -> Result<Node<M>> {
+        track!(self.validate())?;
+        let id = self
+            .identity_file
+            .as_ref()
+            .and_then(|path| self.load_identity(path, &service))
+            .unwrap_or_else(|| service.generate_node_id());
         let logger = self.logger.new(o! {"node_id" => id.to_string()});
-        let metrics = NodeMetrics::new(service.metric_builder());
+        if let Some(ref path) = self.identity_file {
+            if let Err(e) = track!(node_id::save_node_id(path, id)) {
+                error!(logger, "Cannot save the node identity to {:?}: {}", path, e);
+            }
+        }
+        let metrics = NodeMetrics::new(
+            service.metrics_factory(self.metrics.clone(), self.metric_labels.clone()),
+            &self.histogram_buckets,
+        );
         let (message_tx, message_rx) = mpsc::channel();
+        let inbound_queue_len = Arc::new(AtomicUsize::new(0));
+        let lease = Lease::new();
+        let status = Arc::new(AtomicImmut::new(None));
+        let event_log = EventLog::new(self.event_log_capacity);
         let handle = NodeHandle {
             local_id: id.local_id(),
             message_tx,
+            inbound_queue_len: Arc::clone(&inbound_queue_len),
+            max_inbound_queue_len: self.max_inbound_queue_len,
             metrics: metrics.clone(),
+            lease: lease.clone(),
+            status: Arc::clone(&status),
+            event_log: event_log.clone(),
+            observer: self.observer,
+            zone: self.zone.clone(),
         };
-        let rng = StdRng::from_seed(rand::thread_rng().gen());
-        service.register_local_node(handle);
+        let seed = self.rng_seed.unwrap_or_else(random::random_seed);
+        let mut rng = StdRng::from_seed(seed);
+        let hyparview_rng = StdRng::from_seed(rng.gen());
+        track!(service.register_local_node(handle))?;
 
-        let plumtree_node = PlumtreeNode::with_options(id, self.plumtree_options.clone());
+        let lan_discovery = self.lan_discovery.clone().and_then(|options| {
+            LanDiscovery::new(logger.clone(), options, id)
+                .map_err(|e| error!(logger, "Cannot enable LAN discovery: {}", e))
+                .ok()
+        });
+        let uses_service_clock = self.clock.is_none() && service.clock().is_some();
+        let ticker = match self.clock.as_ref().or_else(|| service.clock()) {
+            None => Ticker::Timer(timer::timeout(self.params.tick_interval())),
+            Some(clock) => Ticker::Clock(clock.subscribe()),
+        };
+        let mut plumtree_options = self.plumtree_options.clone();
+        if let Some((_, non_realtime)) = self.ihave_timeouts {
+            plumtree_options.ihave_timeout = non_realtime;
+        }
+        let plumtree_node = PlumtreeNode::with_options(id, plumtree_options.clone());
         let now = plumtree_node.clock().now();
-        let hyparview_shuffle_time = now + gen_interval(self.params.hyparview_shuffle_interval);
-        let hyparview_sync_active_view_time =
-            now + gen_interval(self.params.hyparview_sync_active_view_interval);
-        let hyparview_fill_active_view_time =
-            now + gen_interval(self.params.hyparview_fill_active_view_interval);
-        Node {
+        let hyparview_shuffle_time = now + self.params.gen_hyparview_shuffle_interval(&mut rng);
+        let hyparview_sync_active_view_time = now
+            + self
+                .params
+                .gen_hyparview_sync_active_view_interval(&mut rng);
+        let hyparview_fill_active_view_time = now
+            + self
+                .params
+                .gen_hyparview_fill_active_view_interval(&mut rng);
+        let mut node = Node {
             logger,
             service,
+            service_down: false,
             message_rx,
-            hyparview_node: HyparviewNode::with_options(id, rng, self.hyparview_options.clone()),
+            inbound_queue_len,
+            max_inbound_queue_len: self.max_inbound_queue_len,
+            hyparview_node: HyparviewNode::with_options(
+                id,
+                hyparview_rng,
+                self.hyparview_options.clone(),
+            ),
             plumtree_node,
             message_seqno: 0,
             hyparview_shuffle_time,
             hyparview_sync_active_view_time,
             hyparview_fill_active_view_time,
-            tick_timeout: timer::timeout(self.params.tick_interval),
+            ticker,
+            uses_service_clock,
+            ticks: 0,
+            status,
             params: self.params.clone(),
             metrics,
+            event_log,
+            pending_confirmations: HashMap::new(),
+            quarantine: Quarantine::new(
+                self.quarantine_failure_threshold,
+                self.quarantine_duration,
+            ),
+            max_joins_per_tick: self.max_joins_per_tick,
+            joins_in_tick: 0,
+            max_messages_per_poll: self.max_messages_per_poll,
+            max_forward_joins_per_tick: self.max_forward_joins_per_tick,
+            max_deferred_forward_joins: self.max_deferred_forward_joins,
+            forward_joins_in_tick: 0,
+            deferred_forward_joins: VecDeque::new(),
+            join_retry_backoff: self.join_retry_backoff,
+            join_retry: None,
+            content_hashes: RecentContentHashes::new(self.dedup_window),
+            broadcast_quotas: self.max_broadcasts_per_origin.map(BroadcastQuotas::new),
+            quota_exceeded_origins: VecDeque::new(),
+            periodic_broadcasts: Vec::new(),
+            periodic_broadcast_seqno: 0,
+            subscribers: Subscribers::default(),
+            rng,
+            lease,
+            membership_export_time: now,
+            membership_exporter: self.membership_exporter.clone(),
+            cluster_size_estimator: ClusterSizeEstimator::new(id),
+            adaptive_base_options: if self.adaptive {
+                Some(plumtree_options)
+            } else {
+                None
+            },
+            realtime_ihave_timeout: self.ihave_timeouts.map(|(realtime, _)| realtime),
+            known_messages: HashMap::new(),
+            message_id_policy: None,
+            delivery_filter: None,
+            relay_policy: None,
+            shuffle_policy: None,
+            rejected_messages: RecentMessageIds::default(),
+            retracted_messages: RecentMessageIds::default(),
+            delivered_messages: RecentMessageIds::default(),
+            cached_messages: 0,
+            graft_requests: HashMap::new(),
+            local_messages: VecDeque::new(),
+            held_delivery: None,
+            disconnect_stale_identities: self.disconnect_stale_identities,
+            send_errors: VecDeque::new(),
+            down_causes: HashMap::new(),
+            left_neighbors: VecDeque::new(),
+            lan_discovery,
+            static_mode: self.static_members.is_some(),
+            observer: self.observer,
+            topology_awareness: self.topology_awareness.clone(),
+            rtts: RttTable::default(),
+            rtt_probe_time: now,
+            zone: self.zone.clone(),
+            min_cross_zone_links: self.min_cross_zone_links,
+            delivery_watermarks: self.delivery_watermarks,
+            overload_policy: self.overload_policy,
+            cache_budget: self.message_cache_budget.map(CacheBudget::new),
+            falling_behind: false,
+            watermark_event: None,
+            overloaded: false,
+            overload_event: None,
+        };
+        if let Some(ref members) = self.static_members {
+            node.seed_static_members(members);
+        } else if let Some(ref snapshot) = self.initial_passive_view {
+            match track!(membership::decode_view_snapshot(snapshot)) {
+                Err(e) => error!(node.logger, "Cannot seed the passive view: {}", e),
+                Ok(nodes) => node.seed_passive_view(nodes),
+            }
+        }
+        Ok(node)
+    }
+}
+impl NodeBuilder {
+    fn validate(&self) -> Result<()> {
+        track!(self.intervals().validate())?;
+        let max_active_view_size = usize::from(self.hyparview_options.max_active_view_size);
+        track_assert!(
+            max_active_view_size >= 1,
+            ErrorKind::InvalidInput,
+            "The maximum active view size must be at least 1"
+        );
+        if self.zone.is_some() {
+            track_assert!(
+                self.min_cross_zone_links <= max_active_view_size,
+                ErrorKind::InvalidInput,
+                "The minimum cross-zone links {} exceed the maximum active view size {}",
+                self.min_cross_zone_links,
+                max_active_view_size
+            );
+        }
+        if let Some(ref options) = self.topology_awareness {
+            track_assert_ne!(
+                options.probe_interval,
+                Duration::from_secs(0),
+                ErrorKind::InvalidInput,
+                "The RTT probe interval must be positive"
+            );
+            track_assert!(
+                options.eager_push_degree >= 1,
+                ErrorKind::InvalidInput,
+                "The eager push degree must be at least 1"
+            );
+        }
+        for (i, (name, _)) in self.metric_labels.iter().enumerate() {
+            track!(validate_metric_label_name(name))?;
+            track_assert!(
+                self.metric_labels[..i].iter().all(|(n, _)| n != name),
+                ErrorKind::InvalidInput,
+                "Duplicate metric label: {:?}",
+                name
+            );
+        }
+        if let Some(len) = self.max_inbound_queue_len {
+            track_assert!(
+                len >= 1,
+                ErrorKind::InvalidInput,
+                "The maximum inbound queue length must be at least 1"
+            );
+        }
+        if let Some(ref policy) = self.overload_policy {
+            track_assert!(
+                (1..=100).contains(&policy.recovery_percent),
+                ErrorKind::InvalidInput,
+                "The recovery percent of the overload policy out of bounds: {}",
+                policy.recovery_percent
+            );
+        }
+        if let Some(budget) = self.message_cache_budget {
+            track_assert!(
+                budget >= 1,
+                ErrorKind::InvalidInput,
+                "The message cache budget must be positive"
+            );
+        }
+        if let Some(n) = self.max_joins_per_tick {
+            track_assert!(
+                n >= 1,
+                ErrorKind::InvalidInput,
+                "The maximum JOIN messages per tick must be at least 1"
+            );
+        }
+        if let Some(n) = self.max_messages_per_poll {
+            track_assert!(
+                n >= 1,
+                ErrorKind::InvalidInput,
+                "The maximum messages per poll must be at least 1"
+            );
+        }
+        if let Some(n) = self.max_broadcasts_per_origin {
+            track_assert!(
+                n >= 1,
+                ErrorKind::InvalidInput,
+                "The maximum broadcasts per origin must be at least 1"
+            );
+        }
+        if let Some(n) = self.max_forward_joins_per_tick {
+            track_assert!(
+                n >= 1,
+                ErrorKind::InvalidInput,
+                "The maximum FORWARD_JOIN messages per tick must be at least 1"
+            );
+        }
+        if let Some((min, _)) = self.join_retry_backoff {
+            track_assert_ne!(
+                min,
+                Duration::from_secs(0),
+                ErrorKind::InvalidInput,
+                "The minimum join retry delay must be positive"
+            );
+        }
+        Ok(())
+    }
+
+    fn intervals(&self) -> Intervals {
+        Intervals {
+            tick_interval: self.params.tick_interval,
+            hyparview_shuffle_interval: self.params.hyparview_shuffle_interval,
+            hyparview_sync_active_view_interval: self.params.hyparview_sync_active_view_interval,
+            hyparview_fill_active_view_interval: self.params.hyparview_fill_active_view_interval,
+        }
+    }
+
+    fn load_identity<M: MessagePayload>(
+        &self,
+        path: &Path,
+        service: &ServiceHandle<M>,
+    ) -> Option<NodeId> {
+        match track!(node_id::load_node_id(path)) {
+            Err(e) => {
+                error!(
+                    self.logger,
+                    "Cannot load the node identity from {:?}: {}", path, e
+                );
+                None
+            }
+            Ok(None) => None,
+            Ok(Some(id)) if id.address() != service.rpc_server_addr() => {
+                warn!(
+                    self.logger,
+                    "Ignores the persisted node identity {} because the address has changed to {}",
+                    id,
+                    service.rpc_server_addr()
+                );
+                None
+            }
+            Ok(Some(id)) => {
+                info!(self.logger, "Reclaims the persisted node identity: {}", id);
+                Some(id)
+            }
         }
     }
 }
@@ -155,21 +1036,131 @@ impl Default for NodeBuilder {
 }
 
 /// Node that broadcasts and receives messages.
+///
+/// # Dedicated execution
+///
+/// A `Node` is an ordinary [`Stream`], so it runs on whichever executor polls it.
+/// If a process hosts a few busy nodes together with many idle ones,
+/// the busy nodes can be pinned to a dedicated executor by [`Node::spawn_on`]
+/// so that they don't compete with the other nodes for the threads of the shared pool
+/// (the `dedicated` group of the `node` benchmark compares the two settings).
+///
+/// ```no_run
+/// use fibers::{Executor, Spawn, ThreadPoolExecutor};
+/// use futures::{Future, Stream};
+/// use plumcast::node::{NodeBuilder, SerialLocalNodeIdGenerator};
+/// use plumcast::service::Service;
+///
+/// let shared = ThreadPoolExecutor::new().unwrap();
+/// let service = Service::<Vec<u8>>::new(
+///     "127.0.0.1:4000".parse().unwrap(),
+///     shared.handle(),
+///     SerialLocalNodeIdGenerator::new(),
+/// );
+///
+/// // The busy node absorbs bursts up to 65536 inbound messages.
+/// let busy = NodeBuilder::new()
+///     .max_inbound_queue_len(65536)
+///     .finish(service.handle());
+///
+/// // The busy node is executed by its own thread.
+/// let (tx, rx) = std::sync::mpsc::channel();
+/// std::thread::spawn(move || {
+///     let dedicated = ThreadPoolExecutor::with_thread_count(1).unwrap();
+///     tx.send(dedicated.handle()).unwrap();
+///     dedicated.run()
+/// });
+/// let busy = busy.spawn_on(&rx.recv().unwrap(), 1024);
+///
+/// shared.spawn(service.map_err(|e| panic!("{}", e)));
+/// shared.spawn(busy.for_each(|_| Ok(())).map_err(|e| panic!("{}", e)));
+/// shared.run().unwrap();
+/// ```
+///
+/// [`Node::spawn_on`]: ./struct.Node.html#method.spawn_on
+///
+/// [`Stream`]: https://docs.rs/futures/0.1/futures/stream/trait.Stream.html
 #[derive(Debug)]
 #[must_use = "streams do nothing unless polled"]
 pub struct Node<M: MessagePayload> {
     logger: Logger,
     service: ServiceHandle<M>,
+    service_down: bool,
     message_rx: mpsc::Receiver<RpcMessage<M>>,
+    inbound_queue_len: Arc<AtomicUsize>,
+    max_inbound_queue_len: Option<usize>,
     hyparview_node: HyparviewNode,
     plumtree_node: PlumtreeNode<M>,
     message_seqno: u64,
     hyparview_shuffle_time: NodeTime,
     hyparview_sync_active_view_time: NodeTime,
     hyparview_fill_active_view_time: NodeTime,
-    tick_timeout: Timeout,
+    ticker: Ticker,
+    uses_service_clock: bool,
+    ticks: u64,
+    status: Arc<AtomicImmut<Option<NodeStatus>>>,
     params: Parameters,
     metrics: NodeMetrics,
+    event_log: EventLog,
+    pending_confirmations: HashMap<MessageId, PendingConfirmation>,
+    quarantine: Quarantine,
+    max_joins_per_tick: Option<usize>,
+    joins_in_tick: usize,
+    max_messages_per_poll: Option<usize>,
+    max_forward_joins_per_tick: Option<usize>,
+    max_deferred_forward_joins: usize,
+    forward_joins_in_tick: usize,
+    deferred_forward_joins: VecDeque<(NodeId, HyparviewMessage)>,
+    join_retry_backoff: Option<(Duration, Duration)>,
+    join_retry: Option<JoinRetry>,
+    content_hashes: RecentContentHashes,
+    broadcast_quotas: Option<BroadcastQuotas>,
+    quota_exceeded_origins: VecDeque<NodeId>,
+    periodic_broadcasts: Vec<PeriodicBroadcast<M>>,
+    periodic_broadcast_seqno: u64,
+    subscribers: Subscribers<M>,
+    rng: StdRng,
+    lease: Lease,
+    membership_export_time: NodeTime,
+    membership_exporter: Option<(ArcMembershipExporter, Duration)>,
+    cluster_size_estimator: ClusterSizeEstimator,
+    adaptive_base_options: Option<PlumtreeNodeOptions>,
+    realtime_ihave_timeout: Option<Duration>,
+    known_messages: HashMap<MessageId, KnownMessage>,
+    message_id_policy: Option<BoxMessageIdPolicy<M>>,
+    delivery_filter: Option<BoxDeliveryFilter<M>>,
+    relay_policy: Option<BoxRelayPolicy<M>>,
+    shuffle_policy: Option<BoxShufflePolicy>,
+    rejected_messages: RecentMessageIds,
+    retracted_messages: RecentMessageIds,
+    delivered_messages: RecentMessageIds,
+
+    // NOTE: This is tracked independently of the metrics, which may be disabled.
+    cached_messages: u64,
+    graft_requests: HashMap<MessageId, GraftRequests>,
+    local_messages: VecDeque<Message<M>>,
+
+    // NOTE: The message that became deliverable while the delivery was paused (see `Forward`).
+    held_delivery: Option<Message<M>>,
+    disconnect_stale_identities: bool,
+    send_errors: VecDeque<Error>,
+    down_causes: HashMap<NodeId, DownCause>,
+    left_neighbors: VecDeque<NeighborLeft>,
+    lan_discovery: Option<LanDiscovery>,
+    static_mode: bool,
+    observer: bool,
+    topology_awareness: Option<TopologyAwareness>,
+    rtts: RttTable,
+    rtt_probe_time: NodeTime,
+    zone: Option<String>,
+    min_cross_zone_links: usize,
+    delivery_watermarks: Option<(usize, usize)>,
+    overload_policy: Option<OverloadPolicy>,
+    cache_budget: Option<CacheBudget>,
+    falling_behind: bool,
+    watermark_event: Option<DeliveryWatermark>,
+    overloaded: bool,
+    overload_event: Option<OverloadEvent>,
 }
 impl<M: MessagePayload> Node<M> {
     /// Makes a new `Node` instance with the default settings.
@@ -187,291 +1178,4256 @@ impl<M: MessagePayload> Node<M> {
     }
 
     /// Joins the cluster to which the given contact node belongs.
+    ///
+    /// See [`NodeBuilder::join_retry_backoff`] for retrying to join.
+    ///
+    /// [`NodeBuilder::join_retry_backoff`]: ./struct.NodeBuilder.html#method.join_retry_backoff
     pub fn join(&mut self, contact_node: NodeId) {
+        let contact_node = self.service.normalize_node_id(contact_node);
         info!(
             self.logger,
             "Joins a cluster by contacting to {:?}", contact_node
         );
+        self.event_log
+            .record("join", Some(contact_node), String::new);
         self.hyparview_node.join(contact_node);
+        self.join_retry = None;
+        if self.join_retry_backoff.is_some() {
+            let retry_time = self.plumtree_node.clock().now() + self.join_retry_delay(0);
+            self.join_retry = Some(JoinRetry {
+                contact_node,
+                attempts: 0,
+                retry_time,
+            });
+        }
     }
 
     /// Broadcasts a message.
     ///
     /// Note that the message will also be delivered to the sender node.
     pub fn broadcast(&mut self, message_payload: M) -> MessageId {
-        let id = MessageId::new(self.id(), self.message_seqno);
-        self.message_seqno += 1;
-        debug!(self.logger, "Starts broadcasting a message: {:?}", id);
+        self.broadcast_envelope(Envelope::new(message_payload))
+    }
 
-        let m = PlumtreeAppMessage {
-            id,
-            payload: message_payload,
-        };
+    /// Broadcasts the given messages in order.
+    ///
+    /// The messages are assigned consecutive sequence numbers and handed to Plumtree in one pass.
+    /// This is cheaper than calling [`broadcast`] for each message
+    /// (e.g., only one log record and one event are recorded for the whole batch),
+    /// so it is suitable for producers emitting a large number of small messages.
+    ///
+    /// Note that the messages will also be delivered to the sender node.
+    ///
+    /// [`broadcast`]: #method.broadcast
+    pub fn broadcast_batch(&mut self, message_payloads: Vec<M>) -> Vec<MessageId> {
+        if message_payloads.is_empty() {
+            return Vec::new();
+        }
+
+        let ids = message_payloads
+            .into_iter()
+            .map(|payload| self.start_broadcast(Envelope::new(payload)).0)
+            .collect::<Vec<_>>();
+        let first = ids[0];
+        let count = ids.len();
+        debug!(
+            self.logger,
+            "Started broadcasting {} messages: first={:?}", count, first
+        );
+        self.event_log.record("broadcast_batch", None, || {
+            format!("first={:?}, count={}", first, count)
+        });
+        ids
+    }
+
+    /// Broadcasts a message that expires at the given deadline.
+    ///
+    /// The deadline is carried with the message.
+    /// After the deadline has passed, nodes stop relaying the message to their neighbors and
+    /// the message is no longer delivered to the applications.
+    /// `IHAVE` messages for it are not sent either, so no nodes request (i.e., `GRAFT`)
+    /// the message after the deadline, and stale messages stop consuming bandwidth.
+    ///
+    /// Note that the deadline is compared with the wall clock of each node,
+    /// so the clocks of the nodes in a cluster should be roughly synchronized.
+    pub fn broadcast_with_deadline(
+        &mut self,
+        message_payload: M,
+        deadline: SystemTime,
+    ) -> MessageId {
+        let mut envelope = Envelope::new(message_payload);
+        envelope.deadline = Some(deadline);
+        self.broadcast_envelope(envelope)
+    }
+
+    /// Broadcasts a message that propagates at most `ttl` hops from this node.
+    ///
+    /// The hop limit is carried with the message next to the Plumtree gossip round
+    /// (i.e., the number of hops the message has traveled so far).
+    /// Nodes stop relaying the message (and sending `IHAVE` messages for it)
+    /// once the round reaches the limit, so the message is only delivered to the nodes
+    /// within `ttl` hops in the broadcast tree.
+    /// This is useful for proximity-scoped announcements in very large overlays.
+    ///
+    /// Note that the message will also be delivered to the sender node, even if `ttl` is `0`.
+    pub fn broadcast_with_ttl(&mut self, message_payload: M, ttl: u16) -> MessageId {
+        let mut envelope = Envelope::new(message_payload);
+        envelope.hop_limit = Some(ttl);
+        self.broadcast_envelope(envelope)
+    }
+
+    /// Broadcasts a message unless the same payload has been seen recently.
+    ///
+    /// The payload is hashed (by its encoded bytes), and if a message having the same hash
+    /// has been broadcasted by this method or delivered to this node within
+    /// the window specified by [`NodeBuilder::dedup_window`], the message is not broadcasted
+    /// and `None` is returned (counted by
+    /// the `plumcast_node_deduplicated_broadcasts_total` metric).
+    ///
+    /// The hash is also carried in the gossip and `IHAVE` frames of the message,
+    /// so the other nodes that have recently delivered the same payload do not fetch the message
+    /// in response to `IHAVE` messages.
+    /// This is useful for workloads re-announcing identical payloads frequently.
+    ///
+    /// Note that the message will also be delivered to the sender node.
+    ///
+    /// [`NodeBuilder::dedup_window`]: ./struct.NodeBuilder.html#method.dedup_window
+    pub fn broadcast_dedup(&mut self, message_payload: M) -> Option<MessageId> {
+        let hash = content_hash(&message_payload)?;
+        let now = self.plumtree_node.clock().now();
+        if self.content_hashes.contains(hash, now) {
+            debug!(
+                self.logger,
+                "Suppressed broadcasting a duplicate payload: hash={:016x}", hash
+            );
+            self.metrics.deduplicated_broadcasts.increment();
+            return None;
+        }
+        self.content_hashes.insert(hash, now);
+
+        let mut envelope = Envelope::new(message_payload);
+        envelope.content_hash = Some(hash);
+        Some(self.broadcast_envelope(envelope))
+    }
+
+    /// Broadcasts a message with high priority.
+    ///
+    /// Gossip messages carrying the message (including the ones sent to repair
+    /// the broadcast tree in response to Plumtree `GRAFT` messages) are sent with
+    /// elevated RPC priority and are never dropped due to the length of the send queues,
+    /// so they are not stuck behind bulk traffic.
+    pub fn broadcast_with_high_priority(&mut self, message_payload: M) -> MessageId {
+        let mut envelope = Envelope::new(message_payload);
+        envelope.high_priority = true;
+        self.broadcast_envelope(envelope)
+    }
+
+    /// Broadcasts a tombstone that redacts the message identified by `message_id`.
+    ///
+    /// The tombstone is broadcasted as an ordinary message carrying `message_payload`
+    /// (e.g., the reason of the redaction), and each node that receives it retracts
+    /// the redacted message in the same way as [`cancel_broadcast`]:
+    ///
+    /// - If the redacted message has not been delivered to the node yet, the delivery is suppressed
+    ///   (and the tombstone itself is not delivered either).
+    /// - Otherwise, the tombstone is delivered to the application, so that it can withdraw
+    ///   the redacted message. [`Message::tombstone_of`] returns the identifier of the redacted message.
+    ///
+    /// Unlike [`cancel_broadcast`], any node can broadcast tombstones.
+    /// Note that only the identifiers of the most recently delivered messages are remembered
+    /// for deciding whether to deliver tombstones.
+    ///
+    /// [`cancel_broadcast`]: #method.cancel_broadcast
+    /// [`Message::tombstone_of`]: ../message/struct.Message.html#method.tombstone_of
+    pub fn broadcast_tombstone(&mut self, message_id: MessageId, message_payload: M) -> MessageId {
+        let mut envelope = Envelope::new(message_payload);
+        envelope.tombstone_of = Some(message_id);
+        self.broadcast_envelope(envelope)
+    }
+
+    /// Broadcasts a message and returns a future that notifies when the message has left the node.
+    ///
+    /// The returned future resolves once the message has been pushed to all the eager push peers
+    /// of the node at the time of this call.
+    /// Note that this is only a local signal: it does not mean that the message has been delivered
+    /// to the peers (or to any other nodes).
+    ///
+    /// If the message is not broadcasted because the node already knows a message having
+    /// the same identifier (see [`set_message_id_policy`]), the future resolves with `0` immediately.
+    ///
+    /// See [`BroadcastConfirmation`] for more details.
+    ///
+    /// [`BroadcastConfirmation`]: ./struct.BroadcastConfirmation.html
+    /// [`set_message_id_policy`]: #method.set_message_id_policy
+    pub fn broadcast_with_confirmation(
+        &mut self,
+        message_payload: M,
+    ) -> (MessageId, BroadcastConfirmation) {
+        let peers = self.plumtree_node.eager_push_peers().clone();
+        let (tx, rx) = oneshot::channel();
+        let (id, started) = self.try_broadcast_envelope(Envelope::new(message_payload));
+        if !started || peers.is_empty() {
+            let _ = tx.send(0);
+        } else {
+            let pending = PendingConfirmation {
+                peers,
+                pushed: 0,
+                tx,
+            };
+            self.pending_confirmations.insert(id, pending);
+        }
+        (id, BroadcastConfirmation(rx))
+    }
+
+    /// Rebroadcasts the given message without changing its identifier.
+    ///
+    /// This is intended to be used by gateways bridging two clusters:
+    /// a message delivered by a node in one cluster can be forwarded to the other cluster
+    /// by rebroadcasting it from a node in the latter.
+    /// Because the identifier is preserved, nodes that already know the message regard
+    /// the rebroadcasted one as a duplicate, so it is not delivered twice to them.
+    ///
+    /// Unlike [`broadcast`], the message is not delivered to this node.
+    /// Note that the message still needs to be forgot by calling [`forget_message`].
+    ///
+    /// Returns `false` if the node already knows the message (in which case nothing happens).
+    ///
+    /// [`broadcast`]: #method.broadcast
+    /// [`forget_message`]: #method.forget_message
+    pub fn rebroadcast(&mut self, message: Message<M>) -> bool {
+        let mut m = message.into_inner();
+        if self.known_messages.contains_key(&m.id) {
+            debug!(self.logger, "The message is already known: {:?}", m.id);
+            return false;
+        }
+        debug!(self.logger, "Starts rebroadcasting a message: {:?}", m.id);
+        self.event_log
+            .record("rebroadcast", None, || format!("{:?}", m.id));
+
+        m.payload.received_from = None;
+        m.payload.duplicates = 0;
+        if m.payload.trace.is_traced() {
+            m.payload.trace = TraceContext::hop(m.payload.trace.trace_id, self.id());
+        }
+        let mut known = KnownMessage::new(&m.payload);
+        known.rebroadcasted = true;
+        self.known_messages.insert(m.id, known);
         self.plumtree_node.broadcast_message(m);
-        self.metrics.broadcasted_messages.increment();
+        true
+    }
+
+    /// Broadcasts a payload generated by `payload_fn` every `interval`
+    /// (e.g., a heartbeat of the application).
+    ///
+    /// The broadcasts are driven by the ticks of the node, so no timer is needed per schedule.
+    /// The first broadcast happens `interval` after this call, and the broadcasts
+    /// missed because of late ticks are skipped instead of being made in a burst.
+    /// Note that `interval` is effectively rounded up to a multiple of the tick interval.
+    ///
+    /// As with [`broadcast`], the messages are also delivered to this node.
+    ///
+    /// The returned identifier can be passed to [`cancel_periodic_broadcast`].
+    ///
+    /// [`broadcast`]: #method.broadcast
+    /// [`cancel_periodic_broadcast`]: #method.cancel_periodic_broadcast
+    pub fn schedule_periodic_broadcast<F>(
+        &mut self,
+        interval: Duration,
+        payload_fn: F,
+    ) -> PeriodicBroadcastId
+    where
+        F: FnMut() -> M + Send + 'static,
+    {
+        let id = PeriodicBroadcastId(self.periodic_broadcast_seqno);
+        self.periodic_broadcast_seqno += 1;
+        let next_time = self.plumtree_node.clock().now() + interval;
+        self.periodic_broadcasts.push(PeriodicBroadcast {
+            id,
+            interval,
+            next_time,
+            payload_fn: Box::new(payload_fn),
+        });
         id
     }
 
+    /// Cancels the periodic broadcast scheduled by [`schedule_periodic_broadcast`].
+    ///
+    /// Returns `false` if there is no such schedule.
+    ///
+    /// [`schedule_periodic_broadcast`]: #method.schedule_periodic_broadcast
+    pub fn cancel_periodic_broadcast(&mut self, id: PeriodicBroadcastId) -> bool {
+        let len = self.periodic_broadcasts.len();
+        self.periodic_broadcasts.retain(|b| b.id != id);
+        self.periodic_broadcasts.len() != len
+    }
+
+    fn run_periodic_broadcasts(&mut self, now: NodeTime) {
+        for i in 0..self.periodic_broadcasts.len() {
+            if now < self.periodic_broadcasts[i].next_time {
+                continue;
+            }
+            let payload = {
+                let b = &mut self.periodic_broadcasts[i];
+                b.next_time = now + b.interval;
+                (b.payload_fn)()
+            };
+            self.broadcast(payload);
+        }
+    }
+
+    fn broadcast_envelope(&mut self, envelope: Envelope<M>) -> MessageId {
+        self.try_broadcast_envelope(envelope).0
+    }
+
+    fn try_broadcast_envelope(&mut self, envelope: Envelope<M>) -> (MessageId, bool) {
+        let (id, started) = self.start_broadcast(envelope);
+        if started {
+            debug!(self.logger, "Started broadcasting a message: {:?}", id);
+            self.event_log
+                .record("broadcast", None, || format!("{:?}", id));
+        }
+        (id, started)
+    }
+
+    // Returns `false` as the second element if the message is already known
+    // (i.e., the `MessageIdPolicy` derived the identifier of a message that has not been forgot).
+    fn start_broadcast(&mut self, mut envelope: Envelope<M>) -> (MessageId, bool) {
+        let node = self.id();
+        let seqno = self.message_seqno;
+        self.message_seqno += 1;
+        let id = match self.message_id_policy {
+            None => MessageId::new(node, seqno),
+            Some(ref mut policy) => {
+                let id = policy.message_id(node, seqno, &envelope.payload);
+                debug_assert_eq!(
+                    id.node(),
+                    node,
+                    "MessageIdPolicy must not change the node identifier part"
+                );
+                id
+            }
+        };
+        if self.known_messages.contains_key(&id) {
+            debug!(
+                self.logger,
+                "The message has already been broadcasted: {:?}", id
+            );
+            return (id, false);
+        }
+
+        envelope.trace = TraceContext::root(self.id());
+        trace::on_broadcast(&id, envelope.trace);
+
+        self.known_messages.insert(id, KnownMessage::new(&envelope));
+        let m = PlumtreeAppMessage {
+            id,
+            payload: envelope,
+        };
+        self.plumtree_node.broadcast_message(m);
+        self.metrics.broadcasted_messages.increment();
+        (id, true)
+    }
+
+    /// Sets the policy used to decide the identifiers of the messages broadcasted by the node.
+    ///
+    /// If a policy derives the same identifier for a message that the node still knows
+    /// (i.e., that has not been forgot yet), broadcasting it is a no-op.
+    /// This makes rebroadcasting the same content idempotent.
+    /// See [`MessageIdPolicy`] for more details.
+    ///
+    /// By default, `MessageId::new(node.id(), seqno)` is used where `seqno` is
+    /// the number of the messages broadcasted by the node so far.
+    ///
+    /// [`MessageIdPolicy`]: ../message/trait.MessageIdPolicy.html
+    pub fn set_message_id_policy<P: MessageIdPolicy<M>>(&mut self, policy: P) {
+        self.message_id_policy = Some(BoxMessageIdPolicy::new(policy));
+    }
+
+    /// Sets the filter invoked for each message before it is delivered to the application.
+    ///
+    /// The messages rejected by the filter are not yielded by the node
+    /// (and counted by the `plumcast_node_filtered_messages_total` metric).
+    /// See [`DeliveryFilter`] for more details.
+    ///
+    /// By default, all messages are delivered.
+    ///
+    /// [`DeliveryFilter`]: ../message/trait.DeliveryFilter.html
+    pub fn set_delivery_filter<F: DeliveryFilter<M>>(&mut self, filter: F) {
+        self.delivery_filter = Some(BoxDeliveryFilter::new(filter));
+    }
+
+    /// Sets the policy used to validate the received messages before relaying them.
+    ///
+    /// The messages rejected by the policy are counted by
+    /// the `plumcast_node_rejected_messages_total` metric.
+    /// See [`RelayPolicy`] for more details.
+    ///
+    /// By default, all messages are relayed.
+    ///
+    /// [`RelayPolicy`]: ../message/trait.RelayPolicy.html
+    pub fn set_relay_policy<P: RelayPolicy<M>>(&mut self, policy: P) {
+        self.relay_policy = Some(BoxRelayPolicy::new(policy));
+    }
+
+    /// Sets the policy used to adjust the samples of nodes exchanged by HyParView shuffles.
+    ///
+    /// See [`ShufflePolicy`] for more details.
+    ///
+    /// By default, the samples randomly selected by HyParView are used as is.
+    ///
+    /// [`ShufflePolicy`]: ../shuffle/trait.ShufflePolicy.html
+    pub fn set_shuffle_policy<P: ShufflePolicy>(&mut self, policy: P) {
+        self.shuffle_policy = Some(BoxShufflePolicy::new(policy));
+    }
+
     /// Forgets the specified message.
     ///
     /// For preventing memory shortage, this method needs to be called appropriately.
     pub fn forget_message(&mut self, message_id: &MessageId) {
+        let known = self.known_messages.remove(message_id);
+        if let Some(ref mut budget) = self.cache_budget {
+            budget.remove(message_id);
+        }
         if self.plumtree_node.forget_message(message_id) {
-            self.metrics.forgot_messages.increment();
+            self.count_forgot_message(known.as_ref());
         } else {
             self.metrics.forget_unknown_message_errors.increment();
         }
     }
 
-    /// Returns a reference to the underlying HyParView node.
-    pub fn hyparview_node(&self) -> &HyparviewNode {
-        &self.hyparview_node
+    /// Cancels the broadcast of the specified message originated by this node.
+    ///
+    /// The message is forgot by this node, so it is no longer eagerly pushed nor advertised
+    /// via `IHAVE` messages.
+    /// In addition, a retraction of the message is propagated to the cluster,
+    /// and the nodes that receive it also stop relaying the message and serving `GRAFT` requests for it.
+    /// Note that the applications to which the message has already been delivered are not notified,
+    /// and the retracted message does not need to be forgot by calling [`forget_message`].
+    ///
+    /// Returns `false` if the message was not broadcasted by this node or has already been cancelled.
+    ///
+    /// [`forget_message`]: #method.forget_message
+    pub fn cancel_broadcast(&mut self, message_id: &MessageId) -> bool {
+        if message_id.node() != self.id() || self.retracted_messages.contains(message_id) {
+            return false;
+        }
+        info!(self.logger, "Cancels a broadcast: {:?}", message_id);
+        self.event_log
+            .record("cancel_broadcast", None, || format!("{:?}", message_id));
+        self.retract_message(*message_id, None);
+        true
+    }
+
+    /// Returns the number of the duplicate gossip and `IHAVE` messages received so far
+    /// for the specified message.
+    ///
+    /// Unlike `Message::duplicates()`, this includes the duplicates received after the delivery.
+    /// If the message is unknown (or has been forgot), `None` is returned.
+    pub fn duplicates(&self, message_id: &MessageId) -> Option<usize> {
+        self.known_messages.get(message_id).map(|k| k.duplicates)
+    }
+
+    /// Updates a runtime parameter of the node.
+    ///
+    /// If the value of `update` is out of the bounds, an `ErrorKind::InvalidInput` error is returned
+    /// and the parameter is left unchanged.
+    /// Every successful update is logged (at the `INFO` level) for auditing purposes.
+    ///
+    /// See [`admin`] module for more details.
+    ///
+    /// [`admin`]: ../admin/index.html
+    pub fn update_parameter(&mut self, update: ParameterUpdate) -> Result<()> {
+        track!(update.validate())?;
+        match update {
+            ParameterUpdate::TickIntervalMultiplier(m) => {
+                self.params.tick_interval_multiplier = m;
+                self.reset_tick_timer();
+            }
+            ParameterUpdate::HyparviewShuffleInterval(d) => {
+                self.params.hyparview_shuffle_interval = d;
+                let now = self.plumtree_node.clock().now();
+                self.hyparview_shuffle_time =
+                    now + self.params.gen_hyparview_shuffle_interval(&mut self.rng);
+            }
+        }
+        info!(self.logger, "Updated a parameter: {:?}", update);
+        self.event_log
+            .record("update_parameter", None, || format!("{:?}", update));
+        Ok(())
+    }
+
+    /// Returns the current execution intervals of the periodic operations of the node.
+    pub fn intervals(&self) -> Intervals {
+        Intervals {
+            tick_interval: self.params.tick_interval,
+            hyparview_shuffle_interval: self.params.hyparview_shuffle_interval,
+            hyparview_sync_active_view_interval: self.params.hyparview_sync_active_view_interval,
+            hyparview_fill_active_view_interval: self.params.hyparview_fill_active_view_interval,
+        }
+    }
+
+    /// Changes the execution intervals of the periodic operations of the node.
+    ///
+    /// The next execution times of the operations are recomputed from the current clock
+    /// (with the jitter policy of the node).
+    /// This enables long-lived nodes to switch between aggressive and quiet maintenance modes
+    /// without restarting.
+    ///
+    /// Note that the tick interval multiplier (see [`ParameterUpdate`]) is still applied
+    /// to the new tick interval.
+    ///
+    /// If any of the intervals is zero or the tick interval is longer than the other intervals,
+    /// an `ErrorKind::InvalidInput` error is returned and the intervals are left unchanged.
+    ///
+    /// [`ParameterUpdate`]: ../admin/enum.ParameterUpdate.html
+    pub fn reconfigure(&mut self, intervals: Intervals) -> Result<()> {
+        track!(intervals.validate())?;
+
+        self.params.tick_interval = intervals.tick_interval;
+        self.params.hyparview_shuffle_interval = intervals.hyparview_shuffle_interval;
+        self.params.hyparview_sync_active_view_interval =
+            intervals.hyparview_sync_active_view_interval;
+        self.params.hyparview_fill_active_view_interval =
+            intervals.hyparview_fill_active_view_interval;
+
+        let now = self.plumtree_node.clock().now();
+        self.reset_tick_timer();
+        self.hyparview_shuffle_time =
+            now + self.params.gen_hyparview_shuffle_interval(&mut self.rng);
+        self.hyparview_sync_active_view_time = now
+            + self
+                .params
+                .gen_hyparview_sync_active_view_interval(&mut self.rng);
+        self.hyparview_fill_active_view_time = now
+            + self
+                .params
+                .gen_hyparview_fill_active_view_interval(&mut self.rng);
+
+        info!(self.logger, "Reconfigured the intervals: {:?}", intervals);
+        self.event_log
+            .record("reconfigure", None, || format!("{:?}", intervals));
+        Ok(())
+    }
+
+    /// Moves the given neighbor to the eager push peers of the node.
+    ///
+    /// After this call, the node eagerly pushes the messages it broadcasts or relays to the peer
+    /// (the same as when the node receives a Plumtree `GRAFT` message from the peer).
+    ///
+    /// Returns `false` if the peer is not a neighbor (i.e., not in the HyParView active view).
+    pub fn promote_peer(&mut self, peer: NodeId) -> bool {
+        use plumtree::message::ProtocolMessage;
+
+        let peer = self.service.normalize_node_id(peer);
+        info!(self.logger, "Promotes {:?} to an eager push peer", peer);
+        self.event_log.record("promote", Some(peer), String::new);
+        let graft = GraftMessage {
+            sender: peer,
+            message_id: None,
+            round: 0,
+        };
+        self.plumtree_node
+            .handle_protocol_message(ProtocolMessage::Graft(graft))
+    }
+
+    /// Moves the given neighbor to the lazy push peers of the node.
+    ///
+    /// After this call, the node only sends `IHAVE` messages to the peer
+    /// (the same as when the node receives a Plumtree `PRUNE` message from the peer).
+    /// Note that the peer may be promoted again by Plumtree for repairing the broadcast tree.
+    ///
+    /// Returns `false` if the peer is not a neighbor (i.e., not in the HyParView active view).
+    pub fn prune_peer(&mut self, peer: NodeId) -> bool {
+        use plumtree::message::ProtocolMessage;
+
+        let peer = self.service.normalize_node_id(peer);
+        info!(self.logger, "Prunes {:?} to a lazy push peer", peer);
+        self.event_log.record("prune", Some(peer), String::new);
+        let prune = PruneMessage { sender: peer };
+        self.plumtree_node
+            .handle_protocol_message(ProtocolMessage::Prune(prune))
+    }
+
+    /// Disconnects the given neighbor.
+    ///
+    /// The peer is removed from the HyParView active view (and moved to the passive view),
+    /// and another node will be promoted to a neighbor by the periodic maintenance of the active view.
+    pub fn disconnect_peer(&mut self, peer: NodeId) {
+        let peer = self.service.normalize_node_id(peer);
+        info!(self.logger, "Disconnects {:?}", peer);
+        self.event_log.record("disconnect", Some(peer), String::new);
+        self.hyparview_node.disconnect(&peer, true);
+    }
+
+    /// Returns `true` if the node is an observer.
+    ///
+    /// See [`NodeBuilder::observer`] for more details.
+    ///
+    /// [`NodeBuilder::observer`]: ./struct.NodeBuilder.html#method.observer
+    pub fn is_observer(&self) -> bool {
+        self.observer
+    }
+
+    /// Returns the zone of the node.
+    ///
+    /// See [`NodeBuilder::zone`] for more details.
+    ///
+    /// [`NodeBuilder::zone`]: ./struct.NodeBuilder.html#method.zone
+    pub fn zone(&self) -> Option<&str> {
+        self.zone.as_ref().map(|z| z.as_str())
+    }
+
+    /// Returns the smoothed RTT to the given neighbor.
+    ///
+    /// RTTs are only measured if [`NodeBuilder::topology_awareness`] is enabled.
+    /// `None` is returned if the RTT has not been measured yet.
+    ///
+    /// [`NodeBuilder::topology_awareness`]: ./struct.NodeBuilder.html#method.topology_awareness
+    pub fn neighbor_rtt(&self, peer: &NodeId) -> Option<Duration> {
+        self.rtts.get(peer)
+    }
+
+    /// Returns the estimated number of the nodes in the cluster (including this node).
+    ///
+    /// The estimate is derived from the nodes sampled by the HyParView shuffles,
+    /// so it is updated every shuffle interval and is only a rough approximation.
+    pub fn estimated_cluster_size(&self) -> usize {
+        let known_nodes = self.hyparview_node.active_view().len() + self.passive_view().len();
+        self.cluster_size_estimator.estimate(known_nodes)
+    }
+
+    /// Takes a snapshot of the HyParView views of the node as bytes.
+    ///
+    /// The snapshot contains the nodes in the active view as well as in the passive view,
+    /// and can be passed to [`NodeBuilder::initial_passive_view`] to warm start a new node
+    /// (it is typically saved to a file when the process shuts down).
+    ///
+    /// [`NodeBuilder::initial_passive_view`]: ./struct.NodeBuilder.html#method.initial_passive_view
+    pub fn passive_view_snapshot(&self) -> Vec<u8> {
+        let nodes = self
+            .hyparview_node
+            .active_view()
+            .iter()
+            .cloned()
+            .chain(self.passive_view())
+            .collect();
+        membership::encode_view_snapshot(nodes)
+    }
+
+    /// Returns the nodes in the HyParView passive view of the node.
+    ///
+    /// Quarantined nodes (see [`NodeBuilder::quarantine_duration`]) are evicted from the view
+    /// until their quarantine expires, so they are not included in the result.
+    /// [`hyparview_node`] returns the underlying view which may still contain them.
+    ///
+    /// [`NodeBuilder::quarantine_duration`]: ./struct.NodeBuilder.html#method.quarantine_duration
+    /// [`hyparview_node`]: #method.hyparview_node
+    pub fn passive_view(&self) -> Vec<NodeId> {
+        let now = self.plumtree_node.clock().now();
+        self.hyparview_node
+            .passive_view()
+            .iter()
+            .filter(|n| !self.quarantine.contains(n, now))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns a reference to the underlying HyParView node.
+    pub fn hyparview_node(&self) -> &HyparviewNode {
+        &self.hyparview_node
+    }
+
+    /// Returns a reference to the underlying Plumtree node.
+    pub fn plumtree_node(&self) -> &PlumtreeNode<M> {
+        &self.plumtree_node
+    }
+
+    /// Returns the clock of the node.
+    pub fn clock(&self) -> &Clock {
+        self.plumtree_node.clock()
+    }
+
+    /// Takes a snapshot of the status of the node.
+    ///
+    /// The snapshot taken at the last tick is also published to the service,
+    /// and can be fetched via [`ServiceHandle::local_node_statuses`] or
+    /// remotely via [`ServiceHandle::node_statuses`].
+    ///
+    /// [`ServiceHandle::local_node_statuses`]: ../service/struct.ServiceHandle.html#method.local_node_statuses
+    /// [`ServiceHandle::node_statuses`]: ../service/struct.ServiceHandle.html#method.node_statuses
+    pub fn status(&self) -> NodeStatus {
+        let mut eager_push_peers = self
+            .plumtree_node
+            .eager_push_peers()
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        eager_push_peers.sort();
+        let mut lazy_push_peers = self
+            .plumtree_node
+            .lazy_push_peers()
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        lazy_push_peers.sort();
+        let metrics = &self.metrics;
+        NodeStatus {
+            id: self.id(),
+            active_view: self.hyparview_node.active_view().to_vec(),
+            passive_view: self.passive_view(),
+            eager_push_peers,
+            lazy_push_peers,
+            ticks: self.ticks,
+            cached_messages: self.cached_messages(),
+            known_messages: self.known_messages.len() as u64,
+            pending_deliveries: self.pending_deliveries() as u64,
+            broadcasted_messages: metrics.broadcasted_messages(),
+            delivered_messages: metrics.delivered_messages(),
+            forgot_messages: metrics.forgot_messages(),
+            connected_neighbors: metrics.connected_neighbors(),
+            disconnected_neighbors: metrics.disconnected_neighbors(),
+        }
+    }
+
+    /// Returns the recent protocol events kept by the node (in the order they occurred).
+    ///
+    /// The number of the kept events is specified by [`NodeBuilder::event_log_capacity`],
+    /// and if it is `0` (the default), the result is always empty.
+    ///
+    /// The events can also be fetched via [`ServiceHandle::local_debug_dump`] or
+    /// remotely via [`ServiceHandle::debug_dump`].
+    ///
+    /// [`NodeBuilder::event_log_capacity`]: ./struct.NodeBuilder.html#method.event_log_capacity
+    /// [`ServiceHandle::local_debug_dump`]: ../service/struct.ServiceHandle.html#method.local_debug_dump
+    /// [`ServiceHandle::debug_dump`]: ../service/struct.ServiceHandle.html#method.debug_dump
+    pub fn debug_dump(&self) -> Vec<DebugEvent> {
+        self.event_log.debug_events(self.id())
+    }
+
+    /// Returns the metrics of the service.
+    pub fn metrics(&self) -> &NodeMetrics {
+        &self.metrics
+    }
+
+    /// Returns the number of the inbound RPC messages that have arrived at the node
+    /// but have not been handled yet.
+    ///
+    /// The messages are handled (and the application messages carried by them are delivered)
+    /// while the node is polled, so a growing number indicates that
+    /// the application is falling behind on processing the deliveries.
+    pub fn pending_deliveries(&self) -> usize {
+        self.inbound_queue_len.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the pending deliveries have reached the high watermark and
+    /// have not dropped to the low watermark yet.
+    ///
+    /// This always returns `false` if [`NodeBuilder::delivery_watermarks`] is not specified.
+    ///
+    /// [`NodeBuilder::delivery_watermarks`]: ./struct.NodeBuilder.html#method.delivery_watermarks
+    pub fn is_falling_behind(&self) -> bool {
+        self.falling_behind
+    }
+
+    /// Polls the crossing of the watermarks specified by [`NodeBuilder::delivery_watermarks`].
+    ///
+    /// `Async::Ready(DeliveryWatermark::High)` is returned once the pending deliveries reach
+    /// the high watermark, and `Async::Ready(DeliveryWatermark::Low)` is returned once they drop
+    /// to the low watermark after that.
+    /// Each crossing is reported only once; if the pending deliveries go back across
+    /// the watermarks before this method is called, nothing is reported.
+    ///
+    /// The watermarks are checked every time the node is polled as a `Stream`,
+    /// so this method should be called from the task driving the node (e.g., after each poll).
+    ///
+    /// [`NodeBuilder::delivery_watermarks`]: ./struct.NodeBuilder.html#method.delivery_watermarks
+    pub fn poll_watermark(&mut self) -> Async<DeliveryWatermark> {
+        match self.watermark_event.take() {
+            None => Async::NotReady,
+            Some(event) => Async::Ready(event),
+        }
+    }
+
+    /// Returns `true` if the node is overloaded (see [`NodeBuilder::overload_policy`]).
+    ///
+    /// [`NodeBuilder::overload_policy`]: ./struct.NodeBuilder.html#method.overload_policy
+    pub fn is_overloaded(&self) -> bool {
+        self.overloaded
+    }
+
+    /// Polls the transitions of the overload state of the node.
+    ///
+    /// `Async::Ready(OverloadEvent::Overloaded)` is returned once the node becomes overloaded,
+    /// and `Async::Ready(OverloadEvent::Recovered)` is returned once it recovers after that.
+    /// As with [`poll_watermark`], each transition is reported only once and
+    /// the state is checked every time the node is polled as a `Stream`.
+    ///
+    /// [`poll_watermark`]: #method.poll_watermark
+    pub fn poll_overload(&mut self) -> Async<OverloadEvent> {
+        match self.overload_event.take() {
+            None => Async::NotReady,
+            Some(event) => Async::Ready(event),
+        }
+    }
+
+    /// Makes a new stream that receives a clone of every message delivered to the node.
+    ///
+    /// The subscription buffers up to `capacity` messages and drops the oldest ones when full.
+    /// See [`Subscription`] for more details.
+    ///
+    /// [`Subscription`]: ../subscription/struct.Subscription.html
+    pub fn subscribe(&mut self, capacity: usize) -> Subscription<M> {
+        self.subscribers.subscribe(capacity)
+    }
+
+    /// Polls the errors occurred when sending messages to remote nodes.
+    ///
+    /// The node handles such errors by itself (e.g., by disconnecting the peer),
+    /// so they are not returned from the `Stream` implementation.
+    /// This method allows applications to observe them
+    /// (e.g., `ErrorKind::PeerUnreachable` tells the unreachable peer,
+    /// and `Error::is_transient()` tells whether retrying later may help).
+    ///
+    /// Only the most recent errors are kept (at most 64), and older ones are discarded.
+    pub fn poll_error(&mut self) -> Async<Error> {
+        match self.send_errors.pop_front() {
+            None => Async::NotReady,
+            Some(e) => Async::Ready(e),
+        }
+    }
+
+    /// Polls the neighbors that have left the HyParView active view of the node.
+    ///
+    /// [`NeighborLeft::graceful`] tells whether the neighbor has been disconnected gracefully
+    /// (e.g., it sent a `DISCONNECT` message) or due to a detected failure
+    /// (e.g., sending a message to it failed).
+    /// The former are also counted by the `plumcast_node_left_neighbors_total` metric
+    /// if the neighbor left the cluster, and the latter by
+    /// the `plumcast_node_failed_neighbors_total` metric.
+    ///
+    /// Only the most recent departures are kept (at most 64), and older ones are discarded.
+    ///
+    /// [`NeighborLeft::graceful`]: ./struct.NeighborLeft.html#structfield.graceful
+    pub fn poll_neighbor_left(&mut self) -> Async<NeighborLeft> {
+        match self.left_neighbors.pop_front() {
+            None => Async::NotReady,
+            Some(left) => Async::Ready(left),
+        }
+    }
+
+    /// Polls the origins which broadcasts exceeded the limit
+    /// (see [`NodeBuilder::max_broadcasts_per_origin`]).
+    ///
+    /// An origin is reported once per second at most while it keeps exceeding the limit.
+    ///
+    /// Only the most recent origins are kept (at most 64), and older ones are discarded.
+    ///
+    /// [`NodeBuilder::max_broadcasts_per_origin`]: ./struct.NodeBuilder.html#method.max_broadcasts_per_origin
+    pub fn poll_quota_exceeded(&mut self) -> Async<NodeId> {
+        match self.quota_exceeded_origins.pop_front() {
+            None => Async::NotReady,
+            Some(origin) => Async::Ready(origin),
+        }
+    }
+
+    /// Returns a future that drives the node and forwards the delivered messages to `sink`.
+    ///
+    /// The delivery is paused while the sink is not ready.
+    /// See [`Forward`] for more details.
+    ///
+    /// [`Forward`]: ../sink/struct.Forward.html
+    pub fn forward_to<S: ExternalSink<M>>(self, sink: S) -> Forward<M, S> {
+        Forward::new(self, sink)
+    }
+
+    /// Spawns a fiber driving the node onto `spawner` (e.g., a dedicated executor),
+    /// and returns the handle of the node.
+    ///
+    /// The delivered messages are passed to the handle through a channel that can hold
+    /// `message_buffer` messages, so a larger buffer absorbs larger bursts of deliveries.
+    /// See [`DedicatedNode`] and the "Dedicated execution" section of [`Node`] for more details.
+    ///
+    /// [`DedicatedNode`]: ../dedicated/struct.DedicatedNode.html
+    /// [`Node`]: ./struct.Node.html
+    pub fn spawn_on<S: Spawn>(self, spawner: &S, message_buffer: usize) -> DedicatedNode<M> {
+        DedicatedNode::spawn(self, spawner, message_buffer)
+    }
+
+    /// Returns a future that resolves with the next message delivered to the node.
+    ///
+    /// This is a convenience method for consumers that want to wait for a single message
+    /// without driving the node as a `Stream` by themselves.
+    ///
+    /// Note that the node handles protocol messages and timers only while it is polled
+    /// (via this future or the `Stream` implementation).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use futures::Future;
+    /// use plumcast::node::{Node, SerialLocalNodeIdGenerator};
+    /// use plumcast::service::Service;
+    ///
+    /// let service = Service::<String>::new(
+    ///     "127.0.0.1:3000".parse().unwrap(),
+    ///     fibers_global::handle(),
+    ///     SerialLocalNodeIdGenerator::new(),
+    /// );
+    /// let mut node = Node::new(service.handle());
+    /// fibers_global::spawn(service.map_err(|e| panic!("{}", e)));
+    ///
+    /// node.broadcast("hello".to_owned());
+    /// let message = node.next_message().wait().unwrap();
+    /// assert_eq!(message.map(|m| m.into_payload()), Some("hello".to_owned()));
+    /// ```
+    pub fn next_message(&mut self) -> impl Future<Item = Option<Message<M>>, Error = Error> + '_ {
+        futures::future::poll_fn(move || self.poll())
+    }
+
+    /// Returns a future that resolves with the next message delivered to the node
+    /// within the given timeout.
+    ///
+    /// Like [`next_message`], the future resolves with `None` if the node has finished
+    /// (i.e., the stream has ended).
+    /// If no message is delivered within the timeout, the future fails with
+    /// an error of the `ErrorKind::Timeout` kind (the node can be polled again after that).
+    ///
+    /// See [`next_message`] for more details.
+    ///
+    /// [`next_message`]: #method.next_message
+    pub fn recv_with_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> impl Future<Item = Option<Message<M>>, Error = Error> + '_ {
+        let mut timeout = timer::timeout(timeout);
+        futures::future::poll_fn(move || {
+            if let Async::Ready(message) = track!(self.poll())? {
+                return Ok(Async::Ready(message));
+            }
+            if track!(timeout.poll().map_err(Error::from))?.is_ready() {
+                track_panic!(ErrorKind::Timeout, "No message is delivered");
+            }
+            Ok(Async::NotReady)
+        })
+    }
+
+    fn handle_hyparview_action(&mut self, action: HyparviewAction) {
+        use hyparview::message::ProtocolMessage;
+        use hyparview::{Action, Event};
+
+        match action {
+            Action::Send {
+                destination,
+                mut message,
+            } => {
+                match message {
+                    ProtocolMessage::Shuffle(ref mut m) if m.origin == self.id() => {
+                        self.apply_shuffle_policy(destination, &mut m.nodes);
+                    }
+                    ProtocolMessage::ShuffleReply(ref mut m) => {
+                        self.apply_shuffle_policy(destination, &mut m.nodes);
+                    }
+                    _ => {}
+                }
+                let is_neighbor_request = match message {
+                    ProtocolMessage::Neighbor(_) => true,
+                    _ => false,
+                };
+                if let ProtocolMessage::ForwardJoin(_) = message {
+                    if let Some(limit) = self.max_forward_joins_per_tick {
+                        if self.forward_joins_in_tick >= limit {
+                            if self.deferred_forward_joins.len() >= self.max_deferred_forward_joins
+                            {
+                                debug!(
+                                    self.logger,
+                                    "Drops a FORWARD_JOIN message to {:?}", destination
+                                );
+                                self.metrics.dropped_forward_joins.increment();
+                                return;
+                            }
+                            debug!(
+                                self.logger,
+                                "Defers a FORWARD_JOIN message to {:?}", destination
+                            );
+                            self.metrics.deferred_forward_joins.increment();
+                            self.deferred_forward_joins
+                                .push_back((destination, message));
+                            return;
+                        }
+                        self.forward_joins_in_tick += 1;
+                    }
+                }
+                if is_neighbor_request {
+                    let now = self.plumtree_node.clock().now();
+                    if self.quarantine.is_quarantined(&destination, now) {
+                        debug!(
+                            self.logger,
+                            "Suppresses a NEIGHBOR request to the quarantined node {:?}",
+                            destination
+                        );
+                        self.metrics.suppressed_neighbor_requests.increment();
+                        return;
+                    }
+                    self.quarantine.handle_neighbor_request(destination);
+                }
+                debug!(
+                    self.logger,
+                    "Sends a HyParView message to {:?}: {:?}", destination, message
+                );
+                self.event_log
+                    .record("send_hyparview", Some(destination), || {
+                        format!("{:?}", message)
+                    });
+                let message = RpcMessage::Hyparview(message);
+                if let Err(e) = self.service.send_message(destination, message) {
+                    warn!(
+                        self.logger,
+                        "Cannot send a HyParView message to {:?}: {}", destination, e
+                    );
+                    self.metrics
+                        .cannot_send_hyparview_message_errors
+                        .increment();
+                    self.record_send_error(e);
+                    self.disconnect_failed(destination);
+                    if is_neighbor_request {
+                        let now = self.plumtree_node.clock().now();
+                        if self.quarantine.handle_failure(destination, now) {
+                            self.handle_quarantined(destination);
+                        }
+                    }
+                }
+            }
+            Action::Notify { event } => match event {
+                Event::NeighborUp { node } => {
+                    info!(
+                        self.logger,
+                        "Neighbor up: {:?} (active_view={:?})",
+                        node,
+                        self.hyparview_node.active_view()
+                    );
+                    self.event_log
+                        .record("neighbor_up", Some(node), String::new);
+                    self.metrics.connected_neighbors.increment();
+                    self.quarantine.handle_neighbor_up(&node);
+                    self.plumtree_node.handle_neighbor_up(&node);
+                    self.demote_observer(node);
+                    if self.hyparview_node.active_view().len() == 1 {
+                        self.metrics.deisolated_times.increment();
+                    }
+                    self.join_retry = None;
+                }
+                Event::NeighborDown { node } => {
+                    info!(
+                        self.logger,
+                        "Neighbor down: {:?} (active_view={:?})",
+                        node,
+                        self.hyparview_node.active_view()
+                    );
+                    let cause = self.down_causes.remove(&node);
+                    let graceful = cause != Some(DownCause::Failure);
+                    self.event_log.record("neighbor_down", Some(node), || {
+                        format!("graceful={}", graceful)
+                    });
+                    self.metrics.disconnected_neighbors.increment();
+                    match cause {
+                        Some(DownCause::Failure) => self.metrics.failed_neighbors.increment(),
+                        Some(DownCause::Leave) => self.metrics.left_neighbors.increment(),
+                        None => {}
+                    }
+                    self.record_neighbor_left(NeighborLeft { node, graceful });
+                    self.plumtree_node.handle_neighbor_down(&node);
+                    self.rtts.remove(&node);
+                    self.abandon_confirmations(&node);
+                    if self.hyparview_node.active_view().is_empty() {
+                        self.metrics.isolated_times.increment();
+                    }
+                    if self.static_mode {
+                        self.hyparview_node.fill_active_view();
+                    }
+                }
+            },
+            Action::Disconnect { node } => {
+                info!(self.logger, "Disconnected: {:?}", node);
+                self.event_log.record("disconnect", Some(node), String::new);
+            }
+        }
+    }
+
+    fn handle_plumtree_action(&mut self, action: PlumtreeAction<M>) -> Option<Message<M>> {
+        use plumtree::Action;
+
+        match action {
+            Action::Send {
+                destination,
+                mut message,
+            } => {
+                if self.observer {
+                    let relaying = match message {
+                        plumtree::message::ProtocolMessage::Gossip(_)
+                        | plumtree::message::ProtocolMessage::Ihave(_) => true,
+                        _ => false,
+                    };
+                    if relaying {
+                        debug!(
+                            self.logger,
+                            "Observer does not relay messages: destination={:?}", destination
+                        );
+                        return None;
+                    }
+                }
+                let mut gossip_id = None;
+                if let plumtree::message::ProtocolMessage::Gossip(ref m) = message {
+                    if self.retracted_messages.contains(&m.message.id) {
+                        self.confirm_push(&m.message.id, &destination, false);
+                        return None;
+                    }
+                    if m.message.payload.is_expired(SystemTime::now()) {
+                        debug!(
+                            self.logger,
+                            "Discards an expired gossip message to {:?}: {:?}",
+                            destination,
+                            m.message.id
+                        );
+                        self.metrics.expired_gossips.increment();
+                        self.confirm_push(&m.message.id, &destination, false);
+                        return None;
+                    }
+                    if m.message.payload.is_hop_limit_exceeded(m.round) {
+                        self.discard_hop_limited(&destination, &m.message.id);
+                        self.confirm_push(&m.message.id, &destination, false);
+                        return None;
+                    }
+                    gossip_id = Some(m.message.id);
+                }
+                if let plumtree::message::ProtocolMessage::Gossip(ref mut m) = message {
+                    self.prepare_repair(&destination, m);
+                }
+                if let Some(ihave) = self.shed_gossip(&message) {
+                    debug!(
+                        self.logger,
+                        "Sheds a gossip message to {:?}: {:?}", destination, ihave.message_id
+                    );
+                    self.metrics.shed_gossips.increment();
+                    message = plumtree::message::ProtocolMessage::Ihave(ihave);
+                    gossip_id = None;
+                }
+                if let plumtree::message::ProtocolMessage::Ihave(ref m) = message {
+                    if self.retracted_messages.contains(&m.message_id) {
+                        return None;
+                    }
+                    // NOTE: `IHAVE` messages for expired messages are not sent,
+                    // so that the receivers never send `GRAFT` messages for them.
+                    let limit = self
+                        .known_messages
+                        .get(&m.message_id)
+                        .and_then(|k| k.relay_limit);
+                    if let Some(limit) = limit {
+                        if limit.is_expired(SystemTime::now()) {
+                            debug!(
+                                self.logger,
+                                "Discards an IHAVE message for an expired message to {:?}: {:?}",
+                                destination,
+                                m.message_id
+                            );
+                            self.metrics.expired_gossips.increment();
+                            return None;
+                        }
+                        if limit.is_hop_limit_exceeded(m.round) {
+                            self.discard_hop_limited(&destination, &m.message_id);
+                            return None;
+                        }
+                    }
+                }
+                debug!(self.logger, "Sends a Plumtree message to {:?}", destination,);
+                self.event_log
+                    .record("send_plumtree", Some(destination), || {
+                        plumtree_message_summary(&message)
+                    });
+                let message = match message {
+                    plumtree::message::ProtocolMessage::Ihave(m) => {
+                        let hash = self
+                            .known_messages
+                            .get(&m.message_id)
+                            .and_then(|k| k.content_hash);
+                        if let Some(hash) = hash {
+                            RpcMessage::HashedIhave(m, hash)
+                        } else {
+                            RpcMessage::Plumtree(plumtree::message::ProtocolMessage::Ihave(m))
+                        }
+                    }
+                    m => RpcMessage::Plumtree(m),
+                };
+                if let Err(e) = self.service.send_message(destination, message) {
+                    warn!(
+                        self.logger,
+                        "Cannot send a Plumtree message to {:?}: {}", destination, e
+                    );
+                    self.metrics.cannot_send_plumtree_message_errors.increment();
+                    self.record_send_error(e);
+                    self.disconnect_failed(destination);
+                    if let Some(id) = gossip_id {
+                        self.confirm_push(&id, &destination, false);
+                    }
+                } else if let Some(id) = gossip_id {
+                    self.confirm_push(&id, &destination, true);
+                }
+                None
+            }
+            Action::Deliver { mut message } => {
+                let now = SystemTime::now();
+                if message.payload.is_expired(now) {
+                    debug!(
+                        self.logger,
+                        "Discards an expired application message: {:?}", message.id
+                    );
+                    self.metrics.expired_messages.increment();
+
+                    // NOTE: The application never sees this message, so it cannot forget it.
+                    self.known_messages.remove(&message.id);
+                    self.plumtree_node.forget_message(&message.id);
+                    return None;
+                }
+                if let Some(target) = message.payload.tombstone_of {
+                    if !self.apply_tombstone(target) {
+                        debug!(
+                            self.logger,
+                            "Suppressed the delivery of a redacted message: {:?}", target
+                        );
+
+                        // NOTE: The application never sees this tombstone, so it cannot forget it.
+                        self.known_messages.remove(&message.id);
+                        self.plumtree_node.forget_message(&message.id);
+                        return None;
+                    }
+                }
+                self.charge_cache_budget(message.id, &message.payload);
+                if self
+                    .known_messages
+                    .get(&message.id)
+                    .map_or(false, |k| k.rebroadcasted)
+                {
+                    debug!(
+                        self.logger,
+                        "Skips delivering a rebroadcasted message: {:?}", message.id
+                    );
+                    return None;
+                }
+                message.payload.duplicates = self
+                    .known_messages
+                    .get(&message.id)
+                    .map_or(0, |k| k.duplicates);
+                let message = Message::new(message);
+                if let Some(ref mut filter) = self.delivery_filter {
+                    if !filter.filter(&message) {
+                        debug!(
+                            self.logger,
+                            "Filters out an application message: {:?}",
+                            message.id()
+                        );
+                        self.metrics.filtered_messages.increment();
+                        self.cached_messages += 1;
+                        return None;
+                    }
+                }
+                let message = message.into_inner();
+                debug!(
+                    self.logger,
+                    "Delivers an application message: {:?}", message.id
+                );
+                self.event_log
+                    .record("deliver", None, || format!("{:?}", message.id));
+                trace::on_deliver(&message.id, message.payload.trace);
+                self.metrics.delivered_messages.increment();
+                self.cached_messages += 1;
+                self.delivered_messages.insert(message.id);
+                if let Some(hash) = message.payload.content_hash {
+                    let now = self.plumtree_node.clock().now();
+                    self.content_hashes.insert(hash, now);
+                }
+                if message.id.node() != self.id() {
+                    let latency = now
+                        .duration_since(message.payload.origin_time)
+                        .unwrap_or_else(|_| Duration::from_secs(0));
+                    self.metrics
+                        .delivery_latency
+                        .observe(duration_to_seconds(latency));
+                }
+                Some(Message::new(message))
+            }
+        }
+    }
+
+    fn discard_hop_limited(&self, destination: &NodeId, id: &MessageId) {
+        debug!(
+            self.logger,
+            "Discards a hop-limited Plumtree message to {:?}: {:?}", destination, id
+        );
+        self.metrics.hop_limited_gossips.increment();
+    }
+
+    fn confirm_push(&mut self, id: &MessageId, peer: &NodeId, pushed: bool) {
+        let completed = if let Some(pending) = self.pending_confirmations.get_mut(id) {
+            pending.confirm(peer, pushed)
+        } else {
+            return;
+        };
+        if completed {
+            if let Some(pending) = self.pending_confirmations.remove(id) {
+                pending.complete();
+            }
+        }
+    }
+
+    fn abandon_confirmations(&mut self, peer: &NodeId) {
+        if self.pending_confirmations.is_empty() {
+            return;
+        }
+        let ids = self
+            .pending_confirmations
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        for id in ids {
+            self.confirm_push(&id, peer, false);
+        }
+    }
+
+    fn handle_rpc_message(&mut self, message: RpcMessage<M>) -> bool {
+        let message = self.service.normalize_rpc_message(message);
+        match message {
+            RpcMessage::Hyparview(m) => {
+                debug!(self.logger, "Received a HyParView message: {:?}", m);
+                self.event_log
+                    .record("recv_hyparview", None, || format!("{:?}", m));
+                match m {
+                    hyparview::message::ProtocolMessage::Shuffle(ref m) => {
+                        self.cluster_size_estimator.observe(&m.nodes);
+                        self.cluster_size_estimator.observe(&[m.origin]);
+                    }
+                    hyparview::message::ProtocolMessage::ShuffleReply(ref m) => {
+                        self.cluster_size_estimator.observe(&m.nodes);
+                    }
+                    _ => {}
+                }
+                if let hyparview::message::ProtocolMessage::Join(ref m) = m {
+                    if !self.accept_join(m.sender) {
+                        return true;
+                    }
+                }
+                let sender = match m {
+                    hyparview::message::ProtocolMessage::Join(ref m) => {
+                        self.disconnect_stale_identities_of(m.sender);
+                        Some(m.sender)
+                    }
+                    hyparview::message::ProtocolMessage::Neighbor(ref m) => Some(m.sender),
+                    hyparview::message::ProtocolMessage::Disconnect(ref m) => {
+                        let sender = m.sender;
+                        if !m.alive && self.hyparview_node.active_view().contains(&sender) {
+                            self.down_causes.entry(sender).or_insert(DownCause::Leave);
+                        }
+                        None
+                    }
+                    _ => None,
+                };
+                self.hyparview_node.handle_protocol_message(m);
+                if let Some(sender) = sender {
+                    // NOTE: The role may have been unknown when the peer became a neighbor.
+                    self.demote_observer(sender);
+                }
+                true
+            }
+            RpcMessage::Plumtree(mut m) => {
+                debug!(self.logger, "Received a Plumtree message");
+                if !self.validate_plumtree_message(&m) {
+                    return false;
+                }
+                if let plumtree::message::ProtocolMessage::Ihave(ref i) = m {
+                    if let Some(k) = self.known_messages.get_mut(&i.message_id) {
+                        k.duplicates += 1;
+                    }
+                }
+                if let plumtree::message::ProtocolMessage::Gossip(ref g) = m {
+                    if !self.known_messages.contains_key(&g.message.id)
+                        && !self.acquire_broadcast_quota(g.message.id.node())
+                    {
+                        debug!(
+                            self.logger,
+                            "Drops a GOSSIP message exceeding the broadcast quota: {:?}",
+                            g.message.id
+                        );
+                        self.metrics.throttled_gossips.increment();
+                        return false;
+                    }
+                }
+                if let plumtree::message::ProtocolMessage::Gossip(ref mut g) = m {
+                    self.metrics.gossip_round.observe(f64::from(g.round));
+                    g.message.payload.received_from = Some((g.sender, g.round));
+                    if let Some(k) = self.known_messages.get_mut(&g.message.id) {
+                        k.duplicates += 1;
+                    } else {
+                        let k = KnownMessage::new(&g.message.payload);
+                        self.known_messages.insert(g.message.id, k);
+                    }
+                    if let Some(size) = g.message.payload.payload_size {
+                        self.metrics.payload_size.observe(size as f64);
+                    }
+
+                    // The message is relayed with the trace context of this node's hop.
+                    let received = g.message.payload.trace;
+                    if received.is_traced() {
+                        let local = TraceContext::hop(received.trace_id, self.id());
+                        trace::on_receive(&g.message.id, g.sender, g.round, received, local);
+                        g.message.payload.trace = local;
+                    }
+                }
+                self.event_log
+                    .record("recv_plumtree", None, || plumtree_message_summary(&m));
+                if let plumtree::message::ProtocolMessage::Graft(ref g) = m {
+                    if !self.accept_graft(g) {
+                        return false;
+                    }
+                }
+                let grafted_by = match m {
+                    plumtree::message::ProtocolMessage::Graft(ref g) => Some(g.sender),
+                    _ => None,
+                };
+                if !self.handle_plumtree_protocol_message(m) {
+                    self.metrics.unknown_plumtree_node_errors.increment();
+                }
+                if let Some(sender) = grafted_by {
+                    // The requested message has been sent, but the observer is not kept eager.
+                    self.demote_observer(sender);
+                }
+                false
+            }
+            RpcMessage::Admin(update) => {
+                if let Err(e) = self.update_parameter(update) {
+                    warn!(self.logger, "Rejected a parameter update: {}", e);
+                }
+                false
+            }
+            RpcMessage::Retract(m) => {
+                debug!(
+                    self.logger,
+                    "Received a retraction from {:?}: {:?}", m.sender, m.message_id
+                );
+                if !self.retracted_messages.contains(&m.message_id) {
+                    self.retract_message(m.message_id, Some(m.sender));
+                }
+                false
+            }
+            RpcMessage::HashedIhave(m, hash) => {
+                let now = self.plumtree_node.clock().now();
+                if !self.known_messages.contains_key(&m.message_id)
+                    && self.content_hashes.contains(hash, now)
+                {
+                    debug!(
+                        self.logger,
+                        "Ignores an IHAVE message for a duplicate payload: {:?}", m.message_id
+                    );
+                    self.metrics.deduplicated_ihaves.increment();
+                    return false;
+                }
+                let m = plumtree::message::ProtocolMessage::Ihave(m);
+                self.handle_rpc_message(RpcMessage::Plumtree(m))
+            }
+            RpcMessage::Local(m) => {
+                debug!(self.logger, "Received a local message: {:?}", m.id);
+                self.local_messages.push_back(Message::new(m));
+                true
+            }
+        }
+    }
+
+    fn handle_plumtree_protocol_message(&mut self, m: PlumtreeMessage<M>) -> bool {
+        let realtime_ihave_timeout = match m {
+            plumtree::message::ProtocolMessage::Ihave(ref i) if i.realtime => {
+                self.realtime_ihave_timeout()
+            }
+            _ => None,
+        };
+        let timeout = match realtime_ihave_timeout {
+            None => return self.plumtree_node.handle_protocol_message(m),
+            Some(timeout) => timeout,
+        };
+
+        // NOTE: The timeout is applied only while handling the realtime `IHAVE` message.
+        let ihave_timeout = self.plumtree_node.options_mut().ihave_timeout;
+        self.plumtree_node.options_mut().ihave_timeout = timeout;
+        let handled = self.plumtree_node.handle_protocol_message(m);
+        self.plumtree_node.options_mut().ihave_timeout = ihave_timeout;
+        handled
+    }
+
+    fn realtime_ihave_timeout(&self) -> Option<Duration> {
+        let timeout = self.realtime_ihave_timeout?;
+        if self.adaptive_base_options.is_some() {
+            let cluster_size = self.estimated_cluster_size();
+            Some(estimator::adaptive_ihave_timeout(timeout, cluster_size))
+        } else {
+            Some(timeout)
+        }
+    }
+
+    fn validate_plumtree_message(&mut self, m: &PlumtreeMessage<M>) -> bool {
+        use plumtree::message::ProtocolMessage;
+
+        let (id, gossip) = match m {
+            ProtocolMessage::Gossip(g) => (g.message.id, Some(g)),
+            ProtocolMessage::Ihave(i) => (i.message_id, None),
+            _ => return true,
+        };
+        if self.rejected_messages.contains(&id) {
+            debug!(self.logger, "Ignores a rejected message: {:?}", id);
+            return false;
+        }
+        if self.retracted_messages.contains(&id) {
+            debug!(self.logger, "Ignores a retracted message: {:?}", id);
+            return false;
+        }
+        let g = match gossip {
+            Some(g) if !self.known_messages.contains_key(&id) => g,
+            _ => return true,
+        };
+        let allowed = match self.relay_policy {
+            None => true,
+            Some(ref mut policy) => policy.allow_relay(g.sender, &id, &g.message.payload.payload),
+        };
+        if !allowed {
+            info!(
+                self.logger,
+                "Rejected a message received from {:?}: {:?}", g.sender, id
+            );
+            self.event_log
+                .record("reject", Some(g.sender), || format!("{:?}", id));
+            self.metrics.rejected_messages.increment();
+            self.rejected_messages.insert(id);
+        }
+        allowed
+    }
+
+    fn reset_tick_timer(&mut self) {
+        if let Ticker::Timer(ref mut timeout) = self.ticker {
+            *timeout = timer::timeout(self.params.tick_interval());
+        }
+    }
+
+    fn poll_tick(&mut self) -> Result<Option<Duration>> {
+        match self.ticker {
+            Ticker::Timer(ref mut timeout) => {
+                if track!(timeout.poll().map_err(Error::from))?.is_not_ready() {
+                    return Ok(None);
+                }
+                let interval = self.params.tick_interval();
+                *timeout = timer::timeout(interval);
+                Ok(Some(interval))
+            }
+            Ticker::Clock(ref mut rx) => match rx.poll().expect("Never fails") {
+                Async::NotReady => Ok(None),
+                Async::Ready(elapsed) => {
+                    let elapsed = track_assert_some!(elapsed, ErrorKind::Other, "Clock down");
+                    Ok(Some(elapsed))
+                }
+            },
+        }
+    }
+
+    fn handle_tick(&mut self, elapsed: Duration) {
+        self.plumtree_node.clock_mut().tick(elapsed);
+        self.ticks += 1;
+        self.joins_in_tick = 0;
+        self.forward_joins_in_tick = 0;
+        self.send_deferred_forward_joins();
+
+        let now = self.plumtree_node.clock().now();
+        self.retry_join(now);
+        self.run_periodic_broadcasts(now);
+        if now >= self.hyparview_shuffle_time && self.overloaded {
+            debug!(
+                self.logger,
+                "Delays a shuffle because the node is overloaded"
+            );
+            self.hyparview_shuffle_time =
+                now + self.params.gen_hyparview_shuffle_interval(&mut self.rng);
+        } else if now >= self.hyparview_shuffle_time {
+            self.cluster_size_estimator.rotate();
+            self.adapt_to_cluster_size();
+            if !self.static_mode {
+                self.hyparview_node.shuffle_passive_view();
+            }
+            self.hyparview_shuffle_time =
+                now + self.params.gen_hyparview_shuffle_interval(&mut self.rng);
+        }
+        if now >= self.hyparview_sync_active_view_time {
+            self.hyparview_node.sync_active_view();
+            self.hyparview_sync_active_view_time = now
+                + self
+                    .params
+                    .gen_hyparview_sync_active_view_interval(&mut self.rng);
+        }
+        if now >= self.hyparview_fill_active_view_time {
+            for node in self.hyparview_node.active_view() {
+                // NOTE: The node may have been a neighbor before the request was sent.
+                self.quarantine.handle_neighbor_up(node);
+            }
+            for node in self.quarantine.expire_pending_requests(now) {
+                self.handle_quarantined(node);
+            }
+            if self.zone.is_some() {
+                self.replace_cross_zone_neighbor();
+                let degree = self.eager_push_degree();
+                self.select_eager_push_peers(degree);
+            }
+            if !self.static_mode || self.hyparview_node.active_view().is_empty() {
+                self.hyparview_node.fill_active_view();
+            }
+            self.hyparview_fill_active_view_time = now
+                + self
+                    .params
+                    .gen_hyparview_fill_active_view_interval(&mut self.rng);
+        }
+        if now >= self.rtt_probe_time {
+            if let Some(options) = self.topology_awareness.clone() {
+                self.probe_neighbor_rtts();
+                let degree = self.eager_push_degree();
+                self.select_eager_push_peers(degree);
+                self.rtt_probe_time = now + options.probe_interval;
+            }
+        }
+
+        let status = self.status();
+        if now >= self.membership_export_time {
+            if let Some((exporter, interval)) = self.membership_exporter.clone() {
+                self.export_membership(&exporter, &status);
+                self.membership_export_time = now + interval;
+            }
+        }
+        self.status.store(Some(status));
+    }
+
+    fn accept_join(&mut self, joiner: NodeId) -> bool {
+        if let Some(limit) = self.max_joins_per_tick {
+            if self.joins_in_tick >= limit {
+                debug!(self.logger, "Drops a JOIN message from {:?}", joiner);
+                self.event_log
+                    .record("reject_join", Some(joiner), String::new);
+                self.metrics.rejected_joins.increment();
+                return false;
+            }
+            self.joins_in_tick += 1;
+        }
+        true
+    }
+
+    fn send_deferred_forward_joins(&mut self) {
+        let limit = match self.max_forward_joins_per_tick {
+            None => self.deferred_forward_joins.len(),
+            Some(n) => n,
+        };
+        while self.forward_joins_in_tick < limit {
+            let (destination, message) = match self.deferred_forward_joins.pop_front() {
+                None => break,
+                Some(x) => x,
+            };
+            self.handle_hyparview_action(hyparview::Action::Send {
+                destination,
+                message,
+            });
+        }
+    }
+
+    fn retry_join(&mut self, now: NodeTime) {
+        let (contact_node, attempts) = match self.join_retry {
+            Some(ref r) if now >= r.retry_time => (r.contact_node, r.attempts + 1),
+            _ => return,
+        };
+        if !self.hyparview_node.active_view().is_empty() {
+            self.join_retry = None;
+            return;
+        }
+        info!(
+            self.logger,
+            "Retries to join a cluster by contacting to {:?} (attempts={})", contact_node, attempts
+        );
+        self.event_log
+            .record("retry_join", Some(contact_node), || attempts.to_string());
+        self.metrics.join_retries.increment();
+        self.hyparview_node.join(contact_node);
+        let retry_time = now + self.join_retry_delay(attempts);
+        self.join_retry = Some(JoinRetry {
+            contact_node,
+            attempts,
+            retry_time,
+        });
+    }
+
+    fn join_retry_delay(&mut self, attempts: u32) -> Duration {
+        let (min, max) = self.join_retry_backoff.unwrap_or_default();
+        let delay = min
+            .checked_mul(1 << cmp::min(attempts, 16))
+            .map_or(max, |d| cmp::min(d, max));
+        JitterPolicy::Percent(50).apply(delay, &mut self.rng)
+    }
+
+    fn seed_static_members(&mut self, members: &[NodeId]) {
+        let id = self.id();
+        let nodes = members
+            .iter()
+            .map(|&n| self.service.normalize_node_id(n))
+            .filter(|&n| n != id)
+            .collect::<Vec<_>>();
+        info!(
+            self.logger,
+            "Starts as a static cluster member: {:?}", nodes
+        );
+        self.event_log
+            .record("static_members", None, || format!("{:?}", nodes));
+        self.add_to_passive_view(nodes);
+        self.hyparview_node.fill_active_view();
+    }
+
+    fn seed_passive_view(&mut self, nodes: Vec<NodeId>) {
+        let id = self.id();
+        let nodes = nodes
+            .into_iter()
+            .map(|n| self.service.normalize_node_id(n))
+            .filter(|&n| n != id)
+            .collect::<Vec<_>>();
+        info!(self.logger, "Seeds the passive view: {:?}", nodes);
+        self.event_log
+            .record("seed_passive_view", None, || format!("{:?}", nodes));
+        self.add_to_passive_view(nodes);
+        self.hyparview_node.fill_active_view();
+    }
+
+    fn add_to_passive_view(&mut self, nodes: Vec<NodeId>) {
+        use hyparview::message::ProtocolMessage;
+
+        // NOTE: HyParView nodes add the nodes contained in `SHUFFLE_REPLY` messages
+        //       to their passive views.
+        let reply = ShuffleReplyMessage {
+            sender: self.id(),
+            nodes,
+        };
+        self.hyparview_node
+            .handle_protocol_message(ProtocolMessage::ShuffleReply(reply));
+    }
+
+    fn adapt_to_cluster_size(&mut self) {
+        let base_ihave_timeout = match self.adaptive_base_options {
+            None => return,
+            Some(ref options) => options.ihave_timeout,
+        };
+        let cluster_size = self.estimated_cluster_size();
+        let degree = estimator::adaptive_eager_push_degree(cluster_size);
+        let ihave_timeout = estimator::adaptive_ihave_timeout(base_ihave_timeout, cluster_size);
+        debug!(
+            self.logger,
+            "Adapts to the estimated cluster size {}: eager_push_degree={}, ihave_timeout={:?}",
+            cluster_size,
+            degree,
+            ihave_timeout
+        );
+        self.plumtree_node.options_mut().ihave_timeout = ihave_timeout;
+        if self.topology_awareness.is_some() || self.zone.is_some() {
+            self.select_eager_push_peers(Some(degree));
+            return;
+        }
+
+        let mut eager = self
+            .plumtree_node
+            .eager_push_peers()
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut lazy = self
+            .plumtree_node
+            .lazy_push_peers()
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        if eager.len() > degree {
+            eager.shuffle(&mut self.rng);
+            for peer in eager.into_iter().skip(degree) {
+                self.prune_peer(peer);
+            }
+        } else {
+            lazy.retain(|peer| !self.service.is_observer(peer));
+            lazy.shuffle(&mut self.rng);
+            for peer in lazy.into_iter().take(degree - eager.len()) {
+                self.promote_peer(peer);
+            }
+        }
+    }
+
+    fn probe_neighbor_rtts(&mut self) {
+        for &peer in self.hyparview_node.active_view() {
+            if !self.rtts.is_probing(&peer) {
+                let check = self.service.ping(peer.address());
+                self.rtts.start_probe(peer, check);
+            }
+        }
+    }
+
+    fn poll_rtt_probes(&mut self) {
+        for peer in self.rtts.poll_probes() {
+            debug!(self.logger, "Cannot measure the RTT to {:?}", peer);
+        }
+    }
+
+    /// Returns the number of the eager push peers if it is decided by the node
+    /// (rather than by Plumtree).
+    fn eager_push_degree(&self) -> Option<usize> {
+        if self.adaptive_base_options.is_some() {
+            Some(estimator::adaptive_eager_push_degree(
+                self.estimated_cluster_size(),
+            ))
+        } else {
+            self.topology_awareness
+                .as_ref()
+                .map(|options| options.eager_push_degree)
+        }
+    }
+
+    /// Selects the eager push peers from the neighbors, and moves the others to lazy push peers.
+    ///
+    /// The neighbors having lower RTTs are preferred.
+    /// If the node has a zone, `min_cross_zone_links` cross-zone neighbors are selected first,
+    /// and then the in-zone ones are preferred.
+    /// If `degree` is `None`, all the in-zone neighbors are selected
+    /// (or all the neighbors if the node has no zone).
+    fn select_eager_push_peers(&mut self, degree: Option<usize>) {
+        let mut neighbors = self
+            .hyparview_node
+            .active_view()
+            .iter()
+            .filter(|n| !self.service.is_observer(n))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.rtts.sort_by_rtt(&mut neighbors);
+
+        let (selected, degree) = if let Some(ref zone) = self.zone {
+            let service = &self.service;
+            let (local, remote): (Vec<_>, Vec<_>) = neighbors
+                .into_iter()
+                .partition(|n| service.zone_of(n).as_ref() == Some(zone));
+            let cross = self.min_cross_zone_links.min(remote.len());
+            let degree = degree.unwrap_or(local.len() + cross).max(cross);
+            let mut selected = remote[..cross].to_vec();
+            selected.extend(local);
+            selected.extend_from_slice(&remote[cross..]);
+            (selected, degree)
+        } else {
+            let degree = degree.unwrap_or_else(|| neighbors.len());
+            (neighbors, degree)
+        };
+        for (i, peer) in selected.into_iter().enumerate() {
+            let eager = self
+                .plumtree_node
+                .eager_push_peers()
+                .iter()
+                .any(|p| *p == peer);
+            if i < degree && !eager {
+                self.promote_peer(peer);
+            } else if i >= degree && eager {
+                self.prune_peer(peer);
+            }
+        }
+    }
+
+    /// Disconnects a surplus cross-zone neighbor if there are in-zone nodes in the passive view,
+    /// so that the following filling of the active view may pick an in-zone node instead.
+    fn replace_cross_zone_neighbor(&mut self) {
+        let zone = match self.zone {
+            None => return,
+            Some(ref zone) => zone.clone(),
+        };
+        let service = &self.service;
+        let in_zone = |n: &NodeId| service.zone_of(n).as_ref() == Some(&zone);
+        let cross = self
+            .hyparview_node
+            .active_view()
+            .iter()
+            .filter(|n| !in_zone(n))
+            .cloned()
+            .collect::<Vec<_>>();
+        if cross.len() <= self.min_cross_zone_links || !self.passive_view().iter().any(in_zone) {
+            return;
+        }
+        if let Some(&peer) = cross.choose(&mut self.rng) {
+            info!(
+                self.logger,
+                "Disconnects the cross-zone neighbor {:?} to prefer in-zone nodes", peer
+            );
+            self.event_log.record("disconnect", Some(peer), String::new);
+            self.hyparview_node.disconnect(&peer, true);
+        }
+    }
+
+    /// Moves the given neighbor to the lazy push peers if it is an observer.
+    fn demote_observer(&mut self, peer: NodeId) {
+        use plumtree::message::ProtocolMessage;
+
+        if !self.service.is_observer(&peer)
+            || !self
+                .plumtree_node
+                .eager_push_peers()
+                .iter()
+                .any(|p| *p == peer)
+        {
+            return;
+        }
+        debug!(
+            self.logger,
+            "Demotes the observer {:?} to a lazy push peer", peer
+        );
+        let prune = PruneMessage { sender: peer };
+        self.plumtree_node
+            .handle_protocol_message(ProtocolMessage::Prune(prune));
+    }
+
+    fn export_membership(&self, exporter: &ArcMembershipExporter, status: &NodeStatus) {
+        let membership = Membership {
+            local_node: status.id(),
+            active_view: status.active_view().to_vec(),
+            passive_view: status.passive_view().to_vec(),
+        };
+        if let Err(e) = exporter.export(&membership) {
+            warn!(self.logger, "Cannot export the membership: {}", e);
+        }
+    }
+
+    fn accept_graft(&mut self, m: &GraftMessage<M>) -> bool {
+        let id = match m.message_id {
+            None => return true,
+            Some(id) => id,
+        };
+        let requests = self.graft_requests.entry(id).or_default();
+        if requests.requesters.insert(m.sender) {
+            return true;
+        }
+        debug!(
+            self.logger,
+            "Coalesces a duplicate GRAFT message from {:?}: {:?}", m.sender, id
+        );
+        self.metrics.coalesced_grafts.increment();
+        false
+    }
+
+    fn prepare_repair(&mut self, destination: &NodeId, m: &mut GossipMessage<M>) {
+        let id = m.message.id;
+        let requests = match self.graft_requests.get_mut(&id) {
+            Some(r) if r.requesters.remove(destination) => r,
+            _ => return,
+        };
+        m.message.payload.repair = true;
+        if requests.encoded_payload.is_none() && !requests.requesters.is_empty() {
+            // NOTE: The payload is encoded once for all the requesters of the message.
+            requests.encoded_payload = encode_payload(&m.message.payload.payload).map(Arc::new);
+        }
+        m.message.payload.encoded_payload = requests.encoded_payload.clone();
+        if requests.requesters.is_empty() {
+            self.graft_requests.remove(&id);
+        }
+    }
+
+    fn charge_cache_budget(&mut self, id: MessageId, envelope: &Envelope<M>) {
+        let evicted = if let Some(ref mut budget) = self.cache_budget {
+            let size = envelope
+                .payload_size
+                .unwrap_or_else(|| encoded_payload_size(&envelope.payload));
+            budget.insert(id, size);
+            budget.evict()
+        } else {
+            return;
+        };
+        for (id, size) in evicted {
+            debug!(
+                self.logger,
+                "Evicts a cached message: {:?} ({} bytes)", id, size
+            );
+            let known = self.known_messages.remove(&id);
+            if self.plumtree_node.forget_message(&id) {
+                self.count_forgot_message(known.as_ref());
+            }
+            self.metrics.cache_evicted_bytes.add_u64(size);
+        }
+    }
+
+    fn apply_tombstone(&mut self, target: MessageId) -> bool {
+        self.event_log
+            .record("tombstone", None, || format!("{:?}", target));
+        if !self.retracted_messages.contains(&target) {
+            self.suppress_message(target);
+        }
+        self.delivered_messages.contains(&target)
+    }
+
+    fn retract_message(&mut self, id: MessageId, from: Option<NodeId>) {
+        self.suppress_message(id);
+
+        let retract = RetractMessage {
+            sender: self.id(),
+            message_id: id,
+        };
+        let peers = self.hyparview_node.active_view().to_vec();
+        for peer in peers.into_iter().filter(|&p| Some(p) != from) {
+            self.event_log
+                .record("send_retract", Some(peer), || format!("{:?}", id));
+            if let Err(e) = self
+                .service
+                .send_message(peer, RpcMessage::Retract(retract))
+            {
+                warn!(self.logger, "Cannot send a retraction to {:?}: {}", peer, e);
+                self.record_send_error(e);
+            }
+        }
+    }
+
+    fn suppress_message(&mut self, id: MessageId) {
+        self.retracted_messages.insert(id);
+        self.metrics.retracted_messages.increment();
+
+        let known = self.known_messages.remove(&id);
+        self.graft_requests.remove(&id);
+        if let Some(ref mut budget) = self.cache_budget {
+            budget.remove(&id);
+        }
+        if self.plumtree_node.forget_message(&id) {
+            self.count_forgot_message(known.as_ref());
+        }
+        if let Some(pending) = self.pending_confirmations.remove(&id) {
+            pending.complete();
+        }
+    }
+
+    fn apply_shuffle_policy(&mut self, destination: NodeId, nodes: &mut Vec<NodeId>) {
+        if self.shuffle_policy.is_none() {
+            return;
+        }
+        let now = self.plumtree_node.clock().now();
+        let mut quarantined = Vec::new();
+        if self.quarantine.is_enabled() {
+            let candidates = self
+                .hyparview_node
+                .active_view()
+                .iter()
+                .chain(self.hyparview_node.passive_view().iter())
+                .chain(nodes.iter())
+                .cloned()
+                .collect::<Vec<_>>();
+            for node in candidates {
+                if self.quarantine.is_quarantined(&node, now) {
+                    quarantined.push(node);
+                }
+            }
+        }
+        let context = ShuffleContext {
+            local_node: self.id(),
+            destination,
+            active_view: self.hyparview_node.active_view(),
+            passive_view: self.hyparview_node.passive_view(),
+            quarantined: &quarantined,
+            max_nodes: nodes.len(),
+        };
+        if let Some(ref mut policy) = self.shuffle_policy {
+            policy.select(&context, nodes);
+        }
+    }
+
+    fn detach_service(&mut self) {
+        warn!(
+            self.logger,
+            "The service has stopped; waiting for its successor (see `ServiceBuilder::restart()`)"
+        );
+        self.service_down = true;
+        self.metrics.service_downs.increment();
+        if self.uses_service_clock {
+            // NOTE: The shared clock is driven by the stopped service.
+            self.ticker = Ticker::Timer(timer::timeout(self.params.tick_interval()));
+            self.uses_service_clock = false;
+        }
+    }
+
+    fn new_node_handle(&self) -> (NodeHandle<M>, mpsc::Receiver<RpcMessage<M>>) {
+        let (message_tx, message_rx) = mpsc::channel();
+        let handle = NodeHandle {
+            local_id: self.id().local_id(),
+            message_tx,
+            inbound_queue_len: Arc::clone(&self.inbound_queue_len),
+            max_inbound_queue_len: self.max_inbound_queue_len,
+            metrics: self.metrics.clone(),
+            lease: self.lease.clone(),
+            status: Arc::clone(&self.status),
+            event_log: self.event_log.clone(),
+            observer: self.observer,
+            zone: self.zone.clone(),
+        };
+        (handle, message_rx)
+    }
+
+    fn renew_lease(&mut self) {
+        self.lease.renew();
+        if !self.lease.revive() {
+            return;
+        }
+        warn!(
+            self.logger,
+            "The liveness lease of the node had expired; re-registers the node with the service"
+        );
+        self.event_log.record("revive_lease", None, String::new);
+        let (handle, message_rx) = self.new_node_handle();
+        if let Err(e) = track!(self.service.register_local_node(handle)) {
+            warn!(
+                self.logger,
+                "Cannot re-register the node with the service (retries later): {}", e
+            );
+            self.lease.expire();
+            return;
+        }
+
+        // NOTE: The messages queued before the expiration have been discarded by the service.
+        self.inbound_queue_len.store(0, Ordering::SeqCst);
+        self.message_rx = message_rx;
+    }
+
+    fn reattach_service(&mut self) -> bool {
+        let successor = match self.service.successor() {
+            None => return false,
+            Some(successor) => successor,
+        };
+        let (handle, message_rx) = self.new_node_handle();
+        if let Err(e) = track!(successor.register_local_node(handle)) {
+            warn!(
+                self.logger,
+                "Cannot register the node with the successor service: {}", e
+            );
+            return false;
+        }
+        info!(
+            self.logger,
+            "Re-registered the node with the successor service"
+        );
+
+        // NOTE: The messages queued by the stopped service have been lost.
+        self.inbound_queue_len.store(0, Ordering::SeqCst);
+        self.message_rx = message_rx;
+        let uses_timer = match self.ticker {
+            Ticker::Timer(_) => true,
+            Ticker::Clock(_) => false,
+        };
+        if let (true, Some(clock)) = (uses_timer, successor.clock()) {
+            self.ticker = Ticker::Clock(clock.subscribe());
+            self.uses_service_clock = true;
+        }
+        self.service = successor;
+        self.service_down = false;
+        true
+    }
+
+    fn acquire_broadcast_quota(&mut self, origin: NodeId) -> bool {
+        const MAX_QUOTA_EXCEEDED_ORIGINS: usize = 64;
+
+        if origin == self.id() {
+            return true;
+        }
+        let now = self.plumtree_node.clock().now();
+        let quotas = match self.broadcast_quotas {
+            None => return true,
+            Some(ref mut quotas) => quotas,
+        };
+        match quotas.acquire(origin, now) {
+            QuotaCheck::Acquired => true,
+            QuotaCheck::Exceeded { first } => {
+                if first {
+                    warn!(
+                        self.logger,
+                        "{:?} exceeded the broadcast quota ({}/s)", origin, quotas.max_per_second
+                    );
+                    if self.quota_exceeded_origins.len() == MAX_QUOTA_EXCEEDED_ORIGINS {
+                        self.quota_exceeded_origins.pop_front();
+                    }
+                    self.quota_exceeded_origins.push_back(origin);
+                }
+                false
+            }
+        }
+    }
+
+    fn record_neighbor_left(&mut self, left: NeighborLeft) {
+        const MAX_LEFT_NEIGHBORS: usize = 64;
+
+        if self.left_neighbors.len() == MAX_LEFT_NEIGHBORS {
+            self.left_neighbors.pop_front();
+        }
+        self.left_neighbors.push_back(left);
+    }
+
+    fn disconnect_failed(&mut self, node: NodeId) {
+        if self.hyparview_node.active_view().contains(&node) {
+            self.down_causes.insert(node, DownCause::Failure);
+        }
+        self.hyparview_node.disconnect(&node, false);
+    }
+
+    fn record_send_error(&mut self, e: Error) {
+        const MAX_SEND_ERRORS: usize = 64;
+
+        if self.send_errors.len() == MAX_SEND_ERRORS {
+            self.send_errors.pop_front();
+        }
+        self.send_errors.push_back(e);
+    }
+
+    fn disconnect_stale_identities_of(&mut self, node: NodeId) {
+        if !self.disconnect_stale_identities || node.address() == self.id().address() {
+            return;
+        }
+        let stale = self
+            .hyparview_node
+            .active_view()
+            .iter()
+            .filter(|n| n.address() == node.address() && **n != node)
+            .cloned()
+            .collect::<Vec<_>>();
+        for old in stale {
+            info!(
+                self.logger,
+                "Disconnects a stale identity {:?} superseded by {:?}", old, node
+            );
+            self.event_log
+                .record("disconnect_stale", Some(old), || format!("{:?}", node));
+            self.metrics.stale_identities.increment();
+            self.disconnect_failed(old);
+        }
+    }
+
+    fn handle_quarantined(&mut self, node: NodeId) {
+        info!(self.logger, "Quarantines {:?}", node);
+        self.event_log.record("quarantine", Some(node), String::new);
+        self.metrics.quarantined_nodes.increment();
+    }
+
+    fn leave(&self) {
+        use hyparview::message::{DisconnectMessage, ProtocolMessage};
+
+        info!(
+            self.logger,
+            "Leaves the current cluster: active_view={:?}",
+            self.hyparview_node.active_view()
+        );
+        for peer in self.hyparview_node.active_view().iter().cloned() {
+            let message = DisconnectMessage {
+                sender: self.id(),
+                alive: false,
+            };
+            let message = ProtocolMessage::Disconnect(message);
+            let message = RpcMessage::Hyparview(message);
+            let _ = self.service.send_message(peer, message);
+        }
+    }
+
+    fn count_forgot_message(&mut self, known: Option<&KnownMessage>) {
+        // NOTE: Rebroadcasted messages are never counted as delivered,
+        // so they must not be counted as forgot either (see `update_gauges()`).
+        if !known.map_or(false, |k| k.rebroadcasted) {
+            self.metrics.forgot_messages.increment();
+            self.cached_messages = self.cached_messages.saturating_sub(1);
+        }
+    }
+
+    fn check_watermarks(&mut self) {
+        let (high, low) = match self.delivery_watermarks {
+            None => return,
+            Some(x) => x,
+        };
+        let pending = self.pending_deliveries();
+        let event = if !self.falling_behind && pending >= high {
+            warn!(
+                self.logger,
+                "The pending deliveries reached the high watermark: {}", pending
+            );
+            self.metrics.falling_behind_times.increment();
+            DeliveryWatermark::High
+        } else if self.falling_behind && pending <= low {
+            info!(
+                self.logger,
+                "The pending deliveries dropped to the low watermark: {}", pending
+            );
+            DeliveryWatermark::Low
+        } else {
+            return;
+        };
+        self.falling_behind = !self.falling_behind;
+
+        // NOTE: A crossing cancels the unreported crossing in the opposite direction.
+        self.watermark_event = if self.watermark_event.is_some() {
+            None
+        } else {
+            Some(event)
+        };
+    }
+
+    fn check_overload(&mut self) {
+        let policy = match self.overload_policy {
+            None => return,
+            Some(x) => x,
+        };
+        let pending = self.pending_deliveries() as u64;
+        let cached = self.cached_messages();
+        let event = if !self.overloaded && policy.reaches(pending, cached, 100) {
+            warn!(
+                self.logger,
+                "The node is overloaded: pending_deliveries={}, cached_messages={}",
+                pending,
+                cached
+            );
+            self.metrics.overloaded_times.increment();
+            OverloadEvent::Overloaded
+        } else if self.overloaded && !policy.reaches(pending, cached, policy.recovery_percent) {
+            info!(
+                self.logger,
+                "The node has recovered from overload: pending_deliveries={}, cached_messages={}",
+                pending,
+                cached
+            );
+            OverloadEvent::Recovered
+        } else {
+            return;
+        };
+        self.overloaded = !self.overloaded;
+        self.event_log
+            .record("overload", None, || format!("{:?}", event));
+
+        // NOTE: A transition cancels the unreported transition in the opposite direction.
+        self.overload_event = if self.overload_event.is_some() {
+            None
+        } else {
+            Some(event)
+        };
+    }
+
+    fn shed_gossip(&self, message: &PlumtreeMessage<M>) -> Option<IhaveMessage<M>> {
+        if !self.overloaded {
+            return None;
+        }
+        let m = match message {
+            plumtree::message::ProtocolMessage::Gossip(m) => m,
+            _ => return None,
+        };
+        let payload = &m.message.payload;
+        if payload.high_priority || payload.repair || m.message.id.node() == self.id() {
+            return None;
+        }
+        Some(IhaveMessage {
+            sender: m.sender,
+            round: m.round,
+            message_id: m.message.id,
+            realtime: false,
+        })
+    }
+
+    fn cached_messages(&self) -> u64 {
+        self.cached_messages
+    }
+
+    fn update_gauges(&self) {
+        let metrics = &self.metrics;
+        let cached_messages = self.cached_messages();
+        metrics
+            .active_view_size
+            .set(self.hyparview_node.active_view().len() as f64);
+        metrics
+            .passive_view_size
+            .set(self.passive_view().len() as f64);
+        metrics
+            .eager_push_peers
+            .set(self.plumtree_node.eager_push_peers().len() as f64);
+        metrics
+            .lazy_push_peers
+            .set(self.plumtree_node.lazy_push_peers().len() as f64);
+        metrics.cached_messages.set(cached_messages as f64);
+        metrics
+            .inbound_queue_len
+            .set(self.inbound_queue_len.load(Ordering::SeqCst) as f64);
+        metrics
+            .estimated_cluster_size
+            .set(self.estimated_cluster_size() as f64);
+    }
+
+    fn poll_lan_discovery(&mut self) {
+        loop {
+            let result = match self.lan_discovery {
+                None => return,
+                Some(ref mut d) => track!(d.poll()),
+            };
+            match result {
+                Err(e) => {
+                    warn!(
+                        self.logger,
+                        "LAN discovery is disabled due to an error: {}", e
+                    );
+                    self.lan_discovery = None;
+                }
+                Ok(Async::NotReady) | Ok(Async::Ready(None)) => return,
+                Ok(Async::Ready(Some(peer))) => {
+                    let peer = self.service.normalize_node_id(peer);
+                    let now = self.clock().now();
+                    if self.hyparview_node.active_view().is_empty()
+                        && !self.quarantine.is_quarantined(&peer, now)
+                    {
+                        debug!(self.logger, "Discovered {:?} on the LAN", peer);
+                        self.join(peer);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Drives the node without delivering messages (used while an external sink is not ready).
+    ///
+    /// The timers and the outgoing HyParView/Plumtree messages are handled as usual,
+    /// but the inbound messages are left queued, and at most one message that
+    /// becomes deliverable meanwhile is held until the next `poll()`.
+    pub(crate) fn poll_without_delivery(&mut self) -> Result<()> {
+        self.renew_lease();
+        if self.service_down && !self.reattach_service() {
+            while track!(self.poll_tick())?.is_some() {}
+            return Ok(());
+        }
+        while let Some(elapsed) = track!(self.poll_tick())? {
+            self.handle_tick(elapsed);
+        }
+        self.poll_lan_discovery();
+        self.poll_rtt_probes();
+        while let Some(action) = self.hyparview_node.poll_action() {
+            self.handle_hyparview_action(action);
+        }
+        while self.held_delivery.is_none() {
+            if let Some(action) = self.plumtree_node.poll_action() {
+                self.held_delivery = self.handle_plumtree_action(action);
+            } else {
+                break;
+            }
+        }
+        self.check_watermarks();
+        self.check_overload();
+        self.update_gauges();
+        Ok(())
+    }
+
+    fn poll_message(&mut self) -> Poll<Option<Message<M>>, Error> {
+        if let Some(message) = self.held_delivery.take() {
+            return Ok(Async::Ready(Some(message)));
+        }
+        if self.service_down && !self.reattach_service() {
+            // NOTE: The ticks are only used for checking the successor service periodically.
+            while track!(self.poll_tick())?.is_some() {}
+            return Ok(Async::NotReady);
+        }
+        while let Some(elapsed) = track!(self.poll_tick())? {
+            self.handle_tick(elapsed);
+        }
+        self.poll_lan_discovery();
+        self.poll_rtt_probes();
+
+        let mut handled_messages = 0;
+        let mut did_something = true;
+        while did_something {
+            did_something = false;
+
+            // NOTE: Local messages are not kept by the node, so they are not counted by metrics.
+            while let Some(message) = self.local_messages.pop_front() {
+                if self
+                    .delivery_filter
+                    .as_mut()
+                    .map_or(true, |f| f.filter(&message))
+                {
+                    return Ok(Async::Ready(Some(message)));
+                }
+            }
+
+            while let Some(action) = self.hyparview_node.poll_action() {
+                self.handle_hyparview_action(action);
+                did_something = true;
+            }
+            while let Some(action) = self.plumtree_node.poll_action() {
+                if let Some(message) = self.handle_plumtree_action(action) {
+                    return Ok(Async::Ready(Some(message)));
+                }
+                did_something = true;
+            }
+
+            // NOTE: The `GRAFT` messages in the previous batch have been served by now.
+            self.graft_requests.clear();
+            while let Async::Ready(message) = self.message_rx.poll().expect("Never fails") {
+                did_something = true;
+                let message = if let Some(message) = message {
+                    message
+                } else {
+                    track_assert!(
+                        self.service.is_restartable() || self.service.successor().is_some(),
+                        ErrorKind::Other,
+                        "Service down"
+                    );
+                    self.detach_service();
+                    task::current().notify();
+                    return Ok(Async::NotReady);
+                };
+                self.inbound_queue_len.fetch_sub(1, Ordering::SeqCst);
+                handled_messages += 1;
+                if self.handle_rpc_message(message) {
+                    break;
+                }
+                if self
+                    .max_messages_per_poll
+                    .map_or(false, |max| handled_messages >= max)
+                {
+                    // NOTE: The remaining messages are handled by the next poll.
+                    self.metrics.exhausted_poll_budgets.increment();
+                    task::current().notify();
+                    return Ok(Async::NotReady);
+                }
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
+impl<M: MessagePayload> Stream for Node<M> {
+    type Item = Message<M>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.renew_lease();
+        let result = self.poll_message();
+        if let Err(ref e) = result {
+            self.event_log.dump_if_inconsistent(&self.logger, e);
+        }
+        if let Ok(Async::Ready(Some(ref message))) = result {
+            self.subscribers.publish(message);
+        }
+        self.check_watermarks();
+        self.check_overload();
+        self.update_gauges();
+        result
+    }
+}
+impl<M: MessagePayload> Drop for Node<M> {
+    fn drop(&mut self) {
+        // NOTE: If the lease has expired, the service has already deregistered the node.
+        if self.lease.release() {
+            self.service.deregister_local_node(self.id().local_id());
+        }
+        self.subscribers.close();
+
+        let messages = self.cached_messages();
+        self.metrics.forgot_messages.add_u64(messages);
+
+        self.leave();
+    }
+}
+
+/// A [`Future`] that resolves once a broadcasted message has left the node.
+///
+/// This is returned by [`Node::broadcast_with_confirmation`].
+///
+/// The resolved value is the number of the eager push peers to which the message has been sent.
+/// It may be smaller than the number of the eager push peers at the time of the broadcast,
+/// because sending to a peer may fail (or the peer may be disconnected) before the message is pushed.
+///
+/// If the node is dropped before the confirmation is completed, the future fails.
+///
+/// [`Future`]: https://docs.rs/futures/0.1/futures/future/trait.Future.html
+/// [`Node::broadcast_with_confirmation`]: ./struct.Node.html#method.broadcast_with_confirmation
+#[derive(Debug)]
+pub struct BroadcastConfirmation(oneshot::Receiver<usize>);
+impl Future for BroadcastConfirmation {
+    type Item = usize;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        track!(self
+            .0
+            .poll()
+            .map_err(|e| Error::from(ErrorKind::Other.cause(e))))
+    }
+}
+
+#[derive(Debug)]
+struct PendingConfirmation {
+    peers: HashSet<NodeId>,
+    pushed: usize,
+    tx: oneshot::Sender<usize>,
+}
+impl PendingConfirmation {
+    fn confirm(&mut self, peer: &NodeId, pushed: bool) -> bool {
+        if self.peers.remove(peer) && pushed {
+            self.pushed += 1;
+        }
+        self.peers.is_empty()
+    }
+
+    fn complete(self) {
+        let _ = self.tx.send(self.pushed);
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct NodeHandle<M: MessagePayload> {
+    local_id: LocalNodeId,
+    message_tx: mpsc::Sender<RpcMessage<M>>,
+    inbound_queue_len: Arc<AtomicUsize>,
+    max_inbound_queue_len: Option<usize>,
+    metrics: NodeMetrics,
+    lease: Lease,
+    status: Arc<AtomicImmut<Option<NodeStatus>>>,
+    event_log: EventLog,
+    observer: bool,
+    zone: Option<String>,
+}
+impl<M: MessagePayload> fmt::Debug for NodeHandle<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NodeHandle {{ local_id: {:?}, .. }}", self.local_id)
+    }
+}
+impl<M: MessagePayload> NodeHandle<M> {
+    pub(crate) fn local_id(&self) -> LocalNodeId {
+        self.local_id
+    }
+
+    pub(crate) fn send_rpc_message(&self, message: RpcMessage<M>) {
+        let is_plumtree = match message {
+            RpcMessage::Plumtree(_) | RpcMessage::HashedIhave(..) => true,
+            _ => false,
+        };
+        if let (true, Some(max)) = (is_plumtree, self.max_inbound_queue_len) {
+            if self.inbound_queue_len.load(Ordering::SeqCst) >= max {
+                self.metrics.inbound_queue_overflow_errors.increment();
+                return;
+            }
+        }
+        self.inbound_queue_len.fetch_add(1, Ordering::SeqCst);
+        if self.message_tx.send(message).is_err() {
+            self.inbound_queue_len.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    pub(crate) fn metrics(&self) -> &NodeMetrics {
+        &self.metrics
+    }
+
+    pub(crate) fn lease(&self) -> &Lease {
+        &self.lease
+    }
+
+    pub(crate) fn status(&self) -> Option<NodeStatus> {
+        (*self.status.load()).clone()
+    }
+
+    pub(crate) fn debug_events(&self, node: NodeId) -> Vec<DebugEvent> {
+        self.event_log.debug_events(node)
+    }
+
+    pub(crate) fn is_observer(&self) -> bool {
+        self.observer
+    }
+
+    pub(crate) fn zone(&self) -> Option<&str> {
+        self.zone.as_ref().map(|z| z.as_str())
+    }
+}
+
+fn validate_metric_label_name(name: &str) -> Result<()> {
+    // NOTE: `kind` is used by the error counters, and `le` by the histogram buckets.
+    const RESERVED_NAMES: &[&str] = &["kind", "le"];
+
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__");
+    track_assert!(
+        valid,
+        ErrorKind::InvalidInput,
+        "Invalid metric label name: {:?}",
+        name
+    );
+    track_assert!(
+        !RESERVED_NAMES.contains(&name),
+        ErrorKind::InvalidInput,
+        "The metric label name {:?} is used by plumcast",
+        name
+    );
+    Ok(())
+}
+
+/// Liveness lease of a node.
+///
+/// The lease is renewed each time the node is polled.
+/// If it has not been renewed for a while (e.g., the fiber of the node died without dropping it),
+/// the service expires the lease and deregisters the node.
+/// If the node is still alive, it revives the lease and re-registers itself at the next poll.
+///
+/// Exactly one of the service (by expiring) and the node (by dropping) deregisters the node,
+/// which is decided by the state transitions of the lease.
+#[derive(Debug, Clone)]
+pub(crate) struct Lease {
+    epoch: Instant,
+    renewed_millis: Arc<AtomicU64>,
+    state: Arc<AtomicUsize>,
+}
+impl Lease {
+    const HELD: usize = 0;
+    const EXPIRED: usize = 1;
+    const RELEASED: usize = 2;
+
+    fn new() -> Self {
+        Lease {
+            epoch: Instant::now(),
+            renewed_millis: Arc::new(AtomicU64::new(0)),
+            state: Arc::new(AtomicUsize::new(Self::HELD)),
+        }
+    }
+
+    /// Marks the lease as expired.
+    ///
+    /// Returns `false` if the lease has already been expired or released by the node.
+    pub(crate) fn expire(&self) -> bool {
+        self.transit(Self::HELD, Self::EXPIRED)
+    }
+
+    fn revive(&self) -> bool {
+        self.transit(Self::EXPIRED, Self::HELD)
+    }
+
+    fn release(&self) -> bool {
+        self.transit(Self::HELD, Self::RELEASED)
+    }
+
+    fn transit(&self, from: usize, to: usize) -> bool {
+        self.state
+            .compare_exchange(from, to, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+
+    fn renew(&self) {
+        let elapsed = duration_to_millis(self.epoch.elapsed());
+        self.renewed_millis.store(elapsed, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_expired(&self, duration: Duration, now: Instant) -> bool {
+        let renewed = Duration::from_millis(self.renewed_millis.load(Ordering::Relaxed));
+        self.epoch + renewed + duration < now
+    }
+}
+
+/// Execution intervals of the periodic operations of a [`Node`].
+///
+/// See [`Node::reconfigure`] and the corresponding methods of [`NodeBuilder`] for more details.
+///
+/// [`Node`]: ./struct.Node.html
+/// [`Node::reconfigure`]: ./struct.Node.html#method.reconfigure
+/// [`NodeBuilder`]: ./struct.NodeBuilder.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Intervals {
+    /// The tick interval of the node (see `NodeBuilder::tick_interval()`).
+    pub tick_interval: Duration,
+
+    /// The execution interval of `HyparviewNode::shuffle_passive_view()` method.
+    pub hyparview_shuffle_interval: Duration,
+
+    /// The execution interval of `HyparviewNode::sync_active_view()` method.
+    pub hyparview_sync_active_view_interval: Duration,
+
+    /// The execution interval of `HyparviewNode::fill_active_view()` method.
+    pub hyparview_fill_active_view_interval: Duration,
+}
+impl Intervals {
+    fn validate(&self) -> Result<()> {
+        track_assert_ne!(
+            self.tick_interval,
+            Duration::from_secs(0),
+            ErrorKind::InvalidInput,
+            "The tick interval must be positive"
+        );
+        for &(name, interval) in &[
+            ("shuffle", self.hyparview_shuffle_interval),
+            ("sync active view", self.hyparview_sync_active_view_interval),
+            ("fill active view", self.hyparview_fill_active_view_interval),
+        ] {
+            track_assert!(
+                self.tick_interval <= interval,
+                ErrorKind::InvalidInput,
+                "The HyParView {} interval {:?} must not be shorter than the tick interval {:?}",
+                name,
+                interval,
+                self.tick_interval
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The source of the ticks of a node.
+#[derive(Debug)]
+enum Ticker {
+    Timer(Timeout),
+    Clock(mpsc::Receiver<Duration>),
+}
+
+/// A message that has been broadcasted or received by a node, and not forgot yet.
+#[derive(Debug, Clone)]
+struct KnownMessage {
+    relay_limit: Option<RelayLimit>,
+
+    // The number of the gossip and `IHAVE` messages received after the first gossip.
+    duplicates: usize,
+
+    // Rebroadcasted messages are not delivered to the local node.
+    rebroadcasted: bool,
+
+    // The hash sent with the `IHAVE` messages for the message (see `Node::broadcast_dedup()`).
+    content_hash: Option<u64>,
+}
+impl KnownMessage {
+    fn new<M>(envelope: &Envelope<M>) -> Self {
+        KnownMessage {
+            relay_limit: RelayLimit::of(envelope),
+            duplicates: 0,
+            rebroadcasted: false,
+            content_hash: envelope.content_hash,
+        }
+    }
+}
+
+/// The content hashes of the payloads seen within a window (see `Node::broadcast_dedup()`).
+#[derive(Debug)]
+struct RecentContentHashes {
+    window: Duration,
+    hashes: HashMap<u64, NodeTime>,
+    order: VecDeque<(NodeTime, u64)>,
+}
+impl RecentContentHashes {
+    fn new(window: Duration) -> Self {
+        RecentContentHashes {
+            window,
+            hashes: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn contains(&self, hash: u64, now: NodeTime) -> bool {
+        self.hashes
+            .get(&hash)
+            .map_or(false, |&time| now < time + self.window)
+    }
+
+    fn insert(&mut self, hash: u64, now: NodeTime) {
+        while let Some(&(time, oldest)) = self.order.front() {
+            if now < time + self.window {
+                break;
+            }
+            self.order.pop_front();
+            if self.hashes.get(&oldest) == Some(&time) {
+                self.hashes.remove(&oldest);
+            }
+        }
+        self.hashes.insert(hash, now);
+        self.order.push_back((now, hash));
+    }
+}
+
+/// Per-origin counters of the new messages received in the current one-second windows.
+#[derive(Debug)]
+struct BroadcastQuotas {
+    max_per_second: u64,
+    windows: HashMap<NodeId, QuotaWindow>,
+}
+impl BroadcastQuotas {
+    const WINDOW: Duration = Duration::from_secs(1);
+    const PURGE_THRESHOLD: usize = 1024;
+
+    fn new(max_per_second: u64) -> Self {
+        BroadcastQuotas {
+            max_per_second,
+            windows: HashMap::new(),
+        }
+    }
+
+    fn acquire(&mut self, origin: NodeId, now: NodeTime) -> QuotaCheck {
+        if self.windows.len() >= Self::PURGE_THRESHOLD {
+            self.windows.retain(|_, w| now < w.start + Self::WINDOW);
+        }
+        let window = self.windows.entry(origin).or_insert(QuotaWindow {
+            start: now,
+            count: 0,
+            exceeded: false,
+        });
+        if window.start + Self::WINDOW <= now {
+            *window = QuotaWindow {
+                start: now,
+                count: 0,
+                exceeded: false,
+            };
+        }
+        if window.count < self.max_per_second {
+            window.count += 1;
+            QuotaCheck::Acquired
+        } else {
+            let first = !window.exceeded;
+            window.exceeded = true;
+            QuotaCheck::Exceeded { first }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct QuotaWindow {
+    start: NodeTime,
+    count: u64,
+    exceeded: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum QuotaCheck {
+    Acquired,
+    Exceeded { first: bool },
+}
+
+/// A set of message identifiers (e.g., the ones rejected by the relay policy or retracted).
+///
+/// Only the most recent ones are kept, so that the memory usage is bounded.
+#[derive(Debug, Default)]
+struct RecentMessageIds {
+    ids: HashSet<MessageId>,
+    order: VecDeque<MessageId>,
+}
+impl RecentMessageIds {
+    const CAPACITY: usize = 4096;
+
+    fn contains(&self, id: &MessageId) -> bool {
+        self.ids.contains(id)
+    }
+
+    fn insert(&mut self, id: MessageId) {
+        if !self.ids.insert(id) {
+            return;
+        }
+        self.order.push_back(id);
+        if self.order.len() > Self::CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// The `GRAFT` requests for a message received in the current batch.
+#[derive(Debug, Default)]
+struct GraftRequests {
+    requesters: HashSet<NodeId>,
+    encoded_payload: Option<Arc<Vec<u8>>>,
+}
+
+/// The accounting of the memory budget of the message cache.
+///
+/// The messages are evicted in the order they were cached.
+#[derive(Debug)]
+struct CacheBudget {
+    limit: u64,
+    used: u64,
+    sizes: HashMap<MessageId, u64>,
+    order: VecDeque<MessageId>,
+}
+impl CacheBudget {
+    fn new(limit: u64) -> Self {
+        CacheBudget {
+            limit,
+            used: 0,
+            sizes: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn insert(&mut self, id: MessageId, size: u64) {
+        if let Entry::Vacant(e) = self.sizes.entry(id) {
+            e.insert(size);
+            self.order.push_back(id);
+            self.used += size;
+        }
+    }
+
+    fn remove(&mut self, id: &MessageId) {
+        if let Some(size) = self.sizes.remove(id) {
+            self.used -= size;
+
+            // NOTE: The removed identifiers are lazily dropped from `order`.
+            if self.order.len() > self.sizes.len() * 2 + 64 {
+                let sizes = &self.sizes;
+                self.order.retain(|id| sizes.contains_key(id));
+            }
+        }
+    }
+
+    fn evict(&mut self) -> Vec<(MessageId, u64)> {
+        let mut evicted = Vec::new();
+        while self.used > self.limit && self.sizes.len() > 1 {
+            let id = match self.order.pop_front() {
+                None => break,
+                Some(id) => id,
+            };
+            if let Some(size) = self.sizes.remove(&id) {
+                self.used -= size;
+                evicted.push((id, size));
+            }
+        }
+        evicted
+    }
+}
+
+/// Identifier of a periodic broadcast scheduled by [`Node::schedule_periodic_broadcast`].
+///
+/// [`Node::schedule_periodic_broadcast`]: ./struct.Node.html#method.schedule_periodic_broadcast
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PeriodicBroadcastId(u64);
+
+struct PeriodicBroadcast<M> {
+    id: PeriodicBroadcastId,
+    interval: Duration,
+    next_time: NodeTime,
+    payload_fn: Box<dyn FnMut() -> M + Send + 'static>,
+}
+impl<M> fmt::Debug for PeriodicBroadcast<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PeriodicBroadcast {{ id: {:?}, interval: {:?}, next_time: {:?}, .. }}",
+            self.id, self.interval, self.next_time
+        )
+    }
+}
+
+/// The reason why a neighbor is going to be disconnected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DownCause {
+    /// The neighbor sent a `DISCONNECT` message with `alive=false`.
+    Leave,
+
+    /// The neighbor was detected to be failed.
+    Failure,
+}
+
+/// The state of retrying to join a cluster.
+#[derive(Debug, Clone, Copy)]
+struct JoinRetry {
+    contact_node: NodeId,
+    attempts: u32,
+    retry_time: NodeTime,
+}
+
+/// The limits on relaying a message, which are kept for suppressing `IHAVE` messages.
+#[derive(Debug, Clone, Copy)]
+struct RelayLimit {
+    deadline: Option<SystemTime>,
+    hop_limit: Option<u16>,
+}
+impl RelayLimit {
+    fn of<M>(envelope: &Envelope<M>) -> Option<Self> {
+        if envelope.deadline.is_none() && envelope.hop_limit.is_none() {
+            return None;
+        }
+        Some(RelayLimit {
+            deadline: envelope.deadline,
+            hop_limit: envelope.hop_limit,
+        })
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.deadline.map_or(false, |deadline| deadline <= now)
+    }
+
+    fn is_hop_limit_exceeded(&self, round: u16) -> bool {
+        self.hop_limit.map_or(false, |limit| round >= limit)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Parameters {
+    tick_interval: Duration,
+    tick_interval_multiplier: f64,
+    hyparview_shuffle_interval: Duration,
+    hyparview_sync_active_view_interval: Duration,
+    hyparview_fill_active_view_interval: Duration,
+    jitter: JitterPolicy,
+}
+impl Parameters {
+    fn tick_interval(&self) -> Duration {
+        let nanos = duration_to_nanos(self.tick_interval) as f64 * self.tick_interval_multiplier;
+        Duration::from_nanos(nanos as u64)
+    }
+
+    fn gen_hyparview_shuffle_interval<R: Rng>(&self, rng: &mut R) -> Duration {
+        self.jitter.apply(self.hyparview_shuffle_interval, rng)
+    }
+
+    fn gen_hyparview_sync_active_view_interval<R: Rng>(&self, rng: &mut R) -> Duration {
+        self.jitter
+            .apply(self.hyparview_sync_active_view_interval, rng)
+    }
+
+    fn gen_hyparview_fill_active_view_interval<R: Rng>(&self, rng: &mut R) -> Duration {
+        self.jitter
+            .apply(self.hyparview_fill_active_view_interval, rng)
+    }
+}
+
+fn plumtree_message_summary<M: MessagePayload>(m: &PlumtreeMessage<M>) -> String {
+    use plumtree::message::ProtocolMessage;
+
+    match m {
+        ProtocolMessage::Gossip(m) => format!(
+            "Gossip {{ sender: {:?}, message_id: {:?}, round: {} }}",
+            m.sender, m.message.id, m.round
+        ),
+        ProtocolMessage::Ihave(m) => format!(
+            "Ihave {{ sender: {:?}, message_id: {:?}, round: {}, realtime: {} }}",
+            m.sender, m.message_id, m.round, m.realtime
+        ),
+        ProtocolMessage::Graft(m) => format!(
+            "Graft {{ sender: {:?}, message_id: {:?}, round: {} }}",
+            m.sender, m.message_id, m.round
+        ),
+        ProtocolMessage::Prune(m) => format!("Prune {{ sender: {:?} }}", m.sender),
+    }
+}
+
+/// Watermark crossed by the pending deliveries of a node.
+///
+/// See [`NodeBuilder::delivery_watermarks`] for more details.
+///
+/// [`NodeBuilder::delivery_watermarks`]: ./struct.NodeBuilder.html#method.delivery_watermarks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryWatermark {
+    /// The pending deliveries have reached the high watermark (i.e., the node is falling behind).
+    High,
+
+    /// The pending deliveries have dropped to the low watermark (i.e., the node has caught up).
+    Low,
+}
+
+/// Policy of the load shedding of a node under overload.
+///
+/// See [`NodeBuilder::overload_policy`] for more details.
+///
+/// [`NodeBuilder::overload_policy`]: ./struct.NodeBuilder.html#method.overload_policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverloadPolicy {
+    /// The number of the pending deliveries (see `Node::pending_deliveries()`)
+    /// at which the node is regarded as overloaded.
+    pub max_pending_deliveries: Option<usize>,
+
+    /// The number of the cached messages (i.e., delivered but not forgot yet)
+    /// at which the node is regarded as overloaded.
+    pub max_cached_messages: Option<u64>,
+
+    /// The node recovers from overload once both numbers drop below
+    /// this percentage of their thresholds.
+    pub recovery_percent: u64,
+}
+impl OverloadPolicy {
+    fn reaches(&self, pending_deliveries: u64, cached_messages: u64, percent: u64) -> bool {
+        let reaches = |value: u64, threshold: Option<u64>| {
+            threshold.map_or(false, |t| value >= t.saturating_mul(percent) / 100)
+        };
+        reaches(
+            pending_deliveries,
+            self.max_pending_deliveries.map(|n| n as u64),
+        ) || reaches(cached_messages, self.max_cached_messages)
+    }
+}
+impl Default for OverloadPolicy {
+    fn default() -> Self {
+        OverloadPolicy {
+            max_pending_deliveries: None,
+            max_cached_messages: None,
+            recovery_percent: 50,
+        }
+    }
+}
+
+/// Transition of the overload state of a node.
+///
+/// See [`NodeBuilder::overload_policy`] for more details.
+///
+/// [`NodeBuilder::overload_policy`]: ./struct.NodeBuilder.html#method.overload_policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverloadEvent {
+    /// The node has become overloaded (and started shedding load).
+    Overloaded,
+
+    /// The node has recovered from overload.
+    Recovered,
+}
+
+/// A departure of a neighbor from the HyParView active view of a node.
+///
+/// See [`Node::poll_neighbor_left`] for more details.
+///
+/// [`Node::poll_neighbor_left`]: ./struct.Node.html#method.poll_neighbor_left
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NeighborLeft {
+    /// The identifier of the neighbor.
+    pub node: NodeId,
+
+    /// `false` if the neighbor has been disconnected due to a detected failure
+    /// (e.g., a crash or a restart with a new identity), otherwise `true`.
+    pub graceful: bool,
+}
+
+/// Jitter policy applied to the execution intervals of the periodic HyParView operations
+/// (i.e., shuffling the passive view, synchronizing and filling the active view).
+///
+/// Randomizing the intervals prevents the nodes started at the same time from
+/// executing the operations in lockstep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JitterPolicy {
+    /// No jitter (the configured interval is used as is).
+    None,
+
+    /// Adds a random duration of up to the given percentage of the configured interval.
+    PositivePercent(u32),
+
+    /// Adds or subtracts a random duration of up to the given percentage of the configured interval.
+    ///
+    /// Percentages greater than `100` are treated as `100`.
+    Percent(u32),
+
+    /// Uses a random duration between zero and the configured interval.
+    Full,
+}
+impl JitterPolicy {
+    fn apply<R: Rng>(self, base: Duration, rng: &mut R) -> Duration {
+        let nanos = duration_to_nanos(base);
+        let (min, max) = match self {
+            JitterPolicy::None => return base,
+            JitterPolicy::PositivePercent(p) => (nanos, nanos.saturating_add(percent_of(nanos, p))),
+            JitterPolicy::Percent(p) => {
+                let delta = percent_of(nanos, std::cmp::min(p, 100));
+                (nanos - delta, nanos.saturating_add(delta))
+            }
+            JitterPolicy::Full => (0, nanos),
+        };
+        if min == max {
+            return Duration::from_nanos(min);
+        }
+        Duration::from_nanos(rng.gen_range(min, max))
+    }
+}
+impl Default for JitterPolicy {
+    fn default() -> Self {
+        JitterPolicy::PositivePercent(10)
+    }
+}
+
+fn duration_to_nanos(d: Duration) -> u64 {
+    d.as_secs()
+        .saturating_mul(1_000_000_000)
+        .saturating_add(u64::from(d.subsec_nanos()))
+}
+
+fn duration_to_millis(d: Duration) -> u64 {
+    d.as_secs()
+        .saturating_mul(1000)
+        .saturating_add(u64::from(d.subsec_millis()))
+}
+
+fn percent_of(n: u64, percent: u32) -> u64 {
+    (u128::from(n) * u128::from(percent) / 100) as u64
+}
+
+fn duration_to_seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1_000_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::{Service, ServiceBuilder};
+
+    #[test]
+    fn jitter_policy_works() {
+        let mut rng = rand::thread_rng();
+        let base = Duration::from_millis(100);
+        assert_eq!(JitterPolicy::None.apply(base, &mut rng), base);
+        for _ in 0..100 {
+            let d = JitterPolicy::PositivePercent(10).apply(base, &mut rng);
+            assert!(base <= d && d <= Duration::from_millis(110));
+
+            let d = JitterPolicy::Percent(10).apply(base, &mut rng);
+            assert!(Duration::from_millis(90) <= d && d <= Duration::from_millis(110));
+
+            let d = JitterPolicy::Full.apply(base, &mut rng);
+            assert!(d <= base);
+        }
+    }
+
+    #[test]
+    fn jitter_policy_is_reproducible_with_seeded_rng() {
+        let base = Duration::from_secs(30);
+        let mut rng0 = StdRng::from_seed([1; 32]);
+        let mut rng1 = StdRng::from_seed([1; 32]);
+        for _ in 0..10 {
+            assert_eq!(
+                JitterPolicy::Full.apply(base, &mut rng0),
+                JitterPolicy::Full.apply(base, &mut rng1)
+            );
+        }
+    }
+
+    #[test]
+    fn jitter_policy_accepts_tiny_intervals() {
+        let mut rng = rand::thread_rng();
+        let policies = [
+            JitterPolicy::None,
+            JitterPolicy::PositivePercent(10),
+            JitterPolicy::Percent(200),
+            JitterPolicy::Full,
+        ];
+        for policy in &policies {
+            for &nanos in &[0, 1, 9_999_999] {
+                let base = Duration::from_nanos(nanos);
+                assert!(policy.apply(base, &mut rng) <= base * 2);
+            }
+        }
+    }
+
+    #[test]
+    fn lease_expires_unless_renewed() {
+        let lease = Lease::new();
+        let duration = Duration::from_secs(10);
+        let now = Instant::now();
+        assert!(!lease.is_expired(duration, now));
+        assert!(lease.is_expired(duration, now + Duration::from_secs(11)));
+
+        lease.renew();
+        let renewed =
+            lease.epoch + Duration::from_millis(lease.renewed_millis.load(Ordering::Relaxed));
+        assert!(!lease.is_expired(duration, renewed + Duration::from_secs(9)));
+    }
+
+    #[test]
+    fn invalid_metric_labels_are_rejected() {
+        let service = ServiceBuilder::new("127.0.0.1:0".parse().unwrap())
+            .enable_metrics(false)
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        let finish = |labels: &[&str]| {
+            let mut builder = NodeBuilder::new();
+            for name in labels {
+                builder.metric_label(name, "foo");
+            }
+            builder
+                .try_finish::<String>(service.handle())
+                .map(|_| ())
+                .map_err(|e| *e.kind())
+        };
+        assert_eq!(finish(&["topic", "zone_1"]), Ok(()));
+        for labels in &[
+            &["1topic"][..],
+            &["to-pic"],
+            &["__topic"],
+            &["kind"],
+            &["le"],
+        ] {
+            assert_eq!(finish(labels), Err(ErrorKind::InvalidInput), "{:?}", labels);
+        }
+        assert_eq!(finish(&["topic", "topic"]), Err(ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn lease_is_deregistered_exactly_once() {
+        let lease = Lease::new();
+        assert!(lease.expire());
+        assert!(!lease.expire());
+        assert!(!lease.release());
+
+        assert!(lease.revive());
+        assert!(lease.release());
+        assert!(!lease.expire());
+        assert!(!lease.revive());
+    }
+
+    #[test]
+    fn broadcasting_known_message_id_is_confirmed_immediately() {
+        let service = Service::<String>::new(
+            "127.0.0.1:0".parse().unwrap(),
+            fibers_global::handle(),
+            SerialLocalNodeIdGenerator::new(),
+        );
+        let mut node = Node::new(service.handle());
+        node.set_message_id_policy(|node: NodeId, _seqno: u64, _payload: &String| {
+            MessageId::new(node, 0)
+        });
+
+        let peer = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(0));
+        node.plumtree_node.handle_neighbor_up(&peer);
+
+        let (id0, _confirmation0) = node.broadcast_with_confirmation("foo".to_owned());
+        let (id1, confirmation1) = node.broadcast_with_confirmation("foo".to_owned());
+        assert_eq!(id0, id1);
+        assert_eq!(confirmation1.wait().unwrap(), 0);
+        assert_eq!(node.metrics().broadcasted_messages(), 1);
+
+        // The confirmation of the first broadcast is still pending.
+        assert_eq!(node.pending_confirmations.len(), 1);
+        assert!(node.pending_confirmations[&id0].peers.contains(&peer));
+    }
+
+    fn poll_once(node: &mut Node<String>) {
+        futures::future::lazy(|| node.poll().map(|_| ()))
+            .wait()
+            .unwrap();
+    }
+
+    fn poll_until_not_ready(node: &mut Node<String>) -> Vec<Message<String>> {
+        futures::future::lazy(|| {
+            let mut messages = Vec::new();
+            while let Async::Ready(Some(m)) = track!(node.poll())? {
+                messages.push(m);
+            }
+            Ok::<_, Error>(messages)
+        })
+        .wait()
+        .unwrap()
+    }
+
+    fn peer(port: u16) -> NodeId {
+        NodeId::new(([127, 0, 0, 1], port).into(), LocalNodeId::new(0))
+    }
+
+    fn gossip(sender: NodeId, id: MessageId, payload: &str, round: u16) -> RpcMessage<String> {
+        RpcMessage::Plumtree(plumtree::message::ProtocolMessage::Gossip(GossipMessage {
+            sender,
+            round,
+            message: PlumtreeAppMessage {
+                id,
+                payload: Envelope::new(payload.to_owned()),
+            },
+        }))
+    }
+
+    #[test]
+    fn intervals_can_be_reconfigured() {
+        let service = Service::<String>::new(
+            "127.0.0.1:0".parse().unwrap(),
+            fibers_global::handle(),
+            SerialLocalNodeIdGenerator::new(),
+        );
+        let mut node = Node::new(service.handle());
+        let original = node.intervals();
+        assert_eq!(
+            original.hyparview_shuffle_interval,
+            Duration::from_secs(300)
+        );
+
+        let mut invalid = original;
+        invalid.hyparview_shuffle_interval = Duration::from_secs(0);
+        assert!(node.reconfigure(invalid).is_err());
+        assert_eq!(node.intervals(), original);
+
+        let intervals = Intervals {
+            tick_interval: Duration::from_millis(100),
+            hyparview_shuffle_interval: Duration::from_secs(1),
+            hyparview_sync_active_view_interval: Duration::from_secs(2),
+            hyparview_fill_active_view_interval: Duration::from_secs(3),
+        };
+        node.reconfigure(intervals).unwrap();
+        assert_eq!(node.intervals(), intervals);
+
+        // The next executions are rescheduled by the new intervals (with up to 10% jitter).
+        let now = node.plumtree_node.clock().now();
+        assert!(node.hyparview_shuffle_time <= now + Duration::from_millis(1100));
+        assert!(node.hyparview_sync_active_view_time <= now + Duration::from_millis(2200));
+        assert!(node.hyparview_fill_active_view_time <= now + Duration::from_millis(3300));
+    }
+
+    #[test]
+    fn neighbors_can_be_promoted_pruned_and_disconnected() {
+        let mut sim = crate::testing::SimulatorBuilder::new().finish::<String>();
+        let a = sim.add_node();
+        let b = sim.add_node();
+        sim.join(b, a);
+        sim.run_for(Duration::from_secs(1));
+        assert!(sim.node(a).hyparview_node().active_view().contains(&b));
+        assert!(sim.node(a).plumtree_node().eager_push_peers().contains(&b));
+
+        assert!(sim.node_mut(a).prune_peer(b));
+        assert!(sim.node(a).plumtree_node().lazy_push_peers().contains(&b));
+        assert!(!sim.node(a).plumtree_node().eager_push_peers().contains(&b));
+
+        assert!(sim.node_mut(a).promote_peer(b));
+        assert!(sim.node(a).plumtree_node().eager_push_peers().contains(&b));
+
+        let stranger = peer(3000);
+        assert!(!sim.node_mut(a).promote_peer(stranger));
+        assert!(!sim.node_mut(a).prune_peer(stranger));
+
+        sim.node_mut(a).disconnect_peer(b);
+        sim.run_for(Duration::from_millis(100));
+        assert!(!sim.node(a).hyparview_node().active_view().contains(&b));
+        assert!(!sim.node(b).hyparview_node().active_view().contains(&a));
+        assert!(sim.node(a).passive_view().contains(&b));
+    }
+
+    #[test]
+    fn expired_messages_are_neither_pushed_nor_announced() {
+        use plumtree::message::ProtocolMessage;
+
+        let (service, outbox) =
+            crate::testing::in_memory_service("127.0.0.1:3000".parse().unwrap());
+        let mut node = Node::<String>::new(service.handle());
+        let (eager, lazy) = (peer(3001), peer(3002));
+        node.plumtree_node.handle_neighbor_up(&eager);
+        node.plumtree_node.handle_neighbor_up(&lazy);
+        assert!(node.prune_peer(lazy));
+
+        let now = SystemTime::now();
+        let expired = node.broadcast_with_deadline("foo".to_owned(), now - Duration::from_secs(1));
+        let live = node.broadcast_with_deadline("bar".to_owned(), now + Duration::from_secs(3600));
+        poll_until_not_ready(&mut node);
+
+        let sent = outbox
+            .take()
+            .into_iter()
+            .filter_map(|(peer, m)| match m {
+                RpcMessage::Plumtree(ProtocolMessage::Gossip(m)) => Some((peer, m.message.id)),
+                RpcMessage::Plumtree(ProtocolMessage::Ihave(m)) => Some((peer, m.message_id)),
+                RpcMessage::HashedIhave(m, _) => Some((peer, m.message_id)),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        assert!(sent.iter().all(|&(_, id)| id != expired));
+        assert_eq!(
+            sent,
+            [(eager, live), (lazy, live)].iter().cloned().collect()
+        );
+    }
+
+    #[test]
+    fn delivered_messages_carry_receiving_metadata() {
+        let (service, _outbox) =
+            crate::testing::in_memory_service("127.0.0.1:3000".parse().unwrap());
+        let mut node = Node::<String>::new(service.handle());
+        let (first, second) = (peer(3001), peer(3002));
+        node.plumtree_node.handle_neighbor_up(&first);
+        node.plumtree_node.handle_neighbor_up(&second);
+
+        let id = MessageId::new(peer(3003), 0);
+        node.handle_rpc_message(gossip(first, id, "foo", 2));
+        node.handle_rpc_message(gossip(second, id, "foo", 5));
+        let messages = poll_until_not_ready(&mut node);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].received_from(), Some(first));
+        assert_eq!(messages[0].round(), Some(2));
+        assert_eq!(messages[0].duplicates(), 1);
+
+        // Duplicates received after the delivery are counted by the node.
+        let ihave = IhaveMessage {
+            sender: second,
+            round: 6,
+            message_id: id,
+            realtime: false,
+        };
+        node.handle_rpc_message(RpcMessage::Plumtree(
+            plumtree::message::ProtocolMessage::Ihave(ihave),
+        ));
+        assert_eq!(node.duplicates(&id), Some(2));
+
+        let own = node.broadcast("bar".to_owned());
+        let messages = poll_until_not_ready(&mut node);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(*messages[0].id(), own);
+        assert_eq!(messages[0].received_from(), None);
+        assert_eq!(messages[0].round(), None);
+    }
+
+    #[test]
+    fn broadcast_is_confirmed_after_eager_pushes() {
+        let (service, outbox) =
+            crate::testing::in_memory_service("127.0.0.1:3000".parse().unwrap());
+        let mut node = Node::<String>::new(service.handle());
+
+        // No eager push peers.
+        let (_, confirmation) = node.broadcast_with_confirmation("foo".to_owned());
+        assert_eq!(confirmation.wait().unwrap(), 0);
+
+        node.plumtree_node.handle_neighbor_up(&peer(3001));
+        node.plumtree_node.handle_neighbor_up(&peer(3002));
+        let (id, mut confirmation) = node.broadcast_with_confirmation("bar".to_owned());
+        assert_eq!(node.pending_confirmations[&id].peers.len(), 2);
+        let not_ready = futures::future::lazy(|| confirmation.poll())
+            .wait()
+            .unwrap();
+        assert_eq!(not_ready, Async::NotReady);
+
+        poll_until_not_ready(&mut node);
+        assert_eq!(confirmation.wait().unwrap(), 2);
+        assert!(node.pending_confirmations.is_empty());
+
+        let gossips = outbox
+            .take()
+            .into_iter()
+            .filter(|(_, m)| match m {
+                RpcMessage::Plumtree(plumtree::message::ProtocolMessage::Gossip(g)) => {
+                    g.message.id == id
+                }
+                _ => false,
+            })
+            .count();
+        assert_eq!(gossips, 2);
     }
 
-    /// Returns a reference to the underlying Plumtree node.
-    pub fn plumtree_node(&self) -> &PlumtreeNode<M> {
-        &self.plumtree_node
+    #[test]
+    fn forgetting_rebroadcasted_message_works() {
+        let service = Service::<String>::new(
+            "127.0.0.1:0".parse().unwrap(),
+            fibers_global::handle(),
+            SerialLocalNodeIdGenerator::new(),
+        );
+        let mut node = Node::new(service.handle());
+        let origin = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(0));
+        let id = MessageId::new(origin, 0);
+        let message = Message::new(PlumtreeAppMessage {
+            id,
+            payload: Envelope::new("foo".to_owned()),
+        });
+        assert!(node.rebroadcast(message));
+
+        poll_once(&mut node);
+        node.forget_message(&id);
+        poll_once(&mut node);
+        assert_eq!(node.metrics().delivered_messages(), 0);
+        assert_eq!(node.metrics().forgot_messages(), 0);
     }
 
-    /// Returns the clock of the node.
-    pub fn clock(&self) -> &Clock {
-        self.plumtree_node.clock()
+    #[test]
+    fn recv_with_timeout_fails_on_timeout() {
+        let service = ServiceBuilder::new("127.0.0.1:0".parse().unwrap())
+            .enable_metrics(false)
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        let mut node = Node::<String>::new(service.handle());
+
+        let e = node
+            .recv_with_timeout(Duration::from_secs(0))
+            .wait()
+            .err()
+            .unwrap();
+        assert_eq!(*e.kind(), ErrorKind::Timeout);
+
+        // The node can be polled again after the timeout.
+        node.broadcast("foo".to_owned());
+        let message = node
+            .recv_with_timeout(Duration::from_secs(0))
+            .wait()
+            .unwrap();
+        assert_eq!(message.map(|m| m.into_payload()), Some("foo".to_owned()));
     }
 
-    /// Returns the metrics of the service.
-    pub fn metrics(&self) -> &NodeMetrics {
-        &self.metrics
+    #[test]
+    fn cached_messages_are_counted_without_metrics() {
+        let service = ServiceBuilder::new("127.0.0.1:0".parse().unwrap())
+            .enable_metrics(false)
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        let mut node = Node::<String>::new(service.handle());
+        let id = node.broadcast("foo".to_owned());
+        poll_once(&mut node);
+        assert_eq!(node.status().cached_messages(), 1);
+        assert_eq!(node.metrics().delivered_messages(), 0);
+
+        node.forget_message(&id);
+        assert_eq!(node.status().cached_messages(), 0);
+
+        // Forgetting an unknown message never underflows the count.
+        node.forget_message(&id);
+        assert_eq!(node.status().cached_messages(), 0);
     }
 
-    fn handle_hyparview_action(&mut self, action: HyparviewAction) {
-        use hyparview::{Action, Event};
+    #[test]
+    fn deferred_forward_joins_are_bounded() {
+        use hyparview::message::{ForwardJoinMessage, ProtocolMessage};
 
-        match action {
-            Action::Send {
-                destination,
-                message,
-            } => {
-                debug!(
-                    self.logger,
-                    "Sends a HyParView message to {:?}: {:?}", destination, message
-                );
-                let message = RpcMessage::Hyparview(message);
-                if let Err(e) = self.service.send_message(destination, message) {
-                    warn!(
-                        self.logger,
-                        "Cannot send a HyParView message to {:?}: {}", destination, e
-                    );
-                    self.metrics
-                        .cannot_send_hyparview_message_errors
-                        .increment();
-                    self.hyparview_node.disconnect(&destination, false);
-                }
-            }
-            Action::Notify { event } => match event {
-                Event::NeighborUp { node } => {
-                    info!(
-                        self.logger,
-                        "Neighbor up: {:?} (active_view={:?})",
-                        node,
-                        self.hyparview_node.active_view()
-                    );
-                    self.metrics.connected_neighbors.increment();
-                    self.plumtree_node.handle_neighbor_up(&node);
-                    if self.hyparview_node.active_view().len() == 1 {
-                        self.metrics.deisolated_times.increment();
-                    }
-                }
-                Event::NeighborDown { node } => {
-                    info!(
-                        self.logger,
-                        "Neighbor down: {:?} (active_view={:?})",
-                        node,
-                        self.hyparview_node.active_view()
-                    );
-                    self.metrics.disconnected_neighbors.increment();
-                    self.plumtree_node.handle_neighbor_down(&node);
-                    if self.hyparview_node.active_view().is_empty() {
-                        self.metrics.isolated_times.increment();
-                    }
-                }
-            },
-            Action::Disconnect { node } => {
-                info!(self.logger, "Disconnected: {:?}", node);
-            }
+        let (service, outbox) =
+            crate::testing::in_memory_service("127.0.0.1:3000".parse().unwrap());
+        let mut node = NodeBuilder::new()
+            .max_forward_joins_per_tick(1)
+            .max_deferred_forward_joins(2)
+            .finish::<String>(service.handle());
+        let sent_forward_joins = || {
+            outbox
+                .take()
+                .into_iter()
+                .filter(|(_, m)| match m {
+                    RpcMessage::Hyparview(ProtocolMessage::ForwardJoin(_)) => true,
+                    _ => false,
+                })
+                .count()
+        };
+
+        let peer = NodeId::new("127.0.0.1:3001".parse().unwrap(), LocalNodeId::new(0));
+        for i in 0..5 {
+            let new_node = NodeId::new("127.0.0.1:3002".parse().unwrap(), LocalNodeId::new(i));
+            let message = ForwardJoinMessage {
+                sender: node.id(),
+                new_node,
+                ttl: hyparview::TimeToLive::new(3),
+            };
+            node.handle_hyparview_action(HyparviewAction::Send {
+                destination: peer,
+                message: ProtocolMessage::ForwardJoin(message),
+            });
         }
+        assert_eq!(sent_forward_joins(), 1);
+        assert_eq!(node.deferred_forward_joins.len(), 2);
+
+        node.handle_tick(Duration::from_millis(200));
+        assert_eq!(sent_forward_joins(), 1);
+        assert_eq!(node.deferred_forward_joins.len(), 1);
+
+        node.handle_tick(Duration::from_millis(200));
+        assert_eq!(sent_forward_joins(), 1);
+        assert!(node.deferred_forward_joins.is_empty());
     }
 
-    fn handle_plumtree_action(&mut self, action: PlumtreeAction<M>) -> Option<Message<M>> {
-        use plumtree::Action;
+    #[test]
+    fn static_members_are_connected_without_shuffles() {
+        use hyparview::message::{NeighborMessage, ProtocolMessage};
 
-        match action {
-            Action::Send {
-                destination,
-                message,
-            } => {
-                debug!(self.logger, "Sends a Plumtree message to {:?}", destination,);
-                let message = RpcMessage::Plumtree(message);
-                if let Err(e) = self.service.send_message(destination, message) {
-                    warn!(
-                        self.logger,
-                        "Cannot send a Plumtree message to {:?}: {}", destination, e
-                    );
-                    self.metrics.cannot_send_plumtree_message_errors.increment();
-                    self.hyparview_node.disconnect(&destination, false);
-                }
-                None
+        let shuffled_after_neighbor_up = |static_mode: bool| {
+            let (service, outbox) =
+                crate::testing::in_memory_service("127.0.0.1:3000".parse().unwrap());
+            let mut builder = NodeBuilder::new();
+            if static_mode {
+                builder.static_members(vec![peer(3000), peer(3001), peer(3002)]);
             }
-            Action::Deliver { message } => {
-                debug!(
-                    self.logger,
-                    "Delivers an application message: {:?}", message.id
-                );
-                self.metrics.delivered_messages.increment();
-                Some(Message::new(message))
+            let mut node = builder.finish::<String>(service.handle());
+            if !static_mode {
+                node.add_to_passive_view(vec![peer(3001), peer(3002)]);
+                node.hyparview_node.fill_active_view();
             }
-        }
+            assert_eq!(node.id(), peer(3000));
+            poll_until_not_ready(&mut node);
+
+            // The node asks one of the other members to be a neighbor.
+            let requested = outbox
+                .take()
+                .into_iter()
+                .filter_map(|(peer, m)| match m {
+                    RpcMessage::Hyparview(ProtocolMessage::Neighbor(_)) => Some(peer),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(requested.len(), 1);
+            assert!(requested[0] == peer(3001) || requested[0] == peer(3002));
+
+            node.handle_rpc_message(RpcMessage::Hyparview(ProtocolMessage::Neighbor(
+                NeighborMessage {
+                    sender: requested[0],
+                    high_priority: true,
+                },
+            )));
+            poll_until_not_ready(&mut node);
+            assert_eq!(node.hyparview_node.active_view(), &requested[..]);
+            outbox.take();
+
+            node.hyparview_shuffle_time = node.plumtree_node.clock().now();
+            node.handle_tick(Duration::from_millis(1));
+            poll_until_not_ready(&mut node);
+            outbox.take().into_iter().any(|(_, m)| match m {
+                RpcMessage::Hyparview(ProtocolMessage::Shuffle(_)) => true,
+                _ => false,
+            })
+        };
+        assert!(!shuffled_after_neighbor_up(true));
+        assert!(shuffled_after_neighbor_up(false));
     }
 
-    fn handle_rpc_message(&mut self, message: RpcMessage<M>) -> bool {
-        match message {
-            RpcMessage::Hyparview(m) => {
-                debug!(self.logger, "Received a HyParView message: {:?}", m);
-                self.hyparview_node.handle_protocol_message(m);
-                true
-            }
-            RpcMessage::Plumtree(m) => {
-                debug!(self.logger, "Received a Plumtree message");
-                if !self.plumtree_node.handle_protocol_message(m) {
-                    self.metrics.unknown_plumtree_node_errors.increment();
+    #[test]
+    fn delivery_watermarks_are_reported_once_per_crossing() {
+        let (service, _outbox) =
+            crate::testing::in_memory_service("127.0.0.1:3000".parse().unwrap());
+        let mut node = NodeBuilder::new()
+            .delivery_watermarks(3, 1)
+            .finish::<String>(service.handle());
+        let set_pending = |node: &mut Node<String>, n: usize| {
+            node.inbound_queue_len.store(n, Ordering::SeqCst);
+            node.check_watermarks();
+            node.poll_watermark()
+        };
+
+        assert_eq!(set_pending(&mut node, 2), Async::NotReady);
+        assert_eq!(
+            set_pending(&mut node, 3),
+            Async::Ready(DeliveryWatermark::High)
+        );
+        assert!(node.is_falling_behind());
+        assert_eq!(set_pending(&mut node, 5), Async::NotReady);
+        assert_eq!(set_pending(&mut node, 2), Async::NotReady);
+        assert!(node.is_falling_behind());
+        assert_eq!(
+            set_pending(&mut node, 1),
+            Async::Ready(DeliveryWatermark::Low)
+        );
+        assert!(!node.is_falling_behind());
+
+        // A crossing cancels the unreported crossing in the opposite direction.
+        node.inbound_queue_len.store(4, Ordering::SeqCst);
+        node.check_watermarks();
+        assert_eq!(set_pending(&mut node, 0), Async::NotReady);
+        assert!(!node.is_falling_behind());
+    }
+
+    #[test]
+    fn rejected_messages_are_relayed_but_not_delivered() {
+        use plumtree::message::ProtocolMessage;
+
+        let (service, outbox) =
+            crate::testing::in_memory_service("127.0.0.1:3000".parse().unwrap());
+        let mut node = Node::<String>::new(service.handle());
+        node.set_delivery_filter(|m: &Message<String>| !m.payload().starts_with("noise"));
+        let (sender, other) = (peer(3001), peer(3002));
+        node.plumtree_node.handle_neighbor_up(&sender);
+        node.plumtree_node.handle_neighbor_up(&other);
+
+        let noise = MessageId::new(peer(3003), 0);
+        let signal = MessageId::new(peer(3003), 1);
+        node.handle_rpc_message(gossip(sender, noise, "noise", 1));
+        node.handle_rpc_message(gossip(sender, signal, "signal", 1));
+        let messages = poll_until_not_ready(&mut node);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(*messages[0].id(), signal);
+
+        // The rejected message is still relayed and kept by the node.
+        let relayed = outbox
+            .take()
+            .into_iter()
+            .filter_map(|(peer, m)| match m {
+                RpcMessage::Plumtree(ProtocolMessage::Gossip(m)) if peer == other => {
+                    Some(m.message.id)
                 }
-                false
-            }
-        }
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        assert_eq!(relayed, [noise, signal].iter().cloned().collect());
+        assert_eq!(node.status().cached_messages(), 2);
+
+        node.broadcast("noise from myself".to_owned());
+        assert!(poll_until_not_ready(&mut node).is_empty());
     }
 
-    fn handle_tick(&mut self) {
-        self.plumtree_node
-            .clock_mut()
-            .tick(self.params.tick_interval);
+    #[test]
+    fn nodes_are_driven_by_shared_clock_of_service() {
+        let service = ServiceBuilder::new("127.0.0.1:0".parse().unwrap())
+            .enable_metrics(false)
+            .shared_tick_interval(Duration::from_secs(3600))
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        let shared = service.handle().clock().cloned().unwrap();
+        let mut a = Node::<String>::new(service.handle());
+        let mut b = Node::<String>::new(service.handle());
+        let own = clock::Clock::new();
+        let mut c = NodeBuilder::new()
+            .clock(own.clone())
+            .finish::<String>(service.handle());
+        assert_eq!(shared.subscribers(), 2);
+        assert_eq!(own.subscribers(), 1);
 
-        let now = self.plumtree_node.clock().now();
-        if now >= self.hyparview_shuffle_time {
-            self.hyparview_node.shuffle_passive_view();
-            self.hyparview_shuffle_time =
-                now + gen_interval(self.params.hyparview_shuffle_interval);
-        }
-        if now >= self.hyparview_sync_active_view_time {
-            self.hyparview_node.sync_active_view();
-            self.hyparview_sync_active_view_time =
-                now + gen_interval(self.params.hyparview_sync_active_view_interval);
-        }
-        if now >= self.hyparview_fill_active_view_time {
-            self.hyparview_node.fill_active_view();
-            self.hyparview_fill_active_view_time =
-                now + gen_interval(self.params.hyparview_fill_active_view_interval);
-        }
+        let starts = [a.clock().now(), b.clock().now(), c.clock().now()];
+        shared.tick(Duration::from_secs(10));
+        poll_once(&mut a);
+        poll_once(&mut b);
+        poll_once(&mut c);
+        assert_eq!(a.clock().now(), starts[0] + Duration::from_secs(10));
+        assert_eq!(b.clock().now(), starts[1] + Duration::from_secs(10));
+        assert_eq!(c.clock().now(), starts[2]);
     }
 
-    fn leave(&self) {
-        use hyparview::message::{DisconnectMessage, ProtocolMessage};
+    #[test]
+    fn stale_identities_are_disconnected_on_join() {
+        use hyparview::message::{JoinMessage, NeighborMessage, ProtocolMessage};
 
-        info!(
-            self.logger,
-            "Leaves the current cluster: active_view={:?}",
-            self.hyparview_node.active_view()
+        let active_view_after_rejoin = |disconnect_stale_identities: bool| {
+            let (service, _outbox) =
+                crate::testing::in_memory_service("127.0.0.1:3000".parse().unwrap());
+            let mut node = NodeBuilder::new()
+                .disconnect_stale_identities(disconnect_stale_identities)
+                .finish::<String>(service.handle());
+            let old = NodeId::new("127.0.0.1:3001".parse().unwrap(), LocalNodeId::new(0));
+            let other = peer(3002);
+            for &sender in &[old, other] {
+                node.handle_rpc_message(RpcMessage::Hyparview(ProtocolMessage::Neighbor(
+                    NeighborMessage {
+                        sender,
+                        high_priority: true,
+                    },
+                )));
+            }
+            poll_until_not_ready(&mut node);
+
+            // The node at the same address has restarted with a new identity.
+            let new = NodeId::new("127.0.0.1:3001".parse().unwrap(), LocalNodeId::new(1));
+            node.handle_rpc_message(RpcMessage::Hyparview(ProtocolMessage::Join(JoinMessage {
+                sender: new,
+            })));
+            poll_until_not_ready(&mut node);
+            node.hyparview_node
+                .active_view()
+                .iter()
+                .cloned()
+                .collect::<HashSet<_>>()
+        };
+
+        let expected = [
+            peer(3002),
+            NodeId::new("127.0.0.1:3001".parse().unwrap(), LocalNodeId::new(1)),
+        ];
+        assert_eq!(
+            active_view_after_rejoin(true),
+            expected.iter().cloned().collect()
         );
-        for peer in self.hyparview_node.active_view().iter().cloned() {
-            let message = DisconnectMessage {
-                sender: self.id(),
-                alive: false,
+        assert_eq!(active_view_after_rejoin(false).len(), 3);
+    }
+
+    #[test]
+    fn graft_requests_are_coalesced_and_served_as_repairs() {
+        use plumtree::message::ProtocolMessage;
+
+        let (service, outbox) =
+            crate::testing::in_memory_service("127.0.0.1:3000".parse().unwrap());
+        let mut node = Node::<String>::new(service.handle());
+        let (a, b) = (peer(3001), peer(3002));
+        node.plumtree_node.handle_neighbor_up(&a);
+        node.plumtree_node.handle_neighbor_up(&b);
+        assert!(node.prune_peer(a));
+        assert!(node.prune_peer(b));
+        let id = node.broadcast("foo".to_owned());
+        poll_until_not_ready(&mut node);
+        outbox.take();
+
+        let graft = |sender| {
+            RpcMessage::Plumtree(ProtocolMessage::Graft(GraftMessage {
+                sender,
+                message_id: Some(id),
+                round: 1,
+            }))
+        };
+        node.handle_rpc_message(graft(a));
+        node.handle_rpc_message(graft(a));
+        node.handle_rpc_message(graft(b));
+        poll_until_not_ready(&mut node);
+
+        let repairs = outbox
+            .take()
+            .into_iter()
+            .filter_map(|(peer, m)| match m {
+                RpcMessage::Plumtree(ProtocolMessage::Gossip(m)) => Some((peer, m)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(repairs.len(), 2);
+        assert!(repairs.iter().any(|(peer, _)| *peer == a));
+        assert!(repairs.iter().any(|(peer, _)| *peer == b));
+        assert!(repairs.iter().all(|(_, m)| m.message.payload.repair));
+
+        // The payload is encoded once for both requesters.
+        let encoded = repairs
+            .iter()
+            .map(|(_, m)| m.message.payload.encoded_payload.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert!(Arc::ptr_eq(&encoded[0], &encoded[1]));
+        assert_eq!(
+            &encoded[0][..],
+            &encode_payload(&"foo".to_owned()).unwrap()[..]
+        );
+        assert!(node.graft_requests.is_empty());
+    }
+
+    #[test]
+    fn realtime_ihaves_are_repaired_with_their_own_timeout() {
+        use plumtree::message::ProtocolMessage;
+
+        let (service, outbox) =
+            crate::testing::in_memory_service("127.0.0.1:3000".parse().unwrap());
+        let mut node = NodeBuilder::new()
+            .ihave_timeouts(Duration::from_millis(100), Duration::from_secs(10))
+            .finish::<String>(service.handle());
+        let sender = peer(3001);
+        node.plumtree_node.handle_neighbor_up(&sender);
+        assert!(node.prune_peer(sender));
+
+        let (realtime, non_realtime) =
+            (MessageId::new(peer(3002), 0), MessageId::new(peer(3002), 1));
+        for &(message_id, realtime) in &[(realtime, true), (non_realtime, false)] {
+            let ihave = IhaveMessage {
+                sender,
+                round: 1,
+                message_id,
+                realtime,
             };
-            let message = ProtocolMessage::Disconnect(message);
-            let message = RpcMessage::Hyparview(message);
-            let _ = self.service.send_message(peer, message);
+            node.handle_rpc_message(RpcMessage::Plumtree(ProtocolMessage::Ihave(ihave)));
         }
+        assert_eq!(
+            node.plumtree_node.options_mut().ihave_timeout,
+            Duration::from_secs(10)
+        );
+        let mut grafted_after = |elapsed| {
+            node.handle_tick(elapsed);
+            poll_until_not_ready(&mut node);
+            outbox
+                .take()
+                .into_iter()
+                .filter_map(|(_, m)| match m {
+                    RpcMessage::Plumtree(ProtocolMessage::Graft(m)) => m.message_id,
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(grafted_after(Duration::from_millis(200)), [realtime]);
+        assert_eq!(grafted_after(Duration::from_secs(10)), [non_realtime]);
     }
-}
-impl<M: MessagePayload> Stream for Node<M> {
-    type Item = Message<M>;
-    type Error = Error;
 
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        while track!(self.tick_timeout.poll().map_err(Error::from))?.is_ready() {
-            self.handle_tick();
-            self.tick_timeout = timer::timeout(self.params.tick_interval);
+    #[test]
+    fn periodic_broadcasts_are_driven_by_ticks() {
+        let (service, _outbox) =
+            crate::testing::in_memory_service("127.0.0.1:3000".parse().unwrap());
+        let mut node = Node::<String>::new(service.handle());
+        let mut seqno = 0;
+        let id = node.schedule_periodic_broadcast(Duration::from_secs(1), move || {
+            seqno += 1;
+            format!("heartbeat{}", seqno)
+        });
+        let mut delivered_after = |elapsed| {
+            node.handle_tick(elapsed);
+            poll_until_not_ready(&mut node)
+                .into_iter()
+                .map(|m| m.payload().clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert!(delivered_after(Duration::from_millis(500)).is_empty());
+        assert_eq!(delivered_after(Duration::from_millis(500)), ["heartbeat1"]);
+
+        // The broadcasts missed by a late tick are skipped.
+        assert_eq!(delivered_after(Duration::from_secs(5)), ["heartbeat2"]);
+        assert!(delivered_after(Duration::from_millis(500)).is_empty());
+
+        assert!(node.cancel_periodic_broadcast(id));
+        assert!(!node.cancel_periodic_broadcast(id));
+        assert!(node.periodic_broadcasts.is_empty());
+    }
+
+    #[test]
+    fn graceful_departures_are_distinguished_from_failures() {
+        use hyparview::message::{DisconnectMessage, NeighborMessage, ProtocolMessage};
+
+        let (service, _outbox) =
+            crate::testing::in_memory_service("127.0.0.1:3000".parse().unwrap());
+        let mut node = Node::<String>::new(service.handle());
+        let (leaving, failed) = (peer(3001), peer(3002));
+        for &sender in &[leaving, failed] {
+            node.handle_rpc_message(RpcMessage::Hyparview(ProtocolMessage::Neighbor(
+                NeighborMessage {
+                    sender,
+                    high_priority: true,
+                },
+            )));
         }
+        poll_until_not_ready(&mut node);
+        assert_eq!(node.hyparview_node.active_view().len(), 2);
 
-        let mut did_something = true;
-        while did_something {
-            did_something = false;
+        node.handle_rpc_message(RpcMessage::Hyparview(ProtocolMessage::Disconnect(
+            DisconnectMessage {
+                sender: leaving,
+                alive: false,
+            },
+        )));
+        poll_until_not_ready(&mut node);
+        node.disconnect_failed(failed);
+        poll_until_not_ready(&mut node);
 
-            while let Some(action) = self.hyparview_node.poll_action() {
-                self.handle_hyparview_action(action);
-                did_something = true;
-            }
-            while let Some(action) = self.plumtree_node.poll_action() {
-                if let Some(message) = self.handle_plumtree_action(action) {
-                    return Ok(Async::Ready(Some(message)));
-                }
-                did_something = true;
-            }
-            while let Async::Ready(message) = self.message_rx.poll().expect("Never fails") {
-                did_something = true;
-                let message = track_assert_some!(message, ErrorKind::Other, "Service down");
-                if self.handle_rpc_message(message) {
-                    break;
-                }
-            }
+        assert_eq!(
+            node.poll_neighbor_left(),
+            Async::Ready(NeighborLeft {
+                node: leaving,
+                graceful: true,
+            })
+        );
+        assert_eq!(
+            node.poll_neighbor_left(),
+            Async::Ready(NeighborLeft {
+                node: failed,
+                graceful: false,
+            })
+        );
+        assert_eq!(node.poll_neighbor_left(), Async::NotReady);
+        assert!(node.down_causes.is_empty());
+    }
+
+    #[test]
+    fn quarantined_nodes_are_evicted_from_passive_view() {
+        let service = ServiceBuilder::new("127.0.0.1:0".parse().unwrap())
+            .enable_metrics(false)
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        let mut node = NodeBuilder::new()
+            .quarantine_failure_threshold(1)
+            .quarantine_duration(Duration::from_secs(60))
+            .finish::<String>(service.handle());
+        let alive = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(0));
+        let dead = NodeId::new("127.0.0.1:3001".parse().unwrap(), LocalNodeId::new(0));
+        node.add_to_passive_view(vec![alive, dead]);
+        assert_eq!(node.passive_view().len(), 2);
+
+        let now = node.clock().now();
+        assert!(node.quarantine.handle_failure(dead, now));
+        node.handle_quarantined(dead);
+        assert_eq!(node.passive_view(), [alive]);
+        assert_eq!(node.status().passive_view, [alive]);
+        let snapshot = membership::decode_view_snapshot(&node.passive_view_snapshot()).unwrap();
+        assert_eq!(snapshot, [alive]);
+    }
+
+    #[test]
+    fn recent_message_ids_are_bounded() {
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(0));
+        let mut ids = RecentMessageIds::default();
+        for seqno in 0..RecentMessageIds::CAPACITY as u64 + 1 {
+            ids.insert(MessageId::new(node, seqno));
         }
-        Ok(Async::NotReady)
+        assert!(!ids.contains(&MessageId::new(node, 0)));
+        assert!(ids.contains(&MessageId::new(node, 1)));
+        assert_eq!(ids.ids.len(), RecentMessageIds::CAPACITY);
     }
-}
-impl<M: MessagePayload> Drop for Node<M> {
-    fn drop(&mut self) {
-        self.service.deregister_local_node(self.id().local_id());
 
-        let messages = self.metrics.delivered_messages() - self.metrics.forgot_messages();
-        self.metrics.forgot_messages.add_u64(messages);
+    #[test]
+    fn cache_budget_evicts_oldest_messages() {
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(0));
+        let id = |seqno| MessageId::new(node, seqno);
+        let mut budget = CacheBudget::new(100);
+        budget.insert(id(0), 40);
+        budget.insert(id(1), 40);
+        budget.insert(id(2), 10);
+        assert!(budget.evict().is_empty());
 
-        self.leave();
+        budget.remove(&id(1));
+        budget.insert(id(3), 80);
+        assert_eq!(budget.evict(), vec![(id(0), 40)]);
+        assert_eq!(budget.used, 90);
+
+        // The most recent message is kept even if it alone exceeds the budget.
+        budget.insert(id(4), 200);
+        assert_eq!(budget.evict(), vec![(id(2), 10), (id(3), 80)]);
+        assert_eq!(budget.used, 200);
     }
-}
 
-#[derive(Clone)]
-pub(crate) struct NodeHandle<M: MessagePayload> {
-    local_id: LocalNodeId,
-    message_tx: mpsc::Sender<RpcMessage<M>>,
-    metrics: NodeMetrics,
-}
-impl<M: MessagePayload> fmt::Debug for NodeHandle<M> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "NodeHandle {{ local_id: {:?}, .. }}", self.local_id)
+    #[test]
+    fn recent_content_hashes_expire() {
+        let now = Clock::new().now();
+        let mut hashes = RecentContentHashes::new(Duration::from_secs(10));
+        hashes.insert(1, now);
+        hashes.insert(2, now + Duration::from_secs(5));
+        assert!(hashes.contains(1, now + Duration::from_secs(9)));
+        assert!(!hashes.contains(1, now + Duration::from_secs(10)));
+
+        hashes.insert(3, now + Duration::from_secs(12));
+        assert_eq!(hashes.hashes.len(), 2);
+        assert!(hashes.contains(2, now + Duration::from_secs(12)));
     }
-}
-impl<M: MessagePayload> NodeHandle<M> {
-    pub(crate) fn local_id(&self) -> LocalNodeId {
-        self.local_id
+
+    #[test]
+    fn broadcast_quotas_work() {
+        let now = Clock::new().now();
+        let origin = |n| NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(n));
+        let mut quotas = BroadcastQuotas::new(2);
+
+        assert_eq!(quotas.acquire(origin(0), now), QuotaCheck::Acquired);
+        assert_eq!(quotas.acquire(origin(0), now), QuotaCheck::Acquired);
+        assert_eq!(
+            quotas.acquire(origin(0), now),
+            QuotaCheck::Exceeded { first: true }
+        );
+        assert_eq!(
+            quotas.acquire(origin(0), now),
+            QuotaCheck::Exceeded { first: false }
+        );
+        assert_eq!(quotas.acquire(origin(1), now), QuotaCheck::Acquired);
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(quotas.acquire(origin(0), later), QuotaCheck::Acquired);
     }
 
-    pub(crate) fn send_rpc_message(&self, message: RpcMessage<M>) {
-        let _ = self.message_tx.send(message);
+    #[test]
+    fn node_builder_validates_settings() {
+        assert!(NodeBuilder::new().validate().is_ok());
+        assert!(NodeBuilder::new()
+            .tick_interval(Duration::from_secs(0))
+            .validate()
+            .is_err());
+        assert!(NodeBuilder::new()
+            .tick_interval(Duration::from_secs(120))
+            .validate()
+            .is_err());
+
+        let mut options = HyparviewNodeOptions::default();
+        options.max_active_view_size = 0;
+        assert!(NodeBuilder::new()
+            .hyparview_options(options)
+            .validate()
+            .is_err());
+
+        assert!(NodeBuilder::new().max_joins_per_tick(0).validate().is_err());
+        assert!(NodeBuilder::new()
+            .max_messages_per_poll(0)
+            .validate()
+            .is_err());
+        assert!(NodeBuilder::new()
+            .max_broadcasts_per_origin(0)
+            .validate()
+            .is_err());
     }
 
-    pub(crate) fn metrics(&self) -> &NodeMetrics {
-        &self.metrics
+    #[test]
+    fn finish_panics_on_invalid_settings() {
+        let service = ServiceBuilder::new("127.0.0.1:0".parse().unwrap())
+            .enable_metrics(false)
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        let mut builder = NodeBuilder::new();
+        builder.tick_interval(Duration::from_secs(0));
+
+        let e = builder
+            .try_finish::<String>(service.handle())
+            .err()
+            .unwrap();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+
+        let handle = service.handle();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+            builder.finish::<String>(handle)
+        }));
+        assert!(result.is_err());
     }
-}
 
-#[derive(Debug, Clone)]
-struct Parameters {
-    tick_interval: Duration,
-    hyparview_shuffle_interval: Duration,
-    hyparview_sync_active_view_interval: Duration,
-    hyparview_fill_active_view_interval: Duration,
-}
+    #[test]
+    fn overload_policy_thresholds_work() {
+        let policy = OverloadPolicy {
+            max_pending_deliveries: Some(100),
+            max_cached_messages: Some(1000),
+            recovery_percent: 50,
+        };
+        assert!(!policy.reaches(99, 999, 100));
+        assert!(policy.reaches(100, 0, 100));
+        assert!(policy.reaches(0, 1000, 100));
 
-fn gen_interval(base: Duration) -> Duration {
-    let millis = base.as_secs() * 1000 + u64::from(base.subsec_millis());
-    let jitter = rand::random::<u64>() % (millis / 10);
-    base + Duration::from_millis(jitter)
+        assert!(policy.reaches(50, 0, policy.recovery_percent));
+        assert!(!policy.reaches(49, 499, policy.recovery_percent));
+
+        assert!(!OverloadPolicy::default().reaches(u64::max_value(), u64::max_value(), 100));
+    }
 }
@@ -1,8 +1,14 @@
+use crate::{Error, ErrorKind, Result};
 #[cfg(feature = "serialize")]
 use serde_derive::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::fmt;
+use std::fs;
+use std::io;
 use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use trackable::error::ErrorKindExt;
 
 /// Identifier used for distinguish local nodes in a process.
 ///
@@ -77,3 +83,67 @@ impl Ord for NodeId {
             .then_with(|| self.local_id.cmp(&other.local_id))
     }
 }
+impl FromStr for NodeId {
+    type Err = Error;
+
+    /// Parses a string in the format of `NodeId::to_string()` (i.e., `${LOCAL_ID_HEX}@${ADDRESS}`).
+    fn from_str(s: &str) -> Result<Self> {
+        let mut tokens = s.splitn(2, '@');
+        let local_id = tokens.next().expect("Never fails");
+        let address = track_assert_some!(tokens.next(), ErrorKind::InvalidInput; s);
+        let local_id = track!(u64::from_str_radix(local_id, 16)
+            .map_err(|e| ErrorKind::InvalidInput.cause(e)); s)?;
+        let address = track!(address
+            .parse()
+            .map_err(|e| ErrorKind::InvalidInput.cause(e)); s)?;
+        Ok(NodeId::new(address, LocalNodeId::new(local_id)))
+    }
+}
+
+/// Loads the node identifier saved by `save_node_id()`.
+///
+/// If the file does not exist, `Ok(None)` is returned.
+pub(crate) fn load_node_id<P: AsRef<Path>>(path: P) -> Result<Option<NodeId>> {
+    let s = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => track_panic!(ErrorKind::Other, "{}", e),
+    };
+    track!(s.trim().parse()).map(Some)
+}
+
+/// Saves the node identifier to the file.
+///
+/// The file is replaced atomically, so a crash while saving never leaves a broken file.
+pub(crate) fn save_node_id<P: AsRef<Path>>(path: P, id: NodeId) -> Result<()> {
+    let path = path.as_ref();
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    track!(fs::write(&temp, format!("{}\n", id)).map_err(|e| ErrorKind::Other.cause(e)))?;
+    track!(fs::rename(&temp, path).map_err(|e| ErrorKind::Other.cause(e)))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_id_can_be_parsed() {
+        let id = NodeId::new(
+            "127.0.0.1:3000".parse().unwrap(),
+            LocalNodeId::new(0xabcdef),
+        );
+        assert_eq!(id.to_string().parse::<NodeId>().unwrap(), id);
+
+        let id = NodeId::new(
+            "[::1]:3000".parse().unwrap(),
+            LocalNodeId::new(std::u64::MAX),
+        );
+        assert_eq!(id.to_string().parse::<NodeId>().unwrap(), id);
+
+        assert!("00000001".parse::<NodeId>().is_err());
+        assert!("xyz@127.0.0.1:3000".parse::<NodeId>().is_err());
+        assert!("00000001@127.0.0.1".parse::<NodeId>().is_err());
+    }
+}
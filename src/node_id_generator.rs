@@ -1,8 +1,10 @@
 use crate::node::LocalNodeId;
+use crate::random;
+use prometrics::metrics::{Counter, MetricBuilder};
 use std::fmt;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use std::time::UNIX_EPOCH;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
 
 /// This trait allows for generating the identifiers of the local nodes that belong to a [`Service`].
 ///
@@ -83,15 +85,47 @@ impl GenerateLocalNodeId for SerialLocalNodeIdGenerator {
 
 /// An implementation of [`GenerateLocalNodeId`] that generates identifiers based on UNIX time in nanoseconds.
 ///
+/// The generated identifiers are strictly increasing even if the wall clock goes backwards
+/// (e.g., by NTP corrections).
+/// In such cases, the generator falls back to incrementing the last identifier,
+/// and the metric `plumcast_node_id_generator_clock_regressions_total` is incremented.
+///
 /// [`GenerateLocalNodeId`]: ./trait.GenerateLocalNodeId.html
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct UnixtimeLocalNodeIdGenerator {
     nanos: AtomicUsize,
+    last: Mutex<LastUnixtimeId>,
+    clock_regressions: Counter,
 }
 impl UnixtimeLocalNodeIdGenerator {
     /// Makes a new `UnixtimeLocalNodeIdGenerator` instance.
     pub fn new() -> Self {
-        Self::default()
+        Self::with_metrics(MetricBuilder::new())
+    }
+
+    /// Makes a new `UnixtimeLocalNodeIdGenerator` instance that registers its metrics by using the given builder.
+    pub fn with_metrics(mut metrics: MetricBuilder) -> Self {
+        metrics.namespace("plumcast").subsystem("node_id_generator");
+        let clock_regressions = metrics
+            .counter("clock_regressions_total")
+            .help("Number of times the wall clock was detected to go backwards")
+            .finish()
+            .expect("Never fails");
+        UnixtimeLocalNodeIdGenerator {
+            nanos: AtomicUsize::new(0),
+            last: Mutex::new(LastUnixtimeId::default()),
+            clock_regressions,
+        }
+    }
+
+    /// Returns the number of times the wall clock was detected to go backwards.
+    pub fn clock_regressions(&self) -> u64 {
+        self.clock_regressions.value() as u64
+    }
+}
+impl Default for UnixtimeLocalNodeIdGenerator {
+    fn default() -> Self {
+        Self::new()
     }
 }
 impl GenerateLocalNodeId for UnixtimeLocalNodeIdGenerator {
@@ -102,12 +136,57 @@ impl GenerateLocalNodeId for UnixtimeLocalNodeIdGenerator {
                 let nanos = self.nanos.fetch_add(1, Ordering::SeqCst) as u64 % 1_000;
                 let micros = u64::from(d.subsec_micros()) * 1_000;
                 let id = d.as_secs() * 1_000_000_000 + micros + nanos;
+
+                let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+                if d < last.unixtime {
+                    self.clock_regressions.increment();
+                } else {
+                    last.unixtime = d;
+                }
+                let id = if id > last.id { id } else { last.id + 1 };
+                last.id = id;
                 LocalNodeId::new(id)
             }
         }
     }
 }
 
+/// An implementation of [`GenerateLocalNodeId`] that generates random identifiers.
+///
+/// Unlike [`UnixtimeLocalNodeIdGenerator`], the generated identifiers do not depend on
+/// the time at which the nodes are started, so they are unlikely to collide even if
+/// many nodes (possibly in different processes sharing the same address across restarts)
+/// are started at the same time.
+/// The identifiers are drawn uniformly from the 64-bit space
+/// (the probability of a collision among `n` identifiers is about `n^2 / 2^65`).
+///
+/// Note that `u64::MAX` is never generated, because it is reserved for
+/// the messages broadcasted by `ServiceHandle::broadcast_local()`.
+///
+/// [`GenerateLocalNodeId`]: ./trait.GenerateLocalNodeId.html
+/// [`UnixtimeLocalNodeIdGenerator`]: ./struct.UnixtimeLocalNodeIdGenerator.html
+#[derive(Debug, Default)]
+pub struct RandomLocalNodeIdGenerator {
+    _private: (),
+}
+impl RandomLocalNodeIdGenerator {
+    /// Makes a new `RandomLocalNodeIdGenerator` instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+impl GenerateLocalNodeId for RandomLocalNodeIdGenerator {
+    fn generate_local_node_id(&self) -> LocalNodeId {
+        LocalNodeId::new(random::random_u64() % std::u64::MAX)
+    }
+}
+
+#[derive(Debug, Default)]
+struct LastUnixtimeId {
+    id: u64,
+    unixtime: Duration,
+}
+
 #[cfg(test)]
 mod tests {
     use std;
@@ -127,6 +206,16 @@ mod tests {
         assert_eq!(generator.generate_local_node_id().value(), 1);
     }
 
+    #[test]
+    fn random_id_generator_works() {
+        let generator = RandomLocalNodeIdGenerator::new();
+        let ids = (0..1000)
+            .map(|_| generator.generate_local_node_id())
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(ids.len(), 1000);
+        assert!(ids.iter().all(|id| id.value() != std::u64::MAX));
+    }
+
     #[test]
     fn unixtime_id_generator_works() {
         let generator = UnixtimeLocalNodeIdGenerator::new();
@@ -135,4 +224,22 @@ mod tests {
         let id1 = generator.generate_local_node_id();
         assert_ne!(id0, id1);
     }
+
+    #[test]
+    fn unixtime_id_generator_is_monotonic() {
+        let generator = UnixtimeLocalNodeIdGenerator::new();
+        let mut prev = generator.generate_local_node_id();
+        for _ in 0..10_000 {
+            let id = generator.generate_local_node_id();
+            assert!(prev < id);
+            prev = id;
+        }
+
+        // Simulates a wall clock regression.
+        generator.last.lock().unwrap().unixtime += Duration::from_secs(60);
+        generator.last.lock().unwrap().id += 60_000_000_000;
+        let id = generator.generate_local_node_id();
+        assert!(prev < id);
+        assert_eq!(generator.clock_regressions(), 1);
+    }
 }
@@ -0,0 +1,220 @@
+//! [`NodePool`] and related components.
+//!
+//! [`NodePool`]: ./struct.NodePool.html
+use crate::message::{Message, MessagePayload};
+use crate::node::{LocalNodeId, Node};
+use crate::Error;
+use futures::{Async, Poll, Stream};
+use std::fmt;
+
+/// A [`Stream`] that drives many nodes in a single task.
+///
+/// Spawning a fiber for each node is costly when a process hosts hundreds of nodes
+/// (e.g., when simulating a large cluster in-process).
+/// A pool owns the nodes, polls them in a round-robin manner,
+/// and yields the messages delivered to them together with the identifiers of the receivers.
+///
+/// A node is removed from the pool when it stops or fails.
+/// In the latter case, the error is yielded by the pool, but the other nodes remain in the pool,
+/// so the pool can still be polled after that.
+///
+/// The stream never terminates, even if the pool becomes empty.
+///
+/// # Examples
+///
+/// ```no_run
+/// use futures::{Future, Stream};
+/// use plumcast::node::{Node, SerialLocalNodeIdGenerator};
+/// use plumcast::pool::NodePool;
+/// use plumcast::service::Service;
+///
+/// let service = Service::<String>::new(
+///     "127.0.0.1:4000".parse().unwrap(),
+///     fibers_global::handle(),
+///     SerialLocalNodeIdGenerator::new(),
+/// );
+/// let mut pool = NodePool::new();
+/// let contact = pool.add(Node::new(service.handle()));
+/// for _ in 0..100 {
+///     let mut node = Node::new(service.handle());
+///     node.join(pool.get(&contact).unwrap().id());
+///     pool.add(node);
+/// }
+/// fibers_global::spawn(service.map_err(|e| panic!("{}", e)));
+/// fibers_global::spawn(
+///     pool.for_each(|(receiver, message)| {
+///         println!("{:?} received {:?}", receiver, message.payload());
+///         Ok(())
+///     })
+///     .map_err(|e| panic!("{}", e)),
+/// );
+/// ```
+///
+/// [`Stream`]: https://docs.rs/futures/0.1/futures/stream/trait.Stream.html
+#[must_use = "streams do nothing unless polled"]
+pub struct NodePool<M: MessagePayload> {
+    nodes: Vec<Node<M>>,
+    next: usize,
+}
+impl<M: MessagePayload> NodePool<M> {
+    /// Makes a new empty `NodePool` instance.
+    pub fn new() -> Self {
+        NodePool {
+            nodes: Vec::new(),
+            next: 0,
+        }
+    }
+
+    /// Adds a node to the pool, and returns its local identifier.
+    pub fn add(&mut self, node: Node<M>) -> LocalNodeId {
+        let id = node.id().local_id();
+        self.nodes.push(node);
+        id
+    }
+
+    /// Removes the specified node from the pool.
+    ///
+    /// If the node does not exist, `None` is returned.
+    pub fn remove(&mut self, id: &LocalNodeId) -> Option<Node<M>> {
+        let i = self.position(id)?;
+        Some(self.remove_at(i))
+    }
+
+    /// Returns a reference to the specified node.
+    pub fn get(&self, id: &LocalNodeId) -> Option<&Node<M>> {
+        self.position(id).map(move |i| &self.nodes[i])
+    }
+
+    /// Returns a mutable reference to the specified node.
+    pub fn get_mut(&mut self, id: &LocalNodeId) -> Option<&mut Node<M>> {
+        let i = self.position(id)?;
+        Some(&mut self.nodes[i])
+    }
+
+    /// Returns an iterator over the nodes in the pool.
+    pub fn iter(&self) -> impl Iterator<Item = &Node<M>> {
+        self.nodes.iter()
+    }
+
+    /// Returns an iterator that allows modifying the nodes in the pool.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Node<M>> {
+        self.nodes.iter_mut()
+    }
+
+    /// Returns the number of the nodes in the pool.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns `true` if the pool has no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Takes ownership of the pool, and returns the nodes in it.
+    pub fn into_nodes(self) -> Vec<Node<M>> {
+        self.nodes
+    }
+
+    fn position(&self, id: &LocalNodeId) -> Option<usize> {
+        self.nodes.iter().position(|n| n.id().local_id() == *id)
+    }
+
+    fn remove_at(&mut self, i: usize) -> Node<M> {
+        if i < self.next {
+            self.next -= 1;
+        }
+        self.nodes.remove(i)
+    }
+}
+impl<M: MessagePayload> Default for NodePool<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<M: MessagePayload> Stream for NodePool<M> {
+    type Item = (LocalNodeId, Message<M>);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        // Every node is polled at most once per call, starting from the one next to
+        // the node that yielded the last message, so that busy nodes cannot starve the others.
+        let mut remaining = self.nodes.len();
+        while remaining > 0 {
+            remaining -= 1;
+            if self.next >= self.nodes.len() {
+                self.next = 0;
+            }
+            let i = self.next;
+            let id = self.nodes[i].id().local_id();
+            match self.nodes[i].poll() {
+                Err(e) => {
+                    self.remove_at(i);
+                    return Err(track!(e));
+                }
+                Ok(Async::Ready(None)) => {
+                    self.remove_at(i);
+                }
+                Ok(Async::Ready(Some(message))) => {
+                    self.next = i + 1;
+                    return Ok(Async::Ready(Some((id, message))));
+                }
+                Ok(Async::NotReady) => {
+                    self.next = i + 1;
+                }
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
+impl<M: MessagePayload> fmt::Debug for NodePool<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NodePool {{ nodes: {}, .. }}", self.nodes.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+
+    fn poll_until_not_ready(pool: &mut NodePool<String>) -> Vec<(LocalNodeId, String)> {
+        futures::future::lazy(|| {
+            let mut messages = Vec::new();
+            while let Async::Ready(Some((id, m))) = track!(pool.poll())? {
+                messages.push((id, m.payload().clone()));
+            }
+            Ok::<_, Error>(messages)
+        })
+        .wait()
+        .unwrap()
+    }
+
+    #[test]
+    fn nodes_are_polled_round_robin() {
+        let (service, _outbox) =
+            crate::testing::in_memory_service("127.0.0.1:3000".parse().unwrap());
+        let mut pool = NodePool::new();
+        let a = pool.add(Node::new(service.handle()));
+        let b = pool.add(Node::new(service.handle()));
+        assert_eq!(pool.len(), 2);
+
+        for payload in &["a0", "a1"] {
+            pool.get_mut(&a).unwrap().broadcast((*payload).to_owned());
+        }
+        for payload in &["b0", "b1"] {
+            pool.get_mut(&b).unwrap().broadcast((*payload).to_owned());
+        }
+        let expected = [(a, "a0"), (b, "b0"), (a, "a1"), (b, "b1")]
+            .iter()
+            .map(|&(id, p)| (id, p.to_owned()))
+            .collect::<Vec<_>>();
+        assert_eq!(poll_until_not_ready(&mut pool), expected);
+
+        let removed = pool.remove(&a).unwrap();
+        assert_eq!(removed.id().local_id(), a);
+        assert!(pool.get(&a).is_none());
+        pool.get_mut(&b).unwrap().broadcast("b2".to_owned());
+        assert_eq!(poll_until_not_ready(&mut pool), [(b, "b2".to_owned())]);
+    }
+}
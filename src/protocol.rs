@@ -0,0 +1,305 @@
+//! Versioning of the wire protocol.
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// The version of the wire protocol spoken by this crate.
+///
+/// Every RPC frame (except for handshakes) starts with the version byte
+/// followed by the extension section (see `codec::extension`).
+/// Note that `0` is never used as a version, because frames sent by the peers that
+/// predate versioning start with the (usually zero) high byte of a `LocalNodeId`.
+///
+/// History:
+/// - `1`: The initial versioned protocol.
+/// - `2`: Gossip frames carry the origin time of messages (see `Message::origin_time()`).
+pub(crate) const PROTOCOL_VERSION: u8 = 2;
+
+/// The oldest version of the wire protocol that this crate can still speak.
+pub(crate) const MIN_PROTOCOL_VERSION: u8 = 1;
+
+/// Returns `true` if frames of the given version can be decoded.
+pub(crate) fn is_supported_version(version: u8) -> bool {
+    MIN_PROTOCOL_VERSION <= version && version <= PROTOCOL_VERSION
+}
+
+/// Layout of the frames sent to a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Framing {
+    /// The layout used by the releases that predate versioning
+    /// (i.e., bare frame bodies sent via the legacy procedures).
+    ///
+    /// This is used for the peers that have not negotiated a version,
+    /// and the fields carried in the extension section are dropped.
+    Legacy,
+
+    /// Versioned frames of the given protocol version.
+    Versioned(u8),
+}
+
+/// Wire codec used to encode the bodies of RPC frames.
+///
+/// Every service speaks `WireCodec::Fixed`.
+/// The other codecs are used for the frames sent to a peer only if both services enable them
+/// (see [`ServiceBuilder::wire_codecs`]).
+///
+/// [`ServiceBuilder::wire_codecs`]: ../service/struct.ServiceBuilder.html#method.wire_codecs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WireCodec {
+    /// The default codec which encodes integers in fixed width.
+    Fixed,
+
+    /// A compact codec which encodes the local node identifiers, the Plumtree rounds and
+    /// the sequence numbers of message identifiers as varints (LEB128).
+    ///
+    /// This is currently applied to `IHAVE` frames, which are the most frequently sent ones.
+    /// For the common case (i.e., small sequence numbers and local identifiers),
+    /// the frames shrink by more than half (e.g., from 52 bytes to 23 bytes for IPv4 nodes).
+    Varint,
+}
+impl WireCodec {
+    /// Returns the identifier of the codec exchanged in handshakes.
+    pub fn id(self) -> u8 {
+        match self {
+            WireCodec::Fixed => 0,
+            WireCodec::Varint => 1,
+        }
+    }
+
+    /// Returns the codec that has the given identifier.
+    ///
+    /// If the identifier is unknown, `None` is returned.
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(WireCodec::Fixed),
+            1 => Some(WireCodec::Varint),
+            _ => None,
+        }
+    }
+}
+
+/// A message exchanged by services when they communicate with each other for the first time.
+///
+/// The message consists of a fixed part (`server_addr`, `min_version` and `max_version`)
+/// and a trailing list of the identifiers of the alternative codecs enabled by the sender
+/// (which is empty if no alternative codecs are enabled).
+/// The layout of the fixed part is never changed between versions,
+/// and the unknown identifiers in the trailing list are ignored on decoding,
+/// so new codecs can be advertised without breaking the services that do not know them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Handshake {
+    pub server_addr: SocketAddr,
+    pub min_version: u8,
+    pub max_version: u8,
+    pub codecs: Vec<WireCodec>,
+}
+impl Handshake {
+    pub(crate) fn local(server_addr: SocketAddr, codecs: &[WireCodec]) -> Self {
+        Handshake {
+            server_addr,
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            codecs: codecs.to_vec(),
+        }
+    }
+
+    /// Returns the highest version supported by both the local service and the sender.
+    pub(crate) fn negotiate(&self) -> Option<u8> {
+        let version = std::cmp::min(self.max_version, PROTOCOL_VERSION);
+        if version < std::cmp::max(self.min_version, MIN_PROTOCOL_VERSION) {
+            None
+        } else {
+            Some(version)
+        }
+    }
+
+    /// Returns the first codec in `local_codecs` (i.e., the codecs enabled by the local service
+    /// in order of preference) that is also enabled by the sender.
+    ///
+    /// If there is no such codec, `WireCodec::Fixed` is returned.
+    pub(crate) fn negotiate_codec(&self, local_codecs: &[WireCodec]) -> WireCodec {
+        local_codecs
+            .iter()
+            .find(|c| self.codecs.contains(c))
+            .cloned()
+            .unwrap_or(WireCodec::Fixed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PeerProtocol {
+    /// A handshake has been sent to the peer, but no reply has been received yet.
+    Negotiating,
+    /// The negotiated version and codec.
+    Compatible(u8, WireCodec),
+    Incompatible,
+}
+
+/// The maximum number of the peers which negotiation results are remembered by a service.
+const MAX_PEER_PROTOCOLS: usize = 4096;
+
+/// The protocol versions negotiated with the remote services (keyed by their RPC server addresses).
+///
+/// At most `capacity` peers are remembered, and the oldest one is forgotten when the limit is
+/// exceeded (so that a churning cluster cannot make the table grow without bound).
+/// Forgotten peers are treated as the ones that have never been contacted.
+#[derive(Debug, Clone)]
+pub(crate) struct PeerProtocols {
+    inner: Arc<Mutex<PeerProtocolsInner>>,
+    capacity: usize,
+}
+impl PeerProtocols {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        PeerProtocols {
+            inner: Default::default(),
+            capacity,
+        }
+    }
+
+    pub(crate) fn get(&self, peer: SocketAddr) -> Option<PeerProtocol> {
+        self.inner
+            .lock()
+            .ok()
+            .and_then(|inner| inner.peers.get(&peer).cloned())
+    }
+
+    /// Marks the peer as negotiating if it is unknown.
+    ///
+    /// Returns `true` if a handshake should be sent to the peer.
+    pub(crate) fn start_negotiation(&self, peer: SocketAddr) -> bool {
+        if let Ok(mut inner) = self.inner.lock() {
+            if !inner.peers.contains_key(&peer) {
+                inner.insert(peer, PeerProtocol::Negotiating, self.capacity);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Records the result of a negotiation, and returns the previous state of the peer.
+    pub(crate) fn complete_negotiation(
+        &self,
+        peer: SocketAddr,
+        protocol: PeerProtocol,
+    ) -> Option<PeerProtocol> {
+        self.inner
+            .lock()
+            .ok()
+            .and_then(|mut inner| inner.insert(peer, protocol, self.capacity))
+    }
+}
+impl Default for PeerProtocols {
+    fn default() -> Self {
+        Self::with_capacity(MAX_PEER_PROTOCOLS)
+    }
+}
+
+#[derive(Debug, Default)]
+struct PeerProtocolsInner {
+    peers: HashMap<SocketAddr, PeerProtocol>,
+    order: VecDeque<SocketAddr>,
+}
+impl PeerProtocolsInner {
+    fn insert(
+        &mut self,
+        peer: SocketAddr,
+        protocol: PeerProtocol,
+        capacity: usize,
+    ) -> Option<PeerProtocol> {
+        let previous = self.peers.insert(peer, protocol);
+        if previous.is_none() {
+            self.order.push_back(peer);
+            while self.order.len() > capacity {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.peers.remove(&oldest);
+                }
+            }
+        }
+        previous
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake(min_version: u8, max_version: u8) -> Handshake {
+        Handshake {
+            server_addr: "127.0.0.1:3000".parse().unwrap(),
+            min_version,
+            max_version,
+            codecs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn negotiation_works() {
+        assert_eq!(
+            Handshake::local("127.0.0.1:3000".parse().unwrap(), &[]).negotiate(),
+            Some(PROTOCOL_VERSION)
+        );
+        assert_eq!(
+            handshake(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION + 1).negotiate(),
+            Some(PROTOCOL_VERSION)
+        );
+        assert_eq!(
+            handshake(PROTOCOL_VERSION + 1, PROTOCOL_VERSION + 2).negotiate(),
+            None
+        );
+        assert_eq!(handshake(0, 0).negotiate(), None);
+        assert!(!is_supported_version(0));
+    }
+
+    #[test]
+    fn codec_negotiation_works() {
+        let h = handshake(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION);
+        assert_eq!(h.negotiate_codec(&[]), WireCodec::Fixed);
+        assert_eq!(h.negotiate_codec(&[WireCodec::Fixed]), WireCodec::Fixed);
+
+        assert_eq!(h.negotiate_codec(&[WireCodec::Varint]), WireCodec::Fixed);
+
+        let h = Handshake::local("127.0.0.1:3000".parse().unwrap(), &[WireCodec::Varint]);
+        assert_eq!(h.negotiate_codec(&[]), WireCodec::Fixed);
+        assert_eq!(h.negotiate_codec(&[WireCodec::Varint]), WireCodec::Varint);
+        for &c in &[WireCodec::Fixed, WireCodec::Varint] {
+            assert_eq!(WireCodec::from_id(c.id()), Some(c));
+        }
+        assert_eq!(WireCodec::from_id(255), None);
+    }
+
+    #[test]
+    fn handshake_is_sent_only_once() {
+        let peers = PeerProtocols::default();
+        let peer = "127.0.0.1:3000".parse().unwrap();
+        assert!(peers.start_negotiation(peer));
+        assert!(!peers.start_negotiation(peer));
+        assert_eq!(peers.get(peer), Some(PeerProtocol::Negotiating));
+
+        let compatible = PeerProtocol::Compatible(1, WireCodec::Fixed);
+        let previous = peers.complete_negotiation(peer, compatible);
+        assert_eq!(previous, Some(PeerProtocol::Negotiating));
+        assert_eq!(peers.get(peer), Some(compatible));
+    }
+
+    #[test]
+    fn oldest_peers_are_forgotten() {
+        let peers = PeerProtocols::with_capacity(2);
+        let addrs = (0..3)
+            .map(|i| SocketAddr::from(([127, 0, 0, 1], 3000 + i)))
+            .collect::<Vec<_>>();
+        assert!(peers.start_negotiation(addrs[0]));
+        assert!(peers.start_negotiation(addrs[1]));
+        let compatible = PeerProtocol::Compatible(1, WireCodec::Fixed);
+        peers.complete_negotiation(addrs[0], compatible);
+        assert_eq!(peers.get(addrs[0]), Some(compatible));
+
+        assert!(peers.start_negotiation(addrs[2]));
+        assert_eq!(peers.get(addrs[0]), None);
+        assert_eq!(peers.get(addrs[1]), Some(PeerProtocol::Negotiating));
+        assert_eq!(peers.get(addrs[2]), Some(PeerProtocol::Negotiating));
+
+        // A forgotten peer is negotiated again.
+        assert!(peers.start_negotiation(addrs[0]));
+        assert_eq!(peers.get(addrs[1]), None);
+    }
+}
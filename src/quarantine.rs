@@ -0,0 +1,138 @@
+use crate::node::NodeId;
+use plumtree::time::NodeTime;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// A list of the nodes that have repeatedly failed to become neighbors.
+///
+/// A HyParView `NEIGHBOR` request is regarded as failed if sending it fails or
+/// if the destination does not become a neighbor until the next execution of
+/// `HyparviewNode::fill_active_view()`.
+/// If the number of consecutive failures of a node reaches the threshold,
+/// the node is quarantined (i.e., requests to it are suppressed) for the configured period.
+#[derive(Debug, Clone)]
+pub(crate) struct Quarantine {
+    failure_threshold: usize,
+    duration: Duration,
+    pending: HashSet<NodeId>,
+    failures: HashMap<NodeId, usize>,
+    quarantined: HashMap<NodeId, NodeTime>,
+}
+impl Quarantine {
+    pub(crate) fn new(failure_threshold: usize, duration: Duration) -> Self {
+        Quarantine {
+            failure_threshold,
+            duration,
+            pending: HashSet::new(),
+            failures: HashMap::new(),
+            quarantined: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.failure_threshold > 0 && self.duration > Duration::from_secs(0)
+    }
+
+    pub(crate) fn is_quarantined(&mut self, node: &NodeId, now: NodeTime) -> bool {
+        match self.quarantined.get(node).cloned() {
+            None => false,
+            Some(until) if now < until => true,
+            Some(_) => {
+                self.quarantined.remove(node);
+                false
+            }
+        }
+    }
+
+    /// Same as `is_quarantined()` but does not forget the expired entry.
+    pub(crate) fn contains(&self, node: &NodeId, now: NodeTime) -> bool {
+        self.quarantined
+            .get(node)
+            .map_or(false, |&until| now < until)
+    }
+
+    pub(crate) fn handle_neighbor_request(&mut self, node: NodeId) {
+        if self.is_enabled() {
+            self.pending.insert(node);
+        }
+    }
+
+    pub(crate) fn handle_neighbor_up(&mut self, node: &NodeId) {
+        self.pending.remove(node);
+        self.failures.remove(node);
+        self.quarantined.remove(node);
+    }
+
+    /// Records a failure of the given node.
+    ///
+    /// Returns `true` if the node has been newly quarantined.
+    pub(crate) fn handle_failure(&mut self, node: NodeId, now: NodeTime) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        self.pending.remove(&node);
+        let failures = {
+            let n = self.failures.entry(node).or_insert(0);
+            *n += 1;
+            *n
+        };
+        if failures < self.failure_threshold {
+            return false;
+        }
+        self.failures.remove(&node);
+        self.quarantined.insert(node, now + self.duration);
+        true
+    }
+
+    /// Regards all the pending requests as failed.
+    ///
+    /// Returns the nodes that have been newly quarantined.
+    pub(crate) fn expire_pending_requests(&mut self, now: NodeTime) -> Vec<NodeId> {
+        let pending = self.pending.drain().collect::<Vec<_>>();
+        pending
+            .into_iter()
+            .filter(|&node| self.handle_failure(node, now))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::LocalNodeId;
+    use plumtree::time::Clock;
+
+    fn node(n: u64) -> NodeId {
+        NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(n))
+    }
+
+    #[test]
+    fn repeatedly_failed_nodes_are_quarantined() {
+        let mut clock = Clock::new();
+        let mut q = Quarantine::new(2, Duration::from_secs(10));
+
+        q.handle_neighbor_request(node(0));
+        q.handle_neighbor_request(node(1));
+        q.handle_neighbor_up(&node(1));
+        assert!(q.expire_pending_requests(clock.now()).is_empty());
+
+        q.handle_neighbor_request(node(0));
+        assert_eq!(q.expire_pending_requests(clock.now()), [node(0)]);
+        assert!(q.is_quarantined(&node(0), clock.now()));
+        assert!(!q.is_quarantined(&node(1), clock.now()));
+        assert!(q.contains(&node(0), clock.now()));
+
+        clock.tick(Duration::from_secs(10));
+        assert!(!q.contains(&node(0), clock.now()));
+        assert!(!q.is_quarantined(&node(0), clock.now()));
+    }
+
+    #[test]
+    fn disabled_quarantine_never_quarantines() {
+        let clock = Clock::new();
+        let mut q = Quarantine::new(1, Duration::from_secs(0));
+        q.handle_neighbor_request(node(0));
+        assert!(!q.handle_failure(node(0), clock.now()));
+        assert!(!q.is_quarantined(&node(0), clock.now()));
+    }
+}
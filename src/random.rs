@@ -0,0 +1,43 @@
+//! Sources of the random values that are not required to be reproducible.
+//!
+//! If the `thread-rng` feature is disabled, the values are derived from the randomly keyed
+//! hashers of the standard library instead of `rand::thread_rng()`.
+#[cfg(not(feature = "thread-rng"))]
+use std::collections::hash_map::RandomState;
+#[cfg(not(feature = "thread-rng"))]
+use std::hash::{BuildHasher, Hasher};
+#[cfg(not(feature = "thread-rng"))]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(feature = "thread-rng")]
+pub(crate) fn random_u64() -> u64 {
+    rand::random()
+}
+
+#[cfg(not(feature = "thread-rng"))]
+pub(crate) fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+pub(crate) fn random_seed() -> [u8; 32] {
+    let mut seed = [0; 32];
+    for chunk in seed.chunks_mut(8) {
+        chunk.copy_from_slice(&random_u64().to_le_bytes());
+    }
+    seed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_values_differ() {
+        assert_ne!(random_u64(), random_u64());
+        assert_ne!(random_seed(), random_seed());
+    }
+}
@@ -0,0 +1,580 @@
+//! Key/value announcements disseminated over plumcast broadcasts.
+//!
+//! A [`Registry`] drives a node, and each node can publish small entries (e.g., the addresses of
+//! the services running on the node) via a [`RegistryHandle`].
+//! The entries published by all the nodes are gossiped, and can be queried locally by
+//! [`RegistryHandle::get`], so service discovery announcements need no external store.
+//!
+//! Each entry has a TTL.
+//! The publisher re-broadcasts its entries every half of their TTLs, and
+//! the entries that are not refreshed within their TTLs (e.g., because the publishers crashed)
+//! expire at every node.
+//! The entries received after the nodes joined the cluster are refreshed in this way,
+//! so the nodes joining later also see all the entries within a TTL.
+//!
+//! Note that the expiry time of an entry is computed from the origin timestamp of
+//! the message carrying it (see [`Message::origin_time`]),
+//! so the clock skew between the nodes should be much smaller than the TTLs.
+//!
+//! [`Registry`]: ./struct.Registry.html
+//! [`RegistryHandle`]: ./struct.RegistryHandle.html
+//! [`RegistryHandle::get`]: ./struct.RegistryHandle.html#method.get
+//! [`Message::origin_time`]: ../message/struct.Message.html#method.origin_time
+use crate::message::{Message, MessageId, MessagePayload};
+use crate::node::{Node, NodeId};
+use crate::Error;
+use bytecodec::bytes::{BytesEncoder, RemainingBytesDecoder};
+use bytecodec::{ByteCount, Decode, Encode, Eos, ErrorKind, Result, SizedEncode};
+use futures::{Async, Future, Poll, Stream};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use trackable::error::ErrorKindExt;
+
+/// The maximum length of keys.
+const MAX_KEY_LEN: usize = 0xFFFF;
+
+/// A message that publishes (or unpublishes) an entry of a registry.
+///
+/// This is the payload of the messages broadcasted by [`Registry`].
+///
+/// [`Registry`]: ./struct.Registry.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryUpdate {
+    key: String,
+    value: Vec<u8>,
+    ttl: Duration,
+}
+impl RegistryUpdate {
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the value of the entry.
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    /// Returns the TTL of the entry.
+    ///
+    /// `Duration::from_secs(0)` means that the entry has been unpublished.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+}
+impl MessagePayload for RegistryUpdate {
+    type Encoder = RegistryUpdateEncoder;
+    type Decoder = RegistryUpdateDecoder;
+}
+
+/// An entry published by a node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryEntry {
+    node: NodeId,
+    value: Vec<u8>,
+    updated_time: SystemTime,
+    expiry_time: SystemTime,
+}
+impl RegistryEntry {
+    /// Returns the node that published the entry.
+    pub fn node(&self) -> NodeId {
+        self.node
+    }
+
+    /// Returns the value of the entry.
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    /// Returns the time at which the entry expires unless it is refreshed.
+    pub fn expiry_time(&self) -> SystemTime {
+        self.expiry_time
+    }
+}
+
+/// A [`Future`] that disseminates the entries of a registry via a node.
+///
+/// The registry is the application of the node,
+/// so received messages are forgot after the retention period has passed
+/// (see [`Registry::retention`]).
+///
+/// The future completes when the node stops.
+///
+/// [`Future`]: https://docs.rs/futures/0.1/futures/future/trait.Future.html
+/// [`Registry::retention`]: ./struct.Registry.html#method.retention
+#[must_use = "futures do nothing unless polled"]
+pub struct Registry {
+    node: Node<RegistryUpdate>,
+    shared: Arc<Mutex<SharedRegistry>>,
+    retention: Duration,
+    history: VecDeque<(Instant, MessageId)>,
+}
+impl Registry {
+    /// Makes a new `Registry` instance.
+    pub fn new(node: Node<RegistryUpdate>) -> Self {
+        let shared = SharedRegistry {
+            local_node: node.id(),
+            entries: HashMap::new(),
+            local_entries: HashMap::new(),
+            unpublished_keys: Vec::new(),
+        };
+        Registry {
+            node,
+            shared: Arc::new(Mutex::new(shared)),
+            retention: Duration::from_secs(60),
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Sets the period during which the node keeps the received messages.
+    ///
+    /// After the period has passed, the messages are forgot by the node
+    /// (i.e., `Node::forget_message()` is called).
+    ///
+    /// The default value is `Duration::from_secs(60)`.
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Returns a handle of the registry.
+    pub fn handle(&self) -> RegistryHandle {
+        RegistryHandle {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    /// Returns a reference to the node.
+    pub fn node(&self) -> &Node<RegistryUpdate> {
+        &self.node
+    }
+
+    /// Returns a mutable reference to the node.
+    pub fn node_mut(&mut self) -> &mut Node<RegistryUpdate> {
+        &mut self.node
+    }
+
+    fn handle_message(&mut self, message: Message<RegistryUpdate>) {
+        let id = *message.id();
+        self.history.push_back((Instant::now(), id));
+        self.shared.lock().expect("Never fails").handle_update(
+            id.node(),
+            message.origin_time(),
+            message.payload(),
+        );
+    }
+
+    fn broadcast_updates(&mut self) {
+        let updates = self
+            .shared
+            .lock()
+            .expect("Never fails")
+            .take_updates(Instant::now());
+        for update in updates {
+            self.node.broadcast(update);
+        }
+    }
+
+    fn forget_expired_messages(&mut self) {
+        let now = Instant::now();
+        while let Some(&(time, id)) = self.history.front() {
+            if now.duration_since(time) < self.retention {
+                break;
+            }
+            self.history.pop_front();
+            self.node.forget_message(&id);
+        }
+    }
+}
+impl Future for Registry {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        while let Async::Ready(message) = track!(self.node.poll())? {
+            if let Some(message) = message {
+                self.handle_message(message);
+            } else {
+                return Ok(Async::Ready(()));
+            }
+        }
+
+        // NOTE: The node wakes up this task at least every tick.
+        self.broadcast_updates();
+        self.forget_expired_messages();
+        self.shared
+            .lock()
+            .expect("Never fails")
+            .expire(SystemTime::now());
+        Ok(Async::NotReady)
+    }
+}
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Registry {{ node: {:?}, retention: {:?}, .. }}",
+            self.node.id(),
+            self.retention
+        )
+    }
+}
+
+/// A handle of a [`Registry`].
+///
+/// [`Registry`]: ./struct.Registry.html
+#[derive(Debug, Clone)]
+pub struct RegistryHandle {
+    shared: Arc<Mutex<SharedRegistry>>,
+}
+impl RegistryHandle {
+    /// Publishes the entry associated with `key` from the local node.
+    ///
+    /// If the local node has already published an entry with the same key, it is replaced.
+    /// The entry is broadcasted at the next tick of the node, and
+    /// re-broadcasted every half of `ttl` until it is unpublished.
+    ///
+    /// # Errors
+    ///
+    /// If `key` is longer than 65535 bytes or `ttl` is zero,
+    /// an `ErrorKind::InvalidInput` error will be returned.
+    pub fn publish(&self, key: &str, value: Vec<u8>, ttl: Duration) -> crate::Result<()> {
+        track_assert!(
+            key.len() <= MAX_KEY_LEN,
+            crate::ErrorKind::InvalidInput,
+            "Too long key: {} bytes",
+            key.len()
+        );
+        track_assert_ne!(ttl, Duration::from_secs(0), crate::ErrorKind::InvalidInput);
+        self.shared
+            .lock()
+            .expect("Never fails")
+            .publish(key, value, ttl, SystemTime::now());
+        Ok(())
+    }
+
+    /// Unpublishes the entry associated with `key` from the local node.
+    ///
+    /// Returns `false` if there is no such entry.
+    pub fn unpublish(&self, key: &str) -> bool {
+        self.shared.lock().expect("Never fails").unpublish(key)
+    }
+
+    /// Returns the live entries associated with `key` (in the order of the publishers).
+    pub fn get(&self, key: &str) -> Vec<RegistryEntry> {
+        let now = SystemTime::now();
+        let mut entries = self
+            .shared
+            .lock()
+            .expect("Never fails")
+            .entries
+            .get(key)
+            .map(|entries| {
+                entries
+                    .values()
+                    .filter(|e| now < e.expiry_time)
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        entries.sort_by_key(|e| e.node);
+        entries
+    }
+
+    /// Returns the keys that have live entries (in ascending order).
+    pub fn keys(&self) -> Vec<String> {
+        let now = SystemTime::now();
+        let mut keys = self
+            .shared
+            .lock()
+            .expect("Never fails")
+            .entries
+            .iter()
+            .filter(|(_, entries)| entries.values().any(|e| now < e.expiry_time))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+}
+
+#[derive(Debug)]
+struct SharedRegistry {
+    local_node: NodeId,
+    entries: HashMap<String, HashMap<NodeId, RegistryEntry>>,
+    local_entries: HashMap<String, LocalEntry>,
+    unpublished_keys: Vec<String>,
+}
+impl SharedRegistry {
+    fn publish(&mut self, key: &str, value: Vec<u8>, ttl: Duration, now: SystemTime) {
+        self.unpublished_keys.retain(|k| k != key);
+        let entry = RegistryEntry {
+            node: self.local_node,
+            value: value.clone(),
+            updated_time: now,
+            expiry_time: now + ttl,
+        };
+        self.entries
+            .entry(key.to_owned())
+            .or_default()
+            .insert(self.local_node, entry);
+        let local = LocalEntry {
+            value,
+            ttl,
+            broadcast_time: None,
+        };
+        self.local_entries.insert(key.to_owned(), local);
+    }
+
+    fn unpublish(&mut self, key: &str) -> bool {
+        if self.local_entries.remove(key).is_none() {
+            return false;
+        }
+        self.remove_entry(key, self.local_node);
+        self.unpublished_keys.push(key.to_owned());
+        true
+    }
+
+    fn handle_update(&mut self, node: NodeId, origin_time: SystemTime, update: &RegistryUpdate) {
+        let is_stale = self
+            .entries
+            .get(&update.key)
+            .and_then(|entries| entries.get(&node))
+            .map_or(false, |e| origin_time < e.updated_time);
+        if is_stale {
+            return;
+        }
+
+        let expiry_time = origin_time + update.ttl;
+        if expiry_time <= SystemTime::now() {
+            self.remove_entry(&update.key, node);
+            return;
+        }
+        let entry = RegistryEntry {
+            node,
+            value: update.value.clone(),
+            updated_time: origin_time,
+            expiry_time,
+        };
+        self.entries
+            .entry(update.key.clone())
+            .or_default()
+            .insert(node, entry);
+    }
+
+    fn take_updates(&mut self, now: Instant) -> Vec<RegistryUpdate> {
+        let mut updates = self
+            .unpublished_keys
+            .drain(..)
+            .map(|key| RegistryUpdate {
+                key,
+                value: Vec::new(),
+                ttl: Duration::from_secs(0),
+            })
+            .collect::<Vec<_>>();
+        for (key, entry) in &mut self.local_entries {
+            let refresh_interval = entry.ttl / 2;
+            let is_due = entry
+                .broadcast_time
+                .map_or(true, |t| now.duration_since(t) >= refresh_interval);
+            if is_due {
+                entry.broadcast_time = Some(now);
+                updates.push(RegistryUpdate {
+                    key: key.clone(),
+                    value: entry.value.clone(),
+                    ttl: entry.ttl,
+                });
+            }
+        }
+        updates
+    }
+
+    fn remove_entry(&mut self, key: &str, node: NodeId) {
+        let is_empty = if let Some(entries) = self.entries.get_mut(key) {
+            entries.remove(&node);
+            entries.is_empty()
+        } else {
+            false
+        };
+        if is_empty {
+            self.entries.remove(key);
+        }
+    }
+
+    fn expire(&mut self, now: SystemTime) {
+        for entries in self.entries.values_mut() {
+            entries.retain(|_, e| now < e.expiry_time);
+        }
+        self.entries.retain(|_, entries| !entries.is_empty());
+    }
+}
+
+#[derive(Debug)]
+struct LocalEntry {
+    value: Vec<u8>,
+    ttl: Duration,
+    broadcast_time: Option<Instant>,
+}
+
+/// Decoder of [`RegistryUpdate`].
+///
+/// [`RegistryUpdate`]: ./struct.RegistryUpdate.html
+#[derive(Debug, Default)]
+pub struct RegistryUpdateDecoder(RemainingBytesDecoder);
+impl Decode for RegistryUpdateDecoder {
+    type Item = RegistryUpdate;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> Result<usize> {
+        track!(self.0.decode(buf, eos))
+    }
+
+    fn finish_decoding(&mut self) -> Result<Self::Item> {
+        let bytes = track!(self.0.finish_decoding())?;
+        track!(decode_update(&bytes))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.0.requiring_bytes()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.0.is_idle()
+    }
+}
+
+/// Encoder of [`RegistryUpdate`].
+///
+/// [`RegistryUpdate`]: ./struct.RegistryUpdate.html
+#[derive(Debug, Default)]
+pub struct RegistryUpdateEncoder(BytesEncoder<Vec<u8>>);
+impl Encode for RegistryUpdateEncoder {
+    type Item = RegistryUpdate;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> Result<usize> {
+        track!(self.0.encode(buf, eos))
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> Result<()> {
+        let bytes = track!(encode_update(&item))?;
+        track!(self.0.start_encoding(bytes))
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.0.requiring_bytes()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.0.is_idle()
+    }
+}
+impl SizedEncode for RegistryUpdateEncoder {
+    fn exact_requiring_bytes(&self) -> u64 {
+        self.0.exact_requiring_bytes()
+    }
+}
+
+// Layout: key length (u16be), key (UTF-8), TTL in milliseconds (u64be) and value (remaining bytes).
+fn encode_update(update: &RegistryUpdate) -> Result<Vec<u8>> {
+    track_assert!(
+        update.key.len() <= MAX_KEY_LEN,
+        ErrorKind::InvalidInput,
+        "Too long key: {} bytes",
+        update.key.len()
+    );
+    let ttl = update.ttl.as_secs() * 1000 + u64::from(update.ttl.subsec_millis());
+    let mut bytes = Vec::with_capacity(2 + update.key.len() + 8 + update.value.len());
+    bytes.extend_from_slice(&(update.key.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(update.key.as_bytes());
+    bytes.extend_from_slice(&ttl.to_be_bytes());
+    bytes.extend_from_slice(&update.value);
+    Ok(bytes)
+}
+
+fn decode_update(bytes: &[u8]) -> Result<RegistryUpdate> {
+    track_assert!(bytes.len() >= 2, ErrorKind::InvalidInput);
+    let key_len = usize::from(u16::from_be_bytes([bytes[0], bytes[1]]));
+    let bytes = &bytes[2..];
+    track_assert!(bytes.len() >= key_len + 8, ErrorKind::InvalidInput);
+
+    let key =
+        track!(String::from_utf8(bytes[..key_len].to_vec())
+            .map_err(|e| ErrorKind::InvalidInput.cause(e)))?;
+    let mut ttl = [0; 8];
+    ttl.copy_from_slice(&bytes[key_len..][..8]);
+    let ttl = Duration::from_millis(u64::from_be_bytes(ttl));
+    let value = bytes[key_len + 8..].to_vec();
+    Ok(RegistryUpdate { key, value, ttl })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::LocalNodeId;
+    use bytecodec::{DecodeExt, EncodeExt};
+
+    fn node(id: u64) -> NodeId {
+        NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(id))
+    }
+
+    fn update(key: &str, value: &[u8], ttl: u64) -> RegistryUpdate {
+        RegistryUpdate {
+            key: key.to_owned(),
+            value: value.to_vec(),
+            ttl: Duration::from_secs(ttl),
+        }
+    }
+
+    #[test]
+    fn registry_update_codec_works() {
+        let update = update("web", b"127.0.0.1:80", 30);
+        let bytes = RegistryUpdateEncoder::default()
+            .encode_into_bytes(update.clone())
+            .unwrap();
+        let decoded = RegistryUpdateDecoder::default()
+            .decode_from_bytes(&bytes)
+            .unwrap();
+        assert_eq!(decoded, update);
+
+        assert!(RegistryUpdateDecoder::default()
+            .decode_from_bytes(&bytes[..5])
+            .is_err());
+    }
+
+    #[test]
+    fn registry_entries_work() {
+        let mut registry = SharedRegistry {
+            local_node: node(0),
+            entries: HashMap::new(),
+            local_entries: HashMap::new(),
+            unpublished_keys: Vec::new(),
+        };
+        let now = SystemTime::now();
+        registry.publish("web", b"a".to_vec(), Duration::from_secs(10), now);
+        registry.handle_update(node(1), now, &update("web", b"b", 10));
+        assert_eq!(registry.entries["web"].len(), 2);
+
+        // Stale updates are ignored.
+        let past = now - Duration::from_secs(1);
+        registry.handle_update(node(1), past, &update("web", b"c", 10));
+        assert_eq!(registry.entries["web"][&node(1)].value(), b"b");
+
+        // Unpublished.
+        registry.handle_update(node(1), now, &update("web", b"", 0));
+        assert_eq!(registry.entries["web"].len(), 1);
+
+        let updates = registry.take_updates(Instant::now());
+        assert_eq!(updates, vec![update("web", b"a", 10)]);
+        assert!(registry.take_updates(Instant::now()).is_empty());
+
+        assert!(registry.unpublish("web"));
+        assert!(!registry.entries.contains_key("web"));
+        let updates = registry.take_updates(Instant::now());
+        assert_eq!(updates, vec![update("web", b"", 0)]);
+
+        registry.handle_update(node(2), now, &update("db", b"d", 10));
+        registry.expire(now + Duration::from_secs(10));
+        assert!(registry.entries.is_empty());
+    }
+}
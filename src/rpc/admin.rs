@@ -0,0 +1,161 @@
+use super::RpcMessage;
+use crate::admin::{
+    DebugDumpQuery, DebugEvent, Health, HealthCheck, NodeStatus, NodeStatusQuery, ParameterUpdate,
+};
+use crate::codec::admin::{
+    DebugEventDecoder, DebugEventEncoder, HealthDecoder, HealthEncoder, ListDecoder, ListEncoder,
+    NodeStatusDecoder, NodeStatusEncoder, ParameterUpdateMessageDecoder,
+    ParameterUpdateMessageEncoder,
+};
+use crate::codec::version::{VersionedDecoder, VersionedEncoder};
+use crate::message::MessagePayload;
+use crate::node::{LocalNodeId, NodeId};
+use crate::service::ServiceHandle;
+use crate::Result;
+use bytecodec::null::{NullDecoder, NullEncoder};
+use fibers_rpc::client::ClientServiceHandle;
+use fibers_rpc::server::{HandleCall, HandleCast, NoReply, Reply, ServerBuilder};
+use fibers_rpc::{Call, Cast, ProcedureId};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+const PING_TIMEOUT: Duration = Duration::from_secs(5); // FIXME: parameterize
+
+pub fn register_handlers<M: MessagePayload>(rpc: &mut ServerBuilder, service: &ServiceHandle<M>) {
+    rpc.add_cast_handler(UpdateParameterHandler(service.clone()));
+    rpc.add_call_handler(PingHandler(service.clone()));
+    rpc.add_call_handler(NodeStatusesHandler(service.clone()));
+    rpc.add_call_handler(DebugDumpHandler(service.clone()));
+}
+
+#[derive(Debug)]
+pub struct UpdateParameterCast;
+impl Cast for UpdateParameterCast {
+    const ID: ProcedureId = ProcedureId(0x17CE_0000);
+    const NAME: &'static str = "admin.update_parameter";
+
+    type Notification = (LocalNodeId, SocketAddr, ParameterUpdate);
+    type Decoder = VersionedDecoder<ParameterUpdateMessageDecoder>;
+    type Encoder = VersionedEncoder<ParameterUpdateMessageEncoder>;
+}
+
+pub fn update_parameter_cast(
+    peer: NodeId,
+    caller: SocketAddr,
+    m: ParameterUpdate,
+    service: &ClientServiceHandle,
+) -> Result<()> {
+    let mut client = UpdateParameterCast::client(service);
+    client.options_mut().force_wakeup = true;
+    client.options_mut().priority = 100;
+    track!(client.cast(peer.address(), (peer.local_id(), caller, m)))?;
+    Ok(())
+}
+
+#[derive(Debug)]
+struct UpdateParameterHandler<M: MessagePayload>(ServiceHandle<M>);
+impl<M: MessagePayload> HandleCast<UpdateParameterCast> for UpdateParameterHandler<M> {
+    fn handle_cast(&self, (id, caller, m): (LocalNodeId, SocketAddr, ParameterUpdate)) -> NoReply {
+        if !self.0.is_parameter_update_allowed(caller, &m) {
+            return NoReply::done();
+        }
+        if let Some(node) = self.0.get_local_node(id) {
+            node.send_rpc_message(RpcMessage::Admin(m));
+        }
+        NoReply::done()
+    }
+}
+
+#[derive(Debug)]
+pub struct PingRpc;
+impl Call for PingRpc {
+    const ID: ProcedureId = ProcedureId(0x17CE_0001);
+    const NAME: &'static str = "admin.ping";
+
+    type Req = ();
+    type ReqDecoder = VersionedDecoder<NullDecoder>;
+    type ReqEncoder = VersionedEncoder<NullEncoder>;
+
+    type Res = Health;
+    type ResDecoder = VersionedDecoder<HealthDecoder>;
+    type ResEncoder = VersionedEncoder<HealthEncoder>;
+}
+
+pub fn ping(server: SocketAddr, service: &ClientServiceHandle) -> HealthCheck {
+    let mut client = PingRpc::client(service);
+    client.options_mut().timeout = Some(PING_TIMEOUT);
+    client.options_mut().force_wakeup = true;
+    client.options_mut().priority = 100;
+    HealthCheck(client.call(server, ()))
+}
+
+#[derive(Debug)]
+struct PingHandler<M: MessagePayload>(ServiceHandle<M>);
+impl<M: MessagePayload> HandleCall<PingRpc> for PingHandler<M> {
+    fn handle_call(&self, (): ()) -> Reply<PingRpc> {
+        let local_nodes = self.0.local_nodes().len() as u64;
+        Reply::done(Health { local_nodes })
+    }
+}
+
+#[derive(Debug)]
+pub struct NodeStatusesRpc;
+impl Call for NodeStatusesRpc {
+    const ID: ProcedureId = ProcedureId(0x17CE_0002);
+    const NAME: &'static str = "admin.node_statuses";
+
+    type Req = ();
+    type ReqDecoder = VersionedDecoder<NullDecoder>;
+    type ReqEncoder = VersionedEncoder<NullEncoder>;
+
+    type Res = Vec<NodeStatus>;
+    type ResDecoder = VersionedDecoder<ListDecoder<NodeStatusDecoder>>;
+    type ResEncoder = VersionedEncoder<ListEncoder<NodeStatusEncoder>>;
+}
+
+pub fn node_statuses(server: SocketAddr, service: &ClientServiceHandle) -> NodeStatusQuery {
+    let mut client = NodeStatusesRpc::client(service);
+    client.options_mut().timeout = Some(PING_TIMEOUT);
+    client.options_mut().force_wakeup = true;
+    client.options_mut().priority = 100;
+    NodeStatusQuery(client.call(server, ()))
+}
+
+#[derive(Debug)]
+struct NodeStatusesHandler<M: MessagePayload>(ServiceHandle<M>);
+impl<M: MessagePayload> HandleCall<NodeStatusesRpc> for NodeStatusesHandler<M> {
+    fn handle_call(&self, (): ()) -> Reply<NodeStatusesRpc> {
+        Reply::done(self.0.local_node_statuses())
+    }
+}
+
+#[derive(Debug)]
+pub struct DebugDumpRpc;
+impl Call for DebugDumpRpc {
+    const ID: ProcedureId = ProcedureId(0x17CE_0003);
+    const NAME: &'static str = "admin.debug_dump";
+
+    type Req = ();
+    type ReqDecoder = VersionedDecoder<NullDecoder>;
+    type ReqEncoder = VersionedEncoder<NullEncoder>;
+
+    type Res = Vec<DebugEvent>;
+    type ResDecoder = VersionedDecoder<ListDecoder<DebugEventDecoder>>;
+    type ResEncoder = VersionedEncoder<ListEncoder<DebugEventEncoder>>;
+}
+
+pub fn debug_dump(server: SocketAddr, service: &ClientServiceHandle) -> DebugDumpQuery {
+    let mut client = DebugDumpRpc::client(service);
+    client.options_mut().timeout = Some(PING_TIMEOUT);
+    client.options_mut().force_wakeup = true;
+    client.options_mut().priority = 100;
+    DebugDumpQuery(client.call(server, ()))
+}
+
+#[derive(Debug)]
+struct DebugDumpHandler<M: MessagePayload>(ServiceHandle<M>);
+impl<M: MessagePayload> HandleCall<DebugDumpRpc> for DebugDumpHandler<M> {
+    fn handle_call(&self, (): ()) -> Reply<DebugDumpRpc> {
+        Reply::done(self.0.local_debug_dump())
+    }
+}
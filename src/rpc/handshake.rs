@@ -0,0 +1,42 @@
+use crate::codec::version::{HandshakeDecoder, HandshakeEncoder};
+use crate::message::MessagePayload;
+use crate::protocol::Handshake;
+use crate::service::ServiceHandle;
+use crate::Result;
+use fibers_rpc::client::ClientServiceHandle;
+use fibers_rpc::server::{HandleCast, NoReply, ServerBuilder};
+use fibers_rpc::{Cast, ProcedureId};
+use std::net::SocketAddr;
+
+pub fn register_handlers<M: MessagePayload>(rpc: &mut ServerBuilder, service: &ServiceHandle<M>) {
+    rpc.add_cast_handler(HandshakeHandler(service.clone()));
+}
+
+// NOTE: Handshake frames have no version byte (their layout never changes).
+#[derive(Debug)]
+pub struct HandshakeCast;
+impl Cast for HandshakeCast {
+    const ID: ProcedureId = ProcedureId(0x17CF_0000);
+    const NAME: &'static str = "protocol.handshake";
+
+    type Notification = Handshake;
+    type Decoder = HandshakeDecoder;
+    type Encoder = HandshakeEncoder;
+}
+
+pub fn handshake_cast(peer: SocketAddr, m: Handshake, service: &ClientServiceHandle) -> Result<()> {
+    let mut client = HandshakeCast::client(service);
+    client.options_mut().force_wakeup = true;
+    client.options_mut().priority = 100;
+    track!(client.cast(peer, m))?;
+    Ok(())
+}
+
+#[derive(Debug)]
+struct HandshakeHandler<M: MessagePayload>(ServiceHandle<M>);
+impl<M: MessagePayload> HandleCast<HandshakeCast> for HandshakeHandler<M> {
+    fn handle_cast(&self, m: Handshake) -> NoReply {
+        self.0.handle_handshake(m);
+        NoReply::done()
+    }
+}
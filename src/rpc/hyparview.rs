@@ -1,209 +1,613 @@
-use super::RpcMessage;
+use super::{
+    LegacyHandler, MeteredDecoderMaker, MeteredEncoderMaker, RpcMessage, VersionedEncoderMaker,
+};
 use crate::codec::hyparview::{
     DisconnectMessageDecoder, DisconnectMessageEncoder, ForwardJoinMessageDecoder,
     ForwardJoinMessageEncoder, JoinMessageDecoder, JoinMessageEncoder, NeighborMessageDecoder,
-    NeighborMessageEncoder, ShuffleMessageDecoder, ShuffleMessageEncoder,
-    ShuffleReplyMessageDecoder, ShuffleReplyMessageEncoder,
+    NeighborMessageEncoder, NodeAttributes, NodeAttributesExtensionFields, ShuffleMessageDecoder,
+    ShuffleMessageEncoder, ShuffleReplyMessageDecoder, ShuffleReplyMessageEncoder,
+    WithAttributesDecoder, WithAttributesEncoder,
 };
+use crate::codec::metered::{MeteredDecoder, MeteredEncoder};
+use crate::codec::version::{VersionedDecoder, VersionedEncoder};
 use crate::message::MessagePayload;
+use crate::metrics::ServiceMetrics;
 use crate::misc::{
     DisconnectMessage, ForwardJoinMessage, JoinMessage, NeighborMessage, ShuffleMessage,
     ShuffleReplyMessage,
 };
 use crate::node::{LocalNodeId, NodeId};
-use crate::service::ServiceHandle;
+use crate::protocol::Framing;
+use crate::service::{JoinKind, ServiceHandle};
 use crate::Result;
 use fibers_rpc::client::ClientServiceHandle;
 use fibers_rpc::server::{HandleCast, NoReply, ServerBuilder};
 use fibers_rpc::{Cast, ProcedureId};
 
 pub fn register_handlers<M: MessagePayload>(rpc: &mut ServerBuilder, service: &ServiceHandle<M>) {
-    rpc.add_cast_handler(JoinHandler(service.clone()));
-    rpc.add_cast_handler(ForwardJoinHandler(service.clone()));
-    rpc.add_cast_handler(NeighborHandler(service.clone()));
-    rpc.add_cast_handler(ShuffleHandler(service.clone()));
-    rpc.add_cast_handler(ShuffleReplyHandler(service.clone()));
-    rpc.add_cast_handler(DisconnectHandler(service.clone()));
+    let metrics = service.metrics();
+    let decoder_maker = |procedure| {
+        MeteredDecoderMaker::new(
+            metrics.received_hyparview_bytes.clone(),
+            metrics.codec_errors.decode(procedure),
+        )
+    };
+    rpc.add_cast_handler_with_decoder(JoinHandler(service.clone()), decoder_maker(JoinCast::NAME));
+    rpc.add_cast_handler_with_decoder(
+        LegacyHandler(JoinHandler(service.clone())),
+        decoder_maker(LegacyJoinCast::NAME),
+    );
+    rpc.add_cast_handler_with_decoder(
+        ForwardJoinHandler(service.clone()),
+        decoder_maker(ForwardJoinCast::NAME),
+    );
+    rpc.add_cast_handler_with_decoder(
+        LegacyHandler(ForwardJoinHandler(service.clone())),
+        decoder_maker(LegacyForwardJoinCast::NAME),
+    );
+    rpc.add_cast_handler_with_decoder(
+        NeighborHandler(service.clone()),
+        decoder_maker(NeighborCast::NAME),
+    );
+    rpc.add_cast_handler_with_decoder(
+        LegacyHandler(NeighborHandler(service.clone())),
+        decoder_maker(LegacyNeighborCast::NAME),
+    );
+    rpc.add_cast_handler_with_decoder(
+        ShuffleHandler(service.clone()),
+        decoder_maker(ShuffleCast::NAME),
+    );
+    rpc.add_cast_handler_with_decoder(
+        LegacyHandler(ShuffleHandler(service.clone())),
+        decoder_maker(LegacyShuffleCast::NAME),
+    );
+    rpc.add_cast_handler_with_decoder(
+        ShuffleReplyHandler(service.clone()),
+        decoder_maker(ShuffleReplyCast::NAME),
+    );
+    rpc.add_cast_handler_with_decoder(
+        LegacyHandler(ShuffleReplyHandler(service.clone())),
+        decoder_maker(LegacyShuffleReplyCast::NAME),
+    );
+    rpc.add_cast_handler_with_decoder(
+        DisconnectHandler(service.clone()),
+        decoder_maker(DisconnectCast::NAME),
+    );
+    rpc.add_cast_handler_with_decoder(
+        LegacyHandler(DisconnectHandler(service.clone())),
+        decoder_maker(LegacyDisconnectCast::NAME),
+    );
 }
 
 #[derive(Debug)]
 pub struct JoinCast;
 impl Cast for JoinCast {
-    const ID: ProcedureId = ProcedureId(0x17CC_0000);
+    const ID: ProcedureId = ProcedureId(0x17CC_1000);
     const NAME: &'static str = "hyparview.join";
 
-    type Notification = (LocalNodeId, JoinMessage);
-    type Decoder = JoinMessageDecoder;
-    type Encoder = JoinMessageEncoder;
+    type Notification = (LocalNodeId, JoinMessage, NodeAttributes);
+    type Decoder = MeteredDecoder<
+        VersionedDecoder<WithAttributesDecoder<JoinMessageDecoder>, NodeAttributesExtensionFields>,
+    >;
+    type Encoder = MeteredEncoder<
+        VersionedEncoder<WithAttributesEncoder<JoinMessageEncoder>, NodeAttributesExtensionFields>,
+    >;
+}
+
+/// The legacy counterpart of `JoinCast` (see `Framing::Legacy`).
+#[derive(Debug)]
+pub struct LegacyJoinCast;
+impl Cast for LegacyJoinCast {
+    const ID: ProcedureId = ProcedureId(0x17CC_0000);
+    const NAME: &'static str = "hyparview.join.legacy";
+
+    type Notification = (LocalNodeId, JoinMessage, NodeAttributes);
+    type Decoder = MeteredDecoder<WithAttributesDecoder<JoinMessageDecoder>>;
+    type Encoder = MeteredEncoder<WithAttributesEncoder<JoinMessageEncoder>>;
 }
 
-pub fn join_cast(peer: NodeId, m: JoinMessage, service: &ClientServiceHandle) -> Result<()> {
-    let mut client = JoinCast::client(service);
-    client.options_mut().force_wakeup = true;
-    client.options_mut().priority = 100;
-    track!(client.cast(peer.address(), (peer.local_id(), m)))?;
+pub fn join_cast(
+    peer: NodeId,
+    m: JoinMessage,
+    attrs: NodeAttributes,
+    framing: Framing,
+    service: &ClientServiceHandle,
+    metrics: &ServiceMetrics,
+) -> Result<()> {
+    let notification = (peer.local_id(), m, attrs);
+    match framing {
+        Framing::Legacy => {
+            let mut client = LegacyJoinCast::client_with_encoder(
+                service,
+                MeteredEncoderMaker::new(
+                    metrics.sent_hyparview_bytes.clone(),
+                    metrics.codec_errors.encode(LegacyJoinCast::NAME),
+                ),
+            );
+            client.options_mut().force_wakeup = true;
+            client.options_mut().priority = 100;
+            track!(client.cast(peer.address(), notification))?;
+        }
+        Framing::Versioned(version) => {
+            let mut client = JoinCast::client_with_encoder(
+                service,
+                VersionedEncoderMaker::new(
+                    metrics.sent_hyparview_bytes.clone(),
+                    metrics.codec_errors.encode(JoinCast::NAME),
+                    version,
+                ),
+            );
+            client.options_mut().force_wakeup = true;
+            client.options_mut().priority = 100;
+            track!(client.cast(peer.address(), notification))?;
+        }
+    }
     Ok(())
 }
 
 #[derive(Debug)]
 struct JoinHandler<M: MessagePayload>(ServiceHandle<M>);
 impl<M: MessagePayload> HandleCast<JoinCast> for JoinHandler<M> {
-    fn handle_cast(&self, (id, m): (LocalNodeId, JoinMessage)) -> NoReply {
+    fn handle_cast(&self, (id, m, attrs): (LocalNodeId, JoinMessage, NodeAttributes)) -> NoReply {
+        if !self
+            .0
+            .is_join_allowed(id, &m.sender, &m.sender, JoinKind::Join)
+        {
+            return NoReply::done();
+        }
+        self.0.update_node_attributes(&[m.sender], &attrs, true);
         if let Some(node) = self.0.get_local_node_or_disconnect(id, &m.sender) {
             node.send_rpc_message(RpcMessage::Hyparview(m.into()));
         }
         NoReply::done()
     }
 }
+impl<M: MessagePayload> HandleCast<LegacyJoinCast> for LegacyHandler<JoinHandler<M>> {
+    fn handle_cast(&self, notification: (LocalNodeId, JoinMessage, NodeAttributes)) -> NoReply {
+        self.0.handle_cast(notification)
+    }
+}
 
 #[derive(Debug)]
 pub struct ForwardJoinCast;
 impl Cast for ForwardJoinCast {
-    const ID: ProcedureId = ProcedureId(0x17CC_0001);
+    const ID: ProcedureId = ProcedureId(0x17CC_1001);
     const NAME: &'static str = "hyparview.forward_join";
 
-    type Notification = (LocalNodeId, ForwardJoinMessage);
-    type Decoder = ForwardJoinMessageDecoder;
-    type Encoder = ForwardJoinMessageEncoder;
+    type Notification = (LocalNodeId, ForwardJoinMessage, NodeAttributes);
+    type Decoder = MeteredDecoder<
+        VersionedDecoder<
+            WithAttributesDecoder<ForwardJoinMessageDecoder>,
+            NodeAttributesExtensionFields,
+        >,
+    >;
+    type Encoder = MeteredEncoder<
+        VersionedEncoder<
+            WithAttributesEncoder<ForwardJoinMessageEncoder>,
+            NodeAttributesExtensionFields,
+        >,
+    >;
+}
+
+/// The legacy counterpart of `ForwardJoinCast` (see `Framing::Legacy`).
+#[derive(Debug)]
+pub struct LegacyForwardJoinCast;
+impl Cast for LegacyForwardJoinCast {
+    const ID: ProcedureId = ProcedureId(0x17CC_0001);
+    const NAME: &'static str = "hyparview.forward_join.legacy";
+
+    type Notification = (LocalNodeId, ForwardJoinMessage, NodeAttributes);
+    type Decoder = MeteredDecoder<WithAttributesDecoder<ForwardJoinMessageDecoder>>;
+    type Encoder = MeteredEncoder<WithAttributesEncoder<ForwardJoinMessageEncoder>>;
 }
 
 pub fn forward_join_cast(
     peer: NodeId,
     m: ForwardJoinMessage,
+    attrs: NodeAttributes,
+    framing: Framing,
     service: &ClientServiceHandle,
+    metrics: &ServiceMetrics,
 ) -> Result<()> {
-    let mut client = ForwardJoinCast::client(service);
-    client.options_mut().force_wakeup = true;
-    client.options_mut().priority = 100;
-    track!(client.cast(peer.address(), (peer.local_id(), m)))?;
+    let notification = (peer.local_id(), m, attrs);
+    match framing {
+        Framing::Legacy => {
+            let mut client = LegacyForwardJoinCast::client_with_encoder(
+                service,
+                MeteredEncoderMaker::new(
+                    metrics.sent_hyparview_bytes.clone(),
+                    metrics.codec_errors.encode(LegacyForwardJoinCast::NAME),
+                ),
+            );
+            client.options_mut().force_wakeup = true;
+            client.options_mut().priority = 100;
+            track!(client.cast(peer.address(), notification))?;
+        }
+        Framing::Versioned(version) => {
+            let mut client = ForwardJoinCast::client_with_encoder(
+                service,
+                VersionedEncoderMaker::new(
+                    metrics.sent_hyparview_bytes.clone(),
+                    metrics.codec_errors.encode(ForwardJoinCast::NAME),
+                    version,
+                ),
+            );
+            client.options_mut().force_wakeup = true;
+            client.options_mut().priority = 100;
+            track!(client.cast(peer.address(), notification))?;
+        }
+    }
     Ok(())
 }
 
 #[derive(Debug)]
 struct ForwardJoinHandler<M: MessagePayload>(ServiceHandle<M>);
 impl<M: MessagePayload> HandleCast<ForwardJoinCast> for ForwardJoinHandler<M> {
-    fn handle_cast(&self, (id, m): (LocalNodeId, ForwardJoinMessage)) -> NoReply {
+    fn handle_cast(
+        &self,
+        (id, m, attrs): (LocalNodeId, ForwardJoinMessage, NodeAttributes),
+    ) -> NoReply {
+        if !self
+            .0
+            .is_join_allowed(id, &m.sender, &m.new_node, JoinKind::ForwardJoin)
+        {
+            return NoReply::done();
+        }
+        self.0.update_node_attributes(&[m.new_node], &attrs, true);
         if let Some(node) = self.0.get_local_node_or_disconnect(id, &m.sender) {
             node.send_rpc_message(RpcMessage::Hyparview(m.into()));
         }
         NoReply::done()
     }
 }
+impl<M: MessagePayload> HandleCast<LegacyForwardJoinCast> for LegacyHandler<ForwardJoinHandler<M>> {
+    fn handle_cast(
+        &self,
+        notification: (LocalNodeId, ForwardJoinMessage, NodeAttributes),
+    ) -> NoReply {
+        self.0.handle_cast(notification)
+    }
+}
 
 #[derive(Debug)]
 pub struct NeighborCast;
 impl Cast for NeighborCast {
-    const ID: ProcedureId = ProcedureId(0x17CC_0002);
+    const ID: ProcedureId = ProcedureId(0x17CC_1002);
     const NAME: &'static str = "hyparview.neighbor";
 
-    type Notification = (LocalNodeId, NeighborMessage);
-    type Decoder = NeighborMessageDecoder;
-    type Encoder = NeighborMessageEncoder;
+    type Notification = (LocalNodeId, NeighborMessage, NodeAttributes);
+    type Decoder = MeteredDecoder<
+        VersionedDecoder<
+            WithAttributesDecoder<NeighborMessageDecoder>,
+            NodeAttributesExtensionFields,
+        >,
+    >;
+    type Encoder = MeteredEncoder<
+        VersionedEncoder<
+            WithAttributesEncoder<NeighborMessageEncoder>,
+            NodeAttributesExtensionFields,
+        >,
+    >;
+}
+
+/// The legacy counterpart of `NeighborCast` (see `Framing::Legacy`).
+#[derive(Debug)]
+pub struct LegacyNeighborCast;
+impl Cast for LegacyNeighborCast {
+    const ID: ProcedureId = ProcedureId(0x17CC_0002);
+    const NAME: &'static str = "hyparview.neighbor.legacy";
+
+    type Notification = (LocalNodeId, NeighborMessage, NodeAttributes);
+    type Decoder = MeteredDecoder<WithAttributesDecoder<NeighborMessageDecoder>>;
+    type Encoder = MeteredEncoder<WithAttributesEncoder<NeighborMessageEncoder>>;
 }
 
 pub fn neighbor_cast(
     peer: NodeId,
     m: NeighborMessage,
+    attrs: NodeAttributes,
+    framing: Framing,
     service: &ClientServiceHandle,
+    metrics: &ServiceMetrics,
 ) -> Result<()> {
-    let mut client = NeighborCast::client(service);
-    client.options_mut().force_wakeup = true;
-    client.options_mut().priority = 100;
-    track!(client.cast(peer.address(), (peer.local_id(), m)))?;
+    let notification = (peer.local_id(), m, attrs);
+    match framing {
+        Framing::Legacy => {
+            let mut client = LegacyNeighborCast::client_with_encoder(
+                service,
+                MeteredEncoderMaker::new(
+                    metrics.sent_hyparview_bytes.clone(),
+                    metrics.codec_errors.encode(LegacyNeighborCast::NAME),
+                ),
+            );
+            client.options_mut().force_wakeup = true;
+            client.options_mut().priority = 100;
+            track!(client.cast(peer.address(), notification))?;
+        }
+        Framing::Versioned(version) => {
+            let mut client = NeighborCast::client_with_encoder(
+                service,
+                VersionedEncoderMaker::new(
+                    metrics.sent_hyparview_bytes.clone(),
+                    metrics.codec_errors.encode(NeighborCast::NAME),
+                    version,
+                ),
+            );
+            client.options_mut().force_wakeup = true;
+            client.options_mut().priority = 100;
+            track!(client.cast(peer.address(), notification))?;
+        }
+    }
     Ok(())
 }
 
 #[derive(Debug)]
 struct NeighborHandler<M: MessagePayload>(ServiceHandle<M>);
 impl<M: MessagePayload> HandleCast<NeighborCast> for NeighborHandler<M> {
-    fn handle_cast(&self, (id, m): (LocalNodeId, NeighborMessage)) -> NoReply {
+    fn handle_cast(
+        &self,
+        (id, m, attrs): (LocalNodeId, NeighborMessage, NodeAttributes),
+    ) -> NoReply {
+        if !self
+            .0
+            .is_join_allowed(id, &m.sender, &m.sender, JoinKind::Neighbor)
+        {
+            return NoReply::done();
+        }
+        self.0.update_node_attributes(&[m.sender], &attrs, true);
         if let Some(node) = self.0.get_local_node_or_disconnect(id, &m.sender) {
             node.send_rpc_message(RpcMessage::Hyparview(m.into()));
         }
         NoReply::done()
     }
 }
+impl<M: MessagePayload> HandleCast<LegacyNeighborCast> for LegacyHandler<NeighborHandler<M>> {
+    fn handle_cast(&self, notification: (LocalNodeId, NeighborMessage, NodeAttributes)) -> NoReply {
+        self.0.handle_cast(notification)
+    }
+}
 
 #[derive(Debug)]
 pub struct ShuffleCast;
 impl Cast for ShuffleCast {
-    const ID: ProcedureId = ProcedureId(0x17CC_0003);
+    const ID: ProcedureId = ProcedureId(0x17CC_1003);
     const NAME: &'static str = "hyparview.shuffle";
 
-    type Notification = (LocalNodeId, ShuffleMessage);
-    type Decoder = ShuffleMessageDecoder;
-    type Encoder = ShuffleMessageEncoder;
+    type Notification = (LocalNodeId, ShuffleMessage, NodeAttributes);
+    type Decoder = MeteredDecoder<
+        VersionedDecoder<
+            WithAttributesDecoder<ShuffleMessageDecoder>,
+            NodeAttributesExtensionFields,
+        >,
+    >;
+    type Encoder = MeteredEncoder<
+        VersionedEncoder<
+            WithAttributesEncoder<ShuffleMessageEncoder>,
+            NodeAttributesExtensionFields,
+        >,
+    >;
+}
+
+/// The legacy counterpart of `ShuffleCast` (see `Framing::Legacy`).
+#[derive(Debug)]
+pub struct LegacyShuffleCast;
+impl Cast for LegacyShuffleCast {
+    const ID: ProcedureId = ProcedureId(0x17CC_0003);
+    const NAME: &'static str = "hyparview.shuffle.legacy";
+
+    type Notification = (LocalNodeId, ShuffleMessage, NodeAttributes);
+    type Decoder = MeteredDecoder<WithAttributesDecoder<ShuffleMessageDecoder>>;
+    type Encoder = MeteredEncoder<WithAttributesEncoder<ShuffleMessageEncoder>>;
 }
 
-pub fn shuffle_cast(peer: NodeId, m: ShuffleMessage, service: &ClientServiceHandle) -> Result<()> {
-    let mut client = ShuffleCast::client(service);
-    client.options_mut().priority = 200;
-    track!(client.cast(peer.address(), (peer.local_id(), m)))?;
+pub fn shuffle_cast(
+    peer: NodeId,
+    m: ShuffleMessage,
+    attrs: NodeAttributes,
+    framing: Framing,
+    service: &ClientServiceHandle,
+    metrics: &ServiceMetrics,
+) -> Result<()> {
+    let notification = (peer.local_id(), m, attrs);
+    match framing {
+        Framing::Legacy => {
+            let mut client = LegacyShuffleCast::client_with_encoder(
+                service,
+                MeteredEncoderMaker::new(
+                    metrics.sent_hyparview_bytes.clone(),
+                    metrics.codec_errors.encode(LegacyShuffleCast::NAME),
+                ),
+            );
+            client.options_mut().priority = 200;
+            track!(client.cast(peer.address(), notification))?;
+        }
+        Framing::Versioned(version) => {
+            let mut client = ShuffleCast::client_with_encoder(
+                service,
+                VersionedEncoderMaker::new(
+                    metrics.sent_hyparview_bytes.clone(),
+                    metrics.codec_errors.encode(ShuffleCast::NAME),
+                    version,
+                ),
+            );
+            client.options_mut().priority = 200;
+            track!(client.cast(peer.address(), notification))?;
+        }
+    }
     Ok(())
 }
 
 #[derive(Debug)]
 struct ShuffleHandler<M: MessagePayload>(ServiceHandle<M>);
 impl<M: MessagePayload> HandleCast<ShuffleCast> for ShuffleHandler<M> {
-    fn handle_cast(&self, (id, m): (LocalNodeId, ShuffleMessage)) -> NoReply {
+    fn handle_cast(
+        &self,
+        (id, m, attrs): (LocalNodeId, ShuffleMessage, NodeAttributes),
+    ) -> NoReply {
+        self.0
+            .update_node_attributes(&shuffle_subjects(m.origin, &m.nodes), &attrs, false);
         if let Some(node) = self.0.get_local_node_or_disconnect(id, &m.sender) {
             node.send_rpc_message(RpcMessage::Hyparview(m.into()));
         }
         NoReply::done()
     }
 }
+impl<M: MessagePayload> HandleCast<LegacyShuffleCast> for LegacyHandler<ShuffleHandler<M>> {
+    fn handle_cast(&self, notification: (LocalNodeId, ShuffleMessage, NodeAttributes)) -> NoReply {
+        self.0.handle_cast(notification)
+    }
+}
 
 #[derive(Debug)]
 pub struct ShuffleReplyCast;
 impl Cast for ShuffleReplyCast {
-    const ID: ProcedureId = ProcedureId(0x17CC_0004);
+    const ID: ProcedureId = ProcedureId(0x17CC_1004);
     const NAME: &'static str = "hyparview.shuffle_reply";
 
-    type Notification = (LocalNodeId, ShuffleReplyMessage);
-    type Decoder = ShuffleReplyMessageDecoder;
-    type Encoder = ShuffleReplyMessageEncoder;
+    type Notification = (LocalNodeId, ShuffleReplyMessage, NodeAttributes);
+    type Decoder = MeteredDecoder<
+        VersionedDecoder<
+            WithAttributesDecoder<ShuffleReplyMessageDecoder>,
+            NodeAttributesExtensionFields,
+        >,
+    >;
+    type Encoder = MeteredEncoder<
+        VersionedEncoder<
+            WithAttributesEncoder<ShuffleReplyMessageEncoder>,
+            NodeAttributesExtensionFields,
+        >,
+    >;
+}
+
+/// The legacy counterpart of `ShuffleReplyCast` (see `Framing::Legacy`).
+#[derive(Debug)]
+pub struct LegacyShuffleReplyCast;
+impl Cast for LegacyShuffleReplyCast {
+    const ID: ProcedureId = ProcedureId(0x17CC_0004);
+    const NAME: &'static str = "hyparview.shuffle_reply.legacy";
+
+    type Notification = (LocalNodeId, ShuffleReplyMessage, NodeAttributes);
+    type Decoder = MeteredDecoder<WithAttributesDecoder<ShuffleReplyMessageDecoder>>;
+    type Encoder = MeteredEncoder<WithAttributesEncoder<ShuffleReplyMessageEncoder>>;
 }
 
 pub fn shuffle_reply_cast(
     peer: NodeId,
     m: ShuffleReplyMessage,
+    attrs: NodeAttributes,
+    framing: Framing,
     service: &ClientServiceHandle,
+    metrics: &ServiceMetrics,
 ) -> Result<()> {
-    let mut client = ShuffleReplyCast::client(service);
-    client.options_mut().priority = 200;
-    track!(client.cast(peer.address(), (peer.local_id(), m)))?;
+    let notification = (peer.local_id(), m, attrs);
+    match framing {
+        Framing::Legacy => {
+            let mut client = LegacyShuffleReplyCast::client_with_encoder(
+                service,
+                MeteredEncoderMaker::new(
+                    metrics.sent_hyparview_bytes.clone(),
+                    metrics.codec_errors.encode(LegacyShuffleReplyCast::NAME),
+                ),
+            );
+            client.options_mut().priority = 200;
+            track!(client.cast(peer.address(), notification))?;
+        }
+        Framing::Versioned(version) => {
+            let mut client = ShuffleReplyCast::client_with_encoder(
+                service,
+                VersionedEncoderMaker::new(
+                    metrics.sent_hyparview_bytes.clone(),
+                    metrics.codec_errors.encode(ShuffleReplyCast::NAME),
+                    version,
+                ),
+            );
+            client.options_mut().priority = 200;
+            track!(client.cast(peer.address(), notification))?;
+        }
+    }
     Ok(())
 }
 
 #[derive(Debug)]
 struct ShuffleReplyHandler<M: MessagePayload>(ServiceHandle<M>);
 impl<M: MessagePayload> HandleCast<ShuffleReplyCast> for ShuffleReplyHandler<M> {
-    fn handle_cast(&self, (id, m): (LocalNodeId, ShuffleReplyMessage)) -> NoReply {
+    fn handle_cast(
+        &self,
+        (id, m, attrs): (LocalNodeId, ShuffleReplyMessage, NodeAttributes),
+    ) -> NoReply {
+        self.0
+            .update_node_attributes(&shuffle_subjects(m.sender, &m.nodes), &attrs, false);
         if let Some(node) = self.0.get_local_node_or_disconnect(id, &m.sender) {
             node.send_rpc_message(RpcMessage::Hyparview(m.into()));
         }
         NoReply::done()
     }
 }
+impl<M: MessagePayload> HandleCast<LegacyShuffleReplyCast>
+    for LegacyHandler<ShuffleReplyHandler<M>>
+{
+    fn handle_cast(
+        &self,
+        notification: (LocalNodeId, ShuffleReplyMessage, NodeAttributes),
+    ) -> NoReply {
+        self.0.handle_cast(notification)
+    }
+}
 
 #[derive(Debug)]
 pub struct DisconnectCast;
 impl Cast for DisconnectCast {
-    const ID: ProcedureId = ProcedureId(0x17CC_0005);
+    const ID: ProcedureId = ProcedureId(0x17CC_1005);
     const NAME: &'static str = "hyparview.disconnect";
 
     type Notification = (LocalNodeId, DisconnectMessage);
-    type Decoder = DisconnectMessageDecoder;
-    type Encoder = DisconnectMessageEncoder;
+    type Decoder = MeteredDecoder<VersionedDecoder<DisconnectMessageDecoder>>;
+    type Encoder = MeteredEncoder<VersionedEncoder<DisconnectMessageEncoder>>;
+}
+
+/// The legacy counterpart of `DisconnectCast` (see `Framing::Legacy`).
+#[derive(Debug)]
+pub struct LegacyDisconnectCast;
+impl Cast for LegacyDisconnectCast {
+    const ID: ProcedureId = ProcedureId(0x17CC_0005);
+    const NAME: &'static str = "hyparview.disconnect.legacy";
+
+    type Notification = (LocalNodeId, DisconnectMessage);
+    type Decoder = MeteredDecoder<DisconnectMessageDecoder>;
+    type Encoder = MeteredEncoder<DisconnectMessageEncoder>;
 }
 
 pub fn disconnect_cast(
     peer: NodeId,
     m: DisconnectMessage,
+    framing: Framing,
     service: &ClientServiceHandle,
+    metrics: &ServiceMetrics,
 ) -> Result<()> {
-    let client = DisconnectCast::client(service);
-    track!(client.cast(peer.address(), (peer.local_id(), m)))?;
+    let notification = (peer.local_id(), m);
+    match framing {
+        Framing::Legacy => {
+            let client = LegacyDisconnectCast::client_with_encoder(
+                service,
+                MeteredEncoderMaker::new(
+                    metrics.sent_hyparview_bytes.clone(),
+                    metrics.codec_errors.encode(LegacyDisconnectCast::NAME),
+                ),
+            );
+            track!(client.cast(peer.address(), notification))?;
+        }
+        Framing::Versioned(version) => {
+            let client = DisconnectCast::client_with_encoder(
+                service,
+                VersionedEncoderMaker::new(
+                    metrics.sent_hyparview_bytes.clone(),
+                    metrics.codec_errors.encode(DisconnectCast::NAME),
+                    version,
+                ),
+            );
+            track!(client.cast(peer.address(), notification))?;
+        }
+    }
     Ok(())
 }
 
@@ -211,9 +615,25 @@ pub fn disconnect_cast(
 struct DisconnectHandler<M: MessagePayload>(ServiceHandle<M>);
 impl<M: MessagePayload> HandleCast<DisconnectCast> for DisconnectHandler<M> {
     fn handle_cast(&self, (id, m): (LocalNodeId, DisconnectMessage)) -> NoReply {
+        if !m.alive {
+            self.0.forget_node_attributes(m.sender);
+        }
         if let Some(node) = self.0.get_local_node(id) {
             node.send_rpc_message(RpcMessage::Hyparview(m.into()));
         }
         NoReply::done()
     }
 }
+impl<M: MessagePayload> HandleCast<LegacyDisconnectCast> for LegacyHandler<DisconnectHandler<M>> {
+    fn handle_cast(&self, notification: (LocalNodeId, DisconnectMessage)) -> NoReply {
+        self.0.handle_cast(notification)
+    }
+}
+
+/// Returns the subject nodes of a `SHUFFLE` or `SHUFFLE_REPLY` message (see `NodeAttributes`).
+pub fn shuffle_subjects(first: NodeId, nodes: &[NodeId]) -> Vec<NodeId> {
+    let mut subjects = Vec::with_capacity(nodes.len() + 1);
+    subjects.push(first);
+    subjects.extend_from_slice(nodes);
+    subjects
+}
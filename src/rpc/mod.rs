@@ -1,11 +1,363 @@
-use crate::message::MessagePayload;
-use crate::misc::{HyparviewMessage, PlumtreeMessage};
+use crate::admin::ParameterUpdate;
+use crate::codec::extension::ExtensionFields;
+use crate::codec::metered::{MeteredDecoder, MeteredEncoder};
+use crate::codec::version::VersionedEncoder;
+use crate::message::{MessageId, MessagePayload};
+use crate::metrics::Counter;
+use crate::misc::{
+    HyparviewMessage, IhaveMessage, PlumtreeAppMessage, PlumtreeMessage, RetractMessage,
+};
+use crate::node::NodeId;
+use crate::service::ServiceHandle;
+use bytecodec::{Decode, Encode};
+use fibers_rpc::client::MakeEncoder;
+use fibers_rpc::server::{MakeDecoder, ServerBuilder};
+use fibers_rpc::{Call, Cast, ProcedureId};
+use std::marker::PhantomData;
 
+pub mod admin;
+pub mod handshake;
 pub mod hyparview;
 pub mod plumtree;
 
+/// Registers the handlers of all the plumcast procedures to the given RPC server builder.
+pub fn register_handlers<M: MessagePayload>(
+    rpc: &mut ServerBuilder,
+    service: &ServiceHandle<M>,
+    payload_decoder_maker: plumtree::PayloadDecoderMaker<M>,
+) {
+    admin::register_handlers(rpc, service);
+    handshake::register_handlers(rpc, service);
+    hyparview::register_handlers(rpc, service);
+    plumtree::register_handlers(rpc, service, payload_decoder_maker);
+}
+
 #[derive(Debug)]
 pub enum RpcMessage<M: MessagePayload> {
     Hyparview(HyparviewMessage),
     Plumtree(PlumtreeMessage<M>),
+    Admin(ParameterUpdate),
+    Retract(RetractMessage),
+
+    // An `IHAVE` message carrying the content hash of the message (see `Node::broadcast_dedup()`).
+    HashedIhave(IhaveMessage<M>, u64),
+
+    // A message broadcasted by `ServiceHandle::broadcast_local()` (never sent to remote nodes).
+    Local(PlumtreeAppMessage<M>),
+}
+impl<M: MessagePayload> RpcMessage<M> {
+    pub fn map_node_ids<F>(self, f: F) -> Self
+    where
+        F: Fn(NodeId) -> NodeId,
+    {
+        match self {
+            RpcMessage::Hyparview(m) => RpcMessage::Hyparview(map_hyparview_node_ids(m, f)),
+            RpcMessage::Plumtree(m) => RpcMessage::Plumtree(map_plumtree_node_ids(m, f)),
+            RpcMessage::Admin(m) => RpcMessage::Admin(m),
+            RpcMessage::Retract(m) => RpcMessage::Retract(RetractMessage {
+                sender: f(m.sender),
+                message_id: MessageId::new(f(m.message_id.node()), m.message_id.seqno()),
+            }),
+            RpcMessage::HashedIhave(mut m, hash) => {
+                m.sender = f(m.sender);
+                m.message_id = MessageId::new(f(m.message_id.node()), m.message_id.seqno());
+                RpcMessage::HashedIhave(m, hash)
+            }
+            RpcMessage::Local(m) => RpcMessage::Local(m),
+        }
+    }
+}
+
+/// Returns the identifiers and names of the RPC procedures used by plumcast.
+///
+/// The identifiers are fixed (they are the associated constants of the fibers_rpc procedure
+/// traits), so they cannot be customized per service.
+/// Applications that register their own procedures to the same RPC server
+/// (see [`ServiceBuilder::finish_with_external_server`]) can use this for detecting collisions.
+/// All the identifiers are in the range `0x17CC_0000..=0x17CF_FFFF`, which is reserved for
+/// the future procedures of plumcast as well.
+///
+/// [`ServiceBuilder::finish_with_external_server`]: ./struct.ServiceBuilder.html#method.finish_with_external_server
+pub fn procedures() -> Vec<(ProcedureId, &'static str)> {
+    type P = Vec<u8>;
+    vec![
+        (
+            admin::UpdateParameterCast::ID,
+            admin::UpdateParameterCast::NAME,
+        ),
+        (admin::PingRpc::ID, admin::PingRpc::NAME),
+        (admin::NodeStatusesRpc::ID, admin::NodeStatusesRpc::NAME),
+        (admin::DebugDumpRpc::ID, admin::DebugDumpRpc::NAME),
+        (handshake::HandshakeCast::ID, handshake::HandshakeCast::NAME),
+        (hyparview::JoinCast::ID, hyparview::JoinCast::NAME),
+        (
+            hyparview::LegacyJoinCast::ID,
+            hyparview::LegacyJoinCast::NAME,
+        ),
+        (
+            hyparview::ForwardJoinCast::ID,
+            hyparview::ForwardJoinCast::NAME,
+        ),
+        (
+            hyparview::LegacyForwardJoinCast::ID,
+            hyparview::LegacyForwardJoinCast::NAME,
+        ),
+        (hyparview::NeighborCast::ID, hyparview::NeighborCast::NAME),
+        (
+            hyparview::LegacyNeighborCast::ID,
+            hyparview::LegacyNeighborCast::NAME,
+        ),
+        (hyparview::ShuffleCast::ID, hyparview::ShuffleCast::NAME),
+        (
+            hyparview::LegacyShuffleCast::ID,
+            hyparview::LegacyShuffleCast::NAME,
+        ),
+        (
+            hyparview::ShuffleReplyCast::ID,
+            hyparview::ShuffleReplyCast::NAME,
+        ),
+        (
+            hyparview::LegacyShuffleReplyCast::ID,
+            hyparview::LegacyShuffleReplyCast::NAME,
+        ),
+        (
+            hyparview::DisconnectCast::ID,
+            hyparview::DisconnectCast::NAME,
+        ),
+        (
+            hyparview::LegacyDisconnectCast::ID,
+            hyparview::LegacyDisconnectCast::NAME,
+        ),
+        (
+            plumtree::GossipCast::<P>::ID,
+            plumtree::GossipCast::<P>::NAME,
+        ),
+        (
+            plumtree::LegacyGossipCast::<P>::ID,
+            plumtree::LegacyGossipCast::<P>::NAME,
+        ),
+        (plumtree::IhaveCast::<P>::ID, plumtree::IhaveCast::<P>::NAME),
+        (
+            plumtree::LegacyIhaveCast::<P>::ID,
+            plumtree::LegacyIhaveCast::<P>::NAME,
+        ),
+        (
+            plumtree::VarintIhaveCast::<P>::ID,
+            plumtree::VarintIhaveCast::<P>::NAME,
+        ),
+        (plumtree::GraftCast::<P>::ID, plumtree::GraftCast::<P>::NAME),
+        (
+            plumtree::LegacyGraftCast::<P>::ID,
+            plumtree::LegacyGraftCast::<P>::NAME,
+        ),
+        (
+            plumtree::GraftOptimizeCast::<P>::ID,
+            plumtree::GraftOptimizeCast::<P>::NAME,
+        ),
+        (
+            plumtree::LegacyGraftOptimizeCast::<P>::ID,
+            plumtree::LegacyGraftOptimizeCast::<P>::NAME,
+        ),
+        (plumtree::PruneCast::<P>::ID, plumtree::PruneCast::<P>::NAME),
+        (
+            plumtree::LegacyPruneCast::<P>::ID,
+            plumtree::LegacyPruneCast::<P>::NAME,
+        ),
+        (plumtree::RetractCast::ID, plumtree::RetractCast::NAME),
+    ]
+}
+
+/// An encoder maker that counts the bytes of the sent frames (and the failed encodings).
+#[derive(Debug)]
+pub struct MeteredEncoderMaker<E> {
+    sent_bytes: Counter,
+    encode_errors: Counter,
+    _encoder: PhantomData<fn() -> E>,
+}
+impl<E> MeteredEncoderMaker<E> {
+    pub fn new(sent_bytes: Counter, encode_errors: Counter) -> Self {
+        MeteredEncoderMaker {
+            sent_bytes,
+            encode_errors,
+            _encoder: PhantomData,
+        }
+    }
+}
+impl<E> MakeEncoder<MeteredEncoder<E>> for MeteredEncoderMaker<E>
+where
+    E: Encode + Default + Send + 'static,
+{
+    fn make_encoder(&self) -> MeteredEncoder<E> {
+        MeteredEncoder::new(
+            E::default(),
+            self.sent_bytes.clone(),
+            self.encode_errors.clone(),
+        )
+    }
+}
+
+/// An encoder maker like `MeteredEncoderMaker`, but which makes the encoders of versioned frames
+/// in the protocol version negotiated with the destination peer.
+#[derive(Debug)]
+pub struct VersionedEncoderMaker<E, X> {
+    sent_bytes: Counter,
+    encode_errors: Counter,
+    version: u8,
+    _encoder: PhantomData<fn() -> (E, X)>,
+}
+impl<E, X> VersionedEncoderMaker<E, X> {
+    pub fn new(sent_bytes: Counter, encode_errors: Counter, version: u8) -> Self {
+        VersionedEncoderMaker {
+            sent_bytes,
+            encode_errors,
+            version,
+            _encoder: PhantomData,
+        }
+    }
+}
+impl<E, X> MakeEncoder<MeteredEncoder<VersionedEncoder<E, X>>> for VersionedEncoderMaker<E, X>
+where
+    E: Encode + Default + Send + 'static,
+    X: ExtensionFields<E::Item> + Send + 'static,
+{
+    fn make_encoder(&self) -> MeteredEncoder<VersionedEncoder<E, X>> {
+        MeteredEncoder::new(
+            VersionedEncoder::with_version(E::default(), self.version),
+            self.sent_bytes.clone(),
+            self.encode_errors.clone(),
+        )
+    }
+}
+
+/// A handler of a legacy procedure (i.e., the counterpart of a versioned procedure used by
+/// the peers that predate versioning), which handles the notifications by `H` as is.
+#[derive(Debug)]
+pub struct LegacyHandler<H>(pub H);
+
+/// A decoder maker that counts the bytes of the received frames (and the failed decodings).
+#[derive(Debug)]
+pub struct MeteredDecoderMaker<D> {
+    received_bytes: Counter,
+    decode_errors: Counter,
+    _decoder: PhantomData<fn() -> D>,
+}
+impl<D> MeteredDecoderMaker<D> {
+    pub fn new(received_bytes: Counter, decode_errors: Counter) -> Self {
+        MeteredDecoderMaker {
+            received_bytes,
+            decode_errors,
+            _decoder: PhantomData,
+        }
+    }
+}
+impl<D> MakeDecoder<MeteredDecoder<D>> for MeteredDecoderMaker<D>
+where
+    D: Decode + Default + Send + 'static,
+{
+    fn make_decoder(&self) -> MeteredDecoder<D> {
+        MeteredDecoder::new(
+            D::default(),
+            Some(self.received_bytes.clone()),
+            Some(self.decode_errors.clone()),
+        )
+    }
+}
+
+fn map_hyparview_node_ids<F>(m: HyparviewMessage, f: F) -> HyparviewMessage
+where
+    F: Fn(NodeId) -> NodeId,
+{
+    use ::hyparview::message::ProtocolMessage;
+
+    match m {
+        ProtocolMessage::Join(mut m) => {
+            m.sender = f(m.sender);
+            ProtocolMessage::Join(m)
+        }
+        ProtocolMessage::ForwardJoin(mut m) => {
+            m.sender = f(m.sender);
+            m.new_node = f(m.new_node);
+            ProtocolMessage::ForwardJoin(m)
+        }
+        ProtocolMessage::Neighbor(mut m) => {
+            m.sender = f(m.sender);
+            ProtocolMessage::Neighbor(m)
+        }
+        ProtocolMessage::Shuffle(mut m) => {
+            m.sender = f(m.sender);
+            m.origin = f(m.origin);
+            m.nodes = m.nodes.into_iter().map(&f).collect();
+            ProtocolMessage::Shuffle(m)
+        }
+        ProtocolMessage::ShuffleReply(mut m) => {
+            m.sender = f(m.sender);
+            m.nodes = m.nodes.into_iter().map(&f).collect();
+            ProtocolMessage::ShuffleReply(m)
+        }
+        ProtocolMessage::Disconnect(mut m) => {
+            m.sender = f(m.sender);
+            ProtocolMessage::Disconnect(m)
+        }
+    }
+}
+
+fn map_plumtree_node_ids<M, F>(m: PlumtreeMessage<M>, f: F) -> PlumtreeMessage<M>
+where
+    M: MessagePayload,
+    F: Fn(NodeId) -> NodeId,
+{
+    use ::plumtree::message::ProtocolMessage;
+
+    let map_message_id = |id: MessageId| MessageId::new(f(id.node()), id.seqno());
+    match m {
+        ProtocolMessage::Gossip(mut m) => {
+            m.sender = f(m.sender);
+            m.message.id = map_message_id(m.message.id);
+            ProtocolMessage::Gossip(m)
+        }
+        ProtocolMessage::Ihave(mut m) => {
+            m.sender = f(m.sender);
+            m.message_id = map_message_id(m.message_id);
+            ProtocolMessage::Ihave(m)
+        }
+        ProtocolMessage::Graft(mut m) => {
+            m.sender = f(m.sender);
+            m.message_id = m.message_id.map(map_message_id);
+            ProtocolMessage::Graft(m)
+        }
+        ProtocolMessage::Prune(mut m) => {
+            m.sender = f(m.sender);
+            ProtocolMessage::Prune(m)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn procedure_ids_are_unique_and_reserved() {
+        let procedures = procedures();
+        let ids = procedures.iter().map(|p| (p.0).0).collect::<HashSet<_>>();
+        assert_eq!(ids.len(), procedures.len());
+        assert!(ids.iter().all(|&id| 0x17CC_0000 <= id && id <= 0x17CF_FFFF));
+    }
+
+    #[test]
+    fn legacy_procedures_keep_the_original_ids() {
+        type P = Vec<u8>;
+        assert_eq!(hyparview::LegacyJoinCast::ID.0, 0x17CC_0000);
+        assert_eq!(hyparview::LegacyForwardJoinCast::ID.0, 0x17CC_0001);
+        assert_eq!(hyparview::LegacyNeighborCast::ID.0, 0x17CC_0002);
+        assert_eq!(hyparview::LegacyShuffleCast::ID.0, 0x17CC_0003);
+        assert_eq!(hyparview::LegacyShuffleReplyCast::ID.0, 0x17CC_0004);
+        assert_eq!(hyparview::LegacyDisconnectCast::ID.0, 0x17CC_0005);
+        assert_eq!(plumtree::LegacyGossipCast::<P>::ID.0, 0x17CD_0000);
+        assert_eq!(plumtree::LegacyIhaveCast::<P>::ID.0, 0x17CD_0001);
+        assert_eq!(plumtree::LegacyGraftCast::<P>::ID.0, 0x17CD_0002);
+        assert_eq!(plumtree::LegacyGraftOptimizeCast::<P>::ID.0, 0x17CD_0003);
+        assert_eq!(plumtree::LegacyPruneCast::<P>::ID.0, 0x17CD_0004);
+    }
 }
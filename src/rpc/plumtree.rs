@@ -1,93 +1,472 @@
-use super::RpcMessage;
+use super::{
+    LegacyHandler, MeteredDecoderMaker, MeteredEncoderMaker, RpcMessage, VersionedEncoderMaker,
+};
+use crate::codec::metered::{MeteredDecoder, MeteredEncoder};
 use crate::codec::plumtree::{
-    GossipMessageDecoder, GossipMessageEncoder, GraftMessageDecoder, GraftMessageEncoder,
-    GraftOptimizeMessageDecoder, GraftOptimizeMessageEncoder, IhaveMessageDecoder,
-    IhaveMessageEncoder, PruneMessageDecoder, PruneMessageEncoder,
+    GossipExtensionFields, GossipMessageDecoder, GossipMessageEncoder, GraftMessageDecoder,
+    GraftMessageEncoder, GraftOptimizeMessageDecoder, GraftOptimizeMessageEncoder,
+    IhaveExtensionFields, IhaveMessageDecoder, IhaveMessageEncoder, PayloadDecodeBudget,
+    PayloadSizeLimit, PruneMessageDecoder, PruneMessageEncoder, RetractMessageDecoder,
+    RetractMessageEncoder, VarintIhaveMessageDecoder, VarintIhaveMessageEncoder,
+    WithContentHashDecoder, WithContentHashEncoder,
 };
+use crate::codec::version::{VersionedDecoder, VersionedEncoder};
+#[cfg(feature = "encryption")]
+use crate::encryption::PayloadCipher;
 use crate::message::MessagePayload;
-use crate::misc::{GossipMessage, GraftMessage, IhaveMessage, PruneMessage};
+use crate::metrics::{Counter, ServiceMetrics};
+use crate::misc::{GossipMessage, GraftMessage, IhaveMessage, PruneMessage, RetractMessage};
 use crate::node::{LocalNodeId, NodeId};
-use crate::service::ServiceHandle;
+use crate::protocol::Framing;
+use crate::service::{ServiceHandle, TreeRepairPriorities};
 use crate::Result;
 use fibers_rpc::client::ClientServiceHandle;
-use fibers_rpc::server::{HandleCast, NoReply, ServerBuilder};
+use fibers_rpc::server::{HandleCast, MakeDecoder, NoReply, ServerBuilder};
 use fibers_rpc::{Cast, ProcedureId};
+use std::fmt;
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 const MAX_QUEUE_LEN: u64 = 4096; // FIXME: parameterize
 
-pub fn register_handlers<M: MessagePayload>(rpc: &mut ServerBuilder, service: &ServiceHandle<M>) {
-    rpc.add_cast_handler(GossipHandler(service.clone()));
-    rpc.add_cast_handler(IhaveHandler(service.clone()));
-    rpc.add_cast_handler(GraftHandler(service.clone()));
-    rpc.add_cast_handler(GraftOptimizeHandler(service.clone()));
-    rpc.add_cast_handler(PruneHandler(service.clone()));
+pub fn register_handlers<M: MessagePayload>(
+    rpc: &mut ServerBuilder,
+    service: &ServiceHandle<M>,
+    payload_decoder_maker: PayloadDecoderMaker<M>,
+) {
+    let metrics = service.metrics();
+    rpc.add_cast_handler_with_decoder(
+        LegacyHandler(GossipHandler(service.clone())),
+        payload_decoder_maker.clone(),
+    );
+    rpc.add_cast_handler_with_decoder(GossipHandler(service.clone()), payload_decoder_maker);
+    rpc.add_cast_handler_with_decoder(
+        IhaveHandler(service.clone()),
+        MeteredDecoderMaker::new(
+            metrics.received_ihave_bytes.clone(),
+            metrics.codec_errors.decode(IhaveCast::<M>::NAME),
+        ),
+    );
+    rpc.add_cast_handler_with_decoder(
+        LegacyHandler(IhaveHandler(service.clone())),
+        MeteredDecoderMaker::new(
+            metrics.received_ihave_bytes.clone(),
+            metrics.codec_errors.decode(LegacyIhaveCast::<M>::NAME),
+        ),
+    );
+    rpc.add_cast_handler_with_decoder(
+        VarintIhaveHandler(service.clone()),
+        MeteredDecoderMaker::new(
+            metrics.received_ihave_bytes.clone(),
+            metrics.codec_errors.decode(VarintIhaveCast::<M>::NAME),
+        ),
+    );
+    rpc.add_cast_handler_with_decoder(
+        GraftHandler(service.clone()),
+        MeteredDecoderMaker::new(
+            metrics.received_graft_bytes.clone(),
+            metrics.codec_errors.decode(GraftCast::<M>::NAME),
+        ),
+    );
+    rpc.add_cast_handler_with_decoder(
+        LegacyHandler(GraftHandler(service.clone())),
+        MeteredDecoderMaker::new(
+            metrics.received_graft_bytes.clone(),
+            metrics.codec_errors.decode(LegacyGraftCast::<M>::NAME),
+        ),
+    );
+    rpc.add_cast_handler_with_decoder(
+        GraftOptimizeHandler(service.clone()),
+        MeteredDecoderMaker::new(
+            metrics.received_graft_bytes.clone(),
+            metrics.codec_errors.decode(GraftOptimizeCast::<M>::NAME),
+        ),
+    );
+    rpc.add_cast_handler_with_decoder(
+        LegacyHandler(GraftOptimizeHandler(service.clone())),
+        MeteredDecoderMaker::new(
+            metrics.received_graft_bytes.clone(),
+            metrics
+                .codec_errors
+                .decode(LegacyGraftOptimizeCast::<M>::NAME),
+        ),
+    );
+    rpc.add_cast_handler_with_decoder(
+        PruneHandler(service.clone()),
+        MeteredDecoderMaker::new(
+            metrics.received_prune_bytes.clone(),
+            metrics.codec_errors.decode(PruneCast::<M>::NAME),
+        ),
+    );
+    rpc.add_cast_handler_with_decoder(
+        LegacyHandler(PruneHandler(service.clone())),
+        MeteredDecoderMaker::new(
+            metrics.received_prune_bytes.clone(),
+            metrics.codec_errors.decode(LegacyPruneCast::<M>::NAME),
+        ),
+    );
+    rpc.add_cast_handler_with_decoder(
+        RetractHandler(service.clone()),
+        MeteredDecoderMaker::new(
+            metrics.received_retract_bytes.clone(),
+            metrics.codec_errors.decode(RetractCast::NAME),
+        ),
+    );
 }
 
 #[derive(Debug)]
 pub struct GossipCast<M>(PhantomData<M>);
 unsafe impl<M> Sync for GossipCast<M> {}
 impl<M: MessagePayload> Cast for GossipCast<M> {
-    const ID: ProcedureId = ProcedureId(0x17CD_0000);
+    const ID: ProcedureId = ProcedureId(0x17CD_1000);
     const NAME: &'static str = "plumtree.gossip";
 
     type Notification = (LocalNodeId, GossipMessage<M>);
-    type Decoder = GossipMessageDecoder<M>;
-    type Encoder = GossipMessageEncoder<M>;
+    type Decoder = MeteredDecoder<VersionedDecoder<GossipMessageDecoder<M>, GossipExtensionFields>>;
+    type Encoder = MeteredEncoder<VersionedEncoder<GossipMessageEncoder<M>, GossipExtensionFields>>;
+}
+
+/// The legacy counterpart of `GossipCast` (see `Framing::Legacy`).
+#[derive(Debug)]
+pub struct LegacyGossipCast<M>(PhantomData<M>);
+unsafe impl<M> Sync for LegacyGossipCast<M> {}
+impl<M: MessagePayload> Cast for LegacyGossipCast<M> {
+    const ID: ProcedureId = ProcedureId(0x17CD_0000);
+    const NAME: &'static str = "plumtree.gossip.legacy";
+
+    type Notification = (LocalNodeId, GossipMessage<M>);
+    type Decoder = MeteredDecoder<GossipMessageDecoder<M>>;
+    type Encoder = MeteredEncoder<GossipMessageEncoder<M>>;
 }
 
 pub fn gossip_cast<M: MessagePayload>(
     peer: NodeId,
     m: GossipMessage<M>,
+    framing: Framing,
     service: &ClientServiceHandle,
+    metrics: &ServiceMetrics,
 ) -> Result<()> {
-    let mut client = GossipCast::client(service);
-    client.options_mut().max_queue_len = Some(MAX_QUEUE_LEN);
-    track!(client.cast(peer.address(), (peer.local_id(), m)))?;
+    let (priority, force_wakeup, max_queue_len) = if m.message.payload.high_priority {
+        (Some(50), true, None)
+    } else if m.message.payload.repair {
+        // NOTE: Repairing the broadcast tree takes precedence over fresh broadcasts.
+        (Some(100), true, None)
+    } else {
+        (None, false, Some(MAX_QUEUE_LEN))
+    };
+    let notification = (peer.local_id(), m);
+    match framing {
+        Framing::Legacy => {
+            let mut client = LegacyGossipCast::client_with_encoder(
+                service,
+                MeteredEncoderMaker::new(
+                    metrics.sent_gossip_bytes.clone(),
+                    metrics.codec_errors.encode(LegacyGossipCast::<M>::NAME),
+                ),
+            );
+            if let Some(priority) = priority {
+                client.options_mut().priority = priority;
+            }
+            client.options_mut().force_wakeup = force_wakeup;
+            client.options_mut().max_queue_len = max_queue_len;
+            track!(client.cast(peer.address(), notification))?;
+        }
+        Framing::Versioned(version) => {
+            let mut client = GossipCast::client_with_encoder(
+                service,
+                VersionedEncoderMaker::new(
+                    metrics.sent_gossip_bytes.clone(),
+                    metrics.codec_errors.encode(GossipCast::<M>::NAME),
+                    version,
+                ),
+            );
+            if let Some(priority) = priority {
+                client.options_mut().priority = priority;
+            }
+            client.options_mut().force_wakeup = force_wakeup;
+            client.options_mut().max_queue_len = max_queue_len;
+            track!(client.cast(peer.address(), notification))?;
+        }
+    }
     Ok(())
 }
 
+pub struct PayloadDecoderMaker<M: MessagePayload> {
+    make: Arc<dyn Fn() -> M::Decoder + Send + Sync>,
+    limit: PayloadSizeLimit,
+    budget: PayloadDecodeBudget,
+    received_bytes: Option<Counter>,
+    decode_errors: Option<Counter>,
+    #[cfg(feature = "encryption")]
+    cipher: Option<PayloadCipher>,
+}
+impl<M: MessagePayload> PayloadDecoderMaker<M> {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn() -> M::Decoder + Send + Sync + 'static,
+    {
+        PayloadDecoderMaker {
+            make: Arc::new(f),
+            limit: PayloadSizeLimit::default(),
+            budget: PayloadDecodeBudget::default(),
+            received_bytes: None,
+            decode_errors: None,
+            #[cfg(feature = "encryption")]
+            cipher: None,
+        }
+    }
+
+    pub fn set_payload_size_limit(&mut self, limit: PayloadSizeLimit) {
+        self.limit = limit;
+    }
+
+    pub fn set_payload_decode_budget(&mut self, budget: PayloadDecodeBudget) {
+        self.budget = budget;
+    }
+
+    pub fn set_received_bytes_counter(&mut self, counter: Counter) {
+        self.received_bytes = Some(counter);
+    }
+
+    pub fn set_decode_errors_counter(&mut self, counter: Counter) {
+        self.decode_errors = Some(counter);
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn set_payload_cipher(&mut self, cipher: PayloadCipher) {
+        self.cipher = Some(cipher);
+    }
+}
+impl<M: MessagePayload> Default for PayloadDecoderMaker<M> {
+    fn default() -> Self {
+        Self::new(M::Decoder::default)
+    }
+}
+impl<M: MessagePayload> Clone for PayloadDecoderMaker<M> {
+    fn clone(&self) -> Self {
+        PayloadDecoderMaker {
+            make: Arc::clone(&self.make),
+            limit: self.limit.clone(),
+            budget: self.budget.clone(),
+            received_bytes: self.received_bytes.clone(),
+            decode_errors: self.decode_errors.clone(),
+            #[cfg(feature = "encryption")]
+            cipher: self.cipher.clone(),
+        }
+    }
+}
+impl<M: MessagePayload> fmt::Debug for PayloadDecoderMaker<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PayloadDecoderMaker {{ make: _, limit: {:?}, budget: {:?} }}",
+            self.limit, self.budget
+        )
+    }
+}
+impl<M: MessagePayload> PayloadDecoderMaker<M> {
+    fn make_gossip_decoder(&self) -> GossipMessageDecoder<M> {
+        let mut decoder = GossipMessageDecoder::with_payload_decoder((self.make)());
+        decoder.set_payload_size_limit(self.limit.clone());
+        decoder.set_payload_decode_budget(self.budget.clone());
+        #[cfg(feature = "encryption")]
+        {
+            if let Some(ref cipher) = self.cipher {
+                decoder.set_payload_cipher(cipher.clone());
+            }
+        }
+        decoder
+    }
+}
+impl<M: MessagePayload>
+    MakeDecoder<MeteredDecoder<VersionedDecoder<GossipMessageDecoder<M>, GossipExtensionFields>>>
+    for PayloadDecoderMaker<M>
+{
+    fn make_decoder(
+        &self,
+    ) -> MeteredDecoder<VersionedDecoder<GossipMessageDecoder<M>, GossipExtensionFields>> {
+        MeteredDecoder::new(
+            VersionedDecoder::new(self.make_gossip_decoder()),
+            self.received_bytes.clone(),
+            self.decode_errors.clone(),
+        )
+    }
+}
+impl<M: MessagePayload> MakeDecoder<MeteredDecoder<GossipMessageDecoder<M>>>
+    for PayloadDecoderMaker<M>
+{
+    fn make_decoder(&self) -> MeteredDecoder<GossipMessageDecoder<M>> {
+        MeteredDecoder::new(
+            self.make_gossip_decoder(),
+            self.received_bytes.clone(),
+            self.decode_errors.clone(),
+        )
+    }
+}
+
 #[derive(Debug)]
 struct GossipHandler<M: MessagePayload>(ServiceHandle<M>);
 impl<M: MessagePayload> HandleCast<GossipCast<M>> for GossipHandler<M> {
     fn handle_cast(&self, (id, m): (LocalNodeId, GossipMessage<M>)) -> NoReply {
+        if let Some(size) = m.message.payload.payload_size {
+            self.0.record_received_payload(&m.sender, size);
+        }
         if let Some(node) = self.0.get_local_node_or_disconnect(id, &m.sender) {
             node.send_rpc_message(RpcMessage::Plumtree(m.into()));
         }
         NoReply::done()
     }
 }
+impl<M: MessagePayload> HandleCast<LegacyGossipCast<M>> for LegacyHandler<GossipHandler<M>> {
+    fn handle_cast(&self, notification: (LocalNodeId, GossipMessage<M>)) -> NoReply {
+        self.0.handle_cast(notification)
+    }
+}
 
 #[derive(Debug)]
 pub struct IhaveCast<M>(PhantomData<M>);
 unsafe impl<M> Sync for IhaveCast<M> {}
 impl<M: MessagePayload> Cast for IhaveCast<M> {
-    const ID: ProcedureId = ProcedureId(0x17CD_0001);
+    const ID: ProcedureId = ProcedureId(0x17CD_1001);
     const NAME: &'static str = "plumtree.ihave";
 
-    type Notification = (LocalNodeId, IhaveMessage<M>);
-    type Decoder = IhaveMessageDecoder<M>;
-    type Encoder = IhaveMessageEncoder<M>;
+    type Notification = (LocalNodeId, IhaveMessage<M>, Option<u64>);
+    type Decoder = MeteredDecoder<
+        VersionedDecoder<WithContentHashDecoder<IhaveMessageDecoder<M>>, IhaveExtensionFields>,
+    >;
+    type Encoder = MeteredEncoder<
+        VersionedEncoder<WithContentHashEncoder<IhaveMessageEncoder<M>>, IhaveExtensionFields>,
+    >;
+}
+
+/// The legacy counterpart of `IhaveCast` (the content hash is never carried by it).
+#[derive(Debug)]
+pub struct LegacyIhaveCast<M>(PhantomData<M>);
+unsafe impl<M> Sync for LegacyIhaveCast<M> {}
+impl<M: MessagePayload> Cast for LegacyIhaveCast<M> {
+    const ID: ProcedureId = ProcedureId(0x17CD_0001);
+    const NAME: &'static str = "plumtree.ihave.legacy";
+
+    type Notification = (LocalNodeId, IhaveMessage<M>, Option<u64>);
+    type Decoder = MeteredDecoder<WithContentHashDecoder<IhaveMessageDecoder<M>>>;
+    type Encoder = MeteredEncoder<WithContentHashEncoder<IhaveMessageEncoder<M>>>;
 }
 
 pub fn ihave_cast<M: MessagePayload>(
     peer: NodeId,
     m: IhaveMessage<M>,
+    content_hash: Option<u64>,
+    framing: Framing,
     service: &ClientServiceHandle,
+    metrics: &ServiceMetrics,
 ) -> Result<()> {
-    let mut client = IhaveCast::client(service);
-    client.options_mut().priority = 200;
-    client.options_mut().max_queue_len = Some(MAX_QUEUE_LEN);
-    track!(client.cast(peer.address(), (peer.local_id(), m)))?;
+    let notification = (peer.local_id(), m, content_hash);
+    match framing {
+        Framing::Legacy => {
+            let mut client = LegacyIhaveCast::client_with_encoder(
+                service,
+                MeteredEncoderMaker::new(
+                    metrics.sent_ihave_bytes.clone(),
+                    metrics.codec_errors.encode(LegacyIhaveCast::<M>::NAME),
+                ),
+            );
+            client.options_mut().priority = 200;
+            client.options_mut().max_queue_len = Some(MAX_QUEUE_LEN);
+            track!(client.cast(peer.address(), notification))?;
+        }
+        Framing::Versioned(version) => {
+            let mut client = IhaveCast::client_with_encoder(
+                service,
+                VersionedEncoderMaker::new(
+                    metrics.sent_ihave_bytes.clone(),
+                    metrics.codec_errors.encode(IhaveCast::<M>::NAME),
+                    version,
+                ),
+            );
+            client.options_mut().priority = 200;
+            client.options_mut().max_queue_len = Some(MAX_QUEUE_LEN);
+            track!(client.cast(peer.address(), notification))?;
+        }
+    }
     Ok(())
 }
 
 #[derive(Debug)]
 struct IhaveHandler<M: MessagePayload>(ServiceHandle<M>);
 impl<M: MessagePayload> HandleCast<IhaveCast<M>> for IhaveHandler<M> {
-    fn handle_cast(&self, (id, m): (LocalNodeId, IhaveMessage<M>)) -> NoReply {
+    fn handle_cast(&self, (id, m, hash): (LocalNodeId, IhaveMessage<M>, Option<u64>)) -> NoReply {
         if let Some(node) = self.0.get_local_node_or_disconnect(id, &m.sender) {
-            node.send_rpc_message(RpcMessage::Plumtree(m.into()));
+            node.send_rpc_message(ihave_rpc_message(m, hash));
+        }
+        NoReply::done()
+    }
+}
+impl<M: MessagePayload> HandleCast<LegacyIhaveCast<M>> for LegacyHandler<IhaveHandler<M>> {
+    fn handle_cast(&self, notification: (LocalNodeId, IhaveMessage<M>, Option<u64>)) -> NoReply {
+        self.0.handle_cast(notification)
+    }
+}
+
+fn ihave_rpc_message<M: MessagePayload>(m: IhaveMessage<M>, hash: Option<u64>) -> RpcMessage<M> {
+    if let Some(hash) = hash {
+        RpcMessage::HashedIhave(m, hash)
+    } else {
+        RpcMessage::Plumtree(m.into())
+    }
+}
+
+// NOTE: This is used instead of `IhaveCast` for the peers that negotiated `WireCodec::Varint`
+// (so it has no legacy counterpart).
+#[derive(Debug)]
+pub struct VarintIhaveCast<M>(PhantomData<M>);
+unsafe impl<M> Sync for VarintIhaveCast<M> {}
+impl<M: MessagePayload> Cast for VarintIhaveCast<M> {
+    const ID: ProcedureId = ProcedureId(0x17CD_0006);
+    const NAME: &'static str = "plumtree.ihave.varint";
+
+    type Notification = (LocalNodeId, IhaveMessage<M>, Option<u64>);
+    type Decoder = MeteredDecoder<
+        VersionedDecoder<
+            WithContentHashDecoder<VarintIhaveMessageDecoder<M>>,
+            IhaveExtensionFields,
+        >,
+    >;
+    type Encoder = MeteredEncoder<
+        VersionedEncoder<
+            WithContentHashEncoder<VarintIhaveMessageEncoder<M>>,
+            IhaveExtensionFields,
+        >,
+    >;
+}
+
+pub fn varint_ihave_cast<M: MessagePayload>(
+    peer: NodeId,
+    m: IhaveMessage<M>,
+    content_hash: Option<u64>,
+    version: u8,
+    service: &ClientServiceHandle,
+    metrics: &ServiceMetrics,
+) -> Result<()> {
+    let mut client = VarintIhaveCast::client_with_encoder(
+        service,
+        VersionedEncoderMaker::new(
+            metrics.sent_ihave_bytes.clone(),
+            metrics.codec_errors.encode(VarintIhaveCast::<M>::NAME),
+            version,
+        ),
+    );
+    client.options_mut().priority = 200;
+    client.options_mut().max_queue_len = Some(MAX_QUEUE_LEN);
+    track!(client.cast(peer.address(), (peer.local_id(), m, content_hash)))?;
+    Ok(())
+}
+
+#[derive(Debug)]
+struct VarintIhaveHandler<M: MessagePayload>(ServiceHandle<M>);
+impl<M: MessagePayload> HandleCast<VarintIhaveCast<M>> for VarintIhaveHandler<M> {
+    fn handle_cast(&self, (id, m, hash): (LocalNodeId, IhaveMessage<M>, Option<u64>)) -> NoReply {
+        if let Some(node) = self.0.get_local_node_or_disconnect(id, &m.sender) {
+            node.send_rpc_message(ihave_rpc_message(m, hash));
         }
         NoReply::done()
     }
@@ -97,25 +476,87 @@ impl<M: MessagePayload> HandleCast<IhaveCast<M>> for IhaveHandler<M> {
 pub struct GraftCast<M>(PhantomData<M>);
 unsafe impl<M> Sync for GraftCast<M> {}
 impl<M: MessagePayload> Cast for GraftCast<M> {
-    const ID: ProcedureId = ProcedureId(0x17CD_0002);
+    const ID: ProcedureId = ProcedureId(0x17CD_1002);
     const NAME: &'static str = "plumtree.graft";
 
     type Notification = (LocalNodeId, GraftMessage<M>);
-    type Decoder = GraftMessageDecoder<M>;
-    type Encoder = GraftMessageEncoder<M>;
+    type Decoder = MeteredDecoder<VersionedDecoder<GraftMessageDecoder<M>>>;
+    type Encoder = MeteredEncoder<VersionedEncoder<GraftMessageEncoder<M>>>;
+}
+
+/// The legacy counterpart of `GraftCast`.
+#[derive(Debug)]
+pub struct LegacyGraftCast<M>(PhantomData<M>);
+unsafe impl<M> Sync for LegacyGraftCast<M> {}
+impl<M: MessagePayload> Cast for LegacyGraftCast<M> {
+    const ID: ProcedureId = ProcedureId(0x17CD_0002);
+    const NAME: &'static str = "plumtree.graft.legacy";
+
+    type Notification = (LocalNodeId, GraftMessage<M>);
+    type Decoder = MeteredDecoder<GraftMessageDecoder<M>>;
+    type Encoder = MeteredEncoder<GraftMessageEncoder<M>>;
 }
 
 pub fn graft_cast<M: MessagePayload>(
     peer: NodeId,
     m: GraftMessage<M>,
+    priorities: &TreeRepairPriorities,
+    framing: Framing,
     service: &ClientServiceHandle,
+    metrics: &ServiceMetrics,
 ) -> Result<()> {
-    if m.message_id.is_some() {
-        let client = GraftCast::client(service);
-        track!(client.cast(peer.address(), (peer.local_id(), m)))?;
-    } else {
-        let client = GraftOptimizeCast::client(service);
-        track!(client.cast(peer.address(), (peer.local_id(), m)))?;
+    let optimize = m.message_id.is_none();
+    let priority = priorities.graft_priority(&m);
+    let notification = (peer.local_id(), m);
+    match (framing, optimize) {
+        (Framing::Legacy, false) => {
+            let mut client = LegacyGraftCast::client_with_encoder(
+                service,
+                MeteredEncoderMaker::new(
+                    metrics.sent_graft_bytes.clone(),
+                    metrics.codec_errors.encode(LegacyGraftCast::<M>::NAME),
+                ),
+            );
+            client.options_mut().priority = priority;
+            track!(client.cast(peer.address(), notification))?;
+        }
+        (Framing::Legacy, true) => {
+            let mut client = LegacyGraftOptimizeCast::client_with_encoder(
+                service,
+                MeteredEncoderMaker::new(
+                    metrics.sent_graft_bytes.clone(),
+                    metrics
+                        .codec_errors
+                        .encode(LegacyGraftOptimizeCast::<M>::NAME),
+                ),
+            );
+            client.options_mut().priority = priority;
+            track!(client.cast(peer.address(), notification))?;
+        }
+        (Framing::Versioned(version), false) => {
+            let mut client = GraftCast::client_with_encoder(
+                service,
+                VersionedEncoderMaker::new(
+                    metrics.sent_graft_bytes.clone(),
+                    metrics.codec_errors.encode(GraftCast::<M>::NAME),
+                    version,
+                ),
+            );
+            client.options_mut().priority = priority;
+            track!(client.cast(peer.address(), notification))?;
+        }
+        (Framing::Versioned(version), true) => {
+            let mut client = GraftOptimizeCast::client_with_encoder(
+                service,
+                VersionedEncoderMaker::new(
+                    metrics.sent_graft_bytes.clone(),
+                    metrics.codec_errors.encode(GraftOptimizeCast::<M>::NAME),
+                    version,
+                ),
+            );
+            client.options_mut().priority = priority;
+            track!(client.cast(peer.address(), notification))?;
+        }
     }
     Ok(())
 }
@@ -131,17 +572,35 @@ impl<M: MessagePayload> HandleCast<GraftCast<M>> for GraftHandler<M> {
         NoReply::done()
     }
 }
+impl<M: MessagePayload> HandleCast<LegacyGraftCast<M>> for LegacyHandler<GraftHandler<M>> {
+    fn handle_cast(&self, notification: (LocalNodeId, GraftMessage<M>)) -> NoReply {
+        self.0.handle_cast(notification)
+    }
+}
 
 #[derive(Debug)]
 pub struct GraftOptimizeCast<M>(PhantomData<M>);
 unsafe impl<M> Sync for GraftOptimizeCast<M> {}
 impl<M: MessagePayload> Cast for GraftOptimizeCast<M> {
-    const ID: ProcedureId = ProcedureId(0x17CD_0003);
+    const ID: ProcedureId = ProcedureId(0x17CD_1003);
     const NAME: &'static str = "plumtree.graft.optimize";
 
     type Notification = (LocalNodeId, GraftMessage<M>);
-    type Decoder = GraftOptimizeMessageDecoder<M>;
-    type Encoder = GraftOptimizeMessageEncoder<M>;
+    type Decoder = MeteredDecoder<VersionedDecoder<GraftOptimizeMessageDecoder<M>>>;
+    type Encoder = MeteredEncoder<VersionedEncoder<GraftOptimizeMessageEncoder<M>>>;
+}
+
+/// The legacy counterpart of `GraftOptimizeCast`.
+#[derive(Debug)]
+pub struct LegacyGraftOptimizeCast<M>(PhantomData<M>);
+unsafe impl<M> Sync for LegacyGraftOptimizeCast<M> {}
+impl<M: MessagePayload> Cast for LegacyGraftOptimizeCast<M> {
+    const ID: ProcedureId = ProcedureId(0x17CD_0003);
+    const NAME: &'static str = "plumtree.graft.optimize.legacy";
+
+    type Notification = (LocalNodeId, GraftMessage<M>);
+    type Decoder = MeteredDecoder<GraftOptimizeMessageDecoder<M>>;
+    type Encoder = MeteredEncoder<GraftOptimizeMessageEncoder<M>>;
 }
 
 #[derive(Debug)]
@@ -155,26 +614,73 @@ impl<M: MessagePayload> HandleCast<GraftOptimizeCast<M>> for GraftOptimizeHandle
         NoReply::done()
     }
 }
+impl<M: MessagePayload> HandleCast<LegacyGraftOptimizeCast<M>>
+    for LegacyHandler<GraftOptimizeHandler<M>>
+{
+    fn handle_cast(&self, notification: (LocalNodeId, GraftMessage<M>)) -> NoReply {
+        self.0.handle_cast(notification)
+    }
+}
 
 #[derive(Debug)]
 pub struct PruneCast<M>(PhantomData<M>);
 unsafe impl<M> Sync for PruneCast<M> {}
 impl<M: MessagePayload> Cast for PruneCast<M> {
-    const ID: ProcedureId = ProcedureId(0x17CD_0004);
+    const ID: ProcedureId = ProcedureId(0x17CD_1004);
     const NAME: &'static str = "plumtree.prune";
 
     type Notification = (LocalNodeId, PruneMessage<M>);
-    type Decoder = PruneMessageDecoder<M>;
-    type Encoder = PruneMessageEncoder<M>;
+    type Decoder = MeteredDecoder<VersionedDecoder<PruneMessageDecoder<M>>>;
+    type Encoder = MeteredEncoder<VersionedEncoder<PruneMessageEncoder<M>>>;
+}
+
+/// The legacy counterpart of `PruneCast`.
+#[derive(Debug)]
+pub struct LegacyPruneCast<M>(PhantomData<M>);
+unsafe impl<M> Sync for LegacyPruneCast<M> {}
+impl<M: MessagePayload> Cast for LegacyPruneCast<M> {
+    const ID: ProcedureId = ProcedureId(0x17CD_0004);
+    const NAME: &'static str = "plumtree.prune.legacy";
+
+    type Notification = (LocalNodeId, PruneMessage<M>);
+    type Decoder = MeteredDecoder<PruneMessageDecoder<M>>;
+    type Encoder = MeteredEncoder<PruneMessageEncoder<M>>;
 }
 
 pub fn prune_cast<M: MessagePayload>(
     peer: NodeId,
     m: PruneMessage<M>,
+    priorities: &TreeRepairPriorities,
+    framing: Framing,
     service: &ClientServiceHandle,
+    metrics: &ServiceMetrics,
 ) -> Result<()> {
-    let client = PruneCast::client(service);
-    track!(client.cast(peer.address(), (peer.local_id(), m)))?;
+    let notification = (peer.local_id(), m);
+    match framing {
+        Framing::Legacy => {
+            let mut client = LegacyPruneCast::client_with_encoder(
+                service,
+                MeteredEncoderMaker::new(
+                    metrics.sent_prune_bytes.clone(),
+                    metrics.codec_errors.encode(LegacyPruneCast::<M>::NAME),
+                ),
+            );
+            client.options_mut().priority = priorities.prune;
+            track!(client.cast(peer.address(), notification))?;
+        }
+        Framing::Versioned(version) => {
+            let mut client = PruneCast::client_with_encoder(
+                service,
+                VersionedEncoderMaker::new(
+                    metrics.sent_prune_bytes.clone(),
+                    metrics.codec_errors.encode(PruneCast::<M>::NAME),
+                    version,
+                ),
+            );
+            client.options_mut().priority = priorities.prune;
+            track!(client.cast(peer.address(), notification))?;
+        }
+    }
     Ok(())
 }
 
@@ -188,3 +694,50 @@ impl<M: MessagePayload> HandleCast<PruneCast<M>> for PruneHandler<M> {
         NoReply::done()
     }
 }
+impl<M: MessagePayload> HandleCast<LegacyPruneCast<M>> for LegacyHandler<PruneHandler<M>> {
+    fn handle_cast(&self, notification: (LocalNodeId, PruneMessage<M>)) -> NoReply {
+        self.0.handle_cast(notification)
+    }
+}
+
+// NOTE: The peers that predate versioning do not know this procedure.
+#[derive(Debug)]
+pub struct RetractCast;
+impl Cast for RetractCast {
+    const ID: ProcedureId = ProcedureId(0x17CD_0005);
+    const NAME: &'static str = "plumtree.retract";
+
+    type Notification = (LocalNodeId, RetractMessage);
+    type Decoder = MeteredDecoder<VersionedDecoder<RetractMessageDecoder>>;
+    type Encoder = MeteredEncoder<VersionedEncoder<RetractMessageEncoder>>;
+}
+
+pub fn retract_cast(
+    peer: NodeId,
+    m: RetractMessage,
+    version: u8,
+    service: &ClientServiceHandle,
+    metrics: &ServiceMetrics,
+) -> Result<()> {
+    let client = RetractCast::client_with_encoder(
+        service,
+        VersionedEncoderMaker::new(
+            metrics.sent_retract_bytes.clone(),
+            metrics.codec_errors.encode(RetractCast::NAME),
+            version,
+        ),
+    );
+    track!(client.cast(peer.address(), (peer.local_id(), m)))?;
+    Ok(())
+}
+
+#[derive(Debug)]
+struct RetractHandler<M: MessagePayload>(ServiceHandle<M>);
+impl<M: MessagePayload> HandleCast<RetractCast> for RetractHandler<M> {
+    fn handle_cast(&self, (id, m): (LocalNodeId, RetractMessage)) -> NoReply {
+        if let Some(node) = self.0.get_local_node_or_disconnect(id, &m.sender) {
+            node.send_rpc_message(RpcMessage::Retract(m));
+        }
+        NoReply::done()
+    }
+}
@@ -1,29 +1,67 @@
 //! [`Service`] and related components.
 //!
 //! [`Service`]: ./struct.Service.html
-use crate::message::MessagePayload;
-use crate::metrics::{NodeMetrics, ServiceMetrics};
-use crate::misc::ArcSpawn;
+use crate::addr_normalizer::ArcAddrNormalizer;
+use crate::admin::{
+    ArcParameterUpdatePolicy, DebugDumpQuery, DebugEvent, HealthCheck, NodeStatus, NodeStatusQuery,
+    ParameterUpdate, ParameterUpdatePolicy,
+};
+use crate::clock::{Clock, ClockDriver};
+use crate::codec::hyparview::NodeAttributes;
+use crate::codec::plumtree::{PayloadDecodeBudget, PayloadSizeLimit};
+#[cfg(feature = "encryption")]
+use crate::encryption::PayloadCipher;
+use crate::event_log::EventLog;
+use crate::join_policy::ArcJoinPolicy;
+#[cfg(feature = "encryption")]
+use crate::message::encode_payload;
+use crate::message::{DecoderWithAllocator, Envelope, MessageId, MessagePayload};
+use crate::metrics::{
+    ArcMetricsSink, MetricsFactory, MetricsSink, NodeHistogramBuckets, NodeMetrics, ServiceMetrics,
+};
+#[cfg(feature = "encryption")]
+use crate::misc::GossipMessage;
+use crate::misc::{ArcSpawn, GraftMessage, IhaveMessage, PlumtreeAppMessage};
 use crate::node::{GenerateLocalNodeId, LocalNodeId, NodeHandle, NodeId};
 use crate::node_id_generator::ArcLocalNodeIdGenerator;
+use crate::protocol::{self, Framing, Handshake, PeerProtocol, PeerProtocols};
+
+pub use crate::protocol::WireCodec;
+use crate::rpc::plumtree::{GossipCast, PayloadDecoderMaker};
 use crate::rpc::{self, RpcMessage};
+#[cfg(any(test, feature = "testing"))]
+use crate::testing::InMemoryOutbox;
 use crate::{Error, ErrorKind, Result};
 use atomic_immut::AtomicImmut;
 use fibers::sync::mpsc;
+use fibers::time::timer::{self, Timeout};
 use fibers::Spawn;
+use fibers_rpc::channel::ChannelOptions;
 use fibers_rpc::client::{
     ClientService as RpcClientService, ClientServiceBuilder as RpcClientServiceBuilder,
     ClientServiceHandle as RpcClientServiceHandle,
 };
 use fibers_rpc::server::{Server as RpcServer, ServerBuilder as RpcServerBuilder};
+use fibers_rpc::Cast;
 use futures::{Async, Future, Poll, Stream};
 use prometrics::metrics::MetricBuilder;
 use slog::{Discard, Logger};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use trackable::error::ErrorKindExt;
+
+pub use crate::addr_normalizer::{CanonicalAddrNormalizer, IdentityAddrNormalizer, NormalizeAddr};
+pub use crate::join_policy::{AddrRangeJoinPolicy, JoinKind, JoinPolicy};
+pub use crate::rpc::procedures;
 
 type LocalNodes<M> = Arc<AtomicImmut<HashMap<LocalNodeId, NodeHandle<M>>>>;
+type Tombstones = Arc<Mutex<HashMap<LocalNodeId, Instant>>>;
+type PeerStatsTable = Arc<Mutex<HashMap<SocketAddr, PeerStats>>>;
+type Observers = Arc<Mutex<HashSet<NodeId>>>;
+type Zones = Arc<Mutex<HashMap<NodeId, String>>>;
 
 /// The builder of [`Service`].
 ///
@@ -34,7 +72,28 @@ pub struct ServiceBuilder {
     server_addr: SocketAddr,
     rpc_server_builder: RpcServerBuilder,
     rpc_client_service_builder: RpcClientServiceBuilder,
+    shared_rpc_client_service: Option<RpcClientServiceHandle>,
     metrics: MetricBuilder,
+    metrics_sink: Option<ArcMetricsSink>,
+    metrics_enabled: bool,
+    event_log_capacity: usize,
+    tombstone_duration: Duration,
+    max_payload_size: Option<u64>,
+    max_decoding_payload_bytes: Option<u64>,
+    node_lease_duration: Duration,
+    addr_normalizer: ArcAddrNormalizer,
+    shared_tick_interval: Option<Duration>,
+    wire_codecs: Vec<WireCodec>,
+    protocol_negotiation: bool,
+    restartable: bool,
+    unknown_destination_policy: UnknownDestinationPolicy,
+    join_policy: Option<ArcJoinPolicy>,
+    parameter_update_policy: Option<ArcParameterUpdatePolicy>,
+    tree_repair_priorities: TreeRepairPriorities,
+    circuit_breaker: Option<CircuitBreaker>,
+    max_command_queue_len: usize,
+    #[cfg(feature = "encryption")]
+    payload_key: Option<(u32, [u8; 32])>,
 }
 impl ServiceBuilder {
     /// Makes a new `ServiceBuilder` instance with the default settings.
@@ -44,7 +103,28 @@ impl ServiceBuilder {
             server_addr: rpc_server_bind_addr,
             rpc_server_builder: RpcServerBuilder::new(rpc_server_bind_addr),
             rpc_client_service_builder: RpcClientServiceBuilder::new(),
+            shared_rpc_client_service: None,
             metrics: MetricBuilder::new(),
+            metrics_sink: None,
+            metrics_enabled: true,
+            event_log_capacity: 0,
+            tombstone_duration: Duration::from_secs(0),
+            max_payload_size: None,
+            max_decoding_payload_bytes: None,
+            node_lease_duration: Duration::from_secs(0),
+            addr_normalizer: ArcAddrNormalizer::new(CanonicalAddrNormalizer::new()),
+            shared_tick_interval: None,
+            wire_codecs: Vec::new(),
+            protocol_negotiation: false,
+            restartable: false,
+            unknown_destination_policy: UnknownDestinationPolicy::Disconnect,
+            join_policy: None,
+            parameter_update_policy: None,
+            tree_repair_priorities: TreeRepairPriorities::default(),
+            circuit_breaker: None,
+            max_command_queue_len: 4096,
+            #[cfg(feature = "encryption")]
+            payload_key: None,
         }
     }
 
@@ -58,6 +138,54 @@ impl ServiceBuilder {
         self
     }
 
+    /// Sets the options of the RPC channels used for sending messages to remote nodes.
+    ///
+    /// This is a shorthand of `rpc_client_service_builder_mut().channel_options(options)`.
+    ///
+    /// Note that a channel is shared by all the RPC procedures (i.e., HyParView, Plumtree and
+    /// admin messages) sent to the same peer, so the options can not be set per procedure.
+    ///
+    /// The default value is `ChannelOptions::default()`.
+    pub fn channel_options(mut self, options: ChannelOptions) -> Self {
+        self.rpc_client_service_builder.channel_options(options);
+        self
+    }
+
+    /// Returns a mutable reference to the builder of the underlying RPC server.
+    ///
+    /// Note that the handlers of the plumcast procedures are registered to the builder
+    /// when the service is built.
+    pub fn rpc_server_builder_mut(&mut self) -> &mut RpcServerBuilder {
+        &mut self.rpc_server_builder
+    }
+
+    /// Returns a mutable reference to the builder of the underlying RPC client service.
+    ///
+    /// This can be used for tuning the RPC client (e.g., the buffer sizes and timeouts of channels).
+    pub fn rpc_client_service_builder_mut(&mut self) -> &mut RpcClientServiceBuilder {
+        &mut self.rpc_client_service_builder
+    }
+
+    /// Makes the service send messages via the given RPC client service
+    /// instead of building its own one.
+    ///
+    /// This is useful for a process running multiple services (e.g., on different ports),
+    /// because the services sharing a client service also share the connections to the peers.
+    /// The handle can be taken from another service by [`ServiceHandle::rpc_client_service`].
+    ///
+    /// Note that the client service is not driven by the resulting service,
+    /// so it must be kept running (e.g., by the service that built it) while the service is used.
+    /// The settings made via [`rpc_client_service_builder_mut`] are ignored.
+    ///
+    /// By default, each service builds its own client service.
+    ///
+    /// [`ServiceHandle::rpc_client_service`]: ./struct.ServiceHandle.html#method.rpc_client_service
+    /// [`rpc_client_service_builder_mut`]: #method.rpc_client_service_builder_mut
+    pub fn shared_rpc_client_service(mut self, handle: RpcClientServiceHandle) -> Self {
+        self.shared_rpc_client_service = Some(handle);
+        self
+    }
+
     /// Sets the metrics settings of the service.
     ///
     /// The default value is `MetricBuilder::new()`.
@@ -66,41 +194,548 @@ impl ServiceBuilder {
         self
     }
 
-    /// Returns a mutable reference to the RPC server builder.
-    pub fn rpc_server_builder_mut(&mut self) -> &mut RpcServerBuilder {
-        &mut self.rpc_server_builder
+    /// Sets the sink that receives the updates of the metrics of the service and its nodes.
+    ///
+    /// The metrics are registered to prometrics regardless of this setting.
+    ///
+    /// By default, no sink is set.
+    pub fn metrics_sink<S: MetricsSink>(mut self, sink: S) -> Self {
+        self.metrics_sink = Some(ArcMetricsSink::new(sink));
+        self
+    }
+
+    /// Enables or disables the metrics of the service and its nodes.
+    ///
+    /// If disabled, no metrics are registered and all the updates of them are skipped
+    /// (i.e., all the values returned by `ServiceMetrics` and `NodeMetrics` remain zero).
+    /// This reduces the per-message overhead for applications that do their own instrumentation.
+    ///
+    /// Note that `NodeBuilder::metrics()` does not re-enable the metrics of a node.
+    ///
+    /// The default value is `true` (`false` if the `metrics` feature is disabled,
+    /// in which case this setting is ignored).
+    pub fn enable_metrics(mut self, enabled: bool) -> Self {
+        self.metrics_enabled = enabled;
+        self
+    }
+
+    /// Sets the number of recent node registry events kept by the service for debugging.
+    ///
+    /// If an `ErrorKind::InconsistentState` error occurs in the service,
+    /// the kept events are dumped to the logger.
+    ///
+    /// The default value is `0` (i.e., no events are kept).
+    pub fn event_log_capacity(mut self, capacity: usize) -> Self {
+        self.event_log_capacity = capacity;
+        self
+    }
+
+    /// Sets the period during which the identifiers of deregistered nodes are remembered.
+    ///
+    /// RPC messages addressed to a node deregistered within the period are silently dropped.
+    /// Otherwise, a HyParView `DISCONNECT` message is sent back to the sender of each such message,
+    /// which may result in a flood of them if many peers have not yet observed the departure.
+    ///
+    /// The default value is `Duration::from_secs(0)` (i.e., disabled).
+    pub fn tombstone_duration(mut self, duration: Duration) -> Self {
+        self.tombstone_duration = duration;
+        self
+    }
+
+    /// Sets the maximum size (in bytes) of the encoded payload of a received message.
+    ///
+    /// RPC frames containing larger payloads are rejected with `ErrorKind::InvalidInput`
+    /// (and counted by the `plumcast_service_oversized_payload_frames_total` metric)
+    /// before the whole payload is buffered.
+    ///
+    /// By default, the payload size is unlimited.
+    pub fn max_payload_size(mut self, size: u64) -> Self {
+        self.max_payload_size = Some(size);
+        self
+    }
+
+    /// Sets the maximum number of payload bytes being decoded concurrently in the service.
+    ///
+    /// While received payloads that are being decoded hold this number of bytes or more,
+    /// decoding of the payloads of newly arriving messages is postponed
+    /// (i.e., the bytes are left in the socket buffers) until some of the in-progress decodes complete.
+    /// This prevents memory spikes when many peers push large payloads at the same time.
+    ///
+    /// The payloads that have started to be decoded are never interrupted,
+    /// so the limit may be temporarily exceeded by up to the sizes of those payloads
+    /// (consider combining it with [`max_payload_size`]).
+    ///
+    /// The current number of bytes and the number of postponed decodes are exposed by
+    /// the `plumcast_service_decoding_payload_bytes` and
+    /// `plumcast_service_throttled_payload_decodes_total` metrics respectively.
+    ///
+    /// By default, the number of bytes is unlimited.
+    ///
+    /// [`max_payload_size`]: #method.max_payload_size
+    pub fn max_decoding_payload_bytes(mut self, bytes: u64) -> Self {
+        self.max_decoding_payload_bytes = Some(bytes);
+        self
+    }
+
+    /// Sets the liveness lease duration of the nodes registered in the service.
+    ///
+    /// A node renews its lease each time it is polled
+    /// (usually, a node is polled at least once per `NodeBuilder::tick_interval()`).
+    /// If a node has not renewed its lease within the duration
+    /// (e.g., its fiber has died without dropping the node),
+    /// the service deregisters the node and counts it by
+    /// the `plumcast_service_expired_node_leases_total` metric.
+    /// A node that is still alive (e.g., it has just been polled too rarely)
+    /// re-registers itself with the service at the next poll,
+    /// but the messages sent to it meanwhile are lost.
+    ///
+    /// The default value is `Duration::from_secs(0)` (i.e., leases never expire).
+    pub fn node_lease_duration(mut self, duration: Duration) -> Self {
+        self.node_lease_duration = duration;
+        self
+    }
+
+    /// Sets the normalizer applied to the addresses of local and remote node identifiers.
+    ///
+    /// The default value is `CanonicalAddrNormalizer::new()`.
+    pub fn addr_normalizer<N: NormalizeAddr>(mut self, normalizer: N) -> Self {
+        self.addr_normalizer = ArcAddrNormalizer::new(normalizer);
+        self
+    }
+
+    /// Makes the service tick its local nodes by using a single timer at the given interval.
+    ///
+    /// By default, each node has its own timer, so a process running thousands of nodes
+    /// allocates (and wakes up for) thousands of timers at every tick interval.
+    /// If this is specified, the service drives a shared [`Clock`] and
+    /// the nodes built without [`NodeBuilder::clock`] subscribe to it
+    /// (i.e., their tick intervals are ignored).
+    ///
+    /// By default, no shared clock is used.
+    ///
+    /// [`Clock`]: ../clock/struct.Clock.html
+    /// [`NodeBuilder::clock`]: ../node/struct.NodeBuilder.html#method.clock
+    pub fn shared_tick_interval(mut self, interval: Duration) -> Self {
+        self.shared_tick_interval = Some(interval);
+        self
+    }
+
+    /// Sets the wire codecs that the service can use in addition to `WireCodec::Fixed`,
+    /// in order of preference.
+    ///
+    /// The enabled codecs are advertised to peers in handshakes (see [`protocol_negotiation`]).
+    /// The frames sent to a peer are encoded by the first codec in `codecs` that is also enabled
+    /// by the peer, or by `WireCodec::Fixed` if there is no such codec
+    /// (or the handshake with the peer has not completed yet).
+    /// So services enabling different codecs can coexist in a cluster.
+    ///
+    /// The default value is `Vec::new()` (i.e., only `WireCodec::Fixed` is used).
+    ///
+    /// [`protocol_negotiation`]: #method.protocol_negotiation
+    pub fn wire_codecs(mut self, codecs: Vec<WireCodec>) -> Self {
+        self.wire_codecs = codecs
+            .into_iter()
+            .filter(|&c| c != WireCodec::Fixed)
+            .collect();
+        self
+    }
+
+    /// Sets whether the service initiates protocol negotiations with peers.
+    ///
+    /// If `true`, a handshake is sent to each peer before the first message to it,
+    /// and the later messages are sent as versioned frames once the peer has replied.
+    /// Until then (or if `false`), messages are sent in the layout of the releases that predate
+    /// versioning, which drops the fields carried in the extension section of frames
+    /// (e.g., broadcast deadlines and trace contexts).
+    /// Retractions and parameter updates are only sent to the peers that have negotiated a version.
+    ///
+    /// Handshakes received from peers are always replied regardless of this setting,
+    /// so it is enough to enable this in the services that are deployed
+    /// after all the services in the cluster have been upgraded.
+    ///
+    /// The default value is `false`.
+    pub fn protocol_negotiation(mut self, enabled: bool) -> Self {
+        self.protocol_negotiation = enabled;
+        self
+    }
+
+    /// Sets whether the local nodes wait for a successor when the service stops.
+    ///
+    /// If `true`, a local node that detects that the service has stopped keeps
+    /// waiting until a successor is built by [`restart`], and then re-registers itself with it.
+    /// If `false`, such a node fails with an error as before, unless a successor has
+    /// already been built when the node detects the stop.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`restart`]: #method.restart
+    pub fn restartable(mut self, enabled: bool) -> Self {
+        self.restartable = enabled;
+        self
+    }
+
+    /// Sets how the service handles the RPC messages destined for missing local nodes.
+    ///
+    /// Such messages are counted by the `plumcast_service_destination_unknown_messages_total` metric
+    /// regardless of the policy.
+    /// Note that the messages destined for recently deregistered nodes
+    /// (see [`tombstone_duration`]) are always dropped silently.
+    ///
+    /// The default value is `UnknownDestinationPolicy::Disconnect`.
+    ///
+    /// [`tombstone_duration`]: #method.tombstone_duration
+    pub fn unknown_destination_policy(mut self, policy: UnknownDestinationPolicy) -> Self {
+        self.unknown_destination_policy = policy;
+        self
+    }
+
+    /// Sets the policy restricting the nodes that can enter the overlay via the local nodes.
+    ///
+    /// The rejected messages are counted by the `plumcast_service_rejected_joins_total` metric.
+    /// See [`JoinPolicy`] for more details.
+    ///
+    /// By default, all the nodes are allowed.
+    ///
+    /// [`JoinPolicy`]: ./trait.JoinPolicy.html
+    pub fn join_policy<P: JoinPolicy>(mut self, policy: P) -> Self {
+        self.join_policy = Some(ArcJoinPolicy::new(policy));
+        self
+    }
+
+    /// Enables the parameter updates of the local nodes via the admin RPC,
+    /// and sets the policy restricting the callers of them.
+    ///
+    /// The rejected updates are counted by
+    /// the `plumcast_service_rejected_parameter_updates_total` metric.
+    /// See [`ParameterUpdatePolicy`] for more details.
+    ///
+    /// By default, all the updates sent by remote services are rejected.
+    ///
+    /// [`ParameterUpdatePolicy`]: ../admin/trait.ParameterUpdatePolicy.html
+    pub fn parameter_update_policy<P: ParameterUpdatePolicy>(mut self, policy: P) -> Self {
+        self.parameter_update_policy = Some(ArcParameterUpdatePolicy::new(policy));
+        self
+    }
+
+    /// Sets the RPC priorities of the Plumtree messages used for repairing broadcast trees.
+    ///
+    /// The default value is `TreeRepairPriorities::default()`.
+    pub fn tree_repair_priorities(mut self, priorities: TreeRepairPriorities) -> Self {
+        self.tree_repair_priorities = priorities;
+        self
+    }
+
+    /// Enables the per-peer circuit breaker of sending messages.
+    ///
+    /// If sending messages to a peer has failed `failure_threshold` times in a row,
+    /// the circuit to the peer is opened: the messages sent to the peer during `cool_down`
+    /// fail immediately with `ErrorKind::PeerUnreachable` without being queued
+    /// (and are counted by the `plumcast_service_circuit_rejected_messages_total` metric).
+    /// After the cool-down, the next message is sent as usual,
+    /// and the circuit is closed if it succeeds or re-opened otherwise.
+    ///
+    /// Openings of circuits are logged at the warning level and
+    /// counted by the `plumcast_service_opened_circuits_total` metric.
+    ///
+    /// By default, the circuit breaker is disabled.
+    pub fn circuit_breaker(mut self, failure_threshold: u64, cool_down: Duration) -> Self {
+        self.circuit_breaker = Some(CircuitBreaker {
+            failure_threshold,
+            cool_down,
+        });
+        self
+    }
+
+    /// Sets the maximum number of pending node registrations of the service.
+    ///
+    /// Registrations (i.e., `NodeBuilder::finish()` calls) are queued until the service is polled.
+    /// If the queue is full, the registration fails with `ErrorKind::QueueFull`,
+    /// so that a pathological churn of nodes cannot make the queue grow without bound.
+    /// Deregistrations of dropped nodes are always queued, but they count toward the limit.
+    ///
+    /// The current length of the queue is exposed by
+    /// the `plumcast_service_command_queue_len` metric.
+    ///
+    /// The default value is `4096`.
+    pub fn max_command_queue_len(mut self, max: usize) -> Self {
+        self.max_command_queue_len = max;
+        self
+    }
+
+    /// Enables the encryption of the application payloads of gossip messages with the given key.
+    ///
+    /// The payloads are encrypted by XChaCha20-Poly1305 (an AEAD cipher),
+    /// so they are kept confidential and tampered payloads are rejected
+    /// with `ErrorKind::InvalidInput` on decoding.
+    /// The other parts of messages (e.g., the identifiers of nodes and messages,
+    /// and HyParView messages) are sent in cleartext.
+    ///
+    /// All the services in a cluster must share the key.
+    /// Note that the sizes of encrypted payloads reported by metrics and
+    /// `ServiceBuilder::max_payload_size()` include the encryption overhead (44 bytes).
+    ///
+    /// This is equivalent to `self.payload_key_with_id(0, secret)`.
+    ///
+    /// By default, payloads are not encrypted.
+    #[cfg(feature = "encryption")]
+    pub fn payload_key(self, secret: [u8; 32]) -> Self {
+        self.payload_key_with_id(0, secret)
+    }
+
+    /// Enables the encryption of the application payloads of gossip messages with the given key.
+    ///
+    /// The identifier of the key is sent along with each encrypted payload,
+    /// so the receivers can pick the key for decrypting it among the registered ones.
+    /// Additional keys can be registered and the key used for encryption can be switched
+    /// at runtime by [`ServiceHandle::add_payload_key`] and so on.
+    ///
+    /// See [`payload_key`] for more details.
+    ///
+    /// [`ServiceHandle::add_payload_key`]: ./struct.ServiceHandle.html#method.add_payload_key
+    /// [`payload_key`]: #method.payload_key
+    #[cfg(feature = "encryption")]
+    pub fn payload_key_with_id(mut self, key_id: u32, secret: [u8; 32]) -> Self {
+        self.payload_key = Some((key_id, secret));
+        self
+    }
+
+    /// Builds a [`Service`] with the given settings.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the settings are inconsistent (see [`try_finish`]).
+    ///
+    /// [`Service`]: ./struct.Service.html
+    /// [`try_finish`]: #method.try_finish
+    pub fn finish<S, M, G>(self, spawner: S, local_id_gen: G) -> Service<M>
+    where
+        S: Spawn + Send + Sync + 'static,
+        M: MessagePayload,
+        G: GenerateLocalNodeId,
+    {
+        self.try_finish(spawner, local_id_gen)
+            .unwrap_or_else(|e| panic!("Invalid service settings: {}", e))
     }
 
     /// Builds a [`Service`] with the given settings.
     ///
+    /// If the settings are inconsistent (e.g., the maximum payload size is zero),
+    /// an `ErrorKind::InvalidInput` error describing the problem is returned.
+    ///
+    /// [`Service`]: ./struct.Service.html
+    pub fn try_finish<S, M, G>(self, spawner: S, local_id_gen: G) -> Result<Service<M>>
+    where
+        S: Spawn + Send + Sync + 'static,
+        M: MessagePayload,
+        G: GenerateLocalNodeId,
+    {
+        self.finish_with_payload_decoder_maker(spawner, local_id_gen, Default::default(), None)
+    }
+
+    /// Builds a [`Service`] which procedures are served by the given external RPC server.
+    ///
+    /// The handlers of the plumcast procedures are registered to `rpc_server_builder`,
+    /// and the resulting service does not run its own RPC server
+    /// (i.e., the settings made via [`rpc_server_builder_mut`] are ignored).
+    /// This allows applications that already run a fibers_rpc server to share the listener
+    /// with plumcast.
+    /// The address given to [`ServiceBuilder::new`] must be the one bound by the external server.
+    ///
+    /// The procedures of the application must not use the identifiers reserved by plumcast
+    /// (see [`procedures`]).
+    ///
+    /// [`Service`]: ./struct.Service.html
+    /// [`procedures`]: ./fn.procedures.html
+    /// [`rpc_server_builder_mut`]: #method.rpc_server_builder_mut
+    /// [`ServiceBuilder::new`]: #method.new
+    pub fn finish_with_external_server<S, M, G>(
+        self,
+        spawner: S,
+        local_id_gen: G,
+        rpc_server_builder: &mut RpcServerBuilder,
+    ) -> Result<Service<M>>
+    where
+        S: Spawn + Send + Sync + 'static,
+        M: MessagePayload,
+        G: GenerateLocalNodeId,
+    {
+        self.finish_with_payload_decoder_maker(
+            spawner,
+            local_id_gen,
+            Default::default(),
+            Some(rpc_server_builder),
+        )
+    }
+
+    /// Builds a [`Service`] whose payload decoders are made by using the given allocator.
+    ///
+    /// See [`DecoderWithAllocator`] for more details.
+    ///
+    /// [`Service`]: ./struct.Service.html
+    /// [`DecoderWithAllocator`]: ../message/trait.DecoderWithAllocator.html
+    pub fn finish_with_allocator<S, M, G>(
+        self,
+        spawner: S,
+        local_id_gen: G,
+        allocator: <M::Decoder as DecoderWithAllocator>::Allocator,
+    ) -> Result<Service<M>>
+    where
+        S: Spawn + Send + Sync + 'static,
+        M: MessagePayload,
+        M::Decoder: DecoderWithAllocator,
+        G: GenerateLocalNodeId,
+    {
+        let maker = PayloadDecoderMaker::new(move || M::Decoder::with_allocator(allocator.clone()));
+        self.finish_with_payload_decoder_maker(spawner, local_id_gen, maker, None)
+    }
+
+    /// Builds a [`Service`] that takes over the local nodes of a stopped service.
+    ///
+    /// `previous` is the handle of the stopped service (e.g., the one which future returned
+    /// an error and was dropped), and the new service must bind the same address as it.
+    /// When a local node detects that the previous service has stopped,
+    /// it re-registers itself with the new service and resumes its operation
+    /// (the RPC messages queued by the previous service are lost).
+    /// The identifiers of the new local nodes are generated by the generator of
+    /// the previous service, so they do not collide with the ones of the existing nodes,
+    /// and the gossip payloads are decoded in the same way as the previous service
+    /// (e.g., by the allocator given to [`finish_with_allocator`]).
+    ///
+    /// Only the newest service is kept by the handles of the older ones,
+    /// so the nodes that detect the stop late re-register with it directly.
+    /// Use [`restartable`] to let the nodes wait for the new service
+    /// if they may detect the stop before this method is called.
+    ///
+    /// If the address differs from the one of `previous`, an `ErrorKind::InvalidInput` error
+    /// is returned.
+    ///
     /// [`Service`]: ./struct.Service.html
-    pub fn finish<S, M, G>(mut self, spawner: S, local_id_gen: G) -> Service<M>
+    /// [`finish_with_allocator`]: #method.finish_with_allocator
+    /// [`restartable`]: #method.restartable
+    pub fn restart<S, M>(self, spawner: S, previous: &ServiceHandle<M>) -> Result<Service<M>>
+    where
+        S: Spawn + Send + Sync + 'static,
+        M: MessagePayload,
+    {
+        let addr = self.addr_normalizer.normalize_addr(self.server_addr);
+        track_assert_eq!(addr, previous.server_addr, ErrorKind::InvalidInput);
+
+        let local_id_gen = previous.local_id_gen.clone();
+        let payload_decoder_maker = previous.payload_decoder_maker.clone();
+        let mut service = track!(self.finish_with_payload_decoder_maker(
+            spawner,
+            local_id_gen,
+            payload_decoder_maker,
+            None
+        ))?;
+
+        // NOTE: All the generations share the slot, so it only keeps the newest service alive
+        // (the slot is cleared when the newest service is dropped).
+        service.handle.successor = Arc::clone(&previous.successor);
+        previous.successor.store(Some(service.handle()));
+        Ok(service)
+    }
+
+    fn finish_with_payload_decoder_maker<S, M, G>(
+        mut self,
+        spawner: S,
+        local_id_gen: G,
+        mut payload_decoder_maker: PayloadDecoderMaker<M>,
+        external_server: Option<&mut RpcServerBuilder>,
+    ) -> Result<Service<M>>
     where
         S: Spawn + Send + Sync + 'static,
         M: MessagePayload,
         G: GenerateLocalNodeId,
     {
+        track!(self.validate())?;
         let spawner = ArcSpawn::new(spawner);
         let (command_tx, command_rx) = mpsc::channel();
-        let rpc_client_service = self.rpc_client_service_builder.finish(spawner.clone());
+        let (rpc_client_service, rpc_service) = match self.shared_rpc_client_service.take() {
+            Some(handle) => (None, handle),
+            None => {
+                let client_service = self.rpc_client_service_builder.finish(spawner.clone());
+                let handle = client_service.handle();
+                (Some(client_service), handle)
+            }
+        };
 
-        let metrics = ServiceMetrics::new(self.metrics.clone());
-        let removed_nodes_metrics = NodeMetrics::new(self.metrics.clone());
+        let metrics = ServiceMetrics::new(self.metrics_factory());
+        let clock_driver = self
+            .shared_tick_interval
+            .map(|interval| Clock::new().drive(interval));
+        let removed_nodes_metrics =
+            NodeMetrics::new(self.metrics_factory(), &NodeHistogramBuckets::default());
+        let original_payload_decoder_maker = payload_decoder_maker.clone();
+        #[cfg(feature = "encryption")]
+        let payload_cipher = self
+            .payload_key
+            .map(|(key_id, secret)| PayloadCipher::new(key_id, secret));
         let handle = ServiceHandle {
-            server_addr: self.server_addr,
+            server_addr: self.addr_normalizer.normalize_addr(self.server_addr),
             command_tx,
-            rpc_service: rpc_client_service.handle(),
+            command_queue_len: Default::default(),
+            max_command_queue_len: self.max_command_queue_len,
+            rpc_service,
             local_nodes: Default::default(),
             local_id_gen: ArcLocalNodeIdGenerator::new(local_id_gen),
             metrics: metrics.clone(),
             metric_builder: Arc::new(Mutex::new(self.metrics)),
+            metrics_sink: self.metrics_sink,
+            metrics_enabled: self.metrics_enabled,
+            tombstones: Default::default(),
+            tombstone_duration: self.tombstone_duration,
+            addr_normalizer: self.addr_normalizer,
+            peer_protocols: PeerProtocols::default(),
+            wire_codecs: Arc::new(self.wire_codecs),
+            protocol_negotiation: self.protocol_negotiation,
+            restartable: self.restartable,
+            payload_decoder_maker: original_payload_decoder_maker,
+            unknown_destination_policy: self.unknown_destination_policy,
+            join_policy: self.join_policy,
+            parameter_update_policy: self.parameter_update_policy,
+            tree_repair_priorities: self.tree_repair_priorities,
+            circuit_breaker: self.circuit_breaker,
+            #[cfg(feature = "encryption")]
+            payload_cipher: payload_cipher.clone(),
+            peer_stats: Default::default(),
+            observers: Default::default(),
+            zones: Default::default(),
+            clock: clock_driver.as_ref().map(|d| d.clock().clone()),
+            local_broadcast_seqno: Default::default(),
+            successor: Default::default(),
+            #[cfg(any(test, feature = "testing"))]
+            in_memory_outbox: None,
+            logger: self.logger.clone(),
         };
 
-        rpc::hyparview::register_handlers(&mut self.rpc_server_builder, &handle);
-        rpc::plumtree::register_handlers(&mut self.rpc_server_builder, &handle);
-        let rpc_server = self.rpc_server_builder.finish(spawner);
+        payload_decoder_maker.set_payload_size_limit(PayloadSizeLimit::new(
+            self.max_payload_size,
+            metrics.oversized_payload_frames.clone(),
+        ));
+        payload_decoder_maker.set_payload_decode_budget(PayloadDecodeBudget::new(
+            self.max_decoding_payload_bytes,
+            metrics.decoding_payload_bytes.clone(),
+            metrics.throttled_payload_decodes.clone(),
+        ));
+        payload_decoder_maker.set_received_bytes_counter(metrics.received_gossip_bytes.clone());
+        payload_decoder_maker
+            .set_decode_errors_counter(metrics.codec_errors.decode(GossipCast::<M>::NAME));
+        #[cfg(feature = "encryption")]
+        {
+            if let Some(cipher) = payload_cipher {
+                payload_decoder_maker.set_payload_cipher(cipher);
+            }
+        }
+        let rpc_server = if let Some(rpc_server_builder) = external_server {
+            rpc::register_handlers(rpc_server_builder, &handle, payload_decoder_maker);
+            None
+        } else {
+            rpc::register_handlers(&mut self.rpc_server_builder, &handle, payload_decoder_maker);
+            Some(self.rpc_server_builder.finish(spawner))
+        };
 
-        Service {
+        Ok(Service {
             logger: self.logger.clone(),
             command_rx,
             rpc_server,
@@ -108,6 +743,78 @@ impl ServiceBuilder {
             handle,
             metrics,
             removed_nodes_metrics,
+            event_log: EventLog::new(self.event_log_capacity),
+            lease_duration: self.node_lease_duration,
+            lease_timeout: if self.node_lease_duration > Duration::from_secs(0) {
+                Some(timer::timeout(self.node_lease_duration))
+            } else {
+                None
+            },
+            clock_driver,
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        let zero = Duration::from_secs(0);
+        if let Some(size) = self.max_payload_size {
+            track_assert!(
+                size >= 1,
+                ErrorKind::InvalidInput,
+                "The maximum payload size must be positive"
+            );
+        }
+        if let Some(bytes) = self.max_decoding_payload_bytes {
+            let max_payload_size = self.max_payload_size.unwrap_or(0);
+            track_assert!(
+                bytes >= 1 && bytes >= max_payload_size,
+                ErrorKind::InvalidInput,
+                "The maximum decoding payload bytes {} must be positive and \
+                 at least the maximum payload size {}",
+                bytes,
+                max_payload_size
+            );
+        }
+        if let Some(interval) = self.shared_tick_interval {
+            track_assert_ne!(
+                interval,
+                zero,
+                ErrorKind::InvalidInput,
+                "The shared tick interval must be positive"
+            );
+            track_assert!(
+                self.node_lease_duration == zero || self.node_lease_duration > interval,
+                ErrorKind::InvalidInput,
+                "The node lease duration {:?} must be longer than the shared tick interval {:?}",
+                self.node_lease_duration,
+                interval
+            );
+        }
+        track_assert!(
+            self.max_command_queue_len >= 1,
+            ErrorKind::InvalidInput,
+            "The maximum command queue length must be positive"
+        );
+        if let Some(breaker) = self.circuit_breaker {
+            track_assert!(
+                breaker.failure_threshold >= 1,
+                ErrorKind::InvalidInput,
+                "The failure threshold of the circuit breaker must be positive"
+            );
+            track_assert_ne!(
+                breaker.cool_down,
+                zero,
+                ErrorKind::InvalidInput,
+                "The cool-down of the circuit breaker must be positive"
+            );
+        }
+        Ok(())
+    }
+
+    fn metrics_factory(&self) -> MetricsFactory {
+        if self.metrics_enabled {
+            MetricsFactory::new(self.metrics.clone(), self.metrics_sink.clone(), Vec::new())
+        } else {
+            MetricsFactory::disabled()
         }
     }
 }
@@ -125,11 +832,15 @@ impl ServiceBuilder {
 pub struct Service<M: MessagePayload> {
     logger: Logger,
     command_rx: mpsc::Receiver<Command<M>>, // NOTE: infinite stream
-    rpc_server: RpcServer<ArcSpawn>,
-    rpc_client_service: RpcClientService,
+    rpc_server: Option<RpcServer<ArcSpawn>>,
+    rpc_client_service: Option<RpcClientService>,
     handle: ServiceHandle<M>,
     metrics: ServiceMetrics,
     removed_nodes_metrics: NodeMetrics,
+    event_log: EventLog,
+    lease_duration: Duration,
+    lease_timeout: Option<Timeout>,
+    clock_driver: Option<ClockDriver>,
 }
 impl<M> Service<M>
 where
@@ -154,19 +865,55 @@ where
     }
 
     /// Returns a reference to the RPC server of the service.
-    pub fn rpc_server(&self) -> &RpcServer<ArcSpawn> {
-        &self.rpc_server
+    ///
+    /// `None` is returned if the service uses an external server
+    /// (see [`ServiceBuilder::finish_with_external_server`]) or the server has been taken.
+    ///
+    /// [`ServiceBuilder::finish_with_external_server`]: ./struct.ServiceBuilder.html#method.finish_with_external_server
+    pub fn rpc_server(&self) -> Option<&RpcServer<ArcSpawn>> {
+        self.rpc_server.as_ref()
     }
 
     /// Returns a reference to the RPC client service of the service.
-    pub fn rpc_client_service(&self) -> &RpcClientService {
-        &self.rpc_client_service
+    ///
+    /// `None` is returned if the client service has been taken or the service uses
+    /// a shared client service (see [`ServiceBuilder::shared_rpc_client_service`]).
+    ///
+    /// [`ServiceBuilder::shared_rpc_client_service`]: ./struct.ServiceBuilder.html#method.shared_rpc_client_service
+    pub fn rpc_client_service(&self) -> Option<&RpcClientService> {
+        self.rpc_client_service.as_ref()
+    }
+
+    /// Takes the RPC server out of the service, so that it can be spawned as an independent future.
+    ///
+    /// The service does not poll the taken server anymore.
+    /// The server must be kept running while the service is used.
+    pub fn take_rpc_server(&mut self) -> Option<RpcServer<ArcSpawn>> {
+        self.rpc_server.take()
+    }
+
+    // NOTE: The handles cloned before calling this method keep sending messages via RPC.
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn set_in_memory_outbox(&mut self, outbox: InMemoryOutbox<M>) {
+        self.handle.in_memory_outbox = Some(outbox);
+    }
+
+    /// Takes the RPC client service out of the service,
+    /// so that it can be spawned as an independent future.
+    ///
+    /// The service does not poll the taken client service anymore.
+    /// The client service must be kept running while the service is used,
+    /// otherwise no messages are sent to remote nodes.
+    pub fn take_rpc_client_service(&mut self) -> Option<RpcClientService> {
+        self.rpc_client_service.take()
     }
 
     fn handle_command(&mut self, command: Command<M>) -> Result<()> {
         match command {
             Command::Register(node) => {
                 info!(self.logger, "Registers a local node: {:?}", node);
+                let id = NodeId::new(self.handle.server_addr, node.local_id());
+                self.event_log.record("register", Some(id), String::new);
                 track_assert!(
                     !self.handle
                         .local_nodes
@@ -176,6 +923,7 @@ where
                 );
 
                 self.metrics.registered_nodes.increment();
+                self.handle.remove_tombstone(node.local_id());
                 self.handle.local_nodes.update(|nodes| {
                     let mut nodes = (*nodes).clone();
                     nodes.insert(node.local_id(), node.clone());
@@ -184,6 +932,8 @@ where
             }
             Command::Deregister(node) => {
                 info!(self.logger, "Deregisters a local node: {:?}", node);
+                let id = NodeId::new(self.handle.server_addr, node);
+                self.event_log.record("deregister", Some(id), String::new);
                 track_assert!(
                     self.handle.local_nodes.load().contains_key(&node),
                     ErrorKind::InconsistentState; node
@@ -197,10 +947,43 @@ where
                     }
                     nodes
                 });
+                self.handle.add_tombstone(node);
             }
         }
         Ok(())
     }
+
+    fn expire_leases(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let expired = self
+            .handle
+            .local_nodes
+            .load()
+            .values()
+            .filter(|node| node.lease().is_expired(self.lease_duration, now))
+            .filter(|node| node.lease().expire())
+            .map(|node| node.local_id())
+            .collect::<Vec<_>>();
+        for node in expired {
+            warn!(
+                self.logger,
+                "The liveness lease of a local node has expired: {:?}", node
+            );
+            self.metrics.expired_node_leases.increment();
+            track!(self.handle_command(Command::Deregister(node)))?;
+        }
+        Ok(())
+    }
+
+    fn poll_lease_timeout(&mut self) -> Result<bool> {
+        if let Some(ref mut timeout) = self.lease_timeout {
+            if track!(timeout.poll().map_err(Error::from))?.is_ready() {
+                *timeout = timer::timeout(self.lease_duration);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
 }
 impl<M> Future for Service<M>
 where
@@ -210,23 +993,53 @@ where
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        if let Async::Ready(()) = track!(self.rpc_client_service.poll())? {
-            track_panic!(
-                ErrorKind::Other,
-                "Unexpected termination of RPC client service"
-            );
+        if let Some(ref mut client_service) = self.rpc_client_service {
+            if let Async::Ready(()) = track!(client_service.poll())? {
+                track_panic!(
+                    ErrorKind::Other,
+                    "Unexpected termination of RPC client service"
+                );
+            }
         }
-        if let Async::Ready(()) = track!(self.rpc_server.poll())? {
-            track_panic!(ErrorKind::Other, "Unexpected termination of RPC server");
+        if let Some(ref mut server) = self.rpc_server {
+            if let Async::Ready(()) = track!(server.poll())? {
+                track_panic!(ErrorKind::Other, "Unexpected termination of RPC server");
+            }
         }
         while let Async::Ready(Some(command)) = self.command_rx.poll().expect("Never fails") {
-            track!(self.handle_command(command))?;
+            let len = self.handle.command_queue_len.fetch_sub(1, Ordering::SeqCst) - 1;
+            self.metrics.command_queue_len.set(len as f64);
+            if let Err(e) = self.handle_command(command) {
+                self.event_log.dump_if_inconsistent(&self.logger, &e);
+                return Err(track!(e));
+            }
+        }
+        while track!(self.poll_lease_timeout())? {
+            if let Err(e) = self.expire_leases() {
+                self.event_log.dump_if_inconsistent(&self.logger, &e);
+                return Err(track!(e));
+            }
+        }
+        if let Some(ref mut driver) = self.clock_driver {
+            track!(driver.poll())?;
         }
         Ok(Async::NotReady)
     }
 }
 impl<M: MessagePayload> Drop for Service<M> {
     fn drop(&mut self) {
+        let is_newest = self
+            .handle
+            .successor
+            .load()
+            .as_ref()
+            .as_ref()
+            .map_or(false, |newest| newest.is_same_service(&self.handle));
+        if is_newest {
+            // NOTE: Breaks the reference cycle between the handle and the shared successor slot.
+            self.handle.successor.store(None);
+        }
+
         let old = self.handle.local_nodes.swap(HashMap::new());
         self.metrics.deregistered_nodes.add_u64(old.len() as u64);
         for node in old.values() {
@@ -242,11 +1055,39 @@ impl<M: MessagePayload> Drop for Service<M> {
 pub struct ServiceHandle<M: MessagePayload> {
     server_addr: SocketAddr,
     command_tx: mpsc::Sender<Command<M>>,
+    command_queue_len: Arc<AtomicUsize>,
+    max_command_queue_len: usize,
     rpc_service: RpcClientServiceHandle,
     local_nodes: LocalNodes<M>,
     local_id_gen: ArcLocalNodeIdGenerator,
     metrics: ServiceMetrics,
     metric_builder: Arc<Mutex<MetricBuilder>>,
+    metrics_sink: Option<ArcMetricsSink>,
+    metrics_enabled: bool,
+    tombstones: Tombstones,
+    tombstone_duration: Duration,
+    addr_normalizer: ArcAddrNormalizer,
+    peer_protocols: PeerProtocols,
+    wire_codecs: Arc<Vec<WireCodec>>,
+    protocol_negotiation: bool,
+    restartable: bool,
+    payload_decoder_maker: PayloadDecoderMaker<M>,
+    unknown_destination_policy: UnknownDestinationPolicy,
+    join_policy: Option<ArcJoinPolicy>,
+    parameter_update_policy: Option<ArcParameterUpdatePolicy>,
+    tree_repair_priorities: TreeRepairPriorities,
+    circuit_breaker: Option<CircuitBreaker>,
+    #[cfg(feature = "encryption")]
+    payload_cipher: Option<PayloadCipher>,
+    peer_stats: PeerStatsTable,
+    observers: Observers,
+    zones: Zones,
+    clock: Option<Clock>,
+    local_broadcast_seqno: Arc<AtomicU64>,
+    successor: Arc<AtomicImmut<Option<ServiceHandle<M>>>>,
+    #[cfg(any(test, feature = "testing"))]
+    in_memory_outbox: Option<InMemoryOutbox<M>>,
+    logger: Logger,
 }
 impl<M: MessagePayload> ServiceHandle<M> {
     /// Returns the address of the RPC server used for inter node communications.
@@ -254,22 +1095,288 @@ impl<M: MessagePayload> ServiceHandle<M> {
         self.server_addr
     }
 
-    /// Returns the metrics of the service.
-    pub fn metrics(&self) -> &ServiceMetrics {
-        &self.metrics
-    }
-
+    /// Delivers a message to all the local nodes registered with the service.
+    ///
+    /// Unlike broadcasting via a node, the message is directly enqueued to the local nodes
+    /// without traversing the network, and it is yielded as-is by each node
+    /// (i.e., it is neither relayed to remote nodes nor kept by the local nodes,
+    /// so it needs not to be forgot).
+    /// This is useful for intra-process control messages in multi-node hosts.
+    /// Such messages can be distinguished by `Message::is_local()`.
+    ///
+    /// The node identifier part of the returned message identifier is
+    /// `NodeId::new(self.rpc_server_addr(), LocalNodeId::new(u64::max_value()))`.
+    pub fn broadcast_local(&self, payload: M) -> MessageId {
+        let seqno = self.local_broadcast_seqno.fetch_add(1, Ordering::SeqCst);
+        let origin = NodeId::new(self.server_addr, LocalNodeId::new(u64::max_value()));
+        let id = MessageId::new(origin, seqno);
+        let mut envelope = Envelope::new(payload);
+        envelope.local = true;
+        let m = PlumtreeAppMessage {
+            id,
+            payload: envelope,
+        };
+        for node in self.local_nodes.load().values() {
+            node.send_rpc_message(RpcMessage::Local(m.clone()));
+        }
+        id
+    }
+
+    /// Returns the handle of the RPC client service used for sending messages to remote nodes.
+    ///
+    /// See [`ServiceBuilder::shared_rpc_client_service`] for the usage.
+    ///
+    /// [`ServiceBuilder::shared_rpc_client_service`]: ./struct.ServiceBuilder.html#method.shared_rpc_client_service
+    pub fn rpc_client_service(&self) -> &RpcClientServiceHandle {
+        &self.rpc_service
+    }
+
+    /// Returns the clock shared by the local nodes of the service.
+    ///
+    /// `None` is returned if `ServiceBuilder::shared_tick_interval()` is not specified.
+    pub fn clock(&self) -> Option<&Clock> {
+        self.clock.as_ref()
+    }
+
+    /// Returns the metrics of the service.
+    pub fn metrics(&self) -> &ServiceMetrics {
+        &self.metrics
+    }
+
+    /// Returns the statistics of the communications with remote services
+    /// (keyed by their RPC server addresses).
+    ///
+    /// This is useful for finding the peer that is backpressuring the local nodes
+    /// (e.g., the one which send queue is full).
+    pub fn peer_stats(&self) -> HashMap<SocketAddr, PeerStats> {
+        self.peer_stats
+            .lock()
+            .map(|stats| stats.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the wire codec used for the frames sent to the remote service
+    /// which RPC server address is `peer`.
+    ///
+    /// See [`ServiceBuilder::wire_codecs`] for more details.
+    ///
+    /// [`ServiceBuilder::wire_codecs`]: ./struct.ServiceBuilder.html#method.wire_codecs
+    pub fn wire_codec(&self, peer: SocketAddr) -> WireCodec {
+        match self.peer_protocols.get(peer) {
+            Some(PeerProtocol::Compatible(_, codec)) => codec,
+            _ => WireCodec::Fixed,
+        }
+    }
+
+    /// Checks the health of the service.
+    ///
+    /// This sends a ping to the RPC server of the service itself,
+    /// so the returned future succeeds only if the server is accepting connections.
+    /// The resulting [`Health`] contains the number of the nodes registered in the service.
+    ///
+    /// This is useful for implementing readiness probes (e.g., of Kubernetes).
+    ///
+    /// [`Health`]: ../admin/struct.Health.html
+    pub fn health(&self) -> HealthCheck {
+        self.ping(self.server_addr)
+    }
+
+    /// Sends a ping to the service running on the given address, and waits for its health.
+    pub fn ping(&self, server_addr: SocketAddr) -> HealthCheck {
+        rpc::admin::ping(server_addr, &self.rpc_service)
+    }
+
     /// Returns the identifiers of the nodes registered in the service.
     pub fn local_nodes(&self) -> Vec<LocalNodeId> {
         self.local_nodes.load().keys().cloned().collect()
     }
 
-    pub(crate) fn metric_builder(&self) -> MetricBuilder {
-        if let Ok(m) = self.metric_builder.lock() {
-            m.clone()
-        } else {
-            MetricBuilder::new()
+    /// Returns the statuses of the nodes registered in the service.
+    ///
+    /// Each status is a snapshot taken at the last tick of the node,
+    /// so nodes that have not ticked yet are not included in the result.
+    /// Use [`Node::status`] to take the latest status of a node.
+    ///
+    /// [`Node::status`]: ../node/struct.Node.html#method.status
+    pub fn local_node_statuses(&self) -> Vec<NodeStatus> {
+        let mut statuses = self
+            .local_nodes
+            .load()
+            .values()
+            .filter_map(|node| node.status())
+            .collect::<Vec<_>>();
+        statuses.sort_by_key(|s| s.id());
+        statuses
+    }
+
+    /// Fetches the statuses of the nodes registered in the service running on the given address.
+    ///
+    /// The statuses are sent by the remote service via the admin RPC.
+    /// See also [`local_node_statuses`].
+    ///
+    /// [`local_node_statuses`]: #method.local_node_statuses
+    pub fn node_statuses(&self, server_addr: SocketAddr) -> NodeStatusQuery {
+        rpc::admin::node_statuses(server_addr, &self.rpc_service)
+    }
+
+    /// Returns the recent protocol events kept by the nodes registered in the service.
+    ///
+    /// The events are sorted by the node identifiers and then the order they occurred.
+    /// See [`NodeBuilder::event_log_capacity`] for the number of the events kept by each node.
+    ///
+    /// [`NodeBuilder::event_log_capacity`]: ../node/struct.NodeBuilder.html#method.event_log_capacity
+    pub fn local_debug_dump(&self) -> Vec<DebugEvent> {
+        let mut events = self
+            .local_nodes
+            .load()
+            .values()
+            .flat_map(|node| node.debug_events(NodeId::new(self.server_addr, node.local_id())))
+            .collect::<Vec<_>>();
+        events.sort_by_key(|e| (e.node(), e.seqno()));
+        events
+    }
+
+    /// Fetches the recent protocol events kept by the nodes registered in the service
+    /// running on the given address.
+    ///
+    /// The events are sent by the remote service via the admin RPC.
+    /// See also [`local_debug_dump`].
+    ///
+    /// [`local_debug_dump`]: #method.local_debug_dump
+    pub fn debug_dump(&self, server_addr: SocketAddr) -> DebugDumpQuery {
+        rpc::admin::debug_dump(server_addr, &self.rpc_service)
+    }
+
+    /// Registers a key that can be used for decrypting the payloads of gossip messages.
+    ///
+    /// If a key having the same identifier has been registered, it is replaced.
+    ///
+    /// Keys can be rotated without a coordinated restart of the cluster as follows:
+    /// 1. Register the new key in all the services by this method
+    /// 2. Switch the key used for encryption to the new one by [`set_primary_payload_key`]
+    /// 3. Remove the old key by [`remove_payload_key`]
+    ///
+    /// Each step should be completed in all the services before starting the next step.
+    ///
+    /// If the encryption is disabled (see [`ServiceBuilder::payload_key`]),
+    /// an `ErrorKind::InvalidInput` error is returned.
+    ///
+    /// [`set_primary_payload_key`]: #method.set_primary_payload_key
+    /// [`remove_payload_key`]: #method.remove_payload_key
+    /// [`ServiceBuilder::payload_key`]: ./struct.ServiceBuilder.html#method.payload_key
+    #[cfg(feature = "encryption")]
+    pub fn add_payload_key(&self, key_id: u32, secret: [u8; 32]) -> Result<()> {
+        let cipher = track!(self.payload_cipher())?;
+        cipher.add_key(key_id, secret);
+        self.metrics.payload_key_changes.increment();
+        Ok(())
+    }
+
+    /// Makes the given key the one used for encrypting the payloads of gossip messages.
+    ///
+    /// If the key has not been registered or the encryption is disabled,
+    /// an `ErrorKind::InvalidInput` error is returned.
+    #[cfg(feature = "encryption")]
+    pub fn set_primary_payload_key(&self, key_id: u32) -> Result<()> {
+        let cipher = track!(self.payload_cipher())?;
+        track_assert!(
+            cipher.set_primary_key(key_id),
+            ErrorKind::InvalidInput,
+            "Unknown payload key: {}",
+            key_id
+        );
+        self.metrics.payload_key_changes.increment();
+        Ok(())
+    }
+
+    /// Removes the given key.
+    ///
+    /// The payloads encrypted with the key are rejected afterwards.
+    ///
+    /// If the key is unknown or used for encryption, or the encryption is disabled,
+    /// an `ErrorKind::InvalidInput` error is returned.
+    #[cfg(feature = "encryption")]
+    pub fn remove_payload_key(&self, key_id: u32) -> Result<()> {
+        let cipher = track!(self.payload_cipher())?;
+        track_assert!(
+            cipher.remove_key(key_id),
+            ErrorKind::InvalidInput,
+            "Unknown or primary payload key: {}",
+            key_id
+        );
+        self.metrics.payload_key_changes.increment();
+        Ok(())
+    }
+
+    /// Returns the identifiers of the registered payload keys in ascending order,
+    /// and the identifier of the one used for encryption.
+    ///
+    /// `None` is returned if the encryption is disabled.
+    #[cfg(feature = "encryption")]
+    pub fn payload_key_ids(&self) -> Option<(Vec<u32>, u32)> {
+        self.payload_cipher
+            .as_ref()
+            .map(|c| (c.key_ids(), c.primary_key_id()))
+    }
+
+    /// Sends the given parameter update to the node via the admin RPC.
+    ///
+    /// The update is validated by this method and again by the destination node
+    /// (the result of the latter is only logged by the node).
+    /// Note that the update is dropped by the destination service unless it accepts
+    /// the update from this service (see [`ServiceBuilder::parameter_update_policy`]).
+    /// The update can only be sent to the services that have negotiated a protocol version
+    /// (see [`ServiceBuilder::protocol_negotiation`]).
+    ///
+    /// [`ServiceBuilder::parameter_update_policy`]: ./struct.ServiceBuilder.html#method.parameter_update_policy
+    /// [`ServiceBuilder::protocol_negotiation`]: ./struct.ServiceBuilder.html#method.protocol_negotiation
+    ///
+    /// See also [`Node::update_parameter`].
+    ///
+    /// [`Node::update_parameter`]: ../node/struct.Node.html#method.update_parameter
+    pub fn send_parameter_update(&self, node: NodeId, update: ParameterUpdate) -> Result<()> {
+        track!(update.validate())?;
+        track!(self.send_message(node, RpcMessage::Admin(update)))
+    }
+
+    pub(crate) fn metrics_factory(
+        &self,
+        builder: Option<MetricBuilder>,
+        labels: Vec<(String, String)>,
+    ) -> MetricsFactory {
+        if !self.metrics_enabled {
+            return MetricsFactory::disabled();
         }
+        let builder = builder.unwrap_or_else(|| {
+            if let Ok(m) = self.metric_builder.lock() {
+                m.clone()
+            } else {
+                MetricBuilder::new()
+            }
+        });
+        MetricsFactory::new(builder, self.metrics_sink.clone(), labels)
+    }
+
+    pub(crate) fn successor(&self) -> Option<ServiceHandle<M>> {
+        (*self.successor.load())
+            .clone()
+            .filter(|successor| !successor.is_same_service(self))
+    }
+
+    pub(crate) fn is_restartable(&self) -> bool {
+        self.restartable
+    }
+
+    fn is_same_service(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.command_queue_len, &other.command_queue_len)
+    }
+
+    pub(crate) fn normalize_node_id(&self, id: NodeId) -> NodeId {
+        self.addr_normalizer.normalize_node_id(id)
+    }
+
+    pub(crate) fn normalize_rpc_message(&self, message: RpcMessage<M>) -> RpcMessage<M> {
+        message.map_node_ids(|id| self.addr_normalizer.normalize_node_id(id))
     }
 
     pub(crate) fn generate_node_id(&self) -> NodeId {
@@ -286,34 +1393,394 @@ impl<M: MessagePayload> ServiceHandle<M> {
         id: LocalNodeId,
         sender: &NodeId,
     ) -> Option<NodeHandle<M>> {
-        if let Some(node) = self.local_nodes.load().get(&id).cloned() {
-            Some(node)
-        } else {
-            use hyparview::message::{DisconnectMessage, ProtocolMessage};
+        self.update_peer_stats(sender.address(), |stats| stats.received_messages += 1);
+        let local_nodes = self.local_nodes.load();
+        if let Some(node) = local_nodes.get(&id).cloned() {
+            return Some(node);
+        }
+        if self.is_tombstoned(id) {
+            self.metrics.tombstoned_destination_messages.increment();
+            return None;
+        }
 
-            self.metrics.destination_unknown_messages.increment();
-            let missing = NodeId::new(self.server_addr, id);
-            let message = DisconnectMessage {
-                sender: missing,
-                alive: false,
-            };
-            let message = ProtocolMessage::Disconnect(message);
-            let _ = self.send_message(*sender, RpcMessage::Hyparview(message));
-            None
+        self.metrics.destination_unknown_messages.increment();
+        match self.unknown_destination_policy {
+            UnknownDestinationPolicy::Ignore => None,
+            UnknownDestinationPolicy::Redirect(default) if local_nodes.contains_key(&default) => {
+                self.metrics.redirected_messages.increment();
+                local_nodes.get(&default).cloned()
+            }
+            UnknownDestinationPolicy::Disconnect | UnknownDestinationPolicy::Redirect(_) => {
+                self.reply_disconnect(id, sender);
+                None
+            }
+        }
+    }
+
+    /// Checks whether `peer` is allowed to enter the overlay via the local node `id`
+    /// by the message of `kind` (see [`JoinPolicy`]).
+    ///
+    /// [`JoinPolicy`]: ./trait.JoinPolicy.html
+    pub(crate) fn is_join_allowed(
+        &self,
+        id: LocalNodeId,
+        sender: &NodeId,
+        peer: &NodeId,
+        kind: JoinKind,
+    ) -> bool {
+        let policy = match self.join_policy {
+            None => return true,
+            Some(ref policy) => policy,
+        };
+        if policy.is_allowed(peer, kind) {
+            return true;
+        }
+
+        debug!(
+            self.logger,
+            "Rejects the {:?} message from {:?} (joining node: {:?})", kind, sender, peer
+        );
+        self.update_peer_stats(sender.address(), |stats| stats.received_messages += 1);
+        match kind {
+            JoinKind::Join => self.metrics.rejected_joins.increment(),
+            JoinKind::ForwardJoin => self.metrics.rejected_forward_joins.increment(),
+            JoinKind::Neighbor => self.metrics.rejected_neighbors.increment(),
+        }
+        if kind != JoinKind::ForwardJoin {
+            self.reply_disconnect(id, sender);
+        }
+        false
+    }
+
+    /// Checks whether the service at `caller` is allowed to apply `update` to the local nodes
+    /// (see [`ParameterUpdatePolicy`]).
+    ///
+    /// [`ParameterUpdatePolicy`]: ../admin/trait.ParameterUpdatePolicy.html
+    pub(crate) fn is_parameter_update_allowed(
+        &self,
+        caller: SocketAddr,
+        update: &ParameterUpdate,
+    ) -> bool {
+        let allowed = self
+            .parameter_update_policy
+            .as_ref()
+            .map_or(false, |policy| policy.is_allowed(caller, update));
+        if !allowed {
+            warn!(
+                self.logger,
+                "Rejects the parameter update from {}: {:?}", caller, update
+            );
+            self.metrics.rejected_parameter_updates.increment();
+        }
+        allowed
+    }
+
+    fn reply_disconnect(&self, id: LocalNodeId, sender: &NodeId) {
+        use hyparview::message::{DisconnectMessage, ProtocolMessage};
+
+        let missing = NodeId::new(self.server_addr, id);
+        let message = DisconnectMessage {
+            sender: missing,
+            alive: false,
+        };
+        let message = ProtocolMessage::Disconnect(message);
+        let _ = self.send_message(*sender, RpcMessage::Hyparview(message));
+    }
+
+    pub(crate) fn record_received_payload(&self, sender: &NodeId, size: u64) {
+        self.metrics.received_payload_bytes.add_u64(size);
+        self.update_peer_stats(sender.address(), |stats| {
+            stats.received_payload_bytes += size
+        });
+    }
+
+    fn update_peer_stats<F>(&self, peer: SocketAddr, f: F)
+    where
+        F: FnOnce(&mut PeerStats),
+    {
+        let peer = self.addr_normalizer.normalize_addr(peer);
+        if let Ok(mut stats) = self.peer_stats.lock() {
+            f(stats.entry(peer).or_default());
+        }
+    }
+
+    fn add_tombstone(&self, id: LocalNodeId) {
+        if self.tombstone_duration == Duration::from_secs(0) {
+            return;
+        }
+        if let Ok(mut tombstones) = self.tombstones.lock() {
+            let now = Instant::now();
+            tombstones.retain(|_, until| now < *until);
+            tombstones.insert(id, now + self.tombstone_duration);
         }
     }
 
-    pub(crate) fn register_local_node(&self, node: NodeHandle<M>) {
-        let command = Command::Register(node);
-        let _ = self.command_tx.send(command);
+    fn remove_tombstone(&self, id: LocalNodeId) {
+        if let Ok(mut tombstones) = self.tombstones.lock() {
+            tombstones.remove(&id);
+        }
+    }
+
+    fn is_tombstoned(&self, id: LocalNodeId) -> bool {
+        if let Ok(mut tombstones) = self.tombstones.lock() {
+            match tombstones.get(&id).cloned() {
+                None => false,
+                Some(until) if Instant::now() < until => true,
+                Some(_) => {
+                    tombstones.remove(&id);
+                    false
+                }
+            }
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn register_local_node(&self, node: NodeHandle<M>) -> Result<()> {
+        let len = self.command_queue_len.load(Ordering::SeqCst);
+        track_assert!(
+            len < self.max_command_queue_len,
+            ErrorKind::QueueFull,
+            "Too many pending commands: len={}, node={:?}",
+            len,
+            node.local_id()
+        );
+        self.send_command(Command::Register(node));
+        Ok(())
     }
 
     pub(crate) fn deregister_local_node(&self, node: LocalNodeId) {
-        let command = Command::Deregister(node);
-        let _ = self.command_tx.send(command);
+        // NOTE: Deregistrations are never rejected, otherwise the nodes would be leaked.
+        self.send_command(Command::Deregister(node));
+    }
+
+    fn send_command(&self, command: Command<M>) {
+        let len = self.command_queue_len.fetch_add(1, Ordering::SeqCst) + 1;
+        if self.command_tx.send(command).is_err() {
+            // NOTE: The service has been dropped.
+            self.command_queue_len.fetch_sub(1, Ordering::SeqCst);
+            return;
+        }
+        self.metrics.command_queue_len.set(len as f64);
+    }
+
+    pub(crate) fn handle_handshake(&self, handshake: Handshake) {
+        let peer = self.addr_normalizer.normalize_addr(handshake.server_addr);
+        let protocol = if let Some(version) = handshake.negotiate() {
+            let codec = handshake.negotiate_codec(&self.wire_codecs);
+            debug!(
+                self.logger,
+                "Protocol version negotiated: peer={}, version={}, codec={:?}",
+                peer,
+                version,
+                codec
+            );
+            PeerProtocol::Compatible(version, codec)
+        } else {
+            error!(
+                self.logger,
+                "Incompatible protocol version: peer={}, peer_versions={}..={}, local_versions={}..={}",
+                peer,
+                handshake.min_version,
+                handshake.max_version,
+                protocol::MIN_PROTOCOL_VERSION,
+                protocol::PROTOCOL_VERSION
+            );
+            self.metrics.incompatible_peers.increment();
+            PeerProtocol::Incompatible
+        };
+        if self
+            .peer_protocols
+            .complete_negotiation(peer, protocol)
+            .is_none()
+        {
+            // The peer has contacted us first, so we reply our versions to it.
+            let _ = self.send_handshake(peer);
+        }
+    }
+
+    /// Returns `true` if the given node is known as an observer.
+    ///
+    /// The roles of remote nodes are learned from the HyParView frames sent by (or about) them.
+    pub(crate) fn is_observer(&self, node: &NodeId) -> bool {
+        if node.address() == self.server_addr {
+            self.get_local_node(node.local_id())
+                .map_or(false, |n| n.is_observer())
+        } else {
+            self.observers
+                .lock()
+                .ok()
+                .map_or(false, |observers| observers.contains(node))
+        }
+    }
+
+    /// Returns the zone of the given node if it is known.
+    ///
+    /// The zones of remote nodes are learned from the HyParView frames
+    /// in the same way as their roles.
+    pub(crate) fn zone_of(&self, node: &NodeId) -> Option<String> {
+        if node.address() == self.server_addr {
+            self.get_local_node(node.local_id())
+                .and_then(|n| n.zone().map(|z| z.to_owned()))
+        } else {
+            self.zones
+                .lock()
+                .ok()
+                .and_then(|zones| zones.get(node).cloned())
+        }
+    }
+
+    /// Makes the attributes of the given nodes that are sent in a HyParView frame.
+    ///
+    /// If `with_role` is `true`, the role of the first node is included.
+    pub(crate) fn node_attributes(&self, subjects: &[NodeId], with_role: bool) -> NodeAttributes {
+        let observer = with_role && subjects.first().map_or(false, |n| self.is_observer(n));
+        let mut zones = subjects
+            .iter()
+            .map(|n| self.zone_of(n).unwrap_or_default())
+            .collect::<Vec<_>>();
+        if zones.iter().all(|z| z.is_empty()) {
+            zones.clear();
+        }
+        NodeAttributes { observer, zones }
+    }
+
+    /// Records the attributes of the given nodes received in a HyParView frame.
+    ///
+    /// If `with_role` is `true`, the role of the first node is also recorded.
+    pub(crate) fn update_node_attributes(
+        &self,
+        subjects: &[NodeId],
+        attrs: &NodeAttributes,
+        with_role: bool,
+    ) {
+        if with_role {
+            if let Some(&node) = subjects.first() {
+                let node = self.normalize_node_id(node);
+                if let Ok(mut observers) = self.observers.lock() {
+                    if attrs.observer {
+                        observers.insert(node);
+                    } else {
+                        observers.remove(&node);
+                    }
+                }
+            }
+        }
+        if let Ok(mut zones) = self.zones.lock() {
+            for (&node, zone) in subjects.iter().zip(attrs.zones.iter()) {
+                let node = self.normalize_node_id(node);
+                if !zone.is_empty() && node.address() != self.server_addr {
+                    zones.insert(node, zone.clone());
+                }
+            }
+        }
+    }
+
+    /// Forgets the attributes of the given node (e.g., because it has left the cluster).
+    pub(crate) fn forget_node_attributes(&self, node: NodeId) {
+        let node = self.normalize_node_id(node);
+        if let Ok(mut observers) = self.observers.lock() {
+            observers.remove(&node);
+        }
+        if let Ok(mut zones) = self.zones.lock() {
+            zones.remove(&node);
+        }
+    }
+
+    fn send_handshake(&self, peer: SocketAddr) -> Result<()> {
+        let handshake = Handshake::local(self.server_addr, &self.wire_codecs);
+        track!(rpc::handshake::handshake_cast(
+            peer,
+            handshake,
+            &self.rpc_service
+        ))
     }
 
     pub(crate) fn send_message(&self, peer: NodeId, message: RpcMessage<M>) -> Result<()> {
+        #[cfg(any(test, feature = "testing"))]
+        {
+            if let Some(ref outbox) = self.in_memory_outbox {
+                outbox.push(peer, message);
+                return Ok(());
+            }
+        }
+        if self.is_circuit_open(peer.address()) {
+            self.metrics.circuit_rejected_messages.increment();
+            track_panic!(
+                ErrorKind::PeerUnreachable(peer),
+                "The circuit to the peer is open"
+            );
+        }
+
+        let result = self
+            .send_message_without_stats(peer, message)
+            .map_err(|e| match *e.kind() {
+                ErrorKind::Other => track!(ErrorKind::PeerUnreachable(peer).takes_over(e)).into(),
+                _ => e,
+            });
+        self.update_peer_stats(peer.address(), |stats| match result {
+            Ok(()) => {
+                if stats.circuit_open_until.take().is_some() {
+                    info!(self.logger, "Closes the circuit to {}", peer.address());
+                }
+                stats.sent_messages += 1;
+                stats.consecutive_send_errors = 0;
+            }
+            Err(ref e) => {
+                stats.send_errors += 1;
+                stats.consecutive_send_errors += 1;
+                stats.last_error = Some((SystemTime::now(), e.to_string()));
+                if let Some(breaker) = self.circuit_breaker {
+                    if stats.consecutive_send_errors >= breaker.failure_threshold {
+                        warn!(
+                            self.logger,
+                            "Opens the circuit to {} for {:?} after {} consecutive send failures: {}",
+                            peer.address(),
+                            breaker.cool_down,
+                            stats.consecutive_send_errors,
+                            e
+                        );
+                        self.metrics.opened_circuits.increment();
+                        stats.circuit_open_until = Some(Instant::now() + breaker.cool_down);
+                    }
+                }
+            }
+        });
+        result
+    }
+
+    fn is_circuit_open(&self, peer: SocketAddr) -> bool {
+        if self.circuit_breaker.is_none() {
+            return false;
+        }
+        let peer = self.addr_normalizer.normalize_addr(peer);
+        self.peer_stats.lock().ok().map_or(false, |stats| {
+            stats
+                .get(&peer)
+                .and_then(|s| s.circuit_open_until)
+                .map_or(false, |until| Instant::now() < until)
+        })
+    }
+
+    /// Returns the layout of the frames sent to the given peer
+    /// (and starts a negotiation with the peer if it is enabled and not started yet).
+    fn framing(&self, peer: NodeId) -> Result<Framing> {
+        if self.protocol_negotiation && self.peer_protocols.start_negotiation(peer.address()) {
+            track!(self.send_handshake(peer.address()))?;
+        }
+        match self.peer_protocols.get(peer.address()) {
+            Some(PeerProtocol::Compatible(version, _)) => Ok(Framing::Versioned(version)),
+            Some(PeerProtocol::Incompatible) => track_panic!(
+                ErrorKind::Other,
+                "The peer speaks an incompatible protocol version: {:?}",
+                peer
+            ),
+            Some(PeerProtocol::Negotiating) | None => Ok(Framing::Legacy),
+        }
+    }
+
+    fn send_message_without_stats(&self, peer: NodeId, message: RpcMessage<M>) -> Result<()> {
+        let framing = track!(self.framing(peer))?;
         match message {
             RpcMessage::Hyparview(m) => {
                 use crate::rpc::hyparview as hv;
@@ -321,22 +1788,70 @@ impl<M: MessagePayload> ServiceHandle<M> {
 
                 match m {
                     ProtocolMessage::Join(m) => {
-                        track!(hv::join_cast(peer, m, &self.rpc_service))?;
+                        let attrs = self.node_attributes(&[m.sender], true);
+                        track!(hv::join_cast(
+                            peer,
+                            m,
+                            attrs,
+                            framing,
+                            &self.rpc_service,
+                            &self.metrics
+                        ))?;
                     }
                     ProtocolMessage::ForwardJoin(m) => {
-                        track!(hv::forward_join_cast(peer, m, &self.rpc_service))?;
+                        let attrs = self.node_attributes(&[m.new_node], true);
+                        track!(hv::forward_join_cast(
+                            peer,
+                            m,
+                            attrs,
+                            framing,
+                            &self.rpc_service,
+                            &self.metrics
+                        ))?;
                     }
                     ProtocolMessage::Neighbor(m) => {
-                        track!(hv::neighbor_cast(peer, m, &self.rpc_service))?;
+                        let attrs = self.node_attributes(&[m.sender], true);
+                        track!(hv::neighbor_cast(
+                            peer,
+                            m,
+                            attrs,
+                            framing,
+                            &self.rpc_service,
+                            &self.metrics
+                        ))?;
                     }
                     ProtocolMessage::Shuffle(m) => {
-                        track!(hv::shuffle_cast(peer, m, &self.rpc_service))?;
+                        let subjects = hv::shuffle_subjects(m.origin, &m.nodes);
+                        let attrs = self.node_attributes(&subjects, false);
+                        track!(hv::shuffle_cast(
+                            peer,
+                            m,
+                            attrs,
+                            framing,
+                            &self.rpc_service,
+                            &self.metrics
+                        ))?;
                     }
                     ProtocolMessage::ShuffleReply(m) => {
-                        track!(hv::shuffle_reply_cast(peer, m, &self.rpc_service))?;
+                        let subjects = hv::shuffle_subjects(m.sender, &m.nodes);
+                        let attrs = self.node_attributes(&subjects, false);
+                        track!(hv::shuffle_reply_cast(
+                            peer,
+                            m,
+                            attrs,
+                            framing,
+                            &self.rpc_service,
+                            &self.metrics
+                        ))?;
                     }
                     ProtocolMessage::Disconnect(m) => {
-                        track!(hv::disconnect_cast(peer, m, &self.rpc_service))?;
+                        track!(hv::disconnect_cast(
+                            peer,
+                            m,
+                            framing,
+                            &self.rpc_service,
+                            &self.metrics
+                        ))?;
                     }
                 }
             }
@@ -346,22 +1861,297 @@ impl<M: MessagePayload> ServiceHandle<M> {
 
                 match m {
                     ProtocolMessage::Gossip(m) => {
-                        track!(pt::gossip_cast(peer, m, &self.rpc_service))?;
+                        #[cfg(feature = "encryption")]
+                        let m = track!(self.seal_payload(m))?;
+                        track!(pt::gossip_cast(
+                            peer,
+                            m,
+                            framing,
+                            &self.rpc_service,
+                            &self.metrics
+                        ))?;
                     }
                     ProtocolMessage::Ihave(m) => {
-                        track!(pt::ihave_cast(peer, m, &self.rpc_service))?;
+                        track!(self.send_ihave(peer, m, None, framing))?;
                     }
                     ProtocolMessage::Graft(m) => {
-                        track!(pt::graft_cast(peer, m, &self.rpc_service))?;
+                        track!(pt::graft_cast(
+                            peer,
+                            m,
+                            &self.tree_repair_priorities,
+                            framing,
+                            &self.rpc_service,
+                            &self.metrics
+                        ))?;
                     }
                     ProtocolMessage::Prune(m) => {
-                        track!(pt::prune_cast(peer, m, &self.rpc_service))?;
+                        track!(pt::prune_cast(
+                            peer,
+                            m,
+                            &self.tree_repair_priorities,
+                            framing,
+                            &self.rpc_service,
+                            &self.metrics
+                        ))?;
                     }
                 }
             }
+            RpcMessage::Admin(m) => {
+                track_assert_ne!(
+                    framing,
+                    Framing::Legacy,
+                    ErrorKind::Other,
+                    "The peer has not negotiated a protocol version: {:?}",
+                    peer
+                );
+                track!(rpc::admin::update_parameter_cast(
+                    peer,
+                    self.server_addr,
+                    m,
+                    &self.rpc_service
+                ))?;
+            }
+            RpcMessage::Retract(m) => {
+                if let Framing::Versioned(version) = framing {
+                    track!(rpc::plumtree::retract_cast(
+                        peer,
+                        m,
+                        version,
+                        &self.rpc_service,
+                        &self.metrics
+                    ))?;
+                } else {
+                    // NOTE: The peer does not know retractions,
+                    // so the message will just expire there.
+                    debug!(
+                        self.logger,
+                        "Retraction is not sent to the legacy peer {:?}: {:?}", peer, m.message_id
+                    );
+                }
+            }
+            RpcMessage::HashedIhave(m, hash) => {
+                track!(self.send_ihave(peer, m, Some(hash), framing))?;
+            }
+            RpcMessage::Local(m) => {
+                track_panic!(
+                    ErrorKind::Other,
+                    "Local messages cannot be sent to remote nodes: {:?}",
+                    m.id
+                );
+            }
         }
         Ok(())
     }
+
+    #[cfg(feature = "encryption")]
+    fn payload_cipher(&self) -> Result<&PayloadCipher> {
+        let cipher = track_assert_some!(
+            self.payload_cipher.as_ref(),
+            ErrorKind::InvalidInput,
+            "Payload encryption is disabled"
+        );
+        Ok(cipher)
+    }
+
+    #[cfg(feature = "encryption")]
+    fn seal_payload(&self, mut m: GossipMessage<M>) -> Result<GossipMessage<M>> {
+        let cipher = match self.payload_cipher {
+            None => return Ok(m),
+            Some(ref cipher) => cipher,
+        };
+        let payload = match m.message.payload.encoded_payload.take() {
+            Some(bytes) => bytes,
+            None => {
+                let bytes = track_assert_some!(
+                    encode_payload(&m.message.payload.payload),
+                    ErrorKind::InvalidInput,
+                    "Cannot encode the payload of {:?}",
+                    m.message.id
+                );
+                Arc::new(bytes)
+            }
+        };
+        let sealed = track_assert_some!(
+            cipher.seal(&m.message.id, &payload),
+            ErrorKind::InvalidInput,
+            "Cannot encrypt the payload of {:?}",
+            m.message.id
+        );
+        // NOTE: The sealed bytes are sent as is instead of the bytes produced by `M::Encoder`.
+        m.message.payload.encoded_payload = Some(Arc::new(sealed));
+        Ok(m)
+    }
+
+    fn send_ihave(
+        &self,
+        peer: NodeId,
+        m: IhaveMessage<M>,
+        content_hash: Option<u64>,
+        framing: Framing,
+    ) -> Result<()> {
+        use crate::rpc::plumtree as pt;
+
+        match (framing, self.wire_codec(peer.address())) {
+            (Framing::Versioned(version), WireCodec::Varint) => {
+                track!(pt::varint_ihave_cast(
+                    peer,
+                    m,
+                    content_hash,
+                    version,
+                    &self.rpc_service,
+                    &self.metrics
+                ))?;
+            }
+            _ => {
+                track!(pt::ihave_cast(
+                    peer,
+                    m,
+                    content_hash,
+                    framing,
+                    &self.rpc_service,
+                    &self.metrics
+                ))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Policy of handling the RPC messages destined for missing local nodes.
+///
+/// See [`ServiceBuilder::unknown_destination_policy`].
+///
+/// [`ServiceBuilder::unknown_destination_policy`]: ./struct.ServiceBuilder.html#method.unknown_destination_policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownDestinationPolicy {
+    /// Drops the messages silently.
+    ///
+    /// The senders notice the missing nodes only by their own failure detection.
+    Ignore,
+
+    /// Drops the messages and replies with HyParView `DISCONNECT` messages,
+    /// so that the senders remove the missing nodes from their views.
+    Disconnect,
+
+    /// Hands the messages to the given local node instead.
+    ///
+    /// This is useful for services whose local node identifiers change on restart
+    /// (e.g., `UnixtimeLocalNodeIdGenerator`), since the peers of the old nodes are
+    /// taken over by the new node rather than disconnected.
+    /// If the given node is missing too, this behaves the same as `Disconnect`.
+    Redirect(LocalNodeId),
+}
+
+/// RPC priorities of the Plumtree messages used for repairing broadcast trees.
+///
+/// Smaller values mean higher priorities (see `fibers_rpc::client::Options::priority`).
+/// For reference, fresh gossip messages are sent with the default priority of `fibers_rpc` (`128`),
+/// repair gossip messages and HyParView control messages with `100`,
+/// and `IHAVE` messages with `200`.
+///
+/// Since a `GRAFT` message is sent when a node noticed a missing message,
+/// delaying it behind bulk gossip messages directly delays (or loses) the delivery of the message.
+///
+/// See [`ServiceBuilder::tree_repair_priorities`].
+///
+/// [`ServiceBuilder::tree_repair_priorities`]: ./struct.ServiceBuilder.html#method.tree_repair_priorities
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeRepairPriorities {
+    /// The priority of `GRAFT` messages requesting missing messages.
+    ///
+    /// The default value is `100`.
+    pub graft: u8,
+
+    /// The priority of `GRAFT` messages only optimizing the broadcast trees
+    /// (i.e., the ones not requesting any messages).
+    ///
+    /// The default value is `128`.
+    pub graft_optimize: u8,
+
+    /// The priority of `PRUNE` messages.
+    ///
+    /// The default value is `128`.
+    pub prune: u8,
+}
+impl Default for TreeRepairPriorities {
+    fn default() -> Self {
+        TreeRepairPriorities {
+            graft: 100,
+            graft_optimize: 128,
+            prune: 128,
+        }
+    }
+}
+impl TreeRepairPriorities {
+    pub(crate) fn graft_priority<M: MessagePayload>(&self, m: &GraftMessage<M>) -> u8 {
+        if m.message_id.is_some() {
+            self.graft
+        } else {
+            self.graft_optimize
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CircuitBreaker {
+    failure_threshold: u64,
+    cool_down: Duration,
+}
+
+/// Statistics of the communications with a remote service.
+///
+/// See [`ServiceHandle::peer_stats`].
+///
+/// [`ServiceHandle::peer_stats`]: ./struct.ServiceHandle.html#method.peer_stats
+#[derive(Debug, Default, Clone)]
+pub struct PeerStats {
+    sent_messages: u64,
+    send_errors: u64,
+    consecutive_send_errors: u64,
+    circuit_open_until: Option<Instant>,
+    received_messages: u64,
+    received_payload_bytes: u64,
+    last_error: Option<(SystemTime, String)>,
+}
+impl PeerStats {
+    /// Returns the number of messages passed to the RPC client for sending to the peer.
+    pub fn sent_messages(&self) -> u64 {
+        self.sent_messages
+    }
+
+    /// Returns the number of messages that could not be sent to the peer.
+    ///
+    /// For example, gossip messages are dropped if the send queue of the peer is full.
+    pub fn send_errors(&self) -> u64 {
+        self.send_errors
+    }
+
+    /// Returns the number of the send errors occurred in a row since the last successful send.
+    pub fn consecutive_send_errors(&self) -> u64 {
+        self.consecutive_send_errors
+    }
+
+    /// Returns `true` if the circuit to the peer is open
+    /// (see `ServiceBuilder::circuit_breaker()`).
+    pub fn is_circuit_open(&self) -> bool {
+        self.circuit_open_until
+            .map_or(false, |until| Instant::now() < until)
+    }
+
+    /// Returns the number of HyParView/Plumtree messages received from the peer.
+    pub fn received_messages(&self) -> u64 {
+        self.received_messages
+    }
+
+    /// Returns the total encoded size of the payloads of the gossip messages received from the peer.
+    pub fn received_payload_bytes(&self) -> u64 {
+        self.received_payload_bytes
+    }
+
+    /// Returns the time and the description of the last error occurred when sending a message to the peer.
+    pub fn last_error(&self) -> Option<&(SystemTime, String)> {
+        self.last_error.as_ref()
+    }
 }
 
 #[derive(Debug)]
@@ -369,3 +2159,617 @@ enum Command<M: MessagePayload> {
     Register(NodeHandle<M>),
     Deregister(LocalNodeId),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::{Node, SerialLocalNodeIdGenerator};
+
+    fn service(port: u16, protocol_negotiation: bool) -> Service<Vec<u8>> {
+        let addr = ([127, 0, 0, 1], port).into();
+        ServiceBuilder::new(addr)
+            .enable_metrics(false)
+            .protocol_negotiation(protocol_negotiation)
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new())
+    }
+
+    fn peer() -> NodeId {
+        NodeId::new(([127, 0, 0, 1], 14000).into(), LocalNodeId::new(0))
+    }
+
+    fn handle_commands(service: &mut Service<Vec<u8>>) {
+        futures::future::lazy(|| {
+            while let Async::Ready(Some(command)) = service.command_rx.poll().expect("Never fails")
+            {
+                service
+                    .handle
+                    .command_queue_len
+                    .fetch_sub(1, Ordering::SeqCst);
+                service.handle_command(command).unwrap();
+            }
+            Ok::<(), ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    fn poll_node(node: &mut Node<Vec<u8>>) -> Result<()> {
+        futures::future::lazy(|| node.poll().map(|_| ())).wait()
+    }
+
+    fn disconnected_peers(outbox: &InMemoryOutbox<Vec<u8>>) -> Vec<NodeId> {
+        use hyparview::message::ProtocolMessage;
+
+        outbox
+            .take()
+            .into_iter()
+            .filter_map(|(peer, m)| match m {
+                RpcMessage::Hyparview(ProtocolMessage::Disconnect(_)) => Some(peer),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn legacy_framing_is_used_without_negotiation() {
+        let service = service(14001, false);
+        let handle = service.handle();
+        assert_eq!(handle.framing(peer()).unwrap(), Framing::Legacy);
+        assert_eq!(handle.peer_protocols.get(peer().address()), None);
+    }
+
+    #[test]
+    fn legacy_framing_is_used_until_negotiation_completes() {
+        let service = service(14002, true);
+        let handle = service.handle();
+        assert_eq!(handle.framing(peer()).unwrap(), Framing::Legacy);
+        assert_eq!(
+            handle.peer_protocols.get(peer().address()),
+            Some(PeerProtocol::Negotiating)
+        );
+
+        handle.handle_handshake(Handshake::local(peer().address(), &[]));
+        assert_eq!(
+            handle.framing(peer()).unwrap(),
+            Framing::Versioned(protocol::PROTOCOL_VERSION)
+        );
+    }
+
+    #[test]
+    fn parameter_updates_are_opt_in() {
+        let caller = ([127, 0, 0, 1], 14000).into();
+        let update = ParameterUpdate::TickIntervalMultiplier(2.0);
+
+        let service = service(14004, false);
+        assert!(!service
+            .handle()
+            .is_parameter_update_allowed(caller, &update));
+
+        let service = ServiceBuilder::new(([127, 0, 0, 1], 14005).into())
+            .enable_metrics(false)
+            .parameter_update_policy(move |c: SocketAddr, _: &ParameterUpdate| c == caller)
+            .finish::<_, Vec<u8>, _>(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        let handle = service.handle();
+        assert!(handle.is_parameter_update_allowed(caller, &update));
+        let other = ([127, 0, 0, 1], 14006).into();
+        assert!(!handle.is_parameter_update_allowed(other, &update));
+    }
+
+    #[test]
+    fn received_handshakes_enable_versioned_framing() {
+        let service = service(14003, false);
+        let handle = service.handle();
+        handle.handle_handshake(Handshake::local(peer().address(), &[]));
+        assert_eq!(
+            handle.framing(peer()).unwrap(),
+            Framing::Versioned(protocol::PROTOCOL_VERSION)
+        );
+    }
+
+    #[test]
+    fn nodes_fail_when_service_stops_without_successor() {
+        let mut service = service(14007, false);
+        let mut node = Node::new(service.handle());
+        handle_commands(&mut service);
+        assert_eq!(service.handle().local_nodes(), vec![node.id().local_id()]);
+
+        drop(service);
+        assert!(poll_node(&mut node).is_err());
+    }
+
+    #[test]
+    fn nodes_reregister_with_restarted_service() {
+        let addr = ([127, 0, 0, 1], 14008).into();
+        let mut service = ServiceBuilder::new(addr)
+            .enable_metrics(false)
+            .restartable(true)
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        let mut node = Node::new(service.handle());
+        handle_commands(&mut service);
+
+        let previous = service.handle();
+        drop(service);
+        poll_node(&mut node).unwrap();
+        assert!(previous.successor().is_none());
+
+        let mut service = ServiceBuilder::new(addr)
+            .enable_metrics(false)
+            .restart(fibers_global::handle(), &previous)
+            .unwrap();
+        poll_node(&mut node).unwrap();
+        handle_commands(&mut service);
+        assert_eq!(service.handle().local_nodes(), vec![node.id().local_id()]);
+
+        // Dropping the newest service breaks the reference cycle with the shared slot.
+        drop(service);
+        assert!(previous.successor().is_none());
+    }
+
+    #[test]
+    fn only_the_newest_service_is_kept_by_older_handles() {
+        let addr = ([127, 0, 0, 1], 14009).into();
+        let first = service(14009, false).handle();
+        let second = ServiceBuilder::new(addr)
+            .enable_metrics(false)
+            .restart(fibers_global::handle(), &first)
+            .unwrap();
+        let second_handle = second.handle();
+        drop(second);
+
+        let third = ServiceBuilder::new(addr)
+            .enable_metrics(false)
+            .restart(fibers_global::handle(), &second_handle)
+            .unwrap();
+        assert!(first
+            .successor()
+            .map_or(false, |s| s.is_same_service(&third.handle())));
+        assert!(second_handle.successor().is_some());
+        assert!(third.handle().successor().is_none());
+    }
+
+    #[test]
+    fn restart_requires_the_same_address() {
+        let previous = service(14010, false).handle();
+        let e = ServiceBuilder::new(([127, 0, 0, 1], 14011).into())
+            .enable_metrics(false)
+            .restart(fibers_global::handle(), &previous)
+            .err()
+            .unwrap();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn live_nodes_reregister_after_lease_expiration() {
+        let mut service = ServiceBuilder::new(([127, 0, 0, 1], 14012).into())
+            .enable_metrics(false)
+            .node_lease_duration(Duration::from_millis(10))
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        let mut alive = Node::new(service.handle());
+        let dead = Node::new(service.handle());
+        handle_commands(&mut service);
+        assert_eq!(service.handle().local_nodes().len(), 2);
+
+        std::thread::sleep(Duration::from_millis(20));
+        service.expire_leases().unwrap();
+        assert!(service.handle().local_nodes().is_empty());
+
+        // The dropped node has already been deregistered by the service.
+        drop(dead);
+        handle_commands(&mut service);
+
+        poll_node(&mut alive).unwrap();
+        handle_commands(&mut service);
+        assert_eq!(service.handle().local_nodes(), vec![alive.id().local_id()]);
+    }
+
+    #[test]
+    fn payload_decoders_are_made_by_given_allocator() {
+        use crate::codec::metered::MeteredDecoder;
+        use crate::codec::plumtree::{GossipMessageDecoder, GossipMessageEncoder};
+        use crate::message::{DecoderWithAllocator, Envelope, MessageId};
+        use crate::misc::{GossipMessage, PlumtreeAppMessage};
+        use bytecodec::bytes::{BytesEncoder, RemainingBytesDecoder};
+        use bytecodec::{ByteCount, Decode, DecodeExt, Encode, EncodeExt, Eos};
+        use fibers_rpc::server::MakeDecoder;
+
+        #[derive(Debug, Clone, PartialEq)]
+        struct Pooled(Vec<u8>);
+        impl MessagePayload for Pooled {
+            type Encoder = PooledEncoder;
+            type Decoder = PooledDecoder;
+        }
+
+        #[derive(Debug, Default)]
+        struct PooledEncoder(BytesEncoder<Vec<u8>>);
+        impl Encode for PooledEncoder {
+            type Item = Pooled;
+
+            fn encode(&mut self, buf: &mut [u8], eos: Eos) -> bytecodec::Result<usize> {
+                self.0.encode(buf, eos)
+            }
+
+            fn start_encoding(&mut self, item: Self::Item) -> bytecodec::Result<()> {
+                self.0.start_encoding(item.0)
+            }
+
+            fn is_idle(&self) -> bool {
+                self.0.is_idle()
+            }
+
+            fn requiring_bytes(&self) -> ByteCount {
+                self.0.requiring_bytes()
+            }
+        }
+
+        // Counts the payloads decoded by using the allocator.
+        #[derive(Debug, Default)]
+        struct PooledDecoder {
+            inner: RemainingBytesDecoder,
+            allocator: Option<Arc<AtomicUsize>>,
+        }
+        impl Decode for PooledDecoder {
+            type Item = Pooled;
+
+            fn decode(&mut self, buf: &[u8], eos: Eos) -> bytecodec::Result<usize> {
+                self.inner.decode(buf, eos)
+            }
+
+            fn finish_decoding(&mut self) -> bytecodec::Result<Self::Item> {
+                if let Some(ref allocator) = self.allocator {
+                    allocator.fetch_add(1, Ordering::SeqCst);
+                }
+                self.inner.finish_decoding().map(Pooled)
+            }
+
+            fn is_idle(&self) -> bool {
+                self.inner.is_idle()
+            }
+
+            fn requiring_bytes(&self) -> ByteCount {
+                self.inner.requiring_bytes()
+            }
+        }
+        impl DecoderWithAllocator for PooledDecoder {
+            type Allocator = Arc<AtomicUsize>;
+
+            fn with_allocator(allocator: Self::Allocator) -> Self {
+                PooledDecoder {
+                    inner: RemainingBytesDecoder::default(),
+                    allocator: Some(allocator),
+                }
+            }
+        }
+
+        let allocator = Arc::new(AtomicUsize::new(0));
+        let service = ServiceBuilder::new(([127, 0, 0, 1], 14013).into())
+            .enable_metrics(false)
+            .finish_with_allocator::<_, Pooled, _>(
+                fibers_global::handle(),
+                SerialLocalNodeIdGenerator::new(),
+                Arc::clone(&allocator),
+            )
+            .unwrap();
+
+        let sender = peer();
+        let gossip = GossipMessage {
+            sender,
+            round: 0,
+            message: PlumtreeAppMessage {
+                id: MessageId::new(sender, 0),
+                payload: Envelope::new(Pooled(b"foo".to_vec())),
+            },
+        };
+        let bytes = GossipMessageEncoder::default()
+            .encode_into_bytes((LocalNodeId::new(0), gossip))
+            .unwrap();
+
+        let maker = &service.handle().payload_decoder_maker;
+        let mut decoder: MeteredDecoder<GossipMessageDecoder<Pooled>> = maker.make_decoder();
+        let (_, m) = decoder.decode_from_bytes(&bytes).unwrap();
+        assert_eq!(m.message.payload.payload, Pooled(b"foo".to_vec()));
+        assert_eq!(allocator.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn messages_to_tombstoned_nodes_are_dropped_silently() {
+        let mut service = ServiceBuilder::new(([127, 0, 0, 1], 14014).into())
+            .enable_metrics(false)
+            .tombstone_duration(Duration::from_secs(60))
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        let outbox = InMemoryOutbox::default();
+        service.set_in_memory_outbox(outbox.clone());
+        let handle = service.handle();
+
+        let node = Node::new(service.handle());
+        let id = node.id().local_id();
+        handle_commands(&mut service);
+        assert!(handle.get_local_node_or_disconnect(id, &peer()).is_some());
+
+        drop(node);
+        handle_commands(&mut service);
+        assert!(handle.get_local_node_or_disconnect(id, &peer()).is_none());
+        assert!(disconnected_peers(&outbox).is_empty());
+
+        // Unknown nodes are not tombstoned.
+        let unknown = LocalNodeId::new(100);
+        assert!(handle
+            .get_local_node_or_disconnect(unknown, &peer())
+            .is_none());
+        assert_eq!(disconnected_peers(&outbox), vec![peer()]);
+    }
+
+    #[test]
+    fn local_broadcasts_are_delivered_to_all_local_nodes() {
+        let (mut service, outbox) =
+            crate::testing::in_memory_service(([127, 0, 0, 1], 14015).into());
+        let handle = service.handle();
+        let mut nodes = vec![Node::new(service.handle()), Node::new(service.handle())];
+        handle_commands(&mut service);
+
+        let id = handle.broadcast_local(b"hello".to_vec());
+        assert_eq!(id.node().local_id(), LocalNodeId::new(u64::max_value()));
+        for node in &mut nodes {
+            let message = futures::future::lazy(|| node.poll()).wait().unwrap();
+            let message = match message {
+                Async::Ready(Some(m)) => m,
+                other => panic!("Unexpected: {:?}", other),
+            };
+            assert!(message.is_local());
+            assert_eq!(*message.id(), id);
+            assert_eq!(message.payload(), b"hello");
+
+            // Local messages are neither relayed nor kept by the nodes.
+            poll_node(node).unwrap();
+            assert_eq!(node.status().cached_messages(), 0);
+        }
+        assert!(outbox.take().is_empty());
+    }
+
+    #[test]
+    fn messages_to_unknown_nodes_are_handled_by_policy() {
+        let check = |port: u16, policy: UnknownDestinationPolicy| {
+            let mut service = ServiceBuilder::new(([127, 0, 0, 1], port).into())
+                .enable_metrics(false)
+                .unknown_destination_policy(policy)
+                .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+            let outbox = InMemoryOutbox::default();
+            service.set_in_memory_outbox(outbox.clone());
+            let node = Node::new(service.handle());
+            handle_commands(&mut service);
+
+            let unknown = LocalNodeId::new(100);
+            let redirected = service
+                .handle()
+                .get_local_node_or_disconnect(unknown, &peer())
+                .map(|n| n.local_id());
+            (
+                node.id().local_id(),
+                redirected,
+                disconnected_peers(&outbox),
+            )
+        };
+
+        let (_, redirected, disconnected) = check(14016, UnknownDestinationPolicy::Ignore);
+        assert_eq!(redirected, None);
+        assert!(disconnected.is_empty());
+
+        let (_, redirected, disconnected) = check(14017, UnknownDestinationPolicy::Disconnect);
+        assert_eq!(redirected, None);
+        assert_eq!(disconnected, vec![peer()]);
+
+        let policy = UnknownDestinationPolicy::Redirect(LocalNodeId::new(0));
+        let (default, redirected, disconnected) = check(14018, policy);
+        assert_eq!(redirected, Some(default));
+        assert!(disconnected.is_empty());
+
+        // Falls back to disconnecting if the default node is also missing.
+        let policy = UnknownDestinationPolicy::Redirect(LocalNodeId::new(200));
+        let (_, redirected, disconnected) = check(14019, policy);
+        assert_eq!(redirected, None);
+        assert_eq!(disconnected, vec![peer()]);
+    }
+
+    #[test]
+    fn graft_priorities_depend_on_requested_messages() {
+        let priorities = TreeRepairPriorities {
+            graft: 10,
+            graft_optimize: 20,
+            prune: 30,
+        };
+        let service: Service<Vec<u8>> = ServiceBuilder::new(([127, 0, 0, 1], 14020).into())
+            .enable_metrics(false)
+            .tree_repair_priorities(priorities)
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        let handle = service.handle();
+        let graft = |message_id| -> GraftMessage<Vec<u8>> {
+            GraftMessage {
+                sender: peer(),
+                message_id,
+                round: 1,
+            }
+        };
+        let requesting = graft(Some(MessageId::new(peer(), 0)));
+        assert_eq!(
+            handle.tree_repair_priorities.graft_priority(&requesting),
+            10
+        );
+        assert_eq!(
+            handle.tree_repair_priorities.graft_priority(&graft(None)),
+            20
+        );
+
+        // Requests for missing messages take precedence over fresh gossips by default.
+        let defaults = TreeRepairPriorities::default();
+        assert!(defaults.graft_priority(&requesting) < 128);
+        assert_eq!(defaults.graft_priority(&graft(None)), 128);
+    }
+
+    #[test]
+    fn circuit_is_opened_by_consecutive_failures_and_closed_by_success() {
+        use hyparview::message::{DisconnectMessage, ProtocolMessage};
+
+        let service: Service<Vec<u8>> = ServiceBuilder::new(([127, 0, 0, 1], 14021).into())
+            .enable_metrics(false)
+            .circuit_breaker(2, Duration::from_millis(50))
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        let handle = service.handle();
+        let send = || {
+            let m = DisconnectMessage {
+                sender: NodeId::new(handle.server_addr, LocalNodeId::new(0)),
+                alive: true,
+            };
+            handle.send_message(
+                peer(),
+                RpcMessage::Hyparview(ProtocolMessage::Disconnect(m)),
+            )
+        };
+        let is_circuit_open = || handle.peer_stats()[peer().address()].is_circuit_open();
+        let mut handshake = Handshake::local(peer().address(), &[]);
+
+        // The peer speaks no compatible versions, so every send fails.
+        handshake.min_version = u8::max_value();
+        handshake.max_version = u8::max_value();
+        handle.handle_handshake(handshake.clone());
+        for _ in 0..2 {
+            let e = send().err().unwrap();
+            assert_eq!(*e.kind(), ErrorKind::PeerUnreachable(peer()));
+        }
+        assert!(is_circuit_open());
+        assert_eq!(handle.peer_stats()[peer().address()].send_errors(), 2);
+
+        // Messages are rejected without trying to send them while the circuit is open.
+        assert!(send().is_err());
+        assert_eq!(handle.peer_stats()[peer().address()].send_errors(), 2);
+
+        // After the cool-down, a successful send closes the circuit.
+        handshake.min_version = protocol::MIN_PROTOCOL_VERSION;
+        handshake.max_version = protocol::PROTOCOL_VERSION;
+        handle.handle_handshake(handshake);
+        std::thread::sleep(Duration::from_millis(60));
+        send().unwrap();
+        assert!(!is_circuit_open());
+        assert_eq!(
+            handle.peer_stats()[peer().address()].consecutive_send_errors(),
+            0
+        );
+    }
+
+    #[test]
+    fn inbound_messages_are_handled_within_poll_budget() {
+        use crate::node::NodeBuilder;
+
+        let mut service = service(14022, false);
+        let mut node = NodeBuilder::new()
+            .max_messages_per_poll(2)
+            .finish::<Vec<u8>>(service.handle());
+        handle_commands(&mut service);
+        let local = service
+            .handle()
+            .get_local_node(node.id().local_id())
+            .unwrap();
+        for seqno in 0..5 {
+            let m = GossipMessage {
+                sender: peer(),
+                round: 1,
+                message: PlumtreeAppMessage {
+                    id: MessageId::new(peer(), seqno),
+                    payload: Envelope::new(vec![seqno as u8]),
+                },
+            };
+            local.send_rpc_message(RpcMessage::Plumtree(m.into()));
+        }
+        assert_eq!(node.pending_deliveries(), 5);
+
+        for &pending in &[3, 1, 0] {
+            poll_node(&mut node).unwrap();
+            assert_eq!(node.pending_deliveries(), pending);
+        }
+    }
+
+    #[test]
+    fn registrations_fail_while_command_queue_is_full() {
+        use crate::node::NodeBuilder;
+
+        let mut service: Service<Vec<u8>> = ServiceBuilder::new(([127, 0, 0, 1], 14023).into())
+            .enable_metrics(false)
+            .max_command_queue_len(2)
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        let mut nodes = vec![Node::new(service.handle()), Node::new(service.handle())];
+        let e = NodeBuilder::new()
+            .try_finish(service.handle())
+            .err()
+            .unwrap();
+        assert_eq!(*e.kind(), ErrorKind::QueueFull);
+
+        handle_commands(&mut service);
+        assert_eq!(service.handle().local_nodes().len(), 2);
+
+        // Deregistrations are queued even if the queue is full.
+        nodes.push(NodeBuilder::new().try_finish(service.handle()).unwrap());
+        nodes.push(NodeBuilder::new().try_finish(service.handle()).unwrap());
+        nodes.clear();
+        assert_eq!(service.handle.command_queue_len.load(Ordering::SeqCst), 6);
+        handle_commands(&mut service);
+        assert!(service.handle().local_nodes().is_empty());
+    }
+
+    #[test]
+    fn rpc_server_and_client_can_run_separately() {
+        let addr_a: SocketAddr = ([127, 0, 0, 1], 14024).into();
+        let mut server_builder = RpcServerBuilder::new(addr_a);
+        let service_a: Service<Vec<u8>> = ServiceBuilder::new(addr_a)
+            .enable_metrics(false)
+            .finish_with_external_server(
+                fibers_global::handle(),
+                SerialLocalNodeIdGenerator::new(),
+                &mut server_builder,
+            )
+            .unwrap();
+        assert!(service_a.rpc_server().is_none());
+        let a = Node::new(service_a.handle());
+        fibers_global::spawn(
+            server_builder
+                .finish(fibers_global::handle())
+                .map_err(|e| panic!("{}", e)),
+        );
+        fibers_global::spawn(service_a.map_err(|e| panic!("{}", e)));
+
+        let mut service_b = service(14025, true);
+        let server = service_b.take_rpc_server().unwrap();
+        let client = service_b.take_rpc_client_service().unwrap();
+        assert!(service_b.take_rpc_server().is_none());
+        let mut b = Node::new(service_b.handle());
+        fibers_global::spawn(server.map_err(|e| panic!("{}", e)));
+        fibers_global::spawn(client.map_err(|e| panic!("{}", e)));
+        fibers_global::spawn(service_b.map_err(|e| panic!("{}", e)));
+
+        b.join(a.id());
+        b.schedule_periodic_broadcast(Duration::from_millis(100), || b"hello".to_vec());
+        fibers_global::spawn(b.for_each(|_| Ok(())).map_err(|e| panic!("{}", e)));
+        let message = fibers_global::execute(a.into_future().map_err(|(e, _)| e)).unwrap();
+        assert_eq!(message.0.unwrap().payload(), b"hello");
+    }
+
+    #[test]
+    fn services_can_share_rpc_client_service() {
+        let service_a = service(14026, true);
+        let service_b: Service<Vec<u8>> = ServiceBuilder::new(([127, 0, 0, 1], 14027).into())
+            .enable_metrics(false)
+            .shared_rpc_client_service(service_a.handle().rpc_client_service().clone())
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        assert!(service_a.rpc_client_service().is_some());
+        assert!(service_b.rpc_client_service().is_none());
+
+        // The messages from `b` are sent via the client service driven by `service_a`.
+        let a = Node::new(service_a.handle());
+        let mut b = Node::new(service_b.handle());
+        fibers_global::spawn(service_a.map_err(|e| panic!("{}", e)));
+        fibers_global::spawn(service_b.map_err(|e| panic!("{}", e)));
+
+        b.join(a.id());
+        b.schedule_periodic_broadcast(Duration::from_millis(100), || b"hello".to_vec());
+        fibers_global::spawn(b.for_each(|_| Ok(())).map_err(|e| panic!("{}", e)));
+        let message = fibers_global::execute(a.into_future().map_err(|(e, _)| e)).unwrap();
+        assert_eq!(message.0.unwrap().payload(), b"hello");
+    }
+}
@@ -0,0 +1,153 @@
+//! Pluggable selection of the nodes exchanged by HyParView shuffles.
+//!
+//! A node periodically sends a sample of the nodes it knows to a random node in a `SHUFFLE`
+//! message, and the receiver replies with a sample of its own in a `SHUFFLE_REPLY` message.
+//! The samples are added to the passive views of the receivers.
+//! By default, the samples are chosen at random, which may make the passive views of
+//! geo-distributed clusters poor (e.g., dominated by the nodes of remote regions).
+//! A [`ShufflePolicy`] set by [`Node::set_shuffle_policy`] can adjust the samples.
+//!
+//! [`ShufflePolicy`]: ./trait.ShufflePolicy.html
+//! [`Node::set_shuffle_policy`]: ../node/struct.Node.html#method.set_shuffle_policy
+use crate::node::NodeId;
+use std::collections::HashSet;
+use std::fmt;
+
+/// The information available to a [`ShufflePolicy`].
+///
+/// [`ShufflePolicy`]: ./trait.ShufflePolicy.html
+#[derive(Debug)]
+pub struct ShuffleContext<'a> {
+    pub(crate) local_node: NodeId,
+    pub(crate) destination: NodeId,
+    pub(crate) active_view: &'a [NodeId],
+    pub(crate) passive_view: &'a [NodeId],
+    pub(crate) quarantined: &'a [NodeId],
+    pub(crate) max_nodes: usize,
+}
+impl<'a> ShuffleContext<'a> {
+    /// Returns the identifier of the node sending the sample.
+    pub fn local_node(&self) -> NodeId {
+        self.local_node
+    }
+
+    /// Returns the identifier of the node receiving the sample.
+    pub fn destination(&self) -> NodeId {
+        self.destination
+    }
+
+    /// Returns the HyParView active view of the local node.
+    pub fn active_view(&self) -> &[NodeId] {
+        self.active_view
+    }
+
+    /// Returns the HyParView passive view of the local node.
+    pub fn passive_view(&self) -> &[NodeId] {
+        self.passive_view
+    }
+
+    /// Returns `true` if the given node is quarantined by the local node
+    /// (see `NodeBuilder::quarantine_duration()`).
+    pub fn is_quarantined(&self, node: &NodeId) -> bool {
+        self.quarantined.contains(node)
+    }
+
+    /// Returns the maximum number of the nodes in the sample.
+    ///
+    /// The excess nodes selected by a policy are discarded.
+    pub fn max_nodes(&self) -> usize {
+        self.max_nodes
+    }
+}
+
+/// This trait allows for adjusting the samples of nodes exchanged by HyParView shuffles.
+///
+/// A policy is invoked for each `SHUFFLE` message originated by the local node and
+/// each `SHUFFLE_REPLY` message sent by it (the `SHUFFLE` messages relayed
+/// on behalf of other nodes are not affected).
+///
+/// # Examples
+///
+/// Excluding quarantined nodes and preferring the nodes in the same subnet:
+///
+/// ```
+/// use plumcast::node::NodeId;
+/// use plumcast::shuffle::ShuffleContext;
+///
+/// fn policy(context: &ShuffleContext, nodes: &mut Vec<NodeId>) {
+///     nodes.retain(|n| !context.is_quarantined(n));
+///     let local = context.local_node().address().ip();
+///     nodes.sort_by_key(|n| n.address().ip() != local);
+/// }
+/// # let _ = policy;
+/// ```
+pub trait ShufflePolicy: Send + 'static {
+    /// Adjusts `nodes`, which initially holds the sample randomly selected by HyParView.
+    ///
+    /// The policy may remove, reorder or replace the nodes
+    /// (e.g., with the ones taken from `context.passive_view()`).
+    /// Only the first `context.max_nodes()` nodes are sent.
+    fn select(&mut self, context: &ShuffleContext, nodes: &mut Vec<NodeId>);
+}
+impl<F> ShufflePolicy for F
+where
+    F: FnMut(&ShuffleContext, &mut Vec<NodeId>) + Send + 'static,
+{
+    fn select(&mut self, context: &ShuffleContext, nodes: &mut Vec<NodeId>) {
+        self(context, nodes)
+    }
+}
+
+pub(crate) struct BoxShufflePolicy(Box<dyn ShufflePolicy>);
+impl BoxShufflePolicy {
+    pub(crate) fn new<P: ShufflePolicy>(inner: P) -> Self {
+        BoxShufflePolicy(Box::new(inner))
+    }
+
+    pub(crate) fn select(&mut self, context: &ShuffleContext, nodes: &mut Vec<NodeId>) {
+        self.0.select(context, nodes);
+        let mut seen = HashSet::new();
+        nodes.retain(|&n| n != context.local_node && n != context.destination && seen.insert(n));
+        nodes.truncate(context.max_nodes);
+    }
+}
+impl fmt::Debug for BoxShufflePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BoxShufflePolicy(_)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::LocalNodeId;
+
+    fn node(port: u16) -> NodeId {
+        NodeId::new(
+            ([127, 0, 0, 1], port).into(),
+            LocalNodeId::new(u64::from(port)),
+        )
+    }
+
+    #[test]
+    fn box_shuffle_policy_sanitizes_nodes() {
+        let passive_view = vec![node(3), node(4), node(5)];
+        let quarantined = vec![node(4)];
+        let context = ShuffleContext {
+            local_node: node(0),
+            destination: node(1),
+            active_view: &[],
+            passive_view: &passive_view,
+            quarantined: &quarantined,
+            max_nodes: 2,
+        };
+        let mut policy = BoxShufflePolicy::new(|c: &ShuffleContext, nodes: &mut Vec<NodeId>| {
+            nodes.extend_from_slice(c.passive_view());
+            nodes.retain(|n| !c.is_quarantined(n));
+        });
+
+        let mut nodes = vec![node(0), node(1), node(3)];
+        policy.select(&context, &mut nodes);
+        assert_eq!(nodes, vec![node(3), node(5)]);
+    }
+}
@@ -0,0 +1,524 @@
+//! [`ExternalSink`] and related components.
+//!
+//! [`ExternalSink`]: ./trait.ExternalSink.html
+use crate::message::{Message, MessagePayload};
+use crate::node::Node;
+use crate::{Error, ErrorKind, Result};
+use futures::{task, Async, AsyncSink, Future, Poll, Sink, Stream};
+use std::fmt;
+use std::io::{self, Write};
+use trackable::error::ErrorKindExt;
+
+/// This trait allows the implementations to receive the messages delivered to a [`Node`].
+///
+/// A sink signals whether it can accept the next message by [`poll_ready`].
+/// While the sink is not ready, [`Forward`] pauses the delivery of messages
+/// instead of buffering them in memory without limit
+/// (the node keeps running its timers and sending its outgoing messages meanwhile).
+///
+/// [`Node`]: ../node/struct.Node.html
+/// [`poll_ready`]: ./trait.ExternalSink.html#tymethod.poll_ready
+/// [`Forward`]: ./struct.Forward.html
+pub trait ExternalSink<M: MessagePayload> {
+    /// Polls whether the sink is ready to accept a message.
+    ///
+    /// If this returns `Ok(Async::NotReady)`, the current task must be notified
+    /// when the sink becomes ready.
+    fn poll_ready(&mut self) -> Poll<(), Error>;
+
+    /// Passes a message to the sink.
+    ///
+    /// This is called only after `poll_ready()` returned `Ok(Async::Ready(()))`.
+    fn start_send(&mut self, message: Message<M>) -> Result<()>;
+
+    /// Flushes the messages buffered in the sink.
+    ///
+    /// This is called when the node has no messages to deliver.
+    ///
+    /// The default implementation does nothing.
+    fn poll_flush(&mut self) -> Poll<(), Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// An [`ExternalSink`] adapter for `futures::Sink` implementations.
+///
+/// The adapter holds at most one message that the inner sink has not accepted yet,
+/// so the number of messages buffered between a node and the inner sink is bounded by
+/// the capacity of the inner sink (e.g., the buffer size of `futures::sync::mpsc::channel()`).
+///
+/// # Examples
+///
+/// ```no_run
+/// use futures::sync::mpsc;
+/// use futures::{Future, Stream};
+/// use plumcast::node::{Node, SerialLocalNodeIdGenerator};
+/// use plumcast::service::Service;
+/// use plumcast::sink::SinkAdapter;
+///
+/// let service = Service::<Vec<u8>>::new(
+///     "127.0.0.1:4000".parse().unwrap(),
+///     fibers_global::handle(),
+///     SerialLocalNodeIdGenerator::new(),
+/// );
+/// let node = Node::new(service.handle());
+/// fibers_global::spawn(service.map_err(|e| panic!("{}", e)));
+///
+/// let (tx, rx) = mpsc::channel(128);
+/// fibers_global::spawn(node.forward_to(SinkAdapter::new(tx)).map_err(|e| panic!("{}", e)));
+/// fibers_global::spawn(rx.for_each(|m| Ok(println!("{:?}", m.id()))));
+/// ```
+///
+/// [`ExternalSink`]: ./trait.ExternalSink.html
+#[derive(Debug)]
+pub struct SinkAdapter<M: MessagePayload, S> {
+    inner: S,
+    pending: Option<Message<M>>,
+}
+impl<M: MessagePayload, S> SinkAdapter<M, S>
+where
+    S: Sink<SinkItem = Message<M>>,
+    S::SinkError: fmt::Display,
+{
+    /// Makes a new `SinkAdapter` instance.
+    pub fn new(inner: S) -> Self {
+        SinkAdapter {
+            inner,
+            pending: None,
+        }
+    }
+
+    /// Returns a reference to the inner sink.
+    pub fn inner_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner sink.
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Takes ownership of the adapter, and returns the inner sink.
+    ///
+    /// Note that the message not yet accepted by the inner sink (if any) is discarded.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn sink_error(e: S::SinkError) -> Error {
+        ErrorKind::Other.cause(e.to_string()).into()
+    }
+}
+impl<M: MessagePayload, S> ExternalSink<M> for SinkAdapter<M, S>
+where
+    S: Sink<SinkItem = Message<M>>,
+    S::SinkError: fmt::Display,
+{
+    fn poll_ready(&mut self) -> Poll<(), Error> {
+        if let Some(message) = self.pending.take() {
+            match self.inner.start_send(message).map_err(Self::sink_error)? {
+                AsyncSink::Ready => {}
+                AsyncSink::NotReady(message) => {
+                    self.pending = Some(message);
+                    track!(self.inner.poll_complete().map_err(Self::sink_error))?;
+                    return Ok(Async::NotReady);
+                }
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+
+    fn start_send(&mut self, message: Message<M>) -> Result<()> {
+        track_assert!(self.pending.is_none(), ErrorKind::InconsistentState; message.id());
+        self.pending = Some(message);
+        track!(self.poll_ready())?;
+        Ok(())
+    }
+
+    fn poll_flush(&mut self) -> Poll<(), Error> {
+        if track!(self.poll_ready())?.is_not_ready() {
+            return Ok(Async::NotReady);
+        }
+        track!(self.inner.poll_complete().map_err(Self::sink_error))
+    }
+}
+
+/// An [`ExternalSink`] adapter for `std::io::Write` implementations (e.g., files).
+///
+/// Each message is encoded by the given function and appended to an internal buffer,
+/// which is written to the inner writer when its size reaches the limit
+/// (64 KiB by default) and when the node has no messages to deliver.
+/// So the memory used by the adapter is bounded by the limit plus the size of a message.
+///
+/// If the inner writer returns `WouldBlock` errors, the adapter reports that it is not ready
+/// and retries the write in the next poll.
+///
+/// # Examples
+///
+/// ```no_run
+/// use futures::Future;
+/// use plumcast::node::{Node, SerialLocalNodeIdGenerator};
+/// use plumcast::service::Service;
+/// use plumcast::sink::WriteSink;
+/// use std::fs::File;
+///
+/// let service = Service::<Vec<u8>>::new(
+///     "127.0.0.1:4000".parse().unwrap(),
+///     fibers_global::handle(),
+///     SerialLocalNodeIdGenerator::new(),
+/// );
+/// let node = Node::new(service.handle());
+/// fibers_global::spawn(service.map_err(|e| panic!("{}", e)));
+///
+/// let file = File::create("messages.log").unwrap();
+/// let sink = WriteSink::new(file, |m: &_, buf: &mut Vec<u8>| {
+///     buf.extend_from_slice(format!("{:?}\n", m).as_bytes());
+///     Ok(())
+/// });
+/// fibers_global::spawn(node.forward_to(sink).map_err(|e| panic!("{}", e)));
+/// ```
+///
+/// [`ExternalSink`]: ./trait.ExternalSink.html
+pub struct WriteSink<W, F> {
+    inner: W,
+    encode: F,
+    buf: Vec<u8>,
+    written: usize,
+    max_buffer_size: usize,
+}
+impl<W, F> WriteSink<W, F>
+where
+    W: Write,
+{
+    /// The default value of the maximum buffer size.
+    pub const DEFAULT_MAX_BUFFER_SIZE: usize = 64 * 1024;
+
+    /// Makes a new `WriteSink` instance.
+    ///
+    /// `encode` appends the byte representation of a message to the given buffer.
+    pub fn new(inner: W, encode: F) -> Self {
+        WriteSink {
+            inner,
+            encode,
+            buf: Vec::new(),
+            written: 0,
+            max_buffer_size: Self::DEFAULT_MAX_BUFFER_SIZE,
+        }
+    }
+
+    /// Sets the size of the buffer at which the buffered bytes are written to the inner writer.
+    pub fn set_max_buffer_size(&mut self, size: usize) {
+        self.max_buffer_size = size;
+    }
+
+    /// Returns the number of the bytes that have not been written to the inner writer yet.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buf.len() - self.written
+    }
+
+    /// Returns a reference to the inner writer.
+    pub fn inner_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner writer.
+    pub fn inner_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Takes ownership of the adapter, and returns the inner writer.
+    ///
+    /// Note that the bytes not yet written to the inner writer (if any) are discarded.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn write_buffer(&mut self) -> Poll<(), Error> {
+        while self.written < self.buf.len() {
+            match self.inner.write(&self.buf[self.written..]) {
+                Ok(0) => track_panic!(ErrorKind::Other, "The writer accepts no more bytes"),
+                Ok(n) => self.written += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // NOTE: `std::io::Write` has no way to be notified of the readiness.
+                    task::current().notify();
+                    return Ok(Async::NotReady);
+                }
+                Err(e) => return Err(track!(io_error(e))),
+            }
+        }
+        self.buf.clear();
+        self.written = 0;
+        Ok(Async::Ready(()))
+    }
+}
+impl<W: fmt::Debug, F> fmt::Debug for WriteSink<W, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "WriteSink {{ inner: {:?}, encode: _, buffered_bytes: {}, max_buffer_size: {} }}",
+            self.inner,
+            self.buf.len() - self.written,
+            self.max_buffer_size
+        )
+    }
+}
+impl<M, W, F> ExternalSink<M> for WriteSink<W, F>
+where
+    M: MessagePayload,
+    W: Write,
+    F: FnMut(&Message<M>, &mut Vec<u8>) -> Result<()>,
+{
+    fn poll_ready(&mut self) -> Poll<(), Error> {
+        if self.buf.len() < self.max_buffer_size {
+            return Ok(Async::Ready(()));
+        }
+        track!(self.write_buffer())
+    }
+
+    fn start_send(&mut self, message: Message<M>) -> Result<()> {
+        track!((self.encode)(&message, &mut self.buf))?;
+        Ok(())
+    }
+
+    fn poll_flush(&mut self) -> Poll<(), Error> {
+        if track!(self.write_buffer())?.is_not_ready() {
+            return Ok(Async::NotReady);
+        }
+        track!(self.inner.flush().map_err(io_error))?;
+        Ok(Async::Ready(()))
+    }
+}
+
+/// A [`Future`] that drives a [`Node`] and forwards the delivered messages to an [`ExternalSink`].
+///
+/// This is created by calling [`Node::forward_to`] method.
+///
+/// While the sink is not ready, the delivery of messages is paused:
+/// the node keeps running its timers and sending its outgoing HyParView/Plumtree messages,
+/// but the inbound messages are left queued in the node,
+/// so it is recommended to bound the queue by `NodeBuilder::max_inbound_queue_len()`.
+///
+/// The future completes when the node stops (i.e., the node's stream reaches the end)
+/// and the sink has been flushed.
+///
+/// [`Future`]: https://docs.rs/futures/0.1/futures/future/trait.Future.html
+/// [`Node`]: ../node/struct.Node.html
+/// [`ExternalSink`]: ./trait.ExternalSink.html
+/// [`Node::forward_to`]: ../node/struct.Node.html#method.forward_to
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct Forward<M: MessagePayload, S> {
+    node: Node<M>,
+    sink: S,
+    node_finished: bool,
+}
+impl<M: MessagePayload, S: ExternalSink<M>> Forward<M, S> {
+    pub(crate) fn new(node: Node<M>, sink: S) -> Self {
+        Forward {
+            node,
+            sink,
+            node_finished: false,
+        }
+    }
+
+    /// Returns a reference to the node.
+    pub fn node(&self) -> &Node<M> {
+        &self.node
+    }
+
+    /// Returns a mutable reference to the node.
+    ///
+    /// This is useful for broadcasting messages from the node.
+    pub fn node_mut(&mut self) -> &mut Node<M> {
+        &mut self.node
+    }
+
+    /// Returns a reference to the sink.
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Takes ownership of the future, and returns the node and the sink.
+    pub fn into_parts(self) -> (Node<M>, S) {
+        (self.node, self.sink)
+    }
+}
+impl<M: MessagePayload, S: ExternalSink<M>> Future for Forward<M, S> {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.node_finished {
+            return track!(self.sink.poll_flush());
+        }
+        loop {
+            if track!(self.sink.poll_ready())?.is_not_ready() {
+                track!(self.node.poll_without_delivery())?;
+                return Ok(Async::NotReady);
+            }
+            match track!(self.node.poll())? {
+                Async::NotReady => {
+                    track!(self.sink.poll_flush())?;
+                    return Ok(Async::NotReady);
+                }
+                Async::Ready(None) => {
+                    self.node_finished = true;
+                    return track!(self.sink.poll_flush());
+                }
+                Async::Ready(Some(message)) => {
+                    track!(self.sink.start_send(message))?;
+                }
+            }
+        }
+    }
+}
+
+fn io_error(e: io::Error) -> Error {
+    ErrorKind::Other.cause(e).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Envelope, MessageId};
+    use crate::misc::PlumtreeAppMessage;
+    use crate::node::{LocalNodeId, NodeId, SerialLocalNodeIdGenerator};
+    use crate::service::ServiceBuilder;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Debug, Default)]
+    struct TestSink {
+        ready: Arc<AtomicBool>,
+        messages: Vec<Message<String>>,
+        flushes: usize,
+    }
+    impl ExternalSink<String> for TestSink {
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            if self.ready.load(Ordering::SeqCst) {
+                Ok(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        }
+
+        fn start_send(&mut self, message: Message<String>) -> Result<()> {
+            self.messages.push(message);
+            Ok(())
+        }
+
+        fn poll_flush(&mut self) -> Poll<(), Error> {
+            self.flushes += 1;
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn message(seqno: u64, payload: &str) -> Message<String> {
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(0));
+        Message::new(PlumtreeAppMessage {
+            id: MessageId::new(node, seqno),
+            payload: Envelope::new(payload.to_owned()),
+        })
+    }
+
+    fn encode(m: &Message<String>, buf: &mut Vec<u8>) -> Result<()> {
+        buf.extend_from_slice(m.payload().as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn forward_pauses_only_delivery_while_sink_is_not_ready() {
+        let service = ServiceBuilder::new("127.0.0.1:0".parse().unwrap())
+            .enable_metrics(false)
+            .finish(fibers_global::handle(), SerialLocalNodeIdGenerator::new());
+        let sink = TestSink::default();
+        let ready = Arc::clone(&sink.ready);
+        let mut forward = Node::<String>::new(service.handle()).forward_to(sink);
+        forward.node_mut().broadcast("foo".to_owned());
+
+        let polled = futures::future::lazy(|| forward.poll()).wait().unwrap();
+        assert!(polled.is_not_ready());
+
+        // The node has kept running, and holds the deliverable message.
+        assert_eq!(forward.node().status().cached_messages(), 1);
+        assert!(forward.sink().messages.is_empty());
+
+        ready.store(true, Ordering::SeqCst);
+        futures::future::lazy(|| forward.poll()).wait().unwrap();
+        assert_eq!(forward.sink().messages.len(), 1);
+        assert_eq!(forward.sink().messages[0].payload(), "foo");
+        assert!(forward.sink().flushes > 0);
+    }
+
+    #[test]
+    fn write_sink_bounds_buffered_bytes() {
+        let mut sink = WriteSink::new(Vec::new(), encode);
+        sink.set_max_buffer_size(4);
+
+        assert!(ExternalSink::<String>::poll_ready(&mut sink)
+            .unwrap()
+            .is_ready());
+        sink.start_send(message(0, "foo")).unwrap();
+        assert!(ExternalSink::<String>::poll_ready(&mut sink)
+            .unwrap()
+            .is_ready());
+        sink.start_send(message(1, "bar")).unwrap();
+        assert_eq!(sink.buffered_bytes(), 6);
+        assert!(sink.inner_ref().is_empty());
+
+        // The buffer has reached the limit, so it is written before the next message is accepted.
+        assert!(ExternalSink::<String>::poll_ready(&mut sink)
+            .unwrap()
+            .is_ready());
+        assert_eq!(sink.buffered_bytes(), 0);
+        assert_eq!(sink.inner_ref(), b"foobar");
+
+        sink.start_send(message(2, "baz")).unwrap();
+        assert!(ExternalSink::<String>::poll_flush(&mut sink)
+            .unwrap()
+            .is_ready());
+        assert_eq!(sink.into_inner(), b"foobarbaz");
+    }
+
+    #[test]
+    fn write_sink_retries_would_block_writes() {
+        #[derive(Debug)]
+        struct BlockingWriter {
+            blocked: bool,
+            written: Vec<u8>,
+        }
+        impl Write for BlockingWriter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if self.blocked {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+                self.written.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let writer = BlockingWriter {
+            blocked: true,
+            written: Vec::new(),
+        };
+        let mut sink = WriteSink::new(writer, encode);
+        sink.start_send(message(0, "foo")).unwrap();
+
+        let flushed = futures::future::lazy(|| ExternalSink::<String>::poll_flush(&mut sink))
+            .wait()
+            .unwrap();
+        assert!(flushed.is_not_ready());
+        assert_eq!(sink.buffered_bytes(), 3);
+
+        sink.inner_mut().blocked = false;
+        assert!(ExternalSink::<String>::poll_flush(&mut sink)
+            .unwrap()
+            .is_ready());
+        assert_eq!(sink.inner_ref().written, b"foo");
+    }
+}
@@ -0,0 +1,365 @@
+//! Eventually consistent state disseminated by broadcasting CRDT deltas.
+//!
+//! A [`StateDisseminator`] drives a node whose messages are the deltas of a user-provided
+//! CRDT (see [`Crdt`]).
+//! The local updates made via a [`StateHandle`] are applied to the local replica immediately,
+//! and the deltas accumulated since the last broadcast are periodically broadcasted as one message.
+//! The deltas received from the other nodes are merged into the local replica.
+//! The converged state can be observed by [`StateHandle::get`] or [`StateWatch`].
+//!
+//! Note that only the deltas are disseminated, so the nodes that join a cluster later
+//! do not see the updates made before their joins.
+//! If such nodes should catch up, the application can occasionally broadcast
+//! the whole state as a delta (by [`StateHandle::update`]).
+//!
+//! [`Crdt`]: ./trait.Crdt.html
+//! [`StateDisseminator`]: ./struct.StateDisseminator.html
+//! [`StateHandle`]: ./struct.StateHandle.html
+//! [`StateHandle::get`]: ./struct.StateHandle.html#method.get
+//! [`StateHandle::update`]: ./struct.StateHandle.html#method.update
+//! [`StateWatch`]: ./struct.StateWatch.html
+use crate::message::{Message, MessageId, MessagePayload};
+use crate::node::Node;
+use crate::Error;
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll, Stream};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// This trait allows the implementations to be disseminated by [`StateDisseminator`].
+///
+/// The implementations should be delta-state CRDTs:
+/// applying a delta must be commutative, associative and idempotent,
+/// because deltas may be delivered in any order and more than once.
+///
+/// [`StateDisseminator`]: ./struct.StateDisseminator.html
+pub trait Crdt: Clone + Send + 'static {
+    /// The delta of the state broadcasted to the other nodes.
+    type Delta: MessagePayload;
+
+    /// Merges the given delta into the state.
+    fn apply(&mut self, delta: &Self::Delta);
+
+    /// Merges `other` into `delta`.
+    ///
+    /// This is used to combine the local deltas made between broadcasts.
+    fn join(delta: &mut Self::Delta, other: Self::Delta);
+}
+
+/// A [`Future`] that disseminates the state of a CRDT via a node.
+///
+/// The disseminator is the application of the node,
+/// so received deltas are forgot after the retention period has passed
+/// (see [`StateDisseminator::retention`]).
+///
+/// The future completes when the node stops.
+///
+/// [`Future`]: https://docs.rs/futures/0.1/futures/future/trait.Future.html
+/// [`StateDisseminator::retention`]: ./struct.StateDisseminator.html#method.retention
+#[must_use = "futures do nothing unless polled"]
+pub struct StateDisseminator<T: Crdt> {
+    node: Node<T::Delta>,
+    shared: Arc<Mutex<SharedState<T>>>,
+    broadcast_interval: Duration,
+    last_broadcast_time: Instant,
+    retention: Duration,
+    history: VecDeque<(Instant, MessageId)>,
+}
+impl<T: Crdt> StateDisseminator<T> {
+    /// Makes a new `StateDisseminator` instance which local replica starts from `initial_state`.
+    pub fn new(node: Node<T::Delta>, initial_state: T) -> Self {
+        let shared = SharedState {
+            state: initial_state,
+            version: 0,
+            pending_delta: None,
+            watchers: Vec::new(),
+            closed: false,
+        };
+        StateDisseminator {
+            node,
+            shared: Arc::new(Mutex::new(shared)),
+            broadcast_interval: Duration::from_secs(1),
+            last_broadcast_time: Instant::now(),
+            retention: Duration::from_secs(60),
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Sets the interval between broadcasts of the local deltas.
+    ///
+    /// Note that the interval is checked every tick of the node,
+    /// so the actual interval is rounded up to a multiple of the tick interval.
+    ///
+    /// The default value is `Duration::from_secs(1)`.
+    pub fn broadcast_interval(mut self, interval: Duration) -> Self {
+        self.broadcast_interval = interval;
+        self
+    }
+
+    /// Sets the period during which the node keeps the delta messages.
+    ///
+    /// After the period has passed, the messages are forgot by the node
+    /// (i.e., `Node::forget_message()` is called).
+    ///
+    /// The default value is `Duration::from_secs(60)`.
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Returns a handle of the state.
+    pub fn handle(&self) -> StateHandle<T> {
+        StateHandle {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    /// Returns a reference to the node.
+    pub fn node(&self) -> &Node<T::Delta> {
+        &self.node
+    }
+
+    /// Returns a mutable reference to the node.
+    pub fn node_mut(&mut self) -> &mut Node<T::Delta> {
+        &mut self.node
+    }
+
+    fn handle_message(&mut self, message: Message<T::Delta>) {
+        let id = *message.id();
+        self.history.push_back((Instant::now(), id));
+        if id.node() == self.node.id() {
+            // NOTE: The local deltas have already been applied.
+            return;
+        }
+        self.shared
+            .lock()
+            .expect("Never fails")
+            .apply(message.payload());
+    }
+
+    fn broadcast_pending_delta(&mut self) {
+        let now = Instant::now();
+        if now.duration_since(self.last_broadcast_time) < self.broadcast_interval {
+            return;
+        }
+        self.last_broadcast_time = now;
+
+        let delta = self
+            .shared
+            .lock()
+            .expect("Never fails")
+            .pending_delta
+            .take();
+        if let Some(delta) = delta {
+            self.node.broadcast(delta);
+        }
+    }
+
+    fn forget_expired_messages(&mut self) {
+        let now = Instant::now();
+        while let Some(&(time, id)) = self.history.front() {
+            if now.duration_since(time) < self.retention {
+                break;
+            }
+            self.history.pop_front();
+            self.node.forget_message(&id);
+        }
+    }
+}
+impl<T: Crdt> Future for StateDisseminator<T> {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        while let Async::Ready(message) = track!(self.node.poll())? {
+            if let Some(message) = message {
+                self.handle_message(message);
+            } else {
+                return Ok(Async::Ready(()));
+            }
+        }
+
+        // NOTE: The node wakes up this task at least every tick.
+        self.broadcast_pending_delta();
+        self.forget_expired_messages();
+        Ok(Async::NotReady)
+    }
+}
+impl<T: Crdt> Drop for StateDisseminator<T> {
+    fn drop(&mut self) {
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.closed = true;
+            shared.notify();
+        }
+    }
+}
+impl<T: Crdt> fmt::Debug for StateDisseminator<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "StateDisseminator {{ node: {:?}, broadcast_interval: {:?}, retention: {:?}, .. }}",
+            self.node.id(),
+            self.broadcast_interval,
+            self.retention
+        )
+    }
+}
+
+/// A handle of the state disseminated by a [`StateDisseminator`].
+///
+/// [`StateDisseminator`]: ./struct.StateDisseminator.html
+pub struct StateHandle<T: Crdt> {
+    shared: Arc<Mutex<SharedState<T>>>,
+}
+impl<T: Crdt> StateHandle<T> {
+    /// Returns a snapshot of the local replica.
+    pub fn get(&self) -> T {
+        self.shared.lock().expect("Never fails").state.clone()
+    }
+
+    /// Updates the local replica.
+    ///
+    /// `f` should return the delta of the update, which is applied to the local replica
+    /// immediately and broadcasted at the next broadcast interval.
+    pub fn update<F>(&self, f: F)
+    where
+        F: FnOnce(&T) -> T::Delta,
+    {
+        let mut shared = self.shared.lock().expect("Never fails");
+        let delta = f(&shared.state);
+        shared.apply(&delta);
+        match shared.pending_delta {
+            None => shared.pending_delta = Some(delta),
+            Some(ref mut pending) => T::join(pending, delta),
+        }
+    }
+
+    /// Returns a stream that yields a snapshot of the local replica each time it is changed.
+    pub fn watch(&self) -> StateWatch<T> {
+        let version = self.shared.lock().expect("Never fails").version;
+        StateWatch {
+            shared: Arc::clone(&self.shared),
+            version,
+        }
+    }
+}
+impl<T: Crdt> Clone for StateHandle<T> {
+    fn clone(&self) -> Self {
+        StateHandle {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+impl<T: Crdt> fmt::Debug for StateHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StateHandle {{ .. }}")
+    }
+}
+
+/// A stream of the snapshots of the state disseminated by a [`StateDisseminator`].
+///
+/// This is created by [`StateHandle::watch`].
+/// If the state is changed multiple times between polls, only the latest snapshot is yielded.
+///
+/// The stream terminates once the disseminator is dropped.
+///
+/// [`StateDisseminator`]: ./struct.StateDisseminator.html
+/// [`StateHandle::watch`]: ./struct.StateHandle.html#method.watch
+pub struct StateWatch<T: Crdt> {
+    shared: Arc<Mutex<SharedState<T>>>,
+    version: u64,
+}
+impl<T: Crdt> StateWatch<T> {
+    /// Returns a snapshot of the local replica.
+    pub fn get(&self) -> T {
+        self.shared.lock().expect("Never fails").state.clone()
+    }
+}
+impl<T: Crdt> Stream for StateWatch<T> {
+    type Item = T;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut shared = self.shared.lock().expect("Never fails");
+        if shared.version != self.version {
+            self.version = shared.version;
+            Ok(Async::Ready(Some(shared.state.clone())))
+        } else if shared.closed {
+            Ok(Async::Ready(None))
+        } else {
+            shared.watchers.push(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
+impl<T: Crdt> fmt::Debug for StateWatch<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StateWatch {{ version: {}, .. }}", self.version)
+    }
+}
+
+struct SharedState<T: Crdt> {
+    state: T,
+    version: u64,
+    pending_delta: Option<T::Delta>,
+    watchers: Vec<Task>,
+    closed: bool,
+}
+impl<T: Crdt> SharedState<T> {
+    fn apply(&mut self, delta: &T::Delta) {
+        self.state.apply(delta);
+        self.version += 1;
+        self.notify();
+    }
+
+    fn notify(&mut self) {
+        for watcher in self.watchers.drain(..) {
+            watcher.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct GrowOnlySet(BTreeSet<String>);
+    impl Crdt for GrowOnlySet {
+        type Delta = String;
+
+        fn apply(&mut self, delta: &String) {
+            self.0.extend(delta.split(',').map(|s| s.to_owned()));
+        }
+
+        fn join(delta: &mut String, other: String) {
+            delta.push(',');
+            delta.push_str(&other);
+        }
+    }
+
+    #[test]
+    fn local_updates_are_joined() {
+        let shared = SharedState {
+            state: GrowOnlySet::default(),
+            version: 0,
+            pending_delta: None,
+            watchers: Vec::new(),
+            closed: false,
+        };
+        let handle = StateHandle {
+            shared: Arc::new(Mutex::new(shared)),
+        };
+        let watch = handle.watch();
+        handle.update(|_| "a".to_owned());
+        handle.update(|_| "b".to_owned());
+
+        assert_eq!(watch.get().0.len(), 2);
+        let shared = handle.shared.lock().unwrap();
+        assert_eq!(shared.version, 2);
+        assert_eq!(shared.pending_delta, Some("a,b".to_owned()));
+    }
+}
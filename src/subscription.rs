@@ -0,0 +1,173 @@
+//! [`Subscription`] and related components.
+//!
+//! [`Subscription`]: ./struct.Subscription.html
+use crate::message::{Message, MessagePayload};
+use crate::Error;
+use futures::task::{self, Task};
+use futures::{Async, Poll, Stream};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// A stream of the messages delivered to a [`Node`].
+///
+/// This is created by [`Node::subscribe`], and yields a clone of every message yielded by the node
+/// after the subscription was created.
+/// So any number of observers (e.g., a metrics collector) can see the deliveries
+/// in addition to the task polling the node.
+///
+/// A subscription buffers up to the capacity specified on creation.
+/// If it is full when a new message is delivered, the oldest buffered message is dropped
+/// (and counted by [`dropped_messages`]), so a slow subscriber never blocks the node.
+///
+/// Note that the messages yielded by a subscription do not need to be forgot,
+/// because `Node::forget_message()` is the duty of the task polling the node.
+///
+/// The stream terminates once the node is dropped and the buffered messages are consumed.
+///
+/// [`Node`]: ../node/struct.Node.html
+/// [`Node::subscribe`]: ../node/struct.Node.html#method.subscribe
+/// [`dropped_messages`]: #method.dropped_messages
+#[derive(Debug)]
+pub struct Subscription<M: MessagePayload> {
+    queue: Arc<Mutex<SubscriptionQueue<M>>>,
+}
+impl<M: MessagePayload> Subscription<M> {
+    /// Returns the number of the messages dropped because the subscription was full.
+    pub fn dropped_messages(&self) -> u64 {
+        self.queue.lock().expect("Never fails").dropped
+    }
+}
+impl<M: MessagePayload> Stream for Subscription<M> {
+    type Item = Message<M>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut queue = self.queue.lock().expect("Never fails");
+        if let Some(message) = queue.messages.pop_front() {
+            Ok(Async::Ready(Some(message)))
+        } else if queue.closed {
+            Ok(Async::Ready(None))
+        } else {
+            queue.task = Some(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SubscriptionQueue<M: MessagePayload> {
+    messages: VecDeque<Message<M>>,
+    capacity: usize,
+    dropped: u64,
+    closed: bool,
+    task: Option<Task>,
+}
+impl<M: MessagePayload> SubscriptionQueue<M> {
+    fn push(&mut self, message: &Message<M>) {
+        if self.capacity == 0 {
+            self.dropped += 1;
+            return;
+        }
+        if self.messages.len() == self.capacity {
+            self.messages.pop_front();
+            self.dropped += 1;
+        }
+        self.messages.push_back(message.clone());
+        self.notify();
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        self.notify();
+    }
+
+    fn notify(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.notify();
+        }
+    }
+}
+
+/// The subscriptions of a node.
+#[derive(Debug)]
+pub(crate) struct Subscribers<M: MessagePayload> {
+    queues: Vec<Arc<Mutex<SubscriptionQueue<M>>>>,
+}
+impl<M: MessagePayload> Subscribers<M> {
+    pub(crate) fn subscribe(&mut self, capacity: usize) -> Subscription<M> {
+        let queue = Arc::new(Mutex::new(SubscriptionQueue {
+            messages: VecDeque::new(),
+            capacity,
+            dropped: 0,
+            closed: false,
+            task: None,
+        }));
+        self.queues.push(Arc::clone(&queue));
+        Subscription { queue }
+    }
+
+    pub(crate) fn publish(&mut self, message: &Message<M>) {
+        if self.queues.is_empty() {
+            return;
+        }
+
+        // NOTE: The subscriptions dropped by the subscribers are removed here.
+        self.queues.retain(|q| Arc::strong_count(q) > 1);
+        for queue in &self.queues {
+            queue.lock().expect("Never fails").push(message);
+        }
+    }
+
+    pub(crate) fn close(&mut self) {
+        for queue in self.queues.drain(..) {
+            queue.lock().expect("Never fails").close();
+        }
+    }
+}
+impl<M: MessagePayload> Default for Subscribers<M> {
+    fn default() -> Self {
+        Subscribers { queues: Vec::new() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Envelope, MessageId};
+    use crate::misc::PlumtreeAppMessage;
+    use crate::node::{LocalNodeId, NodeId};
+
+    fn message(seqno: u64) -> Message<String> {
+        let node = NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(0));
+        Message::new(PlumtreeAppMessage {
+            id: MessageId::new(node, seqno),
+            payload: Envelope::new(seqno.to_string()),
+        })
+    }
+
+    fn next(subscription: &mut Subscription<String>) -> Option<u64> {
+        match subscription.poll().unwrap() {
+            Async::Ready(m) => m.map(|m| m.id().seqno()),
+            Async::NotReady => panic!(),
+        }
+    }
+
+    #[test]
+    fn subscription_drops_oldest_messages() {
+        let mut subscribers = Subscribers::default();
+        let mut a = subscribers.subscribe(2);
+        let mut b = subscribers.subscribe(8);
+        for seqno in 0..3 {
+            subscribers.publish(&message(seqno));
+        }
+        subscribers.close();
+
+        assert_eq!(next(&mut a), Some(1));
+        assert_eq!(next(&mut a), Some(2));
+        assert_eq!(next(&mut a), None);
+        assert_eq!(a.dropped_messages(), 1);
+
+        assert_eq!(next(&mut b), Some(0));
+        assert_eq!(b.dropped_messages(), 0);
+    }
+}
@@ -0,0 +1,615 @@
+//! Deterministic simulator for evaluating protocol parameters.
+//!
+//! [`Simulator`] runs many [`Node`]s in a single thread without the RPC layer.
+//! The nodes are the same as the ones used in production (built by a [`NodeBuilder`]),
+//! but they are driven by a virtual [`Clock`] and their messages are transmitted via
+//! in-memory links which latency and loss rate are configurable.
+//! Time is virtual (i.e., it advances only when the simulator processes the next event).
+//! Given the same seed and the same operations, a simulation always produces the same result.
+//!
+//! This module is available only if the `testing` feature is enabled.
+//!
+//! # Examples
+//!
+//! ```
+//! use plumcast::testing::SimulatorBuilder;
+//! use std::time::Duration;
+//!
+//! let mut sim = SimulatorBuilder::new().seed([1; 32]).finish::<String>();
+//! let nodes = (0..10).map(|_| sim.add_node()).collect::<Vec<_>>();
+//! for &node in &nodes[1..] {
+//!     sim.join(node, nodes[0]);
+//! }
+//! sim.run_for(Duration::from_secs(10));
+//!
+//! let id = sim.broadcast(nodes[0], "hello".to_owned());
+//! sim.run_for(Duration::from_secs(10));
+//! sim.assert_delivery_ratio(&id, 1.0);
+//! assert!(sim.tree_depth(&id).is_some());
+//! ```
+//!
+//! [`Simulator`]: ./struct.Simulator.html
+//! [`Node`]: ../node/struct.Node.html
+//! [`NodeBuilder`]: ../node/struct.NodeBuilder.html
+//! [`Clock`]: ../clock/struct.Clock.html
+use crate::clock::Clock;
+use crate::message::{MessageId, MessagePayload};
+use crate::node::{Node, NodeBuilder, NodeId, SerialLocalNodeIdGenerator};
+use crate::rpc::RpcMessage;
+use crate::service::{Service, ServiceBuilder};
+use fibers::Spawn;
+use fibers_rpc::server::ServerBuilder as RpcServerBuilder;
+use futures::executor::{self, Notify};
+use futures::{future, Async, Future, Poll, Stream};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::{self, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Options of a simulated link between two nodes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkOptions {
+    /// One-way latency of the link.
+    pub latency: Duration,
+
+    /// Probability that a message sent via the link is lost (between `0.0` and `1.0`).
+    pub loss_rate: f64,
+}
+impl Default for LinkOptions {
+    fn default() -> Self {
+        LinkOptions {
+            latency: Duration::from_millis(1),
+            loss_rate: 0.0,
+        }
+    }
+}
+
+/// The builder of [`Simulator`].
+///
+/// [`Simulator`]: ./struct.Simulator.html
+#[derive(Debug, Clone)]
+pub struct SimulatorBuilder {
+    seed: [u8; 32],
+    tick_interval: Duration,
+    node_builder: NodeBuilder,
+    default_link: LinkOptions,
+}
+impl SimulatorBuilder {
+    /// Makes a new `SimulatorBuilder` instance with the default settings.
+    pub fn new() -> Self {
+        SimulatorBuilder {
+            seed: [0; 32],
+            tick_interval: Duration::from_millis(200),
+            node_builder: NodeBuilder::new(),
+            default_link: LinkOptions::default(),
+        }
+    }
+
+    /// Sets the seed of the random number generators used by the simulator and its nodes.
+    ///
+    /// The default value is `[0; 32]`.
+    pub fn seed(&mut self, seed: [u8; 32]) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Sets the interval of the ticks of the virtual clock driving the nodes.
+    ///
+    /// The default value is `Duration::from_millis(200)`.
+    pub fn tick_interval(&mut self, interval: Duration) -> &mut Self {
+        self.tick_interval = interval;
+        self
+    }
+
+    /// Returns a mutable reference to the builder used for making the simulated nodes.
+    ///
+    /// This can be used for evaluating the parameters of the nodes
+    /// (e.g., `NodeBuilder::hyparview_shuffle_interval`).
+    /// Note that the clock, the tick interval and the random seed of the builder are
+    /// overwritten by the simulator.
+    pub fn node_builder_mut(&mut self) -> &mut NodeBuilder {
+        &mut self.node_builder
+    }
+
+    /// Sets the options of the links for which no specific options are set.
+    ///
+    /// The default value is `LinkOptions::default()`.
+    pub fn default_link(&mut self, options: LinkOptions) -> &mut Self {
+        self.default_link = options;
+        self
+    }
+
+    /// Builds a [`Simulator`] instance with the specified settings.
+    ///
+    /// [`Simulator`]: ./struct.Simulator.html
+    pub fn finish<M: MessagePayload>(&self) -> Simulator<M> {
+        let (service, outbox) = in_memory_service(SocketAddr::from(([127, 0, 0, 1], 0)));
+        let mut node_builder = self.node_builder.clone();
+        let clock = Clock::new();
+        node_builder
+            .clock(clock.clone())
+            .tick_interval(self.tick_interval);
+        Simulator {
+            options: self.clone(),
+            service,
+            node_builder,
+            clock,
+            outbox,
+            wakeup: Arc::new(Wakeup::default()),
+            rng: StdRng::from_seed(self.seed),
+            now: Duration::from_secs(0),
+            next_tick: self.tick_interval,
+            nodes: Vec::new(),
+            node_indices: HashMap::new(),
+            links: HashMap::new(),
+            link_states: HashMap::new(),
+            in_flight: BinaryHeap::new(),
+            messages: HashMap::new(),
+            deliveries: HashMap::new(),
+        }
+    }
+}
+impl Default for SimulatorBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Deterministic single-threaded simulator of a plumcast cluster.
+///
+/// See [module level documentation](./index.html) for more details.
+pub struct Simulator<M: MessagePayload> {
+    options: SimulatorBuilder,
+    service: Service<M>,
+    node_builder: NodeBuilder,
+    clock: Clock,
+    outbox: InMemoryOutbox<M>,
+    wakeup: Arc<Wakeup>,
+    rng: StdRng,
+    now: Duration,
+    next_tick: Duration,
+    nodes: Vec<Node<M>>,
+    node_indices: HashMap<NodeId, usize>,
+    links: HashMap<(NodeId, NodeId), LinkOptions>,
+    link_states: HashMap<Link, LinkState>,
+    in_flight: BinaryHeap<Reverse<(Duration, Link, u64)>>,
+    messages: HashMap<(Link, u64), RpcMessage<M>>,
+    deliveries: HashMap<MessageId, HashMap<NodeId, u16>>,
+}
+impl<M: MessagePayload> Simulator<M> {
+    /// Returns the current virtual time (i.e., the elapsed time since the simulator was created).
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Adds a new node to the simulator, and returns the identifier of it.
+    ///
+    /// Note that the node does not belong to any cluster until [`join`](#method.join) is called.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the node builder (see `SimulatorBuilder::node_builder_mut`)
+    /// has invalid settings.
+    pub fn add_node(&mut self) -> NodeId {
+        let node = self
+            .node_builder
+            .rng_seed(self.rng.gen())
+            .finish(self.service.handle());
+        let id = node.id();
+        self.node_indices.insert(id, self.nodes.len());
+        self.nodes.push(node);
+        self.flush();
+        id
+    }
+
+    /// Returns the identifiers of the nodes in the simulator.
+    pub fn nodes(&self) -> Vec<NodeId> {
+        self.nodes.iter().map(|n| n.id()).collect()
+    }
+
+    /// Sets the options of the link from `from` to `to`.
+    ///
+    /// Note that links are unidirectional.
+    pub fn set_link(&mut self, from: NodeId, to: NodeId, options: LinkOptions) {
+        self.links.insert((from, to), options);
+    }
+
+    /// Makes `node` join the cluster to which `contact_node` belongs.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `node` is not in the simulator.
+    pub fn join(&mut self, node: NodeId, contact_node: NodeId) {
+        self.node_mut(node).join(contact_node);
+        self.flush();
+    }
+
+    /// Broadcasts a message from `node`.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `node` is not in the simulator.
+    pub fn broadcast(&mut self, node: NodeId, payload: M) -> MessageId {
+        let id = self.node_mut(node).broadcast(payload);
+        self.deliveries.entry(id).or_insert_with(HashMap::new);
+        self.flush();
+        id
+    }
+
+    /// Runs the simulation until the virtual time advances by `duration`.
+    pub fn run_for(&mut self, duration: Duration) {
+        let deadline = self.now + duration;
+        loop {
+            let next_arrival = self.in_flight.peek().map(|e| (e.0).0);
+            let next = next_arrival.map_or(self.next_tick, |t| cmp::min(t, self.next_tick));
+            if next > deadline {
+                break;
+            }
+            self.now = next;
+            if next_arrival == Some(next) {
+                let Reverse((_, link, seqno)) = self.in_flight.pop().expect("Never fails");
+                self.receive(link, seqno);
+            } else {
+                self.next_tick += self.options.tick_interval;
+                self.clock.tick(self.options.tick_interval);
+            }
+            self.flush();
+        }
+        self.now = deadline;
+    }
+
+    /// Returns the ratio of the nodes that have delivered the message to all the nodes.
+    pub fn delivery_ratio(&self, message_id: &MessageId) -> f64 {
+        if self.nodes.is_empty() {
+            return 0.0;
+        }
+        let delivered = self.deliveries.get(message_id).map_or(0, |d| d.len());
+        delivered as f64 / self.nodes.len() as f64
+    }
+
+    /// Returns the depth of the broadcast tree of the message.
+    ///
+    /// The depth is the maximum number of hops from the origin node
+    /// at which the message was delivered.
+    /// If no nodes have delivered the message, this returns `None`.
+    pub fn tree_depth(&self, message_id: &MessageId) -> Option<u16> {
+        self.deliveries
+            .get(message_id)
+            .and_then(|d| d.values().cloned().max())
+    }
+
+    /// Asserts that the delivery ratio of the message is at least `min_ratio`.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the assertion fails.
+    pub fn assert_delivery_ratio(&self, message_id: &MessageId, min_ratio: f64) {
+        let ratio = self.delivery_ratio(message_id);
+        assert!(
+            ratio >= min_ratio,
+            "Delivery ratio of {:?} is too low: actual={}, expected>={}",
+            message_id,
+            ratio,
+            min_ratio
+        );
+    }
+
+    /// Asserts that the tree depth of the message is at most `max_depth`.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the assertion fails or no nodes have delivered the message.
+    pub fn assert_tree_depth(&self, message_id: &MessageId, max_depth: u16) {
+        let depth = self.tree_depth(message_id);
+        assert!(
+            depth.map_or(false, |d| d <= max_depth),
+            "Tree depth of {:?} is too deep: actual={:?}, expected<={}",
+            message_id,
+            depth,
+            max_depth
+        );
+    }
+
+    /// Returns a reference to the simulated node.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `node` is not in the simulator.
+    pub fn node(&self, node: NodeId) -> &Node<M> {
+        &self.nodes[self.index(node)]
+    }
+
+    /// Returns a mutable reference to the simulated node.
+    ///
+    /// The operations made via the reference (e.g., `Node::broadcast`) take effect
+    /// the next time the simulator processes an event.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `node` is not in the simulator.
+    pub fn node_mut(&mut self, node: NodeId) -> &mut Node<M> {
+        let i = self.index(node);
+        &mut self.nodes[i]
+    }
+
+    fn index(&self, node: NodeId) -> usize {
+        *self
+            .node_indices
+            .get(&node)
+            .unwrap_or_else(|| panic!("Unknown node: {:?}", node))
+    }
+
+    fn receive(&mut self, link: Link, seqno: u64) {
+        let message = match self.messages.remove(&(link, seqno)) {
+            None => return,
+            Some(m) => m,
+        };
+        let destination = self.nodes[link.1].id();
+        if let Some(node) = self.service.handle().get_local_node(destination.local_id()) {
+            node.send_rpc_message(message);
+        }
+    }
+
+    // Polls the service and the nodes until all of them become idle.
+    //
+    // A node may yield `NotReady` while it still has pending work (e.g., see
+    // `NodeBuilder::max_messages_per_poll`), so the polls are repeated until no one requests them.
+    fn flush(&mut self) {
+        loop {
+            self.wakeup.reset();
+            let mut did_something = false;
+
+            let service = &mut self.service;
+            if let Err(e) = poll_with(&self.wakeup, || service.poll()) {
+                panic!("Simulated service failed: {}", e);
+            }
+            for i in 0..self.nodes.len() {
+                did_something |= self.poll_node(i);
+            }
+            if !did_something && !self.wakeup.is_notified() {
+                break;
+            }
+        }
+    }
+
+    fn poll_node(&mut self, i: usize) -> bool {
+        let mut did_something = false;
+        loop {
+            let node = &mut self.nodes[i];
+            let id = node.id();
+            match poll_with(&self.wakeup, || node.poll()) {
+                Err(e) => panic!("Simulated node {:?} failed: {}", id, e),
+                Ok(Async::NotReady) | Ok(Async::Ready(None)) => break,
+                Ok(Async::Ready(Some(message))) => {
+                    let hops = message.round().map_or(0, |r| r.saturating_add(1));
+                    self.deliveries
+                        .entry(*message.id())
+                        .or_insert_with(HashMap::new)
+                        .insert(id, hops);
+                    did_something = true;
+                }
+            }
+        }
+
+        // NOTE: All the messages in the outbox have been sent by the node polled just now.
+        for (destination, message) in self.outbox.take() {
+            self.transmit(i, destination, message);
+            did_something = true;
+        }
+        did_something
+    }
+
+    // NOTE:
+    // The order of the messages sent by a node to different destinations may vary between runs
+    // (e.g., due to the iteration order of `HashSet`).
+    // In order to keep simulations deterministic, each link has its own random number generator and
+    // the messages arriving at the same time are processed in the order of (link, sequence number).
+    fn transmit(&mut self, i: usize, destination: NodeId, message: RpcMessage<M>) {
+        let j = match self.node_indices.get(&destination) {
+            None => return,
+            Some(&j) => j,
+        };
+        let sender = self.nodes[i].id();
+        let options = self
+            .links
+            .get(&(sender, destination))
+            .cloned()
+            .unwrap_or(self.options.default_link);
+
+        let link = Link(i, j);
+        let seed = self.options.seed;
+        let state = self
+            .link_states
+            .entry(link)
+            .or_insert_with(|| LinkState::new(seed, link));
+        let seqno = state.seqno;
+        state.seqno += 1;
+        if state.rng.gen::<f64>() < options.loss_rate {
+            return;
+        }
+        self.messages.insert((link, seqno), message);
+        self.in_flight
+            .push(Reverse((self.now + options.latency, link, seqno)));
+    }
+}
+impl<M: MessagePayload> fmt::Debug for Simulator<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Simulator {{ now: {:?}, nodes: {}, in_flight: {}, .. }}",
+            self.now,
+            self.nodes.len(),
+            self.in_flight.len()
+        )
+    }
+}
+
+/// The messages sent by the local nodes of a service that does not use the RPC layer.
+///
+/// If it is set to a service, `ServiceHandle::send_message` pushes messages to it
+/// instead of sending them to remote nodes.
+pub(crate) struct InMemoryOutbox<M: MessagePayload>(Arc<Mutex<Vec<(NodeId, RpcMessage<M>)>>>);
+impl<M: MessagePayload> InMemoryOutbox<M> {
+    pub(crate) fn push(&self, destination: NodeId, message: RpcMessage<M>) {
+        let mut messages = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        messages.push((destination, message));
+    }
+
+    pub(crate) fn take(&self) -> Vec<(NodeId, RpcMessage<M>)> {
+        let mut messages = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        std::mem::replace(&mut *messages, Vec::new())
+    }
+}
+impl<M: MessagePayload> Clone for InMemoryOutbox<M> {
+    fn clone(&self) -> Self {
+        InMemoryOutbox(Arc::clone(&self.0))
+    }
+}
+impl<M: MessagePayload> Default for InMemoryOutbox<M> {
+    fn default() -> Self {
+        InMemoryOutbox(Arc::new(Mutex::new(Vec::new())))
+    }
+}
+impl<M: MessagePayload> fmt::Debug for InMemoryOutbox<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "InMemoryOutbox {{ .. }}")
+    }
+}
+
+/// Makes a service which local nodes send messages via the returned outbox.
+///
+/// The service has no RPC server nor client, so `addr` is used only as
+/// the address of the local nodes.
+pub(crate) fn in_memory_service<M: MessagePayload>(
+    addr: SocketAddr,
+) -> (Service<M>, InMemoryOutbox<M>) {
+    let mut service = ServiceBuilder::new(addr)
+        .enable_metrics(false)
+        .finish_with_external_server(
+            NoopSpawner,
+            SerialLocalNodeIdGenerator::new(),
+            &mut RpcServerBuilder::new(addr),
+        )
+        .expect("Never fails");
+    let _ = service.take_rpc_client_service();
+    let outbox = InMemoryOutbox::default();
+    service.set_in_memory_outbox(outbox.clone());
+    (service, outbox)
+}
+
+pub(crate) fn poll_with<T, E, F>(wakeup: &Arc<Wakeup>, f: F) -> Poll<T, E>
+where
+    F: FnMut() -> Poll<T, E>,
+{
+    executor::spawn(future::poll_fn(f)).poll_future_notify(wakeup, 0)
+}
+
+// NOTE: The simulated service has no RPC server nor client, so nothing is spawned.
+struct NoopSpawner;
+impl Spawn for NoopSpawner {
+    fn spawn_boxed(&self, _fiber: Box<dyn Future<Item = (), Error = ()> + Send>) {}
+}
+
+// Records whether the service or a node has requested to be polled again.
+#[derive(Debug, Default)]
+pub(crate) struct Wakeup(AtomicBool);
+impl Wakeup {
+    pub(crate) fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_notified(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+impl Notify for Wakeup {
+    fn notify(&self, _id: usize) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct Link(usize, usize);
+
+#[derive(Debug)]
+struct LinkState {
+    rng: StdRng,
+    seqno: u64,
+}
+impl LinkState {
+    fn new(mut seed: [u8; 32], link: Link) -> Self {
+        let indices = [link.0 as u64, link.1 as u64];
+        for (i, index) in indices.iter().enumerate() {
+            for (j, b) in index.to_be_bytes().iter().enumerate() {
+                seed[i * 8 + j] ^= b;
+            }
+        }
+        LinkState {
+            rng: StdRng::from_seed(seed),
+            seqno: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster(seed: [u8; 32], size: usize) -> (Simulator<String>, Vec<NodeId>) {
+        let mut sim = SimulatorBuilder::new().seed(seed).finish();
+        let nodes = (0..size).map(|_| sim.add_node()).collect::<Vec<_>>();
+        for &node in &nodes[1..] {
+            sim.join(node, nodes[0]);
+        }
+        sim.run_for(Duration::from_secs(10));
+        (sim, nodes)
+    }
+
+    #[test]
+    fn broadcast_reaches_all_nodes() {
+        let (mut sim, nodes) = cluster([1; 32], 30);
+        let id = sim.broadcast(nodes[3], "foo".to_owned());
+        sim.run_for(Duration::from_secs(10));
+        sim.assert_delivery_ratio(&id, 1.0);
+        sim.assert_tree_depth(&id, 30);
+    }
+
+    #[test]
+    fn simulation_is_deterministic() {
+        let run = || {
+            let (mut sim, nodes) = cluster([7; 32], 20);
+            let id = sim.broadcast(nodes[0], "foo".to_owned());
+            sim.run_for(Duration::from_secs(10));
+            let views = nodes
+                .iter()
+                .map(|&n| sim.node(n).hyparview_node().active_view().to_vec())
+                .collect::<Vec<_>>();
+            (views, sim.tree_depth(&id))
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn nodes_are_polled_until_idle() {
+        let mut builder = SimulatorBuilder::new();
+        builder
+            .seed([3; 32])
+            .node_builder_mut()
+            .max_messages_per_poll(1);
+        let mut sim = builder.finish::<String>();
+        let nodes = (0..10).map(|_| sim.add_node()).collect::<Vec<_>>();
+        for &node in &nodes[1..] {
+            sim.join(node, nodes[0]);
+        }
+        sim.run_for(Duration::from_secs(10));
+
+        let id = sim.broadcast(nodes[0], "foo".to_owned());
+        sim.run_for(Duration::from_secs(10));
+        sim.assert_delivery_ratio(&id, 1.0);
+    }
+}
@@ -0,0 +1,149 @@
+//! Topology awareness based on the measured round-trip times (RTTs) between neighbors.
+//!
+//! If [`NodeBuilder::topology_awareness`] is specified, a node periodically measures
+//! the RTTs to its neighbors (by pinging the services the neighbors belong to), and
+//! keeps the neighbors having the lowest RTTs as its Plumtree eager push peers.
+//! The other neighbors become lazy push peers,
+//! so the broadcast trees tend to consist of low-latency links
+//! (e.g., links within the same data center rather than trans-continental ones).
+//!
+//! [`NodeBuilder::topology_awareness`]: ../node/struct.NodeBuilder.html#method.topology_awareness
+use crate::admin::HealthCheck;
+use crate::node::NodeId;
+use futures::{Async, Future};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Options for topology awareness.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopologyAwareness {
+    /// The interval between RTT measurements.
+    ///
+    /// The eager push peers are also reselected at this interval.
+    ///
+    /// The default value is `Duration::from_secs(30)`.
+    pub probe_interval: Duration,
+
+    /// The number of the neighbors kept as eager push peers.
+    ///
+    /// If [`NodeBuilder::adaptive`] is enabled, the number derived from the cluster size
+    /// is used instead.
+    ///
+    /// The default value is `3`.
+    ///
+    /// [`NodeBuilder::adaptive`]: ../node/struct.NodeBuilder.html#method.adaptive
+    pub eager_push_degree: usize,
+}
+impl Default for TopologyAwareness {
+    fn default() -> Self {
+        TopologyAwareness {
+            probe_interval: Duration::from_secs(30),
+            eager_push_degree: 3,
+        }
+    }
+}
+
+/// The smoothed RTTs of neighbors.
+#[derive(Debug, Default)]
+pub(crate) struct RttTable {
+    rtts: HashMap<NodeId, Duration>,
+    probes: Vec<Probe>,
+}
+impl RttTable {
+    pub(crate) fn get(&self, peer: &NodeId) -> Option<Duration> {
+        self.rtts.get(peer).cloned()
+    }
+
+    pub(crate) fn remove(&mut self, peer: &NodeId) {
+        self.rtts.remove(peer);
+        self.probes.retain(|p| p.peer != *peer);
+    }
+
+    pub(crate) fn is_probing(&self, peer: &NodeId) -> bool {
+        self.probes.iter().any(|p| p.peer == *peer)
+    }
+
+    pub(crate) fn start_probe(&mut self, peer: NodeId, check: HealthCheck) {
+        self.probes.push(Probe {
+            peer,
+            start: Instant::now(),
+            check,
+        });
+    }
+
+    /// Polls the ongoing probes, and returns the peers that could not be probed.
+    pub(crate) fn poll_probes(&mut self) -> Vec<NodeId> {
+        let mut failed = Vec::new();
+        let mut i = 0;
+        while i < self.probes.len() {
+            match self.probes[i].check.poll() {
+                Ok(Async::NotReady) => {
+                    i += 1;
+                    continue;
+                }
+                Ok(Async::Ready(_)) => {
+                    let probe = self.probes.swap_remove(i);
+                    self.observe(probe.peer, probe.start.elapsed());
+                }
+                Err(_) => {
+                    let probe = self.probes.swap_remove(i);
+                    failed.push(probe.peer);
+                }
+            }
+        }
+        failed
+    }
+
+    /// Sorts the given peers in ascending order of their RTTs.
+    ///
+    /// The peers whose RTTs have not been measured yet are placed last.
+    pub(crate) fn sort_by_rtt(&self, peers: &mut [NodeId]) {
+        peers.sort_by_key(|p| {
+            let rtt = self.get(p);
+            (rtt.is_none(), rtt)
+        });
+    }
+
+    fn observe(&mut self, peer: NodeId, sample: Duration) {
+        // The same smoothing as the TCP's SRTT (i.e., `alpha = 1/8`).
+        let rtt = self
+            .rtts
+            .get(&peer)
+            .map_or(sample, |&rtt| (rtt * 7 + sample) / 8);
+        self.rtts.insert(peer, rtt);
+    }
+}
+
+#[derive(Debug)]
+struct Probe {
+    peer: NodeId,
+    start: Instant,
+    check: HealthCheck,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::LocalNodeId;
+
+    fn node(n: u64) -> NodeId {
+        NodeId::new("127.0.0.1:3000".parse().unwrap(), LocalNodeId::new(n))
+    }
+
+    #[test]
+    fn rtts_are_smoothed_and_sorted() {
+        let mut table = RttTable::default();
+        table.observe(node(1), Duration::from_millis(80));
+        assert_eq!(table.get(&node(1)), Some(Duration::from_millis(80)));
+        table.observe(node(1), Duration::from_millis(160));
+        assert_eq!(table.get(&node(1)), Some(Duration::from_millis(90)));
+        table.observe(node(2), Duration::from_millis(10));
+
+        let mut peers = vec![node(3), node(1), node(2)];
+        table.sort_by_rtt(&mut peers);
+        assert_eq!(peers, vec![node(2), node(1), node(3)]);
+
+        table.remove(&node(2));
+        assert_eq!(table.get(&node(2)), None);
+    }
+}
@@ -0,0 +1,115 @@
+//! Distributed tracing support.
+//!
+//! Gossip frames of traced messages carry a `TraceContext` in their extension section,
+//! so that the path of a message through the spanning tree can be reconstructed from
+//! the spans emitted by each node.
+//! Messages are traced (and the spans are emitted via the [tracing] crate) only if
+//! the `tracing` feature is enabled on their origin node; otherwise the functions in
+//! this module are no-ops and the frames carry no context
+//! (the context of traced messages received from other nodes is still relayed as is).
+//!
+//! [tracing]: https://crates.io/crates/tracing
+use crate::message::MessageId;
+use crate::node::NodeId;
+use crate::random;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Trace context carried in gossip frames.
+///
+/// `trace_id` identifies the broadcast of a message in a cluster (`0` means "not traced"), and
+/// `span_id` identifies the node hop that sent the frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TraceContext {
+    pub(crate) trace_id: u64,
+    pub(crate) span_id: u64,
+}
+impl TraceContext {
+    /// Makes a new root context for a message broadcasted by `origin`.
+    pub(crate) fn root(origin: NodeId) -> Self {
+        if cfg!(feature = "tracing") {
+            let trace_id = std::cmp::max(1, random::random_u64());
+            TraceContext::hop(trace_id, origin)
+        } else {
+            TraceContext::default()
+        }
+    }
+
+    /// Returns the context that `node` attaches to the frames it sends.
+    pub(crate) fn hop(trace_id: u64, node: NodeId) -> Self {
+        let mut hasher = DefaultHasher::new();
+        (trace_id, node).hash(&mut hasher);
+        TraceContext {
+            trace_id,
+            span_id: hasher.finish(),
+        }
+    }
+
+    pub(crate) fn is_traced(&self) -> bool {
+        self.trace_id != 0
+    }
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) fn on_broadcast(id: &MessageId, context: TraceContext) {
+    if context.is_traced() {
+        tracing::info_span!(
+            "plumcast.broadcast",
+            message_id = %format_args!("{:?}", id),
+            trace_id = context.trace_id,
+            span_id = context.span_id
+        )
+        .in_scope(|| tracing::debug!("broadcast"));
+    }
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) fn on_receive(
+    id: &MessageId,
+    sender: NodeId,
+    round: u16,
+    received: TraceContext,
+    local: TraceContext,
+) {
+    if received.is_traced() {
+        tracing::info_span!(
+            "plumcast.receive",
+            message_id = %format_args!("{:?}", id),
+            trace_id = local.trace_id,
+            span_id = local.span_id,
+            parent_span_id = received.span_id,
+            sender = %sender,
+            round
+        )
+        .in_scope(|| tracing::debug!("receive"));
+    }
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) fn on_deliver(id: &MessageId, local: TraceContext) {
+    if local.is_traced() {
+        tracing::info_span!(
+            "plumcast.deliver",
+            message_id = %format_args!("{:?}", id),
+            trace_id = local.trace_id,
+            span_id = local.span_id
+        )
+        .in_scope(|| tracing::debug!("deliver"));
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn on_broadcast(_id: &MessageId, _context: TraceContext) {}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn on_receive(
+    _id: &MessageId,
+    _sender: NodeId,
+    _round: u16,
+    _received: TraceContext,
+    _local: TraceContext,
+) {
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn on_deliver(_id: &MessageId, _local: TraceContext) {}